@@ -0,0 +1,500 @@
+//! Building from other Rust programs, e.g. an `xtask` or a deployment tool, rather than running the `black_magic` command.
+//!
+//! A `BuildConfig` says what to build, with a field for each of the command line's build options, and a `Builder` builds
+//! it, reporting progress as `Event`s to a callback:
+//! ```no_run
+//! use black_magic::{Arch, BuildConfig, Builder, Event};
+//!
+//! let mut config = BuildConfig::lambda("services/api").arch(Arch::Aarch64).features(&["tracing"]);
+//! config.strip = true;
+//! let output = Builder::new()
+//!     .on_event(|event| match event {
+//!         Event::Progress(message) => eprintln!("{}", message),
+//!         _ => {}
+//!     })
+//!     .build(&config)?;
+//! println!("Built {} ({})", output.artifact.path.display(), output.artifact.sha256);
+//! # Ok::<(), black_magic::BmError>(())
+//! ```
+//! The project is wherever `project_dir` says, whatever the current directory is, and progress goes to the callback of the
+//! build's own thread, so builds of different projects can run at once. Building several artifacts at once (`--bins`,
+//! `--platforms`, `--bundle`) and `--watch` are only available from the command line, which runs one build per artifact.
+
+use crate::backend::Backend;
+use crate::baseline::CpuBaseline;
+use crate::bundle::Compression;
+use crate::cargo_cache::CargoCache;
+use crate::error::BmError;
+use crate::lambda_runtime::LambdaRuntime;
+use crate::manifest::Features;
+use crate::openssl::OpensslMode;
+use crate::output;
+use crate::packaging::Serverless;
+use crate::phases::Phase;
+use crate::runtime::Runtime;
+use crate::sbom::SbomFormat;
+use crate::scan::Severity;
+use crate::sign::SignMethod;
+use crate::Arch;
+use crate::Libc;
+use clap::ArgMatches;
+use serde_json::Value;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// What a build produces.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    /// A Lambda zip, like `--lambda`.
+    #[default]
+    Lambda,
+    /// A docker image, like `--docker`.
+    Docker,
+    /// A Lambda container image, like `--lambda-image`.
+    LambdaImage,
+}
+
+/// What to build, with the command line's options. See `black_magic --help` for what each one does.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct BuildConfig {
+    /// The project, which has the `Cargo.toml` (and any `BlackMagic.toml`).
+    pub project_dir: PathBuf,
+    pub mode: Mode,
+
+    // What's compiled.
+    /// `--arch`, otherwise the base image's, or x86_64.
+    pub arch: Option<Arch>,
+    /// `--libc`, musl by default.
+    pub libc: Option<Libc>,
+    /// `--target-triple`.
+    pub target_triple: Option<String>,
+    /// `--cpu-baseline`, e.g. `x86-64-v2`.
+    pub cpu_baseline: Option<CpuBaseline>,
+    /// `--bin`, `--example` and `--bench`.
+    pub bin: Option<String>,
+    pub example: Option<String>,
+    pub bench: Option<String>,
+    /// `--profile`, `release` by default.
+    pub profile: Option<String>,
+    /// `--features`, `--no-default-features` and `--all-features`.
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    pub all_features: bool,
+    pub isolate_features: bool,
+    /// `--cargo-arg`s, and what comes after `--`.
+    pub cargo_args: Vec<String>,
+    pub rustflags: Option<String>,
+    pub jobs: Option<u32>,
+    pub openssl: Option<OpensslMode>,
+    pub no_auto_static: bool,
+    pub hardened: bool,
+    pub reproducible: bool,
+    pub strip: bool,
+    pub upx: bool,
+    pub test: bool,
+    pub clippy: bool,
+
+    // How it's compiled.
+    /// `--runtime`, `docker` or `podman`.
+    pub runtime: Option<Runtime>,
+    pub backend: Option<Backend>,
+    pub builder_image: Option<String>,
+    pub builder_tag: Option<String>,
+    pub builder_cache_from: Option<String>,
+    pub builder_cache_push: bool,
+    pub update_builder: bool,
+    pub auto_update_builder: bool,
+    pub layered: bool,
+    /// `--strategy dockerfile`.
+    pub dockerfile_strategy: bool,
+    pub sccache: bool,
+    pub cache_server: Option<String>,
+    pub no_cache: bool,
+    pub no_artifact_cache: bool,
+    pub skip_unchanged: bool,
+    pub from_phase: Option<Phase>,
+    pub cargo_home: Option<String>,
+    pub cargo_home_volume: Option<String>,
+    pub cargo_cache: Option<CargoCache>,
+    /// `--cargo-lock-timeout`, in seconds, 30 by default.
+    pub cargo_lock_timeout: Option<u64>,
+    pub no_wait: bool,
+    pub shadow_target: bool,
+    pub read_only_source: bool,
+    /// `--user-map` or `--no-user-map`, otherwise `BlackMagic.toml`'s or the runtime's default.
+    pub user_map: Option<bool>,
+    pub keep_on_failure: bool,
+    pub resource_report: bool,
+    pub cpus: Option<String>,
+    pub memory: Option<String>,
+    /// `--timeout`, in seconds.
+    pub timeout: Option<u64>,
+    pub retries: u32,
+    /// `--retry-delay`, in seconds, 5 by default.
+    pub retry_delay: Option<u64>,
+
+    // What the compile can reach.
+    pub ssh: bool,
+    pub ssh_known_hosts: Option<String>,
+    pub gitconfig: Option<String>,
+    pub cargo_config: bool,
+    pub registry_tokens: Vec<String>,
+    pub network: Option<String>,
+    pub dns: Vec<String>,
+    pub add_hosts: Vec<String>,
+    /// `--secret`s, e.g. `id=netrc,src=.netrc`.
+    pub secrets: Vec<String>,
+    /// `--build-env`s, `KEY=VALUE` or `KEY`.
+    pub build_env: Vec<String>,
+    pub env_file: Option<String>,
+    pub offline: bool,
+    pub prefetch: bool,
+    pub locked: bool,
+    pub frozen: bool,
+    pub strict: bool,
+    pub vendor: Option<String>,
+
+    // The artifact.
+    /// `--name`, otherwise the project directory's.
+    pub name: Option<String>,
+    /// `--include`s, `path[:dest]`, otherwise `BlackMagic.toml`'s.
+    pub includes: Vec<String>,
+    pub lambda_runtime: Option<LambdaRuntime>,
+    pub serverless: Option<Serverless>,
+    pub compression: Option<Compression>,
+    pub strict_size: bool,
+    pub size_report: bool,
+    pub sbom: Option<SbomFormat>,
+    pub sbom_label: bool,
+    pub sign: Option<SignMethod>,
+    pub out_dir: Option<String>,
+    pub artifact_name: Option<String>,
+    /// `--s3`, e.g. `s3://my-bucket/lambdas/`.
+    pub s3: Option<String>,
+    pub region: Option<String>,
+    pub aws_profile: Option<String>,
+    pub deploy: Option<String>,
+    pub emit_sam: bool,
+    pub emit_terraform: bool,
+
+    // The image.
+    pub no_image: bool,
+    pub base: Option<String>,
+    pub dockerfile_template: Option<String>,
+    pub entrypoint: Option<String>,
+    pub cmd: Option<String>,
+    pub expose: Vec<String>,
+    /// `--env`s, `KEY=VALUE`.
+    pub env: Vec<String>,
+    /// `--user`, `uid[:gid]`.
+    pub user: Option<String>,
+    pub with_ca_certs: bool,
+    pub with_tzdata: bool,
+    pub debug_image: bool,
+    pub tags: Vec<String>,
+    pub tag_git: bool,
+    pub push: Vec<String>,
+    pub ecr: Option<String>,
+    pub smoke_test: Option<String>,
+    /// `--smoke-test-timeout`, in seconds, 30 by default.
+    pub smoke_test_timeout: Option<u64>,
+    pub integration_test: bool,
+    pub diff_against: Option<String>,
+    pub scan: bool,
+    pub scan_fail_on: Option<Severity>,
+    pub export_oci: Option<String>,
+    /// `--load-into`, `kind:<cluster>` or `minikube[:<profile>]`.
+    pub load_into: Option<String>,
+    pub emit_compose: bool,
+
+    // Only from the command line, which prints a plan or hands over the terminal instead of producing an artifact.
+    pub(crate) no_side_effects: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) deps_only: bool,
+    pub(crate) shell: bool,
+    pub(crate) debug_shell: bool,
+}
+
+impl BuildConfig {
+    /// Building the project in `project_dir` as `mode`, with the defaults for everything else.
+    pub fn new(project_dir: impl AsRef<Path>, mode: Mode) -> BuildConfig {
+        BuildConfig { project_dir: project_dir.as_ref().to_owned(), mode, ..BuildConfig::default() }
+    }
+
+    /// A Lambda zip of the project in `project_dir`, like `black_magic --lambda`.
+    pub fn lambda(project_dir: impl AsRef<Path>) -> BuildConfig {
+        BuildConfig::new(project_dir, Mode::Lambda)
+    }
+
+    /// A docker image of the project in `project_dir`, like `black_magic --docker`.
+    pub fn docker(project_dir: impl AsRef<Path>) -> BuildConfig {
+        BuildConfig::new(project_dir, Mode::Docker)
+    }
+
+    pub fn arch(mut self, arch: Arch) -> BuildConfig {
+        self.arch = Some(arch);
+        self
+    }
+
+    pub fn bin(mut self, bin: &str) -> BuildConfig {
+        self.bin = Some(bin.to_owned());
+        self
+    }
+
+    pub fn profile(mut self, profile: &str) -> BuildConfig {
+        self.profile = Some(profile.to_owned());
+        self
+    }
+
+    pub fn features(mut self, features: &[&str]) -> BuildConfig {
+        self.features = features.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    /// Every option, as `--skip-unchanged` compares them with the last build's, but those only changing how the build runs
+    /// (waiting, retrying, reporting), not what it produces. The project is compared by its source instead, see `state`.
+    pub(crate) fn fingerprint(&self) -> String {
+        let mut compared = self.clone();
+        compared.project_dir = PathBuf::new();
+        compared.skip_unchanged = false;
+        compared.from_phase = None;
+        compared.no_wait = false;
+        compared.retries = 0;
+        compared.retry_delay = None;
+        compared.keep_on_failure = false;
+        compared.resource_report = false;
+        format!("{:?}", compared)
+    }
+
+    /// The build the command line's `matches` ask for, of the project in the current directory.
+    pub(crate) fn from_matches(matches: &ArgMatches) -> Result<BuildConfig, BmError> {
+        let mode = if matches.is_present("LAMBDA_IMAGE") {
+            Mode::LambdaImage
+        } else if matches.is_present("DOCKER") {
+            Mode::Docker
+        } else if matches.is_present("LAMBDA") {
+            Mode::Lambda
+        } else {
+            return Err(BmError::Environment("You need to specify what to build. See `--help`.".to_owned()));
+        };
+        let flag = |arg: &str| matches.is_present(arg);
+        let value = |arg: &str| matches.value_of(arg).map(|v| v.to_owned());
+        let values = |arg: &str| matches.values_of(arg).into_iter().flatten().map(|v| v.to_owned()).collect::<Vec<_>>();
+        // Validated by clap, as are the names parsed below.
+        fn number<T: FromStr>(matches: &ArgMatches, arg: &str) -> Option<T> {
+            matches.value_of(arg).and_then(|v| v.parse().ok())
+        }
+        // Those with defaults, which only count if they're given.
+        let given = |arg: &str| matches.value_of(arg).filter(|_| matches.occurrences_of(arg) > 0);
+
+        let mut config = BuildConfig::new(crate::mounts::current_dir()?, mode);
+        config.arch = given("ARCH").map(Arch::from_name);
+        config.libc = given("LIBC").map(Libc::from_name);
+        config.target_triple = value("TARGET_TRIPLE");
+        config.cpu_baseline = matches.value_of("CPU_BASELINE").map(CpuBaseline::from_name);
+        config.bin = value("BIN");
+        config.example = value("EXAMPLE");
+        config.bench = value("BENCH");
+        config.profile = value("PROFILE");
+        config.features = matches.value_of("FEATURES").map(Features::split).unwrap_or_default();
+        config.no_default_features = flag("NO_DEFAULT_FEATURES");
+        config.all_features = flag("ALL_FEATURES");
+        config.isolate_features = flag("ISOLATE_FEATURES");
+        config.cargo_args = [values("CARGO_ARG"), values("CARGO_ARGS")].concat();
+        config.rustflags = value("RUSTFLAGS");
+        config.jobs = number(matches, "JOBS");
+        config.openssl = matches.value_of("OPENSSL").and_then(OpensslMode::from_name);
+        config.no_auto_static = flag("NO_AUTO_STATIC");
+        config.hardened = flag("HARDENED");
+        config.reproducible = flag("REPRODUCIBLE");
+        config.strip = flag("STRIP");
+        config.upx = flag("UPX");
+        config.test = flag("TEST");
+        config.clippy = flag("CLIPPY");
+
+        config.runtime = matches.value_of("RUNTIME").and_then(Runtime::from_name);
+        config.backend = matches.value_of("BACKEND").and_then(Backend::from_name);
+        config.builder_image = value("BUILDER_IMAGE");
+        config.builder_tag = value("BUILDER_TAG");
+        config.builder_cache_from = value("BUILDER_CACHE_FROM");
+        config.builder_cache_push = flag("BUILDER_CACHE_PUSH");
+        config.update_builder = flag("UPDATE_BUILDER");
+        config.auto_update_builder = flag("AUTO_UPDATE_BUILDER");
+        config.layered = flag("LAYERED");
+        config.dockerfile_strategy = matches.value_of("STRATEGY") == Some("dockerfile");
+        config.sccache = flag("SCCACHE");
+        config.cache_server = value("CACHE_SERVER");
+        config.no_cache = flag("NO_CACHE");
+        config.no_artifact_cache = flag("NO_ARTIFACT_CACHE");
+        config.skip_unchanged = flag("SKIP_UNCHANGED");
+        config.from_phase = matches.value_of("FROM_PHASE").and_then(Phase::from_name);
+        config.cargo_home = value("CARGO_HOME");
+        config.cargo_home_volume = value("CARGO_HOME_VOLUME");
+        config.cargo_cache = matches.value_of("CARGO_CACHE").and_then(CargoCache::from_name);
+        config.cargo_lock_timeout = number(matches, "CARGO_LOCK_TIMEOUT");
+        config.no_wait = flag("NO_WAIT");
+        config.shadow_target = flag("SHADOW_TARGET");
+        config.read_only_source = flag("READ_ONLY_SOURCE");
+        config.user_map = match (flag("USER_MAP"), flag("NO_USER_MAP")) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        };
+        config.keep_on_failure = flag("KEEP_ON_FAILURE");
+        config.resource_report = flag("RESOURCE_REPORT");
+        config.cpus = value("CPUS");
+        config.memory = value("MEMORY");
+        config.timeout = number(matches, "TIMEOUT");
+        config.retries = number(matches, "RETRIES").unwrap_or(0);
+        config.retry_delay = number(matches, "RETRY_DELAY");
+
+        config.ssh = flag("SSH");
+        config.ssh_known_hosts = value("SSH_KNOWN_HOSTS");
+        config.gitconfig = value("GITCONFIG");
+        config.cargo_config = flag("CARGO_CONFIG");
+        config.registry_tokens = values("REGISTRY_TOKEN");
+        config.network = value("NETWORK");
+        config.dns = values("DNS");
+        config.add_hosts = values("ADD_HOST");
+        config.secrets = values("SECRET");
+        config.build_env = values("BUILD_ENV");
+        config.env_file = value("ENV_FILE");
+        config.offline = flag("OFFLINE");
+        config.prefetch = flag("PREFETCH");
+        config.locked = flag("LOCKED");
+        config.frozen = flag("FROZEN");
+        config.strict = flag("STRICT");
+        config.vendor = value("VENDOR");
+
+        config.name = value("NAME");
+        config.includes = values("INCLUDE");
+        config.lambda_runtime = matches.value_of("LAMBDA_RUNTIME").and_then(LambdaRuntime::from_name);
+        config.serverless = matches.value_of("SERVERLESS").and_then(Serverless::from_name);
+        config.compression = matches.value_of("COMPRESSION").map(Compression::from_name);
+        config.strict_size = flag("STRICT_SIZE");
+        config.size_report = flag("SIZE_REPORT");
+        config.sbom = matches.value_of("SBOM").and_then(SbomFormat::from_name);
+        config.sbom_label = flag("SBOM_LABEL");
+        config.sign = matches.value_of("SIGN").and_then(SignMethod::from_name);
+        config.out_dir = value("OUT_DIR");
+        config.artifact_name = value("ARTIFACT_NAME");
+        config.s3 = value("S3");
+        config.region = value("REGION");
+        config.aws_profile = value("AWS_PROFILE");
+        config.deploy = value("DEPLOY");
+        config.emit_sam = flag("EMIT_SAM");
+        config.emit_terraform = flag("EMIT_TERRAFORM");
+
+        config.no_image = flag("NO_IMAGE");
+        config.base = value("BASE");
+        config.dockerfile_template = value("DOCKERFILE_TEMPLATE");
+        config.entrypoint = value("ENTRYPOINT");
+        config.cmd = value("CMD");
+        config.expose = values("EXPOSE");
+        config.env = values("ENV");
+        config.user = value("USER");
+        config.with_ca_certs = flag("WITH_CA_CERTS");
+        config.with_tzdata = flag("WITH_TZDATA");
+        config.debug_image = flag("DEBUG_IMAGE");
+        config.tags = values("TAG");
+        config.tag_git = flag("TAG_GIT");
+        config.push = values("PUSH");
+        config.ecr = value("ECR");
+        config.smoke_test = value("SMOKE_TEST");
+        config.smoke_test_timeout = number(matches, "SMOKE_TEST_TIMEOUT");
+        config.integration_test = flag("INTEGRATION_TEST");
+        config.diff_against = value("DIFF_AGAINST");
+        config.scan = flag("SCAN");
+        config.scan_fail_on = matches.value_of("SCAN_FAIL_ON").and_then(Severity::from_name);
+        config.export_oci = value("EXPORT_OCI");
+        config.load_into = value("LOAD_INTO");
+        config.emit_compose = flag("EMIT_COMPOSE");
+
+        config.no_side_effects = flag("NO_SIDE_EFFECTS") || flag("DRY_RUN");
+        config.dry_run = flag("DRY_RUN");
+        config.deps_only = flag("DEPS_ONLY");
+        config.shell = flag("SHELL");
+        config.debug_shell = flag("DEBUG_SHELL");
+        Ok(config)
+    }
+}
+
+/// Something that happened during a build.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Event {
+    /// A progress message, as the command line prints it.
+    Progress(String),
+    /// What the command line only prints with `--verbose`, like the commands being run.
+    Detail(String),
+    /// Something that went wrong without failing the build.
+    Warning(String),
+}
+
+/// The file a build produced.
+#[derive(Clone, Debug)]
+pub struct Artifact {
+    /// The zip, or the image's tarball.
+    pub path: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// What a build produced.
+#[derive(Clone, Debug)]
+pub struct BuildOutput {
+    pub artifact: Artifact,
+    /// The local image, for docker builds.
+    pub image: Option<String>,
+    pub target: String,
+    pub duration_seconds: f64,
+    /// Everything the command line reports with `--output-format json`, e.g. the S3 URL or resource report.
+    pub record: Value,
+}
+
+type Callback = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/// Runs builds, reporting their progress.
+#[derive(Default)]
+pub struct Builder {
+    on_event: Option<Callback>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Calls `callback` for every event, e.g. to print progress or send it down a channel. Without one, nothing's reported.
+    pub fn on_event(mut self, callback: impl Fn(&Event) + Send + Sync + 'static) -> Builder {
+        self.on_event = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn build(&self, config: &BuildConfig) -> Result<BuildOutput, BmError> {
+        let callback = self.on_event.clone().unwrap_or_else(|| Arc::new(|_: &Event| {}));
+        let built = output::reporting_to(callback, || crate::build(config, Instant::now()))?;
+        built.ok_or_else(|| BmError::Packaging("The build finished without an artifact.".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_what_changes_the_artifact_only() {
+        let config = BuildConfig::lambda("/src/api").features(&["tracing"]);
+        let mut rerun = BuildConfig::lambda("/home/me/api").features(&["tracing"]);
+        rerun.skip_unchanged = true;
+        rerun.retries = 3;
+        rerun.no_wait = true;
+        assert_eq!(config.fingerprint(), rerun.fingerprint());
+        assert_ne!(config.fingerprint(), config.clone().arch(Arch::Aarch64).fingerprint());
+        assert_ne!(config.fingerprint(), BuildConfig::docker("/src/api").features(&["tracing"]).fingerprint());
+    }
+}
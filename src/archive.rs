@@ -0,0 +1,385 @@
+//! Just enough zip, tar, gzip, and ELF reading to look inside artifacts, without unpacking them or needing host tools.
+
+/// A file, directory, or symlink in an archive.
+pub struct Entry {
+    pub path: String,
+    /// Permission bits, when the archive records them.
+    pub mode: Option<u32>,
+    pub kind: EntryKind,
+    /// The uncompressed contents. Empty for directories, the target for symlinks.
+    pub contents: Vec<u8>,
+}
+
+#[derive(PartialEq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16, String> {
+    data.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| "Unexpected end of archive.".to_owned())
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32, String> {
+    data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| "Unexpected end of archive.".to_owned())
+}
+
+fn u64_at(data: &[u8], at: usize) -> Result<u64, String> {
+    let b = data.get(at..at + 8).ok_or_else(|| "Unexpected end of file.".to_owned())?;
+    Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+fn kind_from_mode(mode: u32) -> EntryKind {
+    match mode & 0o170000 {
+        0o040000 => EntryKind::Dir,
+        0o120000 => EntryKind::Symlink,
+        _ => EntryKind::File,
+    }
+}
+
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+
+/// Finds a zip's central directory, returning where it starts and how many entries it has.
+fn zip_directory(data: &[u8]) -> Result<(usize, usize), String> {
+    // The end record is last, followed by a comment of up to 64KiB.
+    let search_from = data.len().saturating_sub(22 + 0xffff);
+    let end = (search_from..data.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(data, i) == Ok(END_OF_DIRECTORY))
+        .ok_or("Not a zip file.")?;
+    Ok((u32_at(data, end + 16)? as usize, u16_at(data, end + 10)? as usize))
+}
+
+/// The total uncompressed size of a zip's entries, without decompressing them.
+pub fn zip_uncompressed_size(data: &[u8]) -> Result<u64, String> {
+    let (mut at, count) = zip_directory(data)?;
+    let mut total = 0;
+    for _ in 0..count {
+        if u32_at(data, at)? != DIRECTORY_ENTRY {
+            return Err("Corrupt zip central directory.".to_owned());
+        }
+        total += u32_at(data, at + 24)? as u64;
+        at += 46 + u16_at(data, at + 28)? as usize + u16_at(data, at + 30)? as usize + u16_at(data, at + 32)? as usize;
+    }
+    Ok(total)
+}
+
+/// Reads every entry of a zip, from its central directory.
+pub fn read_zip(data: &[u8]) -> Result<Vec<Entry>, String> {
+    let (mut at, count) = zip_directory(data)?;
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(data, at)? != DIRECTORY_ENTRY {
+            return Err("Corrupt zip central directory.".to_owned());
+        }
+        let method = u16_at(data, at + 10)?;
+        let compressed_size = u32_at(data, at + 20)? as usize;
+        let name_len = u16_at(data, at + 28)? as usize;
+        let extra_len = u16_at(data, at + 30)? as usize;
+        let comment_len = u16_at(data, at + 32)? as usize;
+        let made_by_unix = data.get(at + 5) == Some(&3);
+        let external = u32_at(data, at + 38)?;
+        let local = u32_at(data, at + 42)? as usize;
+        let path = data.get(at + 46..at + 46 + name_len).ok_or("Unexpected end of archive.")?;
+        let path = String::from_utf8_lossy(path).into_owned();
+        at += 46 + name_len + extra_len + comment_len;
+
+        let data_start = local + 30 + u16_at(data, local + 26)? as usize + u16_at(data, local + 28)? as usize;
+        let compressed = data.get(data_start..data_start + compressed_size).ok_or("Unexpected end of archive.")?;
+        let contents = match method {
+            0 => compressed.to_vec(),
+            8 => inflate(compressed)?,
+            m => return Err(format!("`{}` uses an unsupported compression method ({}).", path, m)),
+        };
+
+        let mode = if made_by_unix { Some(external >> 16) } else { None };
+        let kind = match mode {
+            Some(m) if m != 0 => kind_from_mode(m),
+            _ if path.ends_with('/') => EntryKind::Dir,
+            _ => EntryKind::File,
+        };
+        entries.push(Entry { path: path.trim_end_matches('/').to_owned(), mode: mode.map(|m| m & 0o7777), kind, contents });
+    }
+    Ok(entries)
+}
+
+fn octal(field: &[u8]) -> u64 {
+    field
+        .iter()
+        .skip_while(|b| **b == b' ')
+        .take_while(|b| (b'0'..=b'7').contains(*b))
+        .fold(0, |n, b| n * 8 + (b - b'0') as u64)
+}
+
+fn tar_string(field: &[u8]) -> String {
+    String::from_utf8_lossy(field.split(|b| *b == 0).next().unwrap_or(&[])).into_owned()
+}
+
+/// Reads every entry of an uncompressed tar, including GNU long names.
+pub fn read_tar(data: &[u8]) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut at = 0;
+    let mut long_name = None;
+    while let Some(header) = data.get(at..at + 512) {
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = octal(&header[124..136]) as usize;
+        let contents = data.get(at + 512..at + 512 + size).ok_or("Unexpected end of archive.")?.to_vec();
+        at += 512 + size.div_ceil(512) * 512;
+
+        let mut path = tar_string(&header[0..100]);
+        if &header[257..262] == b"ustar" && header[345] != 0 {
+            path = format!("{}/{}", tar_string(&header[345..500]), path);
+        }
+        let path = long_name.take().unwrap_or(path);
+        let mode = Some(octal(&header[100..108]) as u32 & 0o7777);
+
+        let (kind, contents) = match header[156] {
+            b'L' => {
+                long_name = Some(tar_string(&contents));
+                continue;
+            }
+            b'0' | 0 | b'7' => (EntryKind::File, contents),
+            b'5' => (EntryKind::Dir, Vec::new()),
+            b'2' => (EntryKind::Symlink, tar_string(&header[157..257]).into_bytes()),
+            // Hard links, devices, and extended headers aren't anything an artifact should have, and aren't worth listing.
+            _ => continue,
+        };
+        let path = path.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/').to_owned();
+        if !path.is_empty() {
+            entries.push(Entry { path, mode, kind, contents });
+        }
+    }
+    Ok(entries)
+}
+
+/// Decompresses a gzip file.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.get(0..3) != Some(&[0x1f, 0x8b, 8]) {
+        return Err("Not a gzip file.".to_owned());
+    }
+    let flags = data[3];
+    let mut at = 10;
+    if flags & 4 != 0 {
+        at += 2 + u16_at(data, at)? as usize;
+    }
+    // The file name and comment are NUL terminated.
+    for flag in &[8, 16] {
+        if flags & flag != 0 {
+            at += data.get(at..).and_then(|d| d.iter().position(|b| *b == 0)).ok_or("Corrupt gzip header.")? + 1;
+        }
+    }
+    if flags & 2 != 0 {
+        at += 2;
+    }
+    inflate(data.get(at..).ok_or("Unexpected end of gzip file.")?)
+}
+
+/// Reads bits least significant first, as deflate packs them.
+struct Bits<'a> {
+    data: &'a [u8],
+    at: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn take(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = *self.data.get(self.at).ok_or("Unexpected end of compressed data.")?;
+            self.buffer |= (byte as u32) << self.count;
+            self.at += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+}
+
+/// A canonical Huffman code, as deflate describes them: how many codes of each length, and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = symbol as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).copied().ok_or_else(|| "Corrupt compressed data.".to_owned());
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Corrupt compressed data.".to_owned())
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289,
+    16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Decompresses raw deflate data (RFC 1951).
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = Bits { data, at: 0, buffer: 0, count: 0 };
+    let mut out = Vec::with_capacity(data.len() * 3);
+
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                // Stored blocks start on a byte boundary.
+                bits.buffer = 0;
+                bits.count = 0;
+                let len = u16_at(data, bits.at)? as usize;
+                let block = data.get(bits.at + 4..bits.at + 4 + len).ok_or("Unexpected end of compressed data.")?;
+                out.extend_from_slice(block);
+                bits.at += 4 + len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                for (symbol, l) in lengths.iter_mut().enumerate() {
+                    *l = match symbol {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let literal_count = bits.take(5)? as usize + 257;
+                let distance_count = bits.take(5)? as usize + 1;
+                let code_count = bits.take(4)? as usize + 4;
+
+                const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+                let mut code_lengths = [0u8; 19];
+                for &i in &ORDER[..code_count] {
+                    code_lengths[i] = bits.take(3)? as u8;
+                }
+                let codes = Huffman::new(&code_lengths);
+
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (value, repeat) = match codes.decode(&mut bits)? {
+                        l @ 0..=15 => (l as u8, 1),
+                        16 => (*lengths.last().ok_or("Corrupt compressed data.")?, 3 + bits.take(2)?),
+                        17 => (0, 3 + bits.take(3)?),
+                        _ => (0, 11 + bits.take(7)?),
+                    };
+                    lengths.extend((0..repeat).map(|_| value));
+                }
+                if lengths.len() > literal_count + distance_count {
+                    return Err("Corrupt compressed data.".to_owned());
+                }
+                let (literal_lengths, distance_lengths) = lengths.split_at(literal_count);
+                inflate_block(&mut bits, &mut out, &Huffman::new(literal_lengths), &Huffman::new(distance_lengths))?;
+            }
+            _ => return Err("Corrupt compressed data.".to_owned()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err("Corrupt compressed data.".to_owned());
+                }
+                let length = LENGTH_BASE[i] as usize + bits.take(LENGTH_EXTRA[i] as u32)? as usize;
+                let d = distances.decode(bits)? as usize;
+                if d >= DISTANCE_BASE.len() {
+                    return Err("Corrupt compressed data.".to_owned());
+                }
+                let distance = DISTANCE_BASE[d] as usize + bits.take(DISTANCE_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err("Corrupt compressed data.".to_owned());
+                }
+                // Copies can overlap what they're writing, so go a byte at a time.
+                let start = out.len() - distance;
+                for j in 0..length {
+                    out.push(out[start + j]);
+                }
+            }
+        }
+    }
+}
+
+/// How an executable is linked.
+pub enum Linkage {
+    Static,
+    StaticPie,
+    /// With the interpreter it needs.
+    Dynamic(String),
+}
+
+/// Reads the linkage of a 64-bit little endian ELF executable (x86_64 or aarch64), or `None` if `data` isn't one.
+pub fn elf_linkage(data: &[u8]) -> Option<Linkage> {
+    const PT_DYNAMIC: u32 = 2;
+    const PT_INTERP: u32 = 3;
+
+    if data.get(0..6) != Some(&[0x7f, b'E', b'L', b'F', 2, 1]) {
+        return None;
+    }
+    let program_headers = u64_at(data, 32).ok()? as usize;
+    let header_size = u16_at(data, 54).ok()? as usize;
+    let header_count = u16_at(data, 56).ok()? as usize;
+
+    let mut dynamic = false;
+    for i in 0..header_count {
+        let header = program_headers + i * header_size;
+        match u32_at(data, header).ok()? {
+            PT_INTERP => {
+                let offset = u64_at(data, header + 8).ok()? as usize;
+                let size = u64_at(data, header + 32).ok()? as usize;
+                let interpreter = data.get(offset..offset + size)?;
+                return Some(Linkage::Dynamic(tar_string(interpreter)));
+            }
+            PT_DYNAMIC => dynamic = true,
+            _ => {}
+        }
+    }
+    Some(if dynamic { Linkage::StaticPie } else { Linkage::Static })
+}
@@ -0,0 +1,244 @@
+//! Packaging on this machine rather than in the build container. Lambda zips always are, with the `zip` crate, unless they
+//! can't be (see below): the build container copies what goes in the zip into `target/black_magic/<artifact>.package/`, laid
+//! out as in the zip, and black_magic zips it here, so the builder image needn't have `zip` at all.
+//!
+//! Docker's tarballs are archived in the build container, with its `tar` and `gzip`. The default builder images have them,
+//! but one built from your own `--builder-image`, or changed by `[builder]`'s `setup_script`, mightn't, and finding out after
+//! compiling would waste the whole build. So the build container checks for them once it gets to packaging, and without
+//! them it copies what it would have archived into the package dir for black_magic to archive here instead, with the `tar`
+//! and `flate2` crates, the same way (sorted, with fixed times and owners, for `--reproducible`).
+//!
+//! Neither can be done for `--s3`'s streamed zips, `--layered` builds, or with `post-package` hooks in the container, which
+//! need the archive there. For those, the build container checks it has what it needs before compiling anything, and stops
+//! then.
+
+use crate::bundle::Compression;
+use crate::error::BmError;
+use crate::output::status;
+use crate::shell_quote;
+use crate::tags;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+use tar::EntryType;
+use tar::Header;
+use tar::HeaderMode;
+use zip::DateTime;
+use zip::ZipWriter;
+
+/// Printed to stderr inside the container, with the missing tool, so the failure can be told apart from a compile error.
+pub const MISSING: &str = "BM_MISSING_ARCHIVER";
+
+/// What packaging the artifact needs.
+pub fn needed(is_docker: bool) -> &'static [&'static str] {
+    if is_docker {
+        &["tar", "gzip"]
+    } else {
+        &["zip"]
+    }
+}
+
+/// Where the build container leaves what it couldn't archive, relative to the project.
+pub fn package_dir(artifact_name: &str) -> String {
+    format!("target/black_magic/{}.package", artifact_name)
+}
+
+/// Prefixed to the build command, stopping it before it compiles anything if the builder image doesn't have `tools`.
+pub fn check_cmd(tools: &[&str]) -> String {
+    tools.iter().map(|t| format!("(command -v {tool} > /dev/null || (echo '{} {tool}' >&2 && false)) && ", MISSING, tool = t)).collect()
+}
+
+/// Shell command running `archive` if the builder image has `tools`, or `stage`, copying what it would archive into the
+/// package dir, if it hasn't. Both start with ` && `.
+pub fn fallback_cmd(tools: &[&str], archive: &str, stage: &str) -> String {
+    let has: Vec<String> = tools.iter().map(|t| format!("command -v {} > /dev/null", t)).collect();
+    format!(" && if {}; then true{}; else true{}; fi", has.join(" && "), archive, stage)
+}
+
+/// Shell command copying `files` (absolute paths in the build container, already shell quoted) into `dir` (relative to the
+/// project) at the same paths. It starts with ` && `.
+pub fn stage_cmd(files: &str, dir: &str) -> String {
+    format!(" && rm -rf {dir} && mkdir -p {dir} && cp -a --parents {} {dir}", files, dir = shell_quote(dir))
+}
+
+/// Archives what the build container left in `package_dir`, if it left anything, into `artifact`, as it would have itself.
+pub fn package(project_dir: &Path, package_dir: &str, artifact: &Path, is_docker: bool, source_date_epoch: Option<u64>, compression: Compression) -> Result<(), BmError> {
+    if !project_dir.join(package_dir).is_dir() {
+        return Ok(());
+    }
+    if is_docker {
+        status!("The builder image has no `{}`, so the tarball is packaged on this machine.", needed(is_docker).join("` or `"));
+    }
+    archive(project_dir, package_dir, artifact, is_docker, source_date_epoch, compression)
+}
+
+/// Archives what's in `package_dir` into `artifact` on this machine, then removes it.
+pub fn archive(project_dir: &Path, package_dir: &str, artifact: &Path, is_docker: bool, source_date_epoch: Option<u64>, compression: Compression) -> Result<(), BmError> {
+    let artifact = &project_dir.join(artifact);
+    let dir = project_dir.join(package_dir);
+    let packaged = if is_docker { tarball(&dir, artifact, source_date_epoch) } else { zip(&dir, artifact, source_date_epoch, compression) };
+    let _ = fs::remove_dir_all(&dir);
+    if let Err(e) = packaged {
+        let _ = fs::remove_file(artifact);
+        return Err(BmError::Packaging(format!("Unable to package the artifact on this machine: {}", e)));
+    }
+    Ok(())
+}
+
+/// Tars and gzips everything in `dir` into `artifact`, as `tar -czf` would from inside it: in sorted order, with symlinks kept
+/// as symlinks and everything's mode, gzipped without a timestamp. With `source_date_epoch` (see `reproducible`), everything
+/// has that time and is owned by root, otherwise the times and owners it has.
+fn tarball(dir: &Path, artifact: &Path, source_date_epoch: Option<u64>) -> io::Result<()> {
+    let mut paths = Vec::new();
+    walk(dir, &mut paths)?;
+    paths.sort();
+    let mut tar = tar::Builder::new(GzEncoder::new(fs::File::create(artifact)?, flate2::Compression::default()));
+    for path in paths {
+        let name = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        let metadata = fs::symlink_metadata(&path)?;
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+        header.set_mode(mode(&metadata));
+        if let Some(epoch) = source_date_epoch {
+            header.set_mtime(epoch);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_username("")?;
+            header.set_groupname("")?;
+        }
+        if metadata.file_type().is_symlink() {
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            tar.append_link(&mut header, name, fs::read_link(&path)?)?;
+        } else if metadata.is_dir() {
+            header.set_size(0);
+            tar.append_data(&mut header, format!("{}/", name), io::empty())?;
+        } else {
+            tar.append_data(&mut header, name, fs::File::open(&path)?)?;
+        }
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Zips everything in `dir` into `artifact`, as `zip -r -y` would from inside it: in sorted order, with symlinks kept as
+/// symlinks and everything's mode. `bootstrap` is always zipped executable. With `source_date_epoch` (see `reproducible`),
+/// everything has that time, otherwise when it was last modified.
+fn zip(dir: &Path, artifact: &Path, source_date_epoch: Option<u64>, compression: Compression) -> io::Result<()> {
+    let mut paths = Vec::new();
+    walk(dir, &mut paths)?;
+    paths.sort();
+    let mut zip = ZipWriter::new(fs::File::create(artifact)?);
+    for path in paths {
+        let name = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+        let metadata = fs::symlink_metadata(&path)?;
+        let modified = source_date_epoch
+            .or_else(|| metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()))
+            .unwrap_or(0);
+        let options = compression
+            .file_options()
+            .unix_permissions(if name == "bootstrap" { 0o755 } else { mode(&metadata) })
+            .last_modified_time(zip_time(modified));
+        if metadata.file_type().is_symlink() {
+            zip.add_symlink(name, fs::read_link(&path)?.to_string_lossy(), options)?;
+        } else if metadata.is_dir() {
+            zip.add_directory(name, options)?;
+        } else {
+            zip.start_file(name, options)?;
+            io::copy(&mut fs::File::open(&path)?, &mut zip)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Everything under `dir`, without following symlinks.
+fn walk(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        paths.push(entry.path());
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), paths)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() { 0o755 } else { 0o644 }
+}
+
+/// `epoch` as a zip records it, which can't be before 1980.
+fn zip_time(epoch: u64) -> DateTime {
+    let (year, month, day) = tags::civil(epoch);
+    let seconds = epoch % 86400;
+    DateTime::from_date_and_time(year as u16, month as u8, day as u8, (seconds / 3600) as u8, (seconds % 3600 / 60) as u8, (seconds % 60) as u8)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive;
+    use crate::archive::EntryKind;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn zips_bootstrap_executable_with_symlinks_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("package");
+        fs::create_dir_all(dir.join("static")).unwrap();
+        fs::write(dir.join("bootstrap"), "#!/bin/sh").unwrap();
+        fs::set_permissions(dir.join("bootstrap"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(dir.join("static/index.html"), "<html>").unwrap();
+        symlink("static/index.html", dir.join("index.html")).unwrap();
+
+        for compression in [Compression::Store, Compression::Best] {
+            let artifact = root.path().join("api.zip");
+            zip(&dir, &artifact, Some(1_700_000_000), compression).unwrap();
+            let entries = archive::read_zip(&fs::read(&artifact).unwrap()).unwrap();
+            let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+            assert_eq!(paths, ["bootstrap", "index.html", "static", "static/index.html"]);
+            assert_eq!(entries[0].mode.map(|m| m & 0o777), Some(0o755));
+            assert_eq!(entries[0].contents, b"#!/bin/sh");
+            assert!(entries[1].kind == EntryKind::Symlink && entries[1].contents == b"static/index.html");
+            assert_eq!(entries[3].contents, b"<html>");
+        }
+    }
+
+    #[test]
+    fn tarballs_reproducibly_with_symlinks_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("package");
+        fs::create_dir_all(dir.join("etc")).unwrap();
+        fs::write(dir.join("api"), "#!/bin/sh").unwrap();
+        fs::set_permissions(dir.join("api"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("etc/passwd"), "root:x:0:0::/:").unwrap();
+        symlink("api", dir.join("server")).unwrap();
+
+        let first = root.path().join("first.tar.gz");
+        tarball(&dir, &first, Some(1_700_000_000)).unwrap();
+        fs::write(dir.join("etc/passwd"), "root:x:0:0::/:").unwrap();
+        let second = root.path().join("second.tar.gz");
+        tarball(&dir, &second, Some(1_700_000_000)).unwrap();
+        assert_eq!(fs::read(&first).unwrap(), fs::read(&second).unwrap());
+
+        let entries = archive::read_tar(&archive::gunzip(&fs::read(&first).unwrap()).unwrap()).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["api", "etc", "etc/passwd", "server"]);
+        assert_eq!(entries[0].mode.map(|m| m & 0o777), Some(0o755));
+        assert_eq!(entries[2].contents, b"root:x:0:0::/:");
+        assert!(entries[3].kind == EntryKind::Symlink && entries[3].contents == b"api");
+    }
+}
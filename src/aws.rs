@@ -0,0 +1,293 @@
+//! Talking to AWS, with the AWS SDK for Rust.
+//!
+//! Credentials and the default region come from the usual places (`AWS_*` variables, `~/.aws/config` and
+//! `~/.aws/credentials`, SSO, instance roles), for `--aws-profile`'s profile if it's given, so nothing needs installing. The
+//! SDK is async, so each `Aws` runs its calls on a small runtime of its own, and the rest of black_magic stays synchronous.
+
+use crate::output::status;
+use crate::runtime::Runtime;
+use crate::stream::S3Location;
+use aws_config::BehaviorVersion;
+use aws_config::Region;
+use aws_config::SdkConfig;
+use aws_sdk_ecr::types::ImageIdentifier;
+use aws_sdk_lambda::primitives::Blob;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::CompletedMultipartUpload;
+use aws_sdk_s3::types::CompletedPart;
+use aws_smithy_types::error::display::DisplayErrorContext;
+use std::error::Error;
+use std::fs;
+use std::future::Future;
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::OnceLock;
+
+/// Which AWS account/region to talk to. Unset fields fall back to the SDK's own configuration.
+pub struct Aws {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    sdk: OnceLock<Result<Sdk, String>>,
+}
+
+struct Sdk {
+    runtime: tokio::runtime::Runtime,
+    config: SdkConfig,
+}
+
+/// `error`, with everything the SDK knows about it, e.g. the service's error code and message.
+fn describe(what: &str, error: impl Error) -> String {
+    format!("{}: {}", what, DisplayErrorContext(error))
+}
+
+/// S3 keys go in `x-amz-copy-source` URL-encoded.
+fn url_encode(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl Aws {
+    pub fn new(region: Option<String>, profile: Option<String>) -> Aws {
+        Aws { region, profile, sdk: OnceLock::new() }
+    }
+
+    /// The runtime and configuration, loaded the first time they're needed.
+    fn sdk(&self) -> Result<&Sdk, String> {
+        self.sdk
+            .get_or_init(|| {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(|e| format!("Unable to start the AWS SDK: {}", e))?;
+                let mut loader = aws_config::defaults(BehaviorVersion::latest());
+                if let Some(r) = &self.region {
+                    loader = loader.region(Region::new(r.clone()));
+                }
+                if let Some(p) = &self.profile {
+                    loader = loader.profile_name(p);
+                }
+                let config = runtime.block_on(loader.load());
+                Ok(Sdk { runtime, config })
+            })
+            .as_ref()
+            .map_err(|e| e.clone())
+    }
+
+    /// Runs an SDK call to completion.
+    fn run<T>(&self, call: impl FnOnce(&SdkConfig) -> T) -> Result<T::Output, String>
+    where
+        T: Future,
+    {
+        let sdk = self.sdk()?;
+        Ok(sdk.runtime.block_on(call(&sdk.config)))
+    }
+
+    /// Uploads `zip` as the new code of an existing function, or points it at the copy already uploaded to `s3` (at
+    /// `s3_version`, with a versioned bucket), and publishes a new version. Returns the published version.
+    /// Lambda's `CodeSha256` for the new code has to match `code_sha256`, so a corrupted upload is caught.
+    pub fn deploy_lambda(&self, function_name: &str, zip: &Path, s3: Option<&S3Location>, s3_version: Option<&str>, code_sha256: &str) -> Result<String, String> {
+        let code = match s3 {
+            Some(_) => None,
+            None => Some(fs::read(zip).map_err(|e| format!("Unable to read `{}`: {}", zip.display(), e))?),
+        };
+        let response = self.run(|config| {
+            let mut update = aws_sdk_lambda::Client::new(config).update_function_code().function_name(function_name).publish(true);
+            update = match (s3, code) {
+                (Some(s3), _) => update.s3_bucket(&s3.bucket).s3_key(&s3.key).set_s3_object_version(s3_version.map(|v| v.to_owned())),
+                (None, code) => update.zip_file(Blob::new(code.unwrap_or_default())),
+            };
+            update.send()
+        })?.map_err(|e| describe("Unable to update the function's code", e))?;
+
+        if let Some(deployed) = response.code_sha256().filter(|d| *d != code_sha256) {
+            return Err(format!("Lambda received code with CodeSha256 {}, but the zip's is {}.", deployed, code_sha256));
+        }
+        Ok(response.version().unwrap_or("$LATEST").to_owned())
+    }
+
+    /// Points a container image Lambda function at `image_uri`, publishing a new version, and returns it.
+    pub fn deploy_lambda_image(&self, function_name: &str, image_uri: &str) -> Result<String, String> {
+        let response = self.run(|config| {
+            aws_sdk_lambda::Client::new(config).update_function_code().function_name(function_name).image_uri(image_uri).publish(true).send()
+        })?.map_err(|e| describe("Unable to update the function's image", e))?;
+        Ok(response.version().unwrap_or("$LATEST").to_owned())
+    }
+
+    /// The runtime the Lambda function is configured with, e.g. `provided.al2023`.
+    pub fn lambda_runtime(&self, function_name: &str) -> Result<String, String> {
+        let configuration = self.run(|config| {
+            aws_sdk_lambda::Client::new(config).get_function_configuration().function_name(function_name).send()
+        })?.map_err(|e| describe("Unable to get the function's configuration", e))?;
+        Ok(configuration.runtime().map(|r| r.as_str()).unwrap_or("").to_owned())
+    }
+
+    /// The region to use, falling back to the configured one.
+    pub fn resolved_region(&self) -> Result<String, String> {
+        match self.sdk()?.config.region() {
+            Some(r) => Ok(r.to_string()),
+            None => Err("No AWS region is configured, pass `--region`.".to_owned()),
+        }
+    }
+
+    /// The account id of the current credentials.
+    pub fn account_id(&self) -> Result<String, String> {
+        let identity = self.run(|config| aws_sdk_sts::Client::new(config).get_caller_identity().send())?
+            .map_err(|e| describe("Unable to get the caller's identity", e))?;
+        identity.account().map(|a| a.to_owned()).ok_or_else(|| "Unable to find the AWS account id.".to_owned())
+    }
+
+    /// The ECR registry host of the current account and region.
+    pub fn ecr_registry(&self) -> Result<String, String> {
+        Ok(format!("{}.dkr.ecr.{}.amazonaws.com", self.account_id()?, self.resolved_region()?))
+    }
+
+    /// Logs the container runtime into the account's ECR registry.
+    pub fn ecr_login(&self, runtime: Runtime, registry: &str) -> Result<(), String> {
+        let token = self.run(|config| aws_sdk_ecr::Client::new(config).get_authorization_token().send())?
+            .map_err(|e| describe("Unable to get an ECR login password", e))?;
+        // `AWS:<password>`, base64 encoded.
+        let password = token
+            .authorization_data()
+            .first()
+            .and_then(|d| d.authorization_token())
+            .and_then(|t| aws_smithy_types::base64::decode(t).ok())
+            .and_then(|t| t.strip_prefix(b"AWS:").map(|p| p.to_vec()))
+            .ok_or_else(|| "ECR didn't return a login password.".to_owned())?;
+
+        let mut login = runtime.command()
+            .arg("login")
+            .arg("--username")
+            .arg("AWS")
+            .arg("--password-stdin")
+            .arg(registry)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Unable to run `{} login`: {}", runtime.name(), e))?;
+        login.stdin.take().unwrap().write_all(&password).map_err(|e| format!("Unable to run `{} login`: {}", runtime.name(), e))?;
+        let login = login.wait_with_output().map_err(|e| format!("Unable to run `{} login`: {}", runtime.name(), e))?;
+        if !login.status.success() {
+            return Err(format!("`{} login` to {} failed: {}", runtime.name(), registry, String::from_utf8_lossy(&login.stderr).trim()));
+        }
+        Ok(())
+    }
+
+    /// The digest of the image tagged `tag` in the ECR repository `repository`, e.g. `sha256:…`.
+    pub fn ecr_image_digest(&self, repository: &str, tag: &str) -> Result<String, String> {
+        let images = self.run(|config| {
+            aws_sdk_ecr::Client::new(config)
+                .describe_images()
+                .repository_name(repository)
+                .image_ids(ImageIdentifier::builder().image_tag(tag).build())
+                .send()
+        })?.map_err(|e| describe(&format!("Unable to describe `{}:{}`", repository, tag), e))?;
+        images.image_details()
+            .first()
+            .and_then(|i| i.image_digest())
+            .map(|d| d.to_owned())
+            .ok_or_else(|| format!("ECR has no `{}:{}` image.", repository, tag))
+    }
+
+    /// Creates the ECR repository unless it already exists.
+    pub fn ensure_ecr_repository(&self, name: &str) -> Result<(), String> {
+        if self.run(|config| aws_sdk_ecr::Client::new(config).describe_repositories().repository_names(name).send())?.is_ok() {
+            return Ok(());
+        }
+
+        status!("Creating ECR repository {}...", name);
+        self.run(|config| aws_sdk_ecr::Client::new(config).create_repository().repository_name(name).send())?
+            .map(|_| ())
+            .map_err(|e| describe(&format!("Unable to create the ECR repository `{}`", name), e))
+    }
+
+    /// Starts a multipart upload to `key` in `bucket`, returning its ID.
+    pub fn start_upload(&self, bucket: &str, key: &str) -> Result<String, String> {
+        let upload = self.run(|config| aws_sdk_s3::Client::new(config).create_multipart_upload().bucket(bucket).key(key).send())?
+            .map_err(|e| describe(&format!("Unable to start uploading to s3://{}/{}", bucket, key), e))?;
+        upload.upload_id().map(|u| u.to_owned()).ok_or_else(|| "S3 didn't return an upload ID.".to_owned())
+    }
+
+    /// Uploads part `number` (from 1) of the upload `upload_id`. Every part but the last has to be at least 5 MiB.
+    pub fn upload_part(&self, bucket: &str, key: &str, upload_id: &str, number: i32, bytes: Vec<u8>) -> Result<CompletedPart, String> {
+        let part = self.run(|config| {
+            aws_sdk_s3::Client::new(config)
+                .upload_part()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(number)
+                .body(ByteStream::from(bytes))
+                .send()
+        })?.map_err(|e| describe(&format!("Unable to upload part {} to s3://{}/{}", number, bucket, key), e))?;
+        Ok(CompletedPart::builder().set_e_tag(part.e_tag().map(|t| t.to_owned())).part_number(number).build())
+    }
+
+    /// Finishes the upload `upload_id`, making the object out of its `parts`.
+    pub fn complete_upload(&self, bucket: &str, key: &str, upload_id: &str, parts: Vec<CompletedPart>) -> Result<(), String> {
+        self.run(|config| {
+            aws_sdk_s3::Client::new(config)
+                .complete_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+                .send()
+        })?.map(|_| ()).map_err(|e| describe(&format!("Unable to finish uploading to s3://{}/{}", bucket, key), e))
+    }
+
+    /// Abandons the upload `upload_id`, so nothing of it becomes an object.
+    pub fn abort_upload(&self, bucket: &str, key: &str, upload_id: &str) {
+        let _ = self.run(|config| aws_sdk_s3::Client::new(config).abort_multipart_upload().bucket(bucket).key(key).upload_id(upload_id).send());
+    }
+
+    /// Uploads `file` to `key` in `bucket`, returning the object's version ID, if the bucket is versioned.
+    pub fn put_object(&self, bucket: &str, key: &str, file: &Path) -> Result<Option<String>, String> {
+        let put = self.run(|config| {
+            let client = aws_sdk_s3::Client::new(config);
+            async move {
+                let body = ByteStream::from_path(file).await.map_err(|e| format!("Unable to read `{}`: {}", file.display(), e))?;
+                client
+                    .put_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .body(body)
+                    .send()
+                    .await
+                    .map_err(|e| describe(&format!("Unable to upload to s3://{}/{}", bucket, key), e))
+            }
+        })??;
+        Ok(put.version_id().map(|v| v.to_owned()))
+    }
+
+    /// Moves `from` to `to` in `bucket`, returning the new object's version ID, if the bucket is versioned.
+    pub fn move_object(&self, bucket: &str, from: &str, to: &str) -> Result<Option<String>, String> {
+        let copied = self.run(|config| {
+            aws_sdk_s3::Client::new(config).copy_object().bucket(bucket).key(to).copy_source(format!("{}/{}", bucket, url_encode(from))).send()
+        })?.map_err(|e| describe(&format!("Unable to copy s3://{}/{} to {}", bucket, from, to), e))?;
+        self.run(|config| aws_sdk_s3::Client::new(config).delete_object().bucket(bucket).key(from).send())?
+            .map_err(|e| describe(&format!("Unable to remove s3://{}/{}", bucket, from), e))?;
+        Ok(copied.version_id().map(|v| v.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_copy_sources() {
+        assert_eq!(url_encode("lambdas/api-1.2.0+abc.zip.partial"), "lambdas/api-1.2.0%2Babc.zip.partial");
+        assert_eq!(url_encode("with space/ünï"), "with%20space/%C3%BCn%C3%AF");
+    }
+
+    #[test]
+    fn prefers_the_given_region() {
+        assert_eq!(Aws::new(Some("eu-west-1".to_owned()), None).resolved_region(), Ok("eu-west-1".to_owned()));
+    }
+}
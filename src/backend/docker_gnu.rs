@@ -0,0 +1,26 @@
+//! Compiling inside the glibc builder image (see `builder`), for `--libc gnu`. The default for `-gnu` targets.
+
+use super::docker_musl::DockerMusl;
+use super::Build;
+use super::CompileBackend;
+
+pub struct DockerGnu;
+
+impl CompileBackend for DockerGnu {
+    fn name(&self) -> &'static str {
+        "docker-gnu"
+    }
+
+    fn supports(&self, target: &str) -> bool {
+        target.contains("-linux-gnu")
+    }
+
+    fn in_container(&self) -> bool {
+        true
+    }
+
+    // The same cargo build as docker-musl's, just for the other target.
+    fn container_cmd(&self, build: &Build) -> String {
+        DockerMusl.container_cmd(build)
+    }
+}
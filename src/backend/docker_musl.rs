@@ -0,0 +1,44 @@
+//! Compiling inside the builder image (see `builder`), with the musl toolchain it ships. The default.
+
+use super::Build;
+use super::CompileBackend;
+use crate::CONTAINER_TARGET_DIR;
+
+pub struct DockerMusl;
+
+impl CompileBackend for DockerMusl {
+    fn name(&self) -> &'static str {
+        "docker-musl"
+    }
+
+    fn supports(&self, target: &str) -> bool {
+        // Any other musl target is a `--target-triple`'s, with a builder image for it.
+        target.contains("-linux-musl")
+    }
+
+    fn in_container(&self) -> bool {
+        true
+    }
+
+    /*
+    Build with:
+        - the selected profile, release by default
+        - verbose error messages
+        - target musl for the selected architecture
+        - any features and extra arguments given
+        - the toolchain from `rust-toolchain.toml`, if there is one, installed first
+        - write cargo's JSON messages to a file, with diagnostics still rendered for people
+        - copy the executable to root, from where the messages say it is
+    Then record the compiler's and cargo's versions.
+    */
+    fn container_cmd(&self, build: &Build) -> String {
+        let rustc = match build.toolchain {
+            Some(t) => format!("rustc +{}", t.channel),
+            None => "rustc".to_owned(),
+        };
+        format!(
+            "{}{} build -vv --message-format=json-render-diagnostics --target={}{} > {} && {} && {} -vV > {} && {} -V > {}",
+            build.install_cmd(), build.cargo(), build.target, build.quoted_args(), build.messages,
+            build.copy_executable_cmd(CONTAINER_TARGET_DIR), rustc, build.rustc_version, build.cargo(), build.cargo_version)
+    }
+}
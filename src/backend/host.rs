@@ -0,0 +1,155 @@
+//! Backends compiling on the host, into the project's own `target` dir: `cross`, `cargo zigbuild`, and plain `cargo build`.
+//! The executable is then inspected and packaged on this machine too, so these don't need docker or podman, except to build
+//! a docker image from the tarball, or to fetch companions (`cross` runs its own containers, though).
+
+use super::Build;
+use super::CompileBackend;
+use crate::ci;
+use crate::diagnostics;
+use crate::error::BmError;
+use crate::output;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const MUSL_TARGETS: &[&str] = &["x86_64-unknown-linux-musl", "aarch64-unknown-linux-musl"];
+
+/// For `--libc gnu`, which `cross` has images for too.
+const GNU_TARGETS: &[&str] = &["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"];
+
+/// `cross`'s own configuration, next to `Cargo.toml`, which it reads itself.
+const CROSS_CONFIG: &str = "Cross.toml";
+
+/// Runs `cmd` (the backend's `build`) with the build's target, profile and flags, in `project_dir`.
+fn compile(backend: &str, mut cmd: Command, build: &Build, project_dir: &Path) -> Result<(), BmError> {
+    let (profile_args, _) = build.profile_args();
+    cmd.arg("--target")
+        .arg(build.target)
+        .arg("--message-format=json-render-diagnostics")
+        .args(&profile_args)
+        .args(build.cargo_args)
+        .current_dir(project_dir)
+        // The executable's found in the project's `target` dir, wherever the host keeps it otherwise, see `Build::executables`.
+        .env("CARGO_TARGET_DIR", project_dir.join("target"));
+    if !build.rustflags.is_empty() {
+        cmd.env("RUSTFLAGS", build.rustflags.join(" "));
+    }
+    cmd.envs(build.env.iter().map(|(k, v)| (k, v)));
+    if let Some(cflags) = build.cflags {
+        cmd.env("CFLAGS", cflags);
+    }
+    if let Some(epoch) = build.source_date_epoch {
+        cmd.env("SOURCE_DATE_EPOCH", epoch.to_string());
+    }
+
+    output::detail(&format!("Running {:?}", cmd));
+    let built = cmd.output().map_err(|e| BmError::Environment(format!("Unable to run the `{}` backend: {}", backend, e)))?;
+    if !built.status.success() {
+        let stderr = String::from_utf8_lossy(&built.stderr);
+        ci::annotate(&stderr, project_dir);
+        if let Some(hints) = diagnostics::hints(&stderr, build.diagnostics) {
+            return Err(BmError::Compile(format!(
+                "Build failed with the `{}` backend.\n{}\n\nRun the following command manually to see the whole problem:\n\n{:?}\n\nstderr: {}",
+                backend, hints, cmd, stderr)));
+        }
+        return Err(BmError::Compile(format!(
+            "Build failed with the `{}` backend. Check the `{}` target is installed, e.g. with `rustup target add {}`.\n\n\
+            Run the following command manually to see the problem:\n\n{:?}\n\nstderr: {}",
+            backend, build.target, build.target, cmd, stderr)));
+    }
+    fs::write(project_dir.join(build.messages), &built.stdout)
+        .map_err(|e| BmError::Compile(format!("Unable to write cargo's messages to `{}`: {}", build.messages, e)))?;
+
+    // There's no build container compiler to ask, so the host's is recorded.
+    for (tool, arg, file) in [("rustc", "-vV", build.rustc_version), ("cargo", "-V", build.cargo_version)] {
+        let mut version = Command::new(tool);
+        if let Some(t) = build.toolchain {
+            version.arg(format!("+{}", t.channel));
+        }
+        if let Ok(version) = version.arg(arg).output() {
+            let _ = fs::write(project_dir.join(file), &version.stdout);
+        }
+    }
+    Ok(())
+}
+
+/// Checks the tool the backend runs is installed, with `args` that just print its version.
+fn check_installed(backend: &str, program: &str, args: &[&str], install: &str) -> Result<(), BmError> {
+    match Command::new(program).args(args).output() {
+        Ok(o) if o.status.success() => Ok(()),
+        _ => Err(BmError::Environment(format!("The `{}` backend needs `{}`, install it with `{}`.", backend, program, install))),
+    }
+}
+
+/// `cross`, which compiles in its own per-target images, configured by the project's `Cross.toml` if it has one (e.g. with
+/// its own image, or `pre-build` commands installing system libraries).
+pub struct Cross;
+
+impl CompileBackend for Cross {
+    fn name(&self) -> &'static str {
+        "cross"
+    }
+
+    fn supports(&self, target: &str) -> bool {
+        MUSL_TARGETS.contains(&target) || GNU_TARGETS.contains(&target)
+    }
+
+    fn compile_on_host(&self, build: &Build, project_dir: &Path) -> Result<(), BmError> {
+        check_installed(self.name(), "cross", &["--version"], "cargo install cross")?;
+        if project_dir.join(CROSS_CONFIG).exists() {
+            output::detail(&format!("`cross` is configured by the project's `{}`.", CROSS_CONFIG));
+        }
+        let mut cmd = Command::new("cross");
+        if let Some(t) = build.toolchain {
+            cmd.arg(format!("+{}", t.channel));
+        }
+        cmd.arg("build");
+        compile(self.name(), cmd, build, project_dir)
+    }
+}
+
+/// `cargo zigbuild`, which links with zig rather than a musl toolchain.
+pub struct Zigbuild;
+
+impl CompileBackend for Zigbuild {
+    fn name(&self) -> &'static str {
+        "zigbuild"
+    }
+
+    fn supports(&self, target: &str) -> bool {
+        MUSL_TARGETS.contains(&target)
+    }
+
+    fn compile_on_host(&self, build: &Build, project_dir: &Path) -> Result<(), BmError> {
+        check_installed(self.name(), "cargo-zigbuild", &["--version"], "cargo install cargo-zigbuild")?;
+        let mut cmd = Command::new("cargo");
+        if let Some(t) = build.toolchain {
+            cmd.arg(format!("+{}", t.channel));
+        }
+        cmd.arg("zigbuild");
+        compile(self.name(), cmd, build, project_dir)
+    }
+}
+
+/// Plain `cargo build` on the host, which can only target the host's own architecture.
+pub struct Native;
+
+impl CompileBackend for Native {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn supports(&self, target: &str) -> bool {
+        env::consts::OS == "linux" && target == format!("{}-unknown-linux-musl", env::consts::ARCH)
+    }
+
+    fn compile_on_host(&self, build: &Build, project_dir: &Path) -> Result<(), BmError> {
+        let mut cmd = Command::new("cargo");
+        if let Some(t) = build.toolchain {
+            cmd.arg(format!("+{}", t.channel));
+        }
+        cmd.arg("build");
+        compile(self.name(), cmd, build, project_dir)
+    }
+}
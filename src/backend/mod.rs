@@ -0,0 +1,255 @@
+//! How the executable gets compiled, before it's inspected and packaged.
+//!
+//! Each backend is a `CompileBackend`, declaring which targets it can build for. One is picked per target: `--backend`, then
+//! the target's entry in the `[backend]` section of `BlackMagic.toml`, then that section's `default`, then `docker-musl` (or
+//! `docker-gnu`, for glibc targets). E.g:
+//! ```toml
+//! [backend]
+//! default = "docker-musl"
+//! targets = { "aarch64-unknown-linux-musl" = "zigbuild" }
+//! ```
+//!
+//! The backends compiling in the builder image leave the executable at the root of the build container, where the packaging
+//! commands find it. The ones compiling on the host (`cross`, `zigbuild` and `native`) leave it in the project's `target` dir,
+//! for black_magic to inspect and package on this machine, without docker or podman. Either way, cargo's JSON messages
+//! (`--message-format=json`) say where it is, so renamed `[[bin]]`s and custom profiles need nothing special, on any toolchain.
+
+mod docker_gnu;
+mod docker_musl;
+mod host;
+
+use crate::diagnostics::Diagnostic;
+use crate::error::BmError;
+use crate::shell_quote;
+use crate::toolchain::Toolchain;
+use docker_gnu::DockerGnu;
+use docker_musl::DockerMusl;
+use host::Cross;
+use host::Native;
+use host::Zigbuild;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Printed to stderr inside the container when cargo's messages don't have the executable, e.g. for a `--bin` that isn't one.
+pub const NO_EXECUTABLE: &str = "black_magic: no executable in the cargo messages for";
+
+/// The backends, for error messages.
+pub const NAMES: &[&str] = &["docker-musl", "docker-gnu", "cross", "zigbuild", "native"];
+
+/// What `--backend` accepts: the backends, and `zig` for `zigbuild`.
+pub const ACCEPTED: &[&str] = &["docker-musl", "docker-gnu", "cross", "zigbuild", "zig", "native"];
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct BackendConfig {
+    pub default: Option<String>,
+    /// Backends for particular target triples, overriding `default`.
+    pub targets: BTreeMap<String, String>,
+}
+
+/// What to compile.
+pub struct Build<'a> {
+    pub target: &'a str,
+    pub profile: &'a str,
+    /// Everything passed on to `cargo build`, features included.
+    pub cargo_args: &'a [String],
+    pub toolchain: Option<&'a Toolchain>,
+    /// Whether the target needs adding to the builder image's toolchain first, for `--target-triple` (see `targets`).
+    pub add_target: bool,
+    pub rustflags: &'a [String],
+    /// Variables for the compile, e.g. to link `-sys` crates statically (see `static_linking`) or from `--build-env`.
+    pub env: &'a [(&'a str, String)],
+    pub cflags: Option<&'a str>,
+    /// The executable cargo builds, unquoted.
+    pub binary: &'a str,
+    /// The kind of target it is in cargo's messages: `bin`, `example` or `bench`.
+    pub kind: &'a str,
+    /// Other `[[bin]]`s of the project, copied out next to it, for `helpers` in `[lambda]`.
+    pub helpers: &'a [String],
+    /// Where `rustc -vV` is recorded, relative to the project directory.
+    pub rustc_version: &'a str,
+    /// Where `cargo -V` is recorded, relative to the project directory.
+    pub cargo_version: &'a str,
+    /// Where cargo's JSON messages are written, relative to the project directory, to find the executable in.
+    pub messages: &'a str,
+    /// With `--reproducible`, see `reproducible`.
+    pub source_date_epoch: Option<u64>,
+    /// The project's own hints for failed compiles, see `diagnostics`.
+    pub diagnostics: &'a [Diagnostic],
+}
+
+impl Build<'_> {
+    /// `cargo`, with the project's toolchain if it has one.
+    pub fn cargo(&self) -> String {
+        match self.toolchain {
+            Some(t) => format!("cargo +{}", t.channel),
+            None => "cargo".to_owned(),
+        }
+    }
+
+    /// The arguments selecting the profile, and the directory cargo puts its output in.
+    /// `dev` and `release` have their own flags (and output dirs), which older cargo versions need.
+    fn profile_args(&self) -> (Vec<String>, &str) {
+        match self.profile {
+            "release" => (vec!["--release".to_owned()], "release"),
+            "dev" | "debug" => (Vec::new(), "debug"),
+            custom => (vec!["--profile".to_owned(), custom.to_owned()], custom),
+        }
+    }
+
+    /// The directory cargo puts the profile's output in, under the target's.
+    pub fn profile_dir(&self) -> &str {
+        self.profile_args().1
+    }
+
+    /// Shell command compiling in the build container without putting the executable anywhere, for building just the
+    /// dependencies ahead of the project (see `layered`).
+    pub fn deps_cmd(&self) -> String {
+        format!("{}{} build --target={}{}", self.install_cmd(), self.cargo(), self.target, self.quoted_args())
+    }
+
+    /// Shell command running another cargo subcommand in the build container, e.g. `test` for `--test` (see `gates`), with
+    /// the same target and arguments but the subcommand's own profile.
+    pub fn subcommand_cmd(&self, subcommand: &str) -> String {
+        let args: String = self.cargo_args.iter().map(|a| format!(" {}", shell_quote(a))).collect();
+        format!("{} {} --target={}{}", self.cargo(), subcommand, self.target, args)
+    }
+
+    /// Installs the project's toolchain first, if it has one, or else adds the target if it needs adding.
+    pub fn install_cmd(&self) -> String {
+        match self.toolchain {
+            Some(t) => format!("{} && ", t.install_cmd(self.target)),
+            None if self.add_target => format!("rustup target add {} && ", self.target),
+            None => String::new(),
+        }
+    }
+
+    /// Shell command copying the executable, and any helpers, to the build container's root, from wherever cargo's
+    /// `compiler-artifact` message for each says it is. Only the part under the target's own dir is kept, which is under
+    /// `target_dir` in the container.
+    fn copy_executable_cmd(&self, target_dir: &str) -> String {
+        let executables = std::iter::once((self.kind, self.binary)).chain(self.helpers.iter().map(|h| ("bin", h.as_str())));
+        let copies: Vec<String> = executables.map(|(kind, binary)| {
+            let find = format!(
+                "grep -F '\"reason\":\"compiler-artifact\"' {} | grep -F {} | grep -F {} \
+                | grep -o '\"executable\":\"[^\"]*\"' | tail -n 1 | cut -d '\"' -f 4 | sed {}",
+                self.messages, shell_quote(&format!("\"kind\":[\"{}\"]", kind)), shell_quote(&format!("\"name\":\"{}\"", binary)),
+                shell_quote(&format!("s|.*/{}/|{}/{}/|", self.target, target_dir, self.target)));
+            format!(
+                "{{ exe=$({}) && [ -n \"$exe\" ] || {{ echo '{} {}' >&2; exit 1; }}; }} && cp \"$exe\" /{}",
+                find, NO_EXECUTABLE, binary, shell_quote(binary))
+        }).collect();
+        copies.join(" && ")
+    }
+
+    /// Where cargo's `compiler-artifact` messages say the executable, and each of the helpers, is on this machine, by name,
+    /// for the backends compiling on the host. Only the part under the target's own dir is kept, which is under the project's
+    /// `target` dir (`cross` reports paths inside its own container).
+    pub fn executables(&self, project_dir: &Path) -> Result<Vec<(&str, PathBuf)>, BmError> {
+        let messages = fs::read_to_string(project_dir.join(self.messages))
+            .map_err(|e| BmError::Compile(format!("Unable to read cargo's messages from `{}`: {}", self.messages, e)))?;
+        let messages: Vec<serde_json::Value> = messages.lines().filter_map(|l| serde_json::from_str(l).ok()).collect();
+        let target_dir = project_dir.join("target").join(self.target);
+        std::iter::once((self.kind, self.binary)).chain(self.helpers.iter().map(|h| ("bin", h.as_str()))).map(|(kind, binary)| {
+            let executable = messages.iter()
+                .rev()
+                .filter(|m| m["reason"] == "compiler-artifact" && m["target"]["name"] == binary && m["target"]["kind"][0] == kind)
+                .find_map(|m| m["executable"].as_str())
+                .and_then(|e| e.split_once(&format!("/{}/", self.target)))
+                .ok_or_else(|| BmError::Compile(format!(
+                    "Cargo built the project, but not a `{}` executable. Check the `[[bin]]` targets in `Cargo.toml`.", binary)))?;
+            Ok((binary, target_dir.join(executable.1)))
+        }).collect()
+    }
+
+    fn quoted_args(&self) -> String {
+        let (profile_args, _) = self.profile_args();
+        profile_args.iter().chain(self.cargo_args).map(|a| format!(" {}", shell_quote(a))).collect()
+    }
+}
+
+pub trait CompileBackend {
+    fn name(&self) -> &'static str;
+
+    /// Whether it can compile for the target triple.
+    fn supports(&self, target: &str) -> bool;
+
+    /// Whether it compiles inside the build container, into the project's cache volume (which the cache server fills).
+    fn in_container(&self) -> bool {
+        false
+    }
+
+    /// Compiles on the host, leaving the executable where `Build::executables` finds it, and `rustc -vV` and `cargo -V` in
+    /// `rustc_version` and `cargo_version`. Backends compiling in the container do nothing here.
+    fn compile_on_host(&self, _build: &Build, _project_dir: &Path) -> Result<(), BmError> {
+        Ok(())
+    }
+
+    /// Shell command for the build container, leaving the executable at `/<binary>` and `rustc -vV` and `cargo -V` in
+    /// `rustc_version` and `cargo_version`. Backends compiling on the host have no build container.
+    fn container_cmd(&self, _build: &Build) -> String {
+        String::new()
+    }
+}
+
+/// One of `NAMES`, for `--backend`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    DockerMusl,
+    DockerGnu,
+    Cross,
+    Zigbuild,
+    Native,
+}
+
+impl Backend {
+    /// One of `ACCEPTED`.
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "docker-musl" => Some(Backend::DockerMusl),
+            "docker-gnu" => Some(Backend::DockerGnu),
+            "cross" => Some(Backend::Cross),
+            "zigbuild" | "zig" => Some(Backend::Zigbuild),
+            "native" => Some(Backend::Native),
+            _ => None,
+        }
+    }
+
+    fn compiler(self) -> Box<dyn CompileBackend> {
+        match self {
+            Backend::DockerMusl => Box::new(DockerMusl),
+            Backend::DockerGnu => Box::new(DockerGnu),
+            Backend::Cross => Box::new(Cross),
+            Backend::Zigbuild => Box::new(Zigbuild),
+            Backend::Native => Box::new(Native),
+        }
+    }
+}
+
+/// Picks the backend for `target`, from `--backend` or the config, checking it can build for it.
+pub fn select(requested: Option<Backend>, config: &BackendConfig, target: &str) -> Result<Box<dyn CompileBackend>, BmError> {
+    let backend = match requested {
+        Some(backend) => backend,
+        None => {
+            let name = config.targets.get(target).map(|n| n.as_str())
+                .or(config.default.as_deref())
+                .unwrap_or(if target.contains("-linux-gnu") { "docker-gnu" } else { "docker-musl" });
+            Backend::from_name(name).ok_or_else(|| BmError::Environment(format!(
+                "`{}` isn't a compile backend, use one of: {}.", name, NAMES.join(", "))))?
+        }
+    };
+    let backend = backend.compiler();
+
+    if !backend.supports(target) {
+        let supporting: Vec<&str> = NAMES.iter()
+            .copied()
+            .filter(|n| Backend::from_name(n).map(|b| b.compiler().supports(target)).unwrap_or(false))
+            .collect();
+        return Err(BmError::Environment(format!(
+            "The `{}` backend can't compile for `{}`, use one of: {}.", backend.name(), target, supporting.join(", "))));
+    }
+    Ok(backend)
+}
@@ -0,0 +1,138 @@
+//! Instruction-set baseline control for x86_64 builds.
+//!
+//! The builder image's nightly predates LLVM's `x86-64-v2`/`x86-64-v3` cpu names, so each level is expressed as
+//! `target-cpu=x86-64` plus the matching list of target features instead.
+//!
+//! After building, the disassembly of the binary is scanned for instructions that need a higher level than the one
+//! requested. Crates that pick an implementation at runtime (e.g. via `is_x86_feature_detected!`) will still trip
+//! this check, as there is no way to tell from the disassembly whether a code path is guarded.
+
+/// An x86_64 micro-architecture level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuBaseline {
+    X86_64,
+    V2,
+    V3,
+}
+
+impl CpuBaseline {
+    pub fn from_name(name: &str) -> CpuBaseline {
+        match name {
+            "x86-64-v2" => CpuBaseline::V2,
+            "x86-64-v3" => CpuBaseline::V3,
+            _ => CpuBaseline::X86_64,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CpuBaseline::X86_64 => "x86-64",
+            CpuBaseline::V2 => "x86-64-v2",
+            CpuBaseline::V3 => "x86-64-v3",
+        }
+    }
+
+    fn level(self) -> u8 {
+        match self {
+            CpuBaseline::X86_64 => 1,
+            CpuBaseline::V2 => 2,
+            CpuBaseline::V3 => 3,
+        }
+    }
+
+    /// The `RUSTFLAGS` needed to restrict code generation to this level.
+    pub fn rustflags(self) -> String {
+        let features = match self {
+            CpuBaseline::X86_64 => "",
+            CpuBaseline::V2 => "+sse3,+ssse3,+sse4.1,+sse4.2,+popcnt,+cx16",
+            CpuBaseline::V3 => "+sse3,+ssse3,+sse4.1,+sse4.2,+popcnt,+cx16,+avx,+avx2,+bmi1,+bmi2,+f16c,+fma,+lzcnt,+movbe,+xsave",
+        };
+
+        if features.is_empty() {
+            "-C target-cpu=x86-64".to_owned()
+        } else {
+            format!("-C target-cpu=x86-64 -C target-feature={}", features)
+        }
+    }
+}
+
+const SSE3_SSSE3: &[&str] = &[
+    "addsubpd", "addsubps", "haddpd", "haddps", "hsubpd", "hsubps", "lddqu", "movddup", "movshdup", "movsldup",
+    "pabsb", "pabsw", "pabsd", "palignr", "phaddw", "phaddd", "phaddsw", "phsubw", "phsubd", "phsubsw",
+    "pmaddubsw", "pmulhrsw", "pshufb", "psignb", "psignw", "psignd",
+];
+
+const SSE4: &[&str] = &[
+    "blendpd", "blendps", "blendvpd", "blendvps", "dppd", "dpps", "extractps", "insertps", "movntdqa", "mpsadbw",
+    "packusdw", "pblendvb", "pblendw", "pcmpeqq", "pextrb", "pextrd", "pextrq", "pinsrb", "pinsrd", "pinsrq",
+    "phminposuw", "pmaxsb", "pmaxsd", "pmaxud", "pmaxuw", "pminsb", "pminsd", "pminud", "pminuw", "pmuldq",
+    "pmulld", "ptest", "roundpd", "roundps", "roundsd", "roundss", "pcmpestri", "pcmpestrm", "pcmpistri",
+    "pcmpistrm", "pcmpgtq", "popcnt", "cmpxchg16b",
+];
+
+const BMI_ETC: &[&str] = &[
+    "andn", "bextr", "blsi", "blsmsk", "blsr", "tzcnt", "bzhi", "mulx", "pdep", "pext", "rorx", "sarx", "shlx",
+    "shrx", "lzcnt", "movbe",
+];
+
+const AVX512_MASK: &[&str] = &[
+    "kmov", "kand", "kor", "kxor", "kxnor", "knot", "kshift", "kortest", "ktest", "kunpck", "kadd",
+];
+
+const PREFIXES: &[&str] = &["lock", "rep", "repz", "repnz", "repe", "repne", "data16", "notrack", "bnd"];
+
+/// The lowest level able to execute a single disassembled instruction.
+fn required_level(mnemonic: &str, operands: &str) -> u8 {
+    if operands.contains("%zmm") || operands.contains("{%k") || AVX512_MASK.iter().any(|m| mnemonic.starts_with(m)) {
+        return 4;
+    }
+
+    // `verr`/`verw` are ancient protected mode instructions, not VEX encoded ones.
+    let vex = mnemonic.starts_with('v') && mnemonic != "verr" && mnemonic != "verw";
+    if vex || operands.contains("%ymm") || BMI_ETC.contains(&mnemonic) {
+        return 3;
+    }
+
+    if SSE3_SSSE3.contains(&mnemonic)
+        || SSE4.contains(&mnemonic)
+        || mnemonic.starts_with("pmovsx")
+        || mnemonic.starts_with("pmovzx")
+        || mnemonic.starts_with("crc32")
+    {
+        return 2;
+    }
+
+    1
+}
+
+/// Scans `objdump -d` output and returns each offending mnemonic with the number of times it occurs.
+pub fn violations(disassembly: &str, baseline: CpuBaseline) -> Vec<(String, usize)> {
+    let mut found: Vec<(String, usize)> = Vec::new();
+
+    for line in disassembly.lines() {
+        // Instruction lines look like `  401000:\tmov    %rsp,%rbp`.
+        let mut columns = line.splitn(2, ":\t");
+        let address = columns.next().unwrap_or("");
+        let instruction = match columns.next() {
+            Some(i) if !address.trim().is_empty() && address.trim().chars().all(|c| c.is_ascii_hexdigit()) => i,
+            _ => continue,
+        };
+
+        let mut parts = instruction.split_whitespace().skip_while(|p| PREFIXES.contains(p));
+        let mnemonic = match parts.next() {
+            Some(m) => m,
+            None => continue,
+        };
+        let operands = parts.next().unwrap_or("");
+
+        if required_level(mnemonic, operands) > baseline.level() {
+            match found.iter_mut().find(|(m, _)| m == mnemonic) {
+                Some((_, count)) => *count += 1,
+                None => found.push((mnemonic.to_owned(), 1)),
+            }
+        }
+    }
+
+    found.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    found
+}
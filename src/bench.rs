@@ -0,0 +1,196 @@
+//! The `bench-builders` subcommand: build the project with each candidate builder image, and compare them.
+//!
+//! Candidates are given with `--candidate`, or in `BlackMagic.toml`:
+//! ```toml
+//! [bench]
+//! builders = ["nightly-2020-04-23", "nightly-2020-06-01", "rustlang/rust:nightly"]
+//! ```
+//! A bare tag is a tag of the default builder image. Each candidate gets a cold build, with the project's cache volume
+//! removed first, then a warm one reusing the volume the cold build filled. Neither reuses stored artifacts.
+//! The results are printed, and written to `target/black_magic/bench-builders.json`.
+
+use crate::builder::Builder;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::names;
+use crate::network::Network;
+use crate::output;
+use crate::output::status;
+use crate::registry;
+use crate::release;
+use crate::runtime::Runtime;
+use crate::Arch;
+use crate::proxy::Proxy;
+use crate::retry::Retry;
+use clap::ArgMatches;
+use serde::Deserialize;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::iter;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+use std::time::SystemTime;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct BenchConfig {
+    pub builders: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct BenchResult {
+    candidate: String,
+    builder_image: String,
+    cold_seconds: Option<f64>,
+    warm_seconds: Option<f64>,
+    artifact_size: Option<u64>,
+    error: Option<String>,
+}
+
+/// Splits a candidate into the base image, if it names one, and the tag.
+fn parse_candidate(candidate: &str) -> (Option<&str>, &str) {
+    if candidate.contains('/') {
+        let (image, tag) = registry::split_tag(candidate);
+        (Some(image), tag)
+    } else {
+        (None, candidate)
+    }
+}
+
+/// Runs one build, returning how long it took and the size of the artifact it produced.
+fn timed_build(exe: &Path, args: &[String], bm_dir: &Path) -> Result<(f64, u64), String> {
+    let started = SystemTime::now();
+    let timer = Instant::now();
+    let status = Command::new(exe).args(args).status().map_err(|e| format!("Unable to run black_magic: {}", e))?;
+    let seconds = timer.elapsed().as_secs_f64();
+    if !status.success() {
+        return Err(format!("Build failed with exit code {}.", status.code().unwrap_or(-1)));
+    }
+
+    let manifest = release::manifests_since(bm_dir, started)
+        .into_iter()
+        .next()
+        .ok_or("The build didn't produce an artifact.")?;
+    let manifest: serde_json::Value = fs::read_to_string(&manifest)
+        .ok()
+        .and_then(|m| serde_json::from_str(&m).ok())
+        .ok_or("Unable to read the artifact manifest.")?;
+    let artifact = manifest["artifact"].as_str().ok_or("The artifact manifest doesn't name the artifact.")?;
+    let size = fs::metadata(bm_dir.join(artifact)).map_err(|e| format!("Unable to read artifact: {}", e))?.len();
+    Ok((seconds, size))
+}
+
+fn format_seconds(seconds: Option<f64>) -> String {
+    seconds.map(|s| format!("{:.1}s", s)).unwrap_or_else(|| "-".to_owned())
+}
+
+fn format_size(size: Option<u64>) -> String {
+    size.map(|s| format!("{:.2} MiB", s as f64 / (1024.0 * 1024.0))).unwrap_or_else(|| "-".to_owned())
+}
+
+/// Runs the `bench-builders` subcommand.
+pub fn bench_builders(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = crate::mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+    let bm_dir = current_dir.join("target").join("black_magic");
+    fs::create_dir_all(&bm_dir).map_err(|e| BmError::Environment(format!("Unable to create `target\\black_magic` directory: {}", e)))?;
+
+    let candidates: Vec<String> = match matches.values_of("CANDIDATE") {
+        Some(c) => c.map(|c| c.to_owned()).collect(),
+        None => config.bench.builders.clone(),
+    };
+    if candidates.is_empty() {
+        return Err(BmError::Environment(
+            "There's nothing to compare. Pass builders with `--candidate`, or list them in the `[bench]` section of `BlackMagic.toml`.".to_owned()));
+    }
+
+    // The build's own options decide what the candidates are built for.
+    let build_args: Vec<String> = matches.value_of("ARGS").unwrap().split_whitespace().map(|a| a.to_owned()).collect();
+    let build_matches = crate::app()
+        .get_matches_from_safe(iter::once("black_magic".to_owned()).chain(build_args.iter().cloned()))
+        .map_err(|e| BmError::Environment(format!("Invalid `--args`: {}", e)))?;
+    if ["BUILDER_IMAGE", "BUILDER_TAG", "NO_CACHE"].iter().any(|a| build_matches.is_present(a)) {
+        return Err(BmError::Environment("`--args` can't pick the builder or disable the cache, the benchmark does that.".to_owned()));
+    }
+    let arch = Arch::from_name(build_matches.value_of("ARCH").unwrap());
+    let runtime = Runtime::detect(build_matches.value_of("RUNTIME").or_else(|| matches.value_of("RUNTIME")))?;
+    let name = build_matches.value_of("NAME").or_else(|| matches.value_of("NAME")).or(config.name.as_deref());
+    let name = names::resolve(crate::project_name(&current_dir)?, name)?;
+    let cache_volume = crate::cache_volume(&name, arch.target_triple());
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+
+    let mut results = Vec::new();
+    let network = Network::new(&config.network, None, Vec::new(), Vec::new()).map_err(BmError::Environment)?;
+    let builder_build_args = [Proxy::new(&config.proxy).build_args(), network.build_args()].concat();
+    for candidate in &candidates {
+        let (image, tag) = parse_candidate(candidate);
+        let builder = Builder::new(arch, false, image, Some(tag)).customized(&config.builder.packages, config.builder.setup_script.as_deref())?;
+        let mut result = BenchResult {
+            candidate: candidate.clone(),
+            builder_image: builder.image.clone(),
+            cold_seconds: None,
+            warm_seconds: None,
+            artifact_size: None,
+            error: None,
+        };
+
+        status!("Benchmarking {}...", builder.image);
+        // Built up front, so building the builder image isn't part of the timings.
+        if let Err(e) = builder.ensure(runtime, arch, &bm_dir, false, &builder_build_args, &Retry::none()) {
+            output::warning(&e.to_string());
+            result.error = Some(e.to_string());
+            results.push(result);
+            continue;
+        }
+
+        let mut args = build_args.clone();
+        args.extend(["--runtime", runtime.name(), "--builder-image", &builder.base_image, "--builder-tag", &builder.tag, "--no-artifact-cache"]
+            .iter()
+            .map(|a| a.to_string()));
+
+        // A missing volume is fine, there's just nothing to remove.
+        let _ = runtime.command().arg("volume").arg("rm").arg("-f").arg(&cache_volume).output();
+
+        status!("Cold build with {}...", builder.image);
+        let cold = timed_build(&exe, &args, &bm_dir);
+        let warm = cold.as_ref().map_err(|e| e.clone()).and_then(|_| {
+            status!("Warm build with {}...", builder.image);
+            timed_build(&exe, &args, &bm_dir)
+        });
+
+        match (cold, warm) {
+            (Ok((cold_seconds, size)), Ok((warm_seconds, _))) => {
+                result.cold_seconds = Some(cold_seconds);
+                result.warm_seconds = Some(warm_seconds);
+                result.artifact_size = Some(size);
+            }
+            (Ok((cold_seconds, size)), Err(e)) => {
+                result.cold_seconds = Some(cold_seconds);
+                result.artifact_size = Some(size);
+                result.error = Some(e);
+            }
+            (Err(e), _) => result.error = Some(e),
+        }
+        results.push(result);
+    }
+
+    status!("");
+    status!("{:<60} {:>10} {:>10} {:>12}", "Builder", "Cold", "Warm", "Size");
+    for r in &results {
+        match &r.error {
+            Some(e) if r.cold_seconds.is_none() => status!("{:<60} failed: {}", r.builder_image, e),
+            _ => status!(
+                "{:<60} {:>10} {:>10} {:>12}",
+                r.builder_image, format_seconds(r.cold_seconds), format_seconds(r.warm_seconds), format_size(r.artifact_size)),
+        }
+    }
+
+    let report = bm_dir.join("bench-builders.json");
+    fs::write(&report, serde_json::to_string_pretty(&results).unwrap())
+        .map_err(|e| BmError::Packaging(format!("Unable to write benchmark results: {}", e)))?;
+    status!("");
+    status!("Results: {}", report.display());
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Projects with several `[[bin]]` targets, e.g. an API, a worker and a migrator.
+//!
+//! `--bin <name>` builds one of them, naming the artifact after it (`<bin>.zip`, or the `bm_<bin>` image). `--bins` builds
+//! every one: the first build compiles all of them at once, and the rest package their executable from the same, shared,
+//! cache volume without compiling anything again, `--parallel <n>` of them at once (see `scheduler`). The project's
+//! dependencies are compiled once either way.
+//!
+//! `--example <name>` and `--bench <name>` build one of the project's examples or benches the same way, e.g. an example
+//! shipped as its own Lambda function, or a bench in an image for profiling. Their artifacts are named apart from an
+//! executable with the same name, `example_<name>.zip` or the `bm_example_<name>` image (`bench_` for benches).
+
+use crate::error::BmError;
+use crate::metadata::Metadata;
+use crate::output;
+use crate::output::status;
+use crate::scheduler;
+use serde_json::json;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+/// The `[[bin]]` targets of the project's own package, the one in `project_dir`.
+pub fn binaries(project_dir: &Path, metadata: &Metadata) -> Vec<String> {
+    targets(project_dir, metadata, "bin")
+}
+
+/// The project's own package's targets of `kind`, e.g. `bin` or `example`.
+fn targets(project_dir: &Path, metadata: &Metadata, kind: &str) -> Vec<String> {
+    let manifest = project_dir.join("Cargo.toml");
+    let manifest = manifest.canonicalize().unwrap_or(manifest);
+    metadata.packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+        .find(|p| p.manifest_path.as_deref().map(|m| Path::new(m) == manifest).unwrap_or(false))
+        .map(|p| p.targets.iter().filter(|t| t.kind.iter().any(|k| k == kind)).map(|t| t.name.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// Checks the project has a target of `kind` (`bin`, `example` or `bench`) called `name`.
+pub fn check(project_dir: &Path, metadata: &Metadata, kind: &str, name: &str) -> Result<(), BmError> {
+    let noun = if kind == "bin" { "executable" } else { kind };
+    let targets = targets(project_dir, metadata, kind);
+    if targets.iter().any(|t| t == name) {
+        Ok(())
+    } else if targets.is_empty() {
+        Err(BmError::Environment(format!("The project has no `{}` {}, or any others.", name, noun)))
+    } else {
+        Err(BmError::Environment(format!("The project has no `{}` {}. Its {}s are: {}.", name, noun, noun, targets.join(", "))))
+    }
+}
+
+/// The build of `bin`.
+fn bin_job(exe: &Path, args: &[String], bin: &str, first: bool) -> scheduler::Job {
+    let mut cmd = Command::new(exe);
+    cmd.arg("--bin").arg(bin).arg("--output-format").arg("json");
+    // Compiles every executable, so the other builds only package theirs.
+    if first {
+        cmd.arg("--cargo-arg").arg("--bins");
+    }
+    cmd.args(args);
+    scheduler::Job { name: bin.to_owned(), cmd, failure: format!("Building {} failed", bin) }
+}
+
+/// Runs a `--bins` build.
+pub fn build_all(matches: &clap::ArgMatches, started: Instant) -> Result<(), BmError> {
+    let conflicting = ["BIN", "EXAMPLE", "BENCH", "NAME", "NO_SIDE_EFFECTS", "DRY_RUN", "PLATFORMS"];
+    if conflicting.iter().any(|a| matches.occurrences_of(a) > 0) {
+        return Err(BmError::Environment(
+            "`--bins` names each artifact after its executable, so it can't be used with `--bin`, `--example`, `--bench`, `--name`, `--no-side-effects`, `--dry-run` or `--platforms`.".to_owned()));
+    }
+
+    let current_dir = crate::mounts::current_dir()?;
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    let arch = crate::Arch::from_name(matches.value_of("ARCH").unwrap());
+    let metadata = Metadata::load(&current_dir, arch.target_triple(), None)?;
+    let binaries = binaries(&current_dir, &metadata);
+    if binaries.is_empty() {
+        return Err(BmError::Environment("The project has no executables to build.".to_owned()));
+    }
+    let args = crate::forwarded_args(&["--bins"], &["--parallel"]);
+    let parallel = matches.value_of("PARALLEL").map(|p| p.parse().unwrap()).unwrap_or(1);
+    let bm_dir = current_dir.join("target").join("black_magic");
+
+    // The first build compiles them all, so the others wait for it, then only package theirs.
+    let mut finished = scheduler::run(vec![bin_job(&exe, &args, &binaries[0], true)], 1, &bm_dir, matches.is_present("NO_WAIT"))?;
+    if finished[0].result.is_ok() {
+        let rest = binaries[1..].iter().map(|bin| bin_job(&exe, &args, bin, false)).collect();
+        finished.extend(scheduler::run(rest, parallel, &bm_dir, matches.is_present("NO_WAIT"))?);
+    }
+    scheduler::summary(&finished, |record| record["image"].as_str().or_else(|| record["artifact"].as_str()).unwrap_or("").to_owned());
+    let records = scheduler::first_error(finished)?;
+    for (bin, record) in &records {
+        output::marker("ARTIFACT", &[
+            ("bin", bin), ("path", record["artifact"].as_str().unwrap_or("")),
+            ("digest", &format!("sha256:{}", record["sha256"].as_str().unwrap_or(""))), ("size", &record["size"].to_string())]);
+        if let Some(image) = record["image"].as_str() {
+            output::marker("IMAGE", &[("bin", bin), ("name", image)]);
+        }
+    }
+    status!("...Done!");
+
+    if output::is_json() {
+        output::emit("done", json!({
+            "bins": records.iter().map(|(bin, record)| json!({ "bin": bin, "record": record })).collect::<Vec<_>>(),
+            "duration_seconds": started.elapsed().as_secs_f64(),
+        }));
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! `--cpus`, `--memory` and `--timeout`: keeping the build container from taking over the machine, or running forever.
+//!
+//! The CPUs and memory are `docker run`'s own limits. A build out of memory gets killed by the kernel, and fails like any
+//! other. For the timeout, a watchdog removes the build container once it's run for that long, e.g. stuck on a stalled
+//! download, and the build fails saying so. They only apply to builds in a container: `--layered` builds run inside
+//! `docker build`, which doesn't take them.
+
+use crate::runtime::Runtime;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Checks a `--cpus` value, e.g. `1.5`.
+pub fn validate_cpus(cpus: String) -> Result<(), String> {
+    match cpus.parse::<f64>() {
+        Ok(c) if c > 0.0 => Ok(()),
+        _ => Err(format!("`{}` isn't a number of CPUs, e.g. `2` or `1.5`.", cpus)),
+    }
+}
+
+/// Checks a `--memory` value, as docker takes it: a number of bytes, or with a `b`, `k`, `m` or `g` suffix.
+pub fn validate_memory(memory: String) -> Result<(), String> {
+    let number = memory.trim_end_matches(|c: char| "bkmgBKMG".contains(c));
+    if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) && memory.len() - number.len() <= 1 {
+        Ok(())
+    } else {
+        Err(format!("`{}` isn't an amount of memory, e.g. `4g` or `512m`.", memory))
+    }
+}
+
+/// Removes the build container if it's still running after the timeout.
+pub struct Watchdog {
+    finished: Arc<AtomicBool>,
+    watching: JoinHandle<bool>,
+}
+
+impl Watchdog {
+    pub fn start(runtime: Runtime, container: String, timeout: Duration) -> Watchdog {
+        let finished = Arc::new(AtomicBool::new(false));
+        let done = finished.clone();
+        let started = Instant::now();
+        let watching = thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                if started.elapsed() >= timeout {
+                    let _ = runtime.command().arg("rm").arg("--force").arg(&container).output();
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+            false
+        });
+        Watchdog { finished, watching }
+    }
+
+    /// Stops watching, returning whether the build timed out.
+    pub fn finish(self) -> bool {
+        self.finished.store(true, Ordering::Relaxed);
+        self.watching.join().unwrap_or(false)
+    }
+}
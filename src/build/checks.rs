@@ -0,0 +1,221 @@
+//! The options that can't be used together, or in the mode or with the backend the build has, checked before anything runs.
+
+use crate::api::BuildConfig;
+use crate::api::Mode;
+use crate::backend::CompileBackend;
+use crate::error::BmError;
+use crate::kube::LocalCluster;
+use crate::system_files::SystemFile;
+use crate::system_files::User;
+use crate::targets;
+use crate::targets::CustomTarget;
+use crate::template;
+use crate::Arch;
+use crate::Libc;
+
+/// What the build compiles for, and what the image it goes in is based on.
+pub struct Target {
+    pub arch: Arch,
+    pub libc: Libc,
+    /// For `--target-triple`, cross-compiled in a builder on the host's platform, see `targets`.
+    pub custom: Option<CustomTarget>,
+    pub base: template::Base,
+    pub system_files: Vec<SystemFile>,
+    pub user: Option<User>,
+    pub load_into: Option<LocalCluster>,
+}
+
+impl Target {
+    pub fn triple(&self) -> &str {
+        self.custom.as_ref().map(|t| t.triple.as_str()).unwrap_or_else(|| self.libc.target_triple(self.arch))
+    }
+
+    /// The project image's, which for a custom target isn't the build container's.
+    pub fn image_platform(&self) -> Option<&'static str> {
+        self.custom.as_ref().map(|t| Some(t.platform)).unwrap_or_else(|| self.arch.platform())
+    }
+
+    /// The user whose files go in the image, unless its base has users of its own, see `Base::has_users`.
+    pub fn bundled_user(&self) -> Option<&User> {
+        self.user.as_ref().filter(|_| !self.base.has_users())
+    }
+}
+
+/// Checks the options apply to the build's mode, and to each other, and works out what it compiles for.
+pub fn options(options: &BuildConfig) -> Result<Target, BmError> {
+    let lambda_image = options.mode == Mode::LambdaImage;
+    let is_docker = options.mode != Mode::Lambda;
+    let is_lambda = options.mode == Mode::Lambda;
+    let base_name = options.base.as_deref().or(Some(template::LAMBDA_BASE).filter(|_| lambda_image));
+    let triple = options.target_triple.as_deref().map(targets::parse_triple).transpose()?;
+    if triple.is_some() && (options.arch.is_some() || options.libc.is_some()) {
+        return Err(BmError::Environment("`--target-triple` says the architecture and C library, so it replaces `--arch` and `--libc`.".to_owned()));
+    } else if triple.is_some() && !is_docker {
+        return Err(BmError::Environment("`--target-triple` only applies to docker builds, Lambda only runs x86_64 and arm64.".to_owned()));
+    }
+    let (arch, libc, custom) = match triple {
+        Some(targets::Triple::Custom(t)) => {
+            targets::check_custom(base_name, &t)?;
+            (Arch::X86_64, t.libc, Some(t))
+        }
+        Some(targets::Triple::Builtin(arch, libc)) => {
+            let (arch, libc) = targets::resolve(base_name, Some(arch), Some(libc))?;
+            (arch, libc, None)
+        }
+        None => {
+            let (arch, libc) = targets::resolve(base_name.filter(|_| is_docker), options.arch, options.libc)?;
+            (arch, libc, None)
+        }
+    };
+    if libc == Libc::Gnu && !is_docker {
+        return Err(BmError::Environment(
+            "`--libc gnu` only applies to docker builds: Lambda's runtimes have an older glibc than the builder image.".to_owned()));
+    }
+    let system_files: Vec<SystemFile> = [(options.with_ca_certs, SystemFile::CaCerts), (options.with_tzdata, SystemFile::Tzdata)]
+        .iter()
+        .filter(|(wanted, _)| *wanted)
+        .map(|(_, file)| *file)
+        .collect();
+    if !is_docker && !system_files.is_empty() {
+        return Err(BmError::Environment("`--with-ca-certs` and `--with-tzdata` only apply to docker builds, Lambda already has both.".to_owned()));
+    }
+    let user = options.user.as_deref().map(User::parse).transpose().map_err(BmError::Environment)?;
+    if lambda_image && (!system_files.is_empty() || user.is_some() || options.debug_image) {
+        return Err(BmError::Environment(
+            "`--with-ca-certs`, `--with-tzdata`, `--user` and `--debug-image` don't apply to `--lambda-image`: Lambda's base image has its own files, users and shell.".to_owned()));
+    }
+    let base = template::Base::parse(base_name, libc == Libc::Gnu)?;
+    if !is_docker && user.is_some() {
+        return Err(BmError::Environment("`--user` only applies to docker builds.".to_owned()));
+    }
+    if is_docker && options.deploy.is_some() && !(lambda_image && options.ecr.is_some()) {
+        return Err(BmError::Environment(
+            "`--deploy` only applies to lambda builds, or `--lambda-image` builds with `--ecr`, which point the function at the pushed image.".to_owned()));
+    }
+    if is_docker && options.s3.is_some() {
+        return Err(BmError::Environment("`--s3` only applies to lambda builds.".to_owned()));
+    }
+    let load_into = options.load_into.as_deref().map(LocalCluster::parse).transpose().map_err(BmError::Environment)?;
+
+    if options.cpu_baseline.is_some() && arch != Arch::X86_64 {
+        return Err(BmError::Environment("`--cpu-baseline` only applies to x86_64 builds.".to_owned()));
+    }
+    let image_options = [
+        !options.push.is_empty(), !options.tags.is_empty(), options.tag_git, options.ecr.is_some(), options.smoke_test.is_some(),
+        options.integration_test, options.diff_against.is_some(), options.scan, options.scan_fail_on.is_some(), options.emit_compose,
+        options.export_oci.is_some(), options.load_into.is_some(), options.debug_image, options.dockerfile_template.is_some(),
+        options.entrypoint.is_some(), options.cmd.is_some(), !options.expose.is_empty(), !options.env.is_empty(),
+    ];
+    if options.no_image && (!is_docker || image_options.contains(&true)) {
+        return Err(BmError::Environment("`--no-image` only applies to docker builds, without any of the options for the image.".to_owned()));
+    }
+
+    if is_docker && !options.includes.is_empty() {
+        return Err(BmError::Environment("`--include` only applies to lambda builds.".to_owned()));
+    }
+    if is_docker && options.lambda_runtime.is_some() {
+        return Err(BmError::Environment("`--lambda-runtime` only applies to lambda builds.".to_owned()));
+    }
+    if is_docker && options.serverless.is_some() {
+        return Err(BmError::Environment("`--serverless` only applies to lambda builds.".to_owned()));
+    }
+    if is_docker && options.compression.is_some() {
+        return Err(BmError::Environment("`--compression` only applies to lambda builds.".to_owned()));
+    }
+    if is_docker && options.emit_sam {
+        return Err(BmError::Environment("`--emit-sam` only applies to lambda builds.".to_owned()));
+    }
+    if is_lambda && options.emit_compose {
+        return Err(BmError::Environment("`--emit-compose` only applies to docker builds.".to_owned()));
+    }
+    if is_lambda && options.export_oci.is_some() {
+        return Err(BmError::Environment("`--export-oci` only applies to docker builds.".to_owned()));
+    }
+    if is_docker && options.no_image && options.emit_terraform {
+        return Err(BmError::Environment("`--emit-terraform` needs an image or a zip to describe, so it can't be used with `--no-image`.".to_owned()));
+    }
+    if !is_docker && (options.dockerfile_template.is_some() || options.base.is_some() || options.entrypoint.is_some()
+        || options.cmd.is_some() || !options.expose.is_empty() || !options.env.is_empty())
+    {
+        return Err(BmError::Environment(
+            "`--dockerfile-template`, `--base`, `--entrypoint`, `--cmd`, `--expose`, and `--env` only apply to docker builds.".to_owned()));
+    }
+    if !is_docker && (!options.tags.is_empty() || options.tag_git) {
+        return Err(BmError::Environment("`--tag` and `--tag-git` only apply to docker builds.".to_owned()));
+    }
+    if options.deps_only && options.no_cache {
+        return Err(BmError::Environment("`--deps-only` fills the cache volumes, so it can't be used with `--no-cache`.".to_owned()));
+    }
+
+    Ok(Target { arch, libc, custom, base, system_files, user, load_into })
+}
+
+/// Checks the options that need a build container, or one that runs for the build rather than inside `docker build`, have
+/// one: `layered` builds (`--layered` and remote daemons) don't, and neither do the backends compiling on the host.
+pub fn backend(options: &BuildConfig, backend: &dyn CompileBackend, layered: bool) -> Result<(), BmError> {
+    if layered && options.s3.is_some() {
+        return Err(BmError::Environment(
+            "`--s3` streams the zip out of a running build container, so it can't be used with `--layered` or a remote daemon.".to_owned()));
+    }
+    if layered && !backend.in_container() {
+        return Err(BmError::Environment(format!("`--layered` only applies to the docker-musl and docker-gnu backends, not `{}`.", backend.name())));
+    }
+    if options.shadow_target && !backend.in_container() {
+        return Err(BmError::Environment(format!("`--shadow-target` only applies to the docker-musl and docker-gnu backends, not `{}`.", backend.name())));
+    }
+    if options.read_only_source && (layered || !backend.in_container()) {
+        return Err(BmError::Environment(format!(
+            "`--read-only-source` changes how the project is mounted, and it isn't with the `{}` backend, `--layered` builds or remote daemons.",
+            backend.name())));
+    }
+    if options.prefetch && (layered || !backend.in_container()) {
+        return Err(BmError::Environment(format!(
+            "`--prefetch` fetches in a container like the build's, which the `{}` backend, `--layered` builds and remote daemons don't have.",
+            backend.name())));
+    }
+    if options.deps_only && (layered || !backend.in_container()) {
+        return Err(BmError::Environment(format!(
+            "`--deps-only` compiles into the build container's cache volume, which the `{}` backend, `--layered` builds and remote daemons don't have.",
+            backend.name())));
+    }
+    if layered && (options.ssh || options.gitconfig.is_some() || options.cargo_config || !options.registry_tokens.is_empty()) {
+        return Err(BmError::Environment(
+            "`--ssh`, `--gitconfig`, `--cargo-config` and `--registry-token` pass the host's credentials into the build container, \
+            so they can't be used with `--layered` or a remote daemon.".to_owned()));
+    }
+    if !options.secrets.is_empty() && !backend.in_container() {
+        return Err(BmError::Environment(format!(
+            "`--secret` mounts files into the build container, and there isn't one with `{}`.", backend.name())));
+    }
+    if options.sccache && (layered || !backend.in_container()) {
+        return Err(BmError::Environment("`--sccache` only applies to the docker-musl and docker-gnu backends, without `--layered` or a remote daemon.".to_owned()));
+    }
+    if (options.test || options.clippy) && !backend.in_container() {
+        return Err(BmError::Environment(format!(
+            "`--test` and `--clippy` run in the build container, which the `{}` backend doesn't compile in.", backend.name())));
+    }
+    if options.resource_report && (layered || !backend.in_container()) {
+        return Err(BmError::Environment(format!(
+            "`--resource-report` measures the build container, which the `{}` backend, `--layered` builds and remote daemons don't have.",
+            backend.name())));
+    }
+    if (layered || !backend.in_container()) && (options.cpus.is_some() || options.memory.is_some() || options.timeout.is_some()) {
+        return Err(BmError::Environment(format!(
+            "`--cpus`, `--memory` and `--timeout` limit the build container, which the `{}` backend, `--layered` builds and remote daemons don't have.",
+            backend.name())));
+    }
+    if (options.with_ca_certs || options.with_tzdata) && !backend.in_container() {
+        return Err(BmError::Environment(format!(
+            "`--with-ca-certs` and `--with-tzdata` copy the builder image's files, which the `{}` backend doesn't compile in.", backend.name())));
+    }
+    if options.keep_on_failure && (layered || !backend.in_container()) {
+        let without = if layered { "`--layered` builds".to_owned() } else { format!("the `{}` backend", backend.name()) };
+        return Err(BmError::Environment(format!("`--keep-on-failure` keeps the build container, and there isn't one with {}.", without)));
+    }
+    if (options.shell || options.debug_shell) && (layered || !backend.in_container()) {
+        return Err(BmError::Environment(format!(
+            "`--shell` and `--debug-shell` need a build container, which the `{}` backend, `--layered` builds and remote daemons don't have.",
+            backend.name())));
+    }
+    Ok(())
+}
@@ -0,0 +1,553 @@
+//! The build container: what's mounted into it, its environment, and the script it runs to compile, inspect and package the
+//! executable.
+
+use super::job::Job;
+use super::CONTAINER_CARGO_HOME;
+use super::READ_ONLY_INPUTS;
+use crate::archivers;
+use crate::bundle;
+use crate::cache_server;
+use crate::cargo_cache;
+use crate::cargo_cache::CargoCache;
+use crate::cargo_config;
+use crate::cargo_lock;
+use crate::cargo_lock::PackageCache;
+use crate::companion;
+use crate::debug_shell;
+use crate::elf;
+use crate::error::BmError;
+use crate::gates;
+use crate::hardening;
+use crate::hooks;
+use crate::layered::Layers;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::phases;
+use crate::prefetch;
+use crate::reproducible;
+use crate::resources;
+use crate::sccache;
+use crate::shell_quote;
+use crate::size_report;
+use crate::ssh;
+use crate::system_files;
+use crate::warm;
+use crate::workspace;
+use crate::wrapper;
+use crate::CONTAINER_TARGET_DIR;
+use std::env;
+use std::fs;
+use std::process::Command;
+
+/// The build container, ready to run.
+pub struct Container<'a> {
+    /// `docker run`, without the image and what it runs in it.
+    pub cmd: Command,
+    /// What the build container runs.
+    pub script: String,
+    /// The build container's environment, kept separately as `--layered` builds set it in a Dockerfile instead.
+    pub env: Vec<(&'a str, String)>,
+    /// Named so it can be sampled, and removed on Ctrl-C or a timeout.
+    pub name: String,
+    /// For `--shell` and `--debug-shell`, see `debug_shell`.
+    pub shell: Command,
+    /// For `--prefetch`, which the build then doesn't need the network for, see `prefetch`.
+    pub prefetch: Option<Command>,
+    /// What the tarball or zip is packaged from on the host, see `archivers`.
+    pub package_dir: String,
+    /// What the builder image needs to package the artifact itself.
+    pub archivers: &'static [&'static str],
+    /// Files black_magic writes into the project for the build, e.g. the wrapper's source, for `--layered` builds' context.
+    pub generated: Vec<String>,
+    /// `--layered` builds' `docker build` arguments, with the `--secret`s'.
+    pub layer_build_args: Vec<String>,
+    /// Held until the build is done, see `cargo_lock`.
+    _package_cache: PackageCache,
+}
+
+impl Container<'_> {
+    /// The `--layered` build running the same script, see `layered`.
+    pub fn layers<'b>(&'b self, job: &'b Job) -> Layers<'b> {
+        let cache_volume = job.cache_volume();
+        Layers {
+            builder_image: &job.builder.image,
+            platform: job.target.arch.platform(),
+            env: &self.env,
+            source_env: &job.build_env.metadata,
+            build_args: &self.layer_build_args,
+            deps_cmd: job.compile().deps_cmd(),
+            build_cmd: &self.script,
+            includes: &job.includes,
+            generated: &self.generated,
+            // Nothing's reused without the cache, as with the volume.
+            mounts: if job.options.dockerfile_strategy && job.use_cache {
+                vec![
+                    format!("type=cache,id={},target={}", cache_volume, CONTAINER_TARGET_DIR),
+                    format!("type=cache,id=bm_cargo_registry,target={}/registry", job.container_cargo_home),
+                    format!("type=cache,id=bm_cargo_git,target={}/git", job.container_cargo_home),
+                ]
+            } else {
+                Vec::new()
+            }.into_iter().chain(job.secrets.iter().map(|s| s.mount())).collect(),
+        }
+    }
+}
+
+/*
+Compile using `rust_musl_docker`:
+    - interactive
+    - remove when container finishes, or with `--keep-on-failure` once it's succeeded (see `last_build`)
+    - current working directory as volume, read-only with `--read-only-source`
+    - cargo's target dir outside the working directory, in a named volume unless `--no-cache`
+*/
+/// Puts together the build container for `job`, for backends compiling in the builder image.
+pub fn assemble<'a>(job: &Job<'a>) -> Result<Container<'a>, BmError> {
+    let options = job.options;
+    let runtime = job.runtime()?;
+    let no_side_effects = options.no_side_effects;
+    let cargo_cache = job.cargo_cache;
+
+    // Held until the build is done, see `cargo_lock`.
+    let shares_cargo_home = job.cargo_home_volume.is_none() && (job.cargo_home.join("git").exists() || job.cargo_home.join("registry").exists());
+    let package_cache = if shares_cargo_home && !no_side_effects {
+        let timeout = options.cargo_lock_timeout.unwrap_or(30);
+        PackageCache::lock(&job.cargo_home, std::time::Duration::from_secs(timeout))
+    } else {
+        PackageCache::Unlockable
+    };
+    let private_registry = matches!(package_cache, PackageCache::Busy) && cargo_cache == CargoCache::Share;
+    if private_registry {
+        status!("The cargo registry is still locked, using a private one for this build.");
+    }
+    // What `ro-overlay` copies up from, unless it's still locked.
+    let overlaid: Vec<&str> = match package_cache {
+        PackageCache::Busy => {
+            if cargo_cache == CargoCache::RoOverlay {
+                status!("The cargo registry is still locked, so this build doesn't copy up from it.");
+            }
+            Vec::new()
+        }
+        _ if cargo_cache == CargoCache::RoOverlay => ["registry", "git"].iter().copied().filter(|d| job.cargo_home.join(d).exists()).collect(),
+        _ => Vec::new(),
+    };
+    output::detail(&format!("The build container gets {} (`--cargo-cache {}`).", cargo_cache.description(), cargo_cache.name()));
+
+    let mut cmd = runtime.command();
+    cmd.arg("run").arg("-i");
+    // Read-only builds' outputs are copied out of the container once it's exited.
+    if !options.keep_on_failure && !job.read_only_source {
+        cmd.arg("--rm");
+    }
+    mount(job, &mut cmd, &overlaid, private_registry)?;
+    let mut env = Vec::new();
+
+    let forwarding = ssh::forward(runtime, options.ssh, options.ssh_known_hosts.as_deref(), options.gitconfig.as_deref(), &job.project_dir)?;
+    for v in forwarding.volumes {
+        cmd.arg("-v").arg(v);
+    }
+    env.extend(forwarding.env);
+
+    if options.cargo_config {
+        for v in cargo_config::volumes(runtime, &job.cargo_home, job.container_cargo_home)? {
+            cmd.arg("-v").arg(v);
+        }
+    }
+    let registries: Vec<&str> = options.registry_tokens.iter().map(|r| r.as_str()).collect();
+    for variable in cargo_config::token_variables(&registries)? {
+        cmd.arg("-e").arg(variable);
+    }
+    for secret in &job.secrets {
+        cmd.arg("-v").arg(secret.volume(runtime, &job.project_dir)?);
+    }
+    if job.container_cargo_home != CONTAINER_CARGO_HOME {
+        env.push(("CARGO_HOME", job.container_cargo_home.to_owned()));
+    }
+
+    env.push(("CARGO_TARGET_DIR", CONTAINER_TARGET_DIR.to_owned()));
+    if job.use_cache {
+        cmd.arg("-v").arg(format!("{}:{}", job.cache_volume(), CONTAINER_TARGET_DIR));
+    }
+
+    if let Some(storage) = &job.sccache {
+        match storage {
+            sccache::Storage::Local(dir) => {
+                if !no_side_effects {
+                    fs::create_dir_all(dir).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", dir.display(), e)))?;
+                }
+                cmd.arg("-v").arg(mounts::volume(runtime, dir, sccache::CONTAINER_DIR)?);
+            }
+            // Passed by name, so the values don't show up in the command line.
+            sccache::Storage::S3 { .. } => {
+                for variable in sccache::AWS_VARIABLES.iter().filter(|v| env::var_os(v).is_some()) {
+                    cmd.arg("-e").arg(variable);
+                }
+            }
+            sccache::Storage::Redis(_) => {}
+        }
+        env.extend(storage.env(job.aws.region.as_deref()));
+    }
+    if options.hardened {
+        env.push(("CFLAGS", hardening::CFLAGS.to_owned()));
+    }
+    if let Some(j) = &job.jobs {
+        env.push(("CARGO_BUILD_JOBS", j.clone()));
+    }
+    if let Some(epoch) = job.source_date_epoch {
+        env.push(("SOURCE_DATE_EPOCH", epoch.to_string()));
+    }
+    env.extend(job.linking_env.iter().cloned());
+    for (key, value) in &job.openssl_env {
+        env.retain(|(k, _)| k != key);
+        env.push((*key, value.clone()));
+    }
+    if !job.rustflags.is_empty() {
+        env.push(("RUSTFLAGS", job.rustflags.join(" ")));
+    }
+    env.extend(job.build_env.values.iter().cloned());
+    for (key, value) in env.iter().chain(&job.build_env.metadata) {
+        cmd.arg("-e").arg(format!("{}={}", key, value));
+    }
+    for key in &job.build_env.passed {
+        cmd.arg("-e").arg(key);
+    }
+    cmd.args(job.proxy.run_args());
+    // Prefetched builds only use the network to fetch, see `prefetch`.
+    if !job.prefetch {
+        cmd.args(job.network.run_args());
+    }
+    if let Some(v) = &job.vendor_volume {
+        cmd.arg("-v").arg(v);
+    }
+
+    let build_cmd = build_script(job, &mut cmd);
+    let name = resources::container_name(&job.artifact_name);
+    cmd.arg("--name").arg(&name);
+    if let Some(cpus) = options.cpus.as_deref() {
+        cmd.arg("--cpus").arg(cpus);
+    }
+    if let Some(memory) = options.memory.as_deref() {
+        cmd.arg("--memory").arg(memory);
+    }
+
+    let shell = debug_shell::command(runtime, &cmd, &job.builder.image, &job.container_dir);
+    let prefetch = if job.prefetch {
+        let build = job.compile();
+        let locked = if job.cargo_args.iter().any(|a| a == "--locked") { " --locked" } else { "" };
+        let fetch_cmd = format!(
+            "{}{}{} fetch --target={}{}",
+            cargo_cache::copy_up_cmd(&overlaid, job.container_cargo_home), build.install_cmd(), build.cargo(), job.triple(), locked);
+        let prefetch_cmd = prefetch::command(&cmd, &job.network.run_args(), &job.builder.image, &job.container_dir, &fetch_cmd, &job.config.prefetch.commands);
+        cmd.arg("--network").arg("none");
+        Some(prefetch_cmd)
+    } else {
+        None
+    };
+
+    let archivers = archivers::needed(job.is_docker);
+    let package_dir = archivers::package_dir(&job.artifact_name);
+    let phase_dir = phases::dir(&job.artifact_name);
+    let script = if job.is_docker {
+        tarball_script(job, &build_cmd, archivers, &package_dir, &phase_dir)
+    } else {
+        zip_script(job, &build_cmd, &package_dir, &phase_dir)
+    };
+    let script = match (options.deps_only, job.host_packaging) {
+        (true, _) => warm::cargo_cmd(&job.compile().deps_cmd()),
+        (false, true) => script,
+        (false, false) => format!("{}{}", archivers::check_cmd(archivers), script),
+    };
+    let script = format!("{}{}", cargo_cache::copy_up_cmd(&overlaid, job.container_cargo_home), script);
+    let script = match &job.owner {
+        Some(owner) => {
+            let mut owned = vec![format!("{}/target/black_magic", job.container_dir), format!("{}/Cargo.lock", job.container_dir)];
+            if job.workspace.is_some() {
+                owned.push(format!("{}/Cargo.lock", workspace::CONTAINER_DIR));
+            }
+            if cargo_cache == CargoCache::Share && !private_registry {
+                owned.extend([format!("{}/registry", job.container_cargo_home), format!("{}/git", job.container_cargo_home)]);
+            }
+            format!("{}{}", owner.trap_cmd(&owned), script)
+        }
+        None => script,
+    };
+    let script = if job.read_only_source {
+        format!("mkdir -p target/black_magic && cp -a {}/. target/black_magic/ && {}", READ_ONLY_INPUTS, script)
+    } else {
+        script
+    };
+
+    // The lambda zip's companions are includes, so they're already in the context.
+    let mut generated: Vec<String> = job.config.companions.iter().filter(|_| job.is_docker).map(|c| c.source()).collect();
+    if job.wrapper_source.is_some() {
+        generated.push(job.wrapper_source_file.clone());
+    }
+    let mut layer_build_args = job.builder_build_args.clone();
+    if job.layered {
+        for secret in &job.secrets {
+            layer_build_args.extend(secret.build_args(&job.project_dir)?);
+        }
+    }
+
+    Ok(Container {
+        cmd,
+        script,
+        env,
+        name,
+        shell,
+        prefetch,
+        package_dir,
+        archivers,
+        generated,
+        layer_build_args,
+        _package_cache: package_cache,
+    })
+}
+
+/// Mounts the project, its `target` dir and cargo's home, as the build has them.
+fn mount(job: &Job, cmd: &mut Command, overlaid: &[&str], private_registry: bool) -> Result<(), BmError> {
+    let runtime = job.runtime()?;
+    let project_dir = &job.project_dir;
+    let container_dir = &job.container_dir;
+    let project_volume = mounts::volume(runtime, job.workspace.as_ref().map(|w| &w.root).unwrap_or(project_dir), workspace::CONTAINER_DIR)?;
+    if job.read_only_source {
+        // `target` is an anonymous volume, so `cp` can read it once the container's exited, unlike a tmpfs. What black_magic
+        // wrote into `target/black_magic` for the build (e.g. a wrapper's source) comes in read-only, to be copied over.
+        cmd.arg("-v").arg(format!("{}:ro", project_volume));
+        cmd.arg("-v").arg(format!("{}/target", container_dir));
+        cmd.arg("-v").arg(format!("{}:ro", mounts::volume(runtime, &job.bm_dir, READ_ONLY_INPUTS)?));
+    } else {
+        cmd.arg("-v").arg(project_volume);
+        if job.shadow_target {
+            cmd.arg("--tmpfs").arg(format!("{}/target", container_dir));
+            cmd.arg("-v").arg(mounts::volume(runtime, &job.bm_dir, &format!("{}/target/black_magic", container_dir))?);
+        } else if let Some(v) = mounts::target_volume(runtime, project_dir, container_dir)? {
+            cmd.arg("-v").arg(v);
+        }
+    }
+    if job.workspace.is_some() {
+        cmd.arg("-w").arg(container_dir);
+    }
+
+    if let Some(p) = job.target.arch.platform() {
+        cmd.arg("--platform").arg(p);
+    }
+
+    // With a cargo home volume, the host's cargo home isn't mounted at all, and with `ro-overlay`, only read-only.
+    for dir in ["git", "registry"] {
+        let host = job.cargo_home.join(dir);
+        let container = format!("{}/{}", job.container_cargo_home, dir);
+        if job.cargo_cache == CargoCache::RoOverlay {
+            cmd.arg("-v").arg(format!("{}:{}", cargo_cache::overlay_volume(dir), container));
+        } else if private_registry {
+            let volume = if dir == "git" { cargo_lock::PRIVATE_GIT_VOLUME } else { cargo_lock::PRIVATE_REGISTRY_VOLUME };
+            cmd.arg("-v").arg(format!("{}:{}", volume, container));
+        } else if host.exists() && job.cargo_home_volume.is_none() {
+            cmd.arg("-v").arg(mounts::volume(runtime, &host, &container)?);
+        }
+    }
+
+    for dir in overlaid {
+        cmd.arg("-v").arg(format!("{}:ro", mounts::volume(runtime, &job.cargo_home.join(dir), &cargo_cache::host_dir(dir))?));
+    }
+
+    if let Some(v) = &job.cargo_home_volume {
+        cmd.arg("-v").arg(format!("{}:{}", v, job.container_cargo_home));
+    }
+    Ok(())
+}
+
+/// Compiles the executable (see `backend`), after the gates, from what the cache server has, and with the pre-build hooks.
+fn build_script(job: &Job, cmd: &mut Command) -> String {
+    let options = job.options;
+    let build = job.compile();
+    let mut build_cmd = format!("{}{}", gates::cmd(&build, options.test, options.clippy), job.backend.container_cmd(&build));
+
+    // Share compiled dependencies through the cache server (see `cache_server`), keyed by what changes how they compile.
+    if let Some(url) = &job.cache_server {
+        let deps_options = format!(
+            "{}|{}|{:?}|{}|{:?}|{}|{:?}|{}",
+            job.triple(), job.profile, job.cargo_args, job.builder.image, job.toolchain.as_ref().map(|t| &t.channel),
+            job.container_cargo_home, job.rustflags, job.build_env.fingerprint());
+        match cache_server::key(&job.project_dir, &deps_options) {
+            Some(key) => {
+                let dir = format!("{}/{}/{}", CONTAINER_TARGET_DIR, job.triple(), shell_quote(build.profile_dir()));
+                // Passed by name, so it doesn't show up in the command line. Layered builds have nowhere to pass it.
+                let upload = match cache_server::token(&job.config.cache).filter(|_| !job.layered) {
+                    Some(token) => {
+                        cmd.arg("-e").arg(cache_server::TOKEN_VARIABLE).env(cache_server::TOKEN_VARIABLE, token);
+                        cache_server::upload_cmd(url, &key, &dir)
+                    }
+                    None => {
+                        status!("Without `{}`, or with `--layered`, dependencies are only fetched from the cache server.", cache_server::TOKEN_VARIABLE);
+                        String::new()
+                    }
+                };
+                build_cmd = format!("{}{}{}", cache_server::fetch_cmd(url, &key, &dir), build_cmd, upload);
+            }
+            None => status!("The project has no `Cargo.lock`, so the cache server isn't used."),
+        }
+    }
+
+    if job.sccache.is_some() {
+        build_cmd = sccache::wrap_cmd(&build_cmd, job.triple());
+    }
+
+    if options.resource_report {
+        let registry = format!("{}/registry", job.container_cargo_home);
+        build_cmd = format!("{}{}", resources::cache_size_cmd(&[CONTAINER_TARGET_DIR, &registry], &job.file("cache_size")), build_cmd);
+    }
+    let pre_build = hook_cmd(job, "pre-build", None);
+    if !pre_build.is_empty() {
+        build_cmd = format!("true{} && {}", pre_build, build_cmd);
+    }
+    build_cmd
+}
+
+/// The container hooks run `at` a point of the build, see `hooks`.
+fn hook_cmd(job: &Job, at: &str, executable: Option<&str>) -> String {
+    hooks::container_cmd(&job.config.hooks, at, &job.hook_context(), executable)
+}
+
+/// Inspects the executable before it gets packaged, so it can be checked afterwards:
+/// Disassemble (only with `--cpu-baseline`), dump ELF headers (only with `--hardened`, and the executables' for `elf`),
+/// strip and compress (only with `--strip` and `--upx`), and record the size.
+fn inspect_script(job: &Job) -> String {
+    let options = job.options;
+    let binary = job.binary();
+    let mut inspect_cmd = hook_cmd(job, "post-build", Some(&format!("/{}", job.executable)));
+    if options.cpu_baseline.is_some() {
+        inspect_cmd.push_str(&format!(" && objdump -d --no-show-raw-insn /{} > {}", binary, job.file("objdump")));
+    }
+    if options.hardened {
+        inspect_cmd.push_str(&format!(" && readelf -h -l -d -s --wide /{} > {}", binary, job.file("readelf")));
+    }
+    let elf_executables: Vec<String> = std::iter::once(format!("/{}", binary)).chain(job.helpers.iter().map(|h| format!("/{}", shell_quote(h)))).collect();
+    inspect_cmd.push_str(&elf::dump_cmd(&elf_executables, &job.file("elf")));
+    if options.size_report {
+        inspect_cmd.push_str(&size_report::symbols_cmd(&format!("/{}", binary), &job.file("symbols")));
+    }
+    // Shrunk after the checks above, which need the symbols and the uncompressed code.
+    if options.strip || options.upx {
+        inspect_cmd.push_str(&format!(" && stat -c %s /{} > {}", binary, job.file("unshrunk.size")));
+    }
+    if options.strip {
+        inspect_cmd.push_str(&format!(" && strip /{}", binary));
+        for helper in &job.helpers {
+            inspect_cmd.push_str(&format!(" && strip /{}", shell_quote(helper)));
+        }
+    }
+    if options.upx {
+        inspect_cmd.push_str(&format!(" && upx -q --best /{}", binary));
+    }
+    inspect_cmd.push_str(&format!(" && stat -c %s /{} > {}", binary, job.file("size")));
+    inspect_cmd
+}
+
+/*
+Build (see `build_script`)
+Inspect (see `inspect_script`)
+Check any system files are there (see `system_files::check_cmd`), and write the user's `etc` with `--user`
+With `--lambda-image`, rename it "bootstrap"
+Extract them all into the phase dir at the same paths (see `phases`)
+Tar:
+    - executable at root
+    - to output directory
+    - create
+    - gzip
+    - With filename
+    - with any system files, at the same paths
+    - with the user's `etc/passwd` and `etc/group`
+    - with `--reproducible`, sorted, with fixed times and owners, and gzipped without a timestamp
+Or, without `tar` and `gzip` in the builder image, copy them into the package dir at the same paths
+*/
+/// The docker build's script, leaving the tarball in `target/black_magic`.
+fn tarball_script(job: &Job, build_cmd: &str, archivers: &[&str], package_dir: &str, phase_dir: &str) -> String {
+    let system_files = &job.target.system_files;
+    let bundled_user = job.target.bundled_user();
+    let companions = &job.config.companions;
+    let packaged = job.packaged();
+    let rename = if job.lambda_image { format!(" && mv /{} /bootstrap", job.binary()) } else { String::new() };
+    let files = format!(
+        "/{}{}{}{}",
+        packaged, system_files::tar_args(system_files), bundled_user.map(|u| u.tar_args()).unwrap_or_default(), companion::tar_args(companions));
+    let tarball = job.file("tar.gz");
+    let tar = match job.source_date_epoch {
+        Some(epoch) => format!("set -o pipefail && tar{} -cf - {} | gzip -n > {}", reproducible::tar_options(epoch), files, tarball),
+        None => format!("tar -czf {} {}", tarball, files),
+    };
+    // What's tarred, copied into `dir` at the same paths.
+    let stage = |dir: &str| format!(
+        "{}{}",
+        archivers::stage_cmd(&format!("/{}{}{}", packaged, system_files::tar_args(system_files), companion::tar_args(companions)), dir),
+        bundled_user.map(|u| u.package_cmd(dir)).unwrap_or_default());
+    let extract = if job.resumable { stage(phase_dir) } else { String::new() };
+    let tar = if job.host_packaging {
+        archivers::fallback_cmd(archivers, &format!(" && {}", tar), &stage(package_dir))
+    } else {
+        format!(" && {}", tar)
+    };
+    format!(
+        "{}{}{}{}{}{}{}{}{}{}",
+        build_cmd, inspect_script(job), system_files::check_cmd(system_files), bundled_user.map(|u| u.files_cmd()).unwrap_or_default(),
+        companion::copy_cmd(companions), hook_cmd(job, "pre-package", Some(&format!("/{}", job.executable))), rename, extract, tar,
+        hook_cmd(job, "post-package", None))
+}
+
+/*
+Build (see `build_script`)
+Inspect (see `inspect_script`)
+Rename:
+    - project name
+    - what `--serverless`'s platform calls it, e.g. "bootstrap"
+Or, with a wrapper, compile it to "bootstrap" (see `wrapper::compile_cmd`), or copy the project's own there, and keep
+the executable's name
+Keep the helpers' names
+Make them executable, so the zip records mode 0755
+Extract them into the phase dir (see `phases`)
+Copy them all into the package dir as they'd be zipped, for black_magic to zip (see `archivers`)
+Or, where that can't be done, zip them in the container:
+    - at `--compression`'s level
+    - no directories, just files
+    - to output directory
+    - from "bootstrap" (and the executable it wraps), or the platform's executable, and the helpers, at root
+    - with `--reproducible`, with a fixed time and no extra attributes
+And add any included files (see `bundle::zip_cmd`)
+With `--s3`, everything but the zip goes to stderr, and the zip is written to stdout in one go (see `stream`)
+*/
+/// The lambda build's script, leaving the zip in `target/black_magic`, or streaming it out for `--s3`.
+fn zip_script(job: &Job, build_cmd: &str, package_dir: &str, phase_dir: &str) -> String {
+    let binary = job.binary();
+    let compression = job.compression;
+    let (bootstrap_cmd, mut executables) = match job.wrapper {
+        Some(wrapper::WrapperConfig { script: Some(script), .. }) => (wrapper::script_cmd(script), vec!["/bootstrap".to_owned(), format!("/{}", binary)]),
+        Some(_) => (
+            wrapper::compile_cmd(&job.wrapper_source_file, job.triple()),
+            vec!["/bootstrap".to_owned(), format!("/{}", binary)]),
+        None if job.zip_executable == job.executable => (String::new(), vec![format!("/{}", binary)]),
+        None => (format!(" && mv /{} /{}", binary, shell_quote(&job.zip_executable)), vec![format!("/{}", shell_quote(&job.zip_executable))]),
+    };
+    executables.extend(job.helpers.iter().map(|h| format!("/{}", shell_quote(h))));
+    let bootstrap_cmd = format!("{}{}", hook_cmd(job, "pre-package", Some(&format!("/{}", job.executable))), bootstrap_cmd);
+    let (touch, zip_options) = match job.source_date_epoch {
+        Some(epoch) => (reproducible::touch_cmd(epoch, &executables.join(" ")), format!("{} -X", compression.option())),
+        None => (String::new(), compression.option().to_owned()),
+    };
+    let chmod = format!(" && chmod 0755 {}", executables.join(" "));
+    let extract = if job.resumable { archivers::stage_cmd(&executables.join(" "), phase_dir) } else { String::new() };
+    let inspect_cmd = inspect_script(job);
+    if job.s3.is_some() {
+        return format!(
+            "{{ {}{}{}{}{}; }} >&2{}",
+            build_cmd, inspect_cmd, bootstrap_cmd, chmod, touch, bundle::stream_cmd(&executables, &job.includes, job.source_date_epoch, compression));
+    }
+    let zip = if job.host_packaging {
+        bundle::package_cmd(&executables, &job.includes, package_dir)
+    } else {
+        let zip_file = job.file("zip");
+        format!(
+            " && zip{} -j {} {}{}",
+            zip_options, zip_file, executables.join(" "), bundle::zip_cmd(&job.includes, &zip_file, job.source_date_epoch, compression))
+    };
+    format!("{}{}{}{}{}{}{}{}", build_cmd, inspect_cmd, bootstrap_cmd, chmod, touch, extract, zip, hook_cmd(job, "post-package", None))
+}
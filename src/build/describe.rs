@@ -0,0 +1,230 @@
+//! What a `--no-side-effects` build would do, once it's worked out, see `plan`.
+
+use super::command::Container;
+use super::job::Job;
+use super::publish::project_image_cmd;
+use crate::cas;
+use crate::compose;
+use crate::error::BmError;
+use crate::layered;
+use crate::path_str;
+use crate::phases;
+use crate::phases::Phase;
+use crate::plan::Plan;
+use crate::registry;
+use crate::tags;
+use crate::warm;
+use std::path::Path;
+
+/// Adds running `job`'s build container, if it has one, to `plan`, and prints it.
+pub fn print(job: &Job, container: Option<Container>, mut plan: Plan, resume: Phase, fingerprint: &str) -> Result<(), BmError> {
+    let options = job.options;
+    let container = container.map(|mut c| {
+        c.cmd.arg(&job.builder.image).arg("/bin/bash").arg("-c").arg(&c.script);
+        c
+    });
+    if let Some(container) = container.as_ref().filter(|_| options.deps_only) {
+        plan.step(format!("Write the dependencies' recipe into target/black_magic/{}", warm::RECIPE_DIR));
+        prefetch(container, &mut plan);
+        plan.step(format!("Run {:?}, compiling just the dependencies into the cache volume", container.cmd));
+        plan.command(None, &container.cmd);
+        plan.script("The build container runs", &container.script);
+        if job.read_only_source {
+            plan.step(format!("Remove {}", container.name));
+        }
+        plan.print();
+        return Ok(());
+    }
+
+    if job.use_cache && !options.no_artifact_cache && cas::contains(fingerprint, &[&job.artifact_file, &job.manifest_file]) {
+        plan.step("Reuse the artifact of a previous build of the same source, from the artifact store".to_owned());
+    } else {
+        match resume {
+            Phase::Image => plan.step(format!("Reuse {}, which the last build packaged, only building the image again", job.artifact_file)),
+            Phase::Package => {
+                plan.step(format!("Package what the last compile extracted into {}, on this machine", phases::dir(&job.artifact_name)));
+                checks(job, &mut plan);
+            }
+            Phase::Compile => {
+                compile(job, container.as_ref(), &mut plan)?;
+                checks(job, &mut plan);
+            }
+        }
+    }
+    artifact(job, &mut plan)?;
+    if job.project_image.is_some() {
+        image(job, &mut plan)?;
+    } else if let Some(function_name) = options.deploy.as_deref() {
+        if let Some(r) = job.lambda_runtime {
+            plan.step(format!("Check the {} Lambda function is configured with {}", function_name, r.name()));
+        }
+        plan.step(format!("Deploy the zip to the {} Lambda function, and publish a new version", function_name));
+    }
+    if options.emit_terraform {
+        plan.step(format!("Write a Terraform fragment for the {}", if job.is_docker { "image" } else { "zip" }));
+        plan.output(path_str(&job.terraform_file)?.to_owned());
+    }
+    plan.print();
+    Ok(())
+}
+
+fn prefetch(container: &Container, plan: &mut Plan) {
+    if let Some(prefetch_cmd) = &container.prefetch {
+        plan.step("Fetch the dependencies, and run `[prefetch]`'s commands, for compiling without a network".to_owned());
+        plan.command(None, prefetch_cmd);
+    }
+}
+
+fn compile(job: &Job, container: Option<&Container>, plan: &mut Plan) -> Result<(), BmError> {
+    for hook in job.config.hooks.iter().filter(|h| !h.container && h.at == "pre-build") {
+        plan.step(format!("Run the pre-build hook `{}` on the host", hook.run));
+    }
+    let container = match container {
+        Some(container) => container,
+        None => {
+            plan.step(format!("Compile {} on the host with the `{}` backend", job.triple(), job.backend.name()));
+            plan.step(format!(
+                "Inspect the executable, and package it from {}, where it's extracted, on this machine", phases::dir(&job.artifact_name)));
+            return Ok(());
+        }
+    };
+    prefetch(container, plan);
+    if job.layered {
+        plan.step(format!(
+            "Build the {} image with `docker build`, compiling the dependencies from `Cargo.toml` and `Cargo.lock` first, \
+            then running: {}",
+            job.layered_image, container.script));
+        let layers = container.layers(job);
+        let context_dir = Path::new("target/black_magic").join(layered::context_dir());
+        plan.file(format!("{}/Dockerfile", context_dir.display()), layers.dockerfile());
+        plan.command(None, &layers.build_cmd(job.runtime()?, &context_dir, &job.layered_image));
+    } else {
+        match &job.s3 {
+            Some(s3) => plan.step(format!("Run {:?}, streaming the zip to {}.partial", container.cmd, s3.url())),
+            None => plan.step(format!("Run {:?}", container.cmd)),
+        }
+        plan.command(None, &container.cmd);
+        plan.script("The build container runs", &container.script);
+        if job.read_only_source {
+            plan.step(format!("Copy the outputs out of {} into target/black_magic, then remove it", container.name));
+        }
+        if job.host_packaging {
+            plan.step(format!("If the builder image has no `{}`, package the artifact on this machine", container.archivers.join("` or `")));
+        }
+    }
+    Ok(())
+}
+
+/// What the executable is checked for once it's compiled, see `run::check_executable`.
+fn checks(job: &Job, plan: &mut Plan) {
+    let mut checks = vec![format!("its architecture{}", if job.elf_allowed().is_some() { " and linking" } else { "" })];
+    if job.options.hardened {
+        checks.push("its hardening".to_owned());
+    }
+    if let Some(b) = job.options.cpu_baseline {
+        checks.push(format!("that it only uses `{}` instructions", b.name()));
+    }
+    if job.config.policy.max_binary_size.is_some() {
+        checks.push("its size against the policy".to_owned());
+    }
+    if let Some(r) = job.lambda_runtime {
+        checks.push(format!("that it runs on {}", r.name()));
+    }
+    if !checks.is_empty() {
+        plan.step(format!("Check the executable: {}", checks.join(", ")));
+    }
+    for hook in job.config.hooks.iter().filter(|h| !h.container && h.at == "post-package") {
+        plan.step(format!("Run the post-package hook `{}` on the host", hook.run));
+    }
+    plan.step("Write the manifest, and keep the artifact in the artifact store".to_owned());
+}
+
+fn artifact(job: &Job, plan: &mut Plan) -> Result<(), BmError> {
+    let options = job.options;
+    let artifact = &job.artifact;
+    if !job.is_docker && job.serverless.is_lambda() {
+        plan.step(format!("Check the zip against Lambda's size limits{}", if options.strict_size { ", failing if it's over" } else { "" }));
+    }
+    plan.step("Write the artifact's SHA-256".to_owned());
+    if options.size_report {
+        plan.step("Report the artifact's size against the last build's, and the executable's by crate".to_owned());
+    }
+    plan.output(path_str(artifact)?.to_owned());
+    plan.output(path_str(&job.bm_dir.join(&job.manifest_file))?.to_owned());
+    plan.output(format!("{}.sha256", path_str(artifact)?));
+    if let Some((dir, delivered_name)) = &job.delivery {
+        let extension = job.artifact_file.strip_prefix(job.artifact_name.as_str()).unwrap_or_default();
+        plan.step(format!("Copy the artifact, its SHA-256 and manifest into {}", dir.display()));
+        plan.output(path_str(&dir.join(format!("{}{}", delivered_name, extension)))?.to_owned());
+    }
+
+    if job.signer.is_some() {
+        plan.step(format!("Sign the artifact{}", if job.is_docker { ", and the image once it's pushed" } else { "" }));
+    }
+    if options.emit_sam {
+        plan.step("Write a SAM template for the zip".to_owned());
+        plan.output(path_str(&job.sam_template)?.to_owned());
+    }
+    if let Some(s3) = &job.s3 {
+        plan.step(format!("Move the zip to {}", s3.url()));
+        plan.output(s3.url());
+    }
+    Ok(())
+}
+
+fn image(job: &Job, plan: &mut Plan) -> Result<(), BmError> {
+    let (project_image, dockerfile) = match (&job.project_image, &job.dockerfile) {
+        (Some(project_image), Some(dockerfile)) => (project_image, dockerfile),
+        _ => return Ok(()),
+    };
+    let options = job.options;
+    let runtime = job.runtime()?;
+    let image_platform = job.target.image_platform();
+    plan.step(format!("Build the {} image", job.local_images.join(", ")));
+    plan.output(project_image.clone());
+    plan.file("target/black_magic/Dockerfile".to_owned(), dockerfile.clone());
+    plan.command(Some("target/black_magic"), &project_image_cmd(runtime, image_platform, "Dockerfile", &job.local_images));
+    if let Some(smoke_test) = &job.smoke_test {
+        plan.step("Run the smoke test against it".to_owned());
+        for command in smoke_test.commands(runtime, project_image, image_platform) {
+            plan.command(None, &command);
+        }
+    }
+    if options.integration_test {
+        plan.step("Run the integration test against it".to_owned());
+    }
+    if let Some(previous) = options.diff_against.as_deref() {
+        plan.step(format!("Compare its files with {}, pulling it if needed", previous));
+    }
+    if let Some(scan) = &job.scan {
+        plan.step(scan.describe());
+    }
+    if let Some(path) = &job.export_oci {
+        plan.step(format!("Save it to {}, with its SHA-256", path.display()));
+        plan.output(path_str(path)?.to_owned());
+    }
+    if let Some(cluster) = options.load_into.as_deref() {
+        plan.step(format!("Load it into {}", cluster));
+    }
+    if options.emit_compose {
+        plan.step(format!("Write the {} service running it to {}", compose::service_name(&job.config.compose, &job.name), compose::FILE));
+        plan.output(compose::FILE.to_owned());
+    }
+    let mut push_to = options.push.clone();
+    if let Some(ecr) = options.ecr.as_deref() {
+        plan.step(format!("Log into ECR, creating the `{}` repository if it doesn't exist", registry::split_tag(ecr).0));
+        push_to.push(format!("<ECR registry>/{}", ecr));
+    }
+    for remote in &tags::remotes(&push_to, &job.image_tags) {
+        plan.step(format!("Push it to {}", remote));
+        plan.output(remote.clone());
+    }
+    if options.debug_image {
+        plan.step(format!("Build the {}-debug image", project_image));
+        plan.output(format!("{}-debug", project_image));
+    }
+    if let Some(function_name) = options.deploy.as_deref() {
+        plan.step(format!("Point the {} Lambda function at the image pushed to ECR, and publish a new version", function_name));
+    }
+    Ok(())
+}
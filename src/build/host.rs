@@ -0,0 +1,180 @@
+//! Compiling, inspecting and packaging on this machine, for the backends compiling on the host (see `backend`), which have no
+//! build container. What its script does (see `command`) is done here instead: the executables are laid out in the phase
+//! dir as the build container would extract them, inspected there with this machine's `objdump`, `readelf`, `nm`, `strip`
+//! and `upx`, and packaged from it (see `phases`), so later phases can always rerun on their own.
+
+use super::job::Job;
+use super::run;
+use super::run::Produced;
+use crate::elf;
+use crate::error::BmError;
+use crate::history;
+use crate::hooks;
+use crate::output;
+use crate::output::status;
+use crate::path_str;
+use crate::phases;
+use crate::progress;
+use crate::size_report;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Instant;
+
+/// Compiles the executable with the host backend, then inspects and packages it, checking it links as it needs to before
+/// it's packaged.
+pub fn compile(job: &Job, phase: &mut progress::Phase) -> Result<Produced, BmError> {
+    *phase = progress::phase("compile");
+    let project_dir = &job.project_dir;
+    let compile_started = Instant::now();
+    history::cache("compiled", false);
+    output::marker("BEGIN_COMPILE", &[("target", job.triple())]);
+    if job.is_docker {
+        status!("Compiling project...");
+    } else {
+        status!("Compiling project to lambda zip...");
+    }
+    if job.cache_server.is_some() {
+        status!("The `{}` backend compiles on the host, so the cache server isn't used.", job.backend.name());
+    }
+    hooks::run(&job.config.hooks, "pre-build", project_dir, &job.hook_context())?;
+    let build = job.compile();
+    job.backend.compile_on_host(&build, project_dir)?;
+    phases::forget(&job.bm_dir, &job.artifact_name);
+    let compiled = build.executables(project_dir)?;
+    output::marker("END_COMPILE", &[("seconds", &format!("{:.1}", compile_started.elapsed().as_secs_f64()))]);
+
+    let phase_dir = phases::dir(&job.artifact_name);
+    let executables = lay_out(job, &compiled, &project_dir.join(&phase_dir))
+        .map_err(|e| BmError::Packaging(format!("Unable to put the artifact's files in `{}`: {}", phase_dir, e)))?;
+    inspect(job, &executables)?;
+    // Before anything's packaged or goes anywhere, see `elf`.
+    let elf_file = project_dir.join(job.file("elf"));
+    match elf::dump(&executables) {
+        Some(dump) => {
+            fs::write(&elf_file, &dump).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", elf_file.display(), e)))?;
+            run::check_linking(job, &dump, &phase_dir)?;
+        }
+        None => {
+            let _ = fs::remove_file(&elf_file);
+            output::warning("This machine has no `readelf`, so the executable's architecture and linking weren't checked.");
+        }
+    }
+
+    *phase = progress::phase("package");
+    phases::package(project_dir, &job.artifact_name, &job.includes, &job.artifact, job.is_docker, job.source_date_epoch, job.compression)?;
+    Ok(Produced::default())
+}
+
+/// Copies the executables `compiled` into `dir`, where and as the artifact has them, with what goes next to them, returning
+/// the executable's and the helpers' paths in the artifact and in `dir`.
+fn lay_out(job: &Job, compiled: &[(&str, PathBuf)], dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir)?;
+    // `--lambda-image` runs it as `bootstrap`, and a zip as its platform names it, unless a wrapper runs it.
+    let packaged = if job.lambda_image {
+        "bootstrap"
+    } else if job.is_docker || job.wrapper.is_some() {
+        job.executable
+    } else {
+        job.zip_executable.as_str()
+    };
+    let mut executables = Vec::new();
+    for (i, (name, path)) in compiled.iter().enumerate() {
+        let dest = dir.join(if i == 0 { packaged } else { name });
+        fs::copy(path, &dest)?;
+        set_executable(&dest)?;
+        executables.push((format!("/{}", name), dest));
+    }
+    if let Some(script) = job.wrapper.and_then(|w| w.script.as_deref()) {
+        let bootstrap = dir.join("bootstrap");
+        fs::copy(job.project_dir.join(script), &bootstrap)?;
+        set_executable(&bootstrap)?;
+    }
+    // The zip's companions are includes, packaged with the rest of them.
+    if job.is_docker {
+        if let Some(user) = job.target.bundled_user() {
+            user.write(dir)?;
+        }
+        for companion in &job.config.companions {
+            let dest = dir.join(companion.dest());
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(job.project_dir.join(companion.source()), &dest)?;
+            set_executable(&dest)?;
+        }
+    }
+    Ok(executables)
+}
+
+/// Inspects the executable as the build container does (see `command`): disassembles it for `--cpu-baseline`, dumps its
+/// headers for `--hardened` and lists its symbols for `--size-report`, then strips and compresses it for `--strip` and
+/// `--upx`, and records its size, into the same files, for `run` to check.
+fn inspect(job: &Job, executables: &[(String, PathBuf)]) -> Result<(), BmError> {
+    let options = job.options;
+    let project_dir = &job.project_dir;
+    let binary = &executables[0].1;
+    if options.cpu_baseline.is_some() {
+        let listing = tool("--cpu-baseline", "objdump", &["-d", "--no-show-raw-insn"], binary)?;
+        write(project_dir, &job.file("objdump"), &listing)?;
+    }
+    if options.hardened {
+        let headers = tool("--hardened", "readelf", &["-h", "-l", "-d", "-s", "--wide"], binary)?;
+        write(project_dir, &job.file("readelf"), &headers)?;
+    }
+    if options.size_report {
+        size_report::symbols(binary, &project_dir.join(job.file("symbols")));
+    }
+    // Shrunk after the checks above, which need the symbols and the uncompressed code.
+    if options.strip || options.upx {
+        write(project_dir, &job.file("unshrunk.size"), size(binary)?.as_bytes())?;
+    }
+    if options.strip {
+        for (_, executable) in executables {
+            tool("--strip", "strip", &[], executable)?;
+        }
+    }
+    if options.upx {
+        tool("--upx", "upx", &["-q", "--best"], binary)?;
+    }
+    write(project_dir, &job.file("size"), size(binary)?.as_bytes())
+}
+
+/// Runs `program` with `args` on `executable`, for `flag`, returning what it printed.
+fn tool(flag: &str, program: &str, args: &[&str], executable: &Path) -> Result<Vec<u8>, BmError> {
+    let ran = Command::new(program)
+        .args(args)
+        .arg(executable)
+        .output()
+        .map_err(|e| BmError::Environment(format!("`{}` needs `{}` on this machine, which compiles with the host backends: {}", flag, program, e)))?;
+    if !ran.status.success() {
+        return Err(BmError::Packaging(format!(
+            "`{}` failed on `{}`, for `{}`.\n\nstderr: {}", program, path_str(executable)?, flag, String::from_utf8_lossy(&ran.stderr))));
+    }
+    Ok(ran.stdout)
+}
+
+/// Writes `contents` into `file`, relative to the project.
+fn write(project_dir: &Path, file: &str, contents: &[u8]) -> Result<(), BmError> {
+    fs::write(project_dir.join(file), contents).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", file, e)))
+}
+
+/// `executable`'s size, as `stat -c %s` prints it.
+fn size(executable: &Path) -> Result<String, BmError> {
+    fs::metadata(executable).map(|m| m.len().to_string()).map_err(|e| BmError::Packaging(format!("Unable to read binary size: {}", e)))
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+/// Archived with the mode they'd have anyway, see `archivers`.
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
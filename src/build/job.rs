@@ -0,0 +1,942 @@
+//! Everything the build works out before it runs anything: what it compiles and how, what the artifact is called and holds,
+//! and where it goes, from the options and `BlackMagic.toml`.
+
+use super::checks;
+use super::checks::Target;
+use super::CONTAINER_CARGO_HOME;
+use super::CONTAINER_CARGO_HOME_VOLUME;
+use crate::api::BuildConfig;
+use crate::api::Mode;
+use crate::aws::Aws;
+use crate::backend;
+use crate::backend::CompileBackend;
+use crate::baseline::CpuBaseline;
+use crate::bins;
+use crate::build_env;
+use crate::build_env::BuildEnv;
+use crate::builder;
+use crate::bundle;
+use crate::bundle::Compression;
+use crate::bundle::Include;
+use crate::cache_server;
+use crate::cargo_cache;
+use crate::cargo_cache::CargoCache;
+use crate::companion;
+use crate::config::Config;
+use crate::elf;
+use crate::error::BmError;
+use crate::hardening;
+use crate::hooks;
+use crate::lambda_runtime::LambdaRuntime;
+use crate::layered;
+use crate::lockfile;
+use crate::manifest;
+use crate::metadata::Metadata;
+use crate::names;
+use crate::network::Network;
+use crate::openssl;
+use crate::openssl::OpensslMode;
+use crate::out_dir;
+use crate::output;
+use crate::output::status;
+use crate::packaging;
+use crate::packaging::ServerlessTarget;
+use crate::path_str;
+use crate::phases;
+use crate::progress;
+use crate::project_name;
+use crate::proxy::Proxy;
+use crate::release;
+use crate::reproducible;
+use crate::retry::Retry;
+use crate::run_options;
+use crate::runtime::Runtime;
+use crate::sbom::Sbom;
+use crate::sbom::SbomFormat;
+use crate::scan::Scan;
+use crate::sccache;
+use crate::secrets::Secret;
+use crate::shell_quote;
+use crate::sign::Signer;
+use crate::smoke::SmokeTest;
+use crate::static_linking;
+use crate::stream::S3Location;
+use crate::tags;
+use crate::template;
+use crate::toolchain::Toolchain;
+use crate::unification;
+use crate::unification::Selection;
+use crate::user_map;
+use crate::user_map::Owner;
+use crate::vendor::Vendor;
+use crate::workspace;
+use crate::workspace::Workspace;
+use crate::wrapper::WrapperConfig;
+use crate::Arch;
+use crate::Libc;
+use std::path::PathBuf;
+
+/// A build, worked out.
+pub struct Job<'a> {
+    pub options: &'a BuildConfig,
+    pub config: &'a Config,
+    /// Docker or podman, which the host backends only need for the image and the companions, see `runtime`.
+    pub runtime: Option<Runtime>,
+    pub project_dir: PathBuf,
+    pub cargo_toml: PathBuf,
+    pub bm_dir: PathBuf,
+    pub is_docker: bool,
+    /// A Lambda container image is a docker build, with the executable where Lambda's base images run it from.
+    pub lambda_image: bool,
+    pub use_cache: bool,
+    pub target: Target,
+
+    // What's compiled, and how.
+    pub profile: &'a str,
+    /// Everything passed on to `cargo build`, features included.
+    pub cargo_args: Vec<String>,
+    pub rustflags: Vec<String>,
+    pub toolchain: Option<Toolchain>,
+    pub source_date_epoch: Option<u64>,
+    pub build_env: BuildEnv<'a>,
+    /// Set in the build container, from `static_linking`.
+    pub linking_env: Vec<(&'static str, String)>,
+    /// Set in the build container for `--openssl`, over anything else setting the same variables.
+    pub openssl_env: Vec<(&'static str, String)>,
+    pub jobs: Option<String>,
+    /// The host backends compile in the host's environment already, with these added.
+    pub host_env: Vec<(&'a str, String)>,
+    pub backend: Box<dyn CompileBackend>,
+    pub builder: builder::Builder,
+    pub builder_build_args: Vec<String>,
+    pub proxy: Proxy,
+    pub network: Network,
+    pub retry: Retry,
+    pub project_name: &'a str,
+    /// The cache volume's, which is the project's, shared by all of its executables, see `bins`.
+    pub cache_name: String,
+    /// `bin`, `example` or `bench`.
+    pub target_kind: &'static str,
+    /// The executable cargo builds, unquoted.
+    pub executable: &'a str,
+    /// The other `[[bin]]`s in the zip, for `helpers` in `[lambda]`.
+    pub helpers: Vec<String>,
+
+    // Where it's compiled.
+    /// `--layered`, `--strategy dockerfile` or a remote daemon: compiled in `docker build`, without a build container.
+    pub layered: bool,
+    /// A workspace member, or a project with path dependencies outside it, needs more than itself mounted, see `workspace`.
+    pub workspace: Option<Workspace>,
+    /// The project in the build container.
+    pub container_dir: String,
+    pub prefetch: bool,
+    pub read_only_source: bool,
+    pub shadow_target: bool,
+    /// Who the build's files are given to, see `user_map`.
+    pub owner: Option<Owner>,
+    pub cargo_cache: CargoCache,
+    /// The host's.
+    pub cargo_home: PathBuf,
+    pub cargo_home_volume: Option<String>,
+    pub container_cargo_home: &'a str,
+    pub cache_server: Option<String>,
+    pub sccache: Option<sccache::Storage>,
+    pub secrets: Vec<Secret>,
+    pub vendor_volume: Option<String>,
+    pub aws: Aws,
+
+    // What's packaged.
+    pub name: String,
+    pub artifact_name: String,
+    /// `<artifact_name>.tar.gz` or `.zip`, in `bm_dir`.
+    pub artifact_file: String,
+    pub artifact: PathBuf,
+    pub manifest_file: String,
+    pub includes: Vec<Include>,
+    pub wrapper: Option<&'a WrapperConfig>,
+    /// The wrapper's source, for wrappers compiled into `bootstrap`, written to `wrapper_source_file`.
+    pub wrapper_source: Option<String>,
+    pub wrapper_source_file: String,
+    pub serverless: Box<dyn ServerlessTarget + 'a>,
+    /// The platform's own files, e.g. a `host.json`, zipped as includes.
+    pub serverless_files: Vec<(String, String)>,
+    /// What the executable is called in the zip.
+    pub zip_executable: String,
+    pub lambda_runtime: Option<LambdaRuntime>,
+    pub compression: Compression,
+    /// Zips, and tarballs without `tar` in the builder image, are packaged on the host if they can be, see `archivers`.
+    pub host_packaging: bool,
+    /// Later phases rerun on their own by packaging on this machine too, see `phases`.
+    pub resumable: bool,
+    pub timings_file: PathBuf,
+    /// Where the build container records things about the build, relative to the project, see `file`.
+    pub rustc_version: String,
+    pub cargo_version: String,
+    pub cargo_messages: String,
+    pub hook_artifact: String,
+
+    // Where it goes.
+    pub s3: Option<S3Location>,
+    /// The directory and name it's delivered as, for `--out-dir` and `--artifact-name`.
+    pub delivery: Option<(PathBuf, String)>,
+    pub sam_template: PathBuf,
+    pub terraform_file: PathBuf,
+    pub export_oci: Option<PathBuf>,
+    pub sbom: Option<Sbom>,
+    pub signer: Option<Signer<'a>>,
+
+    // The image.
+    /// Rendered up front, so a broken template fails before the build rather than after it.
+    pub dockerfile: Option<String>,
+    pub run_options: template::RunOptions,
+    pub image_tags: Vec<String>,
+    pub project_image: Option<String>,
+    /// The project image, and it tagged with each of `image_tags`.
+    pub local_images: Vec<String>,
+    pub layered_image: String,
+    pub smoke_test: Option<SmokeTest>,
+    pub scan: Option<Scan>,
+}
+
+impl<'a> Job<'a> {
+    /// Works out the build `options` asks for, of the project configured by `config`, for `target`. Nothing's written yet.
+    pub fn resolve(options: &'a BuildConfig, config: &'a Config, target: Target) -> Result<Job<'a>, BmError> {
+        let is_docker = options.mode != Mode::Lambda;
+        let is_lambda = options.mode == Mode::Lambda;
+        let lambda_image = options.mode == Mode::LambdaImage;
+        let use_cache = !options.no_cache;
+        let no_side_effects = options.no_side_effects;
+        let project_dir = options.project_dir.clone();
+        let cargo_toml = project_dir.join("Cargo.toml");
+        let bm_dir = project_dir.join("target").join("black_magic");
+        let triple = target.triple().to_owned();
+        let arch = target.arch;
+        let profile = options.profile.as_deref().unwrap_or("release");
+
+        let mut cargo_args = Vec::new();
+        if !options.features.is_empty() {
+            cargo_args.push("--features".to_owned());
+            cargo_args.push(options.features.join(","));
+        }
+        if options.no_default_features {
+            cargo_args.push("--no-default-features".to_owned());
+        }
+        if options.all_features {
+            cargo_args.push("--all-features".to_owned());
+        }
+        cargo_args.extend(options.cargo_args.iter().cloned());
+
+        let proxy = Proxy::new(&config.proxy);
+        let network = Network::new(
+            &config.network,
+            options.network.as_deref(),
+            options.dns.clone(),
+            options.add_hosts.clone(),
+        ).map_err(BmError::Environment)?;
+        let builder_build_args = [proxy.build_args(), network.build_args()].concat();
+        let retry = Retry::new(options.retries, options.retry_delay);
+        if options.integration_test && config.integration_test.is_none() {
+            return Err(BmError::Environment("`--integration-test` needs an `[integration_test]` section in `BlackMagic.toml`.".to_owned()));
+        }
+
+        let config_includes: Vec<String> = config.lambda.include.iter().filter(|_| is_lambda).cloned().collect();
+        let mut includes = (if options.includes.is_empty() { &config_includes } else { &options.includes })
+            .iter()
+            .map(|i| Include::parse(i, &project_dir))
+            .collect::<Result<Vec<_>, _>>()?;
+        bundle::check(&includes, &project_dir)?;
+        let wrapper = config.lambda.wrapper.as_ref().filter(|_| is_lambda);
+        let lambda_runtime = if is_lambda { LambdaRuntime::select(options.lambda_runtime, config.lambda.runtime.as_deref())? } else { None };
+        let compression = options.compression.unwrap_or(Compression::Default);
+
+        // Without the cache volume there's nowhere to keep what's fetched, and `--no-cache` means compiling everything anyway.
+        let cache_server = options
+            .cache_server
+            .as_deref()
+            .or(config.cache.server.as_deref())
+            .filter(|_| use_cache)
+            .map(cache_server::parse_url)
+            .transpose()?;
+
+        let aws = Aws::new(
+            options.region.clone().or_else(|| config.aws.region.clone()),
+            options.aws_profile.clone().or_else(|| config.aws.profile.clone()),
+        );
+
+        let cargo_home_volume = options.cargo_home_volume.as_deref().or(config.cargo_home.volume.as_deref());
+        let cargo_cache = CargoCache::select(options.cargo_cache, config.cargo_home.cache.as_deref(), cargo_home_volume)?;
+        let cargo_home_volume = match cargo_cache {
+            CargoCache::Isolated => Some(cargo_home_volume.map(|v| v.to_owned()).unwrap_or_else(cargo_cache::home_volume)),
+            _ => None,
+        };
+        let container_cargo_home = options
+            .cargo_home
+            .as_deref()
+            .or(config.cargo_home.path.as_deref())
+            .unwrap_or(if cargo_home_volume.is_some() { CONTAINER_CARGO_HOME_VOLUME } else { CONTAINER_CARGO_HOME })
+            .trim_end_matches('/');
+        if !container_cargo_home.starts_with('/') {
+            return Err(BmError::Environment("`--cargo-home` must be an absolute path inside the container.".to_owned()));
+        }
+
+        let backend = backend::select(options.backend, &config.backend, &triple)?;
+        if target.custom.is_some() && options.builder_image.is_none() && config.builder.image.is_none() {
+            return Err(BmError::Environment(format!(
+                "The default builder images can't link for `{}`, so give one that can with `--builder-image` (or `image` in `[builder]`).", triple)));
+        }
+        // The host backends compile and package on this machine, and only need a runtime to build the image or fetch companions.
+        let runtime = if backend.in_container() || (is_docker && !options.no_image) || !config.companions.is_empty() {
+            Some(Runtime::detect(options.runtime.map(Runtime::name))?)
+        } else {
+            None
+        };
+        // Only the backends compiling in the builder image have a build container.
+        let container_runtime = runtime.filter(|_| backend.in_container());
+        // A remote daemon's containers can't mount the project, but `docker build` sends it over as the build context.
+        let remote_host = container_runtime.filter(|_| !options.layered && !options.dockerfile_strategy).and_then(|r| r.remote_host());
+        if let (Some(host), Some(runtime)) = (&remote_host, container_runtime) {
+            status!("The {} daemon at {} is remote, so the project is sent to it to compile with `docker build`, as with `--layered`.", runtime.name(), host);
+        }
+        let layered = options.layered || options.dockerfile_strategy || remote_host.is_some();
+        checks::backend(options, backend.as_ref(), layered)?;
+        let workspace = workspace::detect(&project_dir).filter(|_| backend.in_container());
+        if let Some(workspace) = workspace.as_ref().filter(|_| layered) {
+            output::warning(&format!(
+                "The project needs `{}` to build, but `--layered` builds and remote daemons only get the project, so it may not build as it does there.",
+                workspace.root.display()));
+        }
+        let workspace = workspace.filter(|_| !layered);
+        let container_dir = workspace.as_ref().map(|w| w.project_dir.clone()).unwrap_or_else(|| workspace::CONTAINER_DIR.to_owned());
+        if let Some(workspace) = &workspace {
+            status!("Mounting {}, which the project needs to build, and building in {}.", workspace.root.display(), workspace.project_dir);
+        }
+        // Builds that are offline anyway have nothing to fetch.
+        let prefetch = (options.prefetch || config.prefetch.enabled)
+            && backend.in_container()
+            && !layered
+            && !(options.offline || options.vendor.is_some() || options.frozen);
+        let read_only_source = (options.read_only_source || config.build.read_only_source) && backend.in_container() && !layered;
+        // `--layered` builds never have `target` in their context, and read-only ones hide it already.
+        let shadow_target =
+            (options.shadow_target || config.build.shadow_target) && backend.in_container() && !layered && !read_only_source;
+        // `--layered` and read-only builds copy their outputs out, as the user.
+        let owner = container_runtime.filter(|_| !layered && !read_only_source).and_then(|r| user_map::owner(r, options.user_map.or(config.build.user_map)));
+        let sccache = if options.sccache { Some(config.sccache.storage()?) } else { None };
+        let secrets = options.secrets.iter().map(|s| Secret::parse(s)).collect::<Result<Vec<_>, _>>().map_err(BmError::Environment)?;
+        let build_env = BuildEnv::resolve(options.build_env.iter().map(|e| e.as_str()), &config.env)?;
+        if layered && !build_env.passed.is_empty() {
+            return Err(BmError::Environment(format!(
+                "`--layered` builds (and remote daemons) keep their variables in the image, so `--build-env {}` needs a value.",
+                build_env.passed[0])));
+        }
+        let toolchain = Toolchain::detect(&project_dir)?;
+        let source_date_epoch = if options.reproducible { Some(reproducible::source_date_epoch(&project_dir)) } else { None };
+        let metadata_env = config.build.metadata_env.clone().unwrap_or_else(|| build_env::METADATA.iter().map(|m| m.to_string()).collect());
+        let build_env = build_env.with_metadata(&metadata_env, &project_dir, &cargo_toml, source_date_epoch)?;
+
+        // Cargo can't write `Cargo.lock` into a read-only project.
+        lockfile::check(&project_dir, read_only_source || options.strict || options.locked || options.frozen)?;
+        // `cargo metadata` would update `Cargo.lock` on the host otherwise.
+        let metadata_lock = if no_side_effects || options.frozen {
+            Some("--frozen")
+        } else if options.locked {
+            Some("--locked")
+        } else {
+            None
+        };
+
+        if config.policy.checks_dependencies() {
+            let violations = config.policy.check_dependencies(&Metadata::load(&project_dir, &triple, metadata_lock)?);
+            if !violations.is_empty() {
+                let mut message = "The project's dependencies violate the policy in `BlackMagic.toml`:".to_owned();
+                for v in violations {
+                    message.push_str(&format!("\n    {}", v));
+                }
+                return Err(BmError::Packaging(message));
+            }
+        }
+
+        let builder = builder::Builder::new(
+            arch,
+            target.libc == Libc::Gnu,
+            options.builder_image.as_deref().or(config.builder.image.as_deref()),
+            options.builder_tag.as_deref().or(config.builder.tag.as_deref()))
+            .pinned(&config.builder.pins)?
+            .with_upx(options.upx)
+            .customized(&config.builder.packages, config.builder.setup_script.as_deref())?
+            .cached(options.builder_cache_from.as_deref().or(config.builder.cache_from.as_deref()), options.builder_cache_push)?;
+        if builder.digest.is_none() && !config.builder.pins.is_empty() && backend.in_container() {
+            output::warning(&format!(
+                "`[builder.pins]` has no pin for {}, so the builder image isn't pinned. Pin it with `black_magic pin-builder{}{}`.",
+                builder.pin_key(), if arch == Arch::Aarch64 { " --arch aarch64" } else { "" }, if target.libc == Libc::Gnu { " --libc gnu" } else { "" }));
+        }
+
+        let project_name = project_name(&options.project_dir)?;
+        let cache_name = names::resolve(project_name, options.name.as_deref().or(config.name.as_deref()))?;
+        let selected = [(&options.bin, "bin"), (&options.example, "example"), (&options.bench, "bench")].iter()
+            .find_map(|(executable, kind)| executable.as_deref().map(|t| (*kind, t)));
+        let (target_kind, executable, name) = match selected {
+            Some((kind, executable)) => {
+                bins::check(&project_dir, &Metadata::load(&project_dir, &triple, metadata_lock)?, kind, executable)?;
+                if !cargo_args.iter().any(|a| a == "--bins") {
+                    cargo_args.push(format!("--{}", kind));
+                    cargo_args.push(executable.to_owned());
+                }
+                // Apart from an executable with the same name.
+                let artifact = if kind == "bin" { executable.to_owned() } else { format!("{}_{}", kind, executable) };
+                (kind, executable, names::resolve(&artifact, options.name.as_deref())?)
+            }
+            None => ("bin", project_name, cache_name.clone()),
+        };
+        let helpers: Vec<String> = config.lambda.helpers.iter().filter(|_| is_lambda).cloned().collect();
+        if !helpers.is_empty() {
+            let metadata = Metadata::load(&project_dir, &triple, metadata_lock)?;
+            for (i, helper) in helpers.iter().enumerate() {
+                if helper == executable || helper == "bootstrap" || helpers[..i].contains(helper) {
+                    return Err(BmError::Environment(format!(
+                        "`{}` can't be one of the `helpers` in `[lambda]`, it's already in the zip as the executable, `bootstrap` or another helper.", helper)));
+                }
+                bins::check(&project_dir, &metadata, "bin", helper)?;
+            }
+            // Cargo builds every `[[bin]]` unless it's told which.
+            if cargo_args.iter().any(|a| a == "--bin" || a == "--example" || a == "--bench") {
+                for helper in &helpers {
+                    cargo_args.push("--bin".to_owned());
+                    cargo_args.push(helper.clone());
+                }
+            }
+        }
+        if let Some(wrapper) = wrapper {
+            wrapper.check_script(&project_dir, executable)?;
+            if wrapper.script.is_none() && !backend.in_container() {
+                return Err(BmError::Environment(format!(
+                    "`[lambda.wrapper]` is compiled in the build container, which the `{}` backend doesn't have, so give it a `script` instead.",
+                    backend.name())));
+            }
+        }
+        // Docker builds package the executable as it is.
+        let serverless = packaging::select(if is_lambda { options.serverless } else { None }, &config.serverless, &name)?;
+        if !serverless.is_lambda() {
+            let lambda_only = [
+                (options.deploy.is_some(), "--deploy"), (options.s3.is_some(), "--s3"), (options.emit_sam, "--emit-sam"),
+                (options.emit_terraform, "--emit-terraform"),
+            ];
+            if let Some((_, flag)) = lambda_only.iter().find(|(given, _)| *given) {
+                return Err(BmError::Environment(format!("`{}` only applies to Lambda, not `--serverless {}`.", flag, serverless.name())));
+            } else if lambda_runtime.is_some() || wrapper.is_some() {
+                return Err(BmError::Environment(format!(
+                    "`--lambda-runtime`, and `runtime` and `[lambda.wrapper]` in `BlackMagic.toml`, only apply to Lambda, not `--serverless {}`.",
+                    serverless.name())));
+            }
+        }
+        let zip_executable = serverless.executable(executable);
+        let serverless_files = serverless.files(&zip_executable)?;
+        let target_suffix = target.custom.as_ref().map(|t| format!("-{}", t.triple)).unwrap_or_else(|| arch.suffix().to_owned());
+        let artifact_name = format!("{}{}{}{}", name, target_suffix, serverless.suffix(), lambda_runtime.map(|r| r.suffix()).unwrap_or(""));
+        let timings_file = bm_dir.join(format!("{}.timings.json", artifact_name));
+        progress::load_previous(&timings_file);
+        let smoke_test = SmokeTest::new(&config.smoke_test, options.smoke_test.as_deref(), options.smoke_test_timeout, &artifact_name)?
+            .filter(|_| is_docker && !options.no_image);
+        let scan = Scan::new(&config.scan, options.scan, options.scan_fail_on)?
+            .filter(|_| is_docker && !options.no_image);
+        // The wrapper runs the executable from next to it in the zip, where the helpers are too.
+        let beside: Vec<&str> = wrapper.map(|_| executable).into_iter().chain(helpers.iter().map(|h| h.as_str())).collect();
+        if let Some(taken) = beside.iter().find(|e| includes.iter().any(|i| i.dest == **e || i.dest.starts_with(&format!("{}/", e)))) {
+            return Err(BmError::Environment(format!("Can't include anything at `{}`, that's where an executable goes.", taken)));
+        }
+        let mut reserved = vec![if is_docker && !lambda_image { executable } else if lambda_image { "bootstrap" } else { zip_executable.as_str() }];
+        if wrapper.is_some() {
+            reserved.push(executable);
+        }
+        reserved.extend(helpers.iter().map(|h| h.as_str()));
+        for (path, _) in &serverless_files {
+            if let Some(include) = includes.iter().find(|i| i.dest == *path || path.starts_with(&format!("{}/", i.dest))) {
+                return Err(BmError::Environment(format!(
+                    "Can't include `{}` at `{}`, `--serverless {}` puts its own `{}` there.", include.source, include.dest, serverless.name(), path)));
+            }
+            reserved.push(path);
+        }
+        let system_paths: Vec<&str> = target.system_files.iter().map(|f| f.path().trim_start_matches('/')).collect();
+        reserved.extend(&system_paths);
+        companion::check(&config.companions, &includes, &reserved)?;
+        hooks::check(&config.hooks)?;
+        if let Some(hook) = config.hooks.iter().find(|h| h.container && !backend.in_container()) {
+            return Err(BmError::Environment(format!(
+                "The `{}` hook `{}` runs in the build container, which the `{}` backend doesn't have, it compiles and packages on this machine.",
+                hook.at, hook.run, backend.name())));
+        }
+        if options.s3.is_some() && hooks::any(&config.hooks, "post-package") {
+            return Err(BmError::Environment("`post-package` hooks can change the zip, which `--s3` uploads while it's being packaged.".to_owned()));
+        }
+        if is_lambda {
+            includes.extend(config.companions.iter().map(|c| Include { source: c.source(), dest: c.dest().to_owned() }));
+        }
+        // The platform's own files are zipped like includes, from where they're written.
+        if !serverless_files.is_empty() {
+            let staging_dir = packaging::staging_dir(&artifact_name);
+            includes.extend(serverless_files.iter().map(|(path, _)| Include { source: format!("{}/{}", staging_dir, path), dest: path.clone() }));
+        }
+        let wrapper_source_file = file(&artifact_name, "bootstrap.rs");
+        let wrapper_source = wrapper.filter(|w| w.script.is_none()).map(|w| w.source(executable));
+
+        // Packages compiled together share their dependencies' features, see `unification`.
+        let selection = Selection::parse(&cargo_args);
+        if selection.is_several() {
+            let metadata = Metadata::load(&project_dir, &triple, metadata_lock)?;
+            let packages = selection.packages(&metadata);
+            if packages.len() > 1 && options.isolate_features {
+                let package = unification::executable_package(&metadata, executable).unwrap_or_else(|| project_name.to_owned());
+                status!("Compiling `{}` on its own, leaving out {}.", package, packages.iter().filter(|p| **p != package).cloned().collect::<Vec<_>>().join(", "));
+                cargo_args = unification::isolate(&cargo_args, &package);
+            } else if packages.len() > 1 {
+                match unification::compare(&project_dir, &triple, &packages, &cargo_args, no_side_effects) {
+                    Ok(changes) if changes.is_empty() => output::detail("Compiling the packages together doesn't change their dependencies' features."),
+                    Ok(changes) => {
+                        status!("Compiling {} together gives their dependencies extra features:", packages.join(", "));
+                        for change in changes {
+                            status!("    {}: {} gains {}", change.package, change.dependency, change.added.join(", "));
+                        }
+                        status!("Pass `--isolate-features` to compile just the executable's package, with only its own features.");
+                    }
+                    // Only a report, so it doesn't stop the build.
+                    Err(e) => output::warning(&format!("Unable to compare the packages' features: {}", e)),
+                }
+            }
+        }
+
+        let run_options = run_options(options, target.user.as_ref())?;
+        let image_tags = tags::requested(&options.tags, options.tag_git, &project_dir)?;
+        let sbom_format = SbomFormat::select(options.sbom, config.build.sbom.as_deref())?;
+        if options.sbom_label && (!is_docker || sbom_format.is_none()) {
+            return Err(BmError::Environment("`--sbom-label` puts the SBOM in the image, so it needs `--sbom` and docker mode.".to_owned()));
+        }
+        let signer = Signer::new(options.sign, &config.sign)?;
+        let sbom = sbom_format
+            .map(|f| Sbom::generate(f, &Metadata::load(&project_dir, &triple, metadata_lock)?, &project_dir, source_date_epoch))
+            .transpose()?;
+        let dockerfile = if let Some(runtime) = runtime.filter(|_| is_docker && !options.no_image) {
+            let placeholders = template::Placeholders {
+                binary: &if lambda_image { "/var/runtime/bootstrap".to_owned() } else { format!("/{}", executable) },
+                project: &name,
+                artifact: &format!("{}.tar.gz", artifact_name),
+                base: &target.base.image(runtime),
+            };
+            let template = template::load(&project_dir, options.dockerfile_template.as_deref(), &run_options, lambda_image)?;
+            let dockerfile = template::render(&template, &placeholders)?;
+            let sbom_label = sbom.as_ref().filter(|_| options.sbom_label).map(|s| s.label()).unwrap_or_default();
+            Some(dockerfile + &tags::labels(&project_dir, &cargo_toml, source_date_epoch) + &sbom_label)
+        } else {
+            None
+        };
+
+        let cargo_home = home::cargo_home().map_err(|e| BmError::Environment(format!("Unable to get cargo home: {}", e)))?;
+
+        let mut rustflags = Vec::new();
+        if let Some(b) = options.cpu_baseline {
+            rustflags.push(b.rustflags());
+        }
+        if options.hardened {
+            rustflags.extend(hardening::RUSTFLAGS.iter().map(|f| f.to_string()));
+        }
+        if options.reproducible {
+            // Whichever paths the executable is compiled from, see `backend`.
+            let (project_path, cargo_home_path) = if backend.in_container() {
+                ("/workdir".to_owned(), container_cargo_home.to_owned())
+            } else {
+                (path_str(&project_dir)?.to_owned(), path_str(&cargo_home)?.to_owned())
+            };
+            rustflags.extend(reproducible::remap_rustflags(&[(&project_path, "/build"), (&cargo_home_path, "/cargo")]));
+        }
+        // After black_magic's own, so they can override them.
+        if let Some(flags) = options.rustflags.as_deref().or(config.build.rustflags.as_deref()) {
+            rustflags.extend(flags.split_whitespace().map(|f| f.to_owned()));
+        }
+        let jobs = options.jobs.or(config.build.jobs).map(|j| j.to_string());
+        let mut linking_env = Vec::new();
+        if !options.no_auto_static {
+            let linking = static_linking::detect(&project_dir, &triple, &rustflags);
+            for reason in &linking.reasons {
+                status!("Static linking: {}.", reason);
+            }
+            rustflags.extend(linking.rustflags);
+            linking_env = linking.env;
+        }
+        let mut openssl_env = Vec::new();
+        match (openssl::detect(&project_dir), OpensslMode::select(options.openssl, config.build.openssl.as_deref())?) {
+            (Some(usage), Some(mode)) => {
+                if mode == OpensslMode::System && !backend.in_container() {
+                    return Err(BmError::Environment(format!(
+                        "`--openssl system` uses the builder image's OpenSSL, which the `{}` backend doesn't compile with.", backend.name())));
+                }
+                let custom_builder = options.builder_image.is_some() || config.builder.image.is_some();
+                let setup = openssl::setup(mode, &usage, arch, custom_builder, config.build.openssl_dir.as_deref())?;
+                openssl_env = setup.env;
+                cargo_args.extend(setup.cargo_args);
+            }
+            (Some(usage), None) => {
+                if let Some(warning) = openssl::warning(&usage) {
+                    output::warning(&warning);
+                }
+            }
+            (None, Some(_)) => status!("The project doesn't depend on `openssl-sys`, so `--openssl` has nothing to do."),
+            (None, None) => {}
+        }
+
+        let mut vendor_volume = None;
+        if let Some(dir) = options.vendor.as_deref() {
+            let vendor = Vendor::resolve(container_runtime, dir, &project_dir, &container_dir)?;
+            if vendor.volume.is_some() && layered {
+                return Err(BmError::Environment(
+                    "`--vendor` needs the directory inside the project for `--layered` builds (or a remote daemon), which can't mount it.".to_owned()));
+            }
+            cargo_args.extend(vendor.cargo_args(&project_dir));
+            vendor_volume = vendor.volume;
+        } else if options.offline || prefetch {
+            cargo_args.push("--offline".to_owned());
+        }
+        if options.locked || (read_only_source && !options.frozen) {
+            cargo_args.push("--locked".to_owned());
+        } else if options.frozen {
+            cargo_args.push("--frozen".to_owned());
+        }
+
+        let host_env: Vec<(&str, String)> = linking_env.iter()
+            .cloned()
+            .chain(jobs.clone().map(|j| ("CARGO_BUILD_JOBS", j)))
+            .chain(build_env.values.iter().cloned())
+            .chain(build_env.metadata.iter().cloned())
+            .collect();
+
+        let host_packaging = !layered && options.s3.is_none() && !config.hooks.iter().any(|h| h.container && h.at == "post-package");
+        let resumable = (host_packaging || !backend.in_container()) && !options.deps_only;
+        let artifact_file = format!("{}.{}", artifact_name, if is_docker { "tar.gz" } else { "zip" });
+        let artifact = bm_dir.join(&artifact_file);
+        let manifest_file = format!("{}.manifest.json", artifact_name);
+        let out_dir = options.out_dir.as_deref().or(config.build.out_dir.as_deref()).map(|d| project_dir.join(d));
+        // For `--artifact-name` and `--s3`, see `out_dir`.
+        let placeholder = |placeholder: &str| match placeholder {
+            "name" => Ok(name.clone()),
+            "version" => release::read_version(&cargo_toml)
+                .ok_or_else(|| BmError::Environment("`{version}` needs a `version` in `Cargo.toml`.".to_owned())),
+            "git_sha" => tags::short_hash(&project_dir)
+                .ok_or_else(|| BmError::Environment("`{git_sha}` needs the project to be in a git repository with a commit.".to_owned())),
+            "target" => Ok(triple.clone()),
+            _ => Ok(arch.lambda_name().to_owned()),
+        };
+        let delivered_name = match options.artifact_name.as_deref().or(config.build.artifact_name.as_deref()) {
+            Some(template) => {
+                out_dir::validate_name(template.to_owned()).map_err(|e| BmError::Environment(format!("Invalid `artifact_name`: {}", e)))?;
+                Some(out_dir::render(template, placeholder)?)
+            }
+            None => None,
+        };
+        // A prefix gets the zip under the name it's delivered as.
+        let s3_file = delivered_name.as_ref().map(|n| format!("{}.zip", n)).unwrap_or_else(|| artifact_file.clone());
+        let s3 = options.s3.as_deref().map(|u| out_dir::render(u, placeholder).and_then(|u| S3Location::parse(&u, &s3_file))).transpose()?;
+        let delivery = match (out_dir, delivered_name) {
+            (None, None) => None,
+            (dir, delivered_name) => Some((dir.unwrap_or_else(|| bm_dir.clone()), delivered_name.unwrap_or_else(|| artifact_name.clone()))),
+        };
+        let sam_template = bm_dir.join(format!("{}.template.yaml", artifact_name));
+        let terraform_file = bm_dir.join(format!("{}.tf", artifact_name));
+        let export_oci = options.export_oci.as_deref().map(|p| project_dir.join(p));
+
+        let layered_image = format!("{}{}", layered::IMAGE_PREFIX, artifact_name);
+        let project_image = match (is_docker && !options.no_image, lambda_image) {
+            (true, true) => Some(format!("bm_{}_lambda", artifact_name)),
+            (true, false) => Some(format!("bm_{}", artifact_name)),
+            (false, _) => None,
+        };
+        let local_images: Vec<String> = project_image.iter()
+            .flat_map(|i| std::iter::once(i.to_owned()).chain(image_tags.iter().map(move |t| format!("{}:{}", i, t))))
+            .collect();
+
+        Ok(Job {
+            options,
+            config,
+            runtime,
+            is_docker,
+            lambda_image,
+            use_cache,
+            profile,
+            cargo_args,
+            rustflags,
+            toolchain,
+            source_date_epoch,
+            build_env,
+            linking_env,
+            openssl_env,
+            jobs,
+            host_env,
+            backend,
+            builder,
+            builder_build_args,
+            proxy,
+            network,
+            retry,
+            project_name,
+            cache_name,
+            target_kind,
+            executable,
+            helpers,
+            layered,
+            workspace,
+            container_dir,
+            prefetch,
+            read_only_source,
+            shadow_target,
+            owner,
+            cargo_cache,
+            cargo_home,
+            cargo_home_volume,
+            container_cargo_home,
+            cache_server,
+            sccache,
+            secrets,
+            vendor_volume,
+            aws,
+            name,
+            artifact_file,
+            artifact,
+            manifest_file,
+            includes,
+            wrapper,
+            wrapper_source,
+            wrapper_source_file,
+            serverless,
+            serverless_files,
+            zip_executable,
+            lambda_runtime,
+            compression,
+            host_packaging,
+            resumable,
+            timings_file,
+            rustc_version: file(&artifact_name, "rustc"),
+            cargo_version: file(&artifact_name, "cargo_version"),
+            cargo_messages: file(&artifact_name, "cargo.json"),
+            hook_artifact: file(&artifact_name, if is_docker { "tar.gz" } else { "zip" }),
+            artifact_name,
+            s3,
+            delivery,
+            sam_template,
+            terraform_file,
+            export_oci,
+            sbom,
+            signer,
+            dockerfile,
+            run_options,
+            image_tags,
+            project_image,
+            local_images,
+            layered_image,
+            smoke_test,
+            scan,
+            project_dir,
+            cargo_toml,
+            bm_dir,
+            target,
+        })
+    }
+
+    pub fn triple(&self) -> &str {
+        self.target.triple()
+    }
+
+    /// Docker or podman, for what needs one, which builds with a host backend only have for the image or the companions.
+    pub fn runtime(&self) -> Result<Runtime, BmError> {
+        self.runtime.ok_or_else(|| BmError::Environment(format!("The `{}` backend builds without docker or podman.", self.backend.name())))
+    }
+
+    /// Where the build container leaves its `extension` file about the artifact, relative to the project.
+    pub fn file(&self, extension: &str) -> String {
+        file(&self.artifact_name, extension)
+    }
+
+    /// The executable cargo builds, as it appears in the build container's shell commands.
+    pub fn binary(&self) -> String {
+        shell_quote(self.executable)
+    }
+
+    /// What the executable is called in the tarball, which `--lambda-image` extracts into `/var/runtime`.
+    pub fn packaged(&self) -> String {
+        if self.lambda_image { "bootstrap".to_owned() } else { self.binary() }
+    }
+
+    /// The named volume holding cargo's target dir between builds, see `cache_volume`.
+    pub fn cache_volume(&self) -> String {
+        crate::cache_volume(&self.cache_name, self.triple())
+    }
+
+    /// What the backend compiles.
+    pub fn compile(&self) -> backend::Build<'_> {
+        backend::Build {
+            target: self.triple(),
+            profile: self.profile,
+            cargo_args: &self.cargo_args,
+            toolchain: self.toolchain.as_ref(),
+            add_target: self.target.custom.is_some(),
+            rustflags: &self.rustflags,
+            env: &self.host_env,
+            cflags: if self.options.hardened { Some(hardening::CFLAGS) } else { None },
+            binary: self.executable,
+            kind: self.target_kind,
+            helpers: &self.helpers,
+            rustc_version: &self.rustc_version,
+            cargo_version: &self.cargo_version,
+            messages: &self.cargo_messages,
+            source_date_epoch: self.source_date_epoch,
+            diagnostics: &self.config.diagnostics,
+        }
+    }
+
+    /// What the hooks get told about the build.
+    pub fn hook_context(&self) -> hooks::Context<'_> {
+        hooks::Context {
+            name: &self.name,
+            mode: if self.is_docker { "docker" } else { "lambda" },
+            target: self.triple(),
+            profile: self.profile,
+            artifact: &self.hook_artifact,
+            container_dir: &self.container_dir,
+        }
+    }
+
+    /// What the executable is checked to link against, see `elf`. With `--lambda-runtime`, the zip's checked against the
+    /// runtime instead. Custom bases may have a loader, but Lambda's doesn't.
+    pub fn elf_allowed(&self) -> Option<elf::Allowed> {
+        self.lambda_runtime.is_none().then(|| {
+            let base = &self.target.base;
+            let loader = matches!(base, template::Base::Alpine) || (matches!(base, template::Base::Custom(_)) && !self.lambda_image);
+            elf::Allowed::new(self.target.libc == Libc::Gnu, self.is_docker && loader, &self.config.build.allowed_libraries)
+        })
+    }
+
+    /// Everything that changes what ends up in the artifact, besides the source itself, and the gates it passed.
+    pub fn build_options(&self) -> String {
+        format!("{:?}", Packaged {
+            compiled: self.compiled(),
+            artifact_file: &self.artifact_file,
+            s3: self.s3.is_some(),
+            compression: self.compression,
+            includes: &self.includes,
+        })
+    }
+
+    /// What the executable extracted for packaging is compiled from, leaving out what's only packaged with it, see `phases`.
+    pub fn compile_key(&self) -> Option<String> {
+        if !self.resumable {
+            return None;
+        }
+        let compile_options = format!("{:?}", Extracted { compiled: self.compiled(), builder_image_id: self.builder_image_id() });
+        let template = self.options.dockerfile_template.as_deref().unwrap_or(template::TEMPLATE_FILE);
+        let excluded: Vec<&str> = self.includes.iter().map(|i| i.source.as_str()).chain(Some(template)).collect();
+        Some(phases::key(&self.project_dir, &compile_options, &excluded))
+    }
+
+    fn compiled(&self) -> Compiled<'_> {
+        let options = self.options;
+        Compiled {
+            target: self.triple(),
+            backend: self.backend.name(),
+            builder_image: &self.builder.image,
+            toolchain: self.toolchain.as_ref().map(|t| t.channel.as_str()),
+            profile: self.profile,
+            cargo_args: &self.cargo_args,
+            rustflags: &self.rustflags,
+            container_cargo_home: self.container_cargo_home,
+            no_auto_static: options.no_auto_static,
+            build_env: self.build_env.fingerprint(),
+            metadata_env: self.build_env.metadata_fingerprint(),
+            source_date_epoch: self.source_date_epoch,
+            cpu_baseline: options.cpu_baseline,
+            hardened: options.hardened,
+            strip: options.strip,
+            upx: options.upx,
+            test: options.test,
+            clippy: options.clippy,
+            hooks: hooks::fingerprint(&self.config.hooks),
+            packaged: self.packaged(),
+            zip_executable: &self.zip_executable,
+            helpers: &self.helpers,
+            wrapper_source: self.wrapper_source.as_deref(),
+            system_files: self.target.system_files.iter().map(|f| f.path()).collect(),
+            user: self.target.bundled_user().map(|u| u.spec()),
+            companions: self.config.companions.iter().map(|c| (c.origin(), c.dest(), c.checksum(&self.project_dir))).collect(),
+        }
+    }
+
+    /// The features it's compiled with, for the manifest and the build's state.
+    pub fn features(&self) -> manifest::Features {
+        manifest::Features {
+            selected: self.options.features.clone(),
+            default: !self.options.no_default_features,
+            all: self.options.all_features,
+        }
+    }
+
+    /// The builder image's ID, for builds compiling in it.
+    pub fn builder_image_id(&self) -> Option<String> {
+        self.runtime.filter(|_| self.backend.in_container()).and_then(|r| r.image_id(&self.builder.image))
+    }
+}
+
+/// Where the build container leaves its `extension` file about `artifact_name`, relative to the project.
+fn file(artifact_name: &str, extension: &str) -> String {
+    format!("target/black_magic/{}.{}", artifact_name, extension)
+}
+
+/// What the executable's compiled from, and what's done to it in the build container, besides the source, as
+/// `Job::build_options` and `Job::compile_key` both hash it.
+// Only read through `Debug`, which is what's hashed.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Compiled<'a> {
+    target: &'a str,
+    backend: &'static str,
+    builder_image: &'a str,
+    toolchain: Option<&'a str>,
+    profile: &'a str,
+    cargo_args: &'a [String],
+    rustflags: &'a [String],
+    container_cargo_home: &'a str,
+    no_auto_static: bool,
+    build_env: String,
+    metadata_env: String,
+    source_date_epoch: Option<u64>,
+    cpu_baseline: Option<CpuBaseline>,
+    hardened: bool,
+    strip: bool,
+    upx: bool,
+    test: bool,
+    clippy: bool,
+    hooks: String,
+    packaged: String,
+    zip_executable: &'a str,
+    helpers: &'a [String],
+    wrapper_source: Option<&'a str>,
+    system_files: Vec<&'static str>,
+    user: Option<String>,
+    companions: Vec<(String, &'a str, Option<String>)>,
+}
+
+/// What ends up in the artifact, for `Job::build_options`.
+// Only read through `Debug`, which is what's hashed.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Packaged<'a> {
+    compiled: Compiled<'a>,
+    artifact_file: &'a str,
+    s3: bool,
+    compression: Compression,
+    includes: &'a [Include],
+}
+
+/// What the executable extracted for packaging came from, for `Job::compile_key`. The builder image by its ID, as the
+/// extracted executable outlives the build that tagged it.
+// Only read through `Debug`, which is what's hashed.
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Extracted<'a> {
+    compiled: Compiled<'a>,
+    builder_image_id: Option<String>,
+}
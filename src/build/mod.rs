@@ -0,0 +1,98 @@
+//! A build, in the order it happens: the options are checked (`checks`), everything the build needs worked out from them
+//! and `BlackMagic.toml` (`job`), and the build container's command put together (`command`), for backends compiling in
+//! one. Then it's either described (`describe`), for `--no-side-effects`, or run (`run`, or `host` for the backends
+//! compiling on the host) and what it produced published (`publish`).
+
+mod checks;
+mod command;
+mod describe;
+mod host;
+mod job;
+mod publish;
+mod run;
+
+use crate::api::BuildConfig;
+use crate::api::BuildOutput;
+use crate::cas;
+use crate::config::Config;
+use crate::debug_shell;
+use crate::error::BmError;
+use crate::phases;
+use crate::phases::Phase;
+use crate::plan::Plan;
+use crate::progress;
+use crate::project_lock::ProjectLock;
+use crate::scheduler;
+use crate::state;
+use job::Job;
+use std::fs;
+use std::time::Instant;
+use std::time::SystemTime;
+
+/// Where read-only builds see the host's `target/black_magic`, see `--read-only-source`.
+const READ_ONLY_INPUTS: &str = "/bm_inputs";
+/// Where the builder images keep cargo's home.
+const CONTAINER_CARGO_HOME: &str = "/root/.cargo";
+/// Where a cargo home volume is mounted by default, so it doesn't hide the toolchain installed in the image's own cargo home.
+const CONTAINER_CARGO_HOME_VOLUME: &str = "/bm_cargo_home";
+
+/// Builds what `options` asks for, returning it, or nothing for a build that only prints its plan, opens a shell or stops
+/// after the dependencies.
+pub(crate) fn build(options: &BuildConfig, started: Instant) -> Result<Option<BuildOutput>, BmError> {
+    let started_at = SystemTime::now();
+    // Each phase of the build is timed, and a span in its log, see `progress` and `logging`.
+    let mut _phase = progress::phase("checks");
+    let target = checks::options(options)?;
+
+    let project_dir = &options.project_dir;
+    if !project_dir.join("Cargo.toml").exists() {
+        return Err(BmError::Environment(
+            "This doesn't look like a rust project. Are you in the right place? No `Cargo.toml` was found in this directory.".to_owned()));
+    }
+    let bm_dir = project_dir.join("target").join("black_magic");
+    if !options.no_side_effects {
+        fs::create_dir_all(&bm_dir).map_err(|e| BmError::Environment(format!("Unable to create `target\\black_magic` directory: {}", e)))?;
+    }
+    // Several builds at once share the lock of the build running them, see `scheduler`.
+    let _project_lock = if options.no_side_effects || scheduler::job().is_some() {
+        None
+    } else {
+        Some(ProjectLock::acquire(&bm_dir, options.no_wait)?)
+    };
+
+    let config = Config::load(project_dir)?;
+    let job = Job::resolve(options, &config, target)?;
+
+    let mut plan = Plan::new(options.dry_run);
+    _phase = progress::phase("builder image");
+    run::builder(&job, &mut plan)?;
+    _phase = progress::phase("setup");
+    run::setup(&job, &mut plan)?;
+
+    let mut container = if job.backend.in_container() { Some(command::assemble(&job)?) } else { None };
+    if let Some(container) = container.as_mut().filter(|_| options.shell) {
+        return debug_shell::open(&mut container.shell).map(|_| None);
+    }
+    let build_options = job.build_options();
+    let fingerprint = cas::fingerprint(project_dir, &build_options);
+    let resume = if options.deps_only {
+        Phase::Compile
+    } else {
+        let from = options.from_phase.or(Some(Phase::Compile).filter(|_| !job.use_cache));
+        phases::start(from, job.compile_key().as_deref(), project_dir, &bm_dir, &job.artifact_name, &job.artifact, job.project_image.is_some())?
+    };
+    if options.no_side_effects {
+        describe::print(&job, container, plan, resume, &fingerprint)?;
+        return Ok(None);
+    }
+
+    let inputs = state::Inputs::of(project_dir, &build_options, &options.fingerprint(), job.builder_image_id());
+    if let Some(skipped) = run::unchanged(&job, &inputs, started)? {
+        return Ok(Some(skipped));
+    }
+    let produced = match run::artifact(&job, container.as_mut(), resume, &fingerprint, started, &mut _phase)? {
+        Some(produced) => produced,
+        None => return Ok(None),
+    };
+    publish::publish(&job, produced, inputs, started, started_at, _phase).map(Some)
+}
@@ -0,0 +1,400 @@
+//! What's done with the artifact once it's checked: verified, delivered, signed and uploaded, and the image built from it,
+//! tested and pushed, or the function deployed.
+
+use super::job::Job;
+use super::run::Produced;
+use crate::api::Artifact;
+use crate::api::BuildOutput;
+use crate::checksum::Checksum;
+use crate::ci;
+use crate::compose;
+use crate::dockerignore;
+use crate::error::BmError;
+use crate::image_diff;
+use crate::lambda_runtime::LambdaRuntime;
+use crate::limits;
+use crate::out_dir;
+use crate::output;
+use crate::output::status;
+use crate::path_str;
+use crate::progress;
+use crate::registry;
+use crate::runtime::Runtime;
+use crate::sam;
+use crate::scheduler;
+use crate::size_report;
+use crate::state;
+use crate::state::State;
+use crate::stream;
+use crate::tags;
+use crate::template;
+use crate::terraform;
+use crate::verify;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Verifies and publishes the artifact `produced`, builds and publishes its image, and records the build.
+pub fn publish(
+    job: &Job, produced: Produced, inputs: state::Inputs, started: Instant, started_at: SystemTime, mut phase: progress::Phase,
+) -> Result<BuildOutput, BmError> {
+    let options = job.options;
+    let bm_dir = &job.bm_dir;
+    let project_dir = &job.project_dir;
+    let artifact_name = &job.artifact_name;
+    let s3 = &job.s3;
+    let aws = &job.aws;
+    let is_docker = job.is_docker;
+    let image_platform = job.target.image_platform();
+
+    let unzipped_size = if is_docker || !job.serverless.is_lambda() {
+        None
+    } else {
+        Some(limits::check_zip(&job.artifact, options.strip, options.upx, s3.is_some(), options.strict_size)?)
+    };
+    let binary = job.binary();
+    if is_docker {
+        verify::tar(&job.artifact, &job.packaged(), matches!(job.target.base, template::Base::Scratch))?;
+    } else {
+        let mut executables = if job.wrapper.is_some() { vec!["bootstrap", binary.as_str()] } else { vec![job.zip_executable.as_str()] };
+        executables.extend(job.helpers.iter().map(|h| h.as_str()));
+        verify::zip(&job.artifact, &executables)?;
+    }
+
+    let contents = fs::read(&job.artifact).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?;
+    let checksum = Checksum::of(&contents);
+    checksum.write(&job.artifact)?;
+    status!("SHA-256: {}", checksum.hex);
+    if !is_docker && job.serverless.is_lambda() {
+        status!("CodeSha256: {}", checksum.base64);
+    }
+    let mut sbom_file = job.sbom.as_ref().map(|s| bm_dir.join(s.file_name(artifact_name)));
+    if let (Some(sbom), Some(file)) = (&job.sbom, &sbom_file) {
+        sbom.write(file)?;
+    }
+    let artifact = match &job.delivery {
+        Some((dir, delivered_name)) => {
+            let delivered = out_dir::deliver(&job.artifact, &bm_dir.join(&job.manifest_file), &checksum, dir, delivered_name)?;
+            status!("Artifact: {}", delivered.display());
+            if let Some(sbom) = &job.sbom {
+                let file = dir.join(sbom.file_name(delivered_name));
+                sbom.write(&file)?;
+                sbom_file = Some(file);
+            }
+            delivered
+        }
+        None => job.artifact.clone(),
+    };
+    if let Some(file) = &sbom_file {
+        status!("SBOM: {}", file.display());
+    }
+    if options.size_report {
+        let previous = State::load(bm_dir).filter(|s| s.artifact == artifact).map(|s| s.size);
+        size_report::report(bm_dir, artifact_name, &project_dir.join(job.file("symbols")), contents.len() as u64, previous)?;
+    }
+    let signature = match &job.signer {
+        Some(signer) => {
+            status!("Signing the artifact...");
+            let signature = signer.sign_file(&artifact)?;
+            status!("Signature: {}", signature.display());
+            Some(signature)
+        }
+        None => None,
+    };
+    output::marker("ARTIFACT", &[
+        ("path", path_str(&artifact)?), ("digest", &format!("sha256:{}", checksum.hex)), ("size", &contents.len().to_string())]);
+
+    let arch = job.target.arch;
+    if options.emit_sam {
+        let runtime_name = job.lambda_runtime.unwrap_or(LambdaRuntime::Al2023).name();
+        let lambda = &job.config.lambda;
+        sam::write(&job.sam_template, &sam::template(&lambda.function, &lambda.sam, &job.name, &job.artifact_file, runtime_name, arch.lambda_name()))?;
+        status!("SAM template: {}", job.sam_template.display());
+    }
+
+    let mut s3_version = None;
+    if let Some(s3) = s3 {
+        s3_version = stream::publish(&artifact, s3, aws, produced.streamed)?;
+        match &s3_version {
+            Some(version) => status!("Uploaded: {}, version {}", s3.url(), version),
+            None => status!("Uploaded: {}", s3.url()),
+        }
+        let object_url = aws.resolved_region().map(|r| s3.object_url(&r)).unwrap_or_default();
+        if !object_url.is_empty() {
+            status!("Object URL: {}", object_url);
+        }
+        output::marker("UPLOADED", &[("url", &s3.url()), ("object_url", &object_url), ("version_id", s3_version.as_deref().unwrap_or(""))]);
+    }
+
+    let mut push_to = Vec::new();
+    if let (Some(project_image), Some(dockerfile)) = (&job.project_image, &job.dockerfile) {
+        phase = progress::phase("image");
+        push_to = image(job, project_image, dockerfile, image_platform, &mut phase)?;
+    } else if let Some(function_name) = options.deploy.as_deref() {
+        if let Some(r) = job.lambda_runtime {
+            let configured = aws.lambda_runtime(function_name).map_err(|e| BmError::Publish(format!("Deploy failed: {}", e)))?;
+            if configured != r.name() {
+                return Err(BmError::Publish(format!(
+                    "{} is configured with the `{}` runtime, but the zip was built for {}.", function_name, configured, r.name())));
+            }
+        }
+        phase = progress::phase("deploy");
+        status!("Deploying to {}...", function_name);
+        let version = aws.deploy_lambda(function_name, &artifact, s3.as_ref(), s3_version.as_deref(), &checksum.base64)
+            .map_err(|e| BmError::Publish(format!("Deploy failed: {}", e)))?;
+        status!("Published version {} of {}.", version, function_name);
+        output::marker("DEPLOYED", &[("function", function_name), ("version", &version)]);
+    }
+
+    if options.emit_terraform {
+        let fragment = match &job.project_image {
+            Some(project_image) => terraform::image(
+                &job.name, push_to.first().unwrap_or(project_image), &checksum.hex, options.ecr.as_deref().map(registry::split_tag)),
+            None => terraform::lambda(&terraform::Function {
+                name: options.deploy.as_deref().unwrap_or(&job.name),
+                zip: path_str(&artifact)?,
+                s3: s3.as_ref().map(|s| (s.bucket.as_str(), s.key.as_str())),
+                code_sha256: &checksum.base64,
+                runtime: job.lambda_runtime.unwrap_or(LambdaRuntime::Al2023).name(),
+                architecture: arch.lambda_name(),
+            }, &job.config.lambda.function),
+        };
+        terraform::write(&job.terraform_file, &fragment)?;
+        status!("Terraform: {}", job.terraform_file.display());
+    }
+
+    let mut outputs = vec![("artifact", path_str(&artifact)?), ("sha256", checksum.hex.as_str())];
+    outputs.extend(job.project_image.as_deref().map(|i| ("image", i)));
+    ci::outputs(bm_dir, &outputs);
+    let rfc3339 = |t: SystemTime| tags::rfc3339(t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    State {
+        artifact: artifact.clone(),
+        sha256: checksum.hex.clone(),
+        size: contents.len() as u64,
+        image: job.project_image.as_ref().and_then(|i| Some((i.clone(), job.runtime?.image_id(i)?))),
+        provenance: state::Provenance {
+            target: job.triple().to_owned(),
+            profile: job.profile.to_owned(),
+            features: job.features(),
+            builder_image: job.backend.in_container().then(|| job.builder.image.clone()),
+            builder_image_digest: inputs.builder_image.clone(),
+            git_sha: tags::full_hash(project_dir),
+            git_dirty: tags::full_hash(project_dir).map(|_| tags::dirty(project_dir)),
+            rustc: state::recorded_version(&project_dir.join(&job.rustc_version)),
+            cargo: state::recorded_version(&project_dir.join(&job.cargo_version)),
+            started_at: rfc3339(started_at),
+            finished_at: rfc3339(SystemTime::now()),
+        },
+        inputs,
+    }.save(bm_dir)?;
+
+    if let Some(report) = &produced.resources {
+        report.print();
+    }
+    drop(phase);
+    progress::print_report();
+    progress::save(&job.timings_file);
+    status!("...Done!");
+
+    let duration_seconds = started.elapsed().as_secs_f64();
+    let record = json!({
+        "artifact": artifact,
+        "image": job.project_image,
+        "tags": job.image_tags,
+        "size": contents.len(),
+        "unzipped_size": unzipped_size,
+        "sha256": checksum.hex,
+        "code_sha256": if is_docker { None } else { Some(&checksum.base64) },
+        "s3": s3.as_ref().map(|s| s.url()),
+        "s3_object": s3.as_ref().map(|s| json!({ "bucket": s.bucket, "key": s.key, "version_id": s3_version })),
+        "target": job.triple(),
+        "profile": job.profile,
+        "lambda_runtime": job.lambda_runtime.map(|r| r.name()),
+        "sam_template": if options.emit_sam { Some(&job.sam_template) } else { None },
+        "sbom": sbom_file,
+        "signature": signature,
+        "oci_export": job.export_oci,
+        "terraform": if options.emit_terraform { Some(&job.terraform_file) } else { None },
+        "compose": if options.emit_compose { Some(project_dir.join(compose::FILE)) } else { None },
+        "resources": produced.resources,
+        "duration_seconds": duration_seconds,
+        "phases": progress::report(),
+        "builder_image": job.builder.image,
+        "builder_tag": job.builder.tag,
+    });
+    Ok(BuildOutput {
+        artifact: Artifact { path: artifact, sha256: checksum.hex, size: contents.len() as u64 },
+        image: job.project_image.clone(),
+        target: job.triple().to_owned(),
+        duration_seconds,
+        record,
+    })
+}
+
+/// Builds the project image, tests, exports and pushes it, returning where it was pushed to.
+fn image(job: &Job, project_image: &str, dockerfile: &str, image_platform: Option<&str>, phase: &mut progress::Phase)
+    -> Result<Vec<String>, BmError>
+{
+    let options = job.options;
+    let runtime = job.runtime()?;
+    let bm_dir = &job.bm_dir;
+    let aws = &job.aws;
+    status!("Building project image...");
+    build_project_image(runtime, bm_dir, image_platform, &scheduler::job_file("Dockerfile"), dockerfile, &job.local_images)?;
+    status!("Project image: {}", job.local_images.join(", "));
+    for image in &job.local_images {
+        output::marker("IMAGE", &[("name", image)]);
+    }
+
+    if let Some(smoke_test) = &job.smoke_test {
+        status!("Running smoke test...");
+        smoke_test.run(runtime, project_image, image_platform)?;
+        status!("Smoke test passed.");
+    }
+
+    if let Some(test) = job.config.integration_test.as_ref().filter(|_| options.integration_test) {
+        status!("Running integration test...");
+        test.run(runtime, bm_dir, job.executable, &job.name, project_image)?;
+        status!("Integration test passed.");
+    }
+
+    if let Some(previous) = options.diff_against.as_deref() {
+        image_diff::check(runtime, project_image, previous, &job.config.image_diff)?;
+    }
+
+    if let Some(scan) = &job.scan {
+        status!("Scanning image...");
+        scan.run(runtime, bm_dir, project_image)?;
+    }
+
+    if let Some(path) = &job.export_oci {
+        status!("Exporting image...");
+        registry::export(runtime, project_image, path)?;
+        let contents = fs::read(path).map_err(|e| BmError::Packaging(format!("Unable to read `{}`: {}", path.display(), e)))?;
+        Checksum::of(&contents).write(path)?;
+        status!("Exported: {}", path.display());
+    }
+
+    if let Some(cluster) = &job.target.load_into {
+        status!("Loading image into cluster...");
+        cluster.load(project_image)?;
+    }
+
+    if options.emit_compose {
+        compose::write(&job.project_dir, &job.config.compose, &job.name, project_image, &job.run_options)?;
+        status!("Compose service: {} in {}", compose::service_name(&job.config.compose, &job.name), compose::FILE);
+    }
+
+    let mut push_to = options.push.clone();
+    let mut ecr_repository = None;
+    if let Some(ecr) = options.ecr.as_deref() {
+        status!("Logging into ECR...");
+        let (repository, tag) = registry::split_tag(ecr);
+        let ecr_registry = aws.ecr_registry()
+            .and_then(|r| aws.ecr_login(runtime, &r).map(|_| r))
+            .and_then(|r| aws.ensure_ecr_repository(repository).map(|_| r))
+            .map_err(BmError::Publish)?;
+        // Without a tag of its own, the repository is pushed under `--tag`'s.
+        push_to.push(if registry::split_tag(ecr).0 == ecr { format!("{}/{}", ecr_registry, repository) } else { format!("{}/{}:{}", ecr_registry, repository, tag) });
+        ecr_repository = Some((format!("{}/{}", ecr_registry, repository), repository));
+    }
+    let push_to = tags::remotes(&push_to, &job.image_tags);
+
+    // Each push uploads its layers on its own, so they all go at once.
+    if !push_to.is_empty() {
+        status!("Pushing {}...", push_to.join(", "));
+    }
+    let pushed: Vec<Result<(), String>> = thread::scope(|s| {
+        let pushes: Vec<_> = push_to.iter().map(|remote| s.spawn(move || registry::push(runtime, project_image, remote))).collect();
+        pushes.into_iter().map(|p| p.join().unwrap_or_else(|_| Err("The push panicked.".to_owned()))).collect()
+    });
+    for (remote, result) in push_to.iter().zip(pushed) {
+        result.map_err(BmError::Publish)?;
+        status!("Pushed: {}", remote);
+        output::marker("PUSHED", &[("remote", remote)]);
+    }
+    if let Some(signer) = &job.signer {
+        if push_to.is_empty() {
+            status!("{} isn't pushed, and cosign only signs images in registries, so only its tarball is signed.", project_image);
+        }
+        for remote in &push_to {
+            signer.sign_image(remote)?;
+            status!("Signed: {}", remote);
+        }
+    }
+
+    // By digest, so the function runs exactly this image, whatever its tags point at later.
+    if let (Some(function_name), Some((ecr_image, repository))) = (options.deploy.as_deref(), &ecr_repository) {
+        *phase = progress::phase("deploy");
+        let tag = push_to.iter().map(|r| registry::split_tag(r)).find(|(image, _)| image == ecr_image).map(|(_, t)| t).unwrap_or("latest");
+        let image_uri = aws.ecr_image_digest(repository, tag)
+            .map(|digest| format!("{}@{}", ecr_image, digest))
+            .map_err(|e| BmError::Publish(format!("Unable to find the pushed image's digest: {}", e)))?;
+        status!("Deploying {} to {}...", image_uri, function_name);
+        let version = aws.deploy_lambda_image(function_name, &image_uri).map_err(|e| BmError::Publish(format!("Deploy failed: {}", e)))?;
+        status!("Published version {} of {}.", version, function_name);
+        output::marker("DEPLOYED", &[("function", function_name), ("version", &version)]);
+    }
+
+    if options.debug_image {
+        status!("Building debug image...");
+
+        // Same executable, but on top of busybox so there's a shell to exec into.
+        let debug_image = format!("{}-debug", project_image);
+        let dockerfile = format!(r#"
+FROM {}
+ADD {}.tar.gz /
+"#,
+            runtime.qualify("busybox"), job.artifact_name);
+
+        // The production image is already built (and published), so this doesn't fail the build.
+        match build_project_image(runtime, bm_dir, image_platform, &scheduler::job_file("Dockerfile.debug"), &dockerfile, std::slice::from_ref(&debug_image)) {
+            Ok(()) => status!("Debug image: {}", debug_image),
+            Err(e) => output::warning(&e.to_string()),
+        }
+    }
+    Ok(push_to)
+}
+
+/// Writes `dockerfile` into `bm_dir` and builds it, tagged as each of `images`.
+fn build_project_image(runtime: Runtime, bm_dir: &Path, platform: Option<&str>, dockerfile_name: &str, dockerfile: &str, images: &[String]) -> Result<(), BmError> {
+    fs::write(bm_dir.join(dockerfile_name), dockerfile).map_err(|e| BmError::Packaging(format!("Unable to create project dockerfile: {}", e)))?;
+    dockerignore::write_image_context(bm_dir)?;
+
+    let project_image = project_image_cmd(runtime, platform, dockerfile_name, images)
+        .current_dir(bm_dir)
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to build project image: {}", e)))?;
+    if !project_image.status.success() {
+        return Err(BmError::Docker(format!(
+            "Project image failed: {}\nstdout: {}\nstderr: {}",
+            images[0], String::from_utf8_lossy(&project_image.stdout), String::from_utf8_lossy(&project_image.stderr))));
+    }
+    Ok(())
+}
+
+/// Builds the project image, from `target/black_magic`.
+pub fn project_image_cmd(runtime: Runtime, platform: Option<&str>, dockerfile_name: &str, images: &[String]) -> Command {
+    /*
+    Build project image:
+        - no cache
+        - for the selected architecture, or the target triple's
+        - tag as each of `images`
+        - using the given dockerfile in the dir it's run from
+    */
+    let mut project_image = runtime.build();
+    project_image.arg("--no-cache");
+    if let Some(p) = platform {
+        project_image.arg("--platform").arg(p);
+    }
+    for image in images {
+        project_image.arg("-t").arg(image);
+    }
+    project_image.arg("-f").arg(dockerfile_name).arg(".");
+    project_image
+}
@@ -0,0 +1,603 @@
+//! Running the build: the builder image, then the build container (or the host backend, see `host`, or the artifact either
+//! left before), and checking what it compiled before anything's done with it.
+
+use super::command::Container;
+use super::host;
+use super::job::Job;
+use crate::api::Artifact;
+use crate::api::BuildOutput;
+use crate::archive;
+use crate::archivers;
+use crate::backend;
+use crate::baseline;
+use crate::baseline::CpuBaseline;
+use crate::bounds;
+use crate::builder;
+use crate::cache_server;
+use crate::cargo_cache::CargoCache;
+use crate::cas;
+use crate::ci;
+use crate::companion;
+use crate::config::Config;
+use crate::debug_shell;
+use crate::diagnostics;
+use crate::elf;
+use crate::error::BmError;
+use crate::gates;
+use crate::hardening::HardeningReport;
+use crate::history;
+use crate::hooks;
+use crate::interrupt;
+use crate::last_build;
+use crate::layered;
+use crate::manifest;
+use crate::manifest::Environment;
+use crate::manifest::Manifest;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::packaging;
+use crate::path_str;
+use crate::phases;
+use crate::phases::Phase;
+use crate::plan::Plan;
+use crate::progress;
+use crate::release;
+use crate::resources;
+use crate::state;
+use crate::state::State;
+use crate::static_linking;
+use crate::stream;
+use crate::system_files;
+use crate::toolchain;
+use crate::warm;
+use crate::wrapper;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::process::Output;
+use std::time::Instant;
+
+/// How the artifact came about.
+#[derive(Default)]
+pub struct Produced {
+    /// Whether it went to S3 as it was packaged, see `stream`.
+    pub streamed: bool,
+    /// What the build container used, for `--resource-report`.
+    pub resources: Option<resources::Report>,
+}
+
+/// Builds the builder image, unless it's there and current, or adds doing so to `plan`.
+pub fn builder(job: &Job, plan: &mut Plan) -> Result<(), BmError> {
+    // The host backends only use it to fetch companions.
+    if !job.backend.in_container() && job.config.companions.is_empty() {
+        return Ok(());
+    }
+    let options = job.options;
+    let (runtime, builder, arch) = (job.runtime()?, &job.builder, job.target.arch);
+    let max_age_days = job.config.builder.max_age_days.unwrap_or(builder::DEFAULT_MAX_AGE_DAYS);
+    let stale = builder.age_days(runtime).filter(|age| max_age_days > 0 && *age > max_age_days && !options.update_builder);
+    let update_builder = options.update_builder || (stale.is_some() && options.auto_update_builder);
+    match stale {
+        Some(age) if update_builder => status!("The {} image was built {} days ago, rebuilding it...", builder.image, age),
+        Some(age) => output::warning(&format!(
+            "The {} image was built {} days ago, so its toolchain and packages may be out of date. Rebuild it with `--update-builder`, or pass `--auto-update-builder` to have builds do it.",
+            builder.image, age)),
+        None => {}
+    }
+    if !options.no_side_effects {
+        builder.ensure(runtime, arch, &job.bm_dir, update_builder, &job.builder_build_args, &job.retry)?;
+    } else if update_builder || !builder.is_current(runtime, arch) {
+        if let Some(cached) = builder.cache_image().filter(|_| !update_builder) {
+            plan.step(format!("Pull {}, using it if it's current, or else building from it", cached));
+        }
+        plan.step(format!("Build the {} builder image", builder.image));
+        let context_dir = format!("target/black_magic/{}", builder::Builder::context_dir(arch));
+        for (file, contents) in builder.context_files(runtime, arch) {
+            plan.file(format!("{}/{}", context_dir, file), contents);
+        }
+        plan.command(Some(&context_dir), &builder.build_cmd(runtime, arch, update_builder, &job.builder_build_args));
+        if let Some(cached) = builder.cache_image().filter(|_| builder.pushes_cache()) {
+            plan.step(format!("Push it to {}", cached));
+            plan.output(cached);
+        }
+    }
+    Ok(())
+}
+
+/// Writes what the build needs into the project, and fetches the companions, or adds doing so to `plan`.
+pub fn setup(job: &Job, plan: &mut Plan) -> Result<(), BmError> {
+    let options = job.options;
+    let no_side_effects = options.no_side_effects;
+    if let Some(path) = options.env_file.as_deref().filter(|_| !no_side_effects) {
+        job.build_env.write_env_file(&job.project_dir.join(path))?;
+    }
+    let companions = &job.config.companions;
+    if !no_side_effects && !companions.is_empty() {
+        companion::fetch_all(companions, job.runtime()?, &job.builder.image, job.target.arch.platform(), &job.project_dir, &job.proxy, &job.network)?;
+    } else {
+        for c in companions {
+            plan.step(format!("Fetch the {} companion from {}", c.name, c.origin()));
+        }
+    }
+    if !job.serverless_files.is_empty() {
+        let staging_dir = packaging::staging_dir(&job.artifact_name);
+        if no_side_effects {
+            plan.step(format!("Write the files `--serverless {}` needs into {}", job.serverless.name(), staging_dir));
+            for (path, contents) in &job.serverless_files {
+                plan.file(format!("{}/{}", staging_dir, path), contents.clone());
+            }
+        } else {
+            packaging::write(&job.project_dir, &staging_dir, &job.serverless_files)?;
+        }
+    }
+    // What's prefetched has to stay for the compile, so the host's registry is mounted even if it's new.
+    if job.prefetch && job.cargo_cache == CargoCache::Share && !no_side_effects {
+        for dir in ["registry", "git"] {
+            fs::create_dir_all(job.cargo_home.join(dir))
+                .map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", job.cargo_home.join(dir).display(), e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// With `--skip-unchanged`, what the last build produced, if nothing's changed since.
+pub fn unchanged(job: &Job, inputs: &state::Inputs, started: Instant) -> Result<Option<BuildOutput>, BmError> {
+    if !job.options.skip_unchanged {
+        return Ok(None);
+    }
+    let last = State::load(&job.bm_dir);
+    match last.as_ref().map(|l| (l, l.changed(inputs, |i| job.runtime?.image_id(i)))) {
+        Some((last, None)) => {
+            status!("Nothing has changed since the last build, so it's skipped.");
+            status!("Artifact: {}", last.artifact.display());
+            if let Some((image, _)) = &last.image {
+                status!("Image: {}", image);
+            }
+            output::marker("ARTIFACT", &[
+                ("path", path_str(&last.artifact)?), ("digest", &format!("sha256:{}", last.sha256)), ("size", &last.size.to_string())]);
+            output::marker("SKIPPED", &[]);
+            history::cache("unchanged", false);
+            let mut outputs = vec![("artifact", path_str(&last.artifact)?), ("sha256", last.sha256.as_str())];
+            outputs.extend(last.image.as_ref().map(|(i, _)| ("image", i.as_str())));
+            ci::outputs(&job.bm_dir, &outputs);
+            let duration_seconds = started.elapsed().as_secs_f64();
+            Ok(Some(BuildOutput {
+                artifact: Artifact { path: last.artifact.clone(), sha256: last.sha256.clone(), size: last.size },
+                image: last.image.as_ref().map(|(image, _)| image.clone()),
+                target: last.provenance.target.clone(),
+                duration_seconds,
+                record: json!({
+                    "artifact": last.artifact,
+                    "image": last.image.as_ref().map(|(image, _)| image),
+                    "size": last.size,
+                    "sha256": last.sha256,
+                    "skipped": true,
+                    "duration_seconds": duration_seconds,
+                }),
+            }))
+        }
+        Some((_, Some(why))) => {
+            status!("Building, as {} since the last build.", why);
+            Ok(None)
+        }
+        None => {
+            status!("Building, as there's no previous build to compare with.");
+            Ok(None)
+        }
+    }
+}
+
+/// Produces the artifact: from the artifact store, or from the phase the build `resume`s from, see `phases`, compiling in
+/// the build `container` if the backend has one. Nothing, for `--deps-only`.
+pub fn artifact(job: &Job, container: Option<&mut Container>, resume: Phase, fingerprint: &str, started: Instant, phase: &mut progress::Phase)
+    -> Result<Option<Produced>, BmError>
+{
+    let options = job.options;
+    let stored = [job.artifact_file.as_str(), job.manifest_file.as_str()];
+    if job.use_cache && !options.deps_only && !options.no_artifact_cache && cas::restore(fingerprint, &job.bm_dir, &stored) {
+        *phase = progress::phase("package");
+        status!("Source unchanged since a previous build, reusing its artifact.");
+        output::marker("REUSED", &[]);
+        history::cache("artifact", false);
+        return Ok(Some(Produced::default()));
+    }
+    match resume {
+        Phase::Image => reuse(job, phase).map(Some),
+        Phase::Package => {
+            package(job, phase)?;
+            finish(job, fingerprint, None)?;
+            Ok(Some(Produced::default()))
+        }
+        Phase::Compile => {
+            let produced = match container {
+                Some(container) => compile(job, container, started, phase)?,
+                None => Some(host::compile(job, phase)?),
+            };
+            match produced {
+                Some(produced) => {
+                    finish(job, fingerprint, job.compile_key())?;
+                    Ok(Some(produced))
+                }
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+/// Reuses the artifact the last build packaged, to only build the image again.
+fn reuse(job: &Job, phase: &mut progress::Phase) -> Result<Produced, BmError> {
+    *phase = progress::phase("package");
+    status!("Reusing {}, only building the image again.", job.artifact_file);
+    output::marker("REUSED", &[]);
+    history::cache("resumed", false);
+    Ok(Produced::default())
+}
+
+/// Packages the executable the last compile extracted, on this machine.
+fn package(job: &Job, phase: &mut progress::Phase) -> Result<(), BmError> {
+    *phase = progress::phase("package");
+    status!("Packaging the executable the last compile extracted...");
+    history::cache("resumed", false);
+    phases::package(&job.project_dir, &job.artifact_name, &job.includes, &job.artifact, job.is_docker, job.source_date_epoch, job.compression)
+}
+
+/// Checks the packaged executable and writes its manifest, keeping the artifact in the artifact store, and the executable
+/// extracted by the compile keyed by `compile_key`, if it was, for later phases to rerun from.
+fn finish(job: &Job, fingerprint: &str, compile_key: Option<String>) -> Result<(), BmError> {
+    let options = job.options;
+    let (hardening, binary_size) = check_executable(job)?;
+    hooks::run(&job.config.hooks, "post-package", &job.project_dir, &job.hook_context())?;
+
+    Manifest {
+        project: job.project_name.to_owned(),
+        version: release::read_version(&job.cargo_toml),
+        artifact: job.artifact_file.clone(),
+        target: job.triple().to_owned(),
+        profile: job.profile.to_owned(),
+        hardening,
+        environment: Environment::capture(
+            job.runtime, job.backend.name(), Some(job.builder.image.as_str()).filter(|_| job.backend.in_container()),
+            &job.project_dir.join(&job.rustc_version)),
+        source_fingerprint: cas::fingerprint(&job.project_dir, ""),
+        binary_size,
+        source_date_epoch: job.source_date_epoch,
+        stripped: options.strip,
+        upx: options.upx,
+        lambda_runtime: job.lambda_runtime.map(|r| r.name().to_owned()),
+        artifact_size: fs::metadata(&job.artifact).map(|m| m.len()).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?,
+        features: job.features(),
+        cargo_args: job.cargo_args.clone(),
+        dependencies: manifest::locked_dependencies(&job.project_dir),
+    }.write(&job.bm_dir.join(&job.manifest_file))?;
+
+    cas::store(fingerprint, &job.bm_dir, &[&job.artifact_file, &job.manifest_file]);
+    if let Some(key) = compile_key {
+        phases::save(&job.bm_dir, &job.artifact_name, &key)?;
+    }
+    Ok(())
+}
+
+/// Compiles, inspects and packages the executable in the build container (or `docker build`, for `--layered`), checking it
+/// links as it needs to before it's packaged here. Nothing, for `--deps-only`, which only compiles the dependencies.
+fn compile(job: &Job, container: &mut Container, started: Instant, phase: &mut progress::Phase) -> Result<Option<Produced>, BmError> {
+    *phase = progress::phase("compile");
+    let options = job.options;
+    let runtime = job.runtime()?;
+    let project_dir = &job.project_dir;
+    let bm_dir = &job.bm_dir;
+    let compile_started = Instant::now();
+    history::cache("compiled", !(job.use_cache && runtime.volume_exists(&job.cache_volume())));
+    output::marker("BEGIN_COMPILE", &[("target", job.triple())]);
+    if options.deps_only {
+        status!("Compiling dependencies...");
+        layered::write_recipe(project_dir, &bm_dir.join(warm::RECIPE_DIR))?;
+    } else if job.is_docker {
+        status!("Compiling project...");
+    } else {
+        status!("Compiling project to lambda zip...");
+    }
+
+    if !options.deps_only {
+        if let Some(source) = &job.wrapper_source {
+            wrapper::write(&project_dir.join(&job.wrapper_source_file), source)?;
+        }
+        hooks::run(&job.config.hooks, "pre-build", project_dir, &job.hook_context())?;
+        phases::forget(bm_dir, &job.artifact_name);
+    }
+    if let Some(prefetch_cmd) = &mut container.prefetch {
+        status!("Prefetching dependencies...");
+        output::detail(&format!("Running {:?}", prefetch_cmd));
+        let fetched = job.retry.run("The prefetch", || prefetch_cmd.output())
+            .map_err(|e| BmError::Docker(format!("Unable to run the prefetch: {}", e)))?;
+        if !fetched.status.success() {
+            return Err(BmError::Compile(format!(
+                "The prefetch failed, so nothing was compiled. Run the following command manually to see the problem:\n\n{:?}\n\nstderr: {}",
+                prefetch_cmd, String::from_utf8_lossy(&fetched.stderr))));
+        }
+    }
+    container.cmd.arg(&job.builder.image)
+        .arg("/bin/bash")
+        .arg("-c")
+        .arg(format!("{}{}", mounts::check_cmd(project_dir)?, container.script));
+    if job.host_packaging {
+        let _ = fs::remove_dir_all(project_dir.join(&container.package_dir));
+    }
+    let mut produced = Produced::default();
+    let (cmd, built) = if job.layered {
+        container.layers(job).build(runtime, project_dir, bm_dir, &job.layered_image, &job.retry)?
+    } else {
+        let name = &container.name;
+        interrupt::cleanup_container(runtime, name);
+        interrupt::cleanup_file(job.artifact.clone());
+        let sampler = options.resource_report.then(|| resources::Sampler::start(runtime, name.clone()));
+        let watchdog = options.timeout.map(|t| bounds::Watchdog::start(runtime, name.clone(), std::time::Duration::from_secs(t)));
+        let cmd = &mut container.cmd;
+        output::detail(&format!("Running {:?}", cmd));
+        let built = if let Some(s3) = &job.s3 {
+            produced.streamed = true;
+            stream::run(cmd, &job.artifact, s3, &job.aws)
+        } else {
+            let mut attempts = 0;
+            job.retry.run("The build", || {
+                // A container kept by `--keep-on-failure`, or for its outputs, has the name the next attempt's needs.
+                if (options.keep_on_failure || job.read_only_source) && attempts > 0 {
+                    let _ = runtime.command().args(["rm", "-v"]).arg(name).output();
+                }
+                attempts += 1;
+                cmd.output()
+            }).map_err(|e| BmError::Docker(format!("Unable to run build command: {}", e)))
+        };
+        produced.resources = sampler.map(|s| s.finish(project_dir, &job.file("cache_size")));
+        interrupt::finished();
+        if watchdog.map(|w| w.finish()).unwrap_or(false) {
+            let _ = fs::remove_file(&job.artifact);
+            return Err(BmError::Compile(format!(
+                "The build was still running after `--timeout {}` seconds, so its container was removed.", options.timeout.unwrap_or_default())));
+        }
+        let built = built?;
+        (std::mem::replace(cmd, Command::new("")), built)
+    };
+    let name = &container.name;
+    let kept = options.keep_on_failure && !built.status.success();
+    if job.read_only_source {
+        // A failed build's outputs (e.g. `cargo.json`) are for explaining it, if they're there.
+        let copied = mounts::copy_out(runtime, name, &job.container_dir, bm_dir);
+        if built.status.success() {
+            copied?;
+        }
+    }
+    if (options.keep_on_failure || job.read_only_source) && !kept {
+        // `-v` removes a read-only build's `target` volume too.
+        let _ = runtime.command().args(["rm", "-v"]).arg(name).output();
+    }
+    last_build::record(bm_dir, &cmd, &built, compile_started.elapsed().as_secs_f64(), if kept { Some(name.as_str()) } else { None })?;
+    if kept {
+        status!(
+            "Kept the build container {}: inspect it with `{} diff {}` or `{} cp {}:<path> .`, and remove it with `{} rm {}`.",
+            name, runtime.name(), name, runtime.name(), name, runtime.name(), name);
+    }
+    if !built.status.success() {
+        ci::annotate(&String::from_utf8_lossy(&built.stderr), project_dir);
+        let error = build_failed(&cmd, &built, &job.config.diagnostics);
+        if options.debug_shell && matches!(error, BmError::Compile(_) | BmError::Test(_)) {
+            eprintln!("{}", error);
+            debug_shell::open(&mut container.shell)?;
+            return Err(BmError::from_exit_code(Some(error.exit_code()), "The build failed, as explained before the debug shell.".to_owned()));
+        }
+        return Err(error);
+    }
+    output::marker("END_COMPILE", &[("seconds", &format!("{:.1}", compile_started.elapsed().as_secs_f64()))]);
+    if options.deps_only {
+        status!("Compiled the dependencies into the cache volume {}.", job.cache_volume());
+        if output::is_json() {
+            output::emit("done", json!({
+                "deps_only": true,
+                "cache_volume": job.cache_volume(),
+                "duration_seconds": started.elapsed().as_secs_f64(),
+            }));
+        }
+        return Ok(None);
+    }
+    if String::from_utf8_lossy(&built.stderr).contains(cache_server::FETCHED) {
+        status!("Reused dependencies compiled by the cache server.");
+    }
+    // Before anything's packaged here or goes anywhere, see `elf`.
+    match fs::read_to_string(project_dir.join(job.file("elf"))) {
+        Ok(dump) => check_linking(job, &dump, &container.package_dir)?,
+        Err(_) => output::warning("The builder image has no `readelf`, so the executable's architecture and linking weren't checked."),
+    }
+    *phase = progress::phase("package");
+    if job.host_packaging {
+        archivers::package(project_dir, &container.package_dir, &job.artifact, job.is_docker, job.source_date_epoch, job.compression)?;
+    }
+    Ok(Some(produced))
+}
+
+/// Checks the executables link as they need to, from their headers `dump`ed by `elf`, removing what they'd be packaged on
+/// this machine from, `package_dir`, if they don't.
+pub fn check_linking(job: &Job, dump: &str, package_dir: &str) -> Result<(), BmError> {
+    let elf_allowed = job.elf_allowed();
+    let problems = elf::check(dump, job.triple(), elf_allowed.as_ref());
+    if !problems.is_empty() {
+        let _ = fs::remove_dir_all(job.project_dir.join(package_dir));
+        let message = format!(
+            "The executable won't run where it's going, so there's no artifact:\n    - {}\n\
+            Build for musl (without `--libc gnu`) to link statically, or add the libraries to `allowed_libraries` \
+            in `[build]` of `BlackMagic.toml` if the base image has them.",
+            problems.join("\n    - "));
+        // Packaged on this machine, it isn't there yet.
+        return Err(if job.artifact.exists() { reject(&job.artifact, message) } else { BmError::Packaging(message) });
+    }
+    status!("Verified the executable's architecture{}.", if elf_allowed.is_some() { " and linking" } else { "" });
+    Ok(())
+}
+
+/// Checks the executable the build container (or this machine, see `host`) inspected, against `--hardened`, `--cpu-baseline`,
+/// the size policy and `--lambda-runtime`, returning its hardening and size.
+fn check_executable(job: &Job) -> Result<(Option<HardeningReport>, u64), BmError> {
+    let options = job.options;
+    let project_dir = &job.project_dir;
+    let artifact = &job.artifact;
+    let hardening = if options.hardened { Some(check_hardening(&project_dir.join(job.file("readelf")))?) } else { None };
+    check_baseline(options.cpu_baseline, &project_dir.join(job.file("objdump")), artifact)?;
+    let binary_size = read_binary_size(&project_dir.join(job.file("size")))?;
+    if options.strip || options.upx {
+        let unshrunk = read_binary_size(&project_dir.join(job.file("unshrunk.size")))?;
+        status!(
+            "Executable shrunk from {:.2} MiB to {:.2} MiB ({:.0}% smaller).",
+            unshrunk as f64 / (1024.0 * 1024.0), binary_size as f64 / (1024.0 * 1024.0),
+            100.0 - binary_size as f64 * 100.0 / unshrunk.max(1) as f64);
+    }
+    check_binary_size(job.config, binary_size, artifact)?;
+    if let Some(r) = job.lambda_runtime {
+        let zip = fs::read(artifact).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?;
+        let entries = archive::read_zip(&zip).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?;
+        let problems = r.check(&entries);
+        if !problems.is_empty() {
+            return Err(reject(artifact, format!("The zip won't run on {}, the artifact has been removed:\n    {}", r.name(), problems.join("\n    "))));
+        }
+    }
+    if job.lambda_runtime.is_none() && !project_dir.join(job.file("elf")).is_file() {
+        let base = &job.target.base;
+        let loaded = |interpreter: &String| job.is_docker && base.has_loader(interpreter);
+        if let Some(interpreter) = static_linking::dynamic_interpreter(artifact, job.is_docker).filter(|i| !loaded(i)) {
+            status!(
+                "Warning: the executable is dynamically linked against {}, so it won't run in {}.",
+                interpreter, if job.is_docker { base.description() } else { "Lambda's OS-only runtimes".to_owned() });
+        }
+    }
+    if hardening.as_ref().map(|h| !h.is_hardened()).unwrap_or(false) {
+        return Err(reject(artifact, "The binary is missing hardening properties, the artifact has been removed.\n\
+            Static-PIE needs a newer toolchain than the default builder image has.".to_owned()));
+    }
+    Ok((hardening, binary_size))
+}
+
+/// Explains a failed build container run, with the project's `custom` diagnostics.
+fn build_failed(cmd: &Command, built: &Output, custom: &[diagnostics::Diagnostic]) -> BmError {
+    let stderr = String::from_utf8_lossy(&built.stderr);
+    if let Some(line) = stderr.lines().find(|l| l.starts_with(toolchain::INSTALL_FAILED)) {
+        let channel = line.trim_start_matches(toolchain::INSTALL_FAILED).trim();
+        return BmError::Compile(format!(
+            "Unable to install the `{}` toolchain from `rust-toolchain.toml` inside the build container.\n\
+            Check the toolchain exists and supports the target, and that the container has network access.\n\n\
+            stderr: {}",
+            channel, stderr));
+    }
+
+    if let Some(line) = stderr.lines().find(|l| l.starts_with(gates::FAILED)) {
+        let flag = line.trim_start_matches(gates::FAILED).trim();
+        return BmError::Test(format!("`{}` failed in the build container, so nothing was packaged.\n\nstdout: {}\nstderr: {}",
+            flag, String::from_utf8_lossy(&built.stdout), stderr));
+    }
+
+    if let Some(line) = stderr.lines().find(|l| l.starts_with(backend::NO_EXECUTABLE)) {
+        let binary = line.trim_start_matches(backend::NO_EXECUTABLE).trim();
+        return BmError::Compile(format!(
+            "Cargo built the project, but not a `{}` executable. Check the `[[bin]]` targets in `Cargo.toml`.\n\nstderr: {}",
+            binary, stderr));
+    }
+
+    if let Some(line) = stderr.lines().find(|l| l.starts_with(system_files::MISSING)) {
+        let flag = line.trim_start_matches(system_files::MISSING).trim();
+        return BmError::Environment(format!(
+            "The builder image doesn't have the files `{}` copies into the image.\n\
+            Rebuild it with `--update-builder`, or install them in your own builder image.",
+            flag));
+    }
+
+    if let Some(line) = stderr.lines().find(|l| l.starts_with(archivers::MISSING)) {
+        let tool = line.trim_start_matches(archivers::MISSING).trim();
+        return BmError::Environment(format!(
+            "The builder image has no `{}`, which packaging the artifact needs, so nothing was compiled.\n\
+            Add it to `packages` in `[builder]` of `BlackMagic.toml`. Or install it on this machine to package the artifact here, \
+            except for `--s3`, `--layered` builds and `post-package` hooks with `container = true`.",
+            tool));
+    }
+
+    if let Some(hints) = diagnostics::hints(&stderr, custom) {
+        return BmError::Compile(format!(
+            "Build failed.\n{}\n\nRun the following command manually to see the whole problem:\n\n{:?}\n\nstdout: {}\nstderr: {}",
+            hints, cmd, String::from_utf8_lossy(&built.stdout), stderr));
+    }
+
+    if let Some(line) = stderr.lines().find(|l| l.starts_with(mounts::MISSING_PROJECT)) {
+        let host_dir = line.trim_start_matches(mounts::MISSING_PROJECT).trim().trim_matches('\'');
+        return BmError::Environment(format!(
+            "The build container can't see the project: `{}` is empty there.\n\
+            VM-based runtimes (Docker Desktop, colima, podman machine) only share some of the host's directories with containers.\n\
+            Share `{}` (the project's real location, behind any symlinks) in the runtime's settings, or pass `--layered` to send \
+            the project to the daemon instead of mounting it.",
+            host_dir, host_dir));
+    }
+
+    // `run` exits with 125 when the container couldn't be started at all, e.g. the daemon isn't running.
+    if built.status.code() == Some(125) {
+        return BmError::Docker(format!("Unable to start the build container.\n\nstderr: {}", stderr));
+    }
+
+    BmError::Compile(format!(
+        "Build failed. Run the following command manually to see the problem:\n\n{:?}\n\nstdout: {}\nstderr: {}",
+        cmd, String::from_utf8_lossy(&built.stdout), stderr))
+}
+
+/// Checks the disassembled executable against the requested instruction set level.
+/// Fails (and deletes the artifact, so it can't be shipped by accident) if the binary exceeds it.
+fn check_baseline(cpu_baseline: Option<CpuBaseline>, disassembly: &Path, artifact: &Path) -> Result<(), BmError> {
+    let baseline = match cpu_baseline {
+        Some(b) => b,
+        None => return Ok(()),
+    };
+
+    let listing = fs::read_to_string(disassembly).map_err(|e| BmError::Packaging(format!("Unable to read disassembly: {}", e)))?;
+    fs::remove_file(disassembly).map_err(|e| BmError::Packaging(format!("Unable to remove disassembly: {}", e)))?;
+
+    let violations = baseline::violations(&listing, baseline);
+    if violations.is_empty() {
+        status!("Verified binary only uses `{}` instructions.", baseline.name());
+        return Ok(());
+    }
+
+    let mut message = format!("The binary uses instructions beyond `{}`, the artifact has been removed:", baseline.name());
+    for (mnemonic, count) in violations {
+        message.push_str(&format!("\n    {} ({} times)", mnemonic, count));
+    }
+    message.push_str("\nIf these come from a dependency that detects CPU features at runtime, pick a higher baseline or disable that dependency feature.");
+    Err(reject(artifact, message))
+}
+
+/// Reads the executable's size, recorded inside the build container.
+fn read_binary_size(size_file: &Path) -> Result<u64, BmError> {
+    let size = fs::read_to_string(size_file).map_err(|e| BmError::Packaging(format!("Unable to read binary size: {}", e)))?;
+    fs::remove_file(size_file).map_err(|e| BmError::Packaging(format!("Unable to remove binary size: {}", e)))?;
+    size.trim().parse().map_err(|e| BmError::Packaging(format!("Unable to parse binary size: {}", e)))
+}
+
+fn check_binary_size(config: &Config, size: u64, artifact: &Path) -> Result<(), BmError> {
+    match config.policy.check_binary_size(size) {
+        Some(violation) => Err(reject(artifact, format!(
+            "The binary violates the policy in `BlackMagic.toml`, the artifact has been removed:\n    {}", violation))),
+        None => Ok(()),
+    }
+}
+
+/// Reads the dumped ELF headers and prints a checksec-style report.
+fn check_hardening(readelf: &Path) -> Result<HardeningReport, BmError> {
+    let output = fs::read_to_string(readelf).map_err(|e| BmError::Packaging(format!("Unable to read ELF headers: {}", e)))?;
+    fs::remove_file(readelf).map_err(|e| BmError::Packaging(format!("Unable to remove ELF headers: {}", e)))?;
+
+    let report = HardeningReport::from_readelf(&output);
+    status!("Hardening report:");
+    report.print();
+    Ok(report)
+}
+
+/// Deletes an artifact that failed verification, so it can't be shipped by accident, and fails with `message`.
+pub fn reject(artifact: &Path, message: String) -> BmError {
+    match fs::remove_file(artifact) {
+        Ok(()) => BmError::Packaging(message),
+        Err(e) => BmError::Packaging(format!("{}\nUnable to remove artifact: {}", message, e)),
+    }
+}
@@ -0,0 +1,128 @@
+//! `--build-env KEY[=VALUE]`: environment variables for the compile, e.g. for build scripts embedding build metadata.
+//!
+//! Variables can also be set in an `[env]` section of `BlackMagic.toml`, which `--build-env` overrides:
+//! ```toml
+//! [env]
+//! BUILD_CHANNEL = "stable"
+//! ```
+//! A `--build-env KEY` without a value passes `KEY` through from the host's environment, by name, so secrets like API keys
+//! don't show up in the command line. `--layered` builds set variables in their Dockerfile, which docker keeps in the image,
+//! so they only take ones with values.
+//!
+//! The values are part of the artifact's fingerprint, since build scripts can compile them in.
+//!
+//! Builds also get the build's metadata, as a local build's `build.rs` would work it out, for `env!("BM_GIT_SHA")` and the
+//! like:
+//! - `BM_GIT_SHA`: the commit's full hash, if the project is in a git repository
+//! - `BM_GIT_DIRTY`: `true` if tracked files have changed since, or `false`
+//! - `BM_BUILD_TIME`: when it was built, as an RFC 3339 timestamp, or `SOURCE_DATE_EPOCH`'s with `--reproducible`
+//! - `BM_VERSION`: the crate's version
+//!
+//! `metadata_env` in the `[build]` section of `BlackMagic.toml` picks which, e.g. `metadata_env = ["BM_GIT_SHA"]`, or `[]`
+//! for none, and `--build-env` overrides them. The metadata isn't part of what compiled dependencies are kept by, so a new
+//! commit doesn't compile them again with `--layered` or the cache server. `BM_BUILD_TIME` isn't part of the artifact's
+//! fingerprint, or nothing would ever be reused: an artifact from the artifact store keeps the time it was built.
+//!
+//! `--env-file <path>` writes the variables with values (not the ones passed through) to a file, as `KEY=VALUE` lines, for
+//! building the same way locally, or `docker run --env-file`.
+
+use crate::error::BmError;
+use crate::release;
+use crate::tags;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+pub const METADATA: &[&str] = &["BM_GIT_SHA", "BM_GIT_DIRTY", "BM_BUILD_TIME", "BM_VERSION"];
+
+/// Checks a `--build-env` value.
+pub fn validate(value: String) -> Result<(), String> {
+    let key = value.split('=').next().unwrap_or("");
+    let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("`{}` isn't an environment variable for the build, use `KEY=VALUE`, or `KEY` to pass it from the environment.", value))
+    }
+}
+
+#[derive(Default)]
+pub struct BuildEnv<'a> {
+    /// Set to these values.
+    pub values: Vec<(&'a str, String)>,
+    /// Passed through from the host's environment.
+    pub passed: Vec<&'a str>,
+    /// The build metadata, which only the project's own compile gets.
+    pub metadata: Vec<(&'static str, String)>,
+}
+
+impl<'a> BuildEnv<'a> {
+    /// `[env]` from `BlackMagic.toml`, with `--build-env`'s `args` on top.
+    pub fn resolve(args: impl Iterator<Item = &'a str>, config: &'a BTreeMap<String, String>) -> Result<BuildEnv<'a>, BmError> {
+        let mut values: BTreeMap<&str, Option<String>> = config.iter().map(|(k, v)| (k.as_str(), Some(v.clone()))).collect();
+        for arg in args {
+            match arg.split_once('=') {
+                Some((key, value)) => values.insert(key, Some(value.to_owned())),
+                None if env::var_os(arg).is_some() => values.insert(arg, None),
+                None => return Err(BmError::Environment(format!("`--build-env {}` passes `{}` from the environment, but it isn't set.", arg, arg))),
+            };
+        }
+
+        let mut build_env = BuildEnv::default();
+        for (key, value) in values {
+            match value {
+                Some(v) => build_env.values.push((key, v)),
+                None => build_env.passed.push(key),
+            }
+        }
+        Ok(build_env)
+    }
+
+    /// Adds `names` of the build metadata, that `--build-env` and `[env]` don't set already.
+    pub fn with_metadata(mut self, names: &[String], project_dir: &Path, cargo_toml: &Path, epoch: Option<u64>) -> Result<Self, BmError> {
+        if let Some(name) = names.iter().find(|n| !METADATA.contains(&n.as_str())) {
+            return Err(BmError::Environment(format!(
+                "`metadata_env` in `BlackMagic.toml` has `{}`, which isn't build metadata, use {}.",
+                name, METADATA.iter().map(|m| format!("`{}`", m)).collect::<Vec<_>>().join(", "))));
+        }
+        for &name in METADATA.iter().filter(|m| names.iter().any(|n| n == *m)) {
+            if self.values.iter().any(|(k, _)| *k == name) || self.passed.contains(&name) {
+                continue;
+            }
+            let value = match name {
+                "BM_GIT_SHA" => tags::full_hash(project_dir),
+                "BM_GIT_DIRTY" => tags::full_hash(project_dir).map(|_| tags::dirty(project_dir).to_string()),
+                "BM_BUILD_TIME" => {
+                    let now = || SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                    Some(tags::rfc3339(epoch.unwrap_or_else(now)))
+                }
+                _ => release::read_version(cargo_toml),
+            };
+            self.metadata.extend(value.map(|v| (name, v)));
+        }
+        Ok(self)
+    }
+
+    /// For the artifact's fingerprint, with the host's values of the variables passed through.
+    pub fn fingerprint(&self) -> String {
+        let passed = self.passed.iter().map(|k| (*k, env::var(k).unwrap_or_default()));
+        format!("{:?}", self.values.iter().map(|(k, v)| (*k, v.clone())).chain(passed).collect::<Vec<_>>())
+    }
+
+    /// The build metadata for the artifact's fingerprint, without the time.
+    pub fn metadata_fingerprint(&self) -> String {
+        format!("{:?}", self.metadata.iter().filter(|(k, _)| *k != "BM_BUILD_TIME").collect::<Vec<_>>())
+    }
+
+    /// Writes the variables with values to `path`, as `KEY=VALUE` lines.
+    pub fn write_env_file(&self, path: &Path) -> Result<(), BmError> {
+        let mut contents = String::new();
+        for (key, value) in self.values.iter().map(|(k, v)| (*k, v)).chain(self.metadata.iter().map(|(k, v)| (*k, v))) {
+            contents.push_str(&format!("{}={}\n", key, value));
+        }
+        fs::write(path, contents).map_err(|e| BmError::Environment(format!("Unable to write `{}`: {}", path.display(), e)))
+    }
+}
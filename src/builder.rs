@@ -0,0 +1,523 @@
+//! The builder image: the docker image projects are compiled in.
+//!
+//! It's built locally from a base image (by default one of the `rust_musl_docker` images), with the few extra tools
+//! black_magic needs installed on top. `--libc gnu` builds use a glibc one instead, from the official `rust` image on
+//! Debian bookworm, matching the glibc of the distroless image they're packaged onto. Each base image/tag gets its own local image, so switching tags never
+//! silently reuses an image built from a different toolchain.
+//!
+//! Builder images are shared by every project on the machine, so building one is guarded by a host-level lock:
+//! when several projects need the same missing image at once, one builds it and the others wait and reuse it.
+//!
+//! Projects needing system libraries or tools at build time (e.g. `libpq`, `protobuf-compiler`) list them as `packages` in
+//! the `[builder]` section of `BlackMagic.toml`, installed with `apt-get` (or `apk` on Alpine bases), and anything else as a
+//! `setup_script`, run with bash, without writing a Dockerfile. The image is then named after what's added, e.g.
+//! `black_magic_3f9c2e1a7b4d:nightly-2020-04-23`, so projects with different additions don't clash, and ones with the same
+//! share it. `--upx` builds get UPX installed the same way, in an image of their own.
+//!
+//! Each builder image is labelled with a digest of what it's built from (its Dockerfile and setup script), so a builder image
+//! built from anything else, e.g. by an older black_magic, is rebuilt rather than reused. Builds record when they last used
+//! each builder image, in `~/.cache/black_magic/builders`, and `clean --stale-builders <days>` removes the customized ones
+//! (of every project) no build has used for that long, as each change to `packages` or `setup_script` leaves one behind.
+//!
+//! The tag of a base image can move (e.g. `rustlang/rust:nightly`, or `rust:1-slim-bookworm`), so the same builder image name
+//! would mean a different toolchain depending on when it was built. `black_magic pin-builder` records the digest the base
+//! image's tag points at now in the project's `BlackMagic.toml`, keyed by image and tag:
+//! ```toml
+//! [builder.pins]
+//! "rust:1-slim-bookworm" = "sha256:…"
+//! ```
+//! and builds are then built `FROM` exactly that, into a builder image of its own, e.g. `black_magic_gnu:1-slim-bookworm-3f9c2e1a7b4d`,
+//! so projects pinned to different digests never share (or rebuild) each other's, and `clean --stale-builders` removes the
+//! ones no build uses any more, as it does customized ones. A project with pins, but none for the builder it's building
+//! with (e.g. after `tag` changes), is warned that it's unpinned.
+//!
+//! Nothing rebuilds a builder image on its own, so it can fall years behind its base image's toolchain and security updates.
+//! Builds warn once it's older than `max_age_days` in the `[builder]` section of `BlackMagic.toml` (90 by default, 0 never
+//! warns), and `--auto-update-builder` rebuilds it then, as `--update-builder` does.
+//!
+//! Machines that start out empty, like ephemeral CI runners, would build the builder image every time. With
+//! `--builder-cache-from <registry/repo>` (or `cache_from` in `[builder]`), a build missing it pulls it from there first, as
+//! `<registry/repo>:<builder image>`, e.g. `ghcr.io/acme/builders:black_magic_3f9c2e1a7b4d-nightly-2020-04-23`, so each
+//! customized or pinned one has its own tag. It's used as it is if it was built from what it would be built from now, going
+//! by its label, and otherwise as the cache of the build that replaces it. `--builder-cache-push` pushes the builder image
+//! there whenever a build had to build it, for the next machine to pull.
+
+use crate::cas;
+use crate::checksum::Checksum;
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use crate::registry;
+use crate::retry::Retry;
+use crate::runtime::Runtime;
+use crate::sccache;
+use crate::Arch;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::fs::TryLockError;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// The `setup_script`, in the builder's context dir.
+const SETUP_SCRIPT: &str = "setup.sh";
+
+/// The label with the digest of what a builder image is built from.
+const INPUTS_LABEL: &str = "dev.black_magic.builder-inputs";
+
+/// The repositories of the builder images, before `customized` adds to their names.
+pub const REPOSITORIES: &[&str] = &["black_magic", "black_magic_arm64", "black_magic_gnu", "black_magic_gnu_arm64"];
+
+/// Builder images older than this, in days, probably have an outdated toolchain.
+pub const DEFAULT_MAX_AGE_DAYS: i64 = 90;
+
+const BM_DOCKERFILE: &str = r#"
+RUN apt-get update
+RUN apt-get install zip -y
+RUN apt-get install tar curl -y
+RUN DEBIAN_FRONTEND=noninteractive apt-get install ca-certificates tzdata -y
+"#;
+
+const BM_DOCKERFILE_GNU: &str = r#"
+RUN apt-get update
+RUN apt-get install zip -y
+RUN apt-get install tar curl -y
+RUN DEBIAN_FRONTEND=noninteractive apt-get install ca-certificates tzdata -y
+ENV CARGO_HOME=/root/.cargo
+"#;
+
+const BM_DOCKERFILE_ARM64: &str = r#"
+RUN apt-get update
+RUN apt-get install zip -y
+RUN apt-get install tar curl -y
+RUN DEBIAN_FRONTEND=noninteractive apt-get install ca-certificates tzdata -y
+RUN apt-get install musl-tools -y
+RUN rustup target add aarch64-unknown-linux-musl
+ENV CARGO_HOME=/root/.cargo
+"#;
+
+pub struct Builder {
+    /// The image the builder is based on, without a tag.
+    pub base_image: String,
+    pub tag: String,
+    /// The name of the locally built builder image, e.g. `black_magic:nightly-2020-04-23`.
+    pub image: String,
+    /// The base image's digest, from `[builder.pins]`, see `pinned`.
+    pub digest: Option<String>,
+    /// For `--libc gnu`.
+    gnu: bool,
+    /// From `[builder]`, see `customized`.
+    packages: Vec<String>,
+    setup_script: Option<String>,
+    /// For `--upx`, see `with_upx`.
+    upx: bool,
+    /// From `--builder-cache-from`, see `cached`.
+    cache_repository: Option<String>,
+    push_cache: bool,
+}
+
+impl Builder {
+    /// Picks the builder for `arch`, and glibc if `gnu`, with any image and tag overrides from the command line or config.
+    pub fn new(arch: Arch, gnu: bool, base_image: Option<&str>, tag: Option<&str>) -> Builder {
+        // The glibc ones compile natively, on the platform for `arch`.
+        let (default_image, default_tag, local_name) = match (arch, gnu) {
+            (Arch::X86_64, false) => ("registry.gitlab.com/rust_musl_docker/image", "nightly-2020-04-23", "black_magic"),
+            (Arch::Aarch64, false) => ("rustlang/rust", "nightly", "black_magic_arm64"),
+            (Arch::X86_64, true) => ("rust", "1-slim-bookworm", "black_magic_gnu"),
+            (Arch::Aarch64, true) => ("rust", "1-slim-bookworm", "black_magic_gnu_arm64"),
+        };
+
+        let base_image = base_image.unwrap_or(default_image).to_owned();
+        let tag = tag.unwrap_or(default_tag).to_owned();
+
+        let local_tag = if base_image == default_image {
+            sanitize_tag(&tag)
+        } else {
+            sanitize_tag(&format!("{}-{}", base_image, tag))
+        };
+
+        Builder {
+            image: format!("{}:{}", local_name, local_tag),
+            base_image,
+            tag,
+            digest: None,
+            gnu,
+            packages: Vec::new(),
+            setup_script: None,
+            upx: false,
+            cache_repository: None,
+            push_cache: false,
+        }
+    }
+
+    /// What `[builder.pins]` pins the base image by, e.g. `rust:1-slim-bookworm`.
+    pub fn pin_key(&self) -> String {
+        format!("{}:{}", self.base_image, self.tag)
+    }
+
+    /// The builder built from the base image's digest in `pins`, if it has one, under its own name.
+    pub fn pinned(mut self, pins: &BTreeMap<String, String>) -> Result<Builder, BmError> {
+        let digest = match pins.get(&self.pin_key()) {
+            Some(digest) => digest,
+            None => return Ok(self),
+        };
+        let hex = digest.strip_prefix("sha256:").filter(|h| h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()));
+        let hex = hex.ok_or_else(|| BmError::Environment(format!(
+            "`{}` in `[builder.pins]` isn't an image digest, e.g. `sha256:` and 64 hex digits.", digest)))?;
+        self.image = format!("{}-{}", self.image, &hex[..12]);
+        self.digest = Some(digest.clone());
+        Ok(self)
+    }
+
+    /// The digest the base image's tag points at, pulling it for `arch`.
+    pub fn resolve_digest(&self, runtime: Runtime, arch: Arch) -> Result<String, BmError> {
+        let reference = format!("{}:{}", runtime.qualify(&self.base_image), self.tag);
+        status!("Pulling {}...", reference);
+        let mut pull = runtime.command();
+        pull.arg("pull");
+        if let Some(p) = arch.platform() {
+            pull.arg("--platform").arg(p);
+        }
+        let pulled = pull.arg(&reference).output().map_err(|e| BmError::Docker(format!("Unable to pull {}: {}", reference, e)))?;
+        if !pulled.status.success() {
+            return Err(BmError::Docker(format!("Unable to pull {}.\nstderr: {}", reference, String::from_utf8_lossy(&pulled.stderr))));
+        }
+        let inspect = runtime.command()
+            .args(["image", "inspect", "--format", "{{range .RepoDigests}}{{println .}}{{end}}"])
+            .arg(&reference)
+            .output()
+            .map_err(|e| BmError::Docker(format!("Unable to inspect {}: {}", reference, e)))?;
+        // An image pulled from several repositories has a digest for each.
+        let listed = String::from_utf8_lossy(&inspect.stdout).into_owned();
+        let digests: Vec<(&str, &str)> = listed.lines().filter_map(|d| d.trim().split_once('@')).collect();
+        digests.iter()
+            .find(|(repository, _)| *repository == self.base_image || *repository == runtime.qualify(&self.base_image))
+            .or_else(|| digests.first())
+            .map(|(_, digest)| digest.to_string())
+            .ok_or_else(|| BmError::Docker(format!("{} has no digest from a registry to pin.", reference)))
+    }
+
+    /// The builder with UPX installed too, for `--upx`, so builds needn't install it themselves (they can't with `--offline`).
+    /// Call it before `customized`, which names the image after it.
+    pub fn with_upx(mut self, upx: bool) -> Builder {
+        self.upx = upx;
+        self
+    }
+
+    /// The builder with `packages` installed and `setup_script` run on top, under its own name.
+    pub fn customized(mut self, packages: &[String], setup_script: Option<&str>) -> Result<Builder, BmError> {
+        if let Some(p) = packages.iter().find(|p| p.is_empty() || !p.chars().all(|c| c.is_ascii_alphanumeric() || "+-.:=_".contains(c))) {
+            return Err(BmError::Environment(format!("`{}` in `packages` under `[builder]` isn't a package name.", p)));
+        }
+        if packages.is_empty() && setup_script.is_none() && !self.upx {
+            return Ok(self);
+        }
+        self.packages = packages.to_vec();
+        self.setup_script = setup_script.map(|s| s.to_owned());
+        let upx = if self.upx { "|upx" } else { "" };
+        let hash = Checksum::of(format!("{:?}|{:?}{}", self.packages, self.setup_script, upx).as_bytes()).hex;
+        let (name, tag) = self.image.split_once(':').unwrap_or((&self.image, "latest"));
+        self.image = format!("{}_{}:{}", name, &hash[..12], tag);
+        Ok(self)
+    }
+
+    /// The builder pulled from `repository` in a registry, if it's there, before building it, and with `push`, pushed to it
+    /// after building it.
+    pub fn cached(mut self, repository: Option<&str>, push: bool) -> Result<Builder, BmError> {
+        let repository = match repository {
+            Some(repository) => repository,
+            None if push => return Err(BmError::Environment("`--builder-cache-push` needs a `--builder-cache-from` repository to push to.".to_owned())),
+            None => return Ok(self),
+        };
+        if repository.is_empty() || repository.contains('@') || repository.rsplit('/').next().unwrap_or("").contains(':') {
+            return Err(BmError::Environment(format!(
+                "`{}` isn't a repository to cache the builder image in, e.g. `ghcr.io/acme/builders`, without a tag.", repository)));
+        }
+        self.cache_repository = Some(repository.trim_end_matches('/').to_owned());
+        self.push_cache = push;
+        Ok(self)
+    }
+
+    /// Where the image is cached in the registry, with `--builder-cache-from`.
+    pub fn cache_image(&self) -> Option<String> {
+        self.cache_repository.as_ref().map(|r| format!("{}:{}", r, sanitize_tag(&self.image)))
+    }
+
+    /// Whether, with `--builder-cache-push`, it's pushed once it's built.
+    pub fn pushes_cache(&self) -> bool {
+        self.push_cache
+    }
+
+    pub fn dockerfile(&self, runtime: Runtime, arch: Arch) -> String {
+        let body = match (arch, self.gnu) {
+            (_, true) => BM_DOCKERFILE_GNU,
+            (Arch::X86_64, false) => BM_DOCKERFILE,
+            (Arch::Aarch64, false) => BM_DOCKERFILE_ARM64,
+        };
+        // For `--sccache`, see `sccache`.
+        let digest = self.digest.as_ref().map(|d| format!("@{}", d)).unwrap_or_default();
+        let mut dockerfile = format!(
+            "\nFROM {}:{}{}{}RUN {}\n", runtime.qualify(&self.base_image), self.tag, digest, body, sccache::install_cmd(arch.target_triple()));
+        if !self.packages.is_empty() {
+            let packages = self.packages.join(" ");
+            dockerfile.push_str(&format!(
+                "RUN if command -v apt-get > /dev/null; then apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y {}; else apk add --no-cache {}; fi\n",
+                packages, packages));
+        }
+        if self.upx {
+            dockerfile.push_str(
+                "RUN if command -v apt-get > /dev/null; then apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y upx-ucl; else apk add --no-cache upx; fi\n");
+        }
+        if self.setup_script.is_some() {
+            dockerfile.push_str(&format!("COPY {} /bm_setup.sh\nRUN bash -e /bm_setup.sh\n", SETUP_SCRIPT));
+        }
+        dockerfile
+    }
+
+    /// The files `build_cmd` needs in its context dir, by name.
+    pub fn context_files(&self, runtime: Runtime, arch: Arch) -> Vec<(&'static str, String)> {
+        let mut files = vec![("Dockerfile", self.dockerfile(runtime, arch))];
+        if let Some(script) = &self.setup_script {
+            files.push((SETUP_SCRIPT, script.clone()));
+        }
+        files
+    }
+
+    /// The digest of what the image is built from.
+    fn inputs_digest(&self, runtime: Runtime, arch: Arch) -> String {
+        let files = self.context_files(runtime, arch);
+        Checksum::of(files.iter().map(|(name, contents)| format!("{}\n{}\n", name, contents)).collect::<String>().as_bytes()).hex
+    }
+
+    /// Whether the image exists, built from what it would be built from now.
+    pub fn is_current(&self, runtime: Runtime, arch: Arch) -> bool {
+        self.was_built_from_inputs(runtime, arch, &self.image)
+    }
+
+    /// Whether the local `image` was built from what this one would be built from now.
+    fn was_built_from_inputs(&self, runtime: Runtime, arch: Arch, image: &str) -> bool {
+        let inspect = runtime.command()
+            .arg("image")
+            .arg("inspect")
+            .arg("--format")
+            .arg(format!("{{{{index .Config.Labels \"{}\"}}}}", INPUTS_LABEL))
+            .arg(image)
+            .output();
+        match inspect {
+            Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).trim() == self.inputs_digest(runtime, arch),
+            _ => false,
+        }
+    }
+
+    /// Takes the host-wide lock for building this image, waiting for whoever holds it.
+    fn lock(&self) -> Result<File, BmError> {
+        let path = env::temp_dir().join(format!("black_magic-{}.lock", sanitize_tag(&self.image)));
+        let lock_failed = |e| BmError::Environment(format!("Unable to lock {}: {}", path.display(), e));
+        let file = File::create(&path).map_err(lock_failed)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                status!("Waiting for another black_magic run to finish building {} image...", self.image);
+                file.lock().map_err(lock_failed)?;
+            }
+            Err(TryLockError::Error(e)) => return Err(lock_failed(e)),
+        }
+        Ok(file)
+    }
+
+    /// Where the Dockerfile is written, in `target/black_magic`, for `build_cmd` to run in.
+    pub fn context_dir(arch: Arch) -> String {
+        format!("bm_dockerfile{}", arch.suffix())
+    }
+
+    /// Builds the image, from its context dir, with `build_args`: the proxy's and the network's.
+    pub fn build_cmd(&self, runtime: Runtime, arch: Arch, update: bool, build_args: &[String]) -> Command {
+        let mut image_build = runtime.build();
+        if update {
+            image_build.arg("--pull").arg("--no-cache");
+        }
+        if let Some(p) = arch.platform() {
+            image_build.arg("--platform").arg(p);
+        }
+        // Its layers are reused when they're for the same steps, unless they're being refreshed.
+        if let Some(cached) = self.cache_image().filter(|_| !update) {
+            image_build.arg("--cache-from").arg(cached);
+        }
+        // BuildKit only uses a pulled image's layers as a cache if the image says which steps they're for.
+        if self.push_cache {
+            image_build.arg("--build-arg").arg("BUILDKIT_INLINE_CACHE=1");
+        }
+        image_build.args(build_args)
+            .arg("--label")
+            .arg(format!("{}={}", INPUTS_LABEL, self.inputs_digest(runtime, arch)))
+            .arg("-t")
+            .arg(&self.image)
+            .arg(".");
+        image_build
+    }
+
+    /// Pulls the image from where it's cached, if it's there, for `arch`.
+    fn pull_cache(&self, runtime: Runtime, arch: Arch, cached: &str) -> bool {
+        status!("Pulling {}...", cached);
+        let mut pull = runtime.command();
+        pull.arg("pull");
+        if let Some(p) = arch.platform() {
+            pull.arg("--platform").arg(p);
+        }
+        match pull.arg(cached).output() {
+            Ok(o) if o.status.success() => true,
+            Ok(o) => {
+                output::detail(&format!("Unable to pull {}: {}", cached, String::from_utf8_lossy(&o.stderr).trim()));
+                false
+            }
+            Err(e) => {
+                output::detail(&format!("Unable to pull {}: {}", cached, e));
+                false
+            }
+        }
+    }
+
+    /// How many days ago the image was built, if it exists.
+    pub fn age_days(&self, runtime: Runtime) -> Option<i64> {
+        image_age_days(runtime, &self.image)
+    }
+
+    /// Builds the builder image if it doesn't exist yet, or was built from something else, or always when `update` is set.
+    /// Updating pulls the base image again and skips docker's layer cache, so the apt packages are refreshed too.
+    pub fn ensure(&self, runtime: Runtime, arch: Arch, bm_dir: &Path, update: bool, build_args: &[String], retry: &Retry) -> Result<(), BmError> {
+        if !update && self.is_current(runtime, arch) {
+            record_use(&self.image);
+            return Ok(());
+        }
+
+        // Held until the image is built. The OS drops the lock if we die, so there's never a stale one to clean up.
+        let _lock = self.lock()?;
+        if !update && self.is_current(runtime, arch) {
+            status!("Using {} image built by another black_magic run.", self.image);
+            record_use(&self.image);
+            return Ok(());
+        }
+
+        // Updating means building it afresh, not pulling it.
+        if let Some(cached) = self.cache_image().filter(|_| !update) {
+            if !self.pull_cache(runtime, arch, &cached) {
+                status!("{} isn't cached at {} yet.", self.image, cached);
+            } else if self.was_built_from_inputs(runtime, arch, &cached) {
+                registry::tag(runtime, &cached, &self.image).map_err(BmError::Docker)?;
+                status!("Using {} image pulled from {}.", self.image, cached);
+                record_use(&self.image);
+                return Ok(());
+            } else {
+                status!("{} at {} was built from another Dockerfile or setup script, using it as the cache to build it.", self.image, cached);
+            }
+        }
+
+        if !update && runtime.image_exists(&self.image)? {
+            status!("The {} image was built from another Dockerfile or setup script, rebuilding it...", self.image);
+        } else {
+            status!("Building {} image...", self.image);
+        }
+
+        let context_dir = bm_dir.join(Builder::context_dir(arch));
+        fs::create_dir_all(&context_dir).map_err(|e| BmError::Environment(format!("Unable to create `target\\black_magic\\bm_dockerfile`: {}", e)))?;
+        for (file, contents) in self.context_files(runtime, arch) {
+            fs::write(context_dir.join(file), contents).map_err(|e| BmError::Environment(format!("Unable to create {}: {}", file, e)))?;
+        }
+
+        let mut image_build = self.build_cmd(runtime, arch, update, build_args);
+        image_build.current_dir(&context_dir);
+        let image_build = retry.run(&format!("Building {} image", self.image), || image_build.output());
+
+        let image_build = image_build.map_err(|e| BmError::Docker(format!("Unable to build `{}` image: {}", self.image, e)))?;
+        if !image_build.status.success() {
+            return Err(BmError::Docker(format!(
+                "Unable to build `{}` image.\nstderr: {}",
+                self.image, String::from_utf8_lossy(&image_build.stderr))));
+        }
+        record_use(&self.image);
+
+        if let Some(cached) = self.cache_image().filter(|_| self.push_cache) {
+            status!("Pushing {} image to {}...", self.image, cached);
+            // The build has what it needs either way, the next machine just builds it too.
+            if let Err(e) = registry::push(runtime, &self.image, &cached) {
+                output::warning(&format!("Unable to push {} to {}, so it isn't cached there: {}", self.image, cached, e));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `image` is a customized or pinned builder image, e.g. `black_magic_3f9c2e1a7b4d:nightly-2020-04-23` or
+/// `black_magic:nightly-3f9c2e1a7b4d`.
+pub fn is_customized(image: &str) -> bool {
+    let is_hash = |hash: &str| hash.len() == 12 && hash.chars().all(|c| c.is_ascii_hexdigit());
+    let (repository, tag) = image.split_once(':').unwrap_or((image, ""));
+    let customized = match repository.rsplit_once('_') {
+        Some((name, hash)) => REPOSITORIES.contains(&name) && is_hash(hash),
+        None => false,
+    };
+    let pinned = REPOSITORIES.contains(&repository) && tag.rsplit_once('-').map(|(_, hash)| is_hash(hash)).unwrap_or(false);
+    customized || pinned
+}
+
+/// Where builds record using `image`, by the file's modification time.
+fn use_record(image: &str) -> Option<PathBuf> {
+    Some(cas::cache_dir()?.join("builders").join(sanitize_tag(image)))
+}
+
+/// Records a build using `image`, for `clean --stale-builders`. Nothing fails over it.
+fn record_use(image: &str) {
+    if let Some(record) = use_record(image) {
+        let _ = record.parent().map(fs::create_dir_all);
+        let _ = fs::write(record, image);
+    }
+}
+
+/// How many days since a build last used `image`, or if none has recorded it, since it was built.
+pub fn days_unused(runtime: Runtime, image: &str) -> Option<i64> {
+    let used = use_record(image)
+        .and_then(|r| fs::metadata(r).ok())
+        .and_then(|m| m.modified().ok())
+        .and_then(|m| SystemTime::now().duration_since(m).ok());
+    match used {
+        Some(since) => Some(since.as_secs() as i64 / 86400),
+        None => image_age_days(runtime, image),
+    }
+}
+
+/// How many days ago `image` was built, from its creation time (e.g. `2024-03-01T12:00:00Z`), if it exists.
+fn image_age_days(runtime: Runtime, image: &str) -> Option<i64> {
+    let mut inspect = runtime.command();
+    inspect.arg("image").arg("inspect").arg("--format").arg("{{.Created}}").arg(image);
+    let output = inspect.output().ok().filter(|o| o.status.success())?;
+    let created = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    let mut date = created.get(..10)?.split('-').map(|p| p.parse::<i64>().ok());
+    let built = days_from_civil(date.next()??, date.next()??, date.next()??);
+    let today = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64 / 86400;
+    Some(today - built)
+}
+
+/// Days since the civil date `year-month-day`, from the Unix epoch.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Maps an arbitrary string onto docker's tag charset (`[A-Za-z0-9_.-]`, at most 128 characters).
+fn sanitize_tag(tag: &str) -> String {
+    let sanitized: String = tag
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' { c } else { '-' })
+        .take(128)
+        .collect();
+    sanitized.trim_start_matches(['.', '-']).to_owned()
+}
@@ -0,0 +1,398 @@
+//! Extra files bundled into the Lambda zip next to `bootstrap`, from `--include <path[:dest]>` or `BlackMagic.toml`:
+//! ```toml
+//! [lambda]
+//! include = ["config/app.toml", "static:public"]
+//! ```
+//! Paths are relative to the project, which is the only thing mounted into the build container. Without a `dest`, a path keeps
+//! its place in the zip. Directories are copied whole, and permissions are kept.
+//!
+//! Everything is checked on the host before the build, so the zip can't contain anything from outside the project:
+//! - paths, after following symlinks, have to be inside the project
+//! - symlinks inside included directories are kept as symlinks, so they have to be relative, point inside what's included,
+//!   and not dangle. An include that's a symlink itself is followed.
+//! - only regular files, directories, and symlinks can be included
+//! - no two includes can end up at the same place, or over `bootstrap`
+//! - all of it together has to fit in what Lambda accepts unzipped
+//!
+//! The zip is written on this machine, from what the build container copies out (see `archivers`), apart from `--s3`'s, which
+//! is streamed out of the container, and `--layered` builds', whose container has no mounts to copy it out to.
+//!
+//! `--compression store|fast|default|best` picks how hard the zip is compressed, from not at all (quickest to build and to
+//! start) to deflate's level 9. `bootstrap`, and what it runs if it's a wrapper, are zipped with mode `0755`, which `verify`
+//! checks in the finished zip: Lambda can't run an executable recorded without it, and unzip tools restore it as not
+//! executable.
+
+use crate::error::BmError;
+use crate::reproducible;
+use crate::shell_quote;
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+
+/// Where files are staged inside the build container before being zipped.
+const STAGING_DIR: &str = "/bm_include";
+
+/// Lambda's limit for an unzipped deployment package, leaving room for `bootstrap`.
+const MAX_INCLUDE_SIZE: u64 = 200 * 1024 * 1024;
+
+/// `--compression`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Store,
+    Fast,
+    Default,
+    Best,
+}
+
+impl Compression {
+    pub const NAMES: &'static [&'static str] = &["store", "fast", "default", "best"];
+
+    pub fn from_name(name: &str) -> Compression {
+        match name {
+            "store" => Compression::Store,
+            "fast" => Compression::Fast,
+            "best" => Compression::Best,
+            _ => Compression::Default,
+        }
+    }
+
+    /// `zip`'s option for it.
+    pub fn option(self) -> &'static str {
+        match self {
+            Compression::Store => " -0",
+            Compression::Fast => " -1",
+            Compression::Default => "",
+            Compression::Best => " -9",
+        }
+    }
+
+    /// The `zip` crate's options for it, for zips written on this machine.
+    pub fn file_options(self) -> SimpleFileOptions {
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        match self {
+            Compression::Store => options.compression_method(CompressionMethod::Stored),
+            Compression::Fast => options.compression_level(Some(1)),
+            Compression::Default => options,
+            Compression::Best => options.compression_level(Some(9)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Include {
+    /// Relative to the project directory.
+    pub source: String,
+    /// Relative to the root of the zip.
+    pub dest: String,
+}
+
+/// `path` without `.` components, if it's relative and stays inside whatever it's relative to.
+pub fn contained(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for c in Path::new(path).components() {
+        match c {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("/"))
+    }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, BmError> {
+    fs::canonicalize(path).map_err(|e| BmError::Environment(format!("Unable to resolve `{}`: {}", path.display(), e)))
+}
+
+/// Checks everything under `path`, which is being included from `root`, returning the size of it.
+fn check_tree(path: &Path, root: &Path, source: &str) -> Result<u64, BmError> {
+    let relative = Path::new(source).join(path.strip_prefix(root).unwrap_or(path));
+    let display = relative.display();
+    let metadata = fs::symlink_metadata(path).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", display, e)))?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        // Kept as a symlink in the zip, so whatever it points to has to be in the zip too.
+        if fs::read_link(path).map(|t| t.is_absolute()).unwrap_or(true) {
+            return Err(BmError::Environment(format!(
+                "Can't include `{}`, the symlink `{}` is absolute, so it won't point to the same place when deployed.", source, display)));
+        }
+        let target = fs::canonicalize(path)
+            .map_err(|_| BmError::Environment(format!("Can't include `{}`, the symlink `{}` is dangling.", source, display)))?;
+        if !target.starts_with(root) {
+            return Err(BmError::Environment(format!(
+                "Can't include `{}`, the symlink `{}` points outside of it, to `{}`.",
+                source, display, target.display())));
+        }
+        Ok(0)
+    } else if file_type.is_dir() {
+        let entries = fs::read_dir(path).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", display, e)))?;
+        let mut size = 0;
+        for entry in entries {
+            let entry = entry.map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", display, e)))?;
+            size += check_tree(&entry.path(), root, source)?;
+        }
+        Ok(size)
+    } else if file_type.is_file() {
+        Ok(metadata.len())
+    } else {
+        Err(BmError::Environment(format!("Can't include `{}`, `{}` isn't a regular file, directory, or symlink.", source, display)))
+    }
+}
+
+impl Include {
+    /// Parses `path[:dest]`, checking the path exists in `project_dir`.
+    pub fn parse(spec: &str, project_dir: &Path) -> Result<Include, BmError> {
+        let mut parts = spec.splitn(2, ':');
+        let raw_source = parts.next().unwrap_or("");
+        let source = contained(raw_source)
+            .ok_or_else(|| BmError::Environment(format!("Can't include `{}`, only paths inside the project can be included.", raw_source)))?;
+        let raw_dest = parts.next().filter(|d| !d.trim_matches('/').is_empty()).unwrap_or(&source);
+        let dest = contained(raw_dest)
+            .ok_or_else(|| BmError::Environment(format!("Can't include `{}` at `{}`, it has to be a path inside the zip.", source, raw_dest)))?;
+
+        if fs::symlink_metadata(project_dir.join(&source)).is_err() {
+            return Err(BmError::Environment(format!("Can't include `{}`, it doesn't exist.", source)));
+        }
+        // The path itself might go through a symlink out of the project.
+        let project_dir = canonicalize(project_dir)?;
+        if !canonicalize(&project_dir.join(&source))?.starts_with(&project_dir) {
+            return Err(BmError::Environment(format!("Can't include `{}`, it resolves to somewhere outside the project.", source)));
+        }
+        if dest == "bootstrap" {
+            return Err(BmError::Environment(format!("Can't include `{}` as `bootstrap`, that's the executable.", source)));
+        }
+
+        Ok(Include { source, dest })
+    }
+}
+
+/// Checks `includes` from `project_dir` against each other, and everything they'd put in the zip.
+pub fn check(includes: &[Include], project_dir: &Path) -> Result<(), BmError> {
+    let mut total = 0;
+    for (i, include) in includes.iter().enumerate() {
+        for other in &includes[..i] {
+            let overlaps = |a: &str, b: &str| a == b || a.starts_with(&format!("{}/", b));
+            if overlaps(&include.dest, &other.dest) || overlaps(&other.dest, &include.dest) {
+                return Err(BmError::Environment(format!(
+                    "Can't include both `{}` and `{}`, they'd overlap at `{}` in the zip.",
+                    other.source, include.source, include.dest)));
+            }
+        }
+
+        // An included symlink is followed (see `zip_cmd`), so what's checked is what it points to.
+        let root = canonicalize(&project_dir.join(&include.source))?;
+        total += check_tree(&root, &root, &include.source)?;
+    }
+
+    if total > MAX_INCLUDE_SIZE {
+        return Err(BmError::Environment(format!(
+            "The included files add up to {:.1} MiB, more than the {} MiB Lambda allows.",
+            total as f64 / (1024.0 * 1024.0), MAX_INCLUDE_SIZE / (1024 * 1024))));
+    }
+    Ok(())
+}
+
+/*
+Stage into `dir`:
+    - parent directories of each destination
+    - copy, keeping permissions and symlinks, except following the include itself if it's a symlink
+*/
+pub fn stage_cmd(includes: &[Include], dir: &str) -> String {
+    let mut cmd = String::new();
+    for include in includes {
+        let dest = format!("{}/{}", dir, include.dest);
+        let parent = Path::new(&dest).parent().and_then(|p| p.to_str()).unwrap_or(dir);
+        cmd.push_str(&format!(
+            " && mkdir -p {} && cp -a -H {} {}",
+            shell_quote(parent), shell_quote(&include.source), shell_quote(&dest)));
+    }
+    cmd
+}
+
+/*
+Zip everything staged in `dir` into `zip` (both already shell quoted, `-` for stdout):
+    - recursively, from `dir` so paths are relative to it
+    - keeping symlinks as symlinks
+    - with `--reproducible`, listed in sorted order, with fixed times and no extra attributes
+*/
+fn zip_staged_cmd(dir: &str, zip: &str, source_date_epoch: Option<u64>, compression: Compression) -> String {
+    match source_date_epoch {
+        Some(epoch) => format!(
+            "{} && (cd {} && find . -mindepth 1 | sed 's|^\\./||' | LC_ALL=C sort | zip -q{} -X -y {} -@)",
+            reproducible::touch_cmd(epoch, dir), dir, compression.option(), zip),
+        None => format!(" && (cd {} && zip -q{} -r -y {} .)", dir, compression.option(), zip),
+    }
+}
+
+/// Shell command adding `includes` to the zip at `zip` (relative to the project), to run after `bootstrap` is zipped.
+/// With `source_date_epoch` (see `reproducible`), the files get that time and are zipped in sorted order.
+pub fn zip_cmd(includes: &[Include], zip: &str, source_date_epoch: Option<u64>, compression: Compression) -> String {
+    if includes.is_empty() {
+        return String::new();
+    }
+    format!(
+        "{} && z=\"$PWD\"/{}{}",
+        stage_cmd(includes, STAGING_DIR), shell_quote(zip), zip_staged_cmd(STAGING_DIR, "\"$z\"", source_date_epoch, compression))
+}
+
+/// Shell command zipping the `executables` (`/bootstrap`, and what it runs if it's a wrapper) and `includes` to stdout in one
+/// go, for `--s3` (see `stream`).
+pub fn stream_cmd(executables: &[String], includes: &[Include], source_date_epoch: Option<u64>, compression: Compression) -> String {
+    format!(
+        " && mkdir -p {dir} && mv {} {dir}/{}{}",
+        executables.join(" "), stage_cmd(includes, STAGING_DIR), zip_staged_cmd(STAGING_DIR, "-", source_date_epoch, compression), dir = STAGING_DIR)
+}
+
+/// Shell command copying the `executables` and `includes` into `dir` (relative to the project) as they'd be laid out in the
+/// zip, for zipping them on the host (see `archivers`).
+pub fn package_cmd(executables: &[String], includes: &[Include], dir: &str) -> String {
+    format!(
+        " && rm -rf {staging} {dir} && mkdir -p {staging} && cp -a {} {staging}/{} && cp -a {staging} {dir}",
+        executables.join(" "), stage_cmd(includes, STAGING_DIR), staging = STAGING_DIR, dir = shell_quote(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    /// A project with `config/app.toml` and `static/index.html`, and a directory next to it that's outside it.
+    fn project() -> (TempDir, PathBuf, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        let project = root.path().join("project");
+        let outside = root.path().join("outside");
+        fs::create_dir_all(project.join("config")).unwrap();
+        fs::create_dir_all(project.join("static")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(project.join("config/app.toml"), "port = 80").unwrap();
+        fs::write(project.join("static/index.html"), "<html>").unwrap();
+        fs::write(outside.join("secret"), "hunter2").unwrap();
+        (root, project, outside)
+    }
+
+    fn parse(spec: &str, project: &Path) -> Result<Include, String> {
+        Include::parse(spec, project).map_err(|e| e.to_string())
+    }
+
+    fn check_specs(specs: &[&str], project: &Path) -> Result<(), String> {
+        let includes: Vec<Include> = specs.iter().map(|s| parse(s, project)).collect::<Result<_, _>>()?;
+        check(&includes, project).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn contained_paths() {
+        assert_eq!(contained("./config/app.toml").as_deref(), Some("config/app.toml"));
+        assert_eq!(contained("static/"), Some("static".to_owned()));
+        assert_eq!(contained("../outside"), None);
+        assert_eq!(contained("config/../../outside"), None);
+        assert_eq!(contained("/etc/passwd"), None);
+        assert_eq!(contained("."), None);
+    }
+
+    #[test]
+    fn parses_sources_and_dests() {
+        let (_root, project, _) = project();
+        let include = parse("config/app.toml", &project).unwrap();
+        assert_eq!((include.source.as_str(), include.dest.as_str()), ("config/app.toml", "config/app.toml"));
+        let include = parse("static:public/", &project).unwrap();
+        assert_eq!((include.source.as_str(), include.dest.as_str()), ("static", "public"));
+    }
+
+    #[test]
+    fn rejects_sources_outside_the_project() {
+        let (_root, project, _) = project();
+        assert!(parse("../outside/secret", &project).unwrap_err().contains("only paths inside the project"));
+        assert!(parse("/etc/passwd", &project).unwrap_err().contains("only paths inside the project"));
+        assert!(parse("missing.toml", &project).unwrap_err().contains("doesn't exist"));
+    }
+
+    #[test]
+    fn rejects_dests_outside_the_zip() {
+        let (_root, project, _) = project();
+        assert!(parse("config/app.toml:../app.toml", &project).unwrap_err().contains("has to be a path inside the zip"));
+        assert!(parse("config/app.toml:/etc/app.toml", &project).unwrap_err().contains("has to be a path inside the zip"));
+    }
+
+    #[test]
+    fn rejects_bootstrap_as_a_dest() {
+        let (_root, project, _) = project();
+        assert!(parse("config/app.toml:bootstrap", &project).unwrap_err().contains("that's the executable"));
+        assert!(parse("config/app.toml:./bootstrap", &project).unwrap_err().contains("that's the executable"));
+    }
+
+    #[test]
+    fn rejects_includes_resolving_outside_the_project() {
+        let (_root, project, outside) = project();
+        symlink(&outside, project.join("linked")).unwrap();
+        assert!(parse("linked/secret", &project).unwrap_err().contains("resolves to somewhere outside the project"));
+        assert!(parse("linked", &project).unwrap_err().contains("resolves to somewhere outside the project"));
+    }
+
+    #[test]
+    fn follows_an_include_that_is_a_symlink_inside_the_project() {
+        let (_root, project, _) = project();
+        symlink("config/app.toml", project.join("app.toml")).unwrap();
+        assert_eq!(check_specs(&["app.toml"], &project), Ok(()));
+    }
+
+    #[test]
+    fn keeps_relative_symlinks_inside_an_included_dir() {
+        let (_root, project, _) = project();
+        symlink("index.html", project.join("static/home.html")).unwrap();
+        assert_eq!(check_specs(&["static"], &project), Ok(()));
+    }
+
+    #[test]
+    fn rejects_absolute_symlinks_inside_an_included_dir() {
+        let (_root, project, _) = project();
+        symlink(project.join("static/index.html"), project.join("static/home.html")).unwrap();
+        assert!(check_specs(&["static"], &project).unwrap_err().contains("is absolute"));
+    }
+
+    #[test]
+    fn rejects_dangling_symlinks_inside_an_included_dir() {
+        let (_root, project, _) = project();
+        symlink("missing.html", project.join("static/home.html")).unwrap();
+        assert!(check_specs(&["static"], &project).unwrap_err().contains("is dangling"));
+    }
+
+    #[test]
+    fn rejects_symlinks_escaping_an_included_dir() {
+        let (_root, project, _) = project();
+        // Still inside the project, but not inside what's included, so it wouldn't be in the zip.
+        symlink("../config/app.toml", project.join("static/app.toml")).unwrap();
+        assert!(check_specs(&["static"], &project).unwrap_err().contains("points outside of it"));
+        symlink("../../outside/secret", project.join("static/secret")).unwrap();
+        assert!(check_specs(&["static"], &project).unwrap_err().contains("points outside of it"));
+    }
+
+    #[test]
+    fn rejects_overlapping_dests() {
+        let (_root, project, _) = project();
+        assert!(check_specs(&["config/app.toml:app", "static:app"], &project).unwrap_err().contains("they'd overlap at `app`"));
+        assert!(check_specs(&["static:public", "config/app.toml:public/app.toml"], &project).unwrap_err().contains("they'd overlap"));
+        assert!(check_specs(&["config/app.toml:public/app.toml", "static:public"], &project).unwrap_err().contains("they'd overlap"));
+        // Sharing a prefix isn't overlapping.
+        assert_eq!(check_specs(&["static:public", "config/app.toml:public2"], &project), Ok(()));
+    }
+
+    #[test]
+    fn limits_the_total_size() {
+        let (_root, project, _) = project();
+        // Sparse, so it takes no space.
+        let large = File::create(project.join("static/large.bin")).unwrap();
+        large.set_len(MAX_INCLUDE_SIZE - 6).unwrap();
+        assert_eq!(check_specs(&["static"], &project), Ok(()));
+        large.set_len(MAX_INCLUDE_SIZE).unwrap();
+        assert!(check_specs(&["static"], &project).unwrap_err().contains("more than the 200 MiB Lambda allows"));
+    }
+}
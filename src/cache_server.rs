@@ -0,0 +1,370 @@
+//! A cache of compiled dependencies shared over the LAN, so the whole team benefits from any one machine having compiled them.
+//!
+//! `black_magic cache-server` serves a directory of snapshots over plain HTTP: `GET`/`HEAD`/`PUT /deps/<key>`, and
+//! `GET /deps/<key>.sha256` for the digest of what was uploaded. Builds given `--cache-server <url>` (or `server` in the
+//! `[cache]` section of `BlackMagic.toml`) use it from inside the build container:
+//! - when the project's cache volume has nothing compiled yet, the snapshot for its key is fetched, checked against the digest
+//!   the server recorded when it was uploaded, and unpacked into it
+//! - after compiling, if the server has no snapshot for the key, the compiled dependencies are uploaded
+//!
+//! Snapshots end up in everyone's target dir, so only builds with the team's token can upload them: `BM_CACHE_TOKEN`, or
+//! `token` in `[cache]` (better kept out of the repository). The server won't start without one, and the token is passed to
+//! the build container by name, so it isn't in the command line. Without it, builds only fetch. The server listens on
+//! `127.0.0.1:7878` by default, pass `--listen 0.0.0.0:7878` to serve the LAN.
+//!
+//! The key covers `Cargo.lock` and everything that changes how dependencies compile (target, profile, flags, toolchain),
+//! but not the project's own source, so one snapshot serves every commit with the same dependencies. The cache is only an
+//! optimization: failing to reach the server never fails a build.
+
+use crate::cas;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::shell_quote;
+use clap::ArgMatches;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+/// Printed to stderr inside the container when a snapshot was used, so it can be reported.
+pub const FETCHED: &str = "black_magic: fetched dependencies from the cache server";
+
+/// Uploads bigger than this are refused.
+const MAX_SNAPSHOT_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Where snapshots are kept, and fetched to, inside the build container.
+const CONTAINER_SNAPSHOT: &str = "/tmp/bm_deps.tar.gz";
+
+/// The token allowing uploads, on the server and in the build container.
+pub const TOKEN_VARIABLE: &str = "BM_CACHE_TOKEN";
+
+/// The digest of an upload, from the build container.
+const DIGEST_HEADER: &str = "x-sha256";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// E.g. `http://buildbox.lan:7878`.
+    pub server: Option<String>,
+    /// Allows uploads, see `token`.
+    pub token: Option<String>,
+}
+
+/// The token allowing uploads: `BM_CACHE_TOKEN`, or `token` in `config`.
+pub fn token(config: &CacheConfig) -> Option<String> {
+    env::var(TOKEN_VARIABLE).ok().or_else(|| config.token.clone()).filter(|t| !t.trim().is_empty())
+}
+
+/// Checks a `--cache-server` URL.
+pub fn parse_url(url: &str) -> Result<String, BmError> {
+    let url = url.trim_end_matches('/');
+    if !url.starts_with("http://") || url.len() == "http://".len() {
+        return Err(BmError::Environment(format!("`{}` isn't a cache server, use e.g. `http://buildbox.lan:7878`.", url)));
+    }
+    Ok(url.to_owned())
+}
+
+/// The key for the project's dependencies, from `Cargo.lock` in `project_dir` and the `options` they're compiled with.
+/// `None` without a `Cargo.lock`, since then the dependencies aren't pinned.
+pub fn key(project_dir: &Path, options: &str) -> Option<String> {
+    let lock = fs::read(project_dir.join("Cargo.lock")).ok()?;
+    let mut hasher = Sha256::new();
+    hasher.update(options.as_bytes());
+    hasher.update(&lock);
+    Some(cas::hex(&hasher.finalize()))
+}
+
+/*
+Fetch the snapshot for `key` into `profile_dir` (cargo's output dir for the target and profile), before compiling:
+    - only if nothing has been compiled there already
+    - the digest recorded when it was uploaded, then the snapshot
+    - unpacked only if it matches
+Nothing stops the build.
+*/
+pub fn fetch_cmd(url: &str, key: &str, profile_dir: &str) -> String {
+    let snapshot_url = format!("{}/deps/{}", url, key);
+    format!(
+        "(test -d {dir}/deps || ! digest=$(curl -sf {digest_url}) || ! curl -sf {url} -o {snapshot} \
+        || if echo \"$digest  {snapshot}\" | sha256sum -c --status; then mkdir -p {dir} && tar -xzf {snapshot} -C {dir} && echo '{fetched}' >&2; \
+        else echo \"black_magic: the cache server's snapshot doesn't match its digest, so it wasn't used\" >&2; fi; rm -f {snapshot}; true) && ",
+        dir = profile_dir, digest_url = shell_quote(&format!("{}.sha256", snapshot_url)), url = shell_quote(&snapshot_url),
+        snapshot = CONTAINER_SNAPSHOT, fetched = FETCHED)
+}
+
+/// Shell command uploading what's been compiled in `profile_dir` as the snapshot for `key`, with its digest and the token
+/// from `BM_CACHE_TOKEN`, to run after compiling. It does nothing if the server already has one.
+pub fn upload_cmd(url: &str, key: &str, profile_dir: &str) -> String {
+    let snapshot_url = shell_quote(&format!("{}/deps/{}", url, key));
+    format!(
+        " && (curl -sfI {url} > /dev/null || (cd {dir} && tar -czf {snapshot} $(ls -d deps build .fingerprint 2>/dev/null) \
+        && curl -sf -H \"Authorization: Bearer ${token}\" -H \"{digest}: $(sha256sum {snapshot} | cut -d ' ' -f 1)\" -T {snapshot} {url}) \
+        || echo 'black_magic: unable to upload dependencies to the cache server' >&2)",
+        url = snapshot_url, dir = profile_dir, snapshot = CONTAINER_SNAPSHOT, token = TOKEN_VARIABLE, digest = DIGEST_HEADER)
+}
+
+fn default_dir() -> Option<PathBuf> {
+    Some(cas::cache_dir()?.join("cache-server"))
+}
+
+/// Runs the `cache-server` subcommand, until it's killed.
+pub fn serve(matches: &ArgMatches) -> Result<(), BmError> {
+    let dir = match matches.value_of("DIR") {
+        Some(d) => PathBuf::from(d),
+        None => default_dir().ok_or_else(|| BmError::Environment("Unable to find a cache directory, pass `--dir`.".to_owned()))?,
+    };
+    fs::create_dir_all(&dir).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", dir.display(), e)))?;
+    let token = token(&Config::load(&mounts::current_dir()?)?.cache).ok_or_else(|| BmError::Environment(format!(
+        "The cache server needs a token for builds to upload with, so nobody else can put anything in the team's builds.\n\
+        Set `{}` (or `token` in `[cache]` of `BlackMagic.toml`) to a secret shared with the team, here and where they build.",
+        TOKEN_VARIABLE)))?;
+
+    let listen = matches.value_of("LISTEN").unwrap();
+    let listener = TcpListener::bind(listen).map_err(|e| BmError::Environment(format!("Unable to listen on {}: {}", listen, e)))?;
+    status!("Serving dependency snapshots from {} on http://{}", dir.display(), listen);
+    if listener.local_addr().map(|a| a.ip().is_loopback()).unwrap_or(false) {
+        status!("Only this machine can reach it, pass `--listen 0.0.0.0:7878` to serve the LAN.");
+    }
+    status!("Build with `--cache-server http://<this machine>:<port>` to use it, and `{}` to upload to it.", TOKEN_VARIABLE);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                output::warning(&format!("Unable to accept connection: {}", e));
+                continue;
+            }
+        };
+        let dir = dir.clone();
+        let token = token.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &dir, &token) {
+                output::warning(&format!("Cache request failed: {}", e));
+            }
+        });
+    }
+    Ok(())
+}
+
+fn respond(stream: &mut TcpStream, status: &str, length: u64) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, length)
+}
+
+/// Compares in constant time, so the token can't be guessed from how long it takes.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Serves one request, then closes the connection.
+fn handle(mut stream: TcpStream, dir: &Path, token: &str) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_owned();
+    let path = parts.next().unwrap_or("").to_owned();
+
+    let mut content_length = None;
+    let mut expects_continue = false;
+    let mut authorized = false;
+    let mut digest = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let mut header = header.splitn(2, ':');
+        let name = header.next().unwrap_or("").trim().to_ascii_lowercase();
+        let value = header.next().unwrap_or("").trim();
+        match name.as_str() {
+            "content-length" => content_length = value.parse::<u64>().ok(),
+            "expect" => expects_continue = value.eq_ignore_ascii_case("100-continue"),
+            "authorization" => authorized = value.strip_prefix("Bearer ").map(|t| same(t.trim(), token)).unwrap_or(false),
+            DIGEST_HEADER => digest = Some(value.to_ascii_lowercase()),
+            _ => {}
+        }
+    }
+
+    // Keys are hashes, so anything else can't name a snapshot (or escape the directory).
+    let requested = path.strip_prefix("/deps/").unwrap_or("");
+    let (key, digest_requested) = match requested.strip_suffix(".sha256") {
+        Some(k) => (k, true),
+        None => (requested, false),
+    };
+    if !is_sha256(key) {
+        return respond(&mut stream, "404 Not Found", 0);
+    }
+    let snapshot = dir.join(key);
+    let digest_file = dir.join(format!("{}.sha256", key));
+
+    match method.as_str() {
+        "GET" if digest_requested => match fs::read_to_string(&digest_file) {
+            Ok(recorded) => {
+                respond(&mut stream, "200 OK", recorded.len() as u64)?;
+                stream.write_all(recorded.as_bytes())
+            }
+            Err(_) => respond(&mut stream, "404 Not Found", 0),
+        },
+        _ if digest_requested => respond(&mut stream, "405 Method Not Allowed", 0),
+        "GET" | "HEAD" => match File::open(&snapshot) {
+            Ok(mut file) => {
+                respond(&mut stream, "200 OK", file.metadata()?.len())?;
+                if method == "GET" {
+                    io::copy(&mut file, &mut stream)?;
+                }
+                Ok(())
+            }
+            Err(_) => respond(&mut stream, "404 Not Found", 0),
+        },
+        "PUT" if !authorized => respond(&mut stream, "401 Unauthorized", 0),
+        "PUT" => {
+            let digest = match digest.filter(|d| is_sha256(d)) {
+                Some(d) => d,
+                None => return respond(&mut stream, "400 Bad Request", 0),
+            };
+            let length = match content_length {
+                Some(l) if l <= MAX_SNAPSHOT_SIZE => l,
+                Some(_) => return respond(&mut stream, "413 Payload Too Large", 0),
+                None => return respond(&mut stream, "411 Length Required", 0),
+            };
+            if expects_continue {
+                write!(stream, "HTTP/1.1 100 Continue\r\n\r\n")?;
+            }
+
+            // Written aside and renamed, so a failed upload never leaves a partial snapshot to be fetched. The digest's what
+            // was received, so builds can tell if the snapshot's changed since.
+            static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+            let partial = dir.join(format!("{}.partial-{}", key, UPLOADS.fetch_add(1, Ordering::Relaxed)));
+            let written = copy_hashed(&mut reader.by_ref().take(length), &mut File::create(&partial)?);
+            match written {
+                Ok((n, received)) if n == length && received == digest => {
+                    fs::write(&digest_file, &received)?;
+                    fs::rename(&partial, &snapshot)?;
+                    status!("Stored {} ({:.1} MiB)", key, length as f64 / (1024.0 * 1024.0));
+                    respond(&mut stream, "201 Created", 0)
+                }
+                _ => {
+                    let _ = fs::remove_file(&partial);
+                    respond(&mut stream, "400 Bad Request", 0)
+                }
+            }
+        }
+        _ => respond(&mut stream, "405 Method Not Allowed", 0),
+    }
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Copies `reader` into `writer`, returning how much was copied and its digest.
+fn copy_hashed(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok((copied, cas::hex(&hasher.finalize())));
+        }
+        hasher.update(&buffer[..n]);
+        writer.write_all(&buffer[..n])?;
+        copied += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+
+    const TOKEN: &str = "team-secret";
+
+    /// Sends `request` to a server for `dir`, returning the response.
+    fn request(dir: &Path, request: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.write_all(request).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        handle(server, dir, TOKEN).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn put(dir: &Path, key: &str, body: &str, headers: &str) -> String {
+        request(dir, format!("PUT /deps/{} HTTP/1.1\r\nContent-Length: {}\r\n{}\r\n{}", key, body.len(), headers, body).as_bytes())
+    }
+
+    fn digest(body: &str) -> String {
+        cas::hex(&Sha256::digest(body.as_bytes()))
+    }
+
+    #[test]
+    fn stores_uploads_with_the_token_and_their_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = digest("key");
+        let headers = format!("Authorization: Bearer {}\r\nX-Sha256: {}\r\n", TOKEN, digest("snapshot"));
+        assert!(put(dir.path(), &key, "snapshot", &headers).starts_with("HTTP/1.1 201"));
+        assert!(request(dir.path(), format!("GET /deps/{} HTTP/1.1\r\n\r\n", key).as_bytes()).ends_with("\r\n\r\nsnapshot"));
+        let recorded = request(dir.path(), format!("GET /deps/{}.sha256 HTTP/1.1\r\n\r\n", key).as_bytes());
+        assert!(recorded.ends_with(&format!("\r\n\r\n{}", digest("snapshot"))));
+    }
+
+    #[test]
+    fn refuses_uploads_without_the_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = digest("key");
+        let digest_header = format!("X-Sha256: {}\r\n", digest("snapshot"));
+        assert!(put(dir.path(), &key, "snapshot", &digest_header).starts_with("HTTP/1.1 401"));
+        let wrong = format!("Authorization: Bearer team-secreT\r\n{}", digest_header);
+        assert!(put(dir.path(), &key, "snapshot", &wrong).starts_with("HTTP/1.1 401"));
+        assert!(!dir.path().join(&key).exists());
+    }
+
+    #[test]
+    fn refuses_uploads_not_matching_their_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = digest("key");
+        let authorization = format!("Authorization: Bearer {}\r\n", TOKEN);
+        assert!(put(dir.path(), &key, "snapshot", &authorization).starts_with("HTTP/1.1 400"));
+        let headers = format!("{}X-Sha256: {}\r\n", authorization, digest("something else"));
+        assert!(put(dir.path(), &key, "snapshot", &headers).starts_with("HTTP/1.1 400"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn only_serves_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(request(dir.path(), b"GET /deps/../secret HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(request(dir.path(), b"GET /etc/passwd HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn commands_are_valid_shell() {
+        for cmd in [fetch_cmd("http://buildbox.lan:7878", &digest("key"), "/bm_target/x/release") + "true",
+                    format!("true{}", upload_cmd("http://buildbox.lan:7878", &digest("key"), "/bm_target/x/release"))] {
+            assert!(std::process::Command::new("bash").arg("-n").arg("-c").arg(&cmd).status().unwrap().success(), "{}", cmd);
+        }
+    }
+}
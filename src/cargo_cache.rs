@@ -0,0 +1,117 @@
+//! `--cargo-cache`: how the build container gets cargo's registry and git checkouts, the downloads every build shares.
+//!
+//! - `share` (the default): the host's `~/.cargo/registry` and `~/.cargo/git` are mounted read-write, so what one downloads
+//!   the other has already. A cargo running on the host at the same time (often an IDE's) could corrupt the index, so
+//!   black_magic holds cargo's own lock for the build, falling back to a private registry when it can't get it (see
+//!   `cargo_lock`).
+//! - `isolated`: the container's cargo home is a volume of its own, one per user of the daemon (`bm_cargo_home_<user>`, or
+//!   `--cargo-home-volume`'s), and the host's is never mounted. Nothing's shared with the host, so nothing can race it.
+//! - `ro-overlay`: the host's registry and git checkouts are mounted read-only, underneath a volume of the user's for each
+//!   (`bm_cargo_registry_<user>` and `bm_cargo_git_<user>`). Before compiling, whatever the volumes don't have yet is copied
+//!   up into them, under the lock, so the host's downloads are reused but only ever read, and what the build downloads
+//!   stays in the volumes.
+//!
+//! Set it with `cache` in `[cargo_home]` too. `--cargo-home-volume` (or `volume` in `[cargo_home]`) is `isolated` with that
+//! volume.
+
+use crate::error::BmError;
+
+pub const MODES: &[&str] = &["share", "isolated", "ro-overlay"];
+
+/// Where `ro-overlay` mounts the host's cargo home, read-only, in the build container.
+const HOST_CARGO_HOME: &str = "/bm_host_cargo";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CargoCache {
+    Share,
+    Isolated,
+    RoOverlay,
+}
+
+impl CargoCache {
+    pub fn from_name(name: &str) -> Option<CargoCache> {
+        match name {
+            "share" => Some(CargoCache::Share),
+            "isolated" => Some(CargoCache::Isolated),
+            "ro-overlay" => Some(CargoCache::RoOverlay),
+            _ => None,
+        }
+    }
+
+    /// From `--cargo-cache` or else `cache` in `[cargo_home]`, or without either, `isolated` with a cargo home volume.
+    pub fn select(requested: Option<CargoCache>, configured: Option<&str>, volume: Option<&str>) -> Result<CargoCache, BmError> {
+        let configured = configured
+            .map(|c| CargoCache::from_name(c)
+                .ok_or_else(|| BmError::Environment(format!("Unknown cargo cache `{}`, it has to be one of {}.", c, MODES.join(", ")))))
+            .transpose()?;
+        let cache = match requested.or(configured) {
+            Some(cache) => cache,
+            None if volume.is_some() => CargoCache::Isolated,
+            None => CargoCache::Share,
+        };
+        if volume.is_some() && cache != CargoCache::Isolated {
+            return Err(BmError::Environment(format!(
+                "`--cargo-home-volume` keeps the cargo home in a volume of its own, which is `--cargo-cache isolated`, not `{}`.",
+                cache.name())));
+        }
+        Ok(cache)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CargoCache::Share => "share",
+            CargoCache::Isolated => "isolated",
+            CargoCache::RoOverlay => "ro-overlay",
+        }
+    }
+
+    /// What the build container gets, for `--verbose`.
+    pub fn description(self) -> &'static str {
+        match self {
+            CargoCache::Share => "the host's cargo registry and git checkouts, read-write",
+            CargoCache::Isolated => "a cargo home volume of its own",
+            CargoCache::RoOverlay => "the host's cargo registry and git checkouts, read-only, under volumes of its own",
+        }
+    }
+}
+
+/// Who the volumes are for, as a volume name allows, e.g. `alice`.
+fn user() -> String {
+    let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default();
+    let user: String = user.to_ascii_lowercase().chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-').collect();
+    if !user.is_empty() {
+        return user;
+    }
+    #[cfg(unix)]
+    {
+        // It can't fail.
+        unsafe { libc::getuid() }.to_string()
+    }
+    #[cfg(not(unix))]
+    {
+        "default".to_owned()
+    }
+}
+
+/// `isolated`'s cargo home volume.
+pub fn home_volume() -> String {
+    format!("bm_cargo_home_{}", user())
+}
+
+/// `ro-overlay`'s volume for `dir` (`registry` or `git`).
+pub fn overlay_volume(dir: &str) -> String {
+    format!("bm_cargo_{}_{}", dir, user())
+}
+
+/// Where `ro-overlay` mounts the host's `dir`.
+pub fn host_dir(dir: &str) -> String {
+    format!("{}/{}", HOST_CARGO_HOME, dir)
+}
+
+/// The start of the build container's command with `ro-overlay`, copying what the host's `dirs` have that the volumes don't
+/// yet into `cargo_home`.
+pub fn copy_up_cmd(dirs: &[&str], cargo_home: &str) -> String {
+    dirs.iter()
+        .map(|d| format!("mkdir -p {home}/{dir} && cp -an {host}/. {home}/{dir}/ && ", home = cargo_home, dir = d, host = host_dir(d)))
+        .collect()
+}
@@ -0,0 +1,58 @@
+//! Building against private registries: the host's cargo config and credentials, or tokens from the environment in CI.
+//!
+//! `--cargo-config` mounts the host's `~/.cargo/config.toml` and `credentials.toml` (or their older, extensionless names)
+//! read-only into the build container's cargo home, so registries configured there (an Artifactory mirror, say) are used
+//! with the same credentials. Anything else in the host's config comes along too, so host-specific settings like linkers
+//! are better kept in the project's own `.cargo/config.toml`, or left out.
+//!
+//! `--registry-token <registry>` passes the registry's token through from the host's environment, as cargo reads it:
+//! `CARGO_REGISTRIES_<REGISTRY>_TOKEN`, or `CARGO_REGISTRY_TOKEN` for `crates-io`. Its `_INDEX` is passed too, if it's set.
+//! They're passed by name, so the values don't show up in the command line.
+
+use crate::error::BmError;
+use crate::runtime::Runtime;
+use std::env;
+use std::path::Path;
+
+/// The files mounted with `--cargo-config`, each under the name cargo looks for first and then its older one.
+const FILES: &[(&str, &str)] = &[("config.toml", "config"), ("credentials.toml", "credentials")];
+
+/// Mounts of the host's cargo config and credentials in `cargo_home`, at `container_cargo_home`.
+pub fn volumes(runtime: Runtime, cargo_home: &Path, container_cargo_home: &str) -> Result<Vec<String>, BmError> {
+    let mut volumes = Vec::new();
+    for (name, old_name) in FILES {
+        if let Some(file) = [name, old_name].iter().map(|n| cargo_home.join(n)).find(|f| f.is_file()) {
+            volumes.push(format!("{}:ro", crate::mounts::volume(runtime, &file, &format!("{}/{}", container_cargo_home, name))?));
+        }
+    }
+    if volumes.is_empty() {
+        return Err(BmError::Environment(format!(
+            "`--cargo-config` mounts `config.toml` and `credentials.toml` from `{}`, but it has neither.", cargo_home.display())));
+    }
+    Ok(volumes)
+}
+
+/// The variables cargo reads `registry`'s token (and index) from.
+fn variables(registry: &str) -> (String, String) {
+    if registry == "crates-io" {
+        return ("CARGO_REGISTRY_TOKEN".to_owned(), "CARGO_REGISTRY_INDEX".to_owned());
+    }
+    let name = registry.to_uppercase().replace('-', "_");
+    (format!("CARGO_REGISTRIES_{}_TOKEN", name), format!("CARGO_REGISTRIES_{}_INDEX", name))
+}
+
+/// The host's variables to pass through for `registries`' tokens, failing if any aren't set.
+pub fn token_variables(registries: &[&str]) -> Result<Vec<String>, BmError> {
+    let mut passed = Vec::new();
+    for registry in registries {
+        let (token, index) = variables(registry);
+        if env::var_os(&token).map(|t| t.is_empty()).unwrap_or(true) {
+            return Err(BmError::Environment(format!("`--registry-token {}` passes `{}` to the build, but it isn't set.", registry, token)));
+        }
+        passed.push(token);
+        if env::var_os(&index).is_some() {
+            passed.push(index);
+        }
+    }
+    Ok(passed)
+}
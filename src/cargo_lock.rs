@@ -0,0 +1,66 @@
+//! Sharing the host's cargo registry with the build container safely.
+//!
+//! Cargo guards its downloads with a lock on `.package-cache` in cargo's home, but the container can't see locks held on the
+//! host, so a cargo running there (often an IDE's) and the build could both write to the registry at once. Before the build,
+//! black_magic takes the same lock itself, and holds it until the build finishes. If another cargo has it, black_magic waits
+//! for it, counting down, and then falls back to a registry private to the build containers rather than hanging.
+
+use crate::output::status;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs::TryLockError;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Cargo's own lock file, in cargo's home.
+const LOCK_FILE: &str = ".package-cache";
+
+/// The volumes standing in for the host's registry and git checkouts, when they're locked.
+pub const PRIVATE_REGISTRY_VOLUME: &str = "bm_cargo_registry";
+pub const PRIVATE_GIT_VOLUME: &str = "bm_cargo_git";
+
+/// The host's package cache, as far as the build is concerned.
+pub enum PackageCache {
+    /// Locked for the build, until this is dropped.
+    Locked { _file: File },
+    /// There's no lock to take, e.g. cargo's home is read-only, so it's used as is.
+    Unlockable,
+    /// Another cargo still had it locked after waiting.
+    Busy,
+}
+
+impl PackageCache {
+    /// Locks the package cache in `cargo_home`, waiting up to `timeout` for another cargo to finish with it.
+    pub fn lock(cargo_home: &Path, timeout: Duration) -> PackageCache {
+        let path = cargo_home.join(LOCK_FILE);
+        let file = match OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path) {
+            Ok(f) => f,
+            Err(_) => return PackageCache::Unlockable,
+        };
+
+        let started = Instant::now();
+        let mut announced = None;
+        loop {
+            match file.try_lock() {
+                Ok(()) => return PackageCache::Locked { _file: file },
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(_)) => return PackageCache::Unlockable,
+            }
+            let waited = started.elapsed();
+            if waited >= timeout {
+                return PackageCache::Busy;
+            }
+            // Counting down every five seconds, rather than flooding the output.
+            let remaining = (timeout - waited).as_secs_f64().ceil() as u64;
+            if announced.map(|a| a - remaining >= 5).unwrap_or(true) {
+                status!(
+                    "Another cargo process (an IDE?) has the cargo registry locked, waiting {}s for it before using a private registry...",
+                    remaining);
+                announced = Some(remaining);
+            }
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
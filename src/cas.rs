@@ -0,0 +1,181 @@
+//! A local content-addressed store of built artifacts, in `~/.cache/black_magic/cas/<fingerprint>/`.
+//!
+//! The fingerprint covers every source file (anything git tracks, or would track) plus the build options, so
+//! switching between branches re-materializes artifacts that were already built instead of compiling again. For a workspace
+//! member, or a project with path dependencies outside it, that's every source file mounted with it (see `workspace`), and
+//! the workspace's `Cargo.lock`, so changing only a crate it depends on changes the fingerprint too.
+
+use crate::mounts;
+use crate::output;
+use crate::workspace;
+use sha2::Digest;
+use sha2::Sha256;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Where black_magic keeps things between runs, `~/.cache/black_magic` unless `XDG_CACHE_HOME` says otherwise.
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache = match env::var_os("XDG_CACHE_HOME") {
+        Some(c) if !c.is_empty() => PathBuf::from(c),
+        _ => home::home_dir()?.join(".cache"),
+    };
+    Some(cache.join("black_magic"))
+}
+
+fn cas_dir() -> Option<PathBuf> {
+    Some(cache_dir()?.join("cas"))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The project's source files, relative to `project_dir`, in a stable order.
+pub fn source_files(project_dir: &Path) -> Vec<PathBuf> {
+    let git = Command::new("git")
+        .current_dir(project_dir)
+        .args(["ls-files", "-z", "--cached", "--others", "--exclude-standard"])
+        .output();
+
+    let mut files: Vec<PathBuf> = match git {
+        Ok(output) if output.status.success() => output.stdout
+            .split(|b| *b == 0)
+            .filter(|f| !f.is_empty())
+            .map(|f| PathBuf::from(String::from_utf8_lossy(f).into_owned()))
+            .collect(),
+        // Not a git repo, so take everything but build output.
+        _ => {
+            let mut files = Vec::new();
+            walk(project_dir, Path::new(""), &mut files);
+            files
+        }
+    };
+
+    files.sort();
+    files
+}
+
+fn walk(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(root.join(relative)).into_iter().flatten().filter_map(|e| e.ok()) {
+        let name = entry.file_name();
+        if name == "target" || name == ".git" {
+            continue;
+        }
+
+        let path = relative.join(&name);
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => walk(root, &path, files),
+            Ok(t) if t.is_file() => files.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// Hashes the project's source together with `build_options`.
+pub fn fingerprint(project_dir: &Path, build_options: &str) -> String {
+    fingerprint_without(project_dir, build_options, &[])
+}
+
+/// Hashes the project's source, less the files and directories in `excluded` (relative to `project_dir`), together with
+/// `build_options`.
+pub fn fingerprint_without(project_dir: &Path, build_options: &str, excluded: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(build_options.as_bytes());
+
+    // The project, and the files in it, relative to what's hashed.
+    let (root, project, lockfile) = match workspace::detect(project_dir) {
+        Some(w) => {
+            let project = mounts::host_path(project_dir).strip_prefix(&w.root).map(|p| p.to_owned()).unwrap_or_default();
+            (w.root, project, w.lockfile)
+        }
+        None => (project_dir.to_owned(), PathBuf::new(), project_dir.join("Cargo.lock")),
+    };
+    let mut files: Vec<PathBuf> = source_files(&root).into_iter().filter(|f| !excluded.iter().any(|e| f.starts_with(project.join(e)))).collect();
+    // Even when git ignores it.
+    if let Some(lockfile) = lockfile.strip_prefix(&root).ok().filter(|l| root.join(l).is_file()) {
+        if !files.iter().any(|f| f == lockfile) {
+            files.push(lockfile.to_owned());
+            files.sort();
+        }
+    }
+
+    for file in files {
+        // Deleted but still tracked files just don't contribute content.
+        let contents = fs::read(root.join(&file)).unwrap_or_default();
+        hasher.update(file.to_string_lossy().as_bytes());
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+
+    hex(&hasher.finalize())
+}
+
+/// Whether all of `files` are stored for `fingerprint`.
+pub fn contains(fingerprint: &str, files: &[&str]) -> bool {
+    match cas_dir() {
+        Some(d) => files.iter().all(|f| d.join(fingerprint).join(f).is_file()),
+        None => false,
+    }
+}
+
+/// Copies `files` for `fingerprint` out of the store into `bm_dir`. Returns `false` if any of them aren't stored.
+pub fn restore(fingerprint: &str, bm_dir: &Path, files: &[&str]) -> bool {
+    let entry = match cas_dir() {
+        Some(d) => d.join(fingerprint),
+        None => return false,
+    };
+    if !contains(fingerprint, files) {
+        return false;
+    }
+
+    files.iter().all(|f| fs::copy(entry.join(f), bm_dir.join(f)).is_ok())
+}
+
+/// Copies `files` from `bm_dir` into the store under `fingerprint`.
+/// The store is only an optimization, so failing to write to it isn't an error.
+pub fn store(fingerprint: &str, bm_dir: &Path, files: &[&str]) {
+    let entry = match cas_dir() {
+        Some(d) => d.join(fingerprint),
+        None => return,
+    };
+
+    let stored = fs::create_dir_all(&entry).is_ok() && files.iter().all(|f| fs::copy(bm_dir.join(f), entry.join(f)).is_ok());
+    if !stored {
+        output::warning(&format!("Unable to keep a copy of the artifact in {}.", entry.display()));
+        let _ = fs::remove_dir_all(&entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_path_dependencies_outside_the_project() {
+        let root = tempfile::tempdir().unwrap();
+        let project = root.path().join("services/api");
+        let common = root.path().join("libs/common");
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::create_dir_all(common.join("src")).unwrap();
+        fs::write(project.join("Cargo.toml"), "[package]\nname = \"api\"\n\n[dependencies]\ncommon = { path = \"../../libs/common\" }\n").unwrap();
+        fs::write(project.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(common.join("Cargo.toml"), "[package]\nname = \"common\"\n").unwrap();
+        fs::write(common.join("src/lib.rs"), "pub fn greet() {}").unwrap();
+
+        let before = fingerprint(&project, "");
+        fs::write(common.join("src/lib.rs"), "pub fn greet() { println!(\"hello\"); }").unwrap();
+        let after = fingerprint(&project, "");
+        assert_ne!(before, after);
+        // What's excluded is still relative to the project.
+        let without_src = fingerprint_without(&project, "", &["src"]);
+        fs::write(project.join("src/main.rs"), "fn main() { api::run() }").unwrap();
+        assert_eq!(fingerprint_without(&project, "", &["src"]), without_src);
+        let after = fingerprint(&project, "");
+
+        fs::write(project.join("Cargo.lock"), "version = 3\n").unwrap();
+        assert_ne!(fingerprint(&project, ""), after);
+    }
+}
@@ -0,0 +1,164 @@
+//! The `changelog` subcommand: what changed between two builds, from their manifests, as markdown for a deploy PR.
+//!
+//! It covers dependency versions (from the `Cargo.lock` recorded at build time), executable and artifact size, features and
+//! other cargo arguments, and the toolchain. Manifests written before these were recorded just leave those sections out.
+
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use clap::ArgMatches;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+fn read_manifest(path: &str) -> Result<Value, BmError> {
+    let contents = fs::read_to_string(path).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", path, e)))?;
+    serde_json::from_str(&contents).map_err(|e| BmError::Environment(format!("`{}` isn't a manifest: {}", path, e)))
+}
+
+fn dependencies(manifest: &Value) -> Option<BTreeMap<String, Vec<String>>> {
+    serde_json::from_value(manifest.get("dependencies")?.clone()).ok()
+}
+
+fn strings(value: &Value) -> BTreeSet<String> {
+    value.as_array().into_iter().flatten().filter_map(|v| v.as_str()).map(|v| v.to_owned()).collect()
+}
+
+fn format_size(size: u64) -> String {
+    format!("{:.2} MiB", size as f64 / (1024.0 * 1024.0))
+}
+
+fn size_row(label: &str, before: Option<u64>, after: Option<u64>) -> Option<String> {
+    let (before, after) = (before?, after?);
+    let delta = after as i64 - before as i64;
+    let percent = if before == 0 { 0.0 } else { delta as f64 * 100.0 / before as f64 };
+    let sign = if delta >= 0 { "+" } else { "-" };
+    Some(format!(
+        "| {} | {} | {} | {}{} ({}{:.1}%) |",
+        label, format_size(before), format_size(after), sign, format_size(delta.unsigned_abs()), sign, percent.abs()))
+}
+
+/// The changes in dependency versions, as markdown list items.
+fn dependency_changes(before: &BTreeMap<String, Vec<String>>, after: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let mut changes = Vec::new();
+    for name in names {
+        match (before.get(name), after.get(name)) {
+            (None, Some(versions)) => changes.push(format!("- Added `{}` {}", name, versions.join(", "))),
+            (Some(versions), None) => changes.push(format!("- Removed `{}` {}", name, versions.join(", "))),
+            (Some(old), Some(new)) if old != new => changes.push(format!("- Updated `{}` {} → {}", name, old.join(", "), new.join(", "))),
+            _ => {}
+        }
+    }
+    changes
+}
+
+fn feature_changes(before: &Value, after: &Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    let (old, new) = (strings(&before["selected"]), strings(&after["selected"]));
+    for feature in new.difference(&old) {
+        changes.push(format!("- Enabled `{}`", feature));
+    }
+    for feature in old.difference(&new) {
+        changes.push(format!("- Disabled `{}`", feature));
+    }
+    let on_off = |v: &Value| if v.as_bool() == Some(true) { "on" } else { "off" };
+    if before["default"] != after["default"] {
+        changes.push(format!("- Default features: {} → {}", on_off(&before["default"]), on_off(&after["default"])));
+    }
+    if before["all"] != after["all"] {
+        changes.push(format!("- All features: {} → {}", on_off(&before["all"]), on_off(&after["all"])));
+    }
+    changes
+}
+
+/// Renders the changes from `before` to `after` as markdown.
+fn render(before: &Value, after: &Value) -> String {
+    let version = |m: &Value| format!("{} {}", m["project"].as_str().unwrap_or("?"), m["version"].as_str().unwrap_or("")).trim().to_owned();
+    let mut markdown = format!("### Artifact changes: {} → {}\n", version(before), version(after));
+
+    let rows: Vec<String> = vec![
+        size_row("Executable", before["binary_size"].as_u64(), after["binary_size"].as_u64()),
+        size_row("Artifact", before["artifact_size"].as_u64(), after["artifact_size"].as_u64()),
+    ].into_iter().flatten().collect();
+    if !rows.is_empty() {
+        markdown.push_str("\n| | Before | After | Change |\n|---|---|---|---|\n");
+        for row in rows {
+            markdown.push_str(&row);
+            markdown.push('\n');
+        }
+    }
+
+    if let (Some(old), Some(new)) = (dependencies(before), dependencies(after)) {
+        let changes = dependency_changes(&old, &new);
+        markdown.push_str(&format!("\n**Dependencies** ({} changed)\n", changes.len()));
+        if changes.is_empty() {
+            markdown.push_str("- No changes\n");
+        }
+        for change in changes {
+            markdown.push_str(&change);
+            markdown.push('\n');
+        }
+    }
+
+    if before["features"].is_object() && after["features"].is_object() {
+        let mut changes = feature_changes(&before["features"], &after["features"]);
+        if before["cargo_args"] != after["cargo_args"] {
+            changes.push(format!("- Cargo arguments: `{}` → `{}`", strings_joined(&before["cargo_args"]), strings_joined(&after["cargo_args"])));
+        }
+        if !changes.is_empty() {
+            markdown.push_str("\n**Features**\n");
+            for change in changes {
+                markdown.push_str(&change);
+                markdown.push('\n');
+            }
+        }
+    }
+
+    let mut other = Vec::new();
+    for (label, key) in &[("Target", "target"), ("Profile", "profile")] {
+        if before[key] != after[key] {
+            other.push(format!("- {}: {} → {}", label, before[key], after[key]));
+        }
+    }
+    let (old_rustc, new_rustc) = (&before["environment"]["rustc"]["version"], &after["environment"]["rustc"]["version"]);
+    if !old_rustc.is_null() && !new_rustc.is_null() && old_rustc != new_rustc {
+        other.push(format!("- Toolchain: {} → {}", old_rustc.as_str().unwrap_or("?"), new_rustc.as_str().unwrap_or("?")));
+    }
+    let (old_builder, new_builder) = (&before["environment"]["builder_image"], &after["environment"]["builder_image"]);
+    if !old_builder.is_null() && !new_builder.is_null() && old_builder != new_builder {
+        other.push(format!("- Builder: {} → {}", old_builder.as_str().unwrap_or("?"), new_builder.as_str().unwrap_or("?")));
+    }
+    if !other.is_empty() {
+        markdown.push_str("\n**Build**\n");
+        for change in other {
+            markdown.push_str(&change);
+            markdown.push('\n');
+        }
+    }
+    markdown
+}
+
+fn strings_joined(value: &Value) -> String {
+    value.as_array().into_iter().flatten().filter_map(|v| v.as_str()).collect::<Vec<_>>().join(" ")
+}
+
+/// Runs the `changelog` subcommand.
+pub fn changelog(matches: &ArgMatches) -> Result<(), BmError> {
+    let (before, after, out) = (matches.value_of("BEFORE").unwrap(), matches.value_of("AFTER").unwrap(), matches.value_of("OUT"));
+    let markdown = render(&read_manifest(before)?, &read_manifest(after)?);
+
+    if let Some(out) = out {
+        fs::write(Path::new(out), &markdown).map_err(|e| BmError::Environment(format!("Unable to write `{}`: {}", out, e)))?;
+        status!("Changelog: {}", out);
+    } else if !output::is_json() {
+        print!("{}", markdown);
+    }
+    if output::is_json() {
+        output::emit("changelog", json!({ "before": before, "after": after, "markdown": markdown }));
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! The artifact's SHA-256, written next to it as `<artifact>.sha256` in the format `sha256sum -c` checks.
+//!
+//! Lambda reports a function's code hash (`CodeSha256`) as base64 rather than hex, so that's given too, and checked
+//! against what Lambda says it received on `--deploy`.
+
+use crate::cas;
+use crate::error::BmError;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub struct Checksum {
+    pub hex: String,
+    /// As Lambda's `CodeSha256`.
+    pub base64: String,
+}
+
+/// Standard base64, with padding.
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+impl Checksum {
+    pub fn of(contents: &[u8]) -> Checksum {
+        let digest = Sha256::digest(contents);
+        Checksum { hex: cas::hex(&digest), base64: base64(&digest) }
+    }
+
+    /// Writes `<artifact>.sha256` next to the artifact, returning its path.
+    pub fn write(&self, artifact: &Path) -> Result<PathBuf, BmError> {
+        let name = artifact.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let path = artifact.with_file_name(format!("{}.sha256", name));
+        fs::write(&path, format!("{}  {}\n", self.hex, name))
+            .map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", path.display(), e)))?;
+        Ok(path)
+    }
+}
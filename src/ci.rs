@@ -0,0 +1,171 @@
+//! `--ci github|gitlab`: output for CI pipelines, so they don't need a wrapper script to make sense of a build.
+//!
+//! - Each phase of the build (see `progress`) is a collapsible group in the job's log: `::group::` on GitHub Actions, a
+//!   collapsed section on GitLab CI.
+//! - A failed compile's errors are annotations on GitHub, `::error file=…,line=…` against the lines they're about, relative
+//!   to the repository even when the project is in a subdirectory of it.
+//! - The artifact's path and SHA-256 (and the image, for docker builds) are step outputs on GitHub, in `$GITHUB_OUTPUT` as
+//!   `artifact`, `sha256` and `image`. GitLab gets them in `target/black_magic/ci.env`, as `BM_ARTIFACT`, `BM_SHA256` and
+//!   `BM_IMAGE`, for `artifacts:reports:dotenv`. `--lambda --docker` builds prefix them with the mode, e.g. `lambda_artifact`.
+
+use crate::output;
+use crate::progress;
+use crate::scheduler;
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+pub const SYSTEMS: &[&str] = &["github", "gitlab"];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Ci {
+    Github,
+    Gitlab,
+}
+
+/// The CI system, and the group that's open in its log.
+static CI: Mutex<(Option<Ci>, Option<&'static str>)> = Mutex::new((None, None));
+/// One of several builds at once (see `scheduler`), whose groups would interleave, and whose outputs are the build running it's.
+static JOB: AtomicBool = AtomicBool::new(false);
+
+/// Picks the CI system, once, before anything is reported.
+pub fn init(system: Option<&str>) {
+    let ci = match system {
+        Some("github") => Some(Ci::Github),
+        Some("gitlab") => Some(Ci::Gitlab),
+        _ => None,
+    };
+    CI.lock().unwrap_or_else(|e| e.into_inner()).0 = ci;
+    JOB.store(scheduler::job().is_some(), Ordering::Relaxed);
+}
+
+fn ci() -> Option<Ci> {
+    CI.lock().unwrap_or_else(|e| e.into_inner()).0
+}
+
+/// Prints a workflow command, where progress messages go unless stdout is only for JSON or markers.
+fn command(line: &str) {
+    if output::is_json() || output::is_porcelain() {
+        eprintln!("{}", line);
+    } else {
+        progress::above(|| println!("{}", line));
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// The GitLab section for `name`, which only takes letters, digits, `_`, `.` and `-`.
+fn section(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect()
+}
+
+fn close(ci: Ci, name: &str) {
+    match ci {
+        Ci::Github => command("::endgroup::"),
+        Ci::Gitlab => command(&format!("\x1b[0Ksection_end:{}:{}\r\x1b[0K", now(), section(name))),
+    }
+}
+
+/// Opens the group for the phase `name`, closing the one before, as neither system nests them.
+pub fn begin_group(name: &'static str) {
+    let mut state = CI.lock().unwrap_or_else(|e| e.into_inner());
+    let ci = match state.0 {
+        Some(c) if !JOB.load(Ordering::Relaxed) => c,
+        _ => return,
+    };
+    if let Some(open) = state.1.replace(name) {
+        close(ci, open);
+    }
+    match ci {
+        Ci::Github => command(&format!("::group::{}", name)),
+        Ci::Gitlab => command(&format!("\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}", now(), section(name), name)),
+    }
+}
+
+/// Closes the group for the phase `name`, unless another's been opened since.
+pub fn end_group(name: &'static str) {
+    let mut state = CI.lock().unwrap_or_else(|e| e.into_inner());
+    if let (Some(ci), Some(open)) = (state.0, state.1) {
+        if open == name {
+            state.1 = None;
+            close(ci, name);
+        }
+    }
+}
+
+/// Escapes a workflow command's message, or with `property`, one of its properties.
+fn escape(value: &str, property: bool) -> String {
+    let escaped = value.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    if property { escaped.replace(':', "%3A").replace(',', "%2C") } else { escaped }
+}
+
+/// Annotates the errors in cargo's `stderr` from a failed compile of the project in `project_dir`.
+pub fn annotate(stderr: &str, project_dir: &Path) {
+    if ci() != Some(Ci::Github) {
+        return;
+    }
+    // Cargo's paths are relative to the project, GitHub's to the repository.
+    let prefix = Command::new("git")
+        .current_dir(project_dir)
+        .args(["rev-parse", "--show-prefix"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_owned())
+        .unwrap_or_default();
+    let lines: Vec<&str> = stderr.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        if !line.starts_with("error") || line.starts_with("error: could not compile") || line.starts_with("error: aborting") {
+            continue;
+        }
+        let (title, message) = line.split_once(": ").unwrap_or(("error", line));
+        // `  --> src/main.rs:2:5`, straight after the message.
+        let location = lines.get(i + 1)
+            .and_then(|l| l.trim_start().strip_prefix("--> "))
+            .and_then(|l| {
+                let mut parts = l.rsplitn(3, ':');
+                let (col, line, file) = (parts.next()?, parts.next()?, parts.next()?);
+                Some(format!("file={},line={},col={},", escape(&format!("{}{}", prefix, file), true), line, col))
+            })
+            .unwrap_or_default();
+        command(&format!("::error {}title={}::{}", location, escape(title, true), escape(message, false)));
+    }
+}
+
+/// Sets the build's outputs: `outputs`' names and values.
+pub fn outputs(bm_dir: &Path, outputs: &[(&str, &str)]) {
+    if JOB.load(Ordering::Relaxed) {
+        return;
+    }
+    let result = match ci() {
+        Some(Ci::Github) => match env::var_os("GITHUB_OUTPUT") {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| outputs.iter().try_for_each(|(name, value)| writeln!(f, "{}={}", name, value))),
+            // Runners from before `$GITHUB_OUTPUT`.
+            None => {
+                outputs.iter().for_each(|(name, value)| command(&format!("::set-output name={}::{}", name, escape(value, false))));
+                Ok(())
+            }
+        },
+        Some(Ci::Gitlab) => {
+            let lines: String = outputs.iter().map(|(name, value)| format!("BM_{}={}\n", name.to_uppercase(), value)).collect();
+            fs::write(bm_dir.join("ci.env"), lines)
+        }
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        output::warning(&format!("Unable to set the build's CI outputs: {}", e));
+    }
+}
@@ -0,0 +1,281 @@
+//! `black_magic clean`: removing what black_magic keeps for the current project.
+//!
+//! That's `target/black_magic` (artifacts, manifests, Dockerfiles and logs). `--cache` also removes the project's cache
+//! volumes, and `--layered` builds' layer cache images, so the next build compiles everything again. `--images` also removes
+//! the project's images, for both architectures and with their debug images, and the builder images, which every project on
+//! the machine shares, so the next build anywhere builds its builder image again, along with the project's own customized
+//! builder images (see `builder`). `--stale-builders <days>` removes the customized builder images of every project that no
+//! build has used for that many days.
+//!
+//! `--dry-run` lists what would be removed, and how much space it'd reclaim, without removing anything.
+
+use crate::builder;
+use crate::builder::Builder;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::layered;
+use crate::mounts;
+use crate::names;
+use crate::output;
+use crate::output::status;
+use crate::project_lock;
+use crate::project_lock::ProjectLock;
+use crate::resources;
+use crate::runtime::Runtime;
+use crate::Arch;
+use clap::ArgMatches;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+enum Kind {
+    Directory,
+    Volume,
+    Image,
+}
+
+struct Removal {
+    kind: Kind,
+    /// The path, volume name, or image's tags.
+    names: Vec<String>,
+    /// The image's ID, so images with several tags only count once.
+    id: Option<String>,
+    label: String,
+    size: Option<u64>,
+}
+
+pub fn clean(matches: &ArgMatches) -> Result<(), BmError> {
+    let dry_run = matches.is_present("DRY_RUN");
+    let current_dir = mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+    let name = names::resolve(crate::project_name(&current_dir)?, matches.value_of("NAME").or(config.name.as_deref()))?;
+
+    let bm_dir = current_dir.join("target").join("black_magic");
+    let mut removals = Vec::new();
+    if bm_dir.exists() {
+        removals.push(Removal {
+            kind: Kind::Directory, names: vec![bm_dir.display().to_string()], id: None, label: "target/black_magic".to_owned(), size: Some(dir_size(&bm_dir)),
+        });
+    }
+
+    let runtime = if ["CACHE", "IMAGES", "STALE_BUILDERS"].iter().any(|a| matches.is_present(a)) { Some(Runtime::detect(matches.value_of("RUNTIME"))?) } else { None };
+    if let Some(runtime) = runtime.filter(|_| matches.is_present("CACHE")) {
+        for (volume, size) in cache_volumes(runtime, &name)? {
+            removals.push(Removal { kind: Kind::Volume, names: vec![volume.clone()], id: None, label: format!("cache volume {}", volume), size });
+        }
+
+        // The layers `--layered` builds keep their compiled dependencies in, one for each architecture.
+        let layers: Vec<String> = [Arch::X86_64, Arch::Aarch64].iter().map(|a| format!("{}{}{}", layered::IMAGE_PREFIX, name, a.suffix())).collect();
+        removals.extend(images(runtime, &layers, "layer cache image")?);
+    }
+    if let Some(runtime) = runtime.filter(|_| matches.is_present("IMAGES")) {
+        let project_images: Vec<String> = [Arch::X86_64, Arch::Aarch64].iter()
+            .flat_map(|a| [format!("bm_{}{}", name, a.suffix()), format!("bm_{}{}-debug", name, a.suffix())])
+            .collect();
+        removals.extend(images(runtime, &project_images, "image")?);
+        let mut builders: Vec<String> = builder::REPOSITORIES.iter().map(|r| r.to_string()).collect();
+        // The project's own, with the packages and setup script from its `[builder]`.
+        for (arch, gnu) in [(Arch::X86_64, false), (Arch::Aarch64, false), (Arch::X86_64, true), (Arch::Aarch64, true)] {
+            let builder = Builder::new(arch, gnu, config.builder.image.as_deref(), config.builder.tag.as_deref())
+                .pinned(&config.builder.pins)?
+                .customized(&config.builder.packages, config.builder.setup_script.as_deref())?;
+            let repository = builder.image.split(':').next().unwrap_or_default().to_owned();
+            if !builders.contains(&repository) {
+                builders.push(repository);
+            }
+        }
+        removals.extend(images(runtime, &builders, "builder image")?);
+    }
+    if let (Some(runtime), Some(days)) = (runtime, matches.value_of("STALE_BUILDERS")) {
+        for removal in stale_builders(runtime, days.parse().unwrap())? {
+            // `--images` may have it already.
+            if !removals.iter().any(|r| r.id == removal.id) {
+                removals.push(removal);
+            }
+        }
+    }
+
+    if removals.is_empty() {
+        status!("Nothing to remove.");
+    }
+    let mut removed = Vec::new();
+    for removal in &removals {
+        let size = removal.size.map(format_size).unwrap_or_else(|| "size unknown".to_owned());
+        if dry_run {
+            status!("Would remove {} ({})", removal.label, size);
+            removed.push(removal);
+        } else if remove(runtime, removal, &bm_dir)? {
+            status!("Removed {} ({})", removal.label, size);
+            removed.push(removal);
+        }
+    }
+    let reclaimed: u64 = removed.iter().filter_map(|r| r.size).sum();
+    if !removed.is_empty() {
+        status!("{} {}.", if dry_run { "Would reclaim" } else { "Reclaimed" }, format_size(reclaimed));
+    }
+
+    if output::is_json() {
+        output::emit("clean", json!({
+            "dry_run": dry_run,
+            "removed": removed.iter().map(|r| json!({ "names": r.names, "description": r.label, "size": r.size })).collect::<Vec<_>>(),
+            "reclaimed": reclaimed,
+        }));
+    }
+    Ok(())
+}
+
+/// Removes `removal`, returning whether it was. Anything still in use is left, so the rest still get removed.
+fn remove(runtime: Option<Runtime>, removal: &Removal, bm_dir: &Path) -> Result<bool, BmError> {
+    let (runtime, noun, args) = match (&removal.kind, runtime) {
+        (Kind::Directory, _) => return remove_bm_dir(bm_dir).map(|_| true),
+        (Kind::Volume, Some(runtime)) => (runtime, "volume", vec!["volume", "rm"]),
+        (Kind::Image, Some(runtime)) => (runtime, "image", vec!["image", "rm"]),
+        (_, None) => return Ok(false),
+    };
+    let rm = runtime.command()
+        .args(args)
+        .args(&removal.names)
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to remove docker {}: {}", noun, e)))?;
+    if !rm.status.success() {
+        // Usually just in use, e.g. by a running build or container.
+        output::warning(&format!("Unable to remove {}: {}", removal.label, String::from_utf8_lossy(&rm.stderr).trim()));
+    }
+    Ok(rm.status.success())
+}
+
+/// Removes `target/black_magic`, unless a build is using it.
+fn remove_bm_dir(bm_dir: &Path) -> Result<(), BmError> {
+    let lock = ProjectLock::acquire(bm_dir, true)?;
+    let entries = fs::read_dir(bm_dir).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", bm_dir.display(), e)))?;
+    for entry in entries.flatten().filter(|e| e.file_name() != project_lock::LOCK_FILE) {
+        let path = entry.path();
+        let removed = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        removed.map_err(|e| BmError::Environment(format!("Unable to remove `{}`: {}", path.display(), e)))?;
+    }
+    drop(lock);
+    fs::remove_dir_all(bm_dir).map_err(|e| BmError::Environment(format!("Unable to remove `{}`: {}", bm_dir.display(), e)))
+}
+
+/// The local images in any of `repositories`, once each however many tags they have. Only those tags are removed, so an
+/// image that's also tagged for a registry is kept.
+fn images(runtime: Runtime, repositories: &[String], noun: &str) -> Result<Vec<Removal>, BmError> {
+    let mut images: Vec<Removal> = Vec::new();
+    for repository in repositories {
+        let ls = runtime.command()
+            .arg("image")
+            .arg("ls")
+            .arg("--format")
+            .arg("{{.ID}} {{.Repository}}:{{.Tag}}")
+            .arg(repository)
+            .output()
+            .map_err(|e| BmError::Docker(format!("Unable to list docker images: {}", e)))?;
+        for line in String::from_utf8_lossy(&ls.stdout).lines() {
+            // Untagged images go with the tagged ones they're left over from.
+            let (id, tag) = match line.split_once(' ').filter(|(_, tag)| !tag.contains("<none>")) {
+                Some(i) => i,
+                None => continue,
+            };
+            match images.iter_mut().find(|i| i.id.as_deref() == Some(id)) {
+                Some(image) => {
+                    image.label.push_str(&format!(", {}", tag));
+                    image.names.push(tag.to_owned());
+                }
+                None => images.push(Removal {
+                    kind: Kind::Image, names: vec![tag.to_owned()], id: Some(id.to_owned()), label: format!("{} {}", noun, tag), size: image_size(runtime, id),
+                }),
+            }
+        }
+    }
+    Ok(images)
+}
+
+/// The customized builder images, of any project, that no build has used for `days` days, see `builder`.
+fn stale_builders(runtime: Runtime, days: i64) -> Result<Vec<Removal>, BmError> {
+    let ls = runtime.command()
+        .arg("image")
+        .arg("ls")
+        .arg("--format")
+        .arg("{{.ID}} {{.Repository}}:{{.Tag}}")
+        .arg("--filter")
+        .arg("reference=black_magic*")
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to list docker images: {}", e)))?;
+    let mut stale = Vec::new();
+    for line in String::from_utf8_lossy(&ls.stdout).lines() {
+        let (id, image) = match line.split_once(' ').filter(|(_, image)| builder::is_customized(image)) {
+            Some(i) => i,
+            None => continue,
+        };
+        if let Some(unused) = builder::days_unused(runtime, image).filter(|d| *d >= days) {
+            stale.push(Removal {
+                kind: Kind::Image,
+                names: vec![image.to_owned()],
+                id: Some(id.to_owned()),
+                label: format!("builder image {}, unused for {} days", image, unused),
+                size: image_size(runtime, id),
+            });
+        }
+    }
+    Ok(stale)
+}
+
+/// The project's cache volumes, with their sizes if docker knows them.
+pub fn cache_volumes(runtime: Runtime, name: &str) -> Result<Vec<(String, Option<u64>)>, BmError> {
+    let sizes = volume_sizes(runtime);
+    // `name=` filters match substrings, so check the prefix as well.
+    let prefix = crate::cache_volume_prefix(name);
+    let volumes = runtime.command()
+        .arg("volume")
+        .arg("ls")
+        .arg("-q")
+        .arg("--filter")
+        .arg(format!("name={}", prefix))
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to list docker volumes: {}", e)))?;
+    Ok(String::from_utf8_lossy(&volumes.stdout)
+        .lines()
+        .filter(|v| v.starts_with(&prefix))
+        .map(|v| (v.to_owned(), sizes.iter().find(|(s, _)| s == v).map(|(_, s)| *s)))
+        .collect())
+}
+
+pub fn image_size(runtime: Runtime, id: &str) -> Option<u64> {
+    let inspect = runtime.command().arg("image").arg("inspect").arg("--format").arg("{{.Size}}").arg(id).output().ok()?;
+    String::from_utf8_lossy(&inspect.stdout).trim().parse().ok()
+}
+
+/// Each volume's size, from the `Local Volumes space usage` table of `docker system df -v`.
+fn volume_sizes(runtime: Runtime) -> Vec<(String, u64)> {
+    let df = match runtime.command().arg("system").arg("df").arg("-v").output() {
+        Ok(df) if df.status.success() => df,
+        _ => return Vec::new(),
+    };
+    String::from_utf8_lossy(&df.stdout)
+        .lines()
+        .skip_while(|l| !l.starts_with("VOLUME NAME"))
+        .skip(1)
+        .take_while(|l| !l.trim().is_empty())
+        .filter_map(|l| {
+            let fields: Vec<&str> = l.split_whitespace().collect();
+            Some((fields.first()?.to_string(), resources::parse_size(fields.last()?)?))
+        })
+        .collect()
+}
+
+pub fn dir_size(dir: &Path) -> u64 {
+    fs::read_dir(dir).into_iter().flatten().flatten().map(|entry| match entry.metadata() {
+        Ok(m) if m.is_dir() => dir_size(&entry.path()),
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    }).sum()
+}
+
+pub fn format_size(size: u64) -> String {
+    match size {
+        s if s < 1024 => format!("{} B", s),
+        s if s < 1024 * 1024 => format!("{:.1} KiB", s as f64 / 1024.0),
+        s if s < 1024 * 1024 * 1024 => format!("{:.1} MiB", s as f64 / (1024.0 * 1024.0)),
+        s => format!("{:.2} GiB", s as f64 / (1024.0 * 1024.0 * 1024.0)),
+    }
+}
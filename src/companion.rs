@@ -0,0 +1,214 @@
+//! Companion binaries, e.g. the AWS Lambda Web Adapter or a Lambda extension, put into the zip or image next to the executable
+//! without a custom Dockerfile. From `BlackMagic.toml`:
+//! ```toml
+//! [[companions]]
+//! name = "lambda-adapter"
+//! image = "public.ecr.aws/awsguru/aws-lambda-adapter:0.8.4"
+//! path = "/lambda-adapter"
+//! dest = "opt/extensions/lambda-adapter"
+//!
+//! [[companions]]
+//! name = "otel-collector"
+//! url = "https://example.com/otel-collector-x86_64"
+//! sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! dest = "otel-collector"
+//! ```
+//! Each is taken from `path` in an image (for the build's architecture), or downloaded from a `url`, which needs its `sha256`
+//! so nothing unexpected is ever packaged. An image's companion is checked against its `sha256` too, if it has one. They're
+//! kept in `target/black_magic/companions/`, and packaged executable at `dest`: relative to the root of the zip, or of the
+//! image.
+
+use crate::bundle::Include;
+use crate::cas;
+use crate::error::BmError;
+use crate::network::Network;
+use crate::output;
+use crate::output::status;
+use crate::proxy::Proxy;
+use crate::runtime::Runtime;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+
+/// Where companions are kept, relative to the project.
+const DIR: &str = "target/black_magic/companions";
+
+#[derive(Deserialize)]
+pub struct Companion {
+    pub name: String,
+    pub url: Option<String>,
+    pub image: Option<String>,
+    /// The companion's path inside `image`.
+    pub path: Option<String>,
+    pub sha256: Option<String>,
+    pub dest: String,
+}
+
+impl Companion {
+    /// Where it's kept, relative to the project.
+    pub fn source(&self) -> String {
+        format!("{}/{}", DIR, self.name)
+    }
+
+    /// `dest`, without any leading `/`.
+    pub fn dest(&self) -> &str {
+        self.dest.trim_start_matches('/')
+    }
+
+    /// Where it's taken from, for the plan and the artifact's fingerprint.
+    pub fn origin(&self) -> String {
+        match (&self.url, &self.image, &self.path) {
+            (Some(url), ..) => url.clone(),
+            (None, Some(image), Some(path)) => format!("{}:{}", image, path),
+            _ => String::new(),
+        }
+    }
+
+    /// The SHA-256 of the fetched companion, if it's been fetched.
+    pub fn checksum(&self, project_dir: &Path) -> Option<String> {
+        fs::read(project_dir.join(self.source())).ok().map(|c| cas::hex(&Sha256::digest(&c)))
+    }
+
+    fn check(&self) -> Result<(), BmError> {
+        let invalid = |reason: &str| Err(BmError::Environment(format!("The `{}` companion in `BlackMagic.toml` {}.", self.name, reason)));
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) || self.name.starts_with('.') {
+            return invalid("needs a `name` of only letters, digits, `-`, `_` and `.`");
+        }
+        match (&self.url, &self.image) {
+            (Some(_), Some(_)) | (None, None) => return invalid("needs either a `url` or an `image`"),
+            (Some(_), None) if self.sha256.is_none() => return invalid("needs the `sha256` of what its `url` serves"),
+            (None, Some(_)) if self.path.is_none() => return invalid("needs the `path` to copy out of its `image`"),
+            _ => {}
+        }
+        if self.dest().is_empty() || !Path::new(self.dest()).components().all(|c| matches!(c, Component::Normal(_))) {
+            return invalid("needs a `dest` inside the artifact");
+        }
+        Ok(())
+    }
+
+    /// Downloads or copies the companion into `project_dir`, checking its checksum.
+    fn fetch(&self, runtime: Runtime, builder_image: &str, platform: Option<&str>, project_dir: &Path, proxy: &Proxy, network: &Network) -> Result<(), BmError> {
+        let local = project_dir.join(self.source());
+        let matches_checksum = |path: &Path| match (&self.sha256, fs::read(path)) {
+            (Some(expected), Ok(contents)) => cas::hex(&Sha256::digest(&contents)) == expected.to_lowercase(),
+            _ => false,
+        };
+        // Anything with a checksum only ever needs fetching once.
+        if matches_checksum(&local) {
+            return Ok(());
+        }
+        let _ = fs::remove_file(&local);
+
+        let mut cmd = runtime.command();
+        let mut container = None;
+        if let Some(url) = &self.url {
+            status!("Downloading the {} companion...", self.name);
+            cmd.arg("run").arg("--rm");
+            if let Some(p) = platform {
+                cmd.arg("--platform").arg(p);
+            }
+            // Written out here rather than into a mount, which a remote daemon's containers don't have.
+            cmd.args(proxy.run_args())
+                .args(network.run_args())
+                .arg(builder_image)
+                .arg("curl")
+                .arg("-sSfL")
+                .arg(url);
+        } else if let (Some(image), Some(path)) = (&self.image, &self.path) {
+            status!("Copying the {} companion out of {}...", self.name, image);
+            // Like `inspect`, created with a command so scratch images work, but never started.
+            let mut create = runtime.command();
+            create.arg("create");
+            if let Some(p) = platform {
+                create.arg("--platform").arg(p);
+            }
+            let created = create
+                .arg(image)
+                .arg("/")
+                .output()
+                .map_err(|e| BmError::Docker(format!("Unable to create a container from `{}`: {}", image, e)))?;
+            if !created.status.success() {
+                return Err(BmError::Docker(format!(
+                    "Unable to create a container from `{}`.\n\nstderr: {}", image, String::from_utf8_lossy(&created.stderr))));
+            }
+            let id = String::from_utf8_lossy(&created.stdout).trim().to_owned();
+            cmd.arg("cp").arg("-L").arg(format!("{}:{}", id, path)).arg(&local);
+            container = Some(id);
+        }
+
+        output::detail(&format!("Running {:?}", cmd));
+        let fetched = cmd.output();
+        if let Some(id) = container {
+            let _ = runtime.command().arg("rm").arg(&id).output();
+        }
+        let fetched = fetched.map_err(|e| BmError::Docker(format!("Unable to fetch the `{}` companion: {}", self.name, e)))?;
+        if self.url.is_some() && fetched.status.success() {
+            fs::write(&local, &fetched.stdout).map_err(|e| BmError::Environment(format!("Unable to write `{}`: {}", local.display(), e)))?;
+        }
+        if !fetched.status.success() || !local.is_file() {
+            return Err(BmError::Environment(format!(
+                "Unable to fetch the `{}` companion from {}.\n\nstderr: {}", self.name, self.origin(), String::from_utf8_lossy(&fetched.stderr))));
+        }
+        if self.sha256.is_some() && !matches_checksum(&local) {
+            let _ = fs::remove_file(&local);
+            return Err(BmError::Environment(format!(
+                "The `{}` companion from {} doesn't match its `sha256` in `BlackMagic.toml`.", self.name, self.origin())));
+        }
+        set_executable(&local);
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o755));
+}
+
+/// The build container makes it executable, see `copy_cmd`.
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) {}
+
+/// Checks `companions` are configured properly and don't collide with each other, the `includes`, or `reserved` paths.
+pub fn check(companions: &[Companion], includes: &[Include], reserved: &[&str]) -> Result<(), BmError> {
+    let overlaps = |a: &str, b: &str| a == b || a.starts_with(&format!("{}/", b)) || b.starts_with(&format!("{}/", a));
+    for (i, companion) in companions.iter().enumerate() {
+        companion.check()?;
+        if companions[..i].iter().any(|c| c.name == companion.name) {
+            return Err(BmError::Environment(format!("There are two companions named `{}` in `BlackMagic.toml`.", companion.name)));
+        }
+        let dests = companions[..i].iter().map(|c| c.dest()).chain(includes.iter().map(|i| i.dest.as_str())).chain(reserved.iter().copied());
+        for dest in dests {
+            if overlaps(companion.dest(), dest) {
+                return Err(BmError::Environment(format!("The `{}` companion's `dest` overlaps with `{}`.", companion.name, dest)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fetches every companion into the project's `target/black_magic/companions`.
+pub fn fetch_all(companions: &[Companion], runtime: Runtime, builder_image: &str, platform: Option<&str>, project_dir: &Path, proxy: &Proxy, network: &Network) -> Result<(), BmError> {
+    if !companions.is_empty() {
+        fs::create_dir_all(project_dir.join(DIR)).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", DIR, e)))?;
+    }
+    companions.iter().try_for_each(|c| c.fetch(runtime, builder_image, platform, project_dir, proxy, network))
+}
+
+/// Copies the companions into place inside the build container, so they can be tarred at their `dest`.
+pub fn copy_cmd(companions: &[Companion]) -> String {
+    companions
+        .iter()
+        .map(|c| format!(
+            " && mkdir -p \"$(dirname /{dest})\" && cp {source} /{dest} && chmod 755 /{dest}",
+            source = crate::shell_quote(&c.source()), dest = crate::shell_quote(c.dest())))
+        .collect()
+}
+
+/// Arguments for the image's `tar` command, adding the companions.
+pub fn tar_args(companions: &[Companion]) -> String {
+    companions.iter().map(|c| format!(" /{}", crate::shell_quote(c.dest()))).collect()
+}
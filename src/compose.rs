@@ -0,0 +1,169 @@
+//! `--emit-compose`: a service for the built image in the project's `docker-compose.yml`, so the image as it would be
+//! shipped is a `docker compose up` away, e.g. for testing it together with what it talks to.
+//!
+//! The service, named after the project, runs the `bm_<project>` image, publishing each `--expose`d port on the same port
+//! of the host, with the `--env` variables. `BlackMagic.toml` can name it something else, publish other ports, add variables
+//! (e.g. ones only set locally), and give it a health check, which runs in the container like `--smoke-test`'s command does:
+//! ```toml
+//! [compose]
+//! service = "api"
+//! ports = ["8080:80"]
+//! environment = { DATABASE_URL = "postgres://postgres@db/app" }
+//!
+//! [compose.healthcheck]
+//! command = "/my_project healthcheck"
+//! interval = "10s"
+//! retries = 5
+//! ```
+//! If there's a `docker-compose.yml` already, the service is added to its `services`, or replaces the one with its name,
+//! leaving the others and everything else in the file as they are. There's no merging: it's replaced whole.
+
+use crate::error::BmError;
+use crate::template::RunOptions;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const FILE: &str = "docker-compose.yml";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ComposeConfig {
+    pub service: Option<String>,
+    /// `host:container[/protocol]`, instead of the `--expose`d ports.
+    pub ports: Option<Vec<String>>,
+    pub environment: BTreeMap<String, String>,
+    pub healthcheck: Option<HealthcheckConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct HealthcheckConfig {
+    pub command: String,
+    pub interval: Option<String>,
+    pub timeout: Option<String>,
+    pub retries: Option<u32>,
+    pub start_period: Option<String>,
+}
+
+/// A YAML string, quoted as JSON (which YAML accepts) so nothing in it is taken as YAML syntax.
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// The service's name, from `[compose]`, or else `name`.
+pub fn service_name<'a>(config: &'a ComposeConfig, name: &'a str) -> &'a str {
+    config.service.as_deref().unwrap_or(name)
+}
+
+/// The service running `image`, indented by `indent` under `services`, with `run`'s ports and environment.
+fn service(config: &ComposeConfig, name: &str, image: &str, run: &RunOptions, indent: &str) -> Result<String, BmError> {
+    let (one, two, three) = (indent.to_owned(), indent.repeat(2), indent.repeat(3));
+    let mut service = format!("{}{}:\n{}image: {}\n", one, name, two, quote(image));
+
+    let ports: Vec<String> = match &config.ports {
+        Some(ports) => ports.clone(),
+        None => run.expose.iter().map(|p| match p.split_once('/') {
+            Some((port, protocol)) => format!("{}:{}/{}", port, port, protocol),
+            None => format!("{}:{}", p, p),
+        }).collect(),
+    };
+    if !ports.is_empty() {
+        service.push_str(&format!("{}ports:\n", two));
+        for port in &ports {
+            service.push_str(&format!("{}- {}\n", three, quote(port)));
+        }
+    }
+
+    let mut environment: BTreeMap<&str, &str> = run.env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    environment.extend(config.environment.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    if !environment.is_empty() {
+        service.push_str(&format!("{}environment:\n", two));
+        for (key, value) in &environment {
+            service.push_str(&format!("{}{}: {}\n", three, key, quote(value)));
+        }
+    }
+
+    if let Some(healthcheck) = &config.healthcheck {
+        let command: Vec<&str> = healthcheck.command.split_whitespace().collect();
+        if command.is_empty() {
+            return Err(BmError::Environment("`command` in `[compose.healthcheck]` is empty.".to_owned()));
+        }
+        // The exec form, without a shell, as a `scratch` image has none.
+        let test: Vec<&str> = ["CMD"].iter().copied().chain(command).collect();
+        service.push_str(&format!("{}healthcheck:\n{}test: {}\n", two, three, serde_json::to_string(&test).unwrap()));
+        let durations = [("interval", &healthcheck.interval), ("timeout", &healthcheck.timeout), ("start_period", &healthcheck.start_period)];
+        for (key, value) in durations.iter() {
+            if let Some(value) = value {
+                service.push_str(&format!("{}{}: {}\n", three, key, quote(value)));
+            }
+        }
+        if let Some(retries) = healthcheck.retries {
+            service.push_str(&format!("{}retries: {}\n", three, retries));
+        }
+    }
+    Ok(service)
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// `contents` of `docker-compose.yml` with `service` added to `services`, or replacing `name`'s. `service` is built by
+/// `make`, given the file's indentation.
+fn set_service(contents: &str, name: &str, make: impl Fn(&str) -> Result<String, BmError>) -> Result<String, BmError> {
+    let mut lines: Vec<&str> = contents.lines().collect();
+    let is_content = |l: &&str| !l.trim().is_empty() && !l.trim_start().starts_with('#');
+    let header = match lines.iter().position(|l| l.trim_end() == "services:") {
+        Some(header) => header,
+        None if contents.lines().any(|l| l.starts_with("services:")) => return Err(BmError::Environment(format!(
+            "`services` in `{}` isn't a list of services under it, add the `{}` service yourself.", FILE, name))),
+        None => {
+            while lines.last().map(|l| l.trim().is_empty()).unwrap_or(false) {
+                lines.pop();
+            }
+            let mut contents = lines.join("\n");
+            if !contents.is_empty() {
+                contents.push_str("\n\n");
+            }
+            return Ok(format!("{}services:\n{}", contents, make("  ")?));
+        }
+    };
+    // Where `services` ends, at the next top-level key.
+    let end = lines[header + 1..].iter().position(|l| is_content(l) && indentation(l) == 0).map(|e| header + 1 + e).unwrap_or(lines.len());
+    let indent = lines[header + 1..end].iter().find(|l| is_content(l)).map(|l| indentation(l)).unwrap_or(2);
+    let indent = " ".repeat(indent);
+    let service = make(&indent)?;
+    let key = format!("{}{}:", indent, name);
+    let existing = lines[header + 1..end].iter().position(|l| l.trim_end() == key || l.starts_with(&format!("{} ", key))).map(|s| header + 1 + s);
+    let (start, stop) = match existing {
+        Some(start) => {
+            let stop = lines[start + 1..end].iter().position(|l| is_content(l) && indentation(l) <= indent.len()).map(|s| start + 1 + s).unwrap_or(end);
+            // Blank lines and comments before the next service are its.
+            let stop = (start + 1..stop).rev().find(|i| is_content(&lines[*i])).map(|i| i + 1).unwrap_or(start + 1);
+            (start, stop)
+        }
+        None => {
+            let last = (header + 1..end).rev().find(|i| is_content(&lines[*i])).map(|i| i + 1).unwrap_or(header + 1);
+            (last, last)
+        }
+    };
+    let mut updated: Vec<String> = lines[..start].iter().map(|l| l.to_string()).collect();
+    updated.extend(service.lines().map(|l| l.to_owned()));
+    updated.extend(lines[stop..].iter().map(|l| l.to_string()));
+    Ok(updated.join("\n") + "\n")
+}
+
+/// Writes the service running `image` into `docker-compose.yml` in `project_dir`, named `name` unless `[compose]` says otherwise.
+pub fn write(project_dir: &Path, config: &ComposeConfig, name: &str, image: &str, run: &RunOptions) -> Result<(), BmError> {
+    let path = project_dir.join(FILE);
+    let contents = if path.exists() {
+        fs::read_to_string(&path).map_err(|e| BmError::Packaging(format!("Unable to read `{}`: {}", FILE, e)))?
+    } else {
+        String::new()
+    };
+    let name = service_name(config, name);
+    let updated = set_service(&contents, name, |indent| service(config, name, image, run, indent))?;
+    fs::write(&path, updated).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", FILE, e)))
+}
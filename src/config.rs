@@ -0,0 +1,184 @@
+//! Per-project settings from an optional `BlackMagic.toml` next to `Cargo.toml`.
+//!
+//! Everything in here can also be given on the command line, which takes precedence. E.g:
+//! ```toml
+//! [builder]
+//! image = "registry.gitlab.com/rust_musl_docker/image"
+//! tag = "nightly-2020-06-01"
+//! ```
+
+use crate::backend::BackendConfig;
+use crate::bench::BenchConfig;
+use crate::cache_server::CacheConfig;
+use crate::companion::Companion;
+use crate::compose::ComposeConfig;
+use crate::diagnostics::Diagnostic;
+use crate::error::BmError;
+use crate::hooks::Hook;
+use crate::image_diff::ImageDiffConfig;
+use crate::integration::IntegrationTest;
+use crate::packaging::ServerlessConfig;
+use crate::pipeline::Pipeline;
+use crate::policy::Policy;
+use crate::network::NetworkConfig;
+use crate::notify::NotifyConfig;
+use crate::prefetch::PrefetchConfig;
+use crate::proxy::ProxyConfig;
+use crate::release::ReleaseConfig;
+use crate::sam::SamConfig;
+use crate::scan::ScanConfig;
+use crate::sccache::SccacheConfig;
+use crate::sign::SignConfig;
+use crate::smoke::SmokeTestConfig;
+use crate::wrapper::WrapperConfig;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "BlackMagic.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Overrides the name artifacts, images and volumes get, see `names`.
+    pub name: Option<String>,
+    pub builder: BuilderConfig,
+    pub build: BuildConfig,
+    pub policy: Policy,
+    pub integration_test: Option<IntegrationTest>,
+    pub smoke_test: SmokeTestConfig,
+    pub release: ReleaseConfig,
+    pub aws: AwsConfig,
+    pub cargo_home: CargoHomeConfig,
+    pub bench: BenchConfig,
+    pub lambda: LambdaConfig,
+    pub serverless: ServerlessConfig,
+    pub cache: CacheConfig,
+    pub backend: BackendConfig,
+    pub sccache: SccacheConfig,
+    pub image_diff: ImageDiffConfig,
+    pub scan: ScanConfig,
+    pub compose: ComposeConfig,
+    pub proxy: ProxyConfig,
+    pub network: NetworkConfig,
+    pub prefetch: PrefetchConfig,
+    pub sign: SignConfig,
+    pub notify: NotifyConfig,
+    /// Variables for the compile, see `build_env`.
+    pub env: BTreeMap<String, String>,
+    /// See `companion`.
+    pub companions: Vec<Companion>,
+    pub pipelines: BTreeMap<String, Pipeline>,
+    /// Named sets of build options, see `preset`.
+    pub preset: BTreeMap<String, toml::value::Table>,
+    /// The project's own hints for failed compiles, see `diagnostics`.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Commands run at points in the build, see `hooks`.
+    pub hooks: Vec<Hook>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct BuilderConfig {
+    pub image: Option<String>,
+    pub tag: Option<String>,
+    /// Past this, builds warn the builder image is stale, see `builder`.
+    pub max_age_days: Option<i64>,
+    /// Installed in the builder image, and a script run in it, see `builder`.
+    pub packages: Vec<String>,
+    pub setup_script: Option<String>,
+    /// Base images' digests, by image and tag, see `builder`.
+    pub pins: BTreeMap<String, String>,
+    /// See `--builder-cache-from`.
+    pub cache_from: Option<String>,
+}
+
+/// How cargo compiles, and where the artifact goes, from `[build]`. See `--rustflags`, `--jobs`, `--openssl` and `--out-dir`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct BuildConfig {
+    pub rustflags: Option<String>,
+    pub jobs: Option<u32>,
+    pub openssl: Option<String>,
+    /// Where the builder image has OpenSSL built for musl, for `--openssl system`.
+    pub openssl_dir: Option<String>,
+    /// See `--out-dir` and `--artifact-name`.
+    pub out_dir: Option<String>,
+    pub artifact_name: Option<String>,
+    /// Which of the build metadata variables to set, all of them by default, see `build_env`.
+    pub metadata_env: Option<Vec<String>>,
+    /// See `--sbom`.
+    pub sbom: Option<String>,
+    /// See `--shadow-target`.
+    pub shadow_target: bool,
+    /// See `--user-map`.
+    pub user_map: Option<bool>,
+    /// See `--read-only-source`.
+    pub read_only_source: bool,
+    /// Shared libraries the executable may need besides its C library's, see `elf`.
+    pub allowed_libraries: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AwsConfig {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct LambdaConfig {
+    /// Extra files for the zip, see `--include`.
+    pub include: Vec<String>,
+    /// Puts a `bootstrap` wrapper in the zip, see `wrapper`.
+    pub wrapper: Option<WrapperConfig>,
+    /// Other `[[bin]]`s of the project, compiled with it and zipped next to it with mode `0755`, e.g. for a wrapper to start.
+    pub helpers: Vec<String>,
+    /// See `--lambda-runtime`.
+    pub runtime: Option<String>,
+    /// The function's settings, for `--emit-sam` and `--emit-terraform`.
+    pub function: FunctionConfig,
+    /// See `--emit-sam`.
+    pub sam: SamConfig,
+}
+
+/// The Lambda function's settings, from `[lambda.function]`. Defaults to Lambda's own.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FunctionConfig {
+    /// In MB.
+    pub memory_size: u32,
+    /// In seconds.
+    pub timeout: u32,
+    pub environment: BTreeMap<String, String>,
+}
+
+impl Default for FunctionConfig {
+    fn default() -> FunctionConfig {
+        FunctionConfig { memory_size: 128, timeout: 3, environment: BTreeMap::new() }
+    }
+}
+
+/// Where the build container keeps cargo's home, see `--cargo-home`, `--cargo-home-volume` and `--cargo-cache`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct CargoHomeConfig {
+    pub path: Option<String>,
+    pub volume: Option<String>,
+    pub cache: Option<String>,
+}
+
+impl Config {
+    /// Loads `BlackMagic.toml` from the project directory, or the defaults if there isn't one.
+    pub fn load(project_dir: &Path) -> Result<Config, BmError> {
+        let path = project_dir.join(CONFIG_FILE);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|e| BmError::Environment(format!("Unable to read `BlackMagic.toml`: {}", e)))?;
+        toml::from_str(&contents).map_err(|e| BmError::Environment(format!("Unable to parse `BlackMagic.toml`: {}", e)))
+    }
+}
@@ -0,0 +1,71 @@
+//! An interactive shell in the build container, with the same mounts and environment as the build, to run cargo by hand and
+//! look around.
+//!
+//! `--debug-shell` opens one when the compile fails, after explaining why, and `--shell` opens one instead of building.
+//! `black_magic shell` is `--shell` for a `--docker` build, or for the build's own options with `--args`, e.g.
+//! `black_magic shell --args "--lambda --arch aarch64"`. The shell starts in the project, at `/workdir`, and its container is
+//! removed when it exits. The cache volume is mounted as it is for builds, so what's compiled by hand is kept.
+//!
+//! Only the docker-musl backend compiles in a container, without `--layered` or a remote daemon, whose builds run inside
+//! `docker build`.
+
+use crate::error::BmError;
+use crate::output::status;
+use crate::preset;
+use crate::runtime::Runtime;
+use clap::ArgMatches;
+use std::io;
+use std::io::IsTerminal;
+use std::iter;
+use std::process::Command;
+
+/// The shell for the build container `run` would run, from its `docker run` arguments before the image, starting in
+/// `workdir`, the project.
+pub fn command(runtime: Runtime, run: &Command, image: &str, workdir: &str) -> Command {
+    let mut shell = runtime.command();
+    let mut args = run.get_args();
+    while let Some(arg) = args.next() {
+        // The build container's name, which the shell can't share with a build that's still being removed, and its working
+        // directory, given below.
+        if arg == "--name" || arg == "-w" {
+            args.next();
+        } else if arg != "--rm" {
+            shell.arg(arg);
+        }
+    }
+    // Even with `--keep-on-failure`, which is for the build's own container.
+    shell.arg("--rm");
+    if io::stdin().is_terminal() {
+        shell.arg("-t");
+    }
+    shell.arg("-w").arg(workdir).arg(image).arg("/bin/bash");
+    shell
+}
+
+/// Runs `shell` until it exits.
+pub fn open(shell: &mut Command) -> Result<(), BmError> {
+    status!("Opening a shell in the build container, `exit` to leave it...");
+    // However the shell exits, e.g. with the status of the last command run by hand, it did its job.
+    shell.status().map_err(|e| BmError::Docker(format!("Unable to open a shell in the build container: {}", e)))?;
+    Ok(())
+}
+
+/// `black_magic shell`.
+pub fn shell(matches: &ArgMatches) -> Result<(), BmError> {
+    let mut args: Vec<String> = matches.value_of("ARGS").unwrap().split_whitespace().map(|a| a.to_owned()).collect();
+    for (arg, flag) in [("RUNTIME", "--runtime"), ("NAME", "--name")] {
+        if let Some(value) = matches.value_of(arg).filter(|_| !args.iter().any(|a| a == flag)) {
+            args.push(flag.to_owned());
+            args.push(value.to_owned());
+        }
+    }
+    let args: Vec<String> = iter::once("black_magic".to_owned()).chain(args).chain(iter::once("--shell".to_owned())).collect();
+    let build_matches = crate::app()
+        .get_matches_from_safe(&args)
+        .map_err(|e| BmError::Environment(format!("Invalid `--args`: {}", e)))?;
+    let build_matches = preset::apply(build_matches, args)?;
+    if build_matches.subcommand_name().is_some() {
+        return Err(BmError::Environment("`--args` are a build's options, not another subcommand.".to_owned()));
+    }
+    crate::run(&build_matches)
+}
@@ -0,0 +1,101 @@
+//! Hints for compiles that fail for well-known reasons, recognised in cargo's output, so a failed build says what to do
+//! rather than only leaving the output to read through. E.g. a `-sys` crate looking for a C library that isn't built for musl.
+//!
+//! Each matcher is a set of patterns, any of which in the output means it applies, and what to do about it, with a link to
+//! more. Projects can add their own in `BlackMagic.toml`, e.g. for their internal crates:
+//! ```toml
+//! [[diagnostics]]
+//! pattern = "failed to run custom build command for `protobuf-src"
+//! hint = "Install `protobuf-compiler` in the builder image, with `packages` in `[builder]`."
+//! link = "https://wiki.example.com/builds#protobuf"
+//! ```
+
+use crate::openssl;
+use serde::Deserialize;
+
+/// A project's own matcher, from `[[diagnostics]]`.
+#[derive(Deserialize)]
+pub struct Diagnostic {
+    pub pattern: String,
+    pub hint: String,
+    pub link: Option<String>,
+}
+
+struct Matcher {
+    patterns: &'static [&'static str],
+    hint: &'static str,
+    link: &'static str,
+}
+
+const MATCHERS: &[Matcher] = &[
+    Matcher {
+        patterns: &[openssl::NOT_FOUND],
+        hint: "`openssl-sys` couldn't find an OpenSSL built for musl. Build with `--openssl system` for the builder image's, or \
+            `--openssl vendored` to compile it from source.",
+        link: "https://docs.rs/openssl/latest/openssl/#vendored",
+    },
+    Matcher {
+        patterns: &["pkg-config has not been configured to support cross-compilation"],
+        hint: "A `-sys` crate looked for a C library with pkg-config, which has none built for musl. Use the crate's `vendored` or \
+            `bundled` feature if it has one, or build the library for musl in the builder image, with `setup_script` in `[builder]`.",
+        link: "https://docs.rs/pkg-config/latest/pkg_config/#cross-compilation",
+    },
+    Matcher {
+        patterns: &[
+            "musl-gcc: not found",
+            "failed to find tool \"musl-gcc\"",
+            "failed to find tool \"x86_64-linux-musl-gcc\"",
+            "failed to find tool \"aarch64-linux-musl-gcc\"",
+        ],
+        hint: "A crate compiles C code, and the builder image has no C compiler for musl. Install `musl-tools` in it, with \
+            `packages` in `[builder]`, or use the default builder image, which has one.",
+        link: "https://docs.rs/cc/latest/cc/#external-configuration-via-environment-variables",
+    },
+    Matcher {
+        patterns: &["failed to run custom build command for `ring "],
+        hint: "`ring`'s build script compiles C and assembly for the target. `ring` 0.16 and older don't build with newer C \
+            compilers or for arm64 musl, and 0.17 doesn't build with old nightlies: update it with `cargo update -p ring`, or pin \
+            a newer toolchain in `rust-toolchain.toml`.",
+        link: "https://github.com/briansmith/ring/blob/main/BUILDING.md",
+    },
+    Matcher {
+        patterns: &[
+            "undefined reference to `gnu_get_libc_version",
+            "undefined reference to `__isoc23_",
+            "undefined reference to `__memcpy_chk",
+            "undefined reference to `__register_atfork",
+        ],
+        hint: "Something linked in was built against glibc, whose functions musl doesn't have: usually a prebuilt static library, \
+            or a C library from the host's system. Build it from source for musl (e.g. a crate's `vendored` feature), or build \
+            with `--libc gnu` in docker mode.",
+        link: "https://wiki.musl-libc.org/functional-differences-from-glibc.html",
+    },
+    Matcher {
+        patterns: &["does not support these crate types"],
+        hint: "A proc-macro crate was compiled for the musl target instead of the host, which happens when `RUSTFLAGS` has \
+            `-C target-feature=+crt-static` for every target. Set the flags for the target only, under `[target.<triple>]` in \
+            `.cargo/config.toml`, or with `--rustflags`.",
+        link: "https://doc.rust-lang.org/cargo/reference/config.html#targettriplerustflags",
+    },
+];
+
+/// What `output` suggests went wrong, from the known matchers and the project's `custom` ones, as a list.
+pub fn hints(output: &str, custom: &[Diagnostic]) -> Option<String> {
+    let known = MATCHERS.iter()
+        .filter(|m| m.patterns.iter().any(|p| output.contains(p)))
+        .map(|m| (m.hint, Some(m.link)));
+    let custom = custom.iter()
+        .filter(|d| output.contains(&d.pattern))
+        .map(|d| (d.hint.as_str(), d.link.as_deref()));
+    let hints: Vec<String> = known.chain(custom)
+        .map(|(hint, link)| match link {
+            Some(link) => format!("    - {}\n      See {}", hint, link),
+            None => format!("    - {}", hint),
+        })
+        .collect();
+    if hints.is_empty() {
+        None
+    } else {
+        Some(format!("What's likely wrong:\n{}", hints.join("\n")))
+    }
+}
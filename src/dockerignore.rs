@@ -0,0 +1,123 @@
+//! Keeping what builds don't need out of their build contexts, and, with `--shadow-target`, the host's `target` out of the
+//! build container.
+//!
+//! `--layered` (and `--strategy dockerfile`) builds copy the project's source into their context, which is what git tracks
+//! (or would, for untracked files), so `.gitignore`'s `target/` and the like never go. A `.dockerignore` in the project leaves
+//! out more, e.g. test fixtures, with the same patterns as docker's: `*`, `?` and `**`, and `!` to take something back in.
+//! Projects outside git have their `.gitignore` used the same way, if there's no `.dockerignore`.
+//!
+//! Images are built from `target/black_magic`, which mostly needs their `.tar.gz`, so black_magic writes a `.dockerignore`
+//! there leaving out what else it keeps there: the zips, the build contexts of other builds, the build's outputs and logs. A
+//! Dockerfile template copying in one of those can replace it with one of its own, without the first line, which black_magic
+//! then leaves alone.
+//!
+//! `--shadow-target` mounts an empty `tmpfs` over the project's `target` in the build container, with only
+//! `target/black_magic` mounted back, so nothing the host built there can end up in the build, e.g. through a build script
+//! reading it.
+
+use crate::error::BmError;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// The first line of the `.dockerignore`s black_magic writes.
+const HEADER: &str = "# Written by black_magic, see `src/dockerignore.rs`.";
+
+/// What image builds from `target/black_magic` don't send.
+const IMAGE_CONTEXT: &[&str] = &[
+    "bm_dockerfile*", "layered*", "bundle", "companions", "*.zip", "*.bootstrap.rs", "*.objdump", "*.readelf", "*.cargo.json",
+    "*.rustc", "*.cargo_version", "*.symbols", "*.crate_sizes.json", "last_build.*", ".lock",
+];
+
+/// `pattern` matches `path`, both split at `/`.
+fn glob(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => glob(&pattern[1..], path) || (!path.is_empty() && glob(pattern, &path[1..])),
+        (Some(p), Some(name)) => segment(p.as_bytes(), name.as_bytes()) && glob(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+/// `pattern` (with `*` and `?`) matches `name`, a path segment.
+fn segment(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => segment(&pattern[1..], name) || (!name.is_empty() && segment(pattern, &name[1..])),
+        (Some(b'?'), Some(_)) => segment(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) => p == n && segment(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Patterns leaving files out, in order, the last one matching deciding.
+pub struct Ignore {
+    /// Whether it takes files back in, and its segments.
+    patterns: Vec<(bool, Vec<String>)>,
+}
+
+impl Ignore {
+    /// `contents`, as a `.dockerignore`, or as a `.gitignore` if `gitignore`, where patterns without a `/` match at any depth.
+    fn parse(contents: &str, gitignore: bool) -> Ignore {
+        let patterns = contents.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|line| {
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(p) => (true, p),
+                    None => (false, line),
+                };
+                let trimmed = pattern.trim_end_matches('/');
+                let anywhere = gitignore && !trimmed.contains('/');
+                let segments = trimmed.trim_start_matches('/').split('/').filter(|s| !s.is_empty() && *s != ".");
+                let segments = anywhere.then(|| "**".to_owned()).into_iter().chain(segments.map(|s| s.to_owned())).collect();
+                (negated, segments)
+            })
+            .collect();
+        Ignore { patterns }
+    }
+
+    /// The project's `.dockerignore`, or `.gitignore` for projects outside git, which cargo's own listing already follows.
+    pub fn load(project_dir: &Path) -> Ignore {
+        let in_git = Command::new("git")
+            .current_dir(project_dir)
+            .args(["rev-parse", "--is-inside-work-tree"])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        match fs::read_to_string(project_dir.join(".dockerignore")) {
+            Ok(contents) => Ignore::parse(&contents, false),
+            Err(_) if !in_git => Ignore::parse(&fs::read_to_string(project_dir.join(".gitignore")).unwrap_or_default(), true),
+            Err(_) => Ignore { patterns: Vec::new() },
+        }
+    }
+
+    /// The `.dockerignore` of the build context in `context_dir`, as docker reads it, see `engine`.
+    pub fn context(context_dir: &Path) -> Ignore {
+        Ignore::parse(&fs::read_to_string(context_dir.join(".dockerignore")).unwrap_or_default(), false)
+    }
+
+    /// Whether `path`, relative to the project, is left out: it, or a directory it's in, matches.
+    pub fn ignores(&self, path: &Path) -> bool {
+        let segments: Vec<String> = path.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+        let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+        let mut ignored = false;
+        for (negated, pattern) in &self.patterns {
+            let pattern: Vec<&str> = pattern.iter().map(|s| s.as_str()).collect();
+            if (1..=segments.len()).any(|n| glob(&pattern, &segments[..n])) {
+                ignored = !negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Writes the `.dockerignore` image builds from `bm_dir` use, unless it's been replaced.
+pub fn write_image_context(bm_dir: &Path) -> Result<(), BmError> {
+    let path = bm_dir.join(".dockerignore");
+    if fs::read_to_string(&path).is_ok_and(|c| !c.starts_with(HEADER)) {
+        return Ok(());
+    }
+    fs::write(&path, format!("{}\n{}\n", HEADER, IMAGE_CONTEXT.join("\n")))
+        .map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", path.display(), e)))
+}
@@ -0,0 +1,87 @@
+//! The `completions` and `man` subcommands: black_magic's options, for the shell. E.g.
+//! `black_magic completions bash > /etc/bash_completion.d/black_magic`, or `black_magic man > /usr/local/share/man/man1/black_magic.1`.
+//!
+//! Completions are clap's own. clap 2 has no man pages, so `man` writes one in roff from the same definitions `--help` prints:
+//! the usage notes as the description, then every option and subcommand.
+
+use crate::error::BmError;
+use clap::App;
+use clap::ArgMatches;
+use clap::ArgSettings;
+use clap::Shell;
+use std::io;
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// Prints the completion script for the shell `matches` names.
+pub fn completions(matches: &ArgMatches) -> Result<(), BmError> {
+    let shell: Shell = matches.value_of("SHELL").unwrap().parse().map_err(|e: String| BmError::Environment(e))?;
+    crate::app().gen_completions_to("black_magic", shell, &mut io::stdout());
+    Ok(())
+}
+
+/// `text`, so roff takes it literally.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', r"\e").replace('-', r"\-");
+    // A line starting with these is a request.
+    text.lines()
+        .map(|l| if l.starts_with(['.', '\'']) { format!(r"\&{}", l) } else { l.to_owned() })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn bold(text: &str) -> String {
+    format!(r"\fB{}\fR", escape(text))
+}
+
+/// `.TP` entries for `app`'s options and arguments, by their long names.
+fn options(app: &App) -> String {
+    let mut entries: Vec<(String, String, String)> = Vec::new();
+    for flag in app.p.flags.iter().filter(|f| !f.b.is_set(ArgSettings::Hidden)) {
+        let names = [flag.s.short.map(|s| format!("-{}", s)), flag.s.long.map(|l| format!("--{}", l))];
+        let key = flag.s.long.unwrap_or(flag.b.name).to_owned();
+        entries.push((key, names.iter().flatten().map(|n| bold(n)).collect::<Vec<_>>().join(", "), flag.b.help.unwrap_or("").to_owned()));
+    }
+    for opt in app.p.opts.iter().filter(|o| !o.b.is_set(ArgSettings::Hidden)) {
+        let names = [opt.s.short.map(|s| format!("-{}", s)), opt.s.long.map(|l| format!("--{}", l))];
+        let value = opt.v.val_names.as_ref().and_then(|v| v.values().next().copied()).unwrap_or(opt.b.name);
+        let mut help = opt.b.help.unwrap_or("").to_owned();
+        if let Some(values) = &opt.v.possible_vals {
+            help.push_str(&format!(" One of: {}.", values.join(", ")));
+        }
+        if let Some(default) = opt.v.default_val {
+            help.push_str(&format!(" Defaults to {}.", default.to_string_lossy()));
+        }
+        let key = opt.s.long.unwrap_or(opt.b.name).to_owned();
+        let names = names.iter().flatten().map(|n| bold(n)).collect::<Vec<_>>().join(", ");
+        entries.push((key, format!(r"{} \fI<{}>\fR", names, escape(value)), help));
+    }
+    entries.sort();
+    for (_, positional) in app.p.positionals.iter().filter(|(_, p)| !p.b.is_set(ArgSettings::Hidden)) {
+        let name = positional.v.val_names.as_ref().and_then(|v| v.values().next().copied()).unwrap_or(positional.b.name);
+        entries.push((String::new(), format!(r"\fI<{}>\fR", escape(name)), positional.b.help.unwrap_or("").to_owned()));
+    }
+    entries.iter().map(|(_, names, help)| format!(".TP\n{}\n{}\n", names, escape(help))).collect()
+}
+
+/// Prints black_magic's man page.
+pub fn man() -> Result<(), BmError> {
+    let app = crate::app();
+    let mut page = format!(
+        ".TH BLACK_MAGIC 1 \"\" \"black_magic {}\"\n.SH NAME\nblack_magic \\- builds rust projects into AWS Lambda zips and docker images\n",
+        app.p.meta.version.unwrap_or(""));
+    page.push_str(&format!(
+        ".SH SYNOPSIS\n{} [\\fIoptions\\fR] [\\fB\\-\\-\\fR \\fIcargo args\\fR...]\n.br\n{} \\fIsubcommand\\fR [\\fIoptions\\fR]\n",
+        bold("black_magic"), bold("black_magic")));
+    // The usage notes are laid out already, so they're kept as they are.
+    let about = app.p.meta.about.unwrap_or("");
+    page.push_str(&format!(".SH DESCRIPTION\n.nf\n{}\n.fi\n", escape(about.trim_matches('\n'))));
+    page.push_str(&format!(".SH OPTIONS\n{}", options(&app)));
+    page.push_str(".SH SUBCOMMANDS\n");
+    for subcommand in &app.p.subcommands {
+        page.push_str(&format!(".SS {}\n{}\n{}", bold(&format!("black_magic {}", subcommand.p.meta.name)),
+            escape(subcommand.p.meta.about.unwrap_or("")), options(subcommand)));
+    }
+    print!("{}", page);
+    Ok(())
+}
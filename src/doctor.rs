@@ -0,0 +1,296 @@
+//! The `doctor` subcommand: whether this machine can build, and what to fix if not.
+//!
+//! Each check passes, warns (builds will work, but slower or with surprises), fails, or is skipped when an earlier failure
+//! means it can't be run. Failures come with what to do about them, and make `doctor` exit with the environment exit code,
+//! so it can gate a CI job. Nothing is built or pulled: the file sharing checks only run if the builder image already exists.
+
+use crate::builder;
+use crate::builder::Builder;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::mounts;
+use crate::openssl;
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use crate::Arch;
+use clap::ArgMatches;
+use serde_json::json;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Below this, builds (a cold one's target dir alone is often a few GiB) may run out of space.
+const MIN_FREE_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The crates.io index, for the network check.
+const REGISTRY_URL: &str = "https://index.crates.io/config.json";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Pass => "pass",
+            Outcome::Warn => "warn",
+            Outcome::Fail => "fail",
+            Outcome::Skip => "skip",
+        }
+    }
+}
+
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+    /// What to do about a warning or failure.
+    hint: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, outcome: Outcome, detail: impl Into<String>) -> Check {
+        Check { name, outcome, detail: detail.into(), hint: None }
+    }
+
+    fn hint(mut self, hint: impl Into<String>) -> Check {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+fn openssl_check(project_dir: &Path, config: &Config) -> Option<Check> {
+    let usage = openssl::detect(project_dir)?;
+    Some(match (&config.build.openssl, openssl::warning(&usage)) {
+        (Some(mode), _) => Check::new("openssl", Outcome::Pass, format!("`openssl-sys` uses the `{}` OpenSSL set in `BlackMagic.toml`.", mode)),
+        (None, None) => Check::new("openssl", Outcome::Pass, "`openssl-sys` is vendored, so OpenSSL is compiled for musl along with the project."),
+        (None, Some(_)) => Check::new("openssl", Outcome::Warn, "`openssl-sys` is in `Cargo.lock`, and needs an OpenSSL built for musl.")
+            .hint("Build with `--openssl system` for the builder image's, or `--openssl vendored` to compile it from source, or set `openssl` in `[build]`."),
+    })
+}
+
+/// Runs `cmd`, returning its trimmed stdout if it succeeded.
+fn stdout(mut cmd: Command) -> Option<String> {
+    let output = cmd.output().ok().filter(|o| o.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn runtime_checks(runtime: Runtime, checks: &mut Vec<Check>) -> bool {
+    let mut info = runtime.command();
+    info.arg("info").arg("--format").arg(match runtime {
+        Runtime::Docker => "{{.ServerVersion}}|{{.OSType}}",
+        Runtime::Podman => "{{.Version.Version}}|{{.Host.OS}}",
+    });
+    let info = match stdout(info) {
+        Some(i) => i,
+        None => {
+            checks.push(Check::new("daemon", Outcome::Fail, format!("The {} daemon isn't reachable.", runtime.name())).hint(match runtime {
+                Runtime::Docker => "Start Docker Desktop, or the docker service (`sudo systemctl start docker`), and check you're in the `docker` group.",
+                Runtime::Podman => "On macOS and Windows, start the podman VM with `podman machine start`.",
+            }));
+            return false;
+        }
+    };
+    let (version, os) = info.split_once('|').unwrap_or((&info, ""));
+    let location = runtime.remote_host().map(|h| format!(" at {}", h)).unwrap_or_default();
+    checks.push(Check::new("daemon", Outcome::Pass, format!("The {} daemon{} is reachable, version {}.", runtime.name(), location, version)));
+
+    checks.push(if os == "linux" {
+        Check::new("linux containers", Outcome::Pass, "The daemon runs Linux containers.")
+    } else {
+        Check::new("linux containers", Outcome::Fail, format!("The daemon runs `{}` containers, builds need Linux ones.", os))
+            .hint("Switch Docker Desktop to Linux containers, from its tray icon's menu.")
+    });
+    true
+}
+
+/// The free space on the filesystem holding `dir`, from `df`.
+fn free_bytes(dir: &Path) -> Option<u64> {
+    let mut df = Command::new("df");
+    df.arg("-Pk").arg(dir);
+    let output = stdout(df)?;
+    let available: u64 = output.lines().nth(1)?.split_whitespace().nth(3)?.parse().ok()?;
+    Some(available * 1024)
+}
+
+fn disk_check(project_dir: &Path) -> Check {
+    let target = project_dir.join("target");
+    let dir = if target.is_dir() { target } else { project_dir.to_owned() };
+    match free_bytes(&dir) {
+        Some(free) if free >= MIN_FREE_BYTES => {
+            Check::new("disk space", Outcome::Pass, format!("{:.1} GiB free for `{}`.", gib(free), dir.display()))
+        }
+        Some(free) => Check::new("disk space", Outcome::Warn, format!("Only {:.1} GiB free for `{}`.", gib(free), dir.display()))
+            .hint("Free some space, e.g. with `black_magic clean` or `cargo clean`, and `docker system prune` for old images."),
+        None => Check::new("disk space", Outcome::Skip, "`df` isn't available to measure it."),
+    }
+}
+
+fn gib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0 * 1024.0)
+}
+
+fn cargo_home_check(cargo_home: &Path) -> Check {
+    let shared: Vec<&str> = ["registry", "git"].iter().copied().filter(|d| cargo_home.join(d).is_dir()).collect();
+    if shared.is_empty() {
+        Check::new("cargo home", Outcome::Warn, format!("`{}` has no registry or git checkouts to share with builds.", cargo_home.display()))
+            .hint("Builds download every dependency themselves until something (e.g. `cargo fetch`) fills the registry.")
+    } else {
+        Check::new("cargo home", Outcome::Pass, format!("`{}`'s {} will be mounted into builds.", cargo_home.display(), shared.join(" and ")))
+    }
+}
+
+fn builder_check(runtime: Runtime, builder: &Builder, max_age_days: i64) -> (Check, bool) {
+    if !runtime.image_exists(&builder.image).unwrap_or(false) {
+        let check = Check::new("builder image", Outcome::Warn, format!("`{}` hasn't been built yet.", builder.image))
+            .hint("The first build builds it from the base image, which takes a few minutes.");
+        return (check, false);
+    }
+    let check = match builder.age_days(runtime) {
+        Some(age) if max_age_days > 0 && age > max_age_days => {
+            Check::new("builder image", Outcome::Warn, format!("`{}` was built {} days ago.", builder.image, age))
+                .hint("Rebuild it with `--update-builder` (or have builds do it with `--auto-update-builder`), for the base image's latest toolchain and security updates.")
+        }
+        Some(age) => Check::new("builder image", Outcome::Pass, format!("`{}` was built {} days ago.", builder.image, age)),
+        None => Check::new("builder image", Outcome::Pass, format!("`{}` exists.", builder.image)),
+    };
+    (check, true)
+}
+
+fn network_check() -> Check {
+    let mut curl = Command::new("curl");
+    curl.args(["-s", "-o", "/dev/null", "-w", "%{http_code}", "--max-time", "10", REGISTRY_URL]);
+    match curl.output() {
+        Err(_) => Check::new("registry access", Outcome::Skip, "`curl` isn't available to check it."),
+        Ok(o) if String::from_utf8_lossy(&o.stdout).trim() == "200" => {
+            Check::new("registry access", Outcome::Pass, format!("Reached {}.", REGISTRY_URL))
+        }
+        Ok(_) => Check::new("registry access", Outcome::Fail, format!("Unable to reach {}.", REGISTRY_URL))
+            .hint("Behind a proxy, set `HTTPS_PROXY` or `[proxy]` in `BlackMagic.toml`. Without network access, build with `--offline` or `--vendor <dir>`."),
+    }
+}
+
+/// Whether a container can see what's in `host_dir`, mounted from this machine.
+fn sees_mount(runtime: Runtime, image: &str, host_dir: &Path) -> Result<bool, BmError> {
+    let mut run = runtime.command();
+    run.arg("run").arg("--rm").arg("-v").arg(mounts::volume(runtime, &mounts::host_path(host_dir), "/bm_doctor")?)
+        .arg(image).arg("/bin/bash").arg("-c").arg("[ -n \"$(ls -A /bm_doctor)\" ]");
+    Ok(run.output().map(|o| o.status.success()).unwrap_or(false))
+}
+
+fn file_sharing_check(runtime: Runtime, image: &str, name: &'static str, dir: &Path) -> Check {
+    match sees_mount(runtime, image, dir) {
+        Ok(true) => Check::new(name, Outcome::Pass, format!("Containers can see `{}`.", dir.display())),
+        Ok(false) => Check::new(name, Outcome::Fail, format!("`{}` is empty inside containers.", dir.display()))
+            .hint(format!(
+                "Share `{}` with containers in the runtime's settings (Docker Desktop: Settings, Resources, File sharing), \
+                or build with `--layered`.", mounts::host_path(dir).display())),
+        Err(e) => Check::new(name, Outcome::Skip, e.to_string()),
+    }
+}
+
+/// Docker Desktop without its WSL 2 backend only shares the drives picked in its settings, and builds need the project's.
+fn drive_check(runtime: Runtime, image: &str, project_dir: &Path) -> Option<Check> {
+    if mounts::Host::detect() != mounts::Host::Windows {
+        return None;
+    }
+    let (letter, _) = mounts::drive(mounts::host_path(project_dir).to_str()?)?;
+    let drive = format!("{}:", letter.to_ascii_uppercase());
+    Some(match sees_mount(runtime, image, &PathBuf::from(format!("{}\\", drive))) {
+        Ok(true) => Check::new("project drive", Outcome::Pass, format!("The `{}` drive is shared with containers.", drive)),
+        Ok(false) => Check::new("project drive", Outcome::Fail, format!("The `{}` drive isn't shared with containers.", drive))
+            .hint(format!(
+                "Share `{}` in Docker Desktop's settings (Resources, File sharing), or turn on its WSL 2 backend, which shares \
+                every drive.", drive)),
+        Err(e) => Check::new("project drive", Outcome::Skip, e.to_string()),
+    })
+}
+
+pub fn doctor(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = mounts::current_dir()?;
+    let mut checks = Vec::new();
+
+    let runtime = match Runtime::detect(matches.value_of("RUNTIME")) {
+        Ok(r) => {
+            checks.push(Check::new("runtime", Outcome::Pass, r.version().unwrap_or_else(|| r.name().to_owned())));
+            Some(r)
+        }
+        Err(e) => {
+            checks.push(Check::new("runtime", Outcome::Fail, e.to_string())
+                .hint("Install Docker (https://docs.docker.com/get-docker/) or podman (https://podman.io/docs/installation)."));
+            None
+        }
+    };
+    let daemon = runtime.filter(|&r| runtime_checks(r, &mut checks));
+
+    checks.push(disk_check(&current_dir));
+    let cargo_home = home::cargo_home().ok();
+    if let Some(cargo_home) = &cargo_home {
+        checks.push(cargo_home_check(cargo_home));
+    }
+
+    let config = Config::load(&current_dir).unwrap_or_default();
+    let builder = Builder::new(
+        Arch::from_name(matches.value_of("ARCH").unwrap()), false, config.builder.image.as_deref(), config.builder.tag.as_deref())
+        .pinned(&config.builder.pins)?
+        .customized(&config.builder.packages, config.builder.setup_script.as_deref())?;
+    let builder_exists = match daemon {
+        Some(runtime) => {
+            let (check, exists) = builder_check(runtime, &builder, config.builder.max_age_days.unwrap_or(builder::DEFAULT_MAX_AGE_DAYS));
+            checks.push(check);
+            exists
+        }
+        None => {
+            checks.push(Check::new("builder image", Outcome::Skip, "There's no daemon to check it with."));
+            false
+        }
+    };
+    checks.push(network_check());
+    checks.extend(openssl_check(&current_dir, &config));
+
+    // Docker Desktop, colima and podman machine only share some of the host's directories with their VM.
+    let sharing = [("project mount", Some(current_dir.clone()).filter(|d| d.join("Cargo.toml").exists())), ("cargo home mount", cargo_home.map(|c| c.join("registry")))];
+    for (name, dir) in sharing {
+        let dir = match dir.filter(|d| d.is_dir()) {
+            Some(d) => d,
+            None => continue,
+        };
+        checks.push(match daemon {
+            Some(runtime) if runtime.remote_host().is_some() => {
+                Check::new(name, Outcome::Skip, "The daemon is remote, builds send the project to it with `docker build` instead.")
+            }
+            Some(runtime) if builder_exists => file_sharing_check(runtime, &builder.image, name, &dir),
+            _ => Check::new(name, Outcome::Skip, "Checked once the builder image exists."),
+        });
+    }
+    if let Some(runtime) = daemon.filter(|r| r.remote_host().is_none() && builder_exists) {
+        checks.extend(drive_check(runtime, &builder.image, &current_dir));
+    }
+
+    let failed = checks.iter().filter(|c| c.outcome == Outcome::Fail).count();
+    if output::is_json() {
+        output::emit("doctor", json!({
+            "checks": checks.iter().map(|c| json!({
+                "name": c.name, "outcome": c.outcome.name(), "detail": c.detail, "hint": c.hint,
+            })).collect::<Vec<_>>(),
+            "failed": failed,
+        }));
+    } else {
+        for check in &checks {
+            status!("[{}] {}: {}", check.outcome.name(), check.name, check.detail);
+            if let Some(hint) = check.hint.as_ref().filter(|_| check.outcome != Outcome::Pass) {
+                status!("       {}", hint);
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        1 => Err(BmError::Environment("1 check failed, see above for how to fix it.".to_owned())),
+        n => Err(BmError::Environment(format!("{} checks failed, see above for how to fix them.", n))),
+    }
+}
@@ -0,0 +1,92 @@
+//! `--lambda --docker`: the lambda zip and the docker image from the same executable, e.g. for a service deployed both as a
+//! Lambda function and on Fargate.
+//!
+//! The lambda build runs first, compiling the executable, then the docker build packages it from the same, shared, cache
+//! volume, without compiling anything again (see `bins`, which does the same for several executables). Both run here, one
+//! after the other, from the same options: those for only one of them are only given to that one, and `--test` and
+//! `--clippy` only run for the first, as the second builds the same source.
+
+use crate::api::BuildConfig;
+use crate::api::Mode;
+use crate::ci;
+use crate::error::BmError;
+use crate::output;
+use serde_json::json;
+use std::time::Instant;
+
+/// The lambda build's options, without those only docker builds take.
+fn lambda(options: &BuildConfig) -> BuildConfig {
+    let mut lambda = options.clone();
+    lambda.mode = Mode::Lambda;
+    lambda.no_image = false;
+    lambda.debug_image = false;
+    lambda.with_ca_certs = false;
+    lambda.with_tzdata = false;
+    lambda.tag_git = false;
+    lambda.integration_test = false;
+    lambda.sbom_label = false;
+    lambda.user = None;
+    lambda.diff_against = None;
+    lambda.export_oci = None;
+    lambda.load_into = None;
+    lambda.push.clear();
+    lambda.tags.clear();
+    lambda.ecr = None;
+    lambda.dockerfile_template = None;
+    lambda.base = None;
+    lambda.entrypoint = None;
+    lambda.cmd = None;
+    lambda.expose.clear();
+    lambda.env.clear();
+    lambda
+}
+
+/// The docker build's options, without those only lambda builds take, or the checks the lambda build already ran.
+fn docker(options: &BuildConfig) -> BuildConfig {
+    let mut docker = options.clone();
+    docker.mode = Mode::Docker;
+    docker.strict_size = false;
+    docker.emit_sam = false;
+    docker.includes.clear();
+    docker.s3 = None;
+    docker.lambda_runtime = None;
+    docker.compression = None;
+    docker.deploy = None;
+    docker.test = false;
+    docker.clippy = false;
+    docker
+}
+
+/// Runs a `--lambda --docker` build.
+pub fn build(matches: &clap::ArgMatches, started: Instant) -> Result<(), BmError> {
+    let conflicting = ["NO_SIDE_EFFECTS", "DRY_RUN", "SHELL", "DEBUG_SHELL"];
+    if conflicting.iter().any(|a| matches.occurrences_of(a) > 0) {
+        return Err(BmError::Environment(
+            "`--lambda --docker` runs a build for each, so it can't be used with `--no-side-effects`, `--dry-run`, `--shell` or `--debug-shell`.".to_owned()));
+    } else if matches.value_of("LIBC") == Some("gnu") {
+        return Err(BmError::Environment(
+            "`--lambda --docker` compiles once for both, but `--libc gnu` only applies to docker builds. Build them one at a time.".to_owned()));
+    }
+
+    let options = BuildConfig::from_matches(matches)?;
+    let bm_dir = options.project_dir.join("target").join("black_magic");
+    let mut records = Vec::new();
+    for (mode, options) in [("lambda", lambda(&options)), ("docker", docker(&options))] {
+        let built = crate::build(&options, Instant::now())?
+            .ok_or_else(|| BmError::Packaging(format!("The {} build finished without an artifact.", mode)))?;
+        records.push((mode, built.record));
+    }
+    let outputs: Vec<(String, &str)> = records.iter()
+        .flat_map(|(mode, record)| ["artifact", "sha256", "image"].iter().filter_map(move |o| Some((format!("{}_{}", mode, o), record[*o].as_str()?))))
+        .collect();
+    ci::outputs(&bm_dir, &outputs.iter().map(|(name, value)| (name.as_str(), *value)).collect::<Vec<_>>());
+
+    if output::is_json() {
+        let mut done = json!({ "duration_seconds": started.elapsed().as_secs_f64() });
+        for (mode, record) in records {
+            done[mode] = record;
+        }
+        output::emit("done", done);
+    }
+    Ok(())
+}
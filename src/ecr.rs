@@ -0,0 +1,94 @@
+//! `black_magic publish-ecr --repo <name>`: the whole flow of publishing the project's image to ECR, in one command, for
+//! Lambda container images and ECS services.
+//!
+//! It resolves the AWS account and region (from `--region` and `--aws-profile`, `[aws]` in `BlackMagic.toml`, or the AWS
+//! configuration, see `aws`), then runs a docker build with `--ecr`, which logs docker into the registry, creates the
+//! repository if it doesn't exist yet, and pushes the image tagged with the commit (see `--tag-git`) and `latest`. Options
+//! for the build go after `--`, e.g. `black_magic publish-ecr --repo api -- --base distroless`.
+//!
+//! It ends with the image's URI by digest, e.g. `123456789012.dkr.ecr.eu-west-1.amazonaws.com/api@sha256:…`, which unlike a
+//! tag always refers to this image: in the `done` JSON record as `image_uri`, the `PUBLISHED` marker, and with `--ci`, as
+//! the `image_uri` output.
+
+use crate::aws::Aws;
+use crate::ci;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::registry;
+use crate::tags;
+use clap::ArgMatches;
+use serde_json::json;
+use std::env;
+use std::process::Command;
+use std::process::Stdio;
+
+/// Runs `black_magic publish-ecr`.
+pub fn publish(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+    let aws = Aws::new(
+        matches.value_of("REGION").map(|r| r.to_owned()).or_else(|| config.aws.region.clone()),
+        matches.value_of("AWS_PROFILE").map(|p| p.to_owned()).or_else(|| config.aws.profile.clone()),
+    );
+    let repository = matches.value_of("REPO").unwrap();
+    if registry::split_tag(repository).0 != repository {
+        return Err(BmError::Environment(format!(
+            "`--repo {}` has a tag, but `publish-ecr` tags the image itself, with the commit and `latest`. Pass just the repository.", repository)));
+    }
+
+    status!("Resolving the AWS account and region...");
+    let ecr_registry = aws.ecr_registry().map_err(|e| BmError::Publish(format!("Unable to find the ECR registry: {}", e)))?;
+    status!("Publishing to {}/{}.", ecr_registry, repository);
+
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    let mut cmd = Command::new(exe);
+    cmd.arg("--docker").arg("--ecr").arg(repository).arg("--tag").arg("latest").arg("--porcelain");
+    if tags::short_hash(&current_dir).is_some() {
+        cmd.arg("--tag-git");
+    } else {
+        status!("The project isn't in a git repository with a commit, so the image is only tagged `latest`.");
+    }
+    for (arg, option) in [("REGION", "--region"), ("AWS_PROFILE", "--aws-profile"), ("RUNTIME", "--runtime")] {
+        if let Some(value) = matches.value_of(arg) {
+            cmd.arg(option).arg(value);
+        }
+    }
+    cmd.args(matches.values_of("BUILD_ARGS").into_iter().flatten());
+    // The build's progress goes straight to stderr, its markers are read here.
+    let built = cmd.stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| BmError::Environment(format!("Unable to run the build: {}", e)))?;
+    let markers = String::from_utf8_lossy(&built.stdout);
+    if output::is_porcelain() {
+        markers.lines().filter(|l| !l.starts_with("BEGIN_BUILD") && !l.starts_with("END_BUILD")).for_each(|l| println!("{}", l));
+    }
+    if !built.status.success() {
+        return Err(BmError::from_exit_code(built.status.code(), "The build failed, see above.".to_owned()));
+    }
+
+    let remotes: Vec<&str> = markers.lines().filter_map(|l| l.strip_prefix("PUSHED remote=")).collect();
+    // The commit's tag, which isn't moved by later builds, unless there's only `latest`.
+    let tag = remotes.iter()
+        .map(|r| registry::split_tag(r).1)
+        .find(|t| *t != "latest")
+        .unwrap_or("latest");
+    let digest = aws.ecr_image_digest(repository, tag).map_err(|e| BmError::Publish(format!("Unable to find the pushed image's digest: {}", e)))?;
+    let image_uri = format!("{}/{}@{}", ecr_registry, repository, digest);
+    status!("Image URI: {}", image_uri);
+    output::marker("PUBLISHED", &[("uri", &image_uri)]);
+    ci::outputs(&current_dir.join("target").join("black_magic"), &[("image_uri", &image_uri), ("digest", &digest)]);
+
+    if output::is_json() {
+        output::emit("done", json!({
+            "repository": format!("{}/{}", ecr_registry, repository),
+            "tags": remotes.iter().map(|r| registry::split_tag(r).1).collect::<Vec<_>>(),
+            "digest": digest,
+            "image_uri": image_uri,
+        }));
+    }
+    Ok(())
+}
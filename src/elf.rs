@@ -0,0 +1,139 @@
+//! Checking the compiled executables will run where they're going, straight after compiling, so one that would only fail
+//! once it's deployed stops the build before anything's packaged, pushed or deployed. The classic one is an executable
+//! dynamically linked against glibc, which Lambda's OS-only runtimes and scratch images don't have.
+//!
+//! The build container (or this machine, for the backends compiling on the host) dumps each executable's ELF header, program
+//! headers and dynamic section with `readelf` into `target/black_magic/<artifact>.elf`. `ldd` would have to run the loader, which it can't for another architecture. Each
+//! executable has to:
+//! - be an ELF executable for the target's architecture, e.g. `AArch64` for `--arch aarch64`
+//! - be statically linked, for musl targets and Lambda zips: no interpreter and no shared libraries, though a static-PIE has a
+//!   dynamic section of its own. Images on `alpine`, which has musl's loader, or on your own base, can link against musl's libc.
+//! - only need glibc's own libraries, for `--libc gnu`
+//!
+//! `allowed_libraries` in `[build]` allows others, for bases that have them:
+//! ```toml
+//! [build]
+//! allowed_libraries = ["libssl.so.3", "libcrypto.so.3"]
+//! ```
+//! With `--lambda-runtime`, the zip is checked against the runtime instead (see `lambda_runtime`), so only the architecture
+//! is checked here. A builder image (or host) without `readelf` can't dump anything, so the build only warns about what the
+//! packaged executable's interpreter says.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// What each executable's dump starts with, followed by its path.
+const SEPARATOR: &str = "=== ";
+
+/// The libraries glibc itself is made of, which any glibc base has.
+const GLIBC: &[&str] = &[
+    "libc.so.6",
+    "libm.so.6",
+    "libpthread.so.0",
+    "libdl.so.2",
+    "librt.so.1",
+    "libutil.so.1",
+    "libgcc_s.so.1",
+    "ld-linux-x86-64.so.2",
+    "ld-linux-aarch64.so.1",
+];
+
+/// musl's libc, as an executable linked against it needs it.
+const MUSL: &[&str] = &["libc.so", "libc.musl-x86_64.so.1", "libc.musl-aarch64.so.1"];
+
+/// How the executables can be linked.
+pub struct Allowed {
+    /// Whether they can have an interpreter, rather than being static (or static-PIE).
+    pub interpreter: bool,
+    /// The shared libraries they can need.
+    pub libraries: Vec<String>,
+}
+
+impl Allowed {
+    /// For glibc executables if `gnu`, or musl ones, which only link dynamically if `dynamic`, as well as `extra`.
+    pub fn new(gnu: bool, dynamic: bool, extra: &[String]) -> Allowed {
+        let libc: &[&str] = match (gnu, dynamic) {
+            (true, _) => GLIBC,
+            (false, true) => MUSL,
+            (false, false) => &[],
+        };
+        Allowed {
+            interpreter: gnu || dynamic,
+            libraries: libc.iter().map(|l| l.to_string()).chain(extra.iter().cloned()).collect(),
+        }
+    }
+}
+
+/// Shell command dumping the `executables` (paths in the build container, already shell quoted) into `file`, relative to
+/// the project, or removing it if there's no `readelf`.
+pub fn dump_cmd(executables: &[String], file: &str) -> String {
+    format!(
+        " && if command -v readelf > /dev/null; then for f in {}; do echo \"{}$f\"; readelf -h -l -d --wide \"$f\" 2>&1 || true; done > {file}; else rm -f {file}; fi",
+        executables.join(" "), SEPARATOR, file = file)
+}
+
+/// Dumps the `executables` (their paths in the artifact, and where they are on this machine) as `dump_cmd` does, for the
+/// backends compiling on the host, or nothing if there's no `readelf`.
+pub fn dump(executables: &[(String, PathBuf)]) -> Option<String> {
+    let mut dump = String::new();
+    for (path, file) in executables {
+        let dumped = Command::new("readelf").args(["-h", "-l", "-d", "--wide"]).arg(file).output().ok()?;
+        dump.push_str(&format!("{}{}\n", SEPARATOR, path));
+        dump.push_str(&String::from_utf8_lossy(&dumped.stdout));
+        dump.push_str(&String::from_utf8_lossy(&dumped.stderr));
+    }
+    Some(dump)
+}
+
+/// `readelf`'s name for the architecture of `target`, if it's one it's known for.
+pub fn machine(target: &str) -> Option<&'static str> {
+    match target.split('-').next().unwrap_or("") {
+        "x86_64" => Some("Advanced Micro Devices X86-64"),
+        "aarch64" => Some("AArch64"),
+        "powerpc64" | "powerpc64le" => Some("PowerPC64"),
+        "s390x" => Some("IBM S/390"),
+        a if a.starts_with("arm") || a.starts_with("thumb") => Some("ARM"),
+        a if a.starts_with("riscv64") => Some("RISC-V"),
+        a if a.starts_with('i') && a.ends_with("86") => Some("Intel 80386"),
+        _ => None,
+    }
+}
+
+/// What's wrong with the executables in `dump` for `target`, linked as `allowed` says, or just their architecture without it.
+pub fn check(dump: &str, target: &str, allowed: Option<&Allowed>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for section in dump.split(SEPARATOR).filter(|s| !s.trim().is_empty()) {
+        let (path, elf) = section.split_once('\n').unwrap_or((section, ""));
+        let path = path.trim();
+        let found = match elf.lines().find_map(|l| l.trim_start().strip_prefix("Machine:")) {
+            Some(m) => m.trim(),
+            None => {
+                problems.push(format!("`{}` isn't an ELF executable.", path));
+                continue;
+            }
+        };
+        if let Some(expected) = machine(target).filter(|m| *m != found) {
+            problems.push(format!("`{}` is built for {}, not {}, which `{}` is.", path, found, expected, target));
+        }
+
+        let allowed = match allowed {
+            Some(a) => a,
+            None => continue,
+        };
+        let interpreter = elf.lines().find_map(|l| l.split("[Requesting program interpreter: ").nth(1)).map(|i| i.trim().trim_end_matches(']'));
+        if let Some(interpreter) = interpreter.filter(|_| !allowed.interpreter) {
+            problems.push(format!("`{}` is dynamically linked, with {}, so it isn't static.", path, interpreter));
+        }
+        let needed: Vec<&str> = elf
+            .lines()
+            .filter(|l| l.contains("(NEEDED)"))
+            .filter_map(|l| l.split("Shared library: [").nth(1))
+            .map(|l| l.trim().trim_end_matches(']'))
+            .filter(|l| !allowed.libraries.iter().any(|a| a == l))
+            .collect();
+        if !needed.is_empty() {
+            problems.push(format!("`{}` needs {}, which won't be there.", path, needed.join(", ")));
+        }
+    }
+    problems
+}
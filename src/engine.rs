@@ -0,0 +1,772 @@
+//! docker's Engine API, for when the docker CLI isn't installed but its daemon is running, as in containers with the
+//! daemon's socket mounted and some CI runners.
+//!
+//! Everything black_magic does with a container runtime is a command, which is what `--dry-run` prints and what failed
+//! builds say to run again. So without the CLI, black_magic runs itself in its place (see `Runtime::command`), as
+//! `black_magic engine <docker's arguments>`, which does what the CLI would through the API, with bollard. That covers what
+//! builds need, with the flags black_magic passes: `image inspect`, `build`, `run` and `logs`, and `pull`, `rm`, `info` and
+//! `--version` around them. Anything else (e.g. `volume`, `cp`, `push`, or `-t` for a shell) says it needs the CLI. The
+//! daemon is `DOCKER_HOST`'s, otherwise the local socket, as for the CLI.
+//!
+//! Builds stream their output as they go, and failures exit as the CLI's do, so they're told apart the same way: `run` exits
+//! with the container's exit code, or 125 when the daemon couldn't run it, and anything else with 1, printing the daemon's
+//! message (or that it isn't reachable).
+
+use crate::dockerignore::Ignore;
+use crate::registry;
+use bollard::container::LogOutput;
+use bollard::errors::Error;
+use bollard::models::ContainerCreateBody;
+use bollard::models::HostConfig;
+use bollard::models::PortBinding;
+use bollard::query_parameters::AttachContainerOptionsBuilder;
+use bollard::query_parameters::BuildImageOptionsBuilder;
+use bollard::query_parameters::CreateContainerOptionsBuilder;
+use bollard::query_parameters::CreateImageOptionsBuilder;
+use bollard::query_parameters::LogsOptionsBuilder;
+use bollard::query_parameters::RemoveContainerOptionsBuilder;
+use bollard::query_parameters::TagImageOptionsBuilder;
+use bollard::Docker;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+/// The argument black_magic runs itself with in place of the docker CLI.
+pub const SUBCOMMAND: &str = "engine";
+
+/// The CLI doesn't time requests out, and builds can take hours. `--timeout` bounds them, see `bounds`.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// What `Runtime::command` runs instead of `docker`.
+pub fn command() -> Command {
+    let mut cmd = Command::new(env::current_exe().unwrap_or_else(|_| PathBuf::from("black_magic")));
+    cmd.arg(SUBCOMMAND);
+    cmd
+}
+
+/// The daemon's version, e.g. `28.2.2`, if it answers.
+pub fn version() -> Option<String> {
+    call(|docker| async move { docker.version().await }).ok()?.version
+}
+
+/// Connects to the daemon, and runs `request` to completion.
+fn call<T, F>(request: impl FnOnce(Docker) -> F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let mut docker = Docker::connect_with_defaults()?;
+    docker.set_timeout(REQUEST_TIMEOUT);
+    runtime.block_on(request(docker))
+}
+
+/// What the CLI prints for a failed request.
+fn describe(error: Error) -> String {
+    match error {
+        Error::DockerResponseServerError { message, .. } => format!("Error response from daemon: {}", message),
+        Error::DockerStreamError { error } => error,
+        Error::SocketNotFoundError(socket) => format!("Cannot connect to the Docker daemon at unix://{}. Is the docker daemon running?", socket),
+        Error::IOError { err } => format!("Cannot connect to the Docker daemon: {}. Is the docker daemon running?", err),
+        e => e.to_string(),
+    }
+}
+
+/// Does what `docker <args>` would, returning the exit code the CLI would exit with.
+pub fn cli(args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let result = match args.as_slice() {
+        ["--version"] | ["-v"] => call(|docker| async move { docker.version().await }).map_err(describe).map(|v| {
+            println!(
+                "Docker version {}, build {} (the daemon's, through its Engine API: the docker CLI isn't installed)",
+                v.version.unwrap_or_default(), v.git_commit.unwrap_or_default());
+            0
+        }),
+        ["image", "inspect", rest @ ..] | ["inspect", rest @ ..] => inspect(rest),
+        ["image", "build", rest @ ..] | ["build", rest @ ..] => build(rest),
+        ["image", "pull", rest @ ..] | ["pull", rest @ ..] => pull_cli(rest),
+        ["container", "run", rest @ ..] | ["run", rest @ ..] => run(rest),
+        ["container", "logs", rest @ ..] | ["logs", rest @ ..] => logs(rest),
+        ["container", "rm", rest @ ..] | ["rm", rest @ ..] => rm(rest),
+        ["info", rest @ ..] => info(rest),
+        _ => Err(format!(
+            "`docker {}` needs the docker CLI, which isn't installed. Without it, black_magic only builds, runs and inspects images, \
+            and reads containers' logs, through the daemon's Engine API.",
+            args.iter().take(2).copied().collect::<Vec<&str>>().join(" "))),
+    };
+    match result {
+        Ok(code) => code,
+        Err(message) => {
+            eprintln!("{}", message);
+            // Like the CLI, `run` tells its own failures apart from the container's.
+            if matches!(args.as_slice(), ["run", ..] | ["container", "run", ..]) { 125 } else { 1 }
+        }
+    }
+}
+
+/// Flags, with their values (empty for those without), and the arguments following them.
+type Parsed<'a> = (Vec<(&'a str, &'a str)>, Vec<&'a str>);
+
+/// Splits `args` into flags (with their values, for those in `valued`) and what follows them, in order.
+fn flags<'a>(args: &[&'a str], valued: &[&str]) -> Result<Parsed<'a>, String> {
+    let mut flags = Vec::new();
+    let mut rest = args.iter().copied();
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            return Ok((flags, std::iter::once(arg).chain(rest).collect()));
+        }
+        let (flag, value) = match arg.split_once('=').filter(|_| arg.starts_with("--")) {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (arg, None),
+        };
+        if valued.contains(&flag) {
+            let value = value.or_else(|| rest.next()).ok_or_else(|| format!("flag needs an argument: {}", flag))?;
+            flags.push((flag, value));
+        } else if value.is_none() {
+            flags.push((flag, ""));
+        } else {
+            return Err(format!("flag doesn't take a value: {}", flag));
+        }
+    }
+    Ok((flags, Vec::new()))
+}
+
+/// The value of `--format`, if it's in `flags`.
+fn format<'a>(flags: &[(&str, &'a str)]) -> Option<&'a str> {
+    flags.iter().find(|(f, _)| *f == "--format" || *f == "-f").map(|(_, v)| *v)
+}
+
+/// Prints each of `values`, as `--format` says, otherwise as a JSON array.
+fn print_formatted(format: Option<&str>, values: &[Value]) -> Result<(), String> {
+    match format {
+        Some(format) => {
+            for value in values {
+                println!("{}", render(format, value)?);
+            }
+        }
+        None => println!("{}", serde_json::to_string_pretty(values).unwrap_or_default()),
+    }
+    Ok(())
+}
+
+/// `value` as JSON, with the field names the CLI's templates use.
+fn json(value: impl Serialize) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+/// `image inspect [--format <template>] <image>...`, and `inspect`, which also finds containers.
+fn inspect(args: &[&str]) -> Result<i32, String> {
+    let (flags, images) = flags(args, &["--format", "-f", "--type"])?;
+    let format = format(&flags);
+    let found = call(|docker| async move {
+        let mut found = Vec::new();
+        for image in &images {
+            match docker.inspect_image(image).await {
+                Ok(inspected) => found.push(Ok(json(inspected))),
+                Err(Error::DockerResponseServerError { status_code: 404, .. }) => match docker.inspect_container(image, None).await {
+                    Ok(inspected) => found.push(Ok(json(inspected))),
+                    Err(Error::DockerResponseServerError { status_code: 404, .. }) => found.push(Err(format!("Error: No such object: {}", image))),
+                    Err(e) => return Err(e),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(found)
+    }).map_err(describe)?;
+    let (values, missing): (Vec<_>, Vec<_>) = found.into_iter().partition(|f| f.is_ok());
+    print_formatted(format, &values.into_iter().filter_map(Result::ok).collect::<Vec<Value>>())?;
+    for error in missing.iter().filter_map(|m| m.as_ref().err()) {
+        eprintln!("{}", error);
+    }
+    Ok(if missing.is_empty() { 0 } else { 1 })
+}
+
+/// `info [--format <template>]`.
+fn info(args: &[&str]) -> Result<i32, String> {
+    let (flags, _) = flags(args, &["--format", "-f"])?;
+    let info = call(|docker| async move { docker.info().await }).map_err(describe)?;
+    match format(&flags) {
+        Some(format) => println!("{}", render(format, &json(info))?),
+        None => println!("{}", serde_json::to_string_pretty(&json(info)).unwrap_or_default()),
+    }
+    Ok(0)
+}
+
+/// Pulls `image`, for `platform` if it's set, printing its progress on stderr (stdout is for what's asked for).
+async fn pull(docker: &Docker, image: &str, platform: Option<&str>) -> Result<(), Error> {
+    let (repository, tag) = registry::split_tag(image);
+    let mut options = CreateImageOptionsBuilder::default().from_image(repository).tag(tag);
+    if let Some(p) = platform {
+        options = options.platform(p);
+    }
+    let mut pulled = docker.create_image(Some(options.build()), None, None);
+    while let Some(progress) = pulled.next().await {
+        let progress = progress?;
+        if let Some(message) = progress.error_detail.and_then(|e| e.message) {
+            return Err(Error::DockerStreamError { error: message });
+        }
+        // Layers' download and extraction progress goes on a terminal's progress bars, which this doesn't have.
+        let ongoing = progress.progress_detail.as_ref().and_then(|d| d.current).is_some();
+        if let Some(status) = progress.status.filter(|_| !ongoing) {
+            eprintln!("{}{}", progress.id.map(|i| format!("{}: ", i)).unwrap_or_default(), status);
+        }
+    }
+    Ok(())
+}
+
+/// `pull [--platform <platform>] <image>`.
+fn pull_cli(args: &[&str]) -> Result<i32, String> {
+    let (flags, images) = flags(args, &["--platform"])?;
+    let image = match images.as_slice() {
+        [image] => *image,
+        _ => return Err("`pull` takes one image.".to_owned()),
+    };
+    let platform = flags.iter().find(|(f, _)| *f == "--platform").map(|(_, v)| *v);
+    call(|docker| async move { pull(&docker, image, platform).await }).map_err(describe)?;
+    println!("{}", image);
+    Ok(0)
+}
+
+/// `build` with the flags black_magic builds images with, from a context dir, which is sent without what its
+/// `.dockerignore` leaves out. Builds with the daemon's own builder, as BuildKit's sessions (for `--secret` and `RUN --mount`)
+/// need the CLI.
+fn build(args: &[&str]) -> Result<i32, String> {
+    let valued = ["-t", "--tag", "-f", "--file", "--platform", "--cache-from", "--build-arg", "--label", "--network", "--add-host", "--target"];
+    let (flags, context) = flags(args, &valued)?;
+    let context = match context.as_slice() {
+        [context] => Path::new(context),
+        _ => return Err("`build` takes one context dir.".to_owned()),
+    };
+    if env::var("DOCKER_BUILDKIT").is_ok_and(|b| b == "1") {
+        return Err("This build needs BuildKit, for its `RUN --mount` steps, which black_magic only reaches through the docker CLI.".to_owned());
+    }
+    let mut options = BuildImageOptionsBuilder::default().rm(true);
+    let mut tags = Vec::new();
+    let mut dockerfile = "Dockerfile".to_owned();
+    let (mut cache_from, mut build_args, mut labels, mut hosts) = (Vec::new(), HashMap::new(), HashMap::new(), Vec::new());
+    let mut quiet = false;
+    for (flag, value) in flags {
+        match flag {
+            "-t" | "--tag" => tags.push(value),
+            "-f" | "--file" => dockerfile = in_context(context, value)?,
+            "--platform" => options = options.platform(value),
+            "--target" => options = options.target(value),
+            "--network" => options = options.networkmode(value),
+            "--add-host" => hosts.push(value),
+            "--cache-from" => cache_from.push(value),
+            "--label" => {
+                let (key, value) = value.split_once('=').unwrap_or((value, ""));
+                labels.insert(key, value);
+            }
+            // Without a value, it's the variable's, if it's set.
+            "--build-arg" => match value.split_once('=') {
+                Some((key, value)) => {
+                    build_args.insert(key.to_owned(), value.to_owned());
+                }
+                None => {
+                    if let Ok(v) = env::var(value) {
+                        build_args.insert(value.to_owned(), v);
+                    }
+                }
+            },
+            "--pull" => options = options.pull("1"),
+            "--no-cache" => options = options.nocache(true),
+            "-q" | "--quiet" => quiet = true,
+            flag => return Err(unsupported("build", flag)),
+        }
+    }
+    options = options.dockerfile(&dockerfile).cachefrom(&cache_from).buildargs(&build_args).labels(&labels);
+    if !hosts.is_empty() {
+        options = options.extrahosts(&hosts.join(","));
+    }
+    if let Some(tag) = tags.first() {
+        options = options.t(tag);
+    }
+    let tar = context_tar(context, &dockerfile).map_err(|e| format!("Unable to read the build context `{}`: {}", context.display(), e))?;
+    let options = options.build();
+
+    let id = call(|docker| async move {
+        let mut built = docker.build_image(options, None, Some(bollard::body_full(Bytes::from(tar))));
+        let mut id = None;
+        while let Some(progress) = built.next().await {
+            let progress = progress?;
+            if let Some(message) = progress.error_detail.and_then(|e| e.message) {
+                return Err(Error::DockerStreamError { error: message });
+            }
+            if let Some(stream) = progress.stream.filter(|_| !quiet) {
+                print!("{}", stream);
+                let _ = io::stdout().flush();
+            }
+            if let Some(built_id) = progress.aux.and_then(|a| a.id) {
+                id = Some(built_id);
+            }
+        }
+        let id = id.ok_or_else(|| Error::DockerStreamError { error: "The daemon didn't say what it built.".to_owned() })?;
+        // The API takes one tag per build.
+        for tag in tags.iter().skip(1) {
+            let (repository, tag) = registry::split_tag(tag);
+            docker.tag_image(&id, Some(TagImageOptionsBuilder::default().repo(repository).tag(tag).build())).await?;
+        }
+        Ok(id)
+    }).map_err(describe)?;
+    if quiet {
+        println!("{}", id);
+    }
+    Ok(0)
+}
+
+/// `dockerfile`'s path in `context`, where the API has to find it.
+fn in_context(context: &Path, dockerfile: &str) -> Result<String, String> {
+    let outside = || format!("The Dockerfile `{}` has to be in the build context, `{}`, without the docker CLI.", dockerfile, context.display());
+    let context = context.canonicalize().map_err(|_| outside())?;
+    let dockerfile = Path::new(dockerfile).canonicalize().map_err(|_| outside())?;
+    dockerfile.strip_prefix(&context).ok().and_then(|p| p.to_str()).map(|p| p.replace('\\', "/")).ok_or_else(outside)
+}
+
+/// The build context in `dir`, as a tar, leaving out what its `.dockerignore` does, apart from the Dockerfile and the
+/// `.dockerignore` itself, which docker always sends.
+fn context_tar(dir: &Path, dockerfile: &str) -> io::Result<Vec<u8>> {
+    let ignore = Ignore::context(dir);
+    let mut tar = tar::Builder::new(Vec::new());
+    tar.follow_symlinks(false);
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(relative) = dirs.pop() {
+        for entry in fs::read_dir(dir.join(&relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if path == Path::new(dockerfile) || path == Path::new(".dockerignore") || !ignore.ignores(&path) {
+                tar.append_path_with_name(entry.path(), &path)?;
+            }
+        }
+    }
+    tar.into_inner()
+}
+
+/// What `docker run`'s arguments ask for.
+#[derive(Default, Debug)]
+struct Run {
+    remove: bool,
+    detach: bool,
+    name: Option<String>,
+    platform: Option<String>,
+    image: String,
+    cmd: Vec<String>,
+    config: ContainerCreateBody,
+    host: HostConfig,
+}
+
+impl Run {
+    /// Reads `run`'s arguments: the flags black_magic runs containers with, the image, then its command.
+    fn parse(args: &[&str]) -> Result<Run, String> {
+        let valued = [
+            "-v", "--volume", "-e", "--env", "-w", "--workdir", "--name", "--platform", "-u", "--user", "--entrypoint", "--network",
+            "--net", "--add-host", "--dns", "--cpus", "-m", "--memory", "--tmpfs", "-p", "--publish", "-l", "--label",
+        ];
+        let (flags, rest) = flags(args, &valued)?;
+        let (image, cmd) = rest.split_first().ok_or_else(|| "`run` needs an image.".to_owned())?;
+        let mut run = Run { image: image.to_string(), cmd: cmd.iter().map(|a| a.to_string()).collect(), ..Run::default() };
+        let (config, host) = (&mut run.config, &mut run.host);
+        for (flag, value) in flags {
+            let value = value.to_owned();
+            match flag {
+                "--rm" => run.remove = true,
+                "-d" | "--detach" => run.detach = true,
+                // Nothing's sent to its input, as with `Command::output`, which black_magic runs it with.
+                "-i" | "--interactive" => {}
+                "--name" => run.name = Some(value),
+                "--platform" => run.platform = Some(value),
+                "-w" | "--workdir" => config.working_dir = Some(value),
+                "-u" | "--user" => config.user = Some(value),
+                "--entrypoint" => config.entrypoint = Some(vec![value]),
+                // Without a value, it's the variable's, if it's set.
+                "-e" | "--env" if value.contains('=') => config.env.get_or_insert_with(Vec::new).push(value),
+                "-e" | "--env" => {
+                    if let Ok(v) = env::var(&value) {
+                        config.env.get_or_insert_with(Vec::new).push(format!("{}={}", value, v));
+                    }
+                }
+                "-l" | "--label" => {
+                    let (key, value) = value.split_once('=').unwrap_or((&value, ""));
+                    config.labels.get_or_insert_with(HashMap::new).insert(key.to_owned(), value.to_owned());
+                }
+                // A container path alone is an anonymous volume, anything else a bind mount or a named volume.
+                "-v" | "--volume" if !value.contains(':') => config.volumes.get_or_insert_with(Vec::new).push(value),
+                "-v" | "--volume" => host.binds.get_or_insert_with(Vec::new).push(value),
+                "--tmpfs" => {
+                    let (path, options) = value.split_once(':').unwrap_or((&value, ""));
+                    host.tmpfs.get_or_insert_with(HashMap::new).insert(path.to_owned(), options.to_owned());
+                }
+                "--network" | "--net" => host.network_mode = Some(value),
+                "--add-host" => host.extra_hosts.get_or_insert_with(Vec::new).push(value),
+                "--dns" => host.dns.get_or_insert_with(Vec::new).push(value),
+                "--cpus" => {
+                    let cpus: f64 = value.parse().map_err(|_| format!("invalid argument \"{}\" for \"--cpus\"", value))?;
+                    host.nano_cpus = Some((cpus * 1e9) as i64);
+                }
+                "-m" | "--memory" => host.memory = Some(bytes(&value).ok_or_else(|| format!("invalid argument \"{}\" for \"--memory\"", value))?),
+                "-p" | "--publish" => {
+                    let (port, binding) = publish(&value)?;
+                    config.exposed_ports.get_or_insert_with(Vec::new).push(port.clone());
+                    host.port_bindings.get_or_insert_with(HashMap::new).entry(port).or_insert_with(|| Some(Vec::new())).get_or_insert_with(Vec::new).push(binding);
+                }
+                "-t" | "--tty" | "-it" | "-ti" => {
+                    return Err("`run -t` needs a terminal, which only the docker CLI gives containers. Install it for shells in containers.".to_owned());
+                }
+                flag => return Err(unsupported("run", flag)),
+            }
+        }
+        Ok(run)
+    }
+
+    fn body(&self) -> ContainerCreateBody {
+        ContainerCreateBody {
+            image: Some(self.image.clone()),
+            cmd: Some(self.cmd.clone()).filter(|c| !c.is_empty()),
+            attach_stdout: Some(!self.detach),
+            attach_stderr: Some(!self.detach),
+            host_config: Some(self.host.clone()),
+            ..self.config.clone()
+        }
+    }
+}
+
+/// `-p [<host ip>:][<host port>]:<container port>[/<protocol>]`'s container port, and what it's published on.
+fn publish(value: &str) -> Result<(String, PortBinding), String> {
+    let parts: Vec<&str> = value.split(':').collect();
+    let (host_ip, host_port, port) = match parts.as_slice() {
+        [port] => (None, None, *port),
+        [host_port, port] => (None, Some(*host_port), *port),
+        [host_ip, host_port, port] => (Some(*host_ip), Some(*host_port), *port),
+        _ => return Err(format!("invalid argument \"{}\" for \"-p\"", value)),
+    };
+    let port = if port.contains('/') { port.to_owned() } else { format!("{}/tcp", port) };
+    let binding = PortBinding {
+        host_ip: host_ip.filter(|i| !i.is_empty()).map(|i| i.to_owned()),
+        host_port: host_port.filter(|p| !p.is_empty()).map(|p| p.to_owned()),
+    };
+    Ok((port, binding))
+}
+
+/// A size as `--memory` takes it, e.g. `2g`, in bytes.
+fn bytes(size: &str) -> Option<i64> {
+    let size = size.to_ascii_lowercase();
+    let size = size.trim_end_matches('b');
+    let (number, unit) = match size.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&size[..i], c),
+        _ => (size, ' '),
+    };
+    let multiplier: i64 = match unit {
+        ' ' => 1,
+        'k' => 1 << 10,
+        'm' => 1 << 20,
+        'g' => 1 << 30,
+        't' => 1 << 40,
+        _ => return None,
+    };
+    number.parse::<f64>().ok().map(|n| (n * multiplier as f64) as i64)
+}
+
+/// The error for a flag that needs the CLI.
+fn unsupported(command: &str, flag: &str) -> String {
+    format!("`{}`'s `{}` needs the docker CLI, which isn't installed.", command, flag)
+}
+
+/// Writes what a container printed where the CLI would.
+fn print_output(output: LogOutput) {
+    let _ = match output {
+        LogOutput::StdErr { message } => io::stderr().write_all(&message),
+        LogOutput::StdOut { message } | LogOutput::Console { message } => io::stdout().write_all(&message).and_then(|_| io::stdout().flush()),
+        LogOutput::StdIn { .. } => Ok(()),
+    };
+}
+
+/// `run`: creates the container, pulling its image if it isn't here yet, then starts it and, unless it's detached, passes on
+/// what it prints until it exits, with its exit code.
+fn run(args: &[&str]) -> Result<i32, String> {
+    let run = Run::parse(args)?;
+    call(|docker| async move {
+        let mut options = CreateContainerOptionsBuilder::default();
+        if let Some(name) = &run.name {
+            options = options.name(name);
+        }
+        if let Some(platform) = &run.platform {
+            options = options.platform(platform);
+        }
+        let options = options.build();
+        let created = match docker.create_container(Some(options.clone()), run.body()).await {
+            Err(Error::DockerResponseServerError { status_code: 404, .. }) => {
+                eprintln!("Unable to find image '{}' locally", run.image);
+                pull(&docker, &run.image, run.platform.as_deref()).await?;
+                docker.create_container(Some(options), run.body()).await?
+            }
+            created => created?,
+        };
+        let id = created.id;
+        if run.detach {
+            docker.start_container(&id, None).await?;
+            println!("{}", id);
+            return Ok(0);
+        }
+
+        let attach = AttachContainerOptionsBuilder::default().stream(true).stdout(true).stderr(true).build();
+        let mut attached = docker.attach_container(&id, Some(attach)).await?;
+        docker.start_container(&id, None).await?;
+        while let Some(Ok(output)) = attached.output.next().await {
+            print_output(output);
+        }
+        let code = match docker.wait_container(&id, None).next().await {
+            Some(Ok(exited)) => exited.status_code,
+            Some(Err(Error::DockerContainerWaitError { code, .. })) => code,
+            Some(Err(e)) => return Err(e),
+            None => 0,
+        };
+        if run.remove {
+            docker.remove_container(&id, Some(RemoveContainerOptionsBuilder::default().v(true).force(true).build())).await?;
+        }
+        Ok(code as i32)
+    }).map_err(describe)
+}
+
+/// `logs [--follow] [--tail <lines>] <container>`.
+fn logs(args: &[&str]) -> Result<i32, String> {
+    let (flags, containers) = flags(args, &["--tail", "-n"])?;
+    let container = match containers.as_slice() {
+        [container] => *container,
+        _ => return Err("`logs` takes one container.".to_owned()),
+    };
+    let mut options = LogsOptionsBuilder::default().stdout(true).stderr(true);
+    for (flag, value) in flags {
+        options = match flag {
+            "-f" | "--follow" => options.follow(true),
+            "-n" | "--tail" => options.tail(value),
+            "-t" | "--timestamps" => options.timestamps(true),
+            flag => return Err(unsupported("logs", flag)),
+        };
+    }
+    let options = options.build();
+    call(|docker| async move {
+        let mut logs = docker.logs(container, Some(options));
+        while let Some(output) = logs.next().await {
+            print_output(output?);
+        }
+        Ok(0)
+    }).map_err(describe)
+}
+
+/// `rm [--force] [--volumes] <container>...`.
+fn rm(args: &[&str]) -> Result<i32, String> {
+    let (flags, containers) = flags(args, &[])?;
+    let mut options = RemoveContainerOptionsBuilder::default();
+    for (flag, _) in flags {
+        options = match flag {
+            "-f" | "--force" => options.force(true),
+            "-v" | "--volumes" => options.v(true),
+            flag => return Err(unsupported("rm", flag)),
+        };
+    }
+    let options = options.build();
+    let failed = call(|docker| async move {
+        let mut failed = false;
+        for container in containers {
+            match docker.remove_container(container, Some(options.clone())).await {
+                Ok(()) => println!("{}", container),
+                Err(e) => {
+                    eprintln!("{}", describe(e));
+                    failed = true;
+                }
+            }
+        }
+        Ok(failed)
+    }).map_err(describe)?;
+    Ok(if failed { 1 } else { 0 })
+}
+
+/// A template action's words, with quoted strings kept quoted, so they're told apart from fields.
+fn words(action: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = action.trim().chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut word = c.to_string();
+        if c == '"' {
+            let mut escaped = false;
+            for next in chars.by_ref() {
+                word.push(next);
+                if next == '"' && !escaped {
+                    break;
+                }
+                escaped = next == '\\' && !escaped;
+            }
+        } else {
+            word.extend(chars.by_ref().take_while(|c| !c.is_whitespace()));
+        }
+        words.push(word);
+    }
+    words
+}
+
+/// A `--format` template, parsed.
+enum Node {
+    Text(String),
+    Action(Vec<String>),
+    Range(Vec<String>, Vec<Node>),
+}
+
+/// Parses the parts of Go's templates black_magic's formats use: fields (`.Id`, `.Config.Labels`), `json`, `index`,
+/// `println`, and `range` over a list.
+fn parse(template: &str) -> Result<Vec<Node>, String> {
+    let mut open: Vec<(Vec<String>, Vec<Node>)> = vec![(Vec::new(), Vec::new())];
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}").ok_or_else(|| format!("template: unclosed action in `{}`", template))? + start;
+        let text = Node::Text(rest[..start].to_owned());
+        open.last_mut().expect("the template's own nodes").1.push(text);
+        let words = words(&rest[start + 2..end]);
+        let node = match words.first().map(|w| w.as_str()) {
+            Some("range") => {
+                open.push((words[1..].to_vec(), Vec::new()));
+                None
+            }
+            Some("end") if open.len() > 1 => {
+                let (expression, body) = open.pop().expect("an open range");
+                Some(Node::Range(expression, body))
+            }
+            _ => Some(Node::Action(words)),
+        };
+        open.last_mut().expect("the template's own nodes").1.extend(node);
+        rest = &rest[end + 2..];
+    }
+    if open.len() > 1 {
+        return Err(format!("template: unexpected EOF in `{}`", template));
+    }
+    let mut nodes = open.pop().expect("the template's own nodes").1;
+    nodes.push(Node::Text(rest.to_owned()));
+    Ok(nodes)
+}
+
+/// One of an action's words: `.`, a field of it, or a quoted string.
+fn term(word: &str, dot: &Value) -> Value {
+    if let Some(quoted) = word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+        return Value::String(quoted.replace("\\\"", "\""));
+    }
+    word.split('.').filter(|f| !f.is_empty()).fold(dot.clone(), |value, field| value.get(field).cloned().unwrap_or(Value::Null))
+}
+
+/// What an action's `words` evaluate to.
+fn evaluate(words: &[String], dot: &Value) -> Result<Value, String> {
+    match words.split_first() {
+        Some((function, args)) if function == "json" => Ok(Value::String(serde_json::to_string(&evaluate(args, dot)?).unwrap_or_default())),
+        Some((function, args)) if function == "println" => Ok(Value::String(format!("{}\n", text(&evaluate(args, dot)?)))),
+        Some((function, [value, key])) if function == "index" => {
+            let value = term(value, dot);
+            Ok(match term(key, dot) {
+                Value::String(key) => value.get(key.as_str()).cloned(),
+                Value::Number(i) => i.as_u64().and_then(|i| value.get(i as usize)).cloned(),
+                _ => None,
+            }.unwrap_or(Value::Null))
+        }
+        Some((word, [])) => Ok(term(word, dot)),
+        _ => Err(format!("template: can't evaluate `{}`", words.join(" "))),
+    }
+}
+
+/// How Go's templates print `value`.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "<no value>".to_owned(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => format!("[{}]", items.iter().map(text).collect::<Vec<String>>().join(" ")),
+        Value::Object(fields) => format!("map[{}]", fields.iter().map(|(k, v)| format!("{}:{}", k, text(v))).collect::<Vec<String>>().join(" ")),
+        value => value.to_string(),
+    }
+}
+
+fn render_nodes(nodes: &[Node], dot: &Value, rendered: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(t) => rendered.push_str(t),
+            Node::Action(words) => rendered.push_str(&text(&evaluate(words, dot)?)),
+            Node::Range(expression, body) => match evaluate(expression, dot)? {
+                Value::Array(items) => {
+                    for item in &items {
+                        render_nodes(body, item, rendered)?;
+                    }
+                }
+                Value::Object(fields) => {
+                    for item in fields.values() {
+                        render_nodes(body, item, rendered)?;
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Fills in a `--format` template from `value`, as the CLI would, see `parse`.
+fn render(template: &str, value: &Value) -> Result<String, String> {
+    let mut rendered = String::new();
+    render_nodes(&parse(template)?, value, &mut rendered)?;
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_the_formats_black_magic_uses() {
+        let image = json!({
+            "Id": "sha256:abc",
+            "Size": 1024,
+            "RepoDigests": ["rust@sha256:1", "mirror/rust@sha256:2"],
+            "Config": { "Labels": { "black_magic.inputs": "digest" }, "ExposedPorts": { "8080/tcp": {} } },
+        });
+        assert_eq!(render("{{.Id}}", &image).unwrap(), "sha256:abc");
+        assert_eq!(render("{{.Size}}", &image).unwrap(), "1024");
+        assert_eq!(render("{{range .RepoDigests}}{{println .}}{{end}}", &image).unwrap(), "rust@sha256:1\nmirror/rust@sha256:2\n");
+        assert_eq!(render("{{index .Config.Labels \"black_magic.inputs\"}}", &image).unwrap(), "digest");
+        assert_eq!(render("{{json .Config.ExposedPorts}}", &image).unwrap(), "{\"8080/tcp\":{}}");
+        assert_eq!(render("{{.Missing}}|{{.Config.Labels}}", &image).unwrap(), "<no value>|map[black_magic.inputs:digest]");
+        assert!(render("{{range .RepoDigests}}", &image).is_err());
+    }
+
+    #[test]
+    fn reads_run_arguments() {
+        let run = Run::parse(&[
+            "-i", "--rm", "-v", "/src:/workdir:z", "-v", "/workdir/target", "-e", "A=1", "--tmpfs", "/tmp:size=64m", "--name=bm_x",
+            "--memory", "2g", "--cpus", "1.5", "-p", "127.0.0.1:9000:8080", "image:tag", "/bin/bash", "-c", "echo -v",
+        ]).unwrap();
+        assert!(run.remove && !run.detach);
+        assert_eq!(run.name.as_deref(), Some("bm_x"));
+        assert_eq!(run.image, "image:tag");
+        assert_eq!(run.cmd, ["/bin/bash", "-c", "echo -v"]);
+        assert_eq!(run.host.binds, Some(vec!["/src:/workdir:z".to_owned()]));
+        assert_eq!(run.config.volumes, Some(vec!["/workdir/target".to_owned()]));
+        assert_eq!(run.config.env, Some(vec!["A=1".to_owned()]));
+        assert_eq!(run.host.tmpfs.as_ref().and_then(|t| t.get("/tmp")).map(|o| o.as_str()), Some("size=64m"));
+        assert_eq!(run.host.memory, Some(2 << 30));
+        assert_eq!(run.host.nano_cpus, Some(1_500_000_000));
+        let bindings = run.host.port_bindings.unwrap();
+        let binding = &bindings["8080/tcp"].as_ref().unwrap()[0];
+        assert_eq!((binding.host_ip.as_deref(), binding.host_port.as_deref()), (Some("127.0.0.1"), Some("9000")));
+        assert!(Run::parse(&["-it", "image"]).is_err());
+        assert!(Run::parse(&["--privileged", "image"]).is_err());
+    }
+}
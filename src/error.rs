@@ -0,0 +1,77 @@
+//! Everything that can make a build fail, and the exit code each kind of failure exits with, so CI can tell them apart.
+//!
+//! | Code | Failure                                                                  |
+//! |------|--------------------------------------------------------------------------|
+//! | 0    | Success                                                                  |
+//! | 1    | Invalid command line arguments                                           |
+//! | 2    | Environment: missing tools, not a cargo project, invalid config          |
+//! | 3    | Compile: the project or its toolchain failed to build                    |
+//! | 4    | Packaging: the artifact couldn't be packaged, or was rejected by a check |
+//! | 5    | Docker: the container runtime failed                                     |
+//! | 6    | Test: `--test`, `--clippy` or the integration test failed                |
+//! | 7    | Publish: pushing, loading, deploying or releasing failed                 |
+//! | 130  | Interrupted with Ctrl-C (or `SIGTERM`), see `interrupt`                  |
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum BmError {
+    Environment(String),
+    Compile(String),
+    Packaging(String),
+    Docker(String),
+    Test(String),
+    Publish(String),
+}
+
+impl BmError {
+    /// The kind of error a black_magic run exiting with `code` failed with, e.g. one of the builds `release` runs.
+    pub fn from_exit_code(code: Option<i32>, message: String) -> BmError {
+        match code {
+            Some(3) => BmError::Compile(message),
+            Some(4) => BmError::Packaging(message),
+            Some(5) => BmError::Docker(message),
+            Some(6) => BmError::Test(message),
+            Some(7) => BmError::Publish(message),
+            // Exited successfully, but didn't produce an artifact.
+            Some(0) => BmError::Packaging(message),
+            _ => BmError::Environment(message),
+        }
+    }
+
+    /// The kind of failure, as reported by `--output-format json`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BmError::Environment(_) => "environment",
+            BmError::Compile(_) => "compile",
+            BmError::Packaging(_) => "packaging",
+            BmError::Docker(_) => "docker",
+            BmError::Test(_) => "test",
+            BmError::Publish(_) => "publish",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BmError::Environment(_) => 2,
+            BmError::Compile(_) => 3,
+            BmError::Packaging(_) => 4,
+            BmError::Docker(_) => 5,
+            BmError::Test(_) => 6,
+            BmError::Publish(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for BmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BmError::Environment(m)
+            | BmError::Compile(m)
+            | BmError::Packaging(m)
+            | BmError::Docker(m)
+            | BmError::Test(m)
+            | BmError::Publish(m) => f.write_str(m),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! `--test` and `--clippy`: running the project's tests, and clippy with `-D warnings`, in the build container ahead of the
+//! build, so failures that only show up against musl (and pass on a glibc host) stop the artifact from being packaged.
+//!
+//! Both use the build's target, toolchain and cargo arguments (features included), with cargo's own profile for tests. A
+//! failing gate exits the build with the test exit code (6) rather than a compile error's.
+
+use crate::backend::Build;
+
+/// Printed to stderr inside the container when a gate fails, so the failure can be told apart from a compile error.
+pub const FAILED: &str = "black_magic: failed gate";
+
+/// Shell command running the gates, to go before the build command, or nothing without any.
+pub fn cmd(build: &Build, test: bool, clippy: bool) -> String {
+    let mut gates = Vec::new();
+    if test {
+        gates.push(format!("({} || (echo '{} --test' >&2 && exit 1))", build.subcommand_cmd("test"), FAILED));
+    }
+    if clippy {
+        // Builder images don't necessarily come with clippy.
+        let component = match build.toolchain {
+            Some(t) => format!("rustup component add clippy --toolchain {}", t.channel),
+            None => "rustup component add clippy".to_owned(),
+        };
+        gates.push(format!(
+            "({} clippy --version > /dev/null 2>&1 || {}) && ({} -- -D warnings || (echo '{} --clippy' >&2 && exit 1))",
+            build.cargo(), component, build.subcommand_cmd("clippy"), FAILED));
+    }
+    if gates.is_empty() {
+        return String::new();
+    }
+    format!("{}{} && ", build.install_cmd(), gates.join(" && "))
+}
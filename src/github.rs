@@ -0,0 +1,48 @@
+//! Publishing release artifacts as GitHub Release assets, via the `gh` CLI.
+//!
+//! The token comes from `GH_TOKEN` or `GITHUB_TOKEN`, so this works unattended in CI.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn token() -> Option<String> {
+    env::var("GH_TOKEN").or_else(|_| env::var("GITHUB_TOKEN")).ok().filter(|t| !t.is_empty())
+}
+
+fn gh(args: &[&str], assets: &[PathBuf], token: &str) -> Result<(), String> {
+    let output = Command::new("gh")
+        .args(args)
+        .args(assets)
+        .env("GH_TOKEN", token)
+        .output()
+        .map_err(|e| format!("Unable to run the `gh` CLI: {}. Is it installed?", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("`gh {}` failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/// Pushes `tag`, then creates the GitHub Release for it (or updates the existing one), uploading `assets`.
+/// Assets that were already uploaded are replaced.
+pub fn publish_release(tag: &str, assets: &[PathBuf]) -> Result<(), String> {
+    let token = token().ok_or("Set `GH_TOKEN` or `GITHUB_TOKEN` to publish GitHub Releases.")?;
+
+    // The release has to point at our (annotated) tag, not one GitHub makes up on its default branch.
+    let push = Command::new("git")
+        .arg("push")
+        .arg("origin")
+        .arg(format!("refs/tags/{}", tag))
+        .output()
+        .map_err(|e| format!("Unable to run git: {}", e))?;
+    if !push.status.success() {
+        return Err(format!("Unable to push tag `{}`: {}", tag, String::from_utf8_lossy(&push.stderr).trim()));
+    }
+
+    if gh(&["release", "view", tag], &[], &token).is_ok() {
+        gh(&["release", "upload", tag, "--clobber"], assets, &token)
+    } else {
+        gh(&["release", "create", tag, "--verify-tag", "--title", tag, "--notes", &format!("Release {}", tag)], assets, &token)
+    }
+}
@@ -0,0 +1,186 @@
+//! `target/black_magic/history.jsonl`: every build's outcome, one JSON line each, and `black_magic stats`, the trends in them.
+//!
+//! Each line has when the build finished, whether it succeeded (or else the kind of error), how long it took and how long it
+//! spent compiling, what the cache did for it (`cache`: `unchanged` for `--skip-unchanged`, `artifact` for an artifact reused
+//! from the artifact store, or `compiled`, with `cold` if the cache volume was new or there was none), and the artifact and
+//! its size. `--dry-run`s, and builds running others (e.g. `--bins`), aren't recorded, as the builds they run are. The file
+//! keeps the last `MAX_ENTRIES`.
+//!
+//! `black_magic stats` summarizes the last `--last` builds (20 by default): how many succeeded, the average and fastest build
+//! times, how often the cache saved compiling, and for each artifact, how its size changed over them.
+
+use crate::clean::format_size;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::progress;
+use crate::state::State;
+use crate::tags;
+use clap::ArgMatches;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const FILE: &str = "history.jsonl";
+
+/// Past this, the oldest entries go.
+const MAX_ENTRIES: usize = 1000;
+
+/// What the cache did for this build, see `cache`.
+static CACHE: Mutex<Option<(&'static str, bool)>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    finished_at: String,
+    succeeded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    duration_seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compile_seconds: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<String>,
+    /// Whether the cache volume was new (or there wasn't one).
+    #[serde(default)]
+    cold: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+}
+
+/// Records what the cache did for the build: `unchanged`, `artifact`, `resumed` (see `phases`) or `compiled`, and whether it
+/// was `cold`.
+pub fn cache(kind: &'static str, cold: bool) {
+    *CACHE.lock().unwrap_or_else(|e| e.into_inner()) = Some((kind, cold));
+}
+
+/// Appends the build in the current directory, started at `started_at`, finishing after `seconds` with `error` if it failed.
+pub fn record(started_at: SystemTime, seconds: f64, error: Option<&BmError>) {
+    let current_dir = match mounts::current_dir() {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let bm_dir = current_dir.join("target").join("black_magic");
+    // A build that failed before making it has nowhere to record it.
+    if !bm_dir.is_dir() {
+        return;
+    }
+    let cache = *CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    // A skipped build leaves the last one's artifact as it was.
+    let state = match cache {
+        Some(("unchanged", _)) => State::load(&bm_dir),
+        _ => State::load_since(&bm_dir, started_at),
+    };
+    let state = state.filter(|_| error.is_none());
+    let compile_seconds = progress::report()
+        .as_array()
+        .and_then(|phases| phases.iter().find(|p| p["name"] == "compile"))
+        .and_then(|p| p["seconds"].as_f64());
+    let entry = Entry {
+        finished_at: tags::rfc3339(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)),
+        succeeded: error.is_none(),
+        error: error.map(|e| e.kind().to_owned()),
+        duration_seconds: seconds,
+        compile_seconds,
+        cache: cache.map(|(kind, _)| kind.to_owned()),
+        cold: cache.map(|(_, cold)| cold).unwrap_or(false),
+        artifact: state.as_ref().map(|s| s.artifact.display().to_string()),
+        size: state.as_ref().map(|s| s.size),
+    };
+    if let Err(e) = append(&bm_dir.join(FILE), &entry) {
+        output::warning(&format!("Unable to record the build in `{}`: {}", FILE, e));
+    }
+}
+
+fn append(path: &Path, entry: &Entry) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry).unwrap())?;
+    drop(file);
+    let contents = fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    if lines.len() > MAX_ENTRIES {
+        fs::write(path, lines[lines.len() - MAX_ENTRIES..].join("\n") + "\n")?;
+    }
+    Ok(())
+}
+
+fn load(bm_dir: &Path) -> Vec<Entry> {
+    // Lines that don't parse, e.g. half-written by an interrupted build, are skipped.
+    fs::read_to_string(bm_dir.join(FILE))
+        .map(|c| c.lines().filter_map(|l| serde_json::from_str(l).ok()).collect())
+        .unwrap_or_default()
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+    Some(values.iter().sum::<f64>() / values.len() as f64).filter(|_| !values.is_empty())
+}
+
+/// Runs `black_magic stats`.
+pub fn stats(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = mounts::current_dir()?;
+    let last: usize = matches.value_of("LAST").unwrap().parse().unwrap();
+    let entries = load(&current_dir.join("target").join("black_magic"));
+    let entries = &entries[entries.len().saturating_sub(last)..];
+    if entries.is_empty() {
+        status!("No builds recorded yet, in `target/black_magic/{}`.", FILE);
+        if output::is_json() {
+            output::emit("stats", json!({ "builds": 0 }));
+        }
+        return Ok(());
+    }
+
+    let succeeded: Vec<&Entry> = entries.iter().filter(|e| e.succeeded).collect();
+    let durations: Vec<f64> = succeeded.iter().map(|e| e.duration_seconds).collect();
+    let compiles: Vec<f64> = succeeded.iter().filter_map(|e| e.compile_seconds).collect();
+    let saved = succeeded.iter().filter(|e| matches!(e.cache.as_deref(), Some("unchanged") | Some("artifact") | Some("resumed"))).count();
+    let cold = succeeded.iter().filter(|e| e.cache.as_deref() == Some("compiled") && e.cold).count();
+    status!("The last {} builds, from {} to {}:", entries.len(), entries[0].finished_at, entries[entries.len() - 1].finished_at);
+    status!("    succeeded        {} of {}", succeeded.len(), entries.len());
+    if let Some(average) = average(&durations) {
+        let fastest = durations.iter().cloned().fold(f64::INFINITY, f64::min);
+        status!("    build time       {:.1}s on average, {:.1}s at the fastest", average, fastest);
+    }
+    if let Some(average) = average(&compiles) {
+        status!("    compile time     {:.1}s on average", average);
+    }
+    status!("    cache            {} skipped, reused an artifact or packaged the last executable, {} compiled, {} of them cold", saved, succeeded.len() - saved, cold);
+
+    // By artifact, oldest to newest, as builds of others (e.g. `--arch aarch64`) are interleaved.
+    let mut sizes: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for entry in &succeeded {
+        if let (Some(artifact), Some(size)) = (&entry.artifact, entry.size) {
+            sizes.entry(artifact.as_str()).or_default().push(size);
+        }
+    }
+    let mut artifacts = Vec::new();
+    for (artifact, sizes) in &sizes {
+        let (first, latest) = (sizes[0], sizes[sizes.len() - 1]);
+        let growth = (latest as f64 - first as f64) * 100.0 / first.max(1) as f64;
+        let name = Path::new(artifact).file_name().and_then(|n| n.to_str()).unwrap_or(artifact);
+        status!("    {}  {} now, {:+.1}% over {} builds (from {})", name, format_size(latest), growth, sizes.len(), format_size(first));
+        artifacts.push(json!({ "artifact": artifact, "size": latest, "first_size": first, "builds": sizes.len() }));
+    }
+
+    if output::is_json() {
+        output::emit("stats", json!({
+            "builds": entries.len(),
+            "succeeded": succeeded.len(),
+            "average_seconds": average(&durations),
+            "average_compile_seconds": average(&compiles),
+            "cache_reused": saved,
+            "cold_compiles": cold,
+            "artifacts": artifacts,
+        }));
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Commands run at points in the build, from `BlackMagic.toml`, for what would otherwise need a wrapper script:
+//! ```toml
+//! [[hooks]]
+//! at = "post-package"
+//! container = true
+//! run = "zip -qr \"$BM_ARTIFACT\" migrations"
+//!
+//! [[hooks]]
+//! at = "post-package"
+//! run = "curl -fsS -d \"built $BM_ARTIFACT\" \"$SLACK_WEBHOOK\""
+//! ```
+//! `at` is one of:
+//! - `pre-build`: before compiling
+//! - `post-build`: straight after compiling, before the executable is inspected, stripped or compressed
+//! - `pre-package`: before the executable is put in the zip or tarball
+//! - `post-package`: once the artifact is packaged and checked, before it's checksummed, so the hook can still change it
+//!
+//! Hooks run with `sh -c`, in the project directory, on the host, or with `container = true`, inside the build container, as
+//! part of its command. Compiling and packaging are one command there, so `post-build` and `pre-package` hooks need
+//! `container = true`. A failing hook fails the build.
+//!
+//! Hooks get `BM_HOOK` (the point they run at), `BM_NAME`, `BM_MODE` (`docker` or `lambda`), `BM_TARGET`, `BM_PROFILE`,
+//! `BM_PROJECT_DIR` and `BM_ARTIFACT`, the artifact's path (relative to the project, `/workdir` in the container). Container
+//! hooks before packaging get `BM_EXECUTABLE` too, the executable's path in the container, e.g. to inspect or sign it there.
+//!
+//! Hooks are part of the artifact's fingerprint, so changing one rebuilds, and none run when a build reuses an artifact.
+
+use crate::error::BmError;
+use crate::output;
+use crate::shell_quote;
+use serde::Deserialize;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+
+pub const POINTS: &[&str] = &["pre-build", "post-build", "pre-package", "post-package"];
+
+#[derive(Deserialize)]
+pub struct Hook {
+    pub at: String,
+    pub run: String,
+    #[serde(default)]
+    pub container: bool,
+}
+
+/// What hooks are told about the build.
+pub struct Context<'a> {
+    pub name: &'a str,
+    pub mode: &'a str,
+    pub target: &'a str,
+    pub profile: &'a str,
+    /// Relative to the project.
+    pub artifact: &'a str,
+    /// The project in the build container, `/workdir` unless it's in a workspace, see `workspace`.
+    pub container_dir: &'a str,
+}
+
+/// Checks each hook runs at a point there is, where it can.
+pub fn check(hooks: &[Hook]) -> Result<(), BmError> {
+    for hook in hooks {
+        if !POINTS.contains(&hook.at.as_str()) {
+            return Err(BmError::Environment(format!(
+                "`at = \"{}\"` in a `[[hooks]]` entry isn't a point in the build, use one of: {}.", hook.at, POINTS.join(", "))));
+        }
+        if !hook.container && (hook.at == "post-build" || hook.at == "pre-package") {
+            return Err(BmError::Environment(format!(
+                "The `{}` hook `{}` runs between compiling and packaging, which happen in the build container, so it needs `container = true`.",
+                hook.at, hook.run)));
+        }
+    }
+    Ok(())
+}
+
+/// Whether any hook runs at `at`.
+pub fn any(hooks: &[Hook], at: &str) -> bool {
+    hooks.iter().any(|h| h.at == at)
+}
+
+fn variables(at: &str, context: &Context, project_dir: &str, artifact: String) -> Vec<(&'static str, String)> {
+    vec![
+        ("BM_HOOK", at.to_owned()),
+        ("BM_NAME", context.name.to_owned()),
+        ("BM_MODE", context.mode.to_owned()),
+        ("BM_TARGET", context.target.to_owned()),
+        ("BM_PROFILE", context.profile.to_owned()),
+        ("BM_PROJECT_DIR", project_dir.to_owned()),
+        ("BM_ARTIFACT", artifact),
+    ]
+}
+
+/// The build container's commands for the hooks at `at`, each starting `&&`, with `executable` where the executable is then.
+pub fn container_cmd(hooks: &[Hook], at: &str, context: &Context, executable: Option<&str>) -> String {
+    let mut variables = variables(at, context, context.container_dir, context.artifact.to_owned());
+    variables.extend(executable.map(|e| ("BM_EXECUTABLE", e.to_owned())));
+    let assignments: String = variables.iter().map(|(k, v)| format!("{}={} ", k, shell_quote(v))).collect();
+    hooks.iter()
+        .filter(|h| h.container && h.at == at)
+        .map(|h| format!(" && {}sh -c {}", assignments, shell_quote(&h.run)))
+        .collect()
+}
+
+/// Runs the host's hooks at `at`, in `project_dir`.
+pub fn run(hooks: &[Hook], at: &str, project_dir: &Path, context: &Context) -> Result<(), BmError> {
+    let project = project_dir.to_string_lossy();
+    let variables = variables(at, context, &project, project_dir.join(context.artifact).to_string_lossy().into_owned());
+    for hook in hooks.iter().filter(|h| !h.container && h.at == at) {
+        output::detail(&format!("Running the {} hook `{}`", at, hook.run));
+        // Stdout may be just for JSON or markers, see `output`.
+        let stdout = if output::is_json() || output::is_porcelain() { Stdio::from(io::stderr()) } else { Stdio::inherit() };
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&hook.run)
+            .current_dir(project_dir)
+            .envs(variables.iter().map(|(k, v)| (k, v)))
+            .stdout(stdout)
+            .status()
+            .map_err(|e| BmError::Environment(format!("Unable to run the {} hook `{}`: {}", at, hook.run, e)))?;
+        if !status.success() {
+            return Err(BmError::Environment(format!("The {} hook `{}` failed with {}.", at, hook.run, status)));
+        }
+    }
+    Ok(())
+}
+
+/// The hooks, for the artifact's fingerprint.
+pub fn fingerprint(hooks: &[Hook]) -> String {
+    hooks.iter().map(|h| format!("{}|{}|{};", h.at, h.container, h.run)).collect()
+}
@@ -0,0 +1,129 @@
+//! `--diff-against`: comparing the built image's filesystem with a previously published image, before pushing, to catch
+//! anything accidentally added to it, e.g. secrets, configs or debug files.
+//!
+//! Every path added, removed, or changed in size is reported. A path that wasn't in the previous image fails the build, unless
+//! it matches a pattern from `BlackMagic.toml`, where `*` matches any part of a path:
+//! ```toml
+//! [image_diff]
+//! allowed_new_paths = ["/etc/ssl/*", "/app/migrations/*"]
+//! ```
+
+use crate::archive::EntryKind;
+use crate::error::BmError;
+use crate::inspect;
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ImageDiffConfig {
+    pub allowed_new_paths: Vec<String>,
+}
+
+/// A path whose size differs between the images. `None` where it doesn't exist.
+pub struct Change {
+    pub path: String,
+    pub before: Option<u64>,
+    pub after: Option<u64>,
+}
+
+/// Matches `path` against `pattern`, where each `*` matches anything, including `/`.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if parts.len() == 1 {
+        return pattern == path;
+    }
+    if !path.starts_with(first) || !path[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &path[first.len()..path.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Every file and symlink in `image`, and its size, absolute like they are inside the container. Directories only matter for
+/// what's in them.
+fn sizes(runtime: Runtime, image: &str) -> Result<BTreeMap<String, u64>, BmError> {
+    Ok(inspect::read_image(runtime, image)?
+        .iter()
+        .filter(|e| e.kind != EntryKind::Dir)
+        .map(|e| (format!("/{}", e.path.trim_start_matches("./")), e.contents.len() as u64))
+        .collect())
+}
+
+fn compare(before: &BTreeMap<String, u64>, after: &BTreeMap<String, u64>) -> Vec<Change> {
+    let mut changes: Vec<Change> = after
+        .iter()
+        .filter(|(path, size)| before.get(*path) != Some(size))
+        .map(|(path, size)| Change { path: path.clone(), before: before.get(path).copied(), after: Some(*size) })
+        .collect();
+    changes.extend(before
+        .iter()
+        .filter(|(path, _)| !after.contains_key(*path))
+        .map(|(path, size)| Change { path: path.clone(), before: Some(*size), after: None }));
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Compares `image` against `previous`, pulling `previous` if it isn't local, and fails if it has any unexpected new paths.
+pub fn check(runtime: Runtime, image: &str, previous: &str, config: &ImageDiffConfig) -> Result<(), BmError> {
+    if !runtime.image_exists(previous)? {
+        status!("Pulling {}...", previous);
+        let pulled = runtime.command()
+            .arg("pull")
+            .arg(previous)
+            .output()
+            .map_err(|e| BmError::Docker(format!("Unable to pull `{}`: {}", previous, e)))?;
+        if !pulled.status.success() {
+            return Err(BmError::Docker(format!("Unable to pull `{}`.\n\nstderr: {}", previous, String::from_utf8_lossy(&pulled.stderr))));
+        }
+    }
+
+    let changes = compare(&sizes(runtime, previous)?, &sizes(runtime, image)?);
+    let unexpected: Vec<&str> = changes
+        .iter()
+        .filter(|c| c.before.is_none() && !config.allowed_new_paths.iter().any(|p| glob_match(p, &c.path)))
+        .map(|c| c.path.as_str())
+        .collect();
+
+    if output::is_json() {
+        output::emit("image_diff", json!({
+            "image": image,
+            "previous": previous,
+            "changes": changes.iter().map(|c| json!({ "path": c.path, "before": c.before, "after": c.after })).collect::<Vec<_>>(),
+            "unexpected": unexpected,
+        }));
+    } else if changes.is_empty() {
+        status!("No changes to the image's files since {}.", previous);
+    } else {
+        status!("Changes since {}:", previous);
+        for change in &changes {
+            match (change.before, change.after) {
+                (None, Some(after)) => status!("    + {} ({} bytes)", change.path, after),
+                (Some(before), None) => status!("    - {} ({} bytes)", change.path, before),
+                (Some(before), Some(after)) => status!(
+                    "    ~ {} ({} -> {} bytes, {:+})", change.path, before, after, after as i64 - before as i64),
+                (None, None) => {}
+            }
+        }
+    }
+
+    if unexpected.is_empty() {
+        Ok(())
+    } else {
+        Err(BmError::Test(format!(
+            "The image has new paths that {} didn't: {}\n\nIf they're meant to be there, add them to `allowed_new_paths` in the \
+            `[image_diff]` section of `BlackMagic.toml`.",
+            previous, unexpected.join(", "))))
+    }
+}
@@ -0,0 +1,375 @@
+//! The `init` subcommand: starting a project from a template known to build and package cleanly with black_magic.
+//!
+//! `--template lambda-http` writes a Lambda function behind an HTTP API:
+//! - `Cargo.toml` and `src/main.rs`, a `lambda_http` handler answering `GET /?name=...`
+//! - `BlackMagic.toml`, for the `provided.al2023` runtime, with the function's settings for `--emit-sam`/`--emit-terraform`
+//! - `events/hello.json`, an API Gateway HTTP API request to invoke it with
+//! - `invoke.sh`, building the zip and running it locally with `black_magic invoke`
+//!
+//! `--lambda` writes the same for a plain `lambda_runtime` handler, taking `{"name": ...}` and answering with a message, and
+//! `--docker` writes an `axum` server answering `GET /?name=...` on port 8080, with a `/health` the `[integration_test]` in
+//! its `BlackMagic.toml` checks, and `run.sh`, building the image and running it.
+//!
+//! In a directory without a `Cargo.toml`, it starts a new crate, and fails without writing anything if any of the files
+//! already exist. In an existing crate, it adds the dependencies `Cargo.toml` is missing, and writes the files that aren't
+//! there yet, leaving the rest (e.g. an existing `src/main.rs`) as they are.
+
+use crate::error::BmError;
+use crate::mounts;
+use crate::names;
+use crate::output;
+use crate::output::status;
+use clap::ArgMatches;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+pub const TEMPLATES: &[&str] = &["lambda-http"];
+
+const CARGO_TOML: &str = r#"[package]
+name = "{{name}}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+{{dependencies}}
+[profile.release]
+lto = true
+codegen-units = 1
+"#;
+
+const LAMBDA_HTTP_DEPENDENCIES: &[(&str, &str)] = &[("lambda_http", r#""0.13""#), ("tokio", r#"{ version = "1", features = ["macros"] }"#)];
+
+const LAMBDA_HTTP_MAIN_RS: &str = r#"use lambda_http::{run, service_fn, Body, Error, Request, RequestExt, Response};
+
+/// Answers `GET /?name=...` with a greeting.
+async fn handler(request: Request) -> Result<Response<Body>, Error> {
+    let parameters = request.query_string_parameters();
+    let name = parameters.first("name").unwrap_or("world");
+    let response = Response::builder()
+        .status(200)
+        .header("content-type", "text/plain")
+        .body(format!("Hello, {}!", name).into())?;
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(service_fn(handler)).await
+}
+"#;
+
+const LAMBDA_BLACK_MAGIC_TOML: &str = r#"# black_magic's settings for this project, see `black_magic --help`.
+# Build the zip with `black_magic --lambda`, and try it with `./invoke.sh`.
+
+[lambda]
+runtime = "provided.al2023"
+
+[lambda.function]
+memory_size = 128
+timeout = 10
+"#;
+
+/// For `lambda-http`, whose SAM template puts an HTTP API in front of it.
+const SAM_API: &str = "\n[lambda.sam]\napi = true\n";
+
+const HELLO_EVENT: &str = r#"{
+  "version": "2.0",
+  "routeKey": "$default",
+  "rawPath": "/",
+  "rawQueryString": "name=black_magic",
+  "headers": {
+    "accept": "text/plain",
+    "host": "localhost"
+  },
+  "queryStringParameters": {
+    "name": "black_magic"
+  },
+  "requestContext": {
+    "accountId": "123456789012",
+    "apiId": "local",
+    "domainName": "localhost",
+    "domainPrefix": "localhost",
+    "http": {
+      "method": "GET",
+      "path": "/",
+      "protocol": "HTTP/1.1",
+      "sourceIp": "127.0.0.1",
+      "userAgent": "black_magic"
+    },
+    "requestId": "00000000-0000-0000-0000-000000000000",
+    "routeKey": "$default",
+    "stage": "$default",
+    "time": "01/Jan/2024:00:00:00 +0000",
+    "timeEpoch": 1704067200000
+  },
+  "isBase64Encoded": false
+}
+"#;
+
+const LAMBDA_DEPENDENCIES: &[(&str, &str)] = &[
+    ("lambda_runtime", r#""0.13""#),
+    ("serde", r#"{ version = "1", features = ["derive"] }"#),
+    ("tokio", r#"{ version = "1", features = ["macros"] }"#),
+];
+
+const LAMBDA_MAIN_RS: &str = r#"use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+struct Request {
+    name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    message: String,
+}
+
+/// Answers `{"name": "..."}` with a greeting.
+async fn handler(event: LambdaEvent<Request>) -> Result<Response, Error> {
+    let name = event.payload.name.unwrap_or_else(|| "world".to_owned());
+    Ok(Response { message: format!("Hello, {}!", name) })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    run(service_fn(handler)).await
+}
+"#;
+
+const LAMBDA_EVENT: &str = r#"{
+  "name": "black_magic"
+}
+"#;
+
+const DOCKER_DEPENDENCIES: &[(&str, &str)] = &[
+    ("axum", r#""0.7""#),
+    ("tokio", r#"{ version = "1", features = ["macros", "net", "rt-multi-thread", "signal"] }"#),
+];
+
+const DOCKER_MAIN_RS: &str = r#"use axum::extract::Query;
+use axum::routing::get;
+use axum::Router;
+use std::collections::HashMap;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Answers `GET /?name=...` with a greeting.
+async fn hello(Query(parameters): Query<HashMap<String, String>>) -> String {
+    format!("Hello, {}!", parameters.get("name").map(String::as_str).unwrap_or("world"))
+}
+
+/// Waits for `docker stop`'s SIGTERM, or Ctrl-C. As the container's PID 1, nothing else would stop it.
+async fn shutdown() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let app = Router::new()
+        .route("/", get(hello))
+        .route("/health", get(|| async { "ok" }));
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.expect("Unable to listen on port 8080");
+    axum::serve(listener, app).with_graceful_shutdown(shutdown()).await.expect("Unable to serve");
+}
+"#;
+
+const DOCKER_BLACK_MAGIC_TOML: &str = r#"# black_magic's settings for this project, see `black_magic --help`.
+# Build the image with `black_magic --docker`, and try it with `./run.sh`.
+
+# `black_magic --docker --integration-test` runs the image, and checks it answers.
+[integration_test]
+image = "curlimages/curl"
+command = "curl --retry 10 --retry-connrefused -f http://app:8080/health"
+"#;
+
+const RUN_SH: &str = r#"#!/bin/sh
+# Builds the image and runs it on port 8080, e.g. for `curl 'localhost:8080/?name=black_magic'`.
+set -e
+cd "$(dirname "$0")"
+black_magic --docker --expose 8080
+docker run --rm -p 8080:8080 {{image}}
+"#;
+
+const INVOKE_SH: &str = r#"#!/bin/sh
+# Builds the Lambda zip and invokes it locally with an event, `events/hello.json` unless another is given.
+set -e
+cd "$(dirname "$0")"
+black_magic --lambda
+black_magic invoke --payload "${1:-events/hello.json}"
+"#;
+
+const GITIGNORE: &str = "/target\n";
+
+#[cfg(unix)]
+fn make_executable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o755));
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) {}
+
+/// `dir`'s name, which black_magic expects the executable to have, so it has to be a valid crate name too.
+fn crate_name(dir: &Path) -> Result<String, BmError> {
+    let name = dir.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(name.to_owned())
+    } else {
+        Err(BmError::Environment(format!(
+            "`{}` can't be a crate's name, and builds expect the executable to be named after the project's directory. \
+            Use a directory named with letters, digits, `-` and `_`, e.g. `{}`.",
+            name, names::sanitize(name).replace('.', "-"))))
+    }
+}
+
+/// What a kind of project is made of, besides its `Cargo.toml`.
+struct Scaffold {
+    kind: &'static str,
+    dependencies: &'static [(&'static str, &'static str)],
+    files: Vec<(&'static str, String)>,
+    script: &'static str,
+    next: &'static str,
+}
+
+/// The project `matches` asks for, whose image is `image`.
+fn scaffold(matches: &ArgMatches, image: &str) -> Scaffold {
+    if matches.is_present("DOCKER") {
+        Scaffold {
+            kind: "docker",
+            dependencies: DOCKER_DEPENDENCIES,
+            files: vec![
+                ("src/main.rs", DOCKER_MAIN_RS.to_owned()),
+                ("BlackMagic.toml", DOCKER_BLACK_MAGIC_TOML.to_owned()),
+                ("run.sh", RUN_SH.replace("{{image}}", image)),
+            ],
+            script: "run.sh",
+            next: "Build its image with `black_magic --docker`, and run it with `./run.sh`.",
+        }
+    } else {
+        let lambda = matches.is_present("LAMBDA");
+        Scaffold {
+            kind: if lambda { "lambda" } else { "lambda-http" },
+            dependencies: if lambda { LAMBDA_DEPENDENCIES } else { LAMBDA_HTTP_DEPENDENCIES },
+            files: vec![
+                ("src/main.rs", if lambda { LAMBDA_MAIN_RS } else { LAMBDA_HTTP_MAIN_RS }.to_owned()),
+                ("BlackMagic.toml", format!("{}{}", LAMBDA_BLACK_MAGIC_TOML, if lambda { "" } else { SAM_API })),
+                ("events/hello.json", if lambda { LAMBDA_EVENT } else { HELLO_EVENT }.to_owned()),
+                ("invoke.sh", INVOKE_SH.to_owned()),
+            ],
+            script: "invoke.sh",
+            next: "Build its Lambda zip with `black_magic --lambda`, and invoke it locally with `./invoke.sh`.",
+        }
+    }
+}
+
+fn dependency_lines(dependencies: &[&(&str, &str)]) -> String {
+    dependencies.iter().map(|(name, version)| format!("{} = {}\n", name, version)).collect()
+}
+
+/// An existing crate's `cargo_toml` with the `dependencies` it doesn't have yet, and their names.
+fn add_dependencies(cargo_toml: &str, dependencies: &'static [(&'static str, &'static str)]) -> Result<(String, Vec<&'static str>), BmError> {
+    let manifest: toml::Value = toml::from_str(cargo_toml).map_err(|e| BmError::Environment(format!("Unable to parse `Cargo.toml`: {}", e)))?;
+    if manifest.get("package").is_none() {
+        return Err(BmError::Environment("`Cargo.toml` is a workspace's, run `init` in one of its packages, or a new directory.".to_owned()));
+    }
+    let existing = manifest.get("dependencies").and_then(|d| d.as_table());
+    let missing: Vec<&(&str, &str)> = dependencies.iter().filter(|(name, _)| !existing.is_some_and(|d| d.contains_key(*name))).collect();
+    let names = missing.iter().map(|(name, _)| *name).collect();
+    if missing.is_empty() {
+        return Ok((cargo_toml.to_owned(), names));
+    }
+
+    // Right under the `[dependencies]` header, or in a new section at the end.
+    let updated = match cargo_toml.lines().position(|l| l.trim() == "[dependencies]") {
+        Some(header) => cargo_toml.lines()
+            .enumerate()
+            .map(|(i, line)| if i == header { format!("{}\n{}", line, dependency_lines(&missing)) } else { format!("{}\n", line) })
+            .collect(),
+        None => format!("{}\n\n[dependencies]\n{}", cargo_toml.trim_end(), dependency_lines(&missing)),
+    };
+    Ok((updated, names))
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), BmError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", parent.display(), e)))?;
+    }
+    fs::write(path, contents).map_err(|e| BmError::Environment(format!("Unable to write `{}`: {}", path.display(), e)))?;
+    output::detail(&format!("Wrote {}", path.display()));
+    Ok(())
+}
+
+pub fn init(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = mounts::current_dir()?;
+    let dir = matches.value_of("DIR").map(|d| current_dir.join(d)).unwrap_or(current_dir);
+    let cargo_toml = dir.join("Cargo.toml");
+    let existing_crate = cargo_toml.is_file();
+    let name = if existing_crate {
+        dir.file_name().and_then(|n| n.to_str()).unwrap_or("").to_owned()
+    } else {
+        crate_name(&dir)?
+    };
+    let Scaffold { kind, dependencies, mut files, script, next } = scaffold(matches, &format!("bm_{}", names::sanitize(&name)));
+    let mut added = Vec::new();
+    let mut skipped = Vec::new();
+    if existing_crate {
+        let contents = fs::read_to_string(&cargo_toml).map_err(|e| BmError::Environment(format!("Unable to read `Cargo.toml`: {}", e)))?;
+        let (updated, missing) = add_dependencies(&contents, dependencies)?;
+        skipped = files.iter().map(|(f, _)| *f).filter(|f| dir.join(f).exists()).collect();
+        files.retain(|(f, _)| !skipped.contains(f));
+        if files.is_empty() && missing.is_empty() {
+            return Err(BmError::Environment(format!("`{}` already has everything `init` would add.", dir.display())));
+        }
+        if !missing.is_empty() {
+            write(&cargo_toml, &updated)?;
+            added = missing;
+        }
+    } else {
+        files.insert(0, ("Cargo.toml", CARGO_TOML.replace("{{name}}", &name)
+            .replace("{{dependencies}}", &dependency_lines(&dependencies.iter().collect::<Vec<_>>()))));
+        files.push((".gitignore", GITIGNORE.to_owned()));
+        let existing: Vec<&str> = files.iter().map(|(f, _)| *f).filter(|f| dir.join(f).exists()).collect();
+        if !existing.is_empty() {
+            return Err(BmError::Environment(format!(
+                "`{}` already has {}, and no `Cargo.toml` for `init` to add to.", dir.display(), existing.join(", "))));
+        }
+    }
+
+    for (file, contents) in &files {
+        write(&dir.join(file), contents)?;
+    }
+    if files.iter().any(|(f, _)| *f == script) {
+        make_executable(&dir.join(script));
+    }
+
+    if existing_crate {
+        status!("Set up {} in {}.", name, dir.display());
+        if !added.is_empty() {
+            status!("Added to `Cargo.toml`'s dependencies: {}.", added.join(", "));
+        }
+        if !skipped.is_empty() {
+            status!("Already there, so left as they were: {}.", skipped.join(", "));
+        }
+    } else {
+        status!("Created the {} project in {}.", name, dir.display());
+    }
+    status!("{}", next);
+    if output::is_json() {
+        output::emit("done", json!({
+            "project": name,
+            "dir": dir,
+            "template": kind,
+            "files": files.iter().map(|(f, _)| *f).collect::<Vec<_>>(),
+            "dependencies_added": added,
+            "skipped": skipped,
+        }));
+    }
+    Ok(())
+}
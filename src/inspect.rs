@@ -0,0 +1,237 @@
+//! The `inspect` subcommand: what exactly is inside an artifact, before it's deployed.
+//!
+//! Takes a Lambda zip, a docker tarball, or a local image. It lists the contents, reads how each executable is linked, and
+//! shows the manifest written next to the artifact, checking it against the project's current source. Images also get their
+//! configuration (entrypoint, ports, environment, user) shown.
+
+use crate::archive;
+use crate::archive::Entry;
+use crate::archive::EntryKind;
+use crate::archive::Linkage;
+use crate::cas;
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use crate::registry;
+use crate::runtime::Runtime;
+use clap::ArgMatches;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+fn format_size(size: u64) -> String {
+    match size {
+        s if s < 1024 => format!("{} B", s),
+        s if s < 1024 * 1024 => format!("{:.1} KiB", s as f64 / 1024.0),
+        s => format!("{:.1} MiB", s as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+fn format_mode(entry: &Entry) -> String {
+    let kind = match entry.kind {
+        EntryKind::File => '-',
+        EntryKind::Dir => 'd',
+        EntryKind::Symlink => 'l',
+    };
+    let mode = match entry.mode {
+        Some(m) => m,
+        None => return format!("{}?????????", kind),
+    };
+    let bits: String = (0..9)
+        .map(|i| if mode & (0o400 >> i) != 0 { ['r', 'w', 'x'][i % 3] } else { '-' })
+        .collect();
+    format!("{}{}", kind, bits)
+}
+
+/// Reads an artifact file, returning its entries and where its manifest would be.
+fn read_file(path: &Path) -> Result<(Vec<Entry>, PathBuf), BmError> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let data = fs::read(path).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", path.display(), e)))?;
+    let unreadable = |e| BmError::Packaging(format!("Unable to read `{}`: {}", path.display(), e));
+
+    let (entries, stem) = if let Some(stem) = name.strip_suffix(".zip") {
+        (archive::read_zip(&data).map_err(unreadable)?, stem)
+    } else if let Some(stem) = name.strip_suffix(".tar.gz") {
+        (archive::gunzip(&data).and_then(|t| archive::read_tar(&t)).map_err(unreadable)?, stem)
+    } else if let Some(stem) = name.strip_suffix(".tar") {
+        (archive::read_tar(&data).map_err(unreadable)?, stem)
+    } else {
+        return Err(BmError::Environment(format!("`{}` isn't an artifact, expected a `.zip`, `.tar.gz`, or an image.", path.display())));
+    };
+    Ok((entries, path.with_file_name(format!("{}.manifest.json", stem))))
+}
+
+/// Reads the filesystem of a local image, by exporting a container created from it.
+pub fn read_image(runtime: Runtime, image: &str) -> Result<Vec<Entry>, BmError> {
+    // Scratch images have no command to create a container with, but it's never started anyway.
+    let created = runtime.command()
+        .arg("create")
+        .arg(image)
+        .arg("/")
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to create a container from `{}`: {}", image, e)))?;
+    if !created.status.success() {
+        return Err(BmError::Docker(format!(
+            "Unable to create a container from `{}`.\n\nstderr: {}", image, String::from_utf8_lossy(&created.stderr))));
+    }
+    let container = String::from_utf8_lossy(&created.stdout).trim().to_owned();
+
+    let exported = runtime.command().arg("export").arg(&container).output();
+    let _ = runtime.command().arg("rm").arg(&container).output();
+    let exported = exported.map_err(|e| BmError::Docker(format!("Unable to export `{}`: {}", image, e)))?;
+    if !exported.status.success() {
+        return Err(BmError::Docker(format!("Unable to export `{}`.\n\nstderr: {}", image, String::from_utf8_lossy(&exported.stderr))));
+    }
+    archive::read_tar(&exported.stdout).map_err(|e| BmError::Docker(format!("Unable to read the export of `{}`: {}", image, e)))
+}
+
+fn image_config(runtime: Runtime, image: &str) -> Result<Value, BmError> {
+    let inspected = runtime.command()
+        .arg("image")
+        .arg("inspect")
+        .arg("--format")
+        .arg("{{json .Config}}")
+        .arg(image)
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to inspect `{}`: {}", image, e)))?;
+    Ok(serde_json::from_slice(&inspected.stdout).unwrap_or(Value::Null))
+}
+
+/// Runs the `inspect` subcommand.
+pub fn inspect(matches: &ArgMatches) -> Result<(), BmError> {
+    let target = matches.value_of("ARTIFACT").unwrap();
+    let current_dir = crate::mounts::current_dir()?;
+
+    let (entries, manifest_path, config) = if Path::new(target).is_file() {
+        let (entries, manifest_path) = read_file(Path::new(target))?;
+        (entries, manifest_path, None)
+    } else {
+        let runtime = Runtime::detect(matches.value_of("RUNTIME"))?;
+        if !runtime.image_exists(target)? {
+            return Err(BmError::Environment(format!("`{}` is neither an artifact file nor a local image.", target)));
+        }
+        // Images are named after the artifact, whose manifest is in the project's `target/black_magic`.
+        let (repository, _) = registry::split_tag(target);
+        let artifact = repository.rsplit('/').next().unwrap_or(repository).trim_start_matches("bm_");
+        let manifest_path = current_dir.join("target").join("black_magic").join(format!("{}.manifest.json", artifact));
+        (read_image(runtime, target)?, manifest_path, Some(image_config(runtime, target)?))
+    };
+
+    let executables: Vec<(&str, Linkage)> = entries
+        .iter()
+        .filter(|e| e.kind == EntryKind::File)
+        .filter_map(|e| archive::elf_linkage(&e.contents).map(|l| (e.path.as_str(), l)))
+        .collect();
+
+    let manifest: Option<Value> = fs::read_to_string(&manifest_path).ok().and_then(|m| serde_json::from_str(&m).ok());
+    // Only comparable when run from the project that built it.
+    let source_matches = manifest.as_ref().and_then(|m| {
+        let fingerprint = m["source_fingerprint"].as_str()?;
+        let project = m["project"].as_str()?;
+        let here = current_dir.file_name().and_then(|n| n.to_str())?;
+        if here == project && current_dir.join("Cargo.toml").exists() {
+            Some(cas::fingerprint(&current_dir, "") == fingerprint)
+        } else {
+            None
+        }
+    });
+
+    if output::is_json() {
+        output::emit("inspect", json!({
+            "artifact": target,
+            "entries": entries.iter().map(|e| json!({
+                "path": e.path,
+                "kind": match e.kind { EntryKind::File => "file", EntryKind::Dir => "dir", EntryKind::Symlink => "symlink" },
+                "size": if e.kind == EntryKind::File { Some(e.contents.len()) } else { None },
+                "mode": e.mode,
+            })).collect::<Vec<_>>(),
+            "executables": executables.iter().map(|(path, linkage)| match linkage {
+                Linkage::Static => json!({ "path": path, "linkage": "static" }),
+                Linkage::StaticPie => json!({ "path": path, "linkage": "static-pie" }),
+                Linkage::Dynamic(i) => json!({ "path": path, "linkage": "dynamic", "interpreter": i }),
+            }).collect::<Vec<_>>(),
+            "manifest": manifest,
+            "source_matches": source_matches,
+            "image_config": config,
+        }));
+        return Ok(());
+    }
+
+    status!("Contents of {}:", target);
+    let mut total = 0;
+    let mut files = 0;
+    for e in &entries {
+        match e.kind {
+            EntryKind::File => {
+                total += e.contents.len() as u64;
+                files += 1;
+                status!("    {} {:>10}  {}", format_mode(e), format_size(e.contents.len() as u64), e.path);
+            }
+            EntryKind::Dir => status!("    {} {:>10}  {}/", format_mode(e), "", e.path),
+            EntryKind::Symlink => status!("    {} {:>10}  {} -> {}", format_mode(e), "", e.path, String::from_utf8_lossy(&e.contents)),
+        }
+    }
+    status!("    {} files, {}", files, format_size(total));
+
+    status!("");
+    if executables.is_empty() {
+        status!("No executables found.");
+    }
+    for (path, linkage) in &executables {
+        match linkage {
+            Linkage::Static => status!("Executable {}: statically linked", path),
+            Linkage::StaticPie => status!("Executable {}: statically linked, position independent (static-PIE)", path),
+            Linkage::Dynamic(i) => status!("Executable {}: dynamically linked, needs {}", path, i),
+        }
+    }
+
+    status!("");
+    match &manifest {
+        Some(m) => {
+            status!("Manifest ({}):", manifest_path.display());
+            status!("    Project: {} {}", m["project"].as_str().unwrap_or("?"), m["version"].as_str().unwrap_or(""));
+            status!("    Target:  {}, `{}` profile", m["target"].as_str().unwrap_or("?"), m["profile"].as_str().unwrap_or("?"));
+            let environment = &m["environment"];
+            if environment.is_object() {
+                // The host backends don't compile in a builder image.
+                let builder = match environment["builder_image"].as_str() {
+                    Some(image) => image.to_owned(),
+                    None => format!("the host, with the `{}` backend", environment["backend"].as_str().unwrap_or("?")),
+                };
+                status!(
+                    "    Built:   black_magic {}, {}, in {}",
+                    environment["black_magic"].as_str().unwrap_or("?"),
+                    environment["rustc"]["version"].as_str().unwrap_or("unknown rustc"),
+                    builder);
+            }
+            if !m["hardening"].is_null() {
+                status!("    Hardening: {}", m["hardening"]);
+            }
+        }
+        None => status!("No manifest found at {}.", manifest_path.display()),
+    }
+    match source_matches {
+        Some(true) => status!("Source: unchanged since this was built."),
+        Some(false) => status!("Source: changed since this was built."),
+        None => {}
+    }
+
+    if let Some(config) = config.as_ref().filter(|c| c.is_object()) {
+        status!("");
+        status!("Image config:");
+        for (label, key) in &[("Entrypoint", "Entrypoint"), ("Cmd", "Cmd"), ("User", "User"), ("Working dir", "WorkingDir")] {
+            if !config[key].is_null() && config[key] != "" {
+                status!("    {}: {}", label, config[key]);
+            }
+        }
+        if let Some(ports) = config["ExposedPorts"].as_object() {
+            status!("    Exposed: {}", ports.keys().cloned().collect::<Vec<_>>().join(", "));
+        }
+        for variable in config["Env"].as_array().into_iter().flatten() {
+            status!("    Env: {}", variable.as_str().unwrap_or(""));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! The optional integration test stage for docker mode, configured in `BlackMagic.toml`:
+//! ```toml
+//! [integration_test]
+//! image = "curlimages/curl"
+//! command = "curl --retry 10 --retry-connrefused -f http://app:8080/health"
+//! env = { DATABASE_URL = "postgres://postgres:postgres@db/postgres" }
+//!
+//! [integration_test.services.db]
+//! image = "postgres:15"
+//! env = { POSTGRES_PASSWORD = "postgres" }
+//! ```
+//! The freshly built image runs as the `app` service alongside the declared services, and `command` runs in a
+//! separate container on the same network. The test passes if `command` exits successfully.
+
+use crate::error::BmError;
+use crate::runtime::Runtime;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+pub struct IntegrationTest {
+    /// The image `command` runs in.
+    #[serde(default = "default_image")]
+    pub image: String,
+    pub command: String,
+    /// Arguments for the project's executable.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment for the project's container.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub services: BTreeMap<String, Service>,
+}
+
+#[derive(Deserialize)]
+pub struct Service {
+    pub image: String,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+fn default_image() -> String {
+    "busybox".to_owned()
+}
+
+impl IntegrationTest {
+    /// Runs the test against `image`, always tearing everything down afterwards.
+    /// `project_name` is the executable's name in the image, and `name` what the compose project is named after.
+    pub fn run(&self, runtime: Runtime, bm_dir: &Path, project_name: &str, name: &str, image: &str) -> Result<(), BmError> {
+        let mut services = serde_json::Map::new();
+        for (name, service) in &self.services {
+            services.insert(name.clone(), json!({
+                "image": service.image,
+                "environment": service.env,
+            }));
+        }
+
+        let mut app_command = vec![format!("/{}", project_name)];
+        app_command.extend(self.args.iter().cloned());
+        services.insert("app".to_owned(), json!({
+            "image": image,
+            "command": app_command,
+            "environment": self.env,
+            "depends_on": self.services.keys().collect::<Vec<_>>(),
+        }));
+
+        services.insert("test".to_owned(), json!({
+            "image": self.image,
+            "command": ["sh", "-c", self.command],
+            "depends_on": ["app"],
+        }));
+
+        // Compose files are YAML, which JSON is a subset of.
+        let compose_file = bm_dir.join("docker-compose.test.json");
+        let compose = json!({ "services": services });
+        fs::write(&compose_file, serde_json::to_string_pretty(&compose).unwrap())
+            .map_err(|e| BmError::Environment(format!("Unable to write compose file: {}", e)))?;
+
+        let compose_project = format!("bm_test_{}", name);
+        let compose_cmd = |args: &[&str]| {
+            let mut cmd = runtime.command();
+            cmd.arg("compose")
+                .arg("-f")
+                .arg(&compose_file)
+                .arg("-p")
+                .arg(&compose_project)
+                .args(args);
+            cmd
+        };
+
+        /*
+        Run the test:
+            - stop everything once the test container exits
+            - with the test container's exit code
+        */
+        let up = compose_cmd(&["up", "--abort-on-container-exit", "--exit-code-from", "test"])
+            .output()
+            .map_err(|e| BmError::Environment(format!("Unable to run `{} compose`: {}. Is compose installed?", runtime.name(), e)))?;
+
+        // Remove the containers and any volumes the services created.
+        compose_cmd(&["down", "-v"]).output().map_err(|e| BmError::Docker(format!("Unable to tear down integration test: {}", e)))?;
+
+        if !up.status.success() {
+            return Err(BmError::Test(format!(
+                "Integration test failed.\nstdout: {}\nstderr: {}",
+                String::from_utf8_lossy(&up.stdout), String::from_utf8_lossy(&up.stderr))));
+        }
+        Ok(())
+    }
+}
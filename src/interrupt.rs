@@ -0,0 +1,67 @@
+//! Ctrl-C (or `SIGTERM`) in the middle of a build.
+//!
+//! `docker run` exits on Ctrl-C, but the build container doesn't: the shell running the build ignores the signal, so cargo
+//! would carry on in the background. Instead, the build container is removed, along with the partial artifact, and
+//! black_magic exits with 130 (128 + `SIGINT`), which CI can tell apart from a failed build.
+//!
+//! Builds register what needs cleaning up while it's being made, see `cleanup_container` and `cleanup_file`. Ctrl-C is
+//! handled with `ctrlc`, so it's Ctrl-C or Ctrl-Break on Windows, and `SIGTERM` and `SIGHUP` too on unix.
+
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+
+pub const EXIT_CODE: i32 = 130;
+
+struct Cleanup {
+    container: Option<(Runtime, String)>,
+    files: Vec<PathBuf>,
+}
+
+static CLEANUP: Mutex<Cleanup> = Mutex::new(Cleanup { container: None, files: Vec::new() });
+
+/// Handles Ctrl-C from now on. It's run on `ctrlc`'s own thread, so it can take the lock and run the runtime.
+pub fn install() {
+    if let Err(e) = ctrlc::set_handler(|| clean_up()) {
+        output::warning(&format!("Unable to handle Ctrl-C, so it won't remove the build container: {}", e));
+    }
+}
+
+fn clean_up() -> ! {
+    let cleanup = CLEANUP.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((runtime, name)) = &cleanup.container {
+        status!("Interrupted, removing the build container...");
+        let _ = runtime.command().arg("rm").arg("--force").arg(name).output();
+    }
+    for file in &cleanup.files {
+        let _ = fs::remove_file(file);
+    }
+    eprintln!("Interrupted.");
+    if output::is_json() {
+        output::emit("error", json!({ "kind": "interrupted", "exit_code": EXIT_CODE, "message": "Interrupted." }));
+    }
+    output::marker("ERROR", &[("kind", "interrupted"), ("exit_code", &EXIT_CODE.to_string())]);
+    process::exit(EXIT_CODE);
+}
+
+/// Removes the container `name` on Ctrl-C, until `finished`.
+pub fn cleanup_container(runtime: Runtime, name: &str) {
+    CLEANUP.lock().unwrap_or_else(|e| e.into_inner()).container = Some((runtime, name.to_owned()));
+}
+
+/// Removes `file` on Ctrl-C, until `finished`.
+pub fn cleanup_file(file: PathBuf) {
+    CLEANUP.lock().unwrap_or_else(|e| e.into_inner()).files.push(file);
+}
+
+/// Nothing needs cleaning up any more, e.g. once the artifact is complete.
+pub fn finished() {
+    let mut cleanup = CLEANUP.lock().unwrap_or_else(|e| e.into_inner());
+    cleanup.container = None;
+    cleanup.files.clear();
+}
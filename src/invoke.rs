@@ -0,0 +1,253 @@
+//! The `invoke` subcommand: smoke testing the exact Lambda zip that would be deployed, locally.
+//!
+//! The zip is unpacked into `target/black_magic/invoke/`, and mounted as the task root of a container from Lambda's own
+//! `provided` base image, which has the Runtime Interface Emulator. Its `bootstrap` is started the way Lambda would, the
+//! payload is posted to the emulator's invoke endpoint, and the function's response is printed, followed by its logs.
+//! A function error fails with the test exit code.
+
+use crate::archive;
+use crate::archive::EntryKind;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::lambda_runtime::LambdaRuntime;
+use crate::names;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use crate::Arch;
+use clap::ArgMatches;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::Component;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const INVOKE_PATH: &str = "/2015-03-31/functions/function/invocations";
+
+/// How long the emulator gets to start listening.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode));
+}
+
+/// Other hosts' mounts don't have unix permissions, docker makes everything executable.
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) {}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn symlink(target: &str, path: &Path) -> std::io::Result<()> {
+    fs::write(path, target)
+}
+
+/// Unpacks the zip at `zip` into `dir`, replacing whatever was there.
+fn unpack(zip: &Path, dir: &Path) -> Result<(), BmError> {
+    let data = fs::read(zip).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}. Has it been built?", zip.display(), e)))?;
+    let entries = archive::read_zip(&data).map_err(|e| BmError::Packaging(format!("Unable to read `{}`: {}", zip.display(), e)))?;
+    if !entries.iter().any(|e| e.path == "bootstrap") {
+        return Err(BmError::Packaging(format!("`{}` has no `bootstrap`, so Lambda couldn't run it.", zip.display())));
+    }
+
+    if dir.exists() {
+        fs::remove_dir_all(dir).map_err(|e| BmError::Environment(format!("Unable to clear `{}`: {}", dir.display(), e)))?;
+    }
+    let unpack_failed = |path: &Path, e: std::io::Error| BmError::Environment(format!("Unable to unpack `{}`: {}", path.display(), e));
+    for entry in &entries {
+        if !Path::new(&entry.path).components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(BmError::Packaging(format!("`{}` has an entry outside of the task root: `{}`.", zip.display(), entry.path)));
+        }
+        let path = dir.join(&entry.path);
+        let parent = path.parent().unwrap_or(dir);
+        fs::create_dir_all(parent).map_err(|e| unpack_failed(parent, e))?;
+        match entry.kind {
+            EntryKind::Dir => fs::create_dir_all(&path).map_err(|e| unpack_failed(&path, e))?,
+            EntryKind::Symlink => symlink(&String::from_utf8_lossy(&entry.contents), &path).map_err(|e| unpack_failed(&path, e))?,
+            EntryKind::File => {
+                fs::write(&path, &entry.contents).map_err(|e| unpack_failed(&path, e))?;
+                set_mode(&path, entry.mode.unwrap_or(0o644));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body.
+fn dechunk(body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let mut rest = body;
+    while let Some(line_end) = rest.windows(2).position(|w| w == b"\r\n") {
+        let size = usize::from_str_radix(String::from_utf8_lossy(&rest[..line_end]).trim(), 16).unwrap_or(0);
+        let start = line_end + 2;
+        if size == 0 || start + size > rest.len() {
+            break;
+        }
+        decoded.extend_from_slice(&rest[start..start + size]);
+        rest = &rest[(start + size + 2).min(rest.len())..];
+    }
+    decoded
+}
+
+/// Posts `payload` to the emulator at `port`, returning the response body. `None` if it isn't listening yet.
+fn post(port: u16, payload: &[u8]) -> Option<Result<Vec<u8>, String>> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        INVOKE_PATH, payload.len());
+    stream.write_all(request.as_bytes()).ok()?;
+    stream.write_all(payload).ok()?;
+    let mut response = Vec::new();
+    // A port that's published but not listening inside the container yet accepts, then closes without a response.
+    if stream.read_to_end(&mut response).is_err() || response.is_empty() {
+        return None;
+    }
+
+    let header_end = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => i,
+        None => return Some(Err("The emulator sent a malformed response.".to_owned())),
+    };
+    let headers = String::from_utf8_lossy(&response[..header_end]).to_lowercase();
+    let body = &response[header_end + 4..];
+    let status = headers.lines().next().unwrap_or("").split_whitespace().nth(1).unwrap_or("").to_owned();
+    let body = if headers.contains("transfer-encoding: chunked") { dechunk(body) } else { body.to_vec() };
+    if status != "200" {
+        return Some(Err(format!("The emulator responded with {}: {}", status, String::from_utf8_lossy(&body))));
+    }
+    Some(Ok(body))
+}
+
+/// A port nothing is listening on right now, for the emulator.
+fn free_port() -> Result<u16, BmError> {
+    TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|l| l.local_addr())
+        .map(|a| a.port())
+        .map_err(|e| BmError::Environment(format!("Unable to find a free port: {}", e)))
+}
+
+/// Runs the `invoke` subcommand.
+pub fn invoke(matches: &ArgMatches) -> Result<(), BmError> {
+    let started = Instant::now();
+    let runtime = Runtime::detect(matches.value_of("RUNTIME"))?;
+    mounts::check_local(runtime, "`invoke`")?;
+    let current_dir = mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+    let arch = Arch::from_name(matches.value_of("ARCH").unwrap());
+    let lambda_runtime = match matches.value_of("LAMBDA_RUNTIME").or(config.lambda.runtime.as_deref()) {
+        Some(r) => Some(LambdaRuntime::from_name(r).ok_or_else(|| BmError::Environment(format!("`{}` isn't a supported Lambda runtime.", r)))?),
+        None => None,
+    };
+
+    let zip = match matches.value_of("ARTIFACT") {
+        Some(a) => current_dir.join(a),
+        None => {
+            let name = names::resolve(crate::project_name(&current_dir)?, matches.value_of("NAME").or(config.name.as_deref()))?;
+            current_dir.join("target").join("black_magic").join(format!("{}{}{}.zip", name, arch.suffix(), lambda_runtime.map(|r| r.suffix()).unwrap_or("")))
+        }
+    };
+    let payload = match matches.value_of("PAYLOAD") {
+        Some("-") => {
+            let mut payload = Vec::new();
+            std::io::stdin().read_to_end(&mut payload).map_err(|e| BmError::Environment(format!("Unable to read the payload: {}", e)))?;
+            payload
+        }
+        Some(p) => fs::read(p).map_err(|e| BmError::Environment(format!("Unable to read the payload `{}`: {}", p, e)))?,
+        None => b"{}".to_vec(),
+    };
+    if serde_json::from_slice::<Value>(&payload).is_err() {
+        return Err(BmError::Environment("The payload isn't valid JSON.".to_owned()));
+    }
+
+    let stem = zip.file_stem().and_then(|s| s.to_str()).unwrap_or("function").to_owned();
+    let task_dir = current_dir.join("target").join("black_magic").join("invoke").join(&stem);
+    unpack(&zip, &task_dir)?;
+
+    /*
+    Run the function:
+        - detached, removed when stopped
+        - the emulator's port published on localhost only
+        - for the artifact's architecture
+        - the unpacked zip as the task root, read-only like on Lambda
+        - any `--env`
+    */
+    let port = free_port()?;
+    let mut cmd = runtime.command();
+    cmd.arg("run")
+        .arg("-d")
+        .arg("--rm")
+        .arg("-p")
+        .arg(format!("127.0.0.1:{}:8080", port));
+    if let Some(p) = arch.platform() {
+        cmd.arg("--platform").arg(p);
+    }
+    cmd.arg("-v").arg(format!("{}:ro", mounts::volume(runtime, &task_dir, "/var/task")?));
+    for env in matches.values_of("ENV").into_iter().flatten() {
+        cmd.arg("-e").arg(env);
+    }
+    let image = matches.value_of("IMAGE").unwrap_or_else(|| lambda_runtime.unwrap_or(LambdaRuntime::Al2023).image());
+    cmd.arg(runtime.qualify(image)).arg("bootstrap");
+
+    status!("Starting {} in the Lambda emulator...", stem);
+    output::detail(&format!("Running {:?}", cmd));
+    let run = cmd.output().map_err(|e| BmError::Docker(format!("Unable to start the Lambda emulator: {}", e)))?;
+    if !run.status.success() {
+        return Err(BmError::Docker(format!("Unable to start the Lambda emulator.\n\nstderr: {}", String::from_utf8_lossy(&run.stderr))));
+    }
+    let container = String::from_utf8_lossy(&run.stdout).trim().to_owned();
+
+    let waiting = Instant::now();
+    let response = loop {
+        if let Some(response) = post(port, &payload) {
+            break response;
+        }
+        if waiting.elapsed() > STARTUP_TIMEOUT {
+            break Err(format!("The Lambda emulator didn't start listening within {}s.", STARTUP_TIMEOUT.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(200));
+    };
+
+    let logs = runtime.command().arg("logs").arg(&container).output().map(|l| {
+        format!("{}{}", String::from_utf8_lossy(&l.stdout), String::from_utf8_lossy(&l.stderr))
+    });
+    let _ = runtime.command().arg("rm").arg("-f").arg(&container).output();
+    let logs = logs.unwrap_or_default();
+    let response = response.map_err(BmError::Test)?;
+    let response_text = String::from_utf8_lossy(&response).into_owned();
+    let parsed: Option<Value> = serde_json::from_slice(&response).ok();
+    let function_error = parsed.as_ref().and_then(|r| r.get("errorType").or_else(|| r.get("errorMessage"))).is_some();
+
+    if output::is_json() {
+        output::emit("done", json!({
+            "artifact": zip,
+            "response": parsed.clone().unwrap_or_else(|| Value::String(response_text.clone())),
+            "function_error": function_error,
+            "logs": logs,
+            "duration_seconds": started.elapsed().as_secs_f64(),
+        }));
+    } else {
+        println!("{}", response_text);
+        status!("");
+        status!("Logs:");
+        for line in logs.lines() {
+            status!("    {}", line);
+        }
+    }
+
+    if function_error {
+        return Err(BmError::Test(format!("The function returned an error: {}", response_text)));
+    }
+    Ok(())
+}
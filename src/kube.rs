@@ -0,0 +1,53 @@
+//! Loading built images straight into a local Kubernetes cluster, skipping the registry.
+
+use crate::error::BmError;
+use std::process::Command;
+
+pub enum LocalCluster {
+    /// A `kind` cluster, by name.
+    Kind(String),
+    /// A `minikube` profile, if not the default one.
+    Minikube(Option<String>),
+}
+
+impl LocalCluster {
+    /// Parses `kind:<cluster>`, `kind`, `minikube:<profile>`, or `minikube`.
+    pub fn parse(value: &str) -> Result<LocalCluster, String> {
+        let mut parts = value.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let name = parts.next().filter(|n| !n.is_empty()).map(|n| n.to_owned());
+
+        match kind {
+            "kind" => Ok(LocalCluster::Kind(name.unwrap_or_else(|| "kind".to_owned()))),
+            "minikube" => Ok(LocalCluster::Minikube(name)),
+            _ => Err(format!("`{}` isn't a supported cluster, expected `kind:<cluster>` or `minikube[:<profile>]`.", value)),
+        }
+    }
+
+    /// Copies `image` into the cluster's node image store.
+    pub fn load(&self, image: &str) -> Result<(), BmError> {
+        let mut cmd = match self {
+            LocalCluster::Kind(name) => {
+                let mut cmd = Command::new("kind");
+                cmd.arg("load").arg("docker-image").arg(image).arg("--name").arg(name);
+                cmd
+            }
+            LocalCluster::Minikube(profile) => {
+                let mut cmd = Command::new("minikube");
+                cmd.arg("image").arg("load").arg(image);
+                if let Some(p) = profile {
+                    cmd.arg("-p").arg(p);
+                }
+                cmd
+            }
+        };
+
+        let output = cmd.output().map_err(|e| BmError::Environment(format!("Unable to run `{:?}`: {}. Is it installed?", cmd, e)))?;
+        if !output.status.success() {
+            return Err(BmError::Publish(format!(
+                "Unable to load `{}` into the cluster.\nstdout: {}\nstderr: {}",
+                image, String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+//! `--lambda-runtime`: which of Lambda's OS-only runtimes a zip is built for, `provided.al2` or `provided.al2023`.
+//!
+//! They differ in their glibc, so the built zip is checked to be runnable on the chosen one: statically linked, or needing no
+//! newer glibc than it has. The runtime is recorded in the manifest and the artifact's name, checked against the function's
+//! configuration when deploying, and picks the image `invoke` runs the zip in.
+
+use crate::archive;
+use crate::archive::Entry;
+use crate::archive::EntryKind;
+use crate::archive::Linkage;
+use crate::error::BmError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LambdaRuntime {
+    Al2,
+    Al2023,
+}
+
+pub const NAMES: &[&str] = &["provided.al2", "provided.al2023"];
+
+impl LambdaRuntime {
+    pub fn from_name(name: &str) -> Option<LambdaRuntime> {
+        match name {
+            "provided.al2" => Some(LambdaRuntime::Al2),
+            "provided.al2023" => Some(LambdaRuntime::Al2023),
+            _ => None,
+        }
+    }
+
+    /// `--lambda-runtime`'s, or else `runtime` in `[lambda]`'s.
+    pub fn select(requested: Option<LambdaRuntime>, configured: Option<&str>) -> Result<Option<LambdaRuntime>, BmError> {
+        match (requested, configured) {
+            (Some(runtime), _) => Ok(Some(runtime)),
+            (None, Some(name)) => LambdaRuntime::from_name(name).map(Some).ok_or_else(|| BmError::Environment(format!(
+                "`{}` in `BlackMagic.toml` isn't a supported Lambda runtime, expected one of: {}.", name, NAMES.join(", ")))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// As Lambda names it, e.g. in a function's configuration.
+    pub fn name(self) -> &'static str {
+        match self {
+            LambdaRuntime::Al2 => "provided.al2",
+            LambdaRuntime::Al2023 => "provided.al2023",
+        }
+    }
+
+    /// Appended to artifact names, after the architecture's.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            LambdaRuntime::Al2 => "-al2",
+            LambdaRuntime::Al2023 => "-al2023",
+        }
+    }
+
+    /// Lambda's base image for the runtime, with its Runtime Interface Emulator.
+    pub fn image(self) -> &'static str {
+        match self {
+            LambdaRuntime::Al2 => "public.ecr.aws/lambda/provided:al2",
+            LambdaRuntime::Al2023 => "public.ecr.aws/lambda/provided:al2023",
+        }
+    }
+
+    /// The version of glibc the runtime has.
+    fn glibc(self) -> (u32, u32) {
+        match self {
+            LambdaRuntime::Al2 => (2, 26),
+            LambdaRuntime::Al2023 => (2, 34),
+        }
+    }
+
+    /// Checks each executable in the zip's `entries` can run on the runtime, returning why not.
+    pub fn check(self, entries: &[Entry]) -> Vec<String> {
+        let mut problems = Vec::new();
+        for entry in entries.iter().filter(|e| e.kind == EntryKind::File) {
+            if let Some(Linkage::Dynamic(interpreter)) = archive::elf_linkage(&entry.contents) {
+                match required_glibc(&entry.contents) {
+                    Some(required) if required > self.glibc() => problems.push(format!(
+                        "`{}` needs glibc {}.{}, but {} only has {}.{}.",
+                        entry.path, required.0, required.1, self.name(), self.glibc().0, self.glibc().1)),
+                    Some(_) => {}
+                    None => problems.push(format!(
+                        "`{}` is dynamically linked against {}, which {} doesn't have.", entry.path, interpreter, self.name())),
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// The newest `GLIBC_x.y` symbol version an executable refers to, or `None` if it doesn't use glibc.
+fn required_glibc(data: &[u8]) -> Option<(u32, u32)> {
+    const PREFIX: &[u8] = b"GLIBC_";
+    let mut newest = None;
+    for (i, _) in data.windows(PREFIX.len()).enumerate().filter(|(_, w)| *w == PREFIX) {
+        let version: String = data[i + PREFIX.len()..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit() || **b == b'.')
+            .map(|b| *b as char)
+            .collect();
+        let mut parts = version.split('.').map(|p| p.parse::<u32>());
+        if let (Some(Ok(major)), Some(Ok(minor))) = (parts.next(), parts.next()) {
+            newest = newest.max(Some((major, minor)));
+        }
+    }
+    newest
+}
@@ -0,0 +1,36 @@
+//! A record of the last compile, for post-mortems: its whole stdout and stderr in `target/black_magic/last_build.out` and
+//! `last_build.err`, and in `last_build.json` the exact command, how it exited, and how long it took.
+//!
+//! With `--keep-on-failure`, a failed build container isn't removed either, so what it left behind can be inspected,
+//! e.g. with `docker diff` or `docker cp`. It's named in `last_build.json`, and it's up to you to remove it.
+
+use crate::error::BmError;
+use crate::scheduler;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::process::Output;
+
+/// Records `built`, the output of running `cmd`, in `bm_dir`.
+pub fn record(bm_dir: &Path, cmd: &Command, built: &Output, seconds: f64, kept_container: Option<&str>) -> Result<(), BmError> {
+    let write = |file: &str, contents: &[u8]| {
+        fs::write(bm_dir.join(file), contents).map_err(|e| BmError::Environment(format!("Unable to write `target/black_magic/{}`: {}", file, e)))
+    };
+    // Named after the build when it's one of several at once, see `scheduler`.
+    let name = scheduler::job_file("last_build");
+    let (out, err) = (format!("{}.out", name), format!("{}.err", name));
+    write(&out, &built.stdout)?;
+    write(&err, &built.stderr)?;
+    let record = json!({
+        "command": std::iter::once(cmd.get_program()).chain(cmd.get_args()).map(|a| a.to_string_lossy()).collect::<Vec<_>>(),
+        "command_line": crate::command_line(cmd),
+        "success": built.status.success(),
+        "exit_code": built.status.code(),
+        "seconds": seconds,
+        "stdout": format!("target/black_magic/{}", out),
+        "stderr": format!("target/black_magic/{}", err),
+        "kept_container": kept_container,
+    });
+    write(&format!("{}.json", name), serde_json::to_string_pretty(&record).unwrap().as_bytes())
+}
@@ -0,0 +1,263 @@
+//! `--layered`: compiling with `docker build` instead of `docker run`, so the dependencies are a cached image layer.
+//!
+//! Like `cargo-chef`, the build context has a recipe of the project: its `Cargo.toml`s and `Cargo.lock`, with every target it
+//! builds replaced by an empty one. The first layer compiles just that, which is only all the dependencies. The source is
+//! copied on top of it, and the second layer compiles and packages the project as usual. As long as the manifests and lock file
+//! don't change, the dependency layer is reused, which helps where the cache volume doesn't survive between builds, e.g. CI
+//! runners with a registry-backed layer cache.
+//!
+//! The layers are kept in a `bm_build_<artifact>` image, which `clean --cache` removes. The outputs are copied out of it into
+//! `target/black_magic` afterwards.
+//!
+//! `--strategy dockerfile` builds the same way, with BuildKit cache mounts for cargo's registry and the target dir as well,
+//! so the project itself compiles incrementally too, and nothing is ever mounted from the host. That's for Docker Desktop on
+//! Windows, where bind mounts are slow and mangle permissions. `docker builder prune` removes the cache mounts.
+//!
+//! `--secret`s are BuildKit secret mounts of both steps, so they're never in a layer, see `secrets`.
+
+use crate::bundle::Include;
+use crate::cas;
+use crate::dockerignore::Ignore;
+use crate::error::BmError;
+use crate::retry::Retry;
+use crate::runtime::Runtime;
+use crate::scheduler;
+use std::collections::HashSet;
+use std::fs;
+use std::iter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+
+pub const IMAGE_PREFIX: &str = "bm_build_";
+/// The build context, in `target/black_magic`.
+const CONTEXT_DIR: &str = "layered";
+
+/// The build context's directory in `target/black_magic`.
+pub fn context_dir() -> String {
+    scheduler::job_file(CONTEXT_DIR)
+}
+
+const LIB: &str = "";
+const MAIN: &str = "fn main() {}\n";
+
+/// The files a build needs besides its targets, copied into the recipe as they are.
+fn is_recipe_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let in_cargo_dir = path.parent().and_then(|p| p.file_name()).map(|p| p == ".cargo").unwrap_or(false);
+    matches!(name, "Cargo.toml" | "Cargo.lock" | "rust-toolchain" | "rust-toolchain.toml")
+        || (in_cargo_dir && matches!(name, "config" | "config.toml"))
+}
+
+/// The empty targets standing in for a package's real ones, relative to the project, from its `manifest` in `package_dir`.
+/// Declared paths are always included, while the ones cargo would infer are only included if they exist in `sources`.
+fn dummy_targets(manifest: &toml::Value, package_dir: &Path, sources: &HashSet<PathBuf>) -> Vec<(PathBuf, &'static str)> {
+    let mut targets = Vec::new();
+    let mut add = |path: &str, declared: bool, contents: &'static str| {
+        let path = package_dir.join(path.trim_start_matches("./"));
+        if declared || sources.contains(&path) {
+            targets.push((path, contents));
+        }
+    };
+
+    add("src/lib.rs", false, LIB);
+    add("src/main.rs", false, MAIN);
+    add("build.rs", false, MAIN);
+    let bin_dir = package_dir.join("src").join("bin");
+    for source in sources.iter().filter(|s| s.starts_with(&bin_dir)) {
+        let relative = source.strip_prefix(&bin_dir).unwrap();
+        let is_bin = match relative.components().count() {
+            1 => relative.extension().map(|e| e == "rs").unwrap_or(false),
+            2 => relative.file_name().map(|n| n == "main.rs").unwrap_or(false),
+            _ => false,
+        };
+        if is_bin {
+            add(source.strip_prefix(package_dir).unwrap().to_str().unwrap_or(""), true, MAIN);
+        }
+    }
+
+    if let Some(path) = manifest.get("lib").and_then(|l| l.get("path")).and_then(|p| p.as_str()) {
+        add(path, true, LIB);
+    }
+    if let Some(build) = manifest.get("package").and_then(|p| p.get("build")).and_then(|b| b.as_str()) {
+        add(build, true, MAIN);
+    }
+    for (kind, dir) in &[("bin", "src/bin"), ("example", "examples"), ("test", "tests"), ("bench", "benches")] {
+        for target in manifest.get(kind).and_then(|t| t.as_array()).into_iter().flatten() {
+            match (target.get("path").and_then(|p| p.as_str()), target.get("name").and_then(|n| n.as_str())) {
+                (Some(path), _) => add(path, true, MAIN),
+                (None, Some(name)) => {
+                    add(&format!("{}/{}.rs", dir, name), false, MAIN);
+                    add(&format!("{}/{}/main.rs", dir, name), false, MAIN);
+                }
+                _ => {}
+            }
+        }
+    }
+    targets
+}
+
+fn write(path: &Path, contents: &[u8]) -> Result<(), BmError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", parent.display(), e)))?;
+    }
+    fs::write(path, contents).map_err(|e| BmError::Environment(format!("Unable to write `{}`: {}", path.display(), e)))
+}
+
+/// Copies `relative` (a file or directory) from `from` to `to`, following symlinks.
+fn copy_tree(from: &Path, to: &Path, relative: &Path) -> Result<(), BmError> {
+    let source = from.join(relative);
+    if source.is_dir() {
+        for entry in fs::read_dir(&source).into_iter().flatten().filter_map(|e| e.ok()) {
+            copy_tree(from, to, &relative.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        // Deleted but still tracked files are just left out, as they are from the fingerprint.
+        match fs::read(&source) {
+            Ok(contents) => write(&to.join(relative), &contents),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// Writes the build context into `context_dir`: the recipe in `recipe/`, and the source (including any untracked `includes`
+/// and `generated` files) in `source/`.
+fn write_context(project_dir: &Path, context_dir: &Path, includes: &[Include], generated: &[String]) -> Result<(), BmError> {
+    if context_dir.exists() {
+        fs::remove_dir_all(context_dir).map_err(|e| BmError::Environment(format!("Unable to clear `{}`: {}", context_dir.display(), e)))?;
+    }
+    write_tree(project_dir, &context_dir.join("recipe"), Some(&context_dir.join("source")), includes, generated)
+}
+
+/// Writes just the recipe into `recipe_dir`, e.g. for `black_magic warm` to compile in the build container.
+pub fn write_recipe(project_dir: &Path, recipe_dir: &Path) -> Result<(), BmError> {
+    if recipe_dir.exists() {
+        fs::remove_dir_all(recipe_dir).map_err(|e| BmError::Environment(format!("Unable to clear `{}`: {}", recipe_dir.display(), e)))?;
+    }
+    write_tree(project_dir, recipe_dir, None, &[], &[])
+}
+
+/// Writes the recipe into `recipe_dir`, and with a `source_dir`, the source into that.
+fn write_tree(project_dir: &Path, recipe_dir: &Path, source_dir: Option<&Path>, includes: &[Include], generated: &[String]) -> Result<(), BmError> {
+    let ignore = Ignore::load(project_dir);
+    let mut sources: Vec<PathBuf> = cas::source_files(project_dir).into_iter().filter(|s| !ignore.ignores(s)).collect();
+    // Libraries often don't commit their lock file, but it's what pins the dependencies.
+    if project_dir.join("Cargo.lock").is_file() && !sources.iter().any(|s| s == Path::new("Cargo.lock")) {
+        sources.push(PathBuf::from("Cargo.lock"));
+    }
+    let source_set: HashSet<PathBuf> = sources.iter().cloned().collect();
+
+    for source in &sources {
+        if let Some(source_dir) = source_dir {
+            copy_tree(project_dir, source_dir, source)?;
+        }
+        if is_recipe_file(source) {
+            copy_tree(project_dir, recipe_dir, source)?;
+        }
+        if source.file_name().map(|n| n == "Cargo.toml").unwrap_or(false) {
+            let contents = fs::read_to_string(project_dir.join(source)).unwrap_or_default();
+            let manifest: toml::Value = toml::from_str(&contents)
+                .map_err(|e| BmError::Environment(format!("Unable to parse `{}`: {}", source.display(), e)))?;
+            for (target, contents) in dummy_targets(&manifest, source.parent().unwrap_or(Path::new("")), &source_set) {
+                write(&recipe_dir.join(target), contents.as_bytes())?;
+            }
+        }
+    }
+    for path in includes.iter().map(|i| i.source.as_str()).chain(generated.iter().map(|g| g.as_str())) {
+        copy_tree(project_dir, source_dir.unwrap_or(recipe_dir), Path::new(path))?;
+    }
+    Ok(())
+}
+
+/// What to build, and how.
+pub struct Layers<'a> {
+    pub builder_image: &'a str,
+    pub platform: Option<&'a str>,
+    /// The build container's environment.
+    pub env: &'a [(&'a str, String)],
+    /// Set after the dependencies are compiled, so changing them doesn't compile those again, e.g. the build metadata.
+    pub source_env: &'a [(&'a str, String)],
+    /// Arguments for `docker build`: `--build-arg`s, e.g. the proxy's, which are only there while it builds, and `--network`.
+    pub build_args: &'a [String],
+    /// Shell command compiling the recipe, see `backend::Build::deps_cmd`.
+    pub deps_cmd: String,
+    /// Shell command compiling and packaging the project, into `target/black_magic`.
+    pub build_cmd: &'a str,
+    pub includes: &'a [Include],
+    /// Files black_magic writes into the project for the build, e.g. the `bootstrap` wrapper's source, relative to it.
+    pub generated: &'a [String],
+    /// BuildKit mounts for both steps: caches, e.g. `type=cache,id=...,target=/bm_target` for `--strategy dockerfile`, and
+    /// `--secret`s, whose files `build_args` pass.
+    pub mounts: Vec<String>,
+}
+
+impl Layers<'_> {
+    pub fn dockerfile(&self) -> String {
+        // Mounts need the BuildKit Dockerfile frontend.
+        let syntax = if self.mounts.is_empty() { "" } else { "# syntax=docker/dockerfile:1\n" };
+        let mut dockerfile = format!("{}FROM {}\nSHELL [\"/bin/bash\", \"-c\"]\nWORKDIR /workdir\n", syntax, self.builder_image);
+        for (key, value) in self.env {
+            dockerfile.push_str(&format!("ENV {}={}\n", key, serde_json::to_string(value).unwrap()));
+        }
+        let run: String = iter::once("RUN".to_owned()).chain(self.mounts.iter().map(|m| format!(" --mount={}", m))).collect();
+        dockerfile.push_str(&format!("COPY recipe/ ./\n{} {}\nCOPY source/ ./\n", run, self.deps_cmd));
+        for (key, value) in self.source_env {
+            dockerfile.push_str(&format!("ENV {}={}\n", key, serde_json::to_string(value).unwrap()));
+        }
+        dockerfile.push_str(&format!("{} mkdir -p target/black_magic && {}\n", run, self.build_cmd));
+        dockerfile
+    }
+
+    /// Builds `image` from the context in `context_dir`.
+    pub fn build_cmd(&self, runtime: Runtime, context_dir: &Path, image: &str) -> Command {
+        let mut cmd = runtime.build();
+        if !self.mounts.is_empty() {
+            cmd.env("DOCKER_BUILDKIT", "1");
+        }
+        if let Some(p) = self.platform {
+            cmd.arg("--platform").arg(p);
+        }
+        cmd.args(self.build_args).arg("-t").arg(image).arg(context_dir);
+        cmd
+    }
+
+    /// Builds the layers into `image`, with the context in `bm_dir`, then copies the outputs out into `bm_dir`. Returns the
+    /// build command and its output, so a failed build is explained the same way as any other.
+    pub fn build(&self, runtime: Runtime, project_dir: &Path, bm_dir: &Path, image: &str, retry: &Retry) -> Result<(Command, Output), BmError> {
+        let context_dir = bm_dir.join(context_dir());
+        write_context(project_dir, &context_dir, self.includes, self.generated)?;
+        write(&context_dir.join("Dockerfile"), self.dockerfile().as_bytes())?;
+
+        let mut cmd = self.build_cmd(runtime, &context_dir, image);
+        let built = retry.run("The build", || cmd.output()).map_err(|e| BmError::Docker(format!("Unable to run build command: {}", e)))?;
+        if !built.status.success() {
+            return Ok((cmd, built));
+        }
+
+        let created = runtime.command()
+            .arg("create")
+            .arg(image)
+            .arg("/")
+            .output()
+            .map_err(|e| BmError::Docker(format!("Unable to create a container from `{}`: {}", image, e)))?;
+        if !created.status.success() {
+            return Err(BmError::Docker(format!(
+                "Unable to create a container from `{}`.\n\nstderr: {}", image, String::from_utf8_lossy(&created.stderr))));
+        }
+        let container = String::from_utf8_lossy(&created.stdout).trim().to_owned();
+        let copied = runtime.command()
+            .arg("cp")
+            .arg(format!("{}:/workdir/target/black_magic/.", container))
+            .arg(bm_dir)
+            .output();
+        let _ = runtime.command().arg("rm").arg(&container).output();
+        let copied = copied.map_err(|e| BmError::Docker(format!("Unable to copy the build outputs out of `{}`: {}", image, e)))?;
+        if !copied.status.success() {
+            return Err(BmError::Docker(format!(
+                "Unable to copy the build outputs out of `{}`.\n\nstderr: {}", image, String::from_utf8_lossy(&copied.stderr))));
+        }
+        Ok((cmd, built))
+    }
+}
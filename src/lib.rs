@@ -0,0 +1,1854 @@
+//! black_magic builds Rust projects into AWS Lambda zips and docker images, inside a musl builder container, so they run
+//! anywhere without a Rust toolchain. This is the library the `black_magic` command runs, for building from other Rust
+//! programs too, see `BuildConfig` and `Builder`.
+//!
+//! For details reguarding compilation, see:
+//! https://gitlab.com/rust_musl_docker/image
+//! Updating the image tag may be required from time to time, see `--builder-tag`.
+//! https://gitlab.com/rust_musl_docker/image/container_registry/
+
+mod api;
+mod archive;
+mod archivers;
+mod aws;
+mod backend;
+mod baseline;
+mod bench;
+mod bins;
+mod bounds;
+mod build;
+mod build_env;
+mod builder;
+mod bundle;
+mod cache_server;
+mod cargo_cache;
+mod cargo_config;
+mod cargo_lock;
+mod cas;
+mod changelog;
+mod checksum;
+mod ci;
+mod clean;
+mod companion;
+mod compose;
+mod config;
+mod debug_shell;
+mod diagnostics;
+mod docs;
+mod dockerignore;
+mod doctor;
+mod dual;
+mod ecr;
+mod elf;
+mod engine;
+mod error;
+mod gates;
+mod github;
+mod hardening;
+mod history;
+mod hooks;
+mod inspect;
+mod image_diff;
+mod init;
+mod integration;
+mod interrupt;
+mod invoke;
+mod kube;
+mod lambda_runtime;
+mod last_build;
+mod layered;
+mod limits;
+mod lockfile;
+mod logging;
+mod manifest;
+mod metadata;
+mod multiarch;
+mod mounts;
+mod names;
+mod network;
+mod notify;
+mod openssl;
+mod out_dir;
+mod output;
+mod overview;
+mod packaging;
+mod phases;
+mod pin;
+mod pipeline;
+mod plan;
+mod policy;
+mod prefetch;
+mod preset;
+mod progress;
+mod project_lock;
+mod proxy;
+mod registry;
+mod release;
+mod reproducible;
+mod resources;
+mod retry;
+mod run_image;
+mod runtime;
+mod sam;
+mod sbom;
+mod scan;
+mod sccache;
+mod scheduler;
+mod secrets;
+mod self_update;
+mod sign;
+mod size_report;
+mod smoke;
+mod ssh;
+mod state;
+mod static_linking;
+mod stream;
+mod system_files;
+mod tags;
+mod targets;
+mod template;
+mod terraform;
+mod toolchain;
+mod unification;
+mod user_map;
+mod vendor;
+mod verify;
+mod warm;
+mod watch;
+mod workspace;
+mod wrapper;
+
+pub use api::Artifact;
+pub use api::BuildConfig;
+pub use api::BuildOutput;
+pub use api::Builder;
+pub use api::Event;
+pub use api::Mode;
+pub use backend::Backend;
+pub use baseline::CpuBaseline;
+pub use bundle::Compression;
+pub use cargo_cache::CargoCache;
+pub use error::BmError;
+pub use lambda_runtime::LambdaRuntime;
+pub use openssl::OpensslMode;
+pub use packaging::Serverless;
+pub use phases::Phase;
+pub use runtime::Runtime;
+pub use sbom::SbomFormat;
+pub use scan::Severity;
+pub use sign::SignMethod;
+use build::build;
+use kube::LocalCluster;
+use system_files::User;
+use clap::App;
+use clap::Arg;
+use clap::ArgGroup;
+use clap::ArgMatches;
+use clap::SubCommand;
+use serde_json::json;
+use std::env;
+use std::path::Path;
+use std::process;
+use std::process::Command;
+use std::time::Instant;
+use std::time::SystemTime;
+
+const USAGE: &str = r#"
+    Black Magic
+
+    This is for building rust projects. It produces zips for AWS Lambda, or 'FROM scratch' docker images.
+    You will need to have docker (or podman) installed, unless you compile on the host with '--backend zigbuild|cross|native' (see
+    below) and don't build an image.
+    It should work on Windows, Linux, and Mac. Getting builds working on Windows was the main reason for this project.
+    This should only be run on projects that already compile, or at least pass 'cargo check'.
+    Builds can be extremely slow, debugging will be paniful.
+
+    Navigate to the root of your rust project, and run 'black_magic' with either the 'lambda' or 'docker' flag, or both.
+
+    If you have a projected named 'my_project':
+        - Running in lambda mode will produce a 'my_project.zip' file in 'target/black_magic'.
+        - Running in docker mode will produce a 'bm_my_project' image containing the executable only, which you can then use in your own container. E.g:
+            FROM bm_my_project
+            EXPOSE 80/tcp
+            CMD ["/my_project"]
+        - To build 'bm_my_project' from your own Dockerfile instead, put it in 'black_magic.Dockerfile' (or pass '--dockerfile-template <path>').
+          '{{artifact}}' is replaced with the tarball holding the executable, '{{binary}}' with the executable's path in the image,
+          '{{project}}' with the project's name, and '{{base}}' with the '--base' image. E.g:
+            FROM {{base}}
+            ADD {{artifact}} /
+            CMD ["{{binary}}"]
+        - To make 'bm_my_project' runnable as is, pass '--expose 80/tcp', '--env KEY=VALUE' (both can be repeated), '--cmd <args>',
+          or '--entrypoint <command>'. Without '--entrypoint' the executable is the entrypoint, unless you use your own Dockerfile.
+        - Pass '--base <image>' to build it on something other than scratch: 'distroless' (CA certificates, time zone data and a
+          'nonroot' user), 'alpine' (a shell and musl, so dynamically linked musl executables run too), 'busybox' (a shell), or
+          any other image. Bases with their own '/etc/passwd' keep it with '--user', which then only sets the ids.
+        - Scratch images have no CA certificates or time zone data. Pass '--with-ca-certs' to add the builder's
+          '/etc/ssl/certs/ca-certificates.crt', so TLS works, and '--with-tzdata' to add its '/usr/share/zoneinfo'.
+        - Pass '--user <uid[:gid]>' to have the image run as that unprivileged user instead of root. Its '/etc/passwd' and
+          '/etc/group' entries are added to the image.
+        - Adding '--debug-image' in docker mode also produces a 'bm_my_project-debug' image, with the same executable on top of busybox.
+          Unlike the scratch image it has a shell, so you can 'docker exec' into it when debugging.
+
+    Running with both '--lambda' and '--docker' builds the zip, then the image, from the same executable, compiling it once.
+    Options for only one of them only apply to that build.
+
+    '--bundle' builds for both x86_64 and ARM64, and packages both executables into one 'my_project.bundle.tar.gz', with a 'run'
+    script that execs the right one for the machine, and both builds' manifests.
+    Docker builds can skip the image, and just produce the tarball, with '--no-image'.
+    In docker mode, '--platforms linux/amd64,linux/arm64' builds for each platform the same way, then makes one multi-platform
+    'bm_my_project' image from them with 'docker buildx' (or 'podman build --manifest'), pushed to each '--push' registry.
+    Without '--push', docker needs its containerd image store to keep a multi-platform image locally.
+
+    Executables are linked statically against musl by default. For dependencies that don't build under musl, '--libc gnu'
+    compiles for the '-unknown-linux-gnu' target instead, in a glibc builder image (the official 'rust' image, on Debian
+    bookworm), and packages the executable on 'gcr.io/distroless/cc-debian12', which has the same glibc. It only applies to
+    docker mode, and '--base' can pick another glibc image, e.g. 'busybox' (its glibc variant) or 'debian:bookworm-slim'.
+
+    By default everything is built for x86_64. Pass '--arch aarch64' to build for ARM64 (e.g. AWS Graviton) instead.
+    A '--base' for just one architecture by its name (e.g. 'arm64v8/alpine') is built for without '--arch', and '--libc gnu' or
+    an '--arch' the base can't run fails before compiling, saying why. See 'src/targets.rs'.
+    ARM64 builds run in an arm64 builder image, so your docker install must be able to run 'linux/arm64' containers (Docker Desktop can out of the box, Linux needs qemu/binfmt).
+    ARM64 artifacts have an '-arm64' suffix, i.e. 'my_project-arm64.zip' and 'bm_my_project-arm64'.
+    In docker mode, '--target-triple <triple>' builds for any other Linux target instead, e.g. 'armv7-unknown-linux-musleabihf',
+    cross-compiling in a '--builder-image' with its linker, after 'rustup target add'. The image is for the triple's platform
+    (e.g. 'linux/arm/v7'), and its artifacts are suffixed with the triple, e.g. 'my_project-armv7-unknown-linux-musleabihf.tar.gz'.
+
+    Pass '--reproducible' to build the same artifact, byte for byte, from the same source on any machine: timestamps come from
+    'SOURCE_DATE_EPOCH' (by default the last commit's time), machine-specific paths are remapped in the executable, and the zip or
+    tarball is written in a fixed order without owners or timestamps of its own.
+
+    x86_64 builds can be restricted to an instruction set level with '--cpu-baseline <x86-64|x86-64-v2|x86-64-v3>'.
+    The binary is disassembled after compiling, and the build fails if it contains instructions beyond that level.
+
+    '--resource-report' samples 'docker stats' while the build container runs, and reports the CPU-seconds, peak memory and
+    downloads it used, and how much of the cache it reused, at the end of the build (and in the JSON 'done' record).
+
+    '--cpus <n>' and '--memory <size>' (e.g. '4g') limit the build container, and '--timeout <seconds>' removes it and fails the
+    build if it's still running after that long, e.g. stuck on a stalled download.
+
+    This project wouldn't work without this excellent project:
+    https://gitlab.com/rust_musl_docker/image
+    Black magic simply makes it easier to use.
+    If your project doesn't compile, you may need a newer tag (the default is 'nightly-2020-04-23'). Select one with '--builder-tag',
+    or use a different base image entirely with '--builder-image'. Both can also be set in a 'BlackMagic.toml' next to 'Cargo.toml':
+        [builder]
+        tag = "nightly-2020-06-01"
+    System libraries and tools the compile needs go in 'packages' there, e.g. 'packages = ["libpq-dev", "protobuf-compiler"]',
+    and anything else in a 'setup_script', run with bash. The builder image is then named after them, e.g. 'black_magic_<hash>'.
+    Lambda zips are written on this machine, so the builder image needn't have 'zip', except for '--s3', '--layered' and
+    'post-package' hooks run in the container, which check for it before compiling. '--docker' tarballs are made with the builder
+    image's 'tar' and 'gzip', or on this machine instead if it hasn't got them. See 'src/archivers.rs'.
+    Each base image and tag gets its own local builder image (e.g. 'black_magic:nightly-2020-04-23'). Pass '--update-builder' to rebuild it.
+    Builds warn when it's more than 'max_age_days' (under '[builder]', 90 by default) old, and '--auto-update-builder' rebuilds it then.
+    'black_magic pin-builder' pins the base image's tag to the digest it points at now, under '[builder.pins]', so the project
+    keeps building with that toolchain, in a builder image of its own, until it's pinned again.
+    On machines that start empty, like CI runners, '--builder-cache-from <registry/repo>' (or 'cache_from' under '[builder]')
+    pulls a missing builder image from the registry, using it if it's current or its layers to build it, and
+    '--builder-cache-push' pushes it there whenever it's built. See 'src/builder.rs'.
+
+    '--test' and '--clippy' run 'cargo test' and 'cargo clippy -- -D warnings' for the build's target in the build container before
+    compiling the artifact, so failures that only show up against musl stop it from being packaged.
+
+    In docker mode, '--smoke-test <command>' runs the built image once with the command as its entrypoint (e.g. '/my_project
+    --version'), failing the build if it exits with anything but 0 or runs longer than '--smoke-test-timeout' (30 seconds). With a
+    'sidecar' in '[smoke_test]', the command runs in that image instead, next to the built one, e.g. to 'curl' its health check.
+    See 'src/smoke.rs'.
+
+    In docker mode, '--integration-test' runs the built image alongside the services declared in 'BlackMagic.toml' (e.g. postgres),
+    via docker compose, and runs a test command against it. See 'src/integration.rs' for the config format.
+
+    In docker mode, '--diff-against <image>' compares the built image's files with a previously published image before pushing,
+    listing what was added, removed or changed size, and fails on new paths not allowed by '[image_diff]' in 'BlackMagic.toml'.
+
+    In docker mode, '--scan' scans the built image for known vulnerabilities before pushing it, with trivy or grype (whichever is
+    installed, or else trivy's image), summarizing what it finds. '--scan-fail-on <severity>' (low, medium, high or critical) fails
+    the build on any finding that severe or worse. '[scan]' in 'BlackMagic.toml' can pick the scanner, and ignore findings. See
+    'src/scan.rs'.
+
+    If the project has a 'rust-toolchain.toml' (or 'rust-toolchain'), that toolchain is installed inside the build container and used
+    instead of the builder image's own.
+
+    In docker mode, '--load-into kind:<cluster>' or '--load-into minikube[:<profile>]' loads the built image straight into a local
+    Kubernetes cluster, so it can be used there without pushing it to a registry.
+    '--export-oci <path>' saves it to a tarball instead (with its SHA-256 next to it), which 'docker load' or 'podman load' accepts,
+    for carrying into air-gapped environments. Docker 25 and later, and podman, write it in the OCI image layout.
+
+    The executable is found from cargo's JSON messages, wherever the profile or a renamed '[[bin]]' puts it, on any toolchain.
+
+    Executables keep their symbols by default, which can make them much bigger. Pass '--strip' to strip them before packaging,
+    and '--upx' to compress them with UPX as well (they decompress themselves on start, which takes a little time). UPX is
+    installed in a builder image of its own, so '--upx' works offline too. The sizes before and after are printed.
+
+    Lambda builds print the zip's size, and its size unzipped. Lambda only accepts zips up to 50 MiB uploaded directly, and
+    250 MiB unzipped; going over either prints a warning with ways to shrink it, or fails the build with '--strict-size'.
+    '--size-report' prints how the artifact's size changed since the last build, and the executable's size by crate (from its
+    symbols, before '--strip'), with the crates that changed the most since then first. See 'src/size_report.rs'.
+
+    Executables are always linked statically. If the project's '.cargo/config.toml' disables 'crt-static', or it depends on '-sys'
+    crates that link system libraries dynamically by default (e.g. 'openssl-sys'), the flags and variables linking them statically
+    are added, and the build says why. '--no-auto-static' leaves the linking to the project. An executable that still ends up
+    dynamically linked, or built for another architecture, stops the build straight after compiling, checked with 'readelf' in
+    the build container (or on the host, for the backends compiling there). '--libc gnu' executables can only need glibc's own
+    libraries, and 'allowed_libraries' in '[build]' allows others the base image has. See 'src/elf.rs'.
+
+    Pass '--hardened' to build a static-PIE, full RELRO executable. The binary's hardening properties are checked after compiling,
+    and recorded in 'target/black_magic/<artifact>.manifest.json'.
+    Every manifest also records what built the artifact: the compiler's 'rustc -vV', the builder image and its ID, the docker (or
+    podman) version (neither, for the backends compiling on the host without them), the host OS, and the black_magic version.
+
+    Policies for dependencies (count, banned crates, licenses) and binary size can be set in the '[policy]' section of 'BlackMagic.toml'.
+    Builds that violate them fail, and no artifact is produced.
+
+    Every successful build is also kept in a content-addressed store ('~/.cache/black_magic/cas'), keyed by the project's source and
+    build options. Building source that was built before (e.g. after switching back to a branch) reuses that artifact instantly.
+    The last successful build is recorded in 'target/black_magic/artifact.json': its artifact's path, SHA-256 and size, its target,
+    profile and features, the builder image's digest, the commit, the 'rustc' and 'cargo' versions, and when it ran, for deployment
+    tooling and audits. '--skip-unchanged' skips the build altogether (pushing and deploying too) if nothing's changed since,
+    reporting its artifact, so deploy scripts can always run black_magic. See 'src/state.rs'.
+    The compiled executable is also extracted into 'target/black_magic/phases/<artifact>/', so when only the Dockerfile template
+    or the zip's included files have changed, the build packages it again without compiling. '--from-phase package' does that
+    whatever's changed, '--from-phase image' only rebuilds the image, and '--from-phase compile' always compiles. See 'src/phases.rs'.
+
+    Builds reuse cargo's compiled dependencies from a 'bm_cache_<project>_<target>' docker volume, so rebuilds are much quicker.
+    Pass '--no-cache' to compile everything from scratch, or run 'black_magic clean --cache' to delete the volumes.
+    '--sccache' compiles through sccache as well, so crates compiled by one project are reused by others, and with a shared S3
+    bucket or Redis ('[sccache]' in 'BlackMagic.toml') by other machines and CI runners. By default it keeps its cache in
+    '~/.cache/black_magic/sccache' on the host.
+    Where volumes don't survive between builds (e.g. CI), '--layered' compiles with 'docker build' instead: the dependencies are
+    compiled in their own image layer from just 'Cargo.toml' and 'Cargo.lock', which is reused until either changes.
+    Builds on a remote daemon ('DOCKER_HOST=ssh://...' or tcp://, a remote docker context, podman's 'CONTAINER_HOST') do the
+    same, since its containers can't mount the project: it's sent as the build context, and the artifact copied back out.
+    '--strategy dockerfile' builds like '--layered', with BuildKit cache mounts for cargo's registry and the target dir too, so
+    the project compiles incrementally without mounting anything from the host, e.g. where Docker Desktop's mounts are slow.
+    Their build context is the project's source as git sees it, less what its '.dockerignore' leaves out (or its '.gitignore',
+    outside git). '--shadow-target' ('shadow_target' in '[build]') keeps the host's 'target' out of mounted builds too, behind
+    an empty tmpfs with only 'target/black_magic' mounted back.
+    The build container runs as root, so with docker on Linux, the files it writes into the project and the host's cargo home are
+    given back to you when it's done ('--user-map'), so 'cargo clean' still works. '--no-user-map' (or 'user_map = false' in
+    '[build]') leaves them. See 'src/user_map.rs'.
+    '--read-only-source' ('read_only_source' in '[build]') mounts the project read-only, so the build can't change the working
+    tree: it compiles into the container's own 'target' volume, and the outputs are copied into 'target/black_magic' with
+    '<runtime> cp' once it's exited. Cargo can't update 'Cargo.lock' then, so it's checked as with '--strict' and passed '--locked'.
+
+    'black_magic clean' removes 'target/black_magic', '--cache' also removes the project's cache volumes, and '--images' its
+    'bm_<project>' images and the builder images. '--stale-builders <days>' removes the customized and pinned builder images (see
+    '[builder]') of any project that no build has used for that many days. '--dry-run' lists what would go, and how much space it would reclaim.
+    'black_magic status' (or 'list') lists them without removing anything: the artifacts with their sizes and ages, the images'
+    tags and IDs, the cache volumes' sizes, and the builder images' ages, and whether they're out of date.
+    Builder images are rebuilt by themselves when what they're built from changes, e.g. their packages or setup script.
+
+    'black_magic doctor' checks this machine can build, printing what to fix for anything that fails: the container runtime and
+    its daemon (in Linux containers mode), disk space, the cargo home, the builder image and its age, access to crates.io, and
+    whether containers can see the project and cargo home (Docker Desktop's file sharing).
+
+    Every build is recorded in 'target/black_magic/history.jsonl': how it went, how long it took, what the cache did for it,
+    and the artifact's size. 'black_magic stats' prints the trends over the last '--last <n>' (20 by default). See 'src/history.rs'.
+
+    'black_magic inspect <zip|image>' shows what's inside an artifact before you deploy it: its files and their sizes, how the
+    executable is linked, its manifest, and whether the project's source has changed since it was built.
+
+    '--sign minisign|cosign' signs the artifact once it's built, next to it as '.minisig' or '.sigstore.json' (keyless with
+    cosign, unless '[sign]' in 'BlackMagic.toml' has a key), and signs pushed images with 'cosign sign'. 'black_magic verify
+    <artifact|image>' checks the artifact against its '.sha256' and its signature, or an image's signature in its registry.
+    See 'src/sign.rs'.
+
+    'black_magic self-update' replaces black_magic with the newest release's executable for this platform, once it's checked
+    it against its '.sha256' (and its signature, with '--public-key', or '--identity' and '--issuer'). '--channel nightly'
+    includes pre-releases, and '--check' only says whether there's a newer one. Builds say when there is, checking once a day,
+    except in CI or with 'BLACK_MAGIC_NO_UPDATE_CHECK' set. See 'src/self_update.rs'.
+
+    'black_magic completions <bash|zsh|fish|powershell>' prints the completion script for a shell, and 'black_magic man' a man
+    page, e.g. 'black_magic man > /usr/local/share/man/man1/black_magic.1'.
+
+    Companion binaries, like the AWS Lambda Web Adapter or a Lambda extension, can be added to the zip or image from '[[companions]]'
+    in 'BlackMagic.toml', copied out of an image or downloaded with a checksum. See 'src/companion.rs' for the config format.
+
+    '[[hooks]]' in 'BlackMagic.toml' run commands at 'pre-build', 'post-build', 'pre-package' and 'post-package', on the host or
+    with 'container = true' in the build container, with the artifact's path and the build's details in 'BM_*' variables, e.g.
+    to add files to the zip or send a notification. See 'src/hooks.rs'.
+
+    In lambda mode, '--lambda-runtime provided.al2' or '--lambda-runtime provided.al2023' (or 'runtime' under '[lambda]' in
+    'BlackMagic.toml') checks the executable runs on that runtime's glibc, adds it to the zip's name (e.g. 'my_project-al2023.zip'),
+    records it in the manifest, and checks the function is configured with it before '--deploy'.
+
+    '--out-dir <path>' copies the artifact, its '.sha256' and its manifest there once it's built, and '--artifact-name <name>'
+    names them, with placeholders: e.g. '--out-dir dist --artifact-name {name}-{version}-{git_sha}' writes
+    'dist/my_project-1.2.0-3f9c2e1.zip'. '{target}' and '{arch}' are filled in too. Both can be set under '[build]' in
+    'BlackMagic.toml', as 'out_dir' and 'artifact_name'. See 'src/out_dir.rs'.
+
+    '--sbom cyclonedx|spdx' writes a software bill of materials next to the artifact (e.g. 'target/black_magic/my_project.cdx.json'
+    or '.spdx.json'), of the dependencies compiled in, with their licenses and 'Cargo.lock' checksums, and '--sbom-label' puts
+    it in the image's 'dev.black_magic.sbom' label too. 'sbom' under '[build]' sets it. See 'src/sbom.rs'.
+
+    In lambda mode, '--compression store|fast|default|best' picks how hard the zip's compressed, from not at all to deflate's
+    level 9. 'bootstrap' is always zipped with mode 0755. Every artifact is checked once it's packaged: its executables are
+    there and executable, and its symlinks point at something inside it (for images, only on 'scratch'). See 'src/verify.rs'.
+
+    In lambda mode, '--serverless aws|gcf|azure' lays the zip out for a serverless platform, from the same compile: Lambda's
+    'bootstrap' (the default), Google's buildpacks (the executable and a 'Procfile' running it), or an Azure Functions custom
+    handler ('handler', 'host.json' and a 'function.json' for each function). The other platforms' zips are named for them,
+    e.g. 'my_project-azure.zip', and Lambda's own options and limits don't apply to them. 'target' under '[serverless]'
+    in 'BlackMagic.toml' sets it. See 'src/packaging/mod.rs'.
+
+    In lambda mode, '--emit-sam' writes a SAM template next to the zip (e.g. 'target/black_magic/my_project.template.yaml'), with
+    'Handler: bootstrap', the zip's runtime and architecture, and the memory, timeout and environment from '[lambda.function]' in
+    'BlackMagic.toml', so 'sam deploy' or 'sam local start-api' can run the zip as built. See 'src/sam.rs' for the config format.
+    In either mode, '--emit-terraform' writes a Terraform fragment next to the artifact (e.g. 'target/black_magic/my_project.tf'): an
+    'aws_lambda_function' for a zip, with its 'source_code_hash', or a 'docker_image' (and an 'aws_ecr_image' with '--ecr') for
+    an image, triggered by its SHA-256, so Terraform only updates them when the artifact has changed.
+    In docker mode, '--emit-compose' adds a service running the built image to the project's 'docker-compose.yml' (or replaces
+    the one it added before), publishing the '--expose'd ports, with the '--env' variables, so 'docker compose up' runs the image
+    as built. '[compose]' in 'BlackMagic.toml' can name the service, pick its ports, and give it a health check. See
+    'src/compose.rs'.
+
+    'black_magic init --template lambda-http [dir]' starts a Lambda function behind an HTTP API: a 'lambda_http' handler, a
+    'BlackMagic.toml' for it, a sample request in 'events/hello.json', and 'invoke.sh', which builds the zip and invokes it locally.
+    'init --lambda' does the same for a plain 'lambda_runtime' handler, and 'init --docker' writes an 'axum' server, with 'run.sh'
+    building and running its image. In an existing crate, they add the missing dependencies and files, keeping what's there.
+
+    'black_magic invoke --payload event.json' runs the built Lambda zip's 'bootstrap' in Lambda's own 'provided' image, with its
+    Runtime Interface Emulator, posts the payload to it, and prints the response and the function's logs, so the exact artifact
+    can be smoke tested before deploying it.
+
+    'black_magic run -- <args>' builds the docker image if anything has changed since the last build, then runs it in the
+    foreground with those arguments, publishing the ports it exposes on the host's, with the project's '.env' (or '--env-file'),
+    and removes the container when it exits. Pass the build's arguments with '--args' (default '--docker'). See 'src/run_image.rs'.
+
+    'black_magic changelog <before.manifest.json> <after.manifest.json>' describes what changed between two builds as markdown,
+    ready to paste into a deploy PR: dependency versions, executable and artifact size, features, and the toolchain.
+
+    A team can share compiled dependencies too: run 'black_magic cache-server --listen 0.0.0.0:7878' on one machine, and build with
+    '--cache-server http://<that machine>:7878' (or 'server' in the '[cache]' section of 'BlackMagic.toml'). A build whose volume is
+    still empty fetches dependencies compiled for the same 'Cargo.lock' and options, checked against the digest recorded when they
+    were uploaded, and uploads them if nobody has yet. Uploading needs the team's token in 'BM_CACHE_TOKEN' (or 'token' in
+    '[cache]'), on the server and where builds run. See 'src/cache_server.rs'.
+
+    The executable is compiled in the builder image by default ('docker-musl', or 'docker-gnu' for '--libc gnu').
+    '--backend cross|zigbuild|native' compiles it on the host instead, with 'cross', 'cargo zigbuild', or plain 'cargo build'
+    (only for the host's own architecture), and inspects and packages it on the host too, with its 'readelf' (and 'objdump',
+    'strip' and 'upx' for the options needing them), so there's no build container ('--backend zig' is 'zigbuild' too). Those
+    only need docker or podman to build the image in docker mode, or to fetch companions, and 'cross' runs its own containers.
+    Options for the build container ('--with-ca-certs', '--test', '--resource-report', 'container = true' hooks, ...) don't
+    apply to them. Set 'default' in the '[backend]' section of 'BlackMagic.toml', or a backend per target triple under 'targets'.
+
+    Everything is built with the 'release' profile by default, pick another (e.g. 'dev' for quicker builds) with '--profile <name>'.
+    Cargo features can be selected with '--features', '--no-default-features', and '--all-features'.
+    Anything else can be passed on to 'cargo build' with '--cargo-arg <arg>', or after '--'. '--rustflags <flags>' adds to the
+    'RUSTFLAGS' black_magic sets itself, e.g. '--rustflags "-C link-arg=-Wl,--gc-sections"', and '--jobs <n>' sets
+    'CARGO_BUILD_JOBS'. Both can be set in the '[build]' section of 'BlackMagic.toml' as 'rustflags' and 'jobs' too.
+
+    Projects depending on 'openssl-sys' need an OpenSSL built for musl, and are warned unless it's vendored already.
+    '--openssl system' uses the builder image's, and '--openssl vendored' compiles it from source with the 'openssl' crate's
+    'vendored' feature. Set 'openssl' (and, for builder images keeping it elsewhere, 'openssl_dir') in '[build]' too.
+    Failed compiles are checked for common musl problems (OpenSSL and other C libraries, a missing musl C compiler, old 'ring',
+    glibc-only code, proc-macros built for the target), with what to do about them. Add your own with '[[diagnostics]]' in
+    'BlackMagic.toml'. See 'src/diagnostics.rs'.
+    When those select several workspace packages (e.g. '-- --workspace'), cargo compiles their dependencies once, with every
+    feature any of them asks for. What that adds to each package's dependencies is reported before compiling.
+    '--isolate-features' compiles just the executable's package instead, with exactly its own features.
+
+    Dependencies are fetched into the host's '~/.cargo', which is mounted into the build container ('--cargo-cache share'), holding
+    cargo's lock so a cargo on the host can't write at the same time. To leave the host's cargo home alone, '--cargo-cache isolated'
+    keeps the container's in a volume of the user's, or name one with '--cargo-home-volume <name>', while '--cargo-cache ro-overlay'
+    mounts the host's read-only, copying what it has up into volumes of the user's. '--cargo-home <path>' moves it inside the
+    container. They can also be set in the '[cargo_home]' section of 'BlackMagic.toml', as 'cache', 'volume' and 'path'. See
+    'src/cargo_cache.rs'.
+
+    Artifacts, images and volumes are named after the project directory, lowercased and with anything docker or Lambda wouldn't accept
+    replaced (e.g. 'My Project!' builds 'bm_my-project'). Pick the name yourself with '--name', or 'name = "..."' in 'BlackMagic.toml'.
+
+    Podman works as well as docker. Pick one with '--runtime docker|podman' or 'BM_RUNTIME', otherwise whichever is installed is
+    used, docker first. Without docker's CLI, a docker daemon that answers (on its socket, or 'DOCKER_HOST') is used through its
+    Engine API, which builds, runs and inspects images, but not with BuildKit: '--multiarch', '--push' and the like need the CLI.
+
+    In docker mode, '--push <registry/repo:tag>' tags and pushes the built image. '--ecr <repo[:tag]>' does the same for the account's
+    ECR registry, logging docker in and creating the repository if it doesn't exist yet.
+    'black_magic publish-ecr --repo <repo>' does all of that in one go, tagging the image with the commit and 'latest', and prints
+    the pushed image's URI by digest ('<registry>/<repo>@sha256:…') for Lambda or ECS to deploy. See 'src/ecr.rs'.
+
+    '--lambda-image' builds a Lambda container image, 'bm_<name>_lambda': the executable as '/var/runtime/bootstrap' on top of
+    'public.ecr.aws/lambda/provided:al2023' (or '--base'), whose entrypoint runs it, in Lambda or locally with the runtime
+    interface emulator. With '--ecr <repo>' and '--deploy <function>', the function is pointed at the pushed image, by digest,
+    and a new version is published.
+
+    In docker mode, '--tag <tag>' also tags the image 'bm_<name>:<tag>', and '--tag-git' with the commit's short hash ('-dirty'
+    with uncommitted changes). A '--push' or '--ecr' repository without a tag is pushed under those tags instead of 'latest'.
+    Images are labelled with their source, revision, creation time and version, as 'org.opencontainers.image.*'.
+
+    In lambda mode, '--include <path[:dest]>' adds a file or directory of the project to the zip, next to 'bootstrap' or at 'dest'.
+    It can be repeated, or set in 'BlackMagic.toml' as 'include = [...]' in the '[lambda]' section.
+
+    In lambda mode, a '[lambda.wrapper]' section in 'BlackMagic.toml' puts a small 'bootstrap' wrapper in the zip, which sets
+    default environment variables, copies '_HANDLER', and fetches SSM parameters and secrets before running the executable.
+    Its 'script' zips the project's own wrapper script as 'bootstrap' instead. 'helpers' in '[lambda]' compiles other '[[bin]]'s
+    of the project with it, zipped next to it with mode 0755, e.g. for the wrapper to start too. See 'src/wrapper/mod.rs' for
+    the config format.
+
+    In lambda mode, '--deploy <function>' uploads the zip to an existing Lambda function and publishes a new version, with the AWS
+    SDK, so the 'aws' CLI isn't needed. Credentials come from the usual AWS configuration. Use '--region' and '--aws-profile' (or
+    the '[aws]' section of 'BlackMagic.toml') to pick the account and region.
+    With '--s3 <s3://bucket/key>' the zip is uploaded to S3 while it's being packaged, and '--deploy' uses that copy, which also
+    allows zips over the 50 MiB Lambda accepts directly. It's only moved to 'key' once it has passed every check. The key can use
+    the same placeholders as '--artifact-name', e.g. 's3://my-bucket/{name}-{version}-{git_sha}.zip', and with a versioned bucket
+    the object's version ID is printed (and in the JSON output, with the bucket and key), and deployed by '--deploy'.
+    In docker mode, images are pushed to every '--push' and '--ecr' registry at the same time.
+
+    'black_magic retag <registry/bm_api:abc123> <:prod...>' promotes an image that's already been pushed, by copying its manifest
+    within the registry: nothing is pulled or rebuilt, and the image doesn't need to be local. It needs docker's buildx plugin,
+    or 'skopeo' with podman.
+
+    'black_magic release --level <major|minor|patch>' bumps the version in 'Cargo.toml', runs every build listed in the '[release]'
+    section of 'BlackMagic.toml', and if they all succeed commits and tags the release, writing 'target/black_magic/release-<version>.json'.
+    Add '--github' to also push the tag and upload the artifacts to a GitHub Release (needs the 'gh' CLI, and 'GH_TOKEN' or 'GITHUB_TOKEN').
+
+    'black_magic pipeline <name>' runs a pipeline from the '[pipelines.<name>]' section of 'BlackMagic.toml': its 'steps' run in order,
+    each one 'check', 'test', 'build <args>', or 'run <command>', stopping at the first that fails. Without a name it lists them.
+
+    '--notify' says when the build finishes, succeeding or failing, with a desktop notification (through the notify-rust crate,
+    so on Linux, Mac and Windows alike), and a POST to the 'webhook' and 'slack' URLs in '[notify]' in 'BlackMagic.toml' if
+    they're set: the project, how long it took, and the artifact and its size, or the error's first line. See 'src/notify.rs'.
+
+    '--preset <name>' builds with the options in the '[preset.<name>]' section of 'BlackMagic.toml', for projects built several ways:
+        [preset.fargate]
+        docker = true
+        base = "debian:bookworm-slim"
+        include = ["assets:/assets"]
+    Each key is an option's long name, with 'true' for a flag and a list for an option given several times. Options also given
+    on the command line replace the preset's, e.g. '--preset fargate --base alpine'. See 'src/preset.rs'.
+
+    '--retries <n>' retries building the builder image, and the compile, when they fail with what looks like a network problem
+    (a registry or crates.io timing out, say), but never a compile error. Retries back off from '--retry-delay' seconds, doubling.
+
+    Every compile's whole stdout and stderr are kept in 'target/black_magic/last_build.out' and '.err', with the exact command and
+    how it exited in 'last_build.json'. '--keep-on-failure' also keeps a failed build container, to inspect what it left behind.
+
+    'black_magic shell' opens a bash shell in the build container, with the same mounts and environment as a build, to run cargo by
+    hand; pass the build's arguments with '--args' (default '--docker'). '--shell' does the same for the build it's given, and
+    '--debug-shell' opens one only if the compile fails.
+
+    'black_magic warm' compiles just the dependencies, from 'Cargo.toml' and 'Cargo.lock', into the cache volumes, so the next build
+    only compiles the project; run it after 'Cargo.lock' changes, or nightly in CI. Pass the build's arguments with '--args' (default
+    '--docker'), or give a build '--deps-only' to do the same. See 'src/warm.rs'.
+
+    'black_magic bench-builders --candidate <tag|image:tag> ...' builds the project with each candidate builder image, cold and then
+    warm, and compares compile times and artifact sizes. Pass the build's own arguments with '--args' (default '--docker').
+
+    '--watch' builds, then builds again whenever 'src/', 'Cargo.toml', 'Cargo.lock' or 'BlackMagic.toml' change, printing a line
+    about how each build went. The cache volume makes each rebuild only compile the project itself.
+
+    The host's cargo registry is locked for the build, like cargo itself does. If another cargo process (e.g. an IDE's) holds it,
+    black_magic waits '--cargo-lock-timeout' seconds (30 by default) and then builds with a registry private to the build
+    containers, in the 'bm_cargo_registry' and 'bm_cargo_git' volumes, instead.
+
+    Only one build of a project runs at a time: another one waits for it to finish (it holds 'target/black_magic/.lock'), or with
+    '--no-wait' fails straight away. Builds of different projects can run side by side.
+
+    Projects behind symlinks (e.g. '~/dev -> /mnt/data/dev') are named after the path you ran black_magic in, but mounted into
+    containers by their real location, which is what VM-based runtimes like Docker Desktop need to have shared. A 'target'
+    symlinked out of the project is mounted too. If the container still can't see the project, the build says which path to share.
+    On Windows, paths are passed as the runtime's VM sees them ('D:\x' is '/d/x' for Docker Desktop, '/mnt/d/x' for podman),
+    and 'doctor' checks the project's drive is shared.
+
+    A workspace member (e.g. 'services/api'), or a project depending on crates outside it by path (e.g. '../../libs/common'),
+    has the directory holding the workspace and all of those crates mounted instead, and builds in the project inside it,
+    still writing artifacts to the project's 'target/black_magic'. See 'src/workspace.rs'.
+
+    '--ssh' forwards the host's SSH agent into the build container, so git dependencies in private repositories can be fetched.
+    Hosts are checked against '~/.ssh/known_hosts', or '--ssh-known-hosts <path>'. '--gitconfig <path>' mounts a git config,
+    e.g. with 'insteadOf' URL rewrites. On macOS this needs Docker Desktop, and on Windows, WSL.
+
+    For private registries, '--cargo-config' mounts the host's '~/.cargo/config.toml' and 'credentials.toml' read-only into the
+    build container. In CI, '--registry-token <registry>' passes 'CARGO_REGISTRIES_<REGISTRY>_TOKEN' from the environment instead.
+
+    '--secret id=<name>,src=<path>' makes a file available to the compile only, at '/run/secrets/<name>' (or 'target=<path>'):
+    mounted read-only into the build container, or with '--layered' builds a BuildKit secret mount, which is never in a layer.
+    It can be repeated.
+
+    Without network access, '--offline' compiles with 'cargo build --offline', and '--vendor <dir>' compiles from a directory made
+    with 'cargo vendor', passing cargo the source replacement for it (crates.io and every git source in 'Cargo.lock').
+    '--prefetch' (or 'enabled = true' in a '[prefetch]' section) runs 'cargo fetch', and the section's 'commands', in a container like
+    the build's first, then compiles with '--network none' and 'cargo build --offline', so a build script that downloads at compile
+    time fails everywhere, not just where the network's restricted. See 'src/prefetch.rs'.
+    '--locked' and '--frozen' are passed on to cargo, so the build fails rather than update 'Cargo.lock'. Every build first
+    checks the project has a 'Cargo.lock' that's up to date with its 'Cargo.toml', warning if not, or failing with '--strict'
+    (or '--locked' or '--frozen'). See 'src/lockfile.rs'.
+
+    'HTTP_PROXY', 'HTTPS_PROXY' and 'NO_PROXY' are passed on to the build container, the builder image's build, and '--layered'
+    builds, or set them in a '[proxy]' section of 'BlackMagic.toml'. See 'src/proxy.rs'.
+    '--network <name>', '--dns <ip>' and '--add-host <host:ip>' (or a '[network]' section) are passed on to them too, for
+    registries and git hosts only reachable over e.g. a VPN's docker network. 'docker build' has no '--dns', so the builder image
+    and '--layered' builds resolve with the network's or the daemon's DNS. See 'src/network.rs'.
+
+    '--build-env KEY=VALUE' sets a variable for the compile, e.g. for a 'build.rs' to embed, and '--build-env KEY' passes it from
+    the host's environment. An '[env]' section of 'BlackMagic.toml' sets them too. They're part of the artifact's fingerprint.
+    Builds also set 'BM_GIT_SHA', 'BM_GIT_DIRTY', 'BM_BUILD_TIME' and 'BM_VERSION', for 'env!' version stamping, or
+    'metadata_env' in '[build]' picks which. '--env-file <path>' writes them all to a file, for building the same way locally.
+
+    For projects with several '[[bin]]' targets, '--bin <name>' builds one of them, naming the artifact after it (e.g.
+    'target/black_magic/worker.zip' or 'bm_worker'), and '--bins' builds and packages every one, compiling them all once.
+    '--parallel <n>' runs up to n of '--bins'', '--bundle''s or '--platforms'' builds at once (after the first '--bins' build,
+    which compiles them all), with each one's progress prefixed with its name, and a table of how they went at the end.
+    '--example <name>' and '--bench <name>' build one of the project's examples or benches into an artifact the same way, named
+    'example_<name>' or 'bench_<name>' (e.g. 'target/black_magic/example_resize.zip' or 'bm_example_resize').
+
+    '--porcelain' prints only stable lifecycle markers to stdout (e.g. 'ARTIFACT path=… digest=sha256:…'), with progress messages on
+    stderr, so wrapper scripts don't have to parse progress messages. See 'src/output.rs' for the markers.
+    '--ci github' or '--ci gitlab' groups the job's log by phase, annotates compile errors on GitHub, and sets the artifact's
+    path and SHA-256 as step outputs ('$GITHUB_OUTPUT'), or in 'target/black_magic/ci.env' for GitLab. See 'src/ci.rs'.
+
+    '--no-side-effects' resolves and checks everything a build would (arguments, 'BlackMagic.toml', includes, templates, policies),
+    then prints each step it would take, with the exact build command, and what it would produce. It's guaranteed not to write
+    any files, create images or containers, or touch the network, so it can be run wherever builds themselves aren't allowed.
+    '--dry-run' does the same, and also prints every command the build would run (docker build, docker run, and the script the
+    build container runs, a step per line) in a form that can be pasted into a shell, with the Dockerfiles it would write.
+
+    For scripts, '--output-format json' prints a single JSON record at the end instead of progress messages: the artifact's path,
+    image, size, sha256 (and Lambda's base64 'code_sha256'), target triple, build duration, and builder image. Add '--verbose' to stream progress as JSON lines before it.
+
+    '-q' only prints warnings and errors, '-v' also prints the commands being run, and '-vv' the build's whole log, with how long
+    each phase took. '--log-file target/black_magic/build.log' appends the log to a file, to audit long builds afterwards.
+    'RUST_LOG' filters it, e.g. 'RUST_LOG=black_magic=trace'. On a terminal, the phase that's running is shown with how long it's
+    taking, and about how long it has left going by the last build, and every build ends with how long each phase took.
+
+    Errors are printed to stderr, and the exit code says what failed: 1 invalid arguments, 2 environment (missing tools, config),
+    3 compile, 4 packaging or verification, 5 docker, 6 tests ('--test', '--clippy', integration test), 7 publishing (push, deploy, release),
+    and 130 interrupted (Ctrl-C stops and removes the build container, and the partial artifact).
+
+    Example usage:
+        black_magic --lambda
+        black_magic --docker
+        black_magic --lambda --arch aarch64
+        black_magic --docker --features metrics -- --locked
+"#;
+
+/// Where cargo puts its build artifacts inside the build container.
+/// Kept outside of `/workdir` so the cache volume doesn't hide the host's `target/black_magic`.
+const CONTAINER_TARGET_DIR: &str = "/bm_target";
+
+/// The C library the executable links against, see `--libc`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Libc {
+    Musl,
+    Gnu,
+}
+
+impl Libc {
+    fn from_name(name: &str) -> Libc {
+        match name {
+            "gnu" => Libc::Gnu,
+            _ => Libc::Musl,
+        }
+    }
+
+    fn target_triple(self, arch: Arch) -> &'static str {
+        match (self, arch) {
+            (Libc::Musl, arch) => arch.target_triple(),
+            (Libc::Gnu, Arch::X86_64) => "x86_64-unknown-linux-gnu",
+            (Libc::Gnu, Arch::Aarch64) => "aarch64-unknown-linux-gnu",
+        }
+    }
+}
+
+/// The CPU architecture to build for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    fn from_name(name: &str) -> Arch {
+        match name {
+            "aarch64" => Arch::Aarch64,
+            _ => Arch::X86_64,
+        }
+    }
+
+    fn target_triple(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64-unknown-linux-musl",
+            Arch::Aarch64 => "aarch64-unknown-linux-musl",
+        }
+    }
+
+    /// The docker platform to build and run containers with, if it differs from the default.
+    fn platform(self) -> Option<&'static str> {
+        match self {
+            Arch::X86_64 => None,
+            Arch::Aarch64 => Some("linux/arm64"),
+        }
+    }
+
+    /// Appended to artifact and image names so builds for different architectures don't overwrite each other.
+    fn suffix(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "",
+            Arch::Aarch64 => "-arm64",
+        }
+    }
+
+    /// As Lambda names it, in a function's `Architectures`.
+    fn lambda_name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "arm64",
+        }
+    }
+}
+
+/// The `black_magic` command line, which the binary runs.
+#[doc(hidden)]
+pub fn cli() {
+    // Standing in for the docker CLI, when it isn't installed, see `engine`.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|a| a.as_str()) == Some(engine::SUBCOMMAND) {
+        process::exit(engine::cli(&args[1..]));
+    }
+    let matches = app().get_matches();
+    let json = matches.value_of("OUTPUT_FORMAT") == Some("json");
+    output::init(json, matches.is_present("VERBOSE"), matches.is_present("QUIET"), matches.is_present("PORCELAIN") && !json);
+    progress::init(!json && !matches.is_present("QUIET") && !matches.is_present("PORCELAIN"));
+    ci::init(matches.value_of("CI"));
+    interrupt::install();
+
+    let started = Instant::now();
+    let result = if json && matches.is_present("PORCELAIN") {
+        Err(BmError::Environment("`--porcelain` can't be used with `--output-format json`.".to_owned()))
+    } else {
+        logging::init(matches.occurrences_of("VERBOSE"), matches.value_of("LOG_FILE").map(Path::new))
+            .and_then(|_| preset::apply(matches.clone(), env::args().collect()))
+            .and_then(|matches| {
+                let started_at = SystemTime::now();
+                let built = run(&matches);
+                if records_history(&matches) {
+                    history::record(started_at, started.elapsed().as_secs_f64(), built.as_ref().err());
+                }
+                if matches.is_present("NOTIFY") && matches.subcommand_name().is_none() {
+                    notify::finished(matches.value_of("NAME"), started_at, started.elapsed().as_secs_f64(), built.as_ref().err());
+                }
+                if built.is_ok() && records_history(&matches) {
+                    self_update::notice();
+                }
+                built
+            })
+    };
+    if let Err(e) = result {
+        tracing::error!(kind = e.kind(), "{}", e);
+        eprintln!("{}", e);
+        if output::is_json() {
+            output::emit("error", json!({ "kind": e.kind(), "exit_code": e.exit_code(), "message": e.to_string() }));
+        }
+        output::marker("ERROR", &[("kind", e.kind()), ("exit_code", &e.exit_code().to_string())]);
+        process::exit(e.exit_code());
+    }
+    if matches.subcommand_name().is_none() {
+        output::marker("END_BUILD", &[("seconds", &format!("{:.1}", started.elapsed().as_secs_f64()))]);
+    }
+}
+
+/// Whether `matches` are for a build of its own, as `history` records them, rather than a subcommand, a plan, or builds of
+/// others (which record themselves).
+fn records_history(matches: &ArgMatches) -> bool {
+    matches.subcommand_name().is_none()
+        && !["DRY_RUN", "NO_SIDE_EFFECTS", "SHELL", "DEBUG_SHELL", "WATCH", "BUNDLE", "BINS", "PLATFORMS"].iter().any(|a| matches.is_present(a))
+        && !(matches.is_present("LAMBDA") && matches.is_present("DOCKER"))
+}
+
+pub(crate) fn app() -> App<'static, 'static> {
+    App::new("black_magic")
+        .version(self_update::VERSION)
+        .author("Peter Reeves <peter.x.reeves@gmail.com>")
+        .about(USAGE)
+        .arg(Arg::with_name("DOCKER")
+            .help("Build a docker image. With `--lambda` too, builds both from the same executable.")
+            .short("d")
+            .long("docker"))
+        .arg(Arg::with_name("LAMBDA")
+            .help("Build a lambda zip.")
+            .short("l")
+            .long("lambda"))
+        .arg(Arg::with_name("LAMBDA_IMAGE")
+            .help("Build a Lambda container image, `bm_<name>_lambda`, with the executable as `/var/runtime/bootstrap`.")
+            .long("lambda-image")
+            .conflicts_with_all(&["DOCKER", "LAMBDA"]))
+        .arg(Arg::with_name("BUNDLE")
+            .help("Build for both architectures, bundling the executables into one archive that runs the right one.")
+            .long("bundle"))
+        .arg(Arg::with_name("PARALLEL")
+            .help("With `--bins`, `--bundle` or `--platforms`, run this many of their builds at once.")
+            .long("parallel")
+            .value_name("n")
+            .takes_value(true)
+            .validator(scheduler::validate_parallel))
+        .arg(Arg::with_name("BIN")
+            .help("Build this `[[bin]]` of the project, naming the artifact after it.")
+            .long("bin")
+            .takes_value(true))
+        .arg(Arg::with_name("BINS")
+            .help("Build every `[[bin]]` of the project, each packaged into its own artifact.")
+            .long("bins"))
+        .arg(Arg::with_name("EXAMPLE")
+            .help("Build this example of the project, naming the artifact `example_<name>`.")
+            .long("example")
+            .value_name("name")
+            .takes_value(true)
+            .conflicts_with_all(&["BIN", "BINS"]))
+        .arg(Arg::with_name("BENCH")
+            .help("Build this bench of the project, e.g. for a profiling image, naming the artifact `bench_<name>`.")
+            .long("bench")
+            .value_name("name")
+            .takes_value(true)
+            .conflicts_with_all(&["BIN", "BINS", "EXAMPLE"]))
+        .arg(Arg::with_name("NO_IMAGE")
+            .help("In docker mode, only produce the executable's tarball, without building the image.")
+            .long("no-image"))
+        .arg(Arg::with_name("ARCH")
+            .help("The CPU architecture to build for. Defaults to the base image's, if it's only for one.")
+            .short("a")
+            .long("arch")
+            .takes_value(true)
+            .possible_values(&["x86_64", "aarch64"])
+            .default_value("x86_64"))
+        .arg(Arg::with_name("LIBC")
+            .help("In docker mode, the C library to link against: musl, statically (the default), or glibc, in a glibc builder image and on a distroless image with glibc.")
+            .long("libc")
+            .takes_value(true)
+            .possible_values(&["musl", "gnu"])
+            .default_value("musl"))
+        .arg(Arg::with_name("TARGET_TRIPLE")
+            .help("In docker mode, build for this target triple instead of `--arch` and `--libc`'s, e.g. `armv7-unknown-linux-musleabihf`, with a `--builder-image` for it.")
+            .long("target-triple")
+            .value_name("triple")
+            .takes_value(true)
+            .conflicts_with_all(&["LAMBDA", "LAMBDA_IMAGE", "CPU_BASELINE", "PLATFORMS", "BUNDLE"]))
+        .arg(Arg::with_name("CPU_BASELINE")
+            .help("Restrict an x86_64 build to an instruction set level, and verify the binary doesn't exceed it.")
+            .long("cpu-baseline")
+            .takes_value(true)
+            .possible_values(&["x86-64", "x86-64-v2", "x86-64-v3"]))
+        .arg(Arg::with_name("NO_AUTO_STATIC")
+            .help("Don't add the flags and variables the project needs to link statically, see `src/static_linking.rs`.")
+            .long("no-auto-static"))
+        .arg(Arg::with_name("HARDENED")
+            .help("Build a static-PIE, full RELRO executable, and verify its hardening properties.")
+            .long("hardened"))
+        .arg(Arg::with_name("REPRODUCIBLE")
+            .help("Build the same artifact, byte for byte, from the same source on any machine.")
+            .long("reproducible"))
+        .arg(Arg::with_name("STRIP")
+            .help("Strip debug info and symbols from the executable before packaging it.")
+            .long("strip"))
+        .arg(Arg::with_name("UPX")
+            .help("Compress the executable with UPX before packaging it. It decompresses itself when started.")
+            .long("upx"))
+        .arg(Arg::with_name("SIZE_REPORT")
+            .help("Report how the artifact's size changed since the last build, and the executable's size by crate.")
+            .long("size-report"))
+        .arg(Arg::with_name("STRICT_SIZE")
+            .help("In lambda mode, fail the build if the zip is over Lambda's size limits, instead of warning.")
+            .long("strict-size"))
+        .arg(Arg::with_name("DEBUG_IMAGE")
+            .help("In docker mode, also build a `bm_<project>-debug` image with a shell, based on busybox.")
+            .long("debug-image"))
+        .arg(Arg::with_name("WITH_CA_CERTS")
+            .help("In docker mode, put the builder's CA certificates in the image, so the executable can make TLS connections.")
+            .long("with-ca-certs"))
+        .arg(Arg::with_name("WITH_TZDATA")
+            .help("In docker mode, put the builder's time zone database (`/usr/share/zoneinfo`) in the image.")
+            .long("with-tzdata"))
+        .arg(Arg::with_name("USER")
+            .help("In docker mode, run the image as this unprivileged `uid[:gid]`, with `/etc/passwd` and `/etc/group` entries for it.")
+            .long("user")
+            .value_name("uid[:gid]")
+            .takes_value(true)
+            .validator(|v| User::parse(&v).map(|_| ())))
+        // No nightly-only cargo flags are used any more, this is only kept for existing scripts.
+        .arg(Arg::with_name("STABLE")
+            .help("Does nothing, builds no longer use nightly-only cargo flags.")
+            .long("stable")
+            .hidden(true))
+        .arg(Arg::with_name("TEST")
+            .help("Run `cargo test` for the target in the build container first, packaging nothing if it fails.")
+            .long("test"))
+        .arg(Arg::with_name("CLIPPY")
+            .help("Run `cargo clippy -- -D warnings` for the target in the build container first, packaging nothing if it fails.")
+            .long("clippy"))
+        .arg(Arg::with_name("SMOKE_TEST")
+            .help("In docker mode, run the built image with this command as its entrypoint, failing the build if it exits with anything but 0, e.g. `/my_project --version`.")
+            .long("smoke-test")
+            .value_name("command")
+            .takes_value(true)
+            .allow_hyphen_values(true))
+        .arg(Arg::with_name("SMOKE_TEST_TIMEOUT")
+            .help("Fail the smoke test if it's still running after this many seconds. Defaults to 30.")
+            .long("smoke-test-timeout")
+            .value_name("seconds")
+            .takes_value(true)
+            .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|_| "It has to be a number of seconds.".to_owned())))
+        .arg(Arg::with_name("INTEGRATION_TEST")
+            .help("In docker mode, run the `[integration_test]` from `BlackMagic.toml` against the built image.")
+            .long("integration-test"))
+        .arg(Arg::with_name("DIFF_AGAINST")
+            .help("In docker mode, compare the built image's files with this previously published image before pushing, failing on unexpected new paths.")
+            .long("diff-against")
+            .value_name("image")
+            .takes_value(true))
+        .arg(Arg::with_name("SCAN")
+            .help("In docker mode, scan the built image for vulnerabilities with trivy or grype before pushing it.")
+            .long("scan"))
+        .arg(Arg::with_name("SCAN_FAIL_ON")
+            .help("In docker mode, scan the built image, failing on vulnerabilities this severe or worse: low, medium, high or critical.")
+            .long("scan-fail-on")
+            .value_name("severity")
+            .takes_value(true)
+            .validator(scan::validate_severity))
+        .arg(Arg::with_name("EXPORT_OCI")
+            .help("In docker mode, also save the built image to this tarball, which `docker load` accepts, e.g. for air-gapped hosts.")
+            .long("export-oci")
+            .value_name("path")
+            .takes_value(true))
+        .arg(Arg::with_name("LOAD_INTO")
+            .help("In docker mode, load the built image into a local cluster: `kind:<cluster>` or `minikube[:<profile>]`.")
+            .long("load-into")
+            .takes_value(true)
+            .validator(|v| LocalCluster::parse(&v).map(|_| ())))
+        .arg(Arg::with_name("PROFILE")
+            .help("The cargo profile to build with, e.g. `dev` for quick debug builds, or a custom profile.")
+            .long("profile")
+            .takes_value(true)
+            .default_value("release"))
+        .arg(Arg::with_name("FEATURES")
+            .help("Space or comma separated list of cargo features to enable.")
+            .long("features")
+            .takes_value(true))
+        .arg(Arg::with_name("NO_DEFAULT_FEATURES")
+            .help("Don't enable the `default` cargo feature.")
+            .long("no-default-features"))
+        .arg(Arg::with_name("ALL_FEATURES")
+            .help("Enable all cargo features.")
+            .long("all-features"))
+        .arg(Arg::with_name("ISOLATE_FEATURES")
+            .help("When the cargo arguments select several workspace packages, compile just the executable's, with only its own features.")
+            .long("isolate-features"))
+        .arg(Arg::with_name("CARGO_ARG")
+            .help("An extra argument for `cargo build`. Can be repeated.")
+            .long("cargo-arg")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .allow_hyphen_values(true))
+        .arg(Arg::with_name("CARGO_ARGS")
+            .help("Extra arguments for `cargo build`, after `--`.")
+            .multiple(true)
+            .last(true))
+        .arg(Arg::with_name("RUSTFLAGS")
+            .help("Extra flags for rustc, exported as `RUSTFLAGS` for the compile, e.g. `-C target-feature=+crt-static`.")
+            .long("rustflags")
+            .value_name("flags")
+            .takes_value(true)
+            .allow_hyphen_values(true))
+        .arg(Arg::with_name("JOBS")
+            .help("How many jobs cargo runs at once, exported as `CARGO_BUILD_JOBS` for the compile.")
+            .long("jobs")
+            .short("j")
+            .value_name("n")
+            .takes_value(true)
+            .validator(|v| v.parse::<u32>().ok().filter(|j| *j > 0).map(|_| ()).ok_or_else(|| "It has to be a number of jobs.".to_owned())))
+        .arg(Arg::with_name("OPENSSL")
+            .help("Where `openssl-sys` gets OpenSSL for musl: the builder image's (`system`) or compiled from source (`vendored`).")
+            .long("openssl")
+            .takes_value(true)
+            .possible_values(openssl::MODES))
+        .arg(Arg::with_name("PLATFORMS")
+            .help("In docker mode, build one multi-platform image for these platforms, e.g. `linux/amd64,linux/arm64`.")
+            .long("platforms")
+            .takes_value(true)
+            .value_name("platforms"))
+        .arg(Arg::with_name("PUSH")
+            .help("In docker mode, tag the built image as this `registry/repo:tag` and push it. Can be repeated.")
+            .long("push")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("TAG")
+            .help("In docker mode, also tag the image `bm_<name>:<tag>`, and push repositories without a tag under it. Can be repeated.")
+            .long("tag")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(tags::validate))
+        .arg(Arg::with_name("TAG_GIT")
+            .help("In docker mode, also tag the image with the commit's short hash, and `-dirty` with uncommitted changes.")
+            .long("tag-git"))
+        .arg(Arg::with_name("ECR")
+            .help("In docker mode, push the built image to this `repo[:tag]` in the account's ECR registry, creating the repository if needed.")
+            .long("ecr")
+            .takes_value(true))
+        .arg(Arg::with_name("SSH")
+            .help("Forward the host's SSH agent into the build container, for git dependencies in private repositories.")
+            .long("ssh"))
+        .arg(Arg::with_name("SSH_KNOWN_HOSTS")
+            .help("With `--ssh`, the known_hosts file to check git hosts against. Defaults to `~/.ssh/known_hosts`.")
+            .long("ssh-known-hosts")
+            .value_name("path")
+            .takes_value(true)
+            .requires("SSH"))
+        .arg(Arg::with_name("GITCONFIG")
+            .help("Mount this git config into the build container, e.g. with `insteadOf` rewrites for private dependencies.")
+            .long("gitconfig")
+            .value_name("path")
+            .takes_value(true))
+        .arg(Arg::with_name("CARGO_CONFIG")
+            .help("Mount the host's cargo `config.toml` and `credentials.toml` into the build container, for private registries.")
+            .long("cargo-config"))
+        .arg(Arg::with_name("REGISTRY_TOKEN")
+            .help("Pass this registry's token (`CARGO_REGISTRIES_<NAME>_TOKEN`) from the environment to the build. Can be repeated.")
+            .long("registry-token")
+            .value_name("registry")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|r| if !r.is_empty() && r.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                Ok(())
+            } else {
+                Err(format!("`{}` isn't a registry name, as in `[registries.<name>]`.", r))
+            }))
+        .arg(Arg::with_name("NETWORK")
+            .help("Attach the build container, and the builder image's build, to this docker network, e.g. a VPN's.")
+            .long("network")
+            .value_name("name")
+            .takes_value(true))
+        .arg(Arg::with_name("DNS")
+            .help("Resolve names in the build container with this DNS server. Can be repeated.")
+            .long("dns")
+            .value_name("ip")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(network::validate_dns))
+        .arg(Arg::with_name("ADD_HOST")
+            .help("Add `host:ip` to the build container's, and the builder image's build's, `/etc/hosts`. Can be repeated.")
+            .long("add-host")
+            .value_name("host:ip")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(network::validate_host))
+        .arg(Arg::with_name("SECRET")
+            .help("Make this file available to the compile only, at `/run/secrets/<id>`, e.g. `id=netrc,src=.netrc`. Can be repeated.")
+            .long("secret")
+            .value_name("secret")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(secrets::validate))
+        .arg(Arg::with_name("BUILD_ENV")
+            .help("Set this variable for the compile, or pass it from the environment without a value. Can be repeated.")
+            .long("build-env")
+            .value_name("KEY[=VALUE]")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(build_env::validate))
+        .arg(Arg::with_name("ENV_FILE")
+            .help("Write the variables set for the compile, including the build metadata, to this file, as `KEY=VALUE` lines.")
+            .long("env-file")
+            .value_name("PATH")
+            .takes_value(true))
+        .arg(Arg::with_name("OFFLINE")
+            .help("Compile with `cargo build --offline`, from what's already in the cargo registry.")
+            .long("offline"))
+        .arg(Arg::with_name("PREFETCH")
+            .help("Run `cargo fetch` and `[prefetch]`'s commands first, then compile with `--network none`, see `src/prefetch.rs`.")
+            .long("prefetch")
+            .conflicts_with_all(&["OFFLINE", "VENDOR", "FROZEN"]))
+        .arg(Arg::with_name("LOCKED")
+            .help("Compile with `cargo build --locked`, failing rather than updating `Cargo.lock`.")
+            .long("locked"))
+        .arg(Arg::with_name("FROZEN")
+            .help("Compile with `cargo build --frozen`: `--locked`, and `--offline` too.")
+            .long("frozen")
+            .conflicts_with("LOCKED"))
+        .arg(Arg::with_name("STRICT")
+            .help("Fail, instead of warning, when `Cargo.lock` is missing or out of date with `Cargo.toml`.")
+            .long("strict"))
+        .arg(Arg::with_name("VENDOR")
+            .help("Compile offline from this `cargo vendor` directory, instead of the registry.")
+            .long("vendor")
+            .value_name("dir")
+            .takes_value(true))
+        .arg(Arg::with_name("CACHE_SERVER")
+            .help("Fetch and share compiled dependencies through this `black_magic cache-server`, e.g. `http://buildbox.lan:7878`.")
+            .long("cache-server")
+            .value_name("url")
+            .takes_value(true))
+        .arg(Arg::with_name("DOCKERFILE_TEMPLATE")
+            .help("In docker mode, build the project image from this Dockerfile template instead of `black_magic.Dockerfile`.")
+            .long("dockerfile-template")
+            .value_name("path")
+            .takes_value(true))
+        .arg(Arg::with_name("BASE")
+            .help("In docker mode, the image to build the project image on: `scratch` (the default), `distroless`, `alpine`, `busybox`, or any other.")
+            .long("base")
+            .value_name("image")
+            .takes_value(true)
+            .validator(template::validate_base))
+        .arg(Arg::with_name("ENTRYPOINT")
+            .help("In docker mode, the image's entrypoint, split on whitespace. Defaults to the executable if the image is made runnable.")
+            .long("entrypoint")
+            .takes_value(true))
+        .arg(Arg::with_name("CMD")
+            .help("In docker mode, the image's default arguments, split on whitespace.")
+            .long("cmd")
+            .takes_value(true)
+            .allow_hyphen_values(true))
+        .arg(Arg::with_name("EXPOSE")
+            .help("In docker mode, a port the image exposes, e.g. `80/tcp`. Can be repeated.")
+            .long("expose")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v| template::parse_expose(&v)))
+        .arg(Arg::with_name("ENV")
+            .help("In docker mode, set `KEY=VALUE` in the image's environment. Can be repeated.")
+            .long("env")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v| template::parse_env(&v).map(|_| ())))
+        .arg(Arg::with_name("INCLUDE")
+            .help("In lambda mode, also put this file or directory of the project in the zip, at `dest` if given. Can be repeated.")
+            .long("include")
+            .value_name("path[:dest]")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
+        .arg(Arg::with_name("S3")
+            .help("In lambda mode, upload the zip to this S3 location while it's packaged, e.g. `s3://my-bucket/lambdas/` or `s3://my-bucket/{name}-{version}-{git_sha}.zip`.")
+            .long("s3")
+            .visible_alias("upload-s3")
+            .takes_value(true)
+            .value_name("URL"))
+        .arg(Arg::with_name("LAMBDA_RUNTIME")
+            .help("In lambda mode, the Lambda runtime to build for, checking the executable runs on it. Added to the zip's name.")
+            .long("lambda-runtime")
+            .takes_value(true)
+            .possible_values(lambda_runtime::NAMES))
+        .arg(Arg::with_name("SERVERLESS")
+            .help("In lambda mode, the serverless platform to lay the zip out for: `aws` (Lambda's `bootstrap`), `gcf` or `azure`.")
+            .long("serverless")
+            .takes_value(true)
+            .possible_values(packaging::NAMES))
+        .arg(Arg::with_name("RESOURCE_REPORT")
+            .help("Report the CPU-seconds, peak memory, downloads and cache reuse of the build container, sampled with `docker stats`.")
+            .long("resource-report"))
+        .arg(Arg::with_name("CPUS")
+            .help("Limit the build container to this many CPUs, e.g. `2` or `1.5`.")
+            .long("cpus")
+            .value_name("n")
+            .takes_value(true)
+            .validator(bounds::validate_cpus))
+        .arg(Arg::with_name("MEMORY")
+            .help("Limit the build container to this much memory, e.g. `4g`.")
+            .long("memory")
+            .value_name("size")
+            .takes_value(true)
+            .validator(bounds::validate_memory))
+        .arg(Arg::with_name("TIMEOUT")
+            .help("Fail the build, removing its container, if it's still running after this many seconds.")
+            .long("timeout")
+            .value_name("seconds")
+            .takes_value(true)
+            .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|_| "It has to be a number of seconds.".to_owned())))
+        .arg(Arg::with_name("RETRIES")
+            .help("Retry building the builder image, and the compile, this many times when they fail with what looks like a network problem, e.g. a registry or crates.io timing out.")
+            .long("retries")
+            .value_name("n")
+            .takes_value(true)
+            .validator(retry::validate_retries))
+        .arg(Arg::with_name("RETRY_DELAY")
+            .help("Wait this many seconds before the first retry, twice as long before each one after it. 5 by default.")
+            .long("retry-delay")
+            .value_name("seconds")
+            .takes_value(true)
+            .requires("RETRIES")
+            .validator(retry::validate_delay))
+        .arg(Arg::with_name("OUT_DIR")
+            .help("Also copy the artifact, its SHA-256 and manifest into this directory, e.g. CI's artifacts directory.")
+            .long("out-dir")
+            .value_name("path")
+            .takes_value(true))
+        .arg(Arg::with_name("ARTIFACT_NAME")
+            .help("Name the copied artifact this, with `{name}`, `{version}`, `{git_sha}`, `{target}` and `{arch}` filled in, \
+                e.g. `{name}-{version}-{git_sha}`.")
+            .long("artifact-name")
+            .value_name("name")
+            .takes_value(true)
+            .validator(out_dir::validate_name))
+        .arg(Arg::with_name("COMPRESSION")
+            .help("In lambda mode, how hard to compress the zip, from `store` (none, quickest to unzip) to `best`.")
+            .long("compression")
+            .takes_value(true)
+            .possible_values(bundle::Compression::NAMES))
+        .arg(Arg::with_name("SBOM")
+            .help("Write a software bill of materials of the artifact's dependencies next to it, e.g. `api.cdx.json`.")
+            .long("sbom")
+            .value_name("format")
+            .takes_value(true)
+            .possible_values(sbom::FORMATS))
+        .arg(Arg::with_name("SIGN")
+            .help("Sign the artifact, and pushed images with cosign. Configured in the `[sign]` section of `BlackMagic.toml`.")
+            .long("sign")
+            .value_name("method")
+            .takes_value(true)
+            .possible_values(sign::METHODS))
+        .arg(Arg::with_name("SBOM_LABEL")
+            .help("In docker mode, put the SBOM in the image too, in the `dev.black_magic.sbom` label.")
+            .long("sbom-label"))
+        .arg(Arg::with_name("EMIT_SAM")
+            .help("In lambda mode, also write a SAM template for the zip, for `sam deploy` or `sam local`.")
+            .long("emit-sam"))
+        .arg(Arg::with_name("EMIT_TERRAFORM")
+            .help("Also write a Terraform fragment for the zip or image, with its SHA-256 so Terraform picks up changes.")
+            .long("emit-terraform"))
+        .arg(Arg::with_name("EMIT_COMPOSE")
+            .help("In docker mode, also add a service running the image to `docker-compose.yml`, for `docker compose up`.")
+            .long("emit-compose"))
+        .arg(Arg::with_name("DEPLOY")
+            .help("In lambda mode, upload the zip to this existing Lambda function and publish a new version. With `--lambda-image`, point it at the image pushed to `--ecr`.")
+            .long("deploy")
+            .takes_value(true)
+            .value_name("FUNCTION"))
+        .arg(Arg::with_name("REGION")
+            .help("The AWS region to use, instead of the configured default (`AWS_REGION`, or the profile's).")
+            .long("region")
+            .takes_value(true))
+        .arg(Arg::with_name("AWS_PROFILE")
+            .help("The AWS profile to use, from `~/.aws/config`.")
+            .long("aws-profile")
+            .takes_value(true))
+        .arg(Arg::with_name("BUILDER_IMAGE")
+            .help("The base image to build the builder image from. Defaults to the `rust_musl_docker` image.")
+            .long("builder-image")
+            .takes_value(true))
+        .arg(Arg::with_name("BUILDER_TAG")
+            .help("The tag of the base image to build the builder image from.")
+            .long("builder-tag")
+            .takes_value(true))
+        .arg(Arg::with_name("BACKEND")
+            .help("How to compile the executable: in the builder image, or on the host without a build container (`cross`, `zigbuild`, `native`). Defaults to the `[backend]` section of `BlackMagic.toml`, then `docker-musl` (`docker-gnu` for `--libc gnu`).")
+            .long("backend")
+            .takes_value(true)
+            .possible_values(backend::ACCEPTED))
+        .arg(Arg::with_name("UPDATE_BUILDER")
+            .help("Rebuild the builder image, pulling its base image again.")
+            .long("update-builder"))
+        .arg(Arg::with_name("BUILDER_CACHE_FROM")
+            .help("Pull a missing builder image from this repository, e.g. `ghcr.io/acme/builders`, using it, or its layers to build it.")
+            .long("builder-cache-from")
+            .takes_value(true)
+            .value_name("registry/repo"))
+        .arg(Arg::with_name("BUILDER_CACHE_PUSH")
+            .help("With `--builder-cache-from`, push the builder image there whenever it's built.")
+            .long("builder-cache-push"))
+        .arg(Arg::with_name("AUTO_UPDATE_BUILDER")
+            .help("Rebuild the builder image, as `--update-builder` does, once it's older than `max_age_days` in `[builder]` (90 by default).")
+            .long("auto-update-builder"))
+        .arg(Arg::with_name("CARGO_HOME")
+            .help("Where cargo's home is inside the build container.")
+            .long("cargo-home")
+            .takes_value(true))
+        .arg(Arg::with_name("CARGO_LOCK_TIMEOUT")
+            .help("How many seconds to wait for another cargo process to unlock the host's cargo registry, before using a private one.")
+            .long("cargo-lock-timeout")
+            .value_name("seconds")
+            .takes_value(true)
+            .default_value("30")
+            .validator(|v| v.parse::<u64>().map(|_| ()).map_err(|_| "It has to be a number of seconds.".to_owned())))
+        .arg(Arg::with_name("NO_WAIT")
+            .help("Fail straight away if another build of the project is in progress, instead of waiting for it.")
+            .long("no-wait"))
+        .arg(Arg::with_name("CARGO_HOME_VOLUME")
+            .help("Keep the build container's cargo home in this named volume, instead of mounting the host's `~/.cargo`.")
+            .long("cargo-home-volume")
+            .takes_value(true))
+        .arg(Arg::with_name("CARGO_CACHE")
+            .help("How the build container gets cargo's registry and git checkouts: the host's (`share`, the default), a volume of its own (`isolated`), or the host's read-only under volumes of its own (`ro-overlay`).")
+            .long("cargo-cache")
+            .takes_value(true)
+            .possible_values(cargo_cache::MODES))
+        .arg(Arg::with_name("LAYERED")
+            .help("Compile with `docker build`, keeping the compiled dependencies in a cached image layer instead of a volume.")
+            .long("layered"))
+        .arg(Arg::with_name("STRATEGY")
+            .help("How to compile: in a container with the project mounted (`run`, the default), or with one `docker build` (`dockerfile`), like `--layered` but with BuildKit cache mounts.")
+            .long("strategy")
+            .takes_value(true)
+            .possible_values(&["run", "dockerfile"])
+            .default_value("run"))
+        .arg(Arg::with_name("SCCACHE")
+            .help("Compile through sccache, sharing compiled crates between projects, and machines with `[sccache]` in `BlackMagic.toml`.")
+            .long("sccache"))
+        .arg(Arg::with_name("NO_CACHE")
+            .help("Don't reuse the project's cache volume or previously built artifacts, compile everything from scratch.")
+            .long("no-cache"))
+        .arg(Arg::with_name("NO_ARTIFACT_CACHE")
+            .help("Compile even if the source is unchanged since a previous build, still reusing the cache volume.")
+            .long("no-artifact-cache"))
+        .arg(Arg::with_name("SKIP_UNCHANGED")
+            .help("Do nothing if neither the source, the options nor the builder image have changed since the last successful build, but report what it built.")
+            .long("skip-unchanged"))
+        .arg(Arg::with_name("FROM_PHASE")
+            .help("Start the build at `package`, from the executable the last compile extracted, or `image`, from the last tarball. Builds start at `package` anyway if only what's packaged with the executable has changed.")
+            .long("from-phase")
+            .takes_value(true)
+            .possible_values(phases::NAMES)
+            .conflicts_with_all(&["NO_CACHE", "DEPS_ONLY", "S3", "WATCH", "BUNDLE", "BINS", "PLATFORMS"]))
+        .arg(Arg::with_name("WATCH")
+            .help("Build again every time the source changes, until interrupted.")
+            .long("watch"))
+        .arg(Arg::with_name("NO_SIDE_EFFECTS")
+            .help("Resolve and check the build, then print what it would do and produce, without writing files, creating images, or using the network.")
+            .long("no-side-effects"))
+        .arg(Arg::with_name("SHADOW_TARGET")
+            .help("Hide the project's `target` from the build container behind an empty tmpfs, mounting only `target/black_magic` back.")
+            .long("shadow-target"))
+        .arg(Arg::with_name("READ_ONLY_SOURCE")
+            .help("Mount the project read-only, compiling into a volume of the build container's own and copying the outputs out after.")
+            .long("read-only-source"))
+        .arg(Arg::with_name("DEPS_ONLY")
+            .help("Compile just the dependencies into the cache volumes, without the project's own code or an artifact, see `black_magic warm`.")
+            .long("deps-only")
+            .conflicts_with_all(&["S3", "SKIP_UNCHANGED", "SHELL", "DEBUG_SHELL", "WATCH", "BUNDLE", "BINS", "PLATFORMS"]))
+        .arg(Arg::with_name("USER_MAP")
+            .help("Give the files the build container writes into the project and cargo home back to you. The default for docker on Linux.")
+            .long("user-map"))
+        .arg(Arg::with_name("NO_USER_MAP")
+            .help("Leave the files the build container writes owned by its root user, see `--user-map`.")
+            .long("no-user-map")
+            .conflicts_with("USER_MAP"))
+        .arg(Arg::with_name("KEEP_ON_FAILURE")
+            .help("Keep the build container if the build fails, to inspect it. The full output is always in `target/black_magic/last_build.*`.")
+            .long("keep-on-failure"))
+        .arg(Arg::with_name("SHELL")
+            .help("Open a shell in the build container, with the build's mounts and environment, instead of building.")
+            .long("shell"))
+        .arg(Arg::with_name("DEBUG_SHELL")
+            .help("If the compile fails, open a shell in the build container, with the build's mounts and environment.")
+            .long("debug-shell"))
+        .arg(Arg::with_name("DRY_RUN")
+            .help("Like `--no-side-effects`, and also print every command it would run, and the Dockerfiles it would write, to reproduce the build by hand.")
+            .long("dry-run"))
+        .arg(Arg::with_name("RUNTIME")
+            .help("The container runtime to use. Defaults to `BM_RUNTIME`, then whichever is installed, then a running docker daemon.")
+            .long("runtime")
+            .takes_value(true)
+            .possible_values(&["docker", "podman"])
+            .global(true))
+        .arg(Arg::with_name("NOTIFY")
+            .help("Notify when the build finishes: on the desktop, and any webhooks in `[notify]` in `BlackMagic.toml`.")
+            .long("notify"))
+        .arg(Arg::with_name("PRESET")
+            .help("Build with the options in `[preset.<name>]` in `BlackMagic.toml`, except for those given here too.")
+            .long("preset")
+            .value_name("name")
+            .takes_value(true))
+        .arg(Arg::with_name("NAME")
+            .help("The name to give artifacts, images and volumes, instead of one derived from the project directory's.")
+            .long("name")
+            .takes_value(true)
+            .global(true))
+        .arg(Arg::with_name("PORCELAIN")
+            .help("Print only stable lifecycle markers (e.g. `ARTIFACT path=… digest=…`) to stdout, for wrapper scripts. See `src/output.rs`.")
+            .long("porcelain")
+            .global(true))
+        .arg(Arg::with_name("OUTPUT_FORMAT")
+            .help("`json` prints a JSON record describing the artifact at the end, instead of progress messages.")
+            .long("output-format")
+            .takes_value(true)
+            .possible_values(&["human", "json"])
+            .default_value("human")
+            .global(true))
+        .arg(Arg::with_name("CI")
+            .help("Group the log by phase, annotate compile errors, and set the artifact as outputs, for GitHub Actions or GitLab CI. See `src/ci.rs`.")
+            .long("ci")
+            .takes_value(true)
+            .possible_values(ci::SYSTEMS)
+            .global(true))
+        .arg(Arg::with_name("VERBOSE")
+            .help("Also report the commands being run. With `--output-format json`, streams every progress message as a JSON line. \
+                `-vv` also prints the build's log to stderr, see `src/logging.rs`.")
+            .short("v")
+            .long("verbose")
+            .multiple(true)
+            .global(true))
+        .arg(Arg::with_name("QUIET")
+            .help("Don't print progress messages, only warnings and errors.")
+            .short("q")
+            .long("quiet")
+            .conflicts_with("VERBOSE")
+            .global(true))
+        .arg(Arg::with_name("LOG_FILE")
+            .help("Append the build's log to this file, e.g. `target/black_magic/build.log`, with every command run and how long each phase took.")
+            .long("log-file")
+            .value_name("path")
+            .takes_value(true)
+            .global(true))
+        .subcommand(SubCommand::with_name("pipeline")
+            .about("Runs a named pipeline of checks, tests, builds and commands from `[pipelines]` in `BlackMagic.toml`.")
+            .arg(Arg::with_name("PIPELINE")
+                .help("The pipeline to run. Lists the pipelines without it.")))
+        .subcommand(SubCommand::with_name("clean")
+            .about("Removes `target/black_magic`, and with `--cache` or `--images` what black_magic keeps in docker for the project.")
+            .arg(Arg::with_name("CACHE")
+                .help("Also remove the project's cache volumes, and `--layered` builds' layer cache images.")
+                .long("cache"))
+            .arg(Arg::with_name("IMAGES")
+                .help("Also remove the project's `bm_<project>` images, and the builder images every project shares.")
+                .long("images"))
+            .arg(Arg::with_name("STALE_BUILDERS")
+                .help("Also remove the customized and pinned builder images, of any project, that no build has used for this many days.")
+                .long("stale-builders")
+                .value_name("days")
+                .takes_value(true)
+                .validator(|v| v.parse::<u32>().map(|_| ()).map_err(|_| "It has to be a number of days.".to_owned())))
+            .arg(Arg::with_name("DRY_RUN")
+                .help("List what would be removed, and how much space that would reclaim, without removing anything.")
+                .long("dry-run")))
+        .subcommand(SubCommand::with_name("status")
+            .about("Lists what black_magic keeps for the project: its artifacts, images, cache volumes and builder images.")
+            .alias("list"))
+        .subcommand(SubCommand::with_name("shell")
+            .about("Opens a shell in the build container, with the mounts and environment a build has, see `--shell`.")
+            .arg(Arg::with_name("ARGS")
+                .help("The black_magic arguments of the build.")
+                .long("args")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .default_value("--docker")))
+        .subcommand(SubCommand::with_name("warm")
+            .about("Compiles just the project's dependencies into the cache volumes, so the next build is faster.")
+            .arg(Arg::with_name("ARGS")
+                .help("The black_magic arguments of the build.")
+                .long("args")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .default_value("--docker")))
+        .subcommand(SubCommand::with_name("bench-builders")
+            .about("Builds the project with each candidate builder image, comparing compile times and artifact sizes.")
+            .arg(Arg::with_name("CANDIDATE")
+                .help("A builder to compare, as `<tag>` of the default image or `<image>:<tag>`. Defaults to `[bench]` in `BlackMagic.toml`.")
+                .long("candidate")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("ARGS")
+                .help("The black_magic arguments to build with.")
+                .long("args")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .default_value("--docker")))
+        .subcommand(SubCommand::with_name("cache-server")
+            .about("Serves compiled dependencies to teammates' builds over the LAN, see `--cache-server`.")
+            .arg(Arg::with_name("LISTEN")
+                .help("The address to listen on, `0.0.0.0:7878` to serve the LAN.")
+                .long("listen")
+                .takes_value(true)
+                .default_value("127.0.0.1:7878"))
+            .arg(Arg::with_name("DIR")
+                .help("Where to keep the snapshots. Defaults to `~/.cache/black_magic/cache-server`.")
+                .long("dir")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("init")
+            .about("Starts a project (or sets up an existing crate) from a template that builds and packages cleanly with black_magic.")
+            .arg(Arg::with_name("TEMPLATE")
+                .help("The kind of project: `lambda-http` is a Lambda function behind an HTTP API.")
+                .long("template")
+                .takes_value(true)
+                .possible_values(init::TEMPLATES))
+            .arg(Arg::with_name("LAMBDA")
+                .help("A Lambda function, with a `lambda_runtime` handler and a sample event.")
+                .long("lambda"))
+            .arg(Arg::with_name("DOCKER")
+                .help("A container image, with an `axum` server on port 8080.")
+                .long("docker"))
+            .group(ArgGroup::with_name("KIND")
+                .args(&["TEMPLATE", "LAMBDA", "DOCKER"])
+                .required(true))
+            .arg(Arg::with_name("DIR")
+                .help("The directory to start it in, which it's named after, or an existing crate's. Defaults to the current directory.")))
+        .subcommand(SubCommand::with_name("invoke")
+            .about("Runs the built Lambda zip locally in Lambda's runtime emulator, posting a payload to it and printing the response.")
+            .arg(Arg::with_name("PAYLOAD")
+                .help("The event to invoke the function with, as a JSON file, or `-` for stdin. Defaults to `{}`.")
+                .long("payload")
+                .takes_value(true))
+            .arg(Arg::with_name("ARTIFACT")
+                .help("The zip to invoke. Defaults to the project's, for `--arch`.")
+                .long("artifact")
+                .takes_value(true))
+            .arg(Arg::with_name("ARCH")
+                .help("The CPU architecture the zip was built for.")
+                .long("arch")
+                .takes_value(true)
+                .possible_values(&["x86_64", "aarch64"])
+                .default_value("x86_64"))
+            .arg(Arg::with_name("LAMBDA_RUNTIME")
+                .help("The Lambda runtime the zip was built for, see `--lambda-runtime`.")
+                .long("lambda-runtime")
+                .takes_value(true)
+                .possible_values(lambda_runtime::NAMES))
+            .arg(Arg::with_name("IMAGE")
+                .help("The Lambda base image to run it in. Defaults to the runtime's, or `provided.al2023`'s.")
+                .long("image")
+                .takes_value(true))
+            .arg(Arg::with_name("ENV")
+                .help("Set `KEY=VALUE` in the function's environment. Can be repeated.")
+                .long("env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|v| template::parse_env(&v).map(|_| ()))))
+        .subcommand(SubCommand::with_name("run")
+            .about("Builds the docker image if anything has changed, and runs it, publishing its ports.")
+            .arg(Arg::with_name("ARGS")
+                .help("The black_magic arguments of the build.")
+                .long("args")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .default_value("--docker"))
+            .arg(Arg::with_name("PUBLISH")
+                .help("Publish this port, as `host:container[/protocol]`, instead of each one the image exposes. Can be repeated.")
+                .short("p")
+                .long("publish")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("ENV_FILE")
+                .help("Pass the variables in this file, instead of the project's `.env`. Can be repeated.")
+                .long("env-file")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("ENV")
+                .help("Set `KEY=VALUE` in the container's environment. Can be repeated.")
+                .long("env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|v| template::parse_env(&v).map(|_| ())))
+            .arg(Arg::with_name("IMAGE_ARGS")
+                .help("Arguments for the image, after `--`.")
+                .multiple(true)
+                .last(true)))
+        .subcommand(SubCommand::with_name("completions")
+            .about("Prints the completion script for a shell, e.g. `black_magic completions bash > /etc/bash_completion.d/black_magic`.")
+            .arg(Arg::with_name("SHELL")
+                .help("The shell to complete for.")
+                .required(true)
+                .possible_values(docs::SHELLS)))
+        .subcommand(SubCommand::with_name("man")
+            .about("Prints black_magic's man page, e.g. `black_magic man > /usr/local/share/man/man1/black_magic.1`."))
+        .subcommand(SubCommand::with_name("doctor")
+            .about("Checks this machine can build: the container runtime, disk space, the builder image, network, file sharing and the project's OpenSSL.")
+            .arg(Arg::with_name("ARCH")
+                .help("The CPU architecture whose builder image to check.")
+                .long("arch")
+                .takes_value(true)
+                .possible_values(&["x86_64", "aarch64"])
+                .default_value("x86_64")))
+        .subcommand(SubCommand::with_name("stats")
+            .about("Prints trends in the project's recent builds: how long they took, what the cache saved, and how the artifacts grew.")
+            .arg(Arg::with_name("LAST")
+                .help("How many of the most recent builds to go by.")
+                .long("last")
+                .value_name("builds")
+                .takes_value(true)
+                .default_value("20")
+                .validator(|v| v.parse::<usize>().ok().filter(|n| *n > 0).map(|_| ()).ok_or_else(|| "It has to be a number of builds.".to_owned()))))
+        .subcommand(SubCommand::with_name("pin-builder")
+            .about("Pins the builder's base image to the digest its tag points at now, in `[builder.pins]` in `BlackMagic.toml`.")
+            .arg(Arg::with_name("ARCH")
+                .help("The CPU architecture whose base image to pin.")
+                .long("arch")
+                .takes_value(true)
+                .possible_values(&["x86_64", "aarch64"])
+                .default_value("x86_64"))
+            .arg(Arg::with_name("LIBC")
+                .help("The C library whose base image to pin.")
+                .long("libc")
+                .takes_value(true)
+                .possible_values(&["musl", "gnu"])
+                .default_value("musl")))
+        .subcommand(SubCommand::with_name("inspect")
+            .about("Shows what's inside a built zip, tarball, or image: contents, linkage, manifest, and whether the source has changed since.")
+            .arg(Arg::with_name("ARTIFACT")
+                .help("The artifact file (e.g. `target/black_magic/my_project.zip`) or image (e.g. `bm_my_project`).")
+                .required(true)))
+        .subcommand(SubCommand::with_name("verify")
+            .about("Checks a signed artifact against its checksum and signature, or a pushed image's signature in its registry.")
+            .arg(Arg::with_name("TARGET")
+                .help("The artifact file (e.g. `target/black_magic/my_project.zip`) or pushed image (e.g. `registry/bm_my_project:1.0`).")
+                .required(true))
+            .arg(Arg::with_name("PUBLIC_KEY")
+                .help("The minisign or cosign public key to check the signature with.")
+                .long("public-key")
+                .takes_value(true))
+            .arg(Arg::with_name("IDENTITY")
+                .help("Who a keyless cosign signature has to be from, e.g. a CI workflow's URL.")
+                .long("identity")
+                .takes_value(true))
+            .arg(Arg::with_name("ISSUER")
+                .help("The OIDC issuer of `--identity`, e.g. `https://token.actions.githubusercontent.com`.")
+                .long("issuer")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("publish-ecr")
+            .about("Builds the image, and pushes it to ECR tagged with the commit and `latest`, creating the repository if needed, then prints its URI by digest.")
+            .arg(Arg::with_name("REPO")
+                .help("The ECR repository, e.g. `my-service`.")
+                .long("repo")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("REGION")
+                .help("The AWS region to use, instead of the configured default (`AWS_REGION`, or the profile's).")
+                .long("region")
+                .takes_value(true))
+            .arg(Arg::with_name("AWS_PROFILE")
+                .help("The AWS profile to use, from `~/.aws/config`.")
+                .long("aws-profile")
+                .takes_value(true))
+            .arg(Arg::with_name("BUILD_ARGS")
+                .help("Options for the build, e.g. `-- --base distroless`.")
+                .multiple(true)
+                .last(true)))
+        .subcommand(SubCommand::with_name("retag")
+            .about("Tags an image already in a registry with new references, copying its manifest without pulling or pushing layers.")
+            .arg(Arg::with_name("EXISTING")
+                .help("The image to retag, e.g. `registry/bm_api:abc123`.")
+                .required(true))
+            .arg(Arg::with_name("NEW")
+                .help("The new references, e.g. `registry/bm_api:prod`, or just `:prod` for another tag of the same repository.")
+                .required(true)
+                .multiple(true)))
+        .subcommand(SubCommand::with_name("changelog")
+            .about("Describes what changed between two builds, from their manifests, as markdown for a deploy PR.")
+            .arg(Arg::with_name("BEFORE")
+                .help("The manifest of the build being replaced, e.g. a copy of `target/black_magic/my_project.manifest.json`.")
+                .required(true))
+            .arg(Arg::with_name("AFTER")
+                .help("The manifest of the new build.")
+                .required(true))
+            .arg(Arg::with_name("OUT")
+                .help("Write the markdown to this file instead of printing it.")
+                .long("out")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("release")
+            .about("Bumps the crate version, builds everything in `[release]` of `BlackMagic.toml`, then commits and tags it.")
+            .arg(Arg::with_name("LEVEL")
+                .help("Which part of the version to bump.")
+                .long("level")
+                .takes_value(true)
+                .possible_values(&["major", "minor", "patch"])
+                .default_value("patch"))
+            .arg(Arg::with_name("ALLOW_DIRTY")
+                .help("Release even if the git working tree has uncommitted changes.")
+                .long("allow-dirty"))
+            .arg(Arg::with_name("GITHUB")
+                .help("Push the tag and upload the artifacts to a GitHub Release, using `GH_TOKEN` or `GITHUB_TOKEN`.")
+                .long("github")))
+        .subcommand(SubCommand::with_name("self-update")
+            .about("Replaces black_magic with the newest release's executable for this platform, after checking it.")
+            .arg(Arg::with_name("CHANNEL")
+                .help("Which releases to update to: `nightly` includes pre-releases.")
+                .long("channel")
+                .takes_value(true)
+                .possible_values(self_update::CHANNELS)
+                .default_value("stable"))
+            .arg(Arg::with_name("CHECK")
+                .help("Only say whether there's a newer release, without updating.")
+                .long("check"))
+            .arg(Arg::with_name("FORCE")
+                .help("Reinstall the newest release even if it isn't newer than this one.")
+                .long("force")
+                .conflicts_with("CHECK"))
+            .arg(Arg::with_name("SOURCE")
+                .help("Where the releases are.")
+                .long("source")
+                .takes_value(true)
+                .possible_values(self_update::SOURCES)
+                .default_value("github"))
+            .arg(Arg::with_name("REPO")
+                .help("The repository to update from, instead of `peterreeves/black_magic`, e.g. a fork or a mirror.")
+                .long("repo")
+                .takes_value(true)
+                .value_name("owner/repo"))
+            .arg(Arg::with_name("PUBLIC_KEY")
+                .help("The minisign or cosign public key the release's signature has to check out with.")
+                .long("public-key")
+                .takes_value(true))
+            .arg(Arg::with_name("IDENTITY")
+                .help("Who a keyless cosign signature of the release has to be from, e.g. its release workflow's URL.")
+                .long("identity")
+                .takes_value(true))
+            .arg(Arg::with_name("ISSUER")
+                .help("The OIDC issuer of `--identity`, e.g. `https://token.actions.githubusercontent.com`.")
+                .long("issuer")
+                .takes_value(true)))
+}
+
+fn run(matches: &ArgMatches) -> Result<(), BmError> {
+    let started = Instant::now();
+    if matches.subcommand_name().is_none() {
+        output::marker("BEGIN_BUILD", &[("version", env!("CARGO_PKG_VERSION"))]);
+    }
+
+    if let Some(clean_matches) = matches.subcommand_matches("clean") {
+        return clean::clean(clean_matches);
+    } else if let Some(status_matches) = matches.subcommand_matches("status") {
+        return overview::status(status_matches);
+    } else if let Some(release_matches) = matches.subcommand_matches("release") {
+        return release::release(release_matches);
+    } else if let Some(update_matches) = matches.subcommand_matches("self-update") {
+        return self_update::self_update(update_matches);
+    } else if let Some(changelog_matches) = matches.subcommand_matches("changelog") {
+        return changelog::changelog(changelog_matches);
+    } else if let Some(init_matches) = matches.subcommand_matches("init") {
+        return init::init(init_matches);
+    } else if let Some(invoke_matches) = matches.subcommand_matches("invoke") {
+        return invoke::invoke(invoke_matches);
+    } else if let Some(run_matches) = matches.subcommand_matches("run") {
+        return run_image::run(run_matches);
+    } else if let Some(publish_matches) = matches.subcommand_matches("publish-ecr") {
+        return ecr::publish(publish_matches);
+    } else if let Some(retag_matches) = matches.subcommand_matches("retag") {
+        return registry::retag(retag_matches);
+    } else if let Some(completions_matches) = matches.subcommand_matches("completions") {
+        return docs::completions(completions_matches);
+    } else if matches.subcommand_matches("man").is_some() {
+        return docs::man();
+    } else if let Some(doctor_matches) = matches.subcommand_matches("doctor") {
+        return doctor::doctor(doctor_matches);
+    } else if let Some(stats_matches) = matches.subcommand_matches("stats") {
+        return history::stats(stats_matches);
+    } else if let Some(pin_matches) = matches.subcommand_matches("pin-builder") {
+        return pin::pin_builder(pin_matches);
+    } else if let Some(inspect_matches) = matches.subcommand_matches("inspect") {
+        return inspect::inspect(inspect_matches);
+    } else if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        return sign::verify(verify_matches);
+    } else if let Some(server_matches) = matches.subcommand_matches("cache-server") {
+        return cache_server::serve(server_matches);
+    } else if let Some(pipeline_matches) = matches.subcommand_matches("pipeline") {
+        return pipeline::pipeline(pipeline_matches);
+    } else if let Some(bench_matches) = matches.subcommand_matches("bench-builders") {
+        return bench::bench_builders(bench_matches);
+    } else if let Some(shell_matches) = matches.subcommand_matches("shell") {
+        return debug_shell::shell(shell_matches);
+    } else if let Some(warm_matches) = matches.subcommand_matches("warm") {
+        return warm::warm(warm_matches);
+    }
+
+    let shell = matches.is_present("SHELL") || matches.is_present("DEBUG_SHELL");
+    if shell && ["WATCH", "BUNDLE", "BINS", "PLATFORMS"].iter().any(|a| matches.is_present(a)) {
+        return Err(BmError::Environment("`--shell` and `--debug-shell` open a shell for one build, not `--watch`, `--bundle`, `--bins` or `--platforms`.".to_owned()));
+    } else if matches.is_present("SHELL") && (matches.is_present("NO_SIDE_EFFECTS") || matches.is_present("DRY_RUN")) {
+        return Err(BmError::Environment("`--shell` runs the build container, so it can't be used with `--no-side-effects` or `--dry-run`.".to_owned()));
+    }
+
+    if matches.is_present("PARALLEL") && !["BUNDLE", "BINS", "PLATFORMS"].iter().any(|a| matches.is_present(a)) {
+        return Err(BmError::Environment("`--parallel` runs several builds at once, for `--bins`, `--bundle` or `--platforms`.".to_owned()));
+    }
+    if matches.is_present("WATCH") {
+        return watch::watch(matches);
+    } else if matches.is_present("BUNDLE") {
+        return multiarch::bundle(matches, started);
+    } else if matches.is_present("BINS") {
+        return bins::build_all(matches, started);
+    } else if matches.is_present("PLATFORMS") {
+        return multiarch::platforms(matches, started);
+    } else if matches.is_present("LAMBDA") && matches.is_present("DOCKER") {
+        return dual::build(matches, started);
+    }
+
+    if let Some(built) = build(&BuildConfig::from_matches(matches)?, started)?.filter(|_| output::is_json()) {
+        output::emit("done", built.record);
+    }
+    Ok(())
+}
+
+/// The image's `--entrypoint`, `--cmd`, `--expose`, `--env` and `--user`, see `template`.
+fn run_options(options: &BuildConfig, user: Option<&User>) -> Result<template::RunOptions, BmError> {
+    let split = |value: &Option<String>| value.as_ref().map(|v| v.split_whitespace().map(|a| a.to_owned()).collect());
+    Ok(template::RunOptions {
+        entrypoint: split(&options.entrypoint),
+        cmd: split(&options.cmd),
+        expose: options.expose.clone(),
+        env: options.env.iter().map(|e| template::parse_env(e)).collect::<Result<_, _>>().map_err(BmError::Environment)?,
+        user: user.map(|u| u.spec()),
+    })
+}
+
+/// The project is named after its directory.
+fn project_name(current_dir: &Path) -> Result<&str, BmError> {
+    current_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| BmError::Environment(format!("Unable to get a project name from `{}`.", current_dir.display())))
+}
+
+/// The arguments black_magic was run with, for running a build of its own with. Without the `flags`, the `options` and their
+/// values, or the output format.
+fn forwarded_args(flags: &[&str], options: &[&str]) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut skip_value = false;
+    let mut cargo_args = false;
+    let options: Vec<&str> = options.iter().copied().chain(["--output-format"]).collect();
+    for arg in env::args().skip(1) {
+        if cargo_args {
+            args.push(arg);
+        } else if skip_value {
+            skip_value = false;
+        } else if options.contains(&arg.as_str()) {
+            skip_value = true;
+        } else if !flags.contains(&arg.as_str()) && arg != "--porcelain" && arg != "--notify" && !options.iter().any(|o| arg.starts_with(&format!("{}=", o))) {
+            // Everything after `--` is for cargo.
+            cargo_args = arg == "--";
+            args.push(arg);
+        }
+    }
+    args
+}
+
+/// Paths end up in the container runtime's arguments, which need them as strings.
+fn path_str(path: &Path) -> Result<&str, BmError> {
+    path.to_str().ok_or_else(|| BmError::Environment(format!("`{}` isn't valid unicode.", path.display())))
+}
+
+/// Quotes an argument for the `bash -c` command run in the build container.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_=.,/:+@".contains(c)) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// `cmd` as a shell command line.
+fn command_line(cmd: &Command) -> String {
+    let mut line = shell_quote(&cmd.get_program().to_string_lossy());
+    for arg in cmd.get_args() {
+        line.push(' ');
+        line.push_str(&shell_quote(&arg.to_string_lossy()));
+    }
+    line
+}
+
+/// The named docker volume holding cargo's target dir between builds.
+/// Keyed by target triple as well as project, so switching architectures doesn't invalidate the cache.
+fn cache_volume(name: &str, target: &str) -> String {
+    format!("{}{}", cache_volume_prefix(name), target)
+}
+
+fn cache_volume_prefix(name: &str) -> String {
+    format!("bm_cache_{}_", name)
+}
@@ -0,0 +1,64 @@
+//! Lambda's limits on deployment packages: 50 MiB zipped when uploaded directly (rather than through S3), and 250 MiB unzipped.
+//!
+//! Lambda builds report both sizes, and warn when either is over. With `--strict-size`, that fails the build instead.
+
+use crate::archive;
+use crate::error::BmError;
+use crate::output::status;
+use std::fs;
+use std::path::Path;
+
+const MAX_ZIPPED: u64 = 50 * 1024 * 1024;
+const MAX_UNZIPPED: u64 = 250 * 1024 * 1024;
+
+fn mib(size: u64) -> f64 {
+    size as f64 / (1024.0 * 1024.0)
+}
+
+/// Reports the zip's sizes against the limits, returning the unzipped size. `strip` and `upx` are whether the build already
+/// used them, so they aren't suggested again. Zips uploaded through S3 (`via_s3`) only have the unzipped limit.
+pub fn check_zip(zip: &Path, strip: bool, upx: bool, via_s3: bool, strict: bool) -> Result<u64, BmError> {
+    let data = fs::read(zip).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?;
+    let zipped = data.len() as u64;
+    let unzipped = archive::zip_uncompressed_size(&data).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?;
+    if via_s3 {
+        status!("Zip size: {:.2} MiB, unzipped: {:.2} MiB (limit {:.0} MiB).", mib(zipped), mib(unzipped), mib(MAX_UNZIPPED));
+    } else {
+        status!(
+            "Zip size: {:.2} MiB (limit {:.0} MiB), unzipped: {:.2} MiB (limit {:.0} MiB).",
+            mib(zipped), mib(MAX_ZIPPED), mib(unzipped), mib(MAX_UNZIPPED));
+    }
+
+    let mut problems = Vec::new();
+    if zipped > MAX_ZIPPED && !via_s3 {
+        problems.push(format!(
+            "The zip is {:.2} MiB, over the {:.0} MiB Lambda accepts as a direct upload. Upload it through S3 with `--s3` instead.",
+            mib(zipped), mib(MAX_ZIPPED)));
+    }
+    if unzipped > MAX_UNZIPPED {
+        problems.push(format!("Unzipped it's {:.2} MiB, over the {:.0} MiB Lambda allows at all.", mib(unzipped), mib(MAX_UNZIPPED)));
+    }
+    if problems.is_empty() {
+        return Ok(unzipped);
+    }
+
+    let mut message = problems.join("\n");
+    message.push_str("\nTo make it smaller:");
+    if !strip {
+        message.push_str("\n    - pass `--strip`, debug info and symbols are often most of the executable");
+    }
+    if !upx {
+        message.push_str("\n    - pass `--upx` to compress the executable");
+    }
+    message.push_str("\n    - set `opt-level = \"z\"`, `lto = true`, and `codegen-units = 1` in the release profile");
+    message.push_str("\n    - check `--include` isn't bundling more than it needs to");
+
+    if strict {
+        return Err(BmError::Packaging(message));
+    }
+    tracing::warn!("{}", message);
+    eprintln!();
+    eprintln!("WARNING: {}", message.replace('\n', "\nWARNING: "));
+    eprintln!();
+    Ok(unzipped)
+}
@@ -0,0 +1,110 @@
+//! `--locked` and `--frozen`, building against the committed `Cargo.lock`, and checking it before the build.
+//!
+//! Both are passed on to the build's `cargo build`: with `--locked`, cargo fails rather than update `Cargo.lock`, and with
+//! `--frozen` it doesn't touch the network either. Before compiling, black_magic checks there is a `Cargo.lock` (in the
+//! project, or the workspace it's in), and that it's up to date with the project's `Cargo.toml`: the package's version, and
+//! the same dependencies, as cargo would otherwise add or drop them while building. Anything wrong is a warning, or an
+//! error with `--strict`, or with `--locked` or `--frozen`, as the build would fail on it anyway, only later.
+
+use crate::error::BmError;
+use crate::output;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+struct Lock {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    /// E.g. `serde`, or `serde 1.0.100` when the lock has several versions of it.
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+/// The `Cargo.lock` `project_dir` builds with: its own, or its workspace's.
+fn find(project_dir: &Path) -> Option<PathBuf> {
+    project_dir.ancestors().map(|d| d.join("Cargo.lock")).find(|l| l.is_file())
+}
+
+/// The names of the packages `manifest` depends on, of every kind and for every target.
+fn dependencies(manifest: &toml::Value) -> BTreeSet<String> {
+    let tables = ["dependencies", "dev-dependencies", "build-dependencies"];
+    let targets = manifest.get("target").and_then(|t| t.as_table()).into_iter().flat_map(|t| t.values());
+    let mut names = BTreeSet::new();
+    for section in iter_sections(manifest, &tables).chain(targets.flat_map(|t| iter_sections(t, &tables))) {
+        for (name, dependency) in section {
+            let package = dependency.get("package").and_then(|p| p.as_str()).unwrap_or(name);
+            names.insert(package.to_owned());
+        }
+    }
+    names
+}
+
+fn iter_sections<'a>(value: &'a toml::Value, tables: &'a [&str]) -> impl Iterator<Item = &'a toml::value::Table> {
+    tables.iter().filter_map(move |t| value.get(*t).and_then(|s| s.as_table()))
+}
+
+/// What's wrong with the project's `Cargo.lock`, if anything.
+fn problem(project_dir: &Path) -> Result<Option<String>, BmError> {
+    let lock_file = match find(project_dir) {
+        Some(l) => l,
+        None => return Ok(Some(
+            "There's no `Cargo.lock`, so the dependencies are resolved again for every build. Run `cargo generate-lockfile` and commit it.".to_owned())),
+    };
+    let manifest_file = project_dir.join("Cargo.toml");
+    let manifest: toml::Value = fs::read_to_string(&manifest_file)
+        .map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", manifest_file.display(), e)))
+        .and_then(|c| toml::from_str(&c).map_err(|e| BmError::Environment(format!("Unable to parse `{}`: {}", manifest_file.display(), e))))?;
+    let lock: Lock = fs::read_to_string(&lock_file)
+        .ok()
+        .and_then(|c| toml::from_str(&c).ok())
+        .ok_or_else(|| BmError::Environment(format!("Unable to parse `{}`.", lock_file.display())))?;
+
+    // A virtual workspace has nothing of its own to compare.
+    let package = match manifest.get("package") {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let name = package.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+    let stale = |what: String| Ok(Some(format!("`{}` is out of date with `Cargo.toml`: {}. Run `cargo update -w` and commit it.", lock_file.display(), what)));
+    let locked = match lock.package.iter().find(|p| p.name == name && p.source.is_none()) {
+        Some(p) => p,
+        None => return stale(format!("it doesn't have `{}`", name)),
+    };
+    // A version from the workspace is left to cargo.
+    if let Some(version) = package.get("version").and_then(|v| v.as_str()) {
+        if version != locked.version {
+            return stale(format!("it has `{}` {}, not {}", name, locked.version, version));
+        }
+    }
+    let declared = dependencies(&manifest);
+    let in_lock: BTreeSet<String> = locked.dependencies.iter().map(|d| d.split(' ').next().unwrap_or_default().to_owned()).collect();
+    if let Some(missing) = declared.difference(&in_lock).next() {
+        return stale(format!("`{}` isn't in it", missing));
+    }
+    if let Some(removed) = in_lock.difference(&declared).next() {
+        return stale(format!("it still has `{}`", removed));
+    }
+    Ok(None)
+}
+
+/// Checks the project's `Cargo.lock`, failing if it's missing or out of date and `strict`, otherwise warning.
+pub fn check(project_dir: &Path, strict: bool) -> Result<(), BmError> {
+    match problem(project_dir)? {
+        Some(p) if strict => Err(BmError::Environment(p)),
+        Some(p) => {
+            output::warning(&p);
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
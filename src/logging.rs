@@ -0,0 +1,66 @@
+//! Logging what a build did, so long builds can be audited afterwards, and black_magic itself debugged.
+//!
+//! Each phase of a build (checking the environment, the builder image, compiling, packaging, the project image) is a
+//! `tracing` span, and every progress message, command and warning is an event in it. Nothing extra is printed by default:
+//! `-vv` also prints the events, and how long each phase took, to stderr. `--log-file <path>` appends all of it to a file,
+//! e.g. `target/black_magic/build.log`. `RUST_LOG` filters both, e.g. `RUST_LOG=black_magic=trace`.
+//!
+//! Builds through the library (see `api`) report to whichever subscriber the program sets up instead.
+
+use crate::error::BmError;
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::span::EnteredSpan;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+
+/// The target of progress messages, which aren't printed to stderr again, since they're already reported.
+pub const PROGRESS: &str = "black_magic::progress";
+
+/// Starts logging, for `-v`s `verbosity` and `--log-file`.
+pub fn init(verbosity: u64, log_file: Option<&Path>) -> Result<(), BmError> {
+    let filter = |default: &str| EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default));
+
+    let stderr = (verbosity >= 2 || env::var_os("RUST_LOG").is_some()).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_filter(filter(if verbosity >= 3 { "black_magic=trace" } else { "black_magic=debug" }))
+            .with_filter(filter_fn(|metadata| metadata.target() != PROGRESS))
+    });
+
+    let file = match log_file {
+        Some(path) => {
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(dir).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", dir.display(), e)))?;
+            }
+            // Appended to, since `--bins`, `--bundle` and `--watch` builds each log to it too.
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| BmError::Environment(format!("Unable to open `{}` to log to: {}", path.display(), e)))?;
+            Some(tracing_subscriber::fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_span_events(FmtSpan::CLOSE)
+                .with_filter(filter("black_magic=debug")))
+        }
+        None => None,
+    };
+
+    let _ = tracing_subscriber::registry().with(stderr).with(file).try_init();
+    Ok(())
+}
+
+/// The span for a phase of the build, see `progress::phase`.
+pub fn phase(name: &'static str) -> EnteredSpan {
+    tracing::info_span!(parent: None, "phase", name).entered()
+}
@@ -0,0 +1,146 @@
+//! The manifest written next to each artifact, describing how it was built.
+
+use crate::error::BmError;
+use crate::hardening::HardeningReport;
+use crate::runtime::Runtime;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize)]
+pub struct Manifest {
+    pub project: String,
+    pub version: Option<String>,
+    pub artifact: String,
+    pub target: String,
+    pub profile: String,
+    pub hardening: Option<HardeningReport>,
+    pub environment: Environment,
+    /// The project's source alone (see `cas::fingerprint`), so an artifact can be checked against the current source.
+    pub source_fingerprint: String,
+    /// The executable, before packaging (after stripping and compressing it, if it was).
+    pub binary_size: u64,
+    /// With `--reproducible`, the time everything in the artifact was given.
+    pub source_date_epoch: Option<u64>,
+    pub stripped: bool,
+    pub upx: bool,
+    pub artifact_size: u64,
+    /// With `--lambda-runtime`, the Lambda runtime the zip was checked against.
+    pub lambda_runtime: Option<String>,
+    pub features: Features,
+    /// Everything passed on to `cargo build`, features included.
+    pub cargo_args: Vec<String>,
+    /// The versions of each registry or git dependency in `Cargo.lock`.
+    pub dependencies: BTreeMap<String, Vec<String>>,
+}
+
+/// The cargo features the artifact was built with.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Features {
+    pub selected: Vec<String>,
+    pub default: bool,
+    pub all: bool,
+}
+
+impl Features {
+    /// Splits a `--features` value, which cargo allows to be separated by commas or spaces.
+    pub fn split(features: &str) -> Vec<String> {
+        let mut selected: Vec<String> = features.split([',', ' ']).filter(|f| !f.is_empty()).map(|f| f.to_owned()).collect();
+        selected.sort();
+        selected
+    }
+}
+
+#[derive(Deserialize)]
+struct CargoLock {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+    checksum: Option<String>,
+}
+
+fn read_lock(project_dir: &Path) -> Vec<LockedPackage> {
+    let lock = fs::read_to_string(project_dir.join("Cargo.lock")).ok().and_then(|l| toml::from_str::<CargoLock>(&l).ok());
+    lock.map(|l| l.package).unwrap_or_default()
+}
+
+/// Reads the dependencies from the project's `Cargo.lock`, leaving out the project's own (path) crates.
+/// A missing or unreadable lock file just means there's nothing to record.
+pub fn locked_dependencies(project_dir: &Path) -> BTreeMap<String, Vec<String>> {
+    let mut dependencies: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for package in read_lock(project_dir).into_iter().filter(|p| p.source.is_some()) {
+        dependencies.entry(package.name).or_default().push(package.version);
+    }
+    dependencies
+}
+
+/// The SHA-256 of each registry crate in the project's `Cargo.lock`, by name and version.
+pub fn locked_checksums(project_dir: &Path) -> BTreeMap<(String, String), String> {
+    read_lock(project_dir).into_iter()
+        .filter_map(|LockedPackage { name, version, checksum, .. }| checksum.map(|c| ((name, version), c)))
+        .collect()
+}
+
+/// What built the artifact, so that can be answered from the artifact alone.
+#[derive(Serialize)]
+pub struct Environment {
+    pub black_magic: String,
+    /// The compile backend, see `backend`.
+    pub backend: String,
+    /// `rustc -vV` from inside the build container (or the host, for backends compiling there), e.g. `release: 1.45.0-nightly`. The first line is under `version`.
+    pub rustc: BTreeMap<String, String>,
+    /// None for the backends compiling on the host, which don't use it.
+    pub builder_image: Option<String>,
+    pub builder_image_id: Option<String>,
+    /// None for builds with a host backend that didn't need docker or podman at all.
+    pub runtime: Option<String>,
+    pub runtime_version: Option<String>,
+    pub host_os: String,
+    pub host_arch: String,
+}
+
+impl Environment {
+    /// Gathers the environment, with `rustc_version` holding the output of `rustc -vV` from the backend.
+    pub fn capture(runtime: Option<Runtime>, backend: &str, builder_image: Option<&str>, rustc_version: &Path) -> Environment {
+        let mut rustc = BTreeMap::new();
+        let output = fs::read_to_string(rustc_version).unwrap_or_default();
+        let mut lines = output.lines();
+        if let Some(version) = lines.next() {
+            rustc.insert("version".to_owned(), version.trim().to_owned());
+        }
+        for line in lines {
+            let mut parts = line.splitn(2, ':');
+            if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                rustc.insert(key.trim().to_owned(), value.trim().to_owned());
+            }
+        }
+
+        Environment {
+            black_magic: env!("CARGO_PKG_VERSION").to_owned(),
+            backend: backend.to_owned(),
+            rustc,
+            builder_image: builder_image.map(|i| i.to_owned()),
+            builder_image_id: runtime.zip(builder_image).and_then(|(r, i)| r.image_id(i)),
+            runtime: runtime.map(|r| r.name().to_owned()),
+            runtime_version: runtime.and_then(|r| r.version()),
+            host_os: env::consts::OS.to_owned(),
+            host_arch: env::consts::ARCH.to_owned(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn write(&self, path: &Path) -> Result<(), BmError> {
+        let json = serde_json::to_string_pretty(self).expect("Unable to serialize manifest.");
+        fs::write(path, json).map_err(|e| BmError::Packaging(format!("Unable to write manifest: {}", e)))
+    }
+}
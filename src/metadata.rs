@@ -0,0 +1,124 @@
+//! The subset of `cargo metadata` output black_magic cares about.
+
+use crate::error::BmError;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    /// The ids of the workspace's own packages.
+    #[serde(default)]
+    pub workspace_members: Vec<String>,
+    pub resolve: Option<Resolve>,
+}
+
+#[derive(Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub license: Option<String>,
+    /// E.g. `registry+https://github.com/rust-lang/crates.io-index`, or `None` for path dependencies.
+    pub source: Option<String>,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    pub manifest_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Target {
+    pub name: String,
+    /// E.g. `["bin"]` or `["lib"]`.
+    pub kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Resolve {
+    pub root: Option<String>,
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+pub struct Node {
+    pub id: String,
+    #[serde(default)]
+    pub deps: Vec<NodeDep>,
+}
+
+#[derive(Deserialize)]
+pub struct NodeDep {
+    pub pkg: String,
+    #[serde(default)]
+    pub dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Deserialize)]
+pub struct DepKind {
+    /// `None` for normal dependencies, otherwise `"dev"` or `"build"`.
+    pub kind: Option<String>,
+}
+
+impl Metadata {
+    /// Runs `cargo metadata` on the host for the project in `project_dir`, resolved for `target_triple`.
+    /// `lock` is `--frozen`, so cargo can't touch the network or `Cargo.lock`, or `--locked`, for only `Cargo.lock`, and cargo
+    /// fails if it would have to.
+    pub fn load(project_dir: &Path, target_triple: &str, lock: Option<&str>) -> Result<Metadata, BmError> {
+        let mut cmd = Command::new("cargo");
+        cmd.current_dir(project_dir)
+            .arg("metadata")
+            .arg("--format-version")
+            .arg("1")
+            .arg("--filter-platform")
+            .arg(target_triple);
+        if let Some(l) = lock {
+            cmd.arg(l);
+        }
+        let output = cmd
+            .output()
+            .map_err(|e| BmError::Environment(format!("Unable to run `cargo metadata`: {}. Is cargo installed on your system?", e)))?;
+        if !output.status.success() {
+            return Err(BmError::Environment(format!("`cargo metadata` failed: {}", String::from_utf8_lossy(&output.stderr))));
+        }
+
+        serde_json::from_slice(&output.stdout).map_err(|e| BmError::Environment(format!("Unable to parse `cargo metadata` output: {}", e)))
+    }
+
+    pub fn package(&self, id: &str) -> Option<&Package> {
+        self.packages.iter().find(|p| p.id == id)
+    }
+
+    /// Every package the root package depends on, directly or not, excluding dev-dependencies.
+    pub fn dependencies(&self) -> Vec<&Package> {
+        let resolve = match &self.resolve {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+        let root = match &resolve.root {
+            Some(r) => r,
+            None => return Vec::new(),
+        };
+
+        let mut seen = HashSet::new();
+        let mut stack = vec![root.as_str()];
+        while let Some(id) = stack.pop() {
+            let node = match resolve.nodes.iter().find(|n| n.id == id) {
+                Some(n) => n,
+                None => continue,
+            };
+            for dep in &node.deps {
+                // Older cargo versions don't report dependency kinds, treat those as normal dependencies.
+                let is_dev = !dep.dep_kinds.is_empty() && dep.dep_kinds.iter().all(|k| k.kind.as_deref() == Some("dev"));
+                if !is_dev && seen.insert(dep.pkg.as_str()) {
+                    stack.push(&dep.pkg);
+                }
+            }
+        }
+
+        let mut packages: Vec<&Package> = seen.iter().filter_map(|id| self.package(id)).collect();
+        packages.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+        packages
+    }
+}
@@ -0,0 +1,252 @@
+//! Mounting the project into containers when it lives behind symlinks, e.g. `~/dev -> /mnt/data/dev`.
+//!
+//! A bind mount's source is resolved by the container runtime, which with Docker Desktop, colima and the like runs in a VM that
+//! only shares some of the host's paths, and can't follow the host's symlinks. So the project is always mounted by its real
+//! path, with every symlink resolved on the host. A `target` symlinked out of the project (onto a faster disk, say) would
+//! dangle inside the container, so its real location is mounted wherever the symlink points to in there.
+//!
+//! If the runtime still can't share the project, the container sees an empty directory. The build checks for `Cargo.toml`
+//! before anything else, and explains that, rather than cargo failing to find the project.
+//!
+//! On Windows, a bind mount's source is given as the runtime's VM sees it: `D:\x` is `/d/x` for Docker Desktop and `/mnt/d/x`
+//! for podman machine, network shares (`\\server\share\x`) are `//server/share/x`, and the `\\?\` prefix of canonical paths
+//! is dropped. In WSL, a Windows path (e.g. from `BlackMagic.toml`) is where WSL mounts its drive, `/mnt/d/x`. Elsewhere
+//! paths are used as they are. Docker Desktop without its WSL 2 backend only shares the drives picked in its settings, which
+//! `doctor` checks.
+//!
+//! A remote daemon (`DOCKER_HOST=ssh://...`, a remote context) can't mount anything from this machine at all. Builds send the
+//! project to it with `docker build` instead, as `--layered` does, and what can't work without mounts says so up front.
+
+use crate::error::BmError;
+use crate::runtime::Runtime;
+use std::env;
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Printed by the build container when the project isn't there, followed by its path on the host.
+pub const MISSING_PROJECT: &str = "BM_MISSING_PROJECT";
+
+/// The directory black_magic was run in, as the user knows it: through any symlinks, so the project is named after what they
+/// called it (`env::current_dir` resolves them).
+pub fn current_dir() -> Result<PathBuf, BmError> {
+    let real = env::current_dir().map_err(|e| BmError::Environment(format!("Unable to get current directory: {}", e)))?;
+    Ok(env::var_os("PWD")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute() && fs::canonicalize(p).ok().as_ref() == Some(&real))
+        .unwrap_or(real))
+}
+
+/// Where black_magic runs, as far as paths go.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Host {
+    Unix,
+    Windows,
+    Wsl,
+}
+
+impl Host {
+    pub fn detect() -> Host {
+        if cfg!(windows) {
+            Host::Windows
+        } else if cfg!(target_os = "linux") && (env::var_os("WSL_DISTRO_NAME").is_some()
+            || fs::read_to_string("/proc/sys/kernel/osrelease").map(|r| r.to_lowercase().contains("microsoft")).unwrap_or(false))
+        {
+            Host::Wsl
+        } else {
+            Host::Unix
+        }
+    }
+}
+
+/// `path` without the `\\?\` prefix of Windows' canonical paths, which runtimes don't accept.
+fn strip_verbatim(path: &str) -> String {
+    match path.strip_prefix(r"\\?\UNC\") {
+        Some(share) => format!(r"\\{}", share),
+        None => path.strip_prefix(r"\\?\").unwrap_or(path).to_owned(),
+    }
+}
+
+/// The drive letter of a Windows path like `D:\x`, lowercase, and the rest of it.
+pub fn drive(path: &str) -> Option<(char, &str)> {
+    let bytes = path.as_bytes();
+    match bytes {
+        [letter, b':', rest @ ..] if letter.is_ascii_alphabetic() && matches!(rest.first(), None | Some(b'\\' | b'/')) => {
+            Some(((*letter as char).to_ascii_lowercase(), &path[2..]))
+        }
+        _ => None,
+    }
+}
+
+/// `path` on `host`, as `runtime` wants a bind mount's source.
+pub fn runtime_path(runtime: Runtime, host: Host, path: &str) -> String {
+    let path = strip_verbatim(path);
+    if host == Host::Unix {
+        return path;
+    }
+    if let Some(share) = path.strip_prefix(r"\\") {
+        return format!("//{}", share.replace('\\', "/"));
+    }
+    match drive(&path) {
+        Some((letter, rest)) => match (host, runtime) {
+            (Host::Windows, Runtime::Docker) => format!("/{}{}", letter, rest.replace('\\', "/")),
+            _ => format!("/mnt/{}{}", letter, rest.replace('\\', "/")),
+        },
+        None if host == Host::Windows => path.replace('\\', "/"),
+        None => path,
+    }
+}
+
+/// `dir` with every symlink resolved.
+pub fn host_path(dir: &Path) -> PathBuf {
+    match fs::canonicalize(dir) {
+        Ok(p) => p.to_str().map(|s| PathBuf::from(strip_verbatim(s))).unwrap_or(p),
+        Err(_) => dir.to_owned(),
+    }
+}
+
+/// A `-v` argument for `dir` at `container`, by its real path.
+pub fn volume(runtime: Runtime, dir: &Path, container: &str) -> Result<String, BmError> {
+    Ok(runtime.bind_mount(&runtime_path(runtime, Host::detect(), crate::path_str(&host_path(dir))?), container))
+}
+
+/// Copies a read-only build's outputs out of its exited `container` into `bm_dir`, see `--read-only-source`.
+/// `container_dir` is the project's directory in the container.
+pub fn copy_out(runtime: Runtime, container: &str, container_dir: &str, bm_dir: &Path) -> Result<(), BmError> {
+    let copied = runtime.command()
+        .arg("cp")
+        .arg(format!("{}:{}/target/black_magic/.", container, container_dir))
+        .arg(bm_dir)
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to copy the build outputs out of `{}`: {}", container, e)))?;
+    if !copied.status.success() {
+        return Err(BmError::Docker(format!(
+            "Unable to copy the build outputs out of `{}`.\n\nstderr: {}", container, String::from_utf8_lossy(&copied.stderr))));
+    }
+    Ok(())
+}
+
+/// A `-v` argument for the project's `target`, if it's a symlink out of the project mounted at `container_dir`.
+pub fn target_volume(runtime: Runtime, project_dir: &Path, container_dir: &str) -> Result<Option<String>, BmError> {
+    let target = project_dir.join("target");
+    let link = match fs::read_link(&target) {
+        Ok(l) => l,
+        Err(_) => return Ok(None),
+    };
+    // Creates what a dangling symlink points to, like cargo would.
+    let pointed_to = project_dir.join(&link);
+    fs::create_dir_all(&pointed_to).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", pointed_to.display(), e)))?;
+    let resolved = host_path(&target);
+    if resolved.starts_with(host_path(project_dir)) {
+        return Ok(None);
+    }
+    // Where the symlink points inside the container: an absolute one to the same path, a relative one from the project.
+    let mut container = Vec::new();
+    if !link.is_absolute() {
+        container.extend(container_dir.split('/').filter(|c| !c.is_empty()).map(|c| c.to_owned()));
+    }
+    for component in link.components() {
+        match component {
+            Component::Normal(c) => container.push(c.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                container.pop();
+            }
+            _ => {}
+        }
+    }
+    volume(runtime, &resolved, &format!("/{}", container.join("/"))).map(Some)
+}
+
+/// Fails for a remote daemon, whose containers can't mount anything from this machine, for what needs `what` does locally.
+pub fn check_local(runtime: Runtime, what: &str) -> Result<(), BmError> {
+    match runtime.remote_host() {
+        Some(host) => Err(BmError::Environment(format!(
+            "{} mounts files from this machine into a container, which the {} daemon at {} can't do. Use a local daemon for it.",
+            what, runtime.name(), host))),
+        None => Ok(()),
+    }
+}
+
+/// Prefixed to the build command, checking the container can see the project at `host_dir`.
+pub fn check_cmd(host_dir: &Path) -> Result<String, BmError> {
+    Ok(format!(
+        "(test -f Cargo.toml || (echo {} {} >&2 && false)) && ",
+        MISSING_PROJECT, crate::shell_quote(crate::path_str(&host_path(host_dir))?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// `dev -> real/dev`, with a project in it, returning the symlinked path to the project and its real path.
+    fn symlinked_project(root: &Path) -> (PathBuf, String) {
+        fs::create_dir_all(root.join("real/dev/api")).unwrap();
+        symlink(root.join("real/dev"), root.join("dev")).unwrap();
+        let real = fs::canonicalize(root.join("real/dev/api")).unwrap();
+        (root.join("dev/api"), real.to_str().unwrap().to_owned())
+    }
+
+    #[test]
+    fn mounts_a_project_behind_a_symlinked_parent_by_its_real_path() {
+        let root = tempfile::tempdir().unwrap();
+        let (project, real) = symlinked_project(root.path());
+        assert_eq!(volume(Runtime::Docker, &project, "/workdir").unwrap(), format!("{}:/workdir", real));
+        assert_eq!(volume(Runtime::Podman, &project, "/workdir").unwrap(), format!("{}:/workdir:z", real));
+        assert_eq!(check_cmd(&project).unwrap(), format!("(test -f Cargo.toml || (echo {} {} >&2 && false)) && ", MISSING_PROJECT, real));
+    }
+
+    #[test]
+    fn mounts_a_project_behind_a_relative_symlink_by_its_real_path() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("real/api")).unwrap();
+        symlink("real/api", root.path().join("api")).unwrap();
+        let real = fs::canonicalize(root.path().join("real/api")).unwrap();
+        assert_eq!(volume(Runtime::Docker, &root.path().join("api"), "/workdir").unwrap(), format!("{}:/workdir", real.display()));
+    }
+
+    #[test]
+    fn mounts_a_target_symlinked_out_of_the_project_where_it_points() {
+        let root = tempfile::tempdir().unwrap();
+        let (project, _) = symlinked_project(root.path());
+        symlink("../../fast/target", project.join("target")).unwrap();
+        // Created, as cargo would, since it's dangling.
+        let volume = target_volume(Runtime::Docker, &project, "/workdir").unwrap();
+        let real = fs::canonicalize(root.path().join("real/fast/target")).unwrap();
+        assert_eq!(volume, Some(format!("{}:/fast/target", real.display())));
+
+        fs::remove_file(project.join("target")).unwrap();
+        fs::create_dir(project.join("target")).unwrap();
+        assert_eq!(target_volume(Runtime::Docker, &project, "/workdir").unwrap(), None);
+    }
+
+    #[test]
+    fn keeps_a_target_symlinked_inside_the_project() {
+        let root = tempfile::tempdir().unwrap();
+        let (project, _) = symlinked_project(root.path());
+        fs::create_dir(project.join("build")).unwrap();
+        symlink("build", project.join("target")).unwrap();
+        assert_eq!(target_volume(Runtime::Docker, &project, "/workdir").unwrap(), None);
+    }
+
+    /// macOS' temporary directories are under `/var`, a symlink to `/private/var`, which Docker Desktop only shares as the latter.
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn mounts_macos_temporary_directories_under_private() {
+        let project = tempfile::tempdir().unwrap();
+        let path = project.path().to_str().unwrap();
+        assert!(path.starts_with("/var/"), "{}", path);
+        assert_eq!(volume(Runtime::Docker, project.path(), "/workdir").unwrap(), format!("/private{}:/workdir", path));
+    }
+
+    #[test]
+    fn converts_windows_paths_for_the_runtime() {
+        assert_eq!(runtime_path(Runtime::Docker, Host::Windows, r"\\?\D:\dev\api"), "/d/dev/api");
+        assert_eq!(runtime_path(Runtime::Podman, Host::Windows, r"D:\dev\api"), "/mnt/d/dev/api");
+        assert_eq!(runtime_path(Runtime::Docker, Host::Windows, r"\\?\UNC\server\share\api"), "//server/share/api");
+        assert_eq!(runtime_path(Runtime::Docker, Host::Wsl, r"C:\dev\api"), "/mnt/c/dev/api");
+        assert_eq!(runtime_path(Runtime::Docker, Host::Wsl, "/home/me/api"), "/home/me/api");
+        assert_eq!(runtime_path(Runtime::Docker, Host::Unix, "/home/me/api"), "/home/me/api");
+    }
+}
@@ -0,0 +1,342 @@
+//! `--bundle`: one archive holding the executable for both x86_64 and ARM64, for fleets of mixed machines (e.g. on-prem or edge
+//! boxes) that all pull the same artifact.
+//!
+//! The project is built for each architecture on its own, as a docker build without the image (`--no-image`), both at once
+//! with `--parallel 2` (see `scheduler`), and the two tarballs are combined into `target/black_magic/<name>.bundle.tar.gz`:
+//! ```text
+//! run                 picks the executable for the machine's architecture, and execs it with its arguments
+//! manifest.json       both builds' manifests, see `manifest`
+//! x86_64/<project>
+//! aarch64/<project>
+//! ```
+//! Anything else the tarballs had (`--with-ca-certs`, `--user`) is kept next to each executable.
+//!
+//! `--platforms linux/amd64,linux/arm64` builds the same way, then makes one multi-platform `bm_<name>` image from the tarballs,
+//! with `docker buildx` (or `podman build --manifest`), so each machine pulls the image for its own architecture. The
+//! Dockerfile template and the image's options apply to every platform. With `--push`, the manifest list is pushed to each
+//! registry; without, docker needs its containerd image store to keep a multi-platform image locally.
+
+use crate::builder::Builder;
+use crate::checksum::Checksum;
+use crate::config::Config;
+use crate::dockerignore;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::names;
+use crate::reproducible;
+use crate::scheduler;
+use crate::runtime::Runtime;
+use crate::system_files::User;
+use crate::tags;
+use crate::template;
+use crate::Arch;
+use serde_json::json;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+const ARCHS: &[(Arch, &str)] = &[(Arch::X86_64, "x86_64"), (Arch::Aarch64, "aarch64")];
+
+/// The platforms `--platforms` can build for, as docker names them.
+/// With the `--arch` building for each.
+const PLATFORMS: &[(&str, &str)] = &[("linux/amd64", "x86_64"), ("linux/arm64", "aarch64")];
+
+/// Where the bundle is put together inside the container.
+const STAGING_DIR: &str = "/bm_bundle";
+
+fn selector(binary: &str) -> String {
+    format!(r#"#!/bin/sh
+# Runs the {binary} executable built for this machine's architecture.
+dir=$(dirname "$0")
+case "$(uname -m)" in
+    x86_64|amd64) arch=x86_64 ;;
+    aarch64|arm64|armv8*) arch=aarch64 ;;
+    *) echo "{binary} isn't built for $(uname -m)." >&2; exit 1 ;;
+esac
+exec "$dir/$arch/{binary}" "$@"
+"#, binary = binary)
+}
+
+/// The build of the project for `arch`, as a tarball.
+fn part_job(exe: &Path, args: &[String], arch: &str) -> scheduler::Job {
+    let mut cmd = Command::new(exe);
+    cmd.args(["--docker", "--no-image", "--arch", arch, "--output-format", "json"]).args(args);
+    scheduler::Job { name: arch.to_owned(), cmd, failure: format!("The {} build failed", arch) }
+}
+
+/// Runs `jobs`, with `--parallel`, and fails with the first failure after saying how they all went.
+fn run_parts(matches: &clap::ArgMatches, bm_dir: &Path, jobs: Vec<scheduler::Job>) -> Result<Vec<Value>, BmError> {
+    let parallel = matches.value_of("PARALLEL").map(|p| p.parse().unwrap()).unwrap_or(1);
+    let finished = scheduler::run(jobs, parallel, bm_dir, matches.is_present("NO_WAIT"))?;
+    scheduler::summary(&finished, |record| record["artifact"].as_str().unwrap_or("").to_owned());
+    Ok(scheduler::first_error(finished)?.into_iter().map(|(_, record)| record).collect())
+}
+
+/// Runs a `--bundle` build.
+pub fn bundle(matches: &clap::ArgMatches, started: Instant) -> Result<(), BmError> {
+    let conflicting = ["DOCKER", "LAMBDA", "LAMBDA_IMAGE", "SERVERLESS", "ARCH", "CPU_BASELINE", "NO_IMAGE", "WATCH", "NO_SIDE_EFFECTS", "DRY_RUN", "BINS", "PLATFORMS"];
+    if conflicting.iter().any(|a| matches.occurrences_of(a) > 0) {
+        return Err(BmError::Environment(
+            "`--bundle` builds for both architectures itself, so it can't be used with `--docker`, `--lambda`, `--lambda-image`, \
+            `--serverless`, `--arch`, `--cpu-baseline`, `--no-image`, `--watch`, `--no-side-effects`, `--dry-run`, `--bins` or `--platforms`.".to_owned()));
+    }
+
+    let current_dir = mounts::current_dir()?;
+    let bm_dir = current_dir.join("target").join("black_magic");
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    mounts::check_local(Runtime::detect(matches.value_of("RUNTIME"))?, "`--bundle`")?;
+    let args = crate::forwarded_args(&["--bundle"], &["--parallel"]);
+
+    let records = run_parts(matches, &bm_dir, ARCHS.iter().map(|(_, name)| part_job(&exe, &args, name)).collect())?;
+    let mut parts = Vec::new();
+    for ((arch, name), record) in ARCHS.iter().zip(records) {
+        let artifact = record["artifact"].as_str().unwrap_or("").to_owned();
+        let manifest_path = artifact.trim_end_matches(".tar.gz").to_owned() + ".manifest.json";
+        let manifest: Value = fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|m| serde_json::from_str(&m).ok())
+            .ok_or_else(|| BmError::Packaging(format!("Unable to read the {} build's manifest.", name)))?;
+        parts.push((*arch, *name, artifact, record, manifest));
+    }
+
+    let binary = crate::project_name(&current_dir)?;
+    let bundle_name = parts[0].2.trim_end_matches(".tar.gz").to_owned();
+    let artifact = Path::new(&format!("{}.bundle.tar.gz", bundle_name)).to_owned();
+    let artifact_file = artifact.file_name().and_then(|f| f.to_str()).unwrap_or("").to_owned();
+
+    // Written on the host, then copied into the bundle by the container.
+    let inputs_dir = bm_dir.join("bundle");
+    fs::create_dir_all(&inputs_dir).map_err(|e| BmError::Packaging(format!("Unable to create `{}`: {}", inputs_dir.display(), e)))?;
+    let manifest = json!({
+        "project": binary,
+        "selector": "run",
+        "builds": parts.iter().map(|(_, name, _, record, manifest)| json!({
+            "arch": name,
+            "executable": format!("{}/{}", name, binary),
+            "tarball_sha256": record["sha256"],
+            "manifest": manifest,
+        })).collect::<Vec<_>>(),
+    });
+    let write = |file: &str, contents: &str| fs::write(inputs_dir.join(file), contents)
+        .map_err(|e| BmError::Packaging(format!("Unable to write the bundle's `{}`: {}", file, e)));
+    write("manifest.json", &serde_json::to_string_pretty(&manifest).unwrap())?;
+    write("run", &selector(binary))?;
+
+    /*
+    Bundle, in the x86_64 builder image:
+        - each architecture's tarball, unpacked into its own directory
+        - the selector and manifest at the root, the selector executable
+        - tar and gzip, with `--reproducible` sorted, with fixed times and owners, and gzipped without a timestamp
+    */
+    let tarball = |i: usize| format!("target/black_magic/{}", Path::new(&parts[i].2).file_name().and_then(|f| f.to_str()).unwrap_or(""));
+    let mut bundle_cmd = format!("rm -rf {dir} && mkdir -p {dir}", dir = STAGING_DIR);
+    for (i, (_, name, ..)) in parts.iter().enumerate() {
+        bundle_cmd.push_str(&format!(" && mkdir {dir}/{name} && tar -xzf {} -C {dir}/{name}", tarball(i), dir = STAGING_DIR, name = name));
+    }
+    bundle_cmd.push_str(&format!(
+        " && cp target/black_magic/bundle/manifest.json target/black_magic/bundle/run {dir}/ && chmod 755 {dir}/run",
+        dir = STAGING_DIR));
+    match parts[0].4["source_date_epoch"].as_u64() {
+        Some(epoch) => bundle_cmd.push_str(&format!(
+            "{} && set -o pipefail && tar{} -C {} -cf - . | gzip -n > target/black_magic/{}",
+            reproducible::touch_cmd(epoch, STAGING_DIR), reproducible::tar_options(epoch), STAGING_DIR, artifact_file)),
+        None => bundle_cmd.push_str(&format!(" && tar -czf target/black_magic/{} -C {} .", artifact_file, STAGING_DIR)),
+    }
+
+    status!("Bundling...");
+    let config = Config::load(&current_dir)?;
+    let runtime = Runtime::detect(matches.value_of("RUNTIME"))?;
+    let builder = Builder::new(
+        Arch::X86_64,
+        false,
+        matches.value_of("BUILDER_IMAGE").or(config.builder.image.as_deref()),
+        matches.value_of("BUILDER_TAG").or(config.builder.tag.as_deref()))
+        .pinned(&config.builder.pins)?
+        .customized(&config.builder.packages, config.builder.setup_script.as_deref())?;
+    let mut cmd = runtime.command();
+    cmd.arg("run")
+        .arg("--rm")
+        .arg("-v")
+        .arg(mounts::volume(runtime, &current_dir, "/workdir")?)
+        .arg(&builder.image)
+        .arg("/bin/bash")
+        .arg("-c")
+        .arg(&bundle_cmd);
+    output::detail(&format!("Running {:?}", cmd));
+    let bundled = cmd.output().map_err(|e| BmError::Docker(format!("Unable to run bundle command: {}", e)))?;
+    if !bundled.status.success() {
+        return Err(BmError::Packaging(format!("Unable to bundle the builds.\n\nstderr: {}", String::from_utf8_lossy(&bundled.stderr))));
+    }
+
+    let contents = fs::read(&artifact).map_err(|e| BmError::Packaging(format!("Unable to read bundle: {}", e)))?;
+    let checksum = Checksum::of(&contents);
+    checksum.write(&artifact)?;
+    status!("Bundle: {}", artifact.display());
+    status!("SHA-256: {}", checksum.hex);
+    output::marker("ARTIFACT", &[
+        ("path", crate::path_str(&artifact)?), ("digest", &format!("sha256:{}", checksum.hex)), ("size", &contents.len().to_string())]);
+    status!("...Done!");
+
+    if output::is_json() {
+        output::emit("done", json!({
+            "artifact": artifact,
+            "size": contents.len(),
+            "sha256": checksum.hex,
+            "parts": parts.iter().map(|(arch, _, artifact, ..)| json!({ "target": arch.target_triple(), "artifact": artifact })).collect::<Vec<_>>(),
+            "duration_seconds": started.elapsed().as_secs_f64(),
+            "builder_image": builder.image,
+        }));
+    }
+    Ok(())
+}
+
+/// Declares `TARGETPLATFORM`, which the builder sets for each platform, in every stage of `dockerfile`.
+fn with_target_platform(dockerfile: &str) -> String {
+    dockerfile
+        .lines()
+        .map(|l| if l.trim_start().to_uppercase().starts_with("FROM ") { format!("{}\nARG TARGETPLATFORM\n", l) } else { format!("{}\n", l) })
+        .collect()
+}
+
+/// Runs a `--platforms` build.
+pub fn platforms(matches: &clap::ArgMatches, started: Instant) -> Result<(), BmError> {
+    let conflicting = [
+        "LAMBDA", "ARCH", "CPU_BASELINE", "NO_IMAGE", "WATCH", "NO_SIDE_EFFECTS", "DRY_RUN", "BIN", "BINS", "EXAMPLE", "BENCH", "ECR",
+        "SMOKE_TEST", "INTEGRATION_TEST",
+        "DIFF_AGAINST", "EXPORT_OCI", "LOAD_INTO", "DEBUG_IMAGE", "EMIT_TERRAFORM", "EMIT_COMPOSE"];
+    if !matches.is_present("DOCKER") || conflicting.iter().any(|a| matches.occurrences_of(a) > 0) {
+        return Err(BmError::Environment(
+            "`--platforms` is for docker builds, and builds for each platform itself, so it can't be used with `--arch`, \
+            `--cpu-baseline`, `--no-image`, `--watch`, `--no-side-effects`, `--dry-run`, `--bin`, `--bins`, `--example`, `--bench`, or the options that use a \
+            single-platform image (`--ecr`, `--smoke-test`, `--integration-test`, `--diff-against`, `--export-oci`, `--load-into`, \
+            `--debug-image`, `--emit-terraform`, `--emit-compose`).".to_owned()));
+    }
+    let mut platforms: Vec<(&str, &str)> = Vec::new();
+    for requested in matches.value_of("PLATFORMS").unwrap().split(',').map(|p| p.trim()) {
+        let platform = PLATFORMS.iter().find(|(p, ..)| *p == requested).ok_or_else(|| BmError::Environment(format!(
+            "`{}` isn't a platform black_magic builds for, use {}.", requested, PLATFORMS.iter().map(|(p, ..)| *p).collect::<Vec<_>>().join(" or "))))?;
+        if !platforms.contains(platform) {
+            platforms.push(*platform);
+        }
+    }
+
+    let current_dir = mounts::current_dir()?;
+    let bm_dir = current_dir.join("target").join("black_magic");
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    let config = Config::load(&current_dir)?;
+    let runtime = Runtime::detect(matches.value_of("RUNTIME"))?;
+    let project_name = crate::project_name(&current_dir)?;
+    let name = names::resolve(project_name, matches.value_of("NAME").or(config.name.as_deref()))?;
+    let image = format!("bm_{}", name);
+    let options = crate::BuildConfig::from_matches(matches)?;
+    let image_tags = tags::requested(&options.tags, options.tag_git, &current_dir)?;
+    let source_date_epoch = if matches.is_present("REPRODUCIBLE") { Some(reproducible::source_date_epoch(&current_dir)) } else { None };
+    let images: Vec<String> = std::iter::once(image.clone()).chain(image_tags.iter().map(|t| format!("{}:{}", image, t))).collect();
+    let push_to = tags::remotes(&matches.values_of("PUSH").into_iter().flatten().map(|p| p.to_owned()).collect::<Vec<_>>(), &image_tags);
+
+    // Rendered up front, so a broken template fails before the builds rather than after them.
+    let user = options.user.as_deref().map(User::parse).transpose().map_err(BmError::Environment)?;
+    let run_options = crate::run_options(&options, user.as_ref())?;
+    let artifact = format!("platforms/${{TARGETPLATFORM}}/{}.tar.gz", name);
+    let base = template::Base::parse(matches.value_of("BASE"), matches.value_of("LIBC") == Some("gnu"))?.image(runtime);
+    let placeholders = template::Placeholders { binary: &format!("/{}", project_name), project: &name, artifact: &artifact, base: &base };
+    let dockerfile = with_target_platform(&template::render(
+        &template::load(&current_dir, matches.value_of("DOCKERFILE_TEMPLATE"), &run_options, false)?, &placeholders)?)
+        + &tags::labels(&current_dir, &current_dir.join("Cargo.toml"), source_date_epoch);
+
+    // The image's options are for the image built here, not the tarballs.
+    let args = crate::forwarded_args(
+        &["--docker", "--tag-git"],
+        &["--platforms", "--push", "--tag", "--dockerfile-template", "--entrypoint", "--cmd", "--expose", "--env", "--parallel"]);
+    let records = run_parts(matches, &bm_dir, platforms.iter().map(|(_, arch)| part_job(&exe, &args, arch)).collect())?;
+    let mut parts = Vec::new();
+    for ((platform, _), record) in platforms.iter().zip(records) {
+        let tarball = record["artifact"].as_str().unwrap_or("").to_owned();
+        let dir = bm_dir.join("platforms").join(platform);
+        fs::create_dir_all(&dir).map_err(|e| BmError::Packaging(format!("Unable to create `{}`: {}", dir.display(), e)))?;
+        fs::copy(&tarball, dir.join(format!("{}.tar.gz", name)))
+            .map_err(|e| BmError::Packaging(format!("Unable to copy the {} tarball: {}", platform, e)))?;
+        parts.push((*platform, tarball, record));
+    }
+
+    status!("Building the {} image...", platforms.iter().map(|(p, ..)| *p).collect::<Vec<_>>().join(", "));
+    let dockerfile_name = "Dockerfile.platforms";
+    fs::write(bm_dir.join(dockerfile_name), &dockerfile).map_err(|e| BmError::Packaging(format!("Unable to create project dockerfile: {}", e)))?;
+    dockerignore::write_image_context(&bm_dir)?;
+    let platform_list = platforms.iter().map(|(p, ..)| *p).collect::<Vec<_>>().join(",");
+    let mut cmd = match runtime {
+        Runtime::Docker => {
+            let mut cmd = runtime.command();
+            cmd.args(["buildx", "build", "--no-cache", "--platform", &platform_list]);
+            // Pushing pushes every tag, so the local name is only used when the image stays local.
+            if push_to.is_empty() {
+                images.iter().for_each(|i| { cmd.arg("-t").arg(i); });
+                cmd.arg("--load");
+            } else {
+                push_to.iter().for_each(|r| { cmd.arg("-t").arg(r); });
+                cmd.arg("--push");
+            }
+            cmd
+        }
+        Runtime::Podman => {
+            // Building into an existing manifest list adds to it, rather than replacing it.
+            let _ = runtime.command().args(["manifest", "rm", &image]).output();
+            let mut cmd = runtime.build();
+            cmd.args(["--no-cache", "--platform", &platform_list, "--manifest", &image]);
+            cmd
+        }
+    };
+    cmd.arg("-f").arg(dockerfile_name).arg(".").current_dir(&bm_dir);
+    output::detail(&format!("Running {:?}", cmd));
+    let built = cmd.output().map_err(|e| BmError::Docker(format!("Unable to build project image: {}", e)))?;
+    if !built.status.success() {
+        let hint = if runtime == Runtime::Docker && push_to.is_empty() {
+            "\n\nDocker can only keep multi-platform images locally with its containerd image store, pass `--push` to push it instead."
+        } else {
+            ""
+        };
+        return Err(BmError::Docker(format!("Project image failed: {}\nstderr: {}{}", image, String::from_utf8_lossy(&built.stderr), hint)));
+    }
+    if push_to.is_empty() {
+        if runtime == Runtime::Podman {
+            for tagged in &images[1..] {
+                let _ = runtime.command().args(["tag", &image, tagged]).output();
+            }
+        }
+        status!("Project image: {}", images.join(", "));
+        for image in &images {
+            output::marker("IMAGE", &[("name", image), ("platforms", &platform_list)]);
+        }
+    }
+    for remote in &push_to {
+        if runtime == Runtime::Podman {
+            let pushed = runtime.command()
+                .args(["manifest", "push", "--all", &image])
+                .arg(format!("docker://{}", remote))
+                .output()
+                .map_err(|e| BmError::Publish(format!("Unable to run podman: {}", e)))?;
+            if !pushed.status.success() {
+                return Err(BmError::Publish(format!("Unable to push {}: {}", remote, String::from_utf8_lossy(&pushed.stderr).trim())));
+            }
+        }
+        status!("Pushed: {}", remote);
+        output::marker("PUSHED", &[("remote", remote)]);
+    }
+    status!("...Done!");
+
+    if output::is_json() {
+        output::emit("done", json!({
+            "image": if push_to.is_empty() { Some(&image) } else { None },
+            "pushed": push_to,
+            "platforms": parts.iter().map(|(platform, tarball, record)| json!({
+                "platform": platform, "artifact": tarball, "sha256": record["sha256"] })).collect::<Vec<_>>(),
+            "duration_seconds": started.elapsed().as_secs_f64(),
+        }));
+    }
+    Ok(())
+}
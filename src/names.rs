@@ -0,0 +1,68 @@
+//! The name artifacts, images, and volumes are named after.
+//!
+//! It's the project directory's name by default, mapped onto what every consumer accepts: docker image names are lowercase
+//! `[a-z0-9]` separated by `.`, `_`, or `-`, and Lambda function names are at most 64 characters. The mapping is deterministic,
+//! and names that have to be shortened keep a hash of the original so they don't collide. Set `name` in `BlackMagic.toml`, or
+//! pass `--name`, to pick one instead.
+
+use crate::cas;
+use crate::error::BmError;
+use sha2::Digest;
+use sha2::Sha256;
+
+/// Leaves room for `bm_`, `-arm64`, and `-debug` within 64 characters.
+const MAX_LEN: usize = 48;
+
+fn is_separator(c: char) -> bool {
+    c == '.' || c == '_' || c == '-'
+}
+
+/// Maps `project_name` onto a valid name.
+pub fn sanitize(project_name: &str) -> String {
+    let mut name = String::new();
+    for c in project_name.chars().map(|c| c.to_ascii_lowercase()) {
+        let c = if c.is_ascii_lowercase() || c.is_ascii_digit() || is_separator(c) { c } else { '-' };
+        // Docker only allows single separators (besides `__`), so collapse runs of them.
+        if is_separator(c) && name.ends_with(is_separator) {
+            continue;
+        }
+        name.push(c);
+    }
+    let name = name.trim_matches(is_separator);
+
+    let hash = || cas::hex(&Sha256::digest(project_name.as_bytes()))[..8].to_owned();
+    if name.is_empty() {
+        format!("project-{}", hash())
+    } else if name.len() > MAX_LEN {
+        let short = name[..MAX_LEN - 9].trim_end_matches(is_separator);
+        format!("{}-{}", short, hash())
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Checks a name given with `--name` or in `BlackMagic.toml`, which is used as is.
+pub fn validate(name: &str) -> Result<(), BmError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_LEN
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || is_separator(c))
+        && !name.starts_with(is_separator)
+        && !name.ends_with(is_separator)
+        && !name.chars().zip(name.chars().skip(1)).any(|(a, b)| is_separator(a) && is_separator(b));
+    if valid {
+        Ok(())
+    } else {
+        Err(BmError::Environment(format!(
+            "`{}` can't be used as a name. Use at most {} lowercase letters and digits, separated by single `.`, `_` or `-`. \
+            `{}` would work.",
+            name, MAX_LEN, sanitize(name))))
+    }
+}
+
+/// The name to use: the override if there is one, otherwise the sanitized project name.
+pub fn resolve(project_name: &str, name: Option<&str>) -> Result<String, BmError> {
+    match name {
+        Some(n) => validate(n).map(|_| n.to_owned()),
+        None => Ok(sanitize(project_name)),
+    }
+}
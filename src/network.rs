@@ -0,0 +1,89 @@
+//! The docker network and DNS the containers that reach the network use, for registries and git hosts only reachable over
+//! e.g. a VPN's network and resolver.
+//!
+//! `--network <name>`, `--dns <ip>` and `--add-host <host:ip>` (both repeatable), or a `[network]` section in
+//! `BlackMagic.toml`:
+//! ```toml
+//! [network]
+//! name = "corp-vpn"
+//! dns = ["10.0.0.2"]
+//! add_host = ["git.corp:10.0.4.7"]
+//! ```
+//! They're passed on to the build container and companion downloads (`docker run`), and the builder image's build and
+//! `--layered` builds (`docker build`). `docker build` has no `--dns`, so those resolve with the network's DNS, or the
+//! daemon's (`dns` in its `daemon.json`): give the hosts they need with `--add-host` too. Options given on the command line
+//! replace the section's.
+
+use serde::Deserialize;
+use std::net::IpAddr;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub name: Option<String>,
+    pub dns: Vec<String>,
+    pub add_host: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Network {
+    name: Option<String>,
+    dns: Vec<String>,
+    hosts: Vec<String>,
+}
+
+/// Checks `--dns` is an IP address, as docker needs.
+pub fn validate_dns(value: String) -> Result<(), String> {
+    value.parse::<IpAddr>().map(|_| ()).map_err(|_| format!("`{}` isn't an IP address.", value))
+}
+
+/// Checks `--add-host` is `host:ip`.
+pub fn validate_host(value: String) -> Result<(), String> {
+    match value.split_once(':') {
+        Some((host, ip)) if !host.is_empty() && (ip == "host-gateway" || ip.parse::<IpAddr>().is_ok()) => Ok(()),
+        _ => Err(format!("`{}` isn't `host:ip`, e.g. `git.corp:10.0.4.7`.", value)),
+    }
+}
+
+impl Network {
+    /// From `[network]`, with any of `name`, `dns` and `hosts` from the command line instead.
+    pub fn new(config: &NetworkConfig, name: Option<&str>, dns: Vec<String>, hosts: Vec<String>) -> Result<Network, String> {
+        let network = Network {
+            name: name.map(|n| n.to_owned()).or_else(|| config.name.clone()),
+            dns: if dns.is_empty() { config.dns.clone() } else { dns },
+            hosts: if hosts.is_empty() { config.add_host.clone() } else { hosts },
+        };
+        for dns in &network.dns {
+            validate_dns(dns.clone()).map_err(|e| format!("`dns` in `[network]`: {}", e))?;
+        }
+        for host in &network.hosts {
+            validate_host(host.clone()).map_err(|e| format!("`add_host` in `[network]`: {}", e))?;
+        }
+        Ok(network)
+    }
+
+    fn common_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(name) = &self.name {
+            args.extend(["--network".to_owned(), name.clone()]);
+        }
+        for host in &self.hosts {
+            args.extend(["--add-host".to_owned(), host.clone()]);
+        }
+        args
+    }
+
+    /// The arguments for `docker run`.
+    pub fn run_args(&self) -> Vec<String> {
+        let mut args = self.common_args();
+        for dns in &self.dns {
+            args.extend(["--dns".to_owned(), dns.clone()]);
+        }
+        args
+    }
+
+    /// The arguments for `docker build`, which can't set DNS.
+    pub fn build_args(&self) -> Vec<String> {
+        self.common_args()
+    }
+}
@@ -0,0 +1,122 @@
+//! `--notify`: saying when a build has finished, for long builds finishing while you're elsewhere.
+//!
+//! It's a desktop notification (through the desktop's notification service on Linux, or Notification Center on macOS),
+//! and a POST to any webhooks in `[notify]` in `BlackMagic.toml`:
+//! ```toml
+//! [notify]
+//! webhook = "https://example.com/hooks/builds"
+//! slack = "https://hooks.slack.com/services/…"
+//! desktop = false
+//! ```
+//! `webhook` is sent the outcome as JSON, `{"project", "succeeded", "seconds", "artifact", "size", "error"}`, and `slack` (an
+//! incoming webhook's URL) a message saying the same. `desktop = false` leaves out the desktop notification, e.g. on CI.
+//! The error is just its first line, or a compile's first error from rustc. Nothing about notifying fails the build: if it can't, it warns.
+
+use crate::clean::format_size;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output;
+use crate::state::State;
+use serde::Deserialize;
+use serde_json::json;
+use notify_rust::Notification;
+use serde_json::Value;
+use std::time::Duration;
+use std::time::SystemTime;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub webhook: Option<String>,
+    pub slack: Option<String>,
+    pub desktop: Option<bool>,
+}
+
+/// How a build went.
+struct Outcome {
+    project: String,
+    seconds: f64,
+    /// The artifact, and its size.
+    artifact: Option<(String, u64)>,
+    /// The first line of the error, if it failed.
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn summary(&self) -> String {
+        match (&self.error, &self.artifact) {
+            (Some(error), _) => format!("{} failed after {:.0}s: {}", self.project, self.seconds, error),
+            (None, Some((artifact, size))) => format!("{} built in {:.0}s: {} ({})", self.project, self.seconds, artifact, format_size(*size)),
+            (None, None) => format!("{} built in {:.0}s.", self.project, self.seconds),
+        }
+    }
+
+    fn record(&self) -> Value {
+        json!({
+            "project": self.project,
+            "succeeded": self.error.is_none(),
+            "seconds": self.seconds,
+            "artifact": self.artifact.as_ref().map(|(a, _)| a),
+            "size": self.artifact.as_ref().map(|(_, s)| s),
+            "error": self.error,
+        })
+    }
+}
+
+/// Shows `summary` on the desktop.
+fn desktop(title: &str, summary: &str) -> Result<(), String> {
+    Notification::new().summary(title).body(summary).show().map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// POSTs `body` to `url` as JSON.
+fn post(url: &str, body: &Value) -> Result<(), String> {
+    ureq::post(url)
+        .config()
+        .timeout_global(Some(Duration::from_secs(10)))
+        .build()
+        .send_json(body)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Notifies everything `config` says to of `outcome`.
+fn notify(config: &NotifyConfig, outcome: &Outcome) {
+    let summary = outcome.summary();
+    let title = if outcome.error.is_none() { "black_magic: build succeeded" } else { "black_magic: build failed" };
+    if config.desktop.unwrap_or(true) {
+        if let Err(e) = desktop(title, &summary) {
+            output::warning(&format!("Unable to show a desktop notification: {}", e));
+        }
+    }
+    if let Some(url) = &config.webhook {
+        if let Err(e) = post(url, &outcome.record()) {
+            output::warning(&format!("Unable to notify the webhook: {}", e));
+        }
+    }
+    if let Some(url) = &config.slack {
+        let icon = if outcome.error.is_none() { ":white_check_mark:" } else { ":x:" };
+        if let Err(e) = post(url, &json!({ "text": format!("{} {}", icon, summary) })) {
+            output::warning(&format!("Unable to notify Slack: {}", e));
+        }
+    }
+}
+
+/// Notifies of the build in the current directory, started at `started_at`, finishing after `seconds` with `error` if it failed.
+pub fn finished(name: Option<&str>, started_at: SystemTime, seconds: f64, error: Option<&BmError>) {
+    let current_dir = match mounts::current_dir() {
+        Ok(dir) => dir,
+        Err(_) => return,
+    };
+    let config = Config::load(&current_dir).unwrap_or_default();
+    let project = name.or(config.name.as_deref()).or_else(|| crate::project_name(&current_dir).ok()).unwrap_or_default().to_owned();
+    let artifact = State::load_since(&current_dir.join("target").join("black_magic"), started_at)
+        .filter(|_| error.is_none())
+        .map(|s| (s.artifact.display().to_string(), s.size));
+    // A compile's message is mostly how to reproduce it, with rustc's errors in its stderr.
+    let error = error.map(|e| e.to_string()).map(|e| {
+        let first = e.lines().find(|l| l.trim_start().starts_with("error")).or_else(|| e.lines().next());
+        first.unwrap_or_default().trim().to_owned()
+    });
+    notify(&config.notify, &Outcome { project, seconds, artifact, error });
+}
@@ -0,0 +1,114 @@
+//! OpenSSL, the usual reason a musl build fails: `openssl-sys` looks for the host's OpenSSL, which is built for glibc if it's
+//! there at all.
+//!
+//! A project with `openssl-sys` in its `Cargo.lock` (and without `openssl-src`, its `vendored` feature) is warned before
+//! compiling, and a compile that couldn't find OpenSSL says what to do about it. `--openssl` (or `openssl` in `[build]`) picks
+//! where it comes from:
+//! - `system`: the builder image's own OpenSSL, built for musl, with `OPENSSL_DIR` pointing at it (`openssl_dir` in `[build]`
+//!   for images keeping it somewhere else) and `OPENSSL_STATIC` set
+//! - `vendored`: compiled from source along with the project, by turning on the `openssl` crate's `vendored` feature. That
+//!   needs `openssl` as a direct dependency, or the project's `Cargo.toml` is the place to turn it on.
+//!
+//! `doctor` has the same check.
+
+use crate::error::BmError;
+use crate::manifest;
+use crate::Arch;
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+pub const MODES: &[&str] = &["vendored", "system"];
+
+/// Where OpenSSL comes from, see `--openssl`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpensslMode {
+    Vendored,
+    System,
+}
+
+impl OpensslMode {
+    pub fn from_name(name: &str) -> Option<OpensslMode> {
+        match name {
+            "vendored" => Some(OpensslMode::Vendored),
+            "system" => Some(OpensslMode::System),
+            _ => None,
+        }
+    }
+
+    /// `--openssl`'s mode, or else `openssl` in `[build]`'s.
+    pub fn select(requested: Option<OpensslMode>, configured: Option<&str>) -> Result<Option<OpensslMode>, BmError> {
+        match (requested, configured) {
+            (Some(mode), _) => Ok(Some(mode)),
+            (None, Some(name)) => OpensslMode::from_name(name).map(Some).ok_or_else(|| BmError::Environment(format!(
+                "`openssl = \"{}\"` in `BlackMagic.toml` has to be `vendored` or `system`.", name))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// What `openssl-sys`'s build script prints when it can't find OpenSSL, see `diagnostics`.
+pub const NOT_FOUND: &str = "Could not find directory of OpenSSL installation";
+
+/// Where the default x86_64 builder image (`rust_musl_docker`) has OpenSSL built for musl.
+const MUSL_OPENSSL_DIR: &str = "/usr/local/musl";
+
+/// How the project uses OpenSSL, if it does.
+pub struct Usage {
+    /// With `openssl-src`, i.e. compiled from source already.
+    pub vendored: bool,
+    /// With `openssl` as one of the project's own dependencies, whose features it can pick.
+    pub direct: bool,
+}
+
+pub fn detect(project_dir: &Path) -> Option<Usage> {
+    let dependencies = manifest::locked_dependencies(project_dir);
+    if !dependencies.contains_key("openssl-sys") {
+        return None;
+    }
+    let direct = fs::read_to_string(project_dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|m| toml::from_str::<Value>(&m).ok())
+        .and_then(|m| m.get("dependencies").map(|d| d.get("openssl").is_some()))
+        .unwrap_or(false);
+    Some(Usage { vendored: dependencies.contains_key("openssl-src"), direct })
+}
+
+/// The warning for a project whose OpenSSL nothing's been done about, if it's needed.
+pub fn warning(usage: &Usage) -> Option<String> {
+    (!usage.vendored).then(|| {
+        "`openssl-sys` is in `Cargo.lock`, and needs an OpenSSL built for musl. If the compile can't find one, build with \
+        `--openssl system` for the builder image's, or `--openssl vendored` to compile it from source.".to_owned()
+    })
+}
+
+/// What `--openssl <mode>` needs: variables for the compile, and arguments for cargo.
+pub struct Setup {
+    pub env: Vec<(&'static str, String)>,
+    pub cargo_args: Vec<String>,
+}
+
+pub fn setup(mode: OpensslMode, usage: &Usage, arch: Arch, custom_builder: bool, dir: Option<&str>) -> Result<Setup, BmError> {
+    let mut setup = Setup { env: Vec::new(), cargo_args: Vec::new() };
+    match mode {
+        OpensslMode::System => {
+            // Only the default x86_64 image is known to have one.
+            let dir = match (dir, arch, custom_builder) {
+                (Some(dir), _, _) => dir,
+                (None, Arch::X86_64, false) => MUSL_OPENSSL_DIR,
+                _ => return Err(BmError::Environment(
+                    "Only the default x86_64 builder image is known to have OpenSSL built for musl. Set `openssl_dir` in the `[build]` \
+                    section of `BlackMagic.toml` to where this one has it, or build with `--openssl vendored`.".to_owned())),
+            };
+            setup.env.push(("OPENSSL_DIR", dir.to_owned()));
+            setup.env.push(("OPENSSL_STATIC", "1".to_owned()));
+        }
+        _ if usage.vendored => {}
+        _ if usage.direct => setup.cargo_args.extend(["--features".to_owned(), "openssl/vendored".to_owned()]),
+        _ => return Err(BmError::Environment(
+            "`--openssl vendored` turns on the `openssl` crate's `vendored` feature, but it's only a dependency of the project's \
+            dependencies. Add `openssl = { version = \"0.10\", features = [\"vendored\"] }` to `[dependencies]` in `Cargo.toml`, \
+            which turns it on for them too.".to_owned())),
+    }
+    Ok(setup)
+}
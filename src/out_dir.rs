@@ -0,0 +1,75 @@
+//! `--out-dir` and `--artifact-name`: where the finished artifact is delivered, and what it's called, e.g. straight into a
+//! CI job's artifacts directory as `api-1.4.0-3f9c2e1.zip`.
+//!
+//! Builds still happen in `target/black_magic`, under the usual names, and the artifact, its `.sha256` and its manifest are
+//! copied out afterwards. `--artifact-name` can use placeholders:
+//! - `{name}`: the project's (or `--bin`'s, or `--name`'s) name
+//! - `{version}`: the crate's version
+//! - `{git_sha}`: the commit's short hash, with `-dirty` for uncommitted changes
+//! - `{target}`: the target triple, e.g. `x86_64-unknown-linux-musl`
+//! - `{arch}`: `x86_64` or `arm64`
+//!
+//! The extension (`.zip`, `.tar.gz`) is added. Both can be set in the `[build]` section of `BlackMagic.toml` too, as
+//! `out_dir` and `artifact_name`.
+
+use crate::checksum::Checksum;
+use crate::error::BmError;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub const PLACEHOLDERS: &[&str] = &["name", "version", "git_sha", "target", "arch"];
+
+/// `template`'s placeholders, checked against `PLACEHOLDERS`.
+fn placeholders(template: &str) -> Result<Vec<&str>, String> {
+    let mut found = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| format!("`{}` has a `{{` without a `}}`.", template))?;
+        let placeholder = &rest[start + 1..start + end];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(format!("`{{{}}}` isn't a placeholder, use {}.", placeholder,
+                PLACEHOLDERS.iter().map(|p| format!("`{{{}}}`", p)).collect::<Vec<_>>().join(", ")));
+        }
+        found.push(placeholder);
+        rest = &rest[start + end + 1..];
+    }
+    Ok(found)
+}
+
+pub fn validate_name(template: String) -> Result<(), String> {
+    if template.is_empty() || template.contains(['/', '\\']) {
+        return Err("It has to be a file name, without a directory, which is `--out-dir`.".to_owned());
+    }
+    placeholders(&template).map(|_| ())
+}
+
+/// `template` with its placeholders filled in by `value`, which is only asked for the ones used.
+pub fn render(template: &str, value: impl Fn(&str) -> Result<String, BmError>) -> Result<String, BmError> {
+    let mut name = template.to_owned();
+    for placeholder in placeholders(template).map_err(BmError::Environment)? {
+        name = name.replace(&format!("{{{}}}", placeholder), &value(placeholder)?);
+    }
+    Ok(name)
+}
+
+/// Copies `artifact` to `out_dir` as `name` plus its extension, with a `.sha256` for the copy and `manifest` alongside.
+/// Returns the copy.
+pub fn deliver(artifact: &Path, manifest: &Path, checksum: &Checksum, out_dir: &Path, name: &str) -> Result<PathBuf, BmError> {
+    fs::create_dir_all(out_dir).map_err(|e| BmError::Packaging(format!("Unable to create `{}`: {}", out_dir.display(), e)))?;
+    let file_name = artifact.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let extension = [".tar.gz", ".zip"].iter().find(|e| file_name.ends_with(*e)).copied().unwrap_or_default();
+    let copy = |from: &Path, to: PathBuf| {
+        // E.g. `--out-dir target/black_magic` on its own, which would truncate it.
+        if fs::canonicalize(&to).ok().is_some_and(|t| fs::canonicalize(from).ok() == Some(t)) {
+            return Ok(to);
+        }
+        fs::copy(from, &to).map(|_| to).map_err(|e| BmError::Packaging(format!("Unable to copy `{}` to `{}`: {}", from.display(), out_dir.display(), e)))
+    };
+    let delivered = copy(artifact, out_dir.join(format!("{}{}", name, extension)))?;
+    checksum.write(&delivered)?;
+    if manifest.is_file() {
+        copy(manifest, out_dir.join(format!("{}.manifest.json", name)))?;
+    }
+    Ok(delivered)
+}
@@ -0,0 +1,168 @@
+//! How black_magic reports what it's doing: as text for people, or as JSON for tooling with `--output-format json`.
+//!
+//! In JSON mode stdout only carries JSON lines, each with an `event` field. A build ends with one `done` record describing
+//! the artifact, or one `error` record. With `--verbose`, every progress message is streamed before it as a `progress` event.
+//!
+//! With `--porcelain`, stdout only carries lifecycle markers for wrapper scripts, and progress messages go to stderr. Each
+//! marker is one line: its name, then `key=value` fields. Values with spaces, quotes or `=` in them are quoted as JSON
+//! strings. Markers and their fields are only ever added to, never changed:
+//! ```text
+//! BEGIN_BUILD version=1.0.0
+//! BEGIN_COMPILE target=x86_64-unknown-linux-musl
+//! END_COMPILE seconds=41.2
+//! REUSED                                  instead of compiling, when the artifact store had it
+//! SKIPPED                                 with `--skip-unchanged`, when nothing's changed since the last build
+//! ARTIFACT path=/src/my_project/target/black_magic/my_project.zip digest=sha256:… size=5123456
+//! UPLOADED url=s3://bucket/key
+//! IMAGE name=bm_my_project
+//! PUSHED remote=registry/repo:tag
+//! PUBLISHED uri=registry/repo@sha256:…    after `publish-ecr`'s build
+//! DEPLOYED function=my-function version=12
+//! RESOURCES cpu_seconds=310.4 peak_memory=2147483648 downloaded=52428800 cache_reused=1073741824
+//! END_BUILD seconds=52.0
+//! ERROR kind=compile exit_code=3
+//! ```
+//!
+//! Builds through the library (see `api`) report to the callback of the thread they run on instead, as `Event`s, and
+//! return what they built rather than writing a record.
+//!
+//! Everything reported is logged too, see `logging`. With `--quiet`, progress messages aren't printed, only logged.
+
+use crate::api::Event;
+use crate::logging;
+use crate::progress;
+use crate::scheduler;
+use serde_json::json;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+pub type Listener = Arc<dyn Fn(&Event) + Send + Sync>;
+
+thread_local! {
+    static LISTENER: RefCell<Option<Listener>> = RefCell::new(None);
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+static PORCELAIN: AtomicBool = AtomicBool::new(false);
+
+/// Picks the output format, once, before anything is reported.
+pub fn init(json: bool, verbose: bool, quiet: bool, porcelain: bool) {
+    JSON.store(json, Ordering::Relaxed);
+    VERBOSE.store(verbose, Ordering::Relaxed);
+    QUIET.store(quiet, Ordering::Relaxed);
+    PORCELAIN.store(porcelain, Ordering::Relaxed);
+}
+
+/// Unsets the thread's listener when dropped, putting back the one before it.
+struct Listening(Option<Listener>);
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        LISTENER.with(|l| *l.borrow_mut() = previous);
+    }
+}
+
+/// Runs `f`, reporting whatever it reports on this thread to `listener` rather than stdout, and writing no records.
+pub fn reporting_to<T>(listener: Listener, f: impl FnOnce() -> T) -> T {
+    let _listening = Listening(LISTENER.with(|l| l.borrow_mut().replace(listener)));
+    f()
+}
+
+/// Gives `event` to the thread's listener, if it has one.
+fn listened(event: impl FnOnce() -> Event) -> bool {
+    match LISTENER.with(|l| l.borrow().clone()) {
+        Some(listener) => {
+            listener(&event());
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+pub fn is_porcelain() -> bool {
+    PORCELAIN.load(Ordering::Relaxed)
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Reports progress. Use `status!` rather than calling this directly.
+pub fn progress(message: &str) {
+    tracing::info!(target: logging::PROGRESS, "{}", message);
+    if listened(|| Event::Progress(message.to_owned())) {
+        // Reported.
+    } else if QUIET.load(Ordering::Relaxed) {
+        // Only logged.
+    } else if PORCELAIN.load(Ordering::Relaxed) {
+        eprintln!("{}", message);
+    } else if !is_json() {
+        progress::above(|| println!("{}", message));
+    } else if is_verbose() || scheduler::job().is_some() {
+        // One of several builds at once streams its progress too, for the build running them to print, see `scheduler`.
+        emit("progress", json!({ "message": message }));
+    }
+}
+
+/// Reports something only worth knowing with `--verbose`, like the exact commands being run.
+pub fn detail(message: &str) {
+    if listened(|| Event::Detail(message.to_owned())) {
+        tracing::debug!("{}", message);
+    } else if is_verbose() {
+        progress(message);
+    } else {
+        tracing::debug!("{}", message);
+    }
+}
+
+/// Reports something that went wrong without failing the build, to stderr whatever the output format.
+pub fn warning(message: &str) {
+    tracing::warn!("{}", message);
+    if !listened(|| Event::Warning(message.to_owned())) {
+        progress::above(|| eprintln!("{}", message));
+    }
+}
+
+/// Writes a JSON line for `event`, with the fields of `record`.
+pub fn emit(event: &str, record: Value) {
+    if LISTENER.with(|l| l.borrow().is_some()) {
+        return;
+    }
+    let mut line = json!({ "event": event });
+    if let (Some(line), Value::Object(fields)) = (line.as_object_mut(), record) {
+        line.extend(fields);
+    }
+    println!("{}", line);
+}
+
+/// Writes a `--porcelain` marker, if they're wanted.
+pub fn marker(name: &str, fields: &[(&str, &str)]) {
+    if !PORCELAIN.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut line = name.to_owned();
+    for (key, value) in fields {
+        let plain = !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=');
+        let value = if plain { value.to_string() } else { Value::String(value.to_string()).to_string() };
+        line.push_str(&format!(" {}={}", key, value));
+    }
+    println!("{}", line);
+}
+
+/// Like `println!`, but for progress messages, which go wherever the output format says.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::output::progress(&format!($($arg)*))
+    };
+}
+pub(crate) use status;
@@ -0,0 +1,169 @@
+//! `black_magic status`: what black_magic keeps for the current project, to see what's stale before cleaning or rebuilding.
+//!
+//! That's the artifacts in `target/black_magic` (each a manifest names), with their sizes and ages, and which one the last
+//! build made; the project's `bm_<project>` images, with their tags and IDs; its cache volumes and their sizes; and the
+//! builder images it builds with, with their ages, and whether they're out of date with the `[builder]` they'd be built
+//! from now. Without a container runtime, it only lists the artifacts.
+
+use crate::builder::Builder;
+use crate::clean;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::mounts;
+use crate::names;
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use crate::state::State;
+use crate::Arch;
+use clap::ArgMatches;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+struct Artifact {
+    path: PathBuf,
+    size: u64,
+    age_seconds: Option<u64>,
+    last: bool,
+}
+
+struct Image {
+    tags: Vec<String>,
+    id: String,
+    size: Option<u64>,
+}
+
+/// Runs `black_magic status`.
+pub fn status(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+    let name = names::resolve(crate::project_name(&current_dir)?, matches.value_of("NAME").or(config.name.as_deref()))?;
+    let bm_dir = current_dir.join("target").join("black_magic");
+
+    let artifacts = artifacts(&bm_dir);
+    status!("Artifacts in target/black_magic ({}):", clean::format_size(clean::dir_size(&bm_dir)));
+    if artifacts.is_empty() {
+        status!("    none");
+    }
+    for artifact in &artifacts {
+        status!(
+            "    {} ({}, {}){}",
+            artifact.path.file_name().unwrap_or_default().to_string_lossy(), clean::format_size(artifact.size),
+            artifact.age_seconds.map(format_age).unwrap_or_else(|| "age unknown".to_owned()), if artifact.last { ", the last build's" } else { "" });
+    }
+
+    let runtime = match Runtime::detect(matches.value_of("RUNTIME")) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            output::warning(&format!("Without a container runtime, only the artifacts are listed: {}", e));
+            None
+        }
+    };
+    let mut images = Vec::new();
+    let mut volumes = Vec::new();
+    let mut builders = Vec::new();
+    if let Some(runtime) = runtime {
+        let repositories: Vec<String> = [Arch::X86_64, Arch::Aarch64].iter()
+            .flat_map(|a| [format!("bm_{}{}", name, a.suffix()), format!("bm_{}{}-debug", name, a.suffix())])
+            .collect();
+        images = project_images(runtime, &repositories)?;
+        status!("Images:");
+        if images.is_empty() {
+            status!("    none");
+        }
+        for image in &images {
+            let size = image.size.map(clean::format_size).unwrap_or_else(|| "size unknown".to_owned());
+            status!("    {} ({}, {})", image.tags.join(", "), image.id, size);
+        }
+
+        volumes = clean::cache_volumes(runtime, &name)?;
+        status!("Cache volumes:");
+        if volumes.is_empty() {
+            status!("    none");
+        }
+        for (volume, size) in &volumes {
+            status!("    {} ({})", volume, size.map(clean::format_size).unwrap_or_else(|| "size unknown".to_owned()));
+        }
+
+        for (arch, gnu) in [(Arch::X86_64, false), (Arch::Aarch64, false), (Arch::X86_64, true), (Arch::Aarch64, true)] {
+            let builder = Builder::new(arch, gnu, config.builder.image.as_deref(), config.builder.tag.as_deref())
+                .pinned(&config.builder.pins)?
+                .customized(&config.builder.packages, config.builder.setup_script.as_deref())?;
+            if let Some(age) = builder.age_days(runtime) {
+                builders.push((builder.image.clone(), age, builder.is_current(runtime, arch)));
+            }
+        }
+        status!("Builder images:");
+        if builders.is_empty() {
+            status!("    none built yet");
+        }
+        for (image, age, current) in &builders {
+            status!("    {} (built {} days ago{})", image, age, if *current { "" } else { ", out of date, the next build rebuilds it" });
+        }
+    }
+
+    if output::is_json() {
+        output::emit("status", json!({
+            "artifacts": artifacts.iter().map(|a| json!({
+                "path": a.path, "size": a.size, "age_seconds": a.age_seconds, "last_build": a.last,
+            })).collect::<Vec<_>>(),
+            "images": images.iter().map(|i| json!({ "tags": i.tags, "id": i.id, "size": i.size })).collect::<Vec<_>>(),
+            "cache_volumes": volumes.iter().map(|(v, s)| json!({ "name": v, "size": s })).collect::<Vec<_>>(),
+            "builder_images": builders.iter().map(|(i, a, c)| json!({ "image": i, "age_days": a, "current": c })).collect::<Vec<_>>(),
+        }));
+    }
+    Ok(())
+}
+
+/// The artifacts the manifests in `bm_dir` name, that are still there, newest first.
+fn artifacts(bm_dir: &Path) -> Vec<Artifact> {
+    let last = State::load(bm_dir).map(|s| s.artifact);
+    let mut artifacts: Vec<Artifact> = fs::read_dir(bm_dir).into_iter().flatten().flatten()
+        .filter(|e| e.file_name().to_string_lossy().ends_with(".manifest.json"))
+        .filter_map(|e| {
+            let manifest: Value = serde_json::from_slice(&fs::read(e.path()).ok()?).ok()?;
+            let path = bm_dir.join(manifest["artifact"].as_str()?);
+            let metadata = fs::metadata(&path).ok()?;
+            let age_seconds = metadata.modified().ok().and_then(|m| SystemTime::now().duration_since(m).ok()).map(|d| d.as_secs());
+            Some(Artifact { last: last.as_ref() == Some(&path), path, size: metadata.len(), age_seconds })
+        })
+        .collect();
+    artifacts.sort_by_key(|a| a.age_seconds);
+    artifacts
+}
+
+/// The local images in `repositories`, once each however many tags they have.
+fn project_images(runtime: Runtime, repositories: &[String]) -> Result<Vec<Image>, BmError> {
+    let mut images: Vec<Image> = Vec::new();
+    for repository in repositories {
+        let ls = runtime.command()
+            .arg("image")
+            .arg("ls")
+            .arg("--format")
+            .arg("{{.ID}} {{.Repository}}:{{.Tag}}")
+            .arg(repository)
+            .output()
+            .map_err(|e| BmError::Docker(format!("Unable to list docker images: {}", e)))?;
+        for (id, tag) in String::from_utf8_lossy(&ls.stdout).lines().filter_map(|l| l.split_once(' ')).filter(|(_, t)| !t.contains("<none>")) {
+            match images.iter_mut().find(|i| i.id == id) {
+                Some(image) => image.tags.push(tag.to_owned()),
+                None => images.push(Image { tags: vec![tag.to_owned()], id: id.to_owned(), size: clean::image_size(runtime, id) }),
+            }
+        }
+    }
+    Ok(images)
+}
+
+fn format_age(seconds: u64) -> String {
+    let (count, unit) = match seconds {
+        s if s < 60 => return "just made".to_owned(),
+        s if s < 60 * 60 => (s / 60, "minute"),
+        s if s < 24 * 60 * 60 => (s / (60 * 60), "hour"),
+        s => (s / (24 * 60 * 60), "day"),
+    };
+    format!("{} {}{} old", count, unit, if count == 1 { "" } else { "s" })
+}
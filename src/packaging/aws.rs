@@ -0,0 +1,28 @@
+//! Lambda's OS-only runtimes, which run `bootstrap` from the root of the zip.
+
+use super::ServerlessTarget;
+use crate::error::BmError;
+
+pub struct Aws;
+
+impl ServerlessTarget for Aws {
+    fn name(&self) -> &'static str {
+        "aws"
+    }
+
+    fn suffix(&self) -> &'static str {
+        ""
+    }
+
+    fn executable(&self, _binary: &str) -> String {
+        "bootstrap".to_owned()
+    }
+
+    fn files(&self, _executable: &str) -> Result<Vec<(String, String)>, BmError> {
+        Ok(Vec::new())
+    }
+
+    fn is_lambda(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,90 @@
+//! Azure Functions custom handlers: `host.json` points the Functions host at the executable, and each function's
+//! `function.json` gives it an HTTP trigger. With `enableForwardingHttpRequest`, requests reach the executable as they are,
+//! on `$FUNCTIONS_CUSTOMHANDLER_PORT`, rather than wrapped in the host's own JSON.
+
+use super::ServerlessTarget;
+use crate::error::BmError;
+use serde::Deserialize;
+use serde_json::json;
+
+/// A name of its own, so it can't be the same as a function's directory named after the project.
+const EXECUTABLE: &str = "handler";
+
+const AUTH_LEVELS: &[&str] = &["anonymous", "function", "admin"];
+
+/// `[serverless.azure]`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AzureConfig {
+    /// One directory with a `function.json` each, defaulting to the project's name.
+    pub functions: Vec<String>,
+    pub auth_level: Option<String>,
+}
+
+pub struct Azure {
+    functions: Vec<String>,
+    auth_level: String,
+}
+
+impl Azure {
+    pub fn new(config: &AzureConfig, project: &str) -> Result<Azure, BmError> {
+        let functions = if config.functions.is_empty() { vec![project.to_owned()] } else { config.functions.clone() };
+        for (i, function) in functions.iter().enumerate() {
+            if functions[..i].contains(function) {
+                return Err(BmError::Environment(format!("`{}` is in `[serverless.azure]`'s `functions` twice.", function)));
+            }
+            if function.is_empty() || !function.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(BmError::Environment(format!(
+                    "`{}` in `[serverless.azure]` isn't a function name Azure accepts, only letters, digits, `-` and `_`.", function)));
+            }
+        }
+        let auth_level = config.auth_level.clone().unwrap_or_else(|| "function".to_owned());
+        if !AUTH_LEVELS.contains(&auth_level.as_str()) {
+            return Err(BmError::Environment(format!(
+                "`auth_level` in `[serverless.azure]` is `{}`, expected one of: {}.", auth_level, AUTH_LEVELS.join(", "))));
+        }
+        Ok(Azure { functions, auth_level })
+    }
+}
+
+impl ServerlessTarget for Azure {
+    fn name(&self) -> &'static str {
+        "azure"
+    }
+
+    fn suffix(&self) -> &'static str {
+        "-azure"
+    }
+
+    fn executable(&self, _binary: &str) -> String {
+        EXECUTABLE.to_owned()
+    }
+
+    fn files(&self, executable: &str) -> Result<Vec<(String, String)>, BmError> {
+        if self.functions.iter().any(|f| f == executable) {
+            return Err(BmError::Environment(format!(
+                "There can't be a `{}` function in `[serverless.azure]`, that's where the executable goes.", executable)));
+        }
+        let host = json!({
+            "version": "2.0",
+            "extensionBundle": {
+                "id": "Microsoft.Azure.Functions.ExtensionBundle",
+                "version": "[4.*, 5.0.0)",
+            },
+            "customHandler": {
+                "description": { "defaultExecutablePath": executable, "workingDirectory": "", "arguments": [] },
+                "enableForwardingHttpRequest": true,
+            },
+        });
+        let function = json!({
+            "bindings": [
+                { "type": "httpTrigger", "direction": "in", "name": "req", "authLevel": self.auth_level, "methods": ["get", "post", "put", "patch", "delete"] },
+                { "type": "http", "direction": "out", "name": "res" },
+            ],
+        });
+        let function = serde_json::to_string_pretty(&function).unwrap() + "\n";
+        let mut files = vec![("host.json".to_owned(), serde_json::to_string_pretty(&host).unwrap() + "\n")];
+        files.extend(self.functions.iter().map(|f| (format!("{}/function.json", f), function.clone())));
+        Ok(files)
+    }
+}
@@ -0,0 +1,26 @@
+//! Google's buildpacks, which run a source zip's `Procfile` `web` process, as Cloud Functions and `gcloud run deploy --source`
+//! build them. The process is the executable itself, serving HTTP on `$PORT`.
+
+use super::ServerlessTarget;
+use crate::error::BmError;
+use crate::shell_quote;
+
+pub struct Gcf;
+
+impl ServerlessTarget for Gcf {
+    fn name(&self) -> &'static str {
+        "gcf"
+    }
+
+    fn suffix(&self) -> &'static str {
+        "-gcf"
+    }
+
+    fn executable(&self, binary: &str) -> String {
+        binary.to_owned()
+    }
+
+    fn files(&self, executable: &str) -> Result<Vec<(String, String)>, BmError> {
+        Ok(vec![("Procfile".to_owned(), format!("web: ./{}\n", shell_quote(executable)))])
+    }
+}
@@ -0,0 +1,122 @@
+//! `--serverless aws|gcf|azure`: which serverless platform a lambda-mode zip is laid out for. The executable's compiled in
+//! the build container as for any zip; what differs is what it's called in the zip and which files the platform needs
+//! next to it:
+//! - `aws`, the default: Lambda's `bootstrap`, and nothing else (see `bundle` and `wrapper` for what can go with it)
+//! - `gcf`: the executable under its own name, with a `Procfile` starting it, for Google's buildpacks, e.g.
+//!   `gcloud run deploy --source` or Cloud Functions. It has to serve HTTP on `$PORT`.
+//! - `azure`: an Azure Functions custom handler, the executable as `handler`, with `host.json` pointing at it and a
+//!   `function.json` for each function, forwarding its HTTP requests to the executable on `$FUNCTIONS_CUSTOMHANDLER_PORT`.
+//!
+//! The platform can be set in `BlackMagic.toml` too, with what it needs:
+//! ```toml
+//! [serverless]
+//! target = "azure"
+//!
+//! [serverless.azure]
+//! functions = ["api", "webhook"]
+//! auth_level = "anonymous"
+//! ```
+//! `azure`'s functions default to one named after the project, at `/api/<name>`, with the `function` auth level.
+//!
+//! The files are written into `target/black_magic/<artifact>.serverless/` and zipped like `--include`s. Options for Lambda
+//! itself (`--deploy`, `--s3`, `--emit-sam`, `--emit-terraform`, `--lambda-runtime`, and a `[lambda.wrapper]`) only apply to
+//! `aws`, as do its size limits. The other platforms' zips get their own suffix, e.g. `my_project-azure.zip`.
+
+mod aws;
+mod azure;
+mod gcf;
+
+use crate::error::BmError;
+use aws::Aws;
+use azure::Azure;
+use azure::AzureConfig;
+use gcf::Gcf;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// The platforms, for `--serverless`.
+pub const NAMES: &[&str] = &["aws", "gcf", "azure"];
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ServerlessConfig {
+    /// See `--serverless`.
+    pub target: Option<String>,
+    pub azure: AzureConfig,
+}
+
+pub trait ServerlessTarget {
+    /// As `--serverless` names it.
+    fn name(&self) -> &'static str;
+
+    /// Appended to the zip's name, after the architecture's.
+    fn suffix(&self) -> &'static str;
+
+    /// What the executable cargo built as `binary` is called at the root of the zip.
+    fn executable(&self, binary: &str) -> String;
+
+    /// The files the platform needs in the zip next to `executable`, as their paths in it and their contents.
+    fn files(&self, executable: &str) -> Result<Vec<(String, String)>, BmError>;
+
+    /// Whether it's Lambda, which the AWS options and limits are for.
+    fn is_lambda(&self) -> bool {
+        false
+    }
+}
+
+/// One of `NAMES`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Serverless {
+    Aws,
+    Gcf,
+    Azure,
+}
+
+impl Serverless {
+    pub fn from_name(name: &str) -> Option<Serverless> {
+        match name {
+            "aws" => Some(Serverless::Aws),
+            "gcf" => Some(Serverless::Gcf),
+            "azure" => Some(Serverless::Azure),
+            _ => None,
+        }
+    }
+}
+
+/// The platform `requested` (by `--serverless`), or else `target` in `[serverless]`'s, or `aws`, with its section of
+/// `[serverless]`.
+pub fn select<'a>(requested: Option<Serverless>, config: &'a ServerlessConfig, project: &str) -> Result<Box<dyn ServerlessTarget + 'a>, BmError> {
+    let platform = match (requested, config.target.as_deref()) {
+        (Some(platform), _) => platform,
+        (None, Some(name)) => Serverless::from_name(name).ok_or_else(|| BmError::Environment(format!(
+            "`{}` in `BlackMagic.toml` isn't a supported serverless target, expected one of: {}.", name, NAMES.join(", "))))?,
+        (None, None) => Serverless::Aws,
+    };
+    match platform {
+        Serverless::Aws => Ok(Box::new(Aws)),
+        Serverless::Gcf => Ok(Box::new(Gcf)),
+        Serverless::Azure => Ok(Box::new(Azure::new(&config.azure, project)?)),
+    }
+}
+
+/// Where the platform's files for `artifact_name` are written, relative to the project.
+pub fn staging_dir(artifact_name: &str) -> String {
+    format!("target/black_magic/{}.serverless", artifact_name)
+}
+
+/// Writes `files` into `staging_dir`, in `project_dir`, replacing whatever an earlier build left there.
+pub fn write(project_dir: &Path, staging_dir: &str, files: &[(String, String)]) -> Result<(), BmError> {
+    let dir = project_dir.join(staging_dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| BmError::Packaging(format!("Unable to clear `{}`: {}", staging_dir, e)))?;
+    }
+    for (path, contents) in files {
+        let file = dir.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).map_err(|e| BmError::Packaging(format!("Unable to create `{}`: {}", parent.display(), e)))?;
+        }
+        fs::write(&file, contents).map_err(|e| BmError::Packaging(format!("Unable to write `{}/{}`: {}", staging_dir, path, e)))?;
+    }
+    Ok(())
+}
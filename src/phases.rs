@@ -0,0 +1,139 @@
+//! Rerunning just the later phases of a build, when only what's packaged with the executable has changed, e.g. the Dockerfile
+//! template or the Lambda zip's included files.
+//!
+//! A build goes compile → extract → package → image. The build container compiles the executable, inspects and shrinks it,
+//! then extracts it, with whatever else the artifact gets from the builder image (system files, the user's `etc`,
+//! companions), into `target/black_magic/phases/<artifact>/`, laid out as in the artifact. That's packaged into the zip (with
+//! the includes) or tarball, and the tarball is built into the project image. `target/black_magic/phases/<artifact>.json`
+//! records what the extracted executable was compiled from: the source, less the includes and the Dockerfile template, and the
+//! options changing the executable.
+//!
+//! When that's unchanged, the build starts at packaging, from what the last compile extracted. `--from-phase package` does
+//! too, whatever's changed, `--from-phase image` only rebuilds the image from the last build's tarball, and
+//! `--from-phase compile` (or `--no-cache`) always compiles. Compiling and extracting happen in the same build container, so
+//! they can only be rerun together. Packaging on its own happens on this machine, as `archivers` does, so it isn't possible
+//! for `--s3`'s streamed zips, `--layered` builds, or with `post-package` hooks in the container.
+
+use crate::archivers;
+use crate::bundle;
+use crate::bundle::Compression;
+use crate::cas;
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use crate::shell_quote;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The phases a build can start at, for `--from-phase`.
+pub const NAMES: &[&str] = &["compile", "package", "image"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    Compile,
+    Package,
+    Image,
+}
+
+impl Phase {
+    pub fn from_name(name: &str) -> Option<Phase> {
+        match name {
+            "compile" => Some(Phase::Compile),
+            "package" => Some(Phase::Package),
+            "image" => Some(Phase::Image),
+            _ => None,
+        }
+    }
+}
+
+/// Where the build container extracts the executable to, relative to the project.
+pub fn dir(artifact_name: &str) -> String {
+    format!("target/black_magic/phases/{}", artifact_name)
+}
+
+fn record_file(bm_dir: &Path, artifact_name: &str) -> PathBuf {
+    bm_dir.join("phases").join(format!("{}.json", artifact_name))
+}
+
+/// What the executable is compiled from: the source in `project_dir`, less `excluded` (relative to it), and
+/// `compile_options`.
+pub fn key(project_dir: &Path, compile_options: &str, excluded: &[&str]) -> String {
+    cas::fingerprint_without(project_dir, compile_options, excluded)
+}
+
+/// The key of the compile whose executable is extracted for `artifact_name`, if there is one.
+fn cached(project_dir: &Path, bm_dir: &Path, artifact_name: &str) -> Option<String> {
+    if !project_dir.join(dir(artifact_name)).is_dir() {
+        return None;
+    }
+    let record: serde_json::Value = serde_json::from_slice(&fs::read(record_file(bm_dir, artifact_name)).ok()?).ok()?;
+    record["key"].as_str().map(|k| k.to_owned())
+}
+
+/// Records that the executable extracted for `artifact_name` was compiled from `key`.
+pub fn save(bm_dir: &Path, artifact_name: &str, key: &str) -> Result<(), BmError> {
+    let path = record_file(bm_dir, artifact_name);
+    let _ = fs::create_dir_all(bm_dir.join("phases"));
+    let json = serde_json::to_string_pretty(&json!({ "key": key })).unwrap();
+    fs::write(&path, json).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", path.display(), e)))
+}
+
+/// Forgets what the executable extracted for `artifact_name` was compiled from, before it's compiled again, so a build that
+/// fails part-way doesn't leave one extracted executable recorded as another.
+pub fn forget(bm_dir: &Path, artifact_name: &str) {
+    let _ = fs::remove_file(record_file(bm_dir, artifact_name));
+}
+
+/// Which phase the build starts at: `from`, `--from-phase`'s, or packaging if the compile's `key` is the extracted
+/// executable's. Without a `key`, the build can't be packaged on this machine. `artifact` is the last build's, which
+/// `image` builds the image from, if it has one.
+pub fn start(from: Option<Phase>, key: Option<&str>, project_dir: &Path, bm_dir: &Path, artifact_name: &str, artifact: &Path, image: bool) -> Result<Phase, BmError> {
+    let cached = cached(project_dir, bm_dir, artifact_name);
+    match from {
+        Some(Phase::Compile) => Ok(Phase::Compile),
+        Some(Phase::Image) if !image => Err(BmError::Environment("`--from-phase image` only applies to docker builds with an image.".to_owned())),
+        Some(Phase::Image) if !artifact.is_file() => Err(BmError::Environment(format!(
+            "There's no {} to build the image from, build without `--from-phase` first.", artifact.display()))),
+        Some(Phase::Image) => Ok(Phase::Image),
+        Some(Phase::Package) if key.is_none() => Err(BmError::Environment(
+            "`--from-phase package` packages on this machine, which can't be done for `--s3`, `--layered` builds, remote daemons, \
+            or with `post-package` hooks in the container.".to_owned())),
+        Some(Phase::Package) if cached.is_none() => Err(BmError::Environment(format!(
+            "There's no executable in {} to package, build without `--from-phase` first.", dir(artifact_name)))),
+        Some(Phase::Package) => {
+            if cached.as_deref() != key {
+                output::warning("The source or options have changed since the last compile, but packaging its executable anyway.");
+            }
+            Ok(Phase::Package)
+        }
+        None if key.is_some() && cached.as_deref() == key => {
+            status!("The executable's source and options are unchanged since the last compile, so it's packaged from what that extracted.");
+            Ok(Phase::Package)
+        }
+        None => Ok(Phase::Compile),
+    }
+}
+
+/// Packages what the last compile extracted for `artifact_name` into `artifact`, with `includes`, on this machine.
+pub fn package(project_dir: &Path, artifact_name: &str, includes: &[bundle::Include], artifact: &Path, is_docker: bool, source_date_epoch: Option<u64>, compression: Compression) -> Result<(), BmError> {
+    let package_dir = archivers::package_dir(artifact_name);
+    let script = format!(
+        "rm -rf {package} && cp -a {} {package}{}",
+        shell_quote(&dir(artifact_name)), bundle::stage_cmd(includes, &package_dir), package = shell_quote(&package_dir));
+    let staged = Command::new("sh")
+        .arg("-c")
+        .arg(&script)
+        .current_dir(project_dir)
+        .output()
+        .map_err(|e| BmError::Packaging(format!("Unable to run the packaging command: {}", e)))?;
+    if !staged.status.success() {
+        let _ = fs::remove_dir_all(project_dir.join(&package_dir));
+        return Err(BmError::Packaging(format!(
+            "Unable to stage the artifact's files.\n\nThe following command failed:\n\n{}\n\nstderr: {}",
+            script, String::from_utf8_lossy(&staged.stderr))));
+    }
+    archivers::archive(project_dir, &package_dir, artifact, is_docker, source_date_epoch, compression)
+}
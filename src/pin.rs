@@ -0,0 +1,80 @@
+//! `black_magic pin-builder`: pinning the project's builder image to the digest its base image's tag points at now, in
+//! `[builder.pins]` in `BlackMagic.toml` (see `builder`), so it builds with the same toolchain until it's pinned again.
+//!
+//! It pins the builder for `--arch` and `--libc` (x86_64 and musl by default), with the `[builder]` section's `image` and `tag`,
+//! pulling the base image to find the digest. The rest of `BlackMagic.toml`, comments included, is left as it is. Running it
+//! again moves the pin to whatever the tag points at then, which with `--update-builder`, is how a pinned project upgrades.
+
+use crate::builder::Builder;
+use crate::config::Config;
+use crate::config::CONFIG_FILE;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use crate::Arch;
+use clap::ArgMatches;
+use serde_json::json;
+use std::fs;
+
+/// `contents` of `BlackMagic.toml` with `key` pinned to `digest` in `[builder.pins]`.
+fn set_pin(contents: &str, key: &str, digest: &str) -> String {
+    let entry = format!("{:?} = {:?}", key, digest);
+    let mut lines: Vec<String> = contents.lines().map(|l| l.to_owned()).collect();
+    match lines.iter().position(|l| l.trim() == "[builder.pins]") {
+        Some(header) => {
+            let end = lines[header + 1..].iter().position(|l| l.trim_start().starts_with('[')).map(|e| header + 1 + e).unwrap_or(lines.len());
+            let quoted = format!("{:?}", key);
+            match lines[header + 1..end].iter().position(|l| l.trim_start().starts_with(&quoted)) {
+                Some(existing) => lines[header + 1 + existing] = entry,
+                None => lines.insert(header + 1, entry),
+            }
+        }
+        None => {
+            if lines.last().map(|l| !l.trim().is_empty()).unwrap_or(false) {
+                lines.push(String::new());
+            }
+            lines.push("[builder.pins]".to_owned());
+            lines.push(entry);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Runs `black_magic pin-builder`.
+pub fn pin_builder(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+    let arch = Arch::from_name(matches.value_of("ARCH").unwrap());
+    let builder = Builder::new(arch, matches.value_of("LIBC") == Some("gnu"), config.builder.image.as_deref(), config.builder.tag.as_deref());
+    let runtime = Runtime::detect(matches.value_of("RUNTIME"))?;
+    let digest = builder.resolve_digest(runtime, arch)?;
+
+    let path = current_dir.join(CONFIG_FILE);
+    let contents = if path.exists() {
+        fs::read_to_string(&path).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", CONFIG_FILE, e)))?
+    } else {
+        String::new()
+    };
+    let pinned = set_pin(&contents, &builder.pin_key(), &digest);
+    // A `pins` table written some other way, e.g. inline, can't be edited line by line.
+    let written: Config = toml::from_str(&pinned).map_err(|e| BmError::Environment(format!(
+        "Unable to add the pin to `{}` ({}), add `{:?} = {:?}` to `[builder.pins]` yourself.", CONFIG_FILE, e, builder.pin_key(), digest)))?;
+    if written.builder.pins.get(&builder.pin_key()) != Some(&digest) {
+        return Err(BmError::Environment(format!(
+            "Unable to add the pin to `{}`, add `{:?} = {:?}` to `[builder.pins]` yourself.", CONFIG_FILE, builder.pin_key(), digest)));
+    }
+    fs::write(&path, pinned).map_err(|e| BmError::Environment(format!("Unable to write `{}`: {}", CONFIG_FILE, e)))?;
+
+    let previous = config.builder.pins.get(&builder.pin_key());
+    match previous {
+        Some(previous) if *previous == digest => status!("{} is already pinned to {}.", builder.pin_key(), digest),
+        Some(previous) => status!("Moved the pin of {} from {} to {}.", builder.pin_key(), previous, digest),
+        None => status!("Pinned {} to {}.", builder.pin_key(), digest),
+    }
+    if output::is_json() {
+        output::emit("pinned", json!({ "image": builder.pin_key(), "digest": digest, "previous": previous }));
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+//! The `pipeline` subcommand: runs a named sequence of steps from `BlackMagic.toml`, stopping at the first that fails.
+//!
+//! ```toml
+//! [pipelines.quick]
+//! steps = ["build --lambda"]
+//!
+//! [pipelines.ci-release]
+//! steps = [
+//!     "check",
+//!     "test --workspace",
+//!     "build --docker --push registry.example.com/app:latest",
+//!     "build --docker --arch aarch64 --push registry.example.com/app:latest-arm64",
+//!     "run syft registry.example.com/app:latest -o spdx-json > target/black_magic/sbom.json",
+//! ]
+//! ```
+//! Each step is one of:
+//! - `check [args]` and `test [args]`: `cargo check` and `cargo test` on the host
+//! - `build <args>`: a black_magic build with those arguments, as `release` runs them
+//! - `run <command>`: a shell command, run in the project directory
+//!
+//! Every step is parsed before any of them runs, so a typo fails the pipeline straight away rather than after a long build.
+
+use crate::config::Config;
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use crate::release;
+use clap::ArgMatches;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+use std::time::SystemTime;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Pipeline {
+    pub steps: Vec<String>,
+}
+
+enum Step {
+    /// `cargo <command> <args>`, for `check` and `test`.
+    Cargo(String, Vec<String>),
+    Build(Vec<String>),
+    Run(String),
+}
+
+impl Step {
+    fn parse(step: &str) -> Result<Step, String> {
+        let step = step.trim();
+        let (kind, rest) = match step.find(char::is_whitespace) {
+            Some(i) => (&step[..i], step[i..].trim()),
+            None => (step, ""),
+        };
+        let args = || rest.split_whitespace().map(|a| a.to_owned()).collect::<Vec<_>>();
+        match kind {
+            "check" | "test" => Ok(Step::Cargo(kind.to_owned(), args())),
+            "build" if !rest.is_empty() => Ok(Step::Build(args())),
+            "build" => Err("`build` needs the arguments to build with, e.g. `build --lambda`.".to_owned()),
+            "run" if !rest.is_empty() => Ok(Step::Run(rest.to_owned())),
+            "run" => Err("`run` needs a command to run.".to_owned()),
+            _ => Err(format!("`{}` isn't a step, use `check`, `test`, `build <args>`, or `run <command>`.", step)),
+        }
+    }
+
+    fn run(&self, project_dir: &Path, exe: &Path) -> Result<(), BmError> {
+        match self {
+            Step::Cargo(command, args) => {
+                let status = Command::new("cargo")
+                    .arg(command)
+                    .args(args)
+                    .current_dir(project_dir)
+                    .status()
+                    .map_err(|e| BmError::Environment(format!("Unable to run cargo: {}", e)))?;
+                match (status.success(), command.as_str()) {
+                    (true, _) => Ok(()),
+                    (false, "test") => Err(BmError::Test("`cargo test` failed.".to_owned())),
+                    (false, _) => Err(BmError::Compile(format!("`cargo {}` failed.", command))),
+                }
+            }
+            Step::Build(args) => {
+                let started = SystemTime::now();
+                let status = Command::new(exe).args(args).current_dir(project_dir).status();
+                let succeeded = status.as_ref().map(|s| s.success()).unwrap_or(false);
+                if succeeded && !release::manifests_since(&project_dir.join("target").join("black_magic"), started).is_empty() {
+                    return Ok(());
+                }
+                // The build has already explained what went wrong, so just pass on what kind of failure it was.
+                Err(BmError::from_exit_code(status.ok().and_then(|s| s.code()), format!("`black_magic {}` failed.", args.join(" "))))
+            }
+            Step::Run(command) => {
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .current_dir(project_dir)
+                    .status()
+                    .map_err(|e| BmError::Environment(format!("Unable to run `{}`: {}", command, e)))?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(BmError::Environment(format!("`{}` failed with {}.", command, status)))
+                }
+            }
+        }
+    }
+}
+
+/// Runs the `pipeline` subcommand, or lists the pipelines without a name.
+pub fn pipeline(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = crate::mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+
+    let name = match matches.value_of("PIPELINE") {
+        Some(n) => n,
+        None => {
+            if config.pipelines.is_empty() {
+                status!("There are no pipelines. Add them to `BlackMagic.toml` as `[pipelines.<name>]`.");
+            }
+            for (name, pipeline) in &config.pipelines {
+                status!("{}: {}", name, pipeline.steps.join(" → "));
+            }
+            return Ok(());
+        }
+    };
+    let pipeline = config.pipelines.get(name).ok_or_else(|| BmError::Environment(format!(
+        "There's no `{}` pipeline in `BlackMagic.toml`, it has: {}.",
+        name, config.pipelines.keys().cloned().collect::<Vec<_>>().join(", "))))?;
+    if pipeline.steps.is_empty() {
+        return Err(BmError::Environment(format!("The `{}` pipeline has no steps.", name)));
+    }
+    let steps = pipeline
+        .steps
+        .iter()
+        .map(|s| Step::parse(s))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| BmError::Environment(format!("Invalid step in the `{}` pipeline: {}", name, e)))?;
+
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    let started = Instant::now();
+    let mut durations = Vec::new();
+    for (i, (step, description)) in steps.iter().zip(&pipeline.steps).enumerate() {
+        status!("[{}/{}] {}", i + 1, steps.len(), description);
+        let step_started = Instant::now();
+        step.run(&current_dir, &exe)
+            .map_err(|e| BmError::from_exit_code(Some(e.exit_code()), format!("{}\nPipeline `{}` stopped at step {}.", e, name, i + 1)))?;
+        durations.push(step_started.elapsed().as_secs_f64());
+    }
+
+    status!("...Pipeline `{}` done in {:.1}s.", name, started.elapsed().as_secs_f64());
+    if output::is_json() {
+        output::emit("pipeline", json!({
+            "pipeline": name,
+            "steps": pipeline.steps.iter().zip(&durations).map(|(s, d)| json!({ "step": s, "duration_seconds": d })).collect::<Vec<_>>(),
+            "duration_seconds": started.elapsed().as_secs_f64(),
+        }));
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! `--no-side-effects`: resolving and checking a build, then describing it instead of running it.
+//!
+//! Everything up to the compile still happens (arguments, `BlackMagic.toml`, includes, the Dockerfile template, the dependency
+//! policy, the backend and the fingerprint), so a build that would be refused is refused here too. What's guaranteed not to
+//! happen is anything with an effect:
+//! - no files are written, not even `target/black_magic` or the artifact store
+//! - no images, containers or volumes are created, the container runtime is only asked for its version and what images exist
+//! - nothing touches the network: `cargo metadata` runs with `--frozen`, and nothing talks to AWS, registries or a cache server
+//!
+//! What would have happened is printed as a plan: each step in order, with the exact build command, and the outputs it would
+//! produce. With `--output-format json` it's a single `plan` record instead.
+//!
+//! `--dry-run` also prints every command the build would run (building the builder image, the build container, `docker build`s)
+//! as a shell command line run from the project's directory, with the script the build container runs broken into a line per
+//! step, and the Dockerfiles it would write, so a build can be reproduced by hand. They're `commands` and `files` in JSON.
+
+use crate::output;
+use crate::output::status;
+use serde_json::json;
+use std::process::Command;
+
+#[derive(Default)]
+pub struct Plan {
+    steps: Vec<String>,
+    outputs: Vec<String>,
+    dry_run: bool,
+    commands: Vec<String>,
+    files: Vec<(String, String)>,
+}
+
+impl Plan {
+    pub fn new(dry_run: bool) -> Plan {
+        Plan { dry_run, ..Plan::default() }
+    }
+
+    pub fn step(&mut self, step: String) {
+        self.steps.push(step);
+    }
+
+    /// Something the build would write or create: a file, an image, an S3 object.
+    pub fn output(&mut self, output: String) {
+        self.outputs.push(output);
+    }
+
+    /// A command the build would run, from the directory `dir` of the project if it's not the project's own.
+    pub fn command(&mut self, dir: Option<&str>, cmd: &Command) {
+        let line = crate::command_line(cmd);
+        self.commands.push(match dir {
+            Some(dir) => format!("(cd {} && {})", crate::shell_quote(dir), line),
+            None => line,
+        });
+    }
+
+    /// The script the build container would run, with each step on a line of its own.
+    pub fn script(&mut self, description: &str, script: &str) {
+        self.commands.push(format!("# {}:\n{}", description, script.replace(" && ", " &&\n    ")));
+    }
+
+    /// A file the build would write for a command, e.g. a Dockerfile.
+    pub fn file(&mut self, path: String, contents: String) {
+        self.files.push((path, contents));
+    }
+
+    pub fn print(&self) {
+        if output::is_json() {
+            let mut plan = json!({ "steps": self.steps, "outputs": self.outputs });
+            if self.dry_run {
+                plan["commands"] = json!(self.commands);
+                plan["files"] = json!(self.files.iter().map(|(path, contents)| json!({ "path": path, "contents": contents })).collect::<Vec<_>>());
+            }
+            output::emit("plan", plan);
+            return;
+        }
+
+        status!("Nothing was changed (`{}`). The build would:", if self.dry_run { "--dry-run" } else { "--no-side-effects" });
+        for (i, step) in self.steps.iter().enumerate() {
+            status!("    {}. {}", i + 1, step);
+        }
+        status!("Producing:");
+        for output in &self.outputs {
+            status!("    {}", output);
+        }
+        if !self.dry_run {
+            return;
+        }
+        for (path, contents) in &self.files {
+            status!("\n{}:\n{}", path, contents.trim_matches('\n'));
+        }
+        status!("\nCommands, from the project's directory:");
+        for command in &self.commands {
+            status!("{}", command);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Build policies from the `[policy]` section of `BlackMagic.toml`, e.g:
+//! ```toml
+//! [policy]
+//! max_dependencies = 150
+//! banned_crates = ["openssl-sys"]
+//! max_binary_size = 20_000_000
+//! allowed_licenses = ["MIT", "Apache-2.0", "BSD-3-Clause"]
+//! ```
+//! Dependency policies are checked before compiling, the binary size once the binary exists.
+
+use crate::metadata::Metadata;
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Policy {
+    pub max_dependencies: Option<usize>,
+    pub banned_crates: Vec<String>,
+    /// In bytes, of the executable itself rather than the compressed artifact.
+    pub max_binary_size: Option<u64>,
+    pub allowed_licenses: Option<Vec<String>>,
+}
+
+impl Policy {
+    pub fn checks_dependencies(&self) -> bool {
+        self.max_dependencies.is_some() || !self.banned_crates.is_empty() || self.allowed_licenses.is_some()
+    }
+
+    /// Returns a description of each violated dependency policy.
+    pub fn check_dependencies(&self, metadata: &Metadata) -> Vec<String> {
+        let mut violations = Vec::new();
+        let dependencies = metadata.dependencies();
+
+        if let Some(max) = self.max_dependencies {
+            if dependencies.len() > max {
+                violations.push(format!("{} dependencies, the maximum is {}.", dependencies.len(), max));
+            }
+        }
+
+        for package in &dependencies {
+            if self.banned_crates.contains(&package.name) {
+                violations.push(format!("`{} {}` is a banned crate.", package.name, package.version));
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_licenses {
+            for package in &dependencies {
+                match &package.license {
+                    Some(license) if license_allowed(license, allowed) => {}
+                    Some(license) => violations.push(format!("`{} {}` has a disallowed license: {}", package.name, package.version, license)),
+                    None => violations.push(format!("`{} {}` doesn't declare an SPDX license.", package.name, package.version)),
+                }
+            }
+        }
+
+        violations
+    }
+
+    pub fn check_binary_size(&self, size: u64) -> Option<String> {
+        match self.max_binary_size {
+            Some(max) if size > max => Some(format!("The binary is {} bytes, the maximum is {}.", size, max)),
+            _ => None,
+        }
+    }
+}
+
+/// Evaluates an SPDX expression such as `(MIT OR Apache-2.0) AND Unicode-DFS-2016` (or the older `MIT/Apache-2.0`).
+/// Any `OR` alternative may be picked, but every license in an `AND` must be allowed.
+fn license_allowed(expression: &str, allowed: &[String]) -> bool {
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ").replace('/', " OR ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let mut position = 0;
+    eval_or(&tokens, &mut position, allowed)
+}
+
+fn eval_or(tokens: &[&str], position: &mut usize, allowed: &[String]) -> bool {
+    let mut result = eval_and(tokens, position, allowed);
+    while tokens.get(*position) == Some(&"OR") {
+        *position += 1;
+        // Evaluate both sides regardless, so the whole expression is consumed.
+        let rhs = eval_and(tokens, position, allowed);
+        result = result || rhs;
+    }
+    result
+}
+
+fn eval_and(tokens: &[&str], position: &mut usize, allowed: &[String]) -> bool {
+    let mut result = eval_license(tokens, position, allowed);
+    while tokens.get(*position) == Some(&"AND") {
+        *position += 1;
+        let rhs = eval_license(tokens, position, allowed);
+        result = result && rhs;
+    }
+    result
+}
+
+fn eval_license(tokens: &[&str], position: &mut usize, allowed: &[String]) -> bool {
+    match tokens.get(*position) {
+        Some(&"(") => {
+            *position += 1;
+            let result = eval_or(tokens, position, allowed);
+            if tokens.get(*position) == Some(&")") {
+                *position += 1;
+            }
+            result
+        }
+        Some(license) => {
+            *position += 1;
+            // Exceptions (`Apache-2.0 WITH LLVM-exception`) only ever grant extra permissions.
+            if tokens.get(*position) == Some(&"WITH") {
+                *position += 2;
+            }
+            allowed.iter().any(|a| a == license)
+        }
+        None => false,
+    }
+}
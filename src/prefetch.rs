@@ -0,0 +1,50 @@
+//! `--prefetch`: downloading everything the build needs first, then compiling without a network, so a build script that
+//! reaches out at compile time fails the same everywhere, rather than only where the network's restricted.
+//!
+//! Before compiling, a container like the build's (with its mounts, environment and `--network`) runs `cargo fetch` for the
+//! target, and then any of the project's own commands for the downloads cargo doesn't know about, e.g. assets a build
+//! script reads from the project instead of downloading them itself:
+//! ```toml
+//! [prefetch]
+//! enabled = true
+//! commands = ["curl -fsSLo assets/model.bin https://example.com/model.bin"]
+//! ```
+//! `enabled` prefetches in every build, as `--prefetch` does. The compile then runs with `--network none` and
+//! `cargo build --offline`, from the cargo home the fetch filled: the host's, or the cache volume (see `--cargo-home-volume`).
+//! The commands run with `sh -c`, in the project (`/workdir`), and a failing one fails the build.
+
+use crate::shell_quote;
+use serde::Deserialize;
+use std::process::Command;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PrefetchConfig {
+    pub enabled: bool,
+    pub commands: Vec<String>,
+}
+
+/// The build container's command with `network_args` (as the build's own go), running `fetch_cmd` and then `commands` in
+/// `workdir`, the project.
+pub fn command(run: &Command, network_args: &[String], image: &str, workdir: &str, fetch_cmd: &str, commands: &[String]) -> Command {
+    let mut prefetch = Command::new(run.get_program());
+    let mut args = run.get_args();
+    while let Some(arg) = args.next() {
+        // The build container's name, which it needs once this has gone, and its working directory, given below.
+        if arg == "--name" || arg == "-w" {
+            args.next();
+        } else if arg != "--rm" {
+            prefetch.arg(arg);
+        }
+    }
+    let script: String = commands.iter().map(|c| format!(" && sh -c {}", shell_quote(c))).collect();
+    prefetch.arg("--rm")
+        .args(network_args)
+        .arg("-w")
+        .arg(workdir)
+        .arg(image)
+        .arg("/bin/bash")
+        .arg("-c")
+        .arg(format!("{}{}", fetch_cmd, script));
+    prefetch
+}
@@ -0,0 +1,87 @@
+//! `--preset <name>`: a named set of build options from `BlackMagic.toml`, for projects built several ways, e.g:
+//! ```toml
+//! [preset.lambda-arm]
+//! lambda = true
+//! arch = "aarch64"
+//! deploy = "my-function-arm"
+//!
+//! [preset.fargate]
+//! docker = true
+//! base = "debian:bookworm-slim"
+//! features = "fargate"
+//! include = ["assets:/assets"]
+//! tag = ["latest", "fargate"]
+//! push = ["registry.example.com/app"]
+//! ```
+//! Each key is an option's long name, without the `--`: `true` passes a flag, a string or number its value, and a list each
+//! value, as the option given that many times. `black_magic --preset fargate` then builds as if they'd been passed, except
+//! for the options also on the command line, which replace the preset's, so `--preset fargate --tag dev` pushes only `dev`.
+
+use crate::config::Config;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output;
+use clap::ArgMatches;
+use toml::Value;
+
+/// The preset's options as arguments, for `name`d options `matches` doesn't have already.
+fn preset_args(name: &str, preset: &toml::value::Table, matches: &ArgMatches) -> Result<Vec<String>, BmError> {
+    let invalid = |option: &str, why: &str| BmError::Environment(format!("`{}` in `[preset.{}]` {}.", option, name, why));
+    let mut args = Vec::new();
+    for (option, value) in preset {
+        if option == "preset" {
+            return Err(invalid(option, "can't pick another preset"));
+        }
+        // Options are named after their long names, e.g. `LAMBDA_IMAGE` for `--lambda-image`.
+        if matches.occurrences_of(option.to_ascii_uppercase().replace('-', "_")) > 0 {
+            continue;
+        }
+        let flag = format!("--{}", option);
+        let scalar = |value: &Value| match value {
+            Value::String(s) => Ok(s.clone()),
+            Value::Integer(i) => Ok(i.to_string()),
+            Value::Float(f) => Ok(f.to_string()),
+            _ => Err(invalid(option, "has to be `true`, a string, a number, or a list of them")),
+        };
+        match value {
+            Value::Boolean(true) => args.push(flag),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for value in values {
+                    args.push(flag.clone());
+                    args.push(scalar(value)?);
+                }
+            }
+            value => {
+                args.push(flag);
+                args.push(scalar(value)?);
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// `matches`, parsed from `args`, with the options of its `--preset` added, if it has one.
+pub fn apply(matches: ArgMatches<'static>, args: Vec<String>) -> Result<ArgMatches<'static>, BmError> {
+    let name = match matches.value_of("PRESET") {
+        Some(name) => name.to_owned(),
+        None => return Ok(matches),
+    };
+    let config = Config::load(&mounts::current_dir()?)?;
+    let preset = config.preset.get(&name).ok_or_else(|| BmError::Environment(if config.preset.is_empty() {
+        format!("There's no `[preset.{}]` in `BlackMagic.toml`, or any presets.", name)
+    } else {
+        format!("There's no `[preset.{}]` in `BlackMagic.toml`, use one of: {}.", name, config.preset.keys().cloned().collect::<Vec<_>>().join(", "))
+    }))?;
+    let added = preset_args(&name, preset, &matches)?;
+    output::detail(&format!("The `{}` preset adds: {}", name, added.join(" ")));
+    // Before everything else, so none of them ends up after `--`, for cargo.
+    let mut args = args.into_iter();
+    let expanded: Vec<String> = args.next().into_iter().chain(added).chain(args).collect();
+    crate::app()
+        .get_matches_from_safe(expanded)
+        .map_err(|e| {
+            let error = e.message.lines().next().unwrap_or_default().trim_start_matches("error: ").to_owned();
+            BmError::Environment(format!("Invalid `[preset.{}]`: {}", name, error))
+        })
+}
@@ -0,0 +1,140 @@
+//! Which phase of the build is running, and how long each one took.
+//!
+//! Compiling can go quiet for minutes, so while stderr is a terminal (and progress messages are printed, see `output`), an
+//! `indicatif` spinner shows the current phase, how long it's been running, and about how much longer it should take, going
+//! by the last build of the same artifact (kept in `target/black_magic/<artifact>.timings.json`). The build ends with a
+//! breakdown of the phases' times, which the `done` JSON record has as `phases` too.
+
+use crate::ci;
+use crate::logging;
+use crate::output::status;
+use indicatif::MultiProgress;
+use indicatif::ProgressBar;
+use indicatif::ProgressState;
+use indicatif::ProgressStyle;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
+use tracing::span::EnteredSpan;
+
+/// Where the phases' spinners are drawn, once `init` has found they should be.
+static SPINNERS: OnceLock<MultiProgress> = OnceLock::new();
+static TIMINGS: Mutex<Vec<(&'static str, f64)>> = Mutex::new(Vec::new());
+static PREVIOUS: Mutex<BTreeMap<String, f64>> = Mutex::new(BTreeMap::new());
+
+/// Shows a spinner for each phase from now on, if `wanted` and stderr is a terminal.
+pub fn init(wanted: bool) {
+    if wanted && io::stderr().is_terminal() {
+        let _ = SPINNERS.set(MultiProgress::new());
+    }
+}
+
+/// A phase of the build, over when it's dropped. It's also a span in the build's log, see `logging`.
+pub struct Phase {
+    name: &'static str,
+    started: Instant,
+    spinner: Option<ProgressBar>,
+    _span: EnteredSpan,
+}
+
+/// Starts a phase. Replacing the previous phase with it ends that one.
+pub fn phase(name: &'static str) -> Phase {
+    ci::begin_group(name);
+    let spinner = SPINNERS.get().map(|spinners| {
+        let spinner = spinners.add(ProgressBar::new_spinner().with_style(style(lock(&PREVIOUS).get(name).copied())).with_message(name));
+        spinner.enable_steady_tick(Duration::from_millis(120));
+        spinner
+    });
+    Phase { name, started: Instant::now(), spinner, _span: logging::phase(name) }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        let seconds = self.started.elapsed().as_secs_f64();
+        ci::end_group(self.name);
+        let mut timings = lock(&TIMINGS);
+        match timings.iter_mut().find(|(name, _)| *name == self.name) {
+            Some((_, total)) => *total += seconds,
+            None => timings.push((self.name, seconds)),
+        }
+        if let (Some(spinner), Some(spinners)) = (&self.spinner, SPINNERS.get()) {
+            spinner.finish_and_clear();
+            spinners.remove(spinner);
+        }
+    }
+}
+
+/// Runs `print` with the spinners cleared, so what it prints doesn't end up on the same line.
+pub fn above(print: impl FnOnce()) {
+    match SPINNERS.get() {
+        Some(spinners) => spinners.suspend(print),
+        None => print(),
+    }
+}
+
+/// The phase's name, how long it's been running, and about how much longer it should take, if it took `last` last time.
+fn style(last: Option<f64>) -> ProgressStyle {
+    ProgressStyle::with_template("{spinner} {msg} {clock}{estimate}")
+        .unwrap()
+        .tick_chars("|/-\\ ")
+        .with_key("clock", |state: &ProgressState, w: &mut dyn Write| {
+            let _ = write!(w, "{}", clock(state.elapsed().as_secs_f64()));
+        })
+        .with_key("estimate", move |state: &ProgressState, w: &mut dyn Write| {
+            let elapsed = state.elapsed().as_secs_f64();
+            let _ = match last {
+                Some(last) if last > elapsed => write!(w, ", about {} left", clock(last - elapsed)),
+                Some(last) => write!(w, ", {} last time", clock(last)),
+                None => Ok(()),
+            };
+        })
+}
+
+/// `m:ss`.
+fn clock(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Reads the phases' times from the artifact's last build, for the spinner's estimates.
+pub fn load_previous(path: &Path) {
+    let previous: Option<BTreeMap<String, f64>> = fs::read(path).ok().and_then(|c| serde_json::from_slice(&c).ok());
+    *lock(&PREVIOUS) = previous.unwrap_or_default();
+}
+
+/// Keeps the phases' times for the next build's estimates, along with any from before that didn't run this time.
+pub fn save(path: &Path) {
+    let mut timings = lock(&PREVIOUS).clone();
+    timings.extend(lock(&TIMINGS).iter().map(|(name, seconds)| (name.to_string(), *seconds)));
+    if let Ok(contents) = serde_json::to_vec_pretty(&timings) {
+        let _ = fs::write(path, contents);
+    }
+}
+
+/// The finished phases, in the order they ran, for the `done` record.
+pub fn report() -> Value {
+    Value::Array(lock(&TIMINGS).iter().map(|(name, seconds)| json!({ "name": name, "seconds": seconds })).collect())
+}
+
+/// Prints how long each finished phase took.
+pub fn print_report() {
+    let timings = lock(&TIMINGS).clone();
+    status!("Timings:");
+    for (name, seconds) in timings.iter() {
+        status!("    {:<14}{:>8.1}s", name, seconds);
+    }
+    status!("    {:<14}{:>8.1}s", "total", timings.iter().map(|(_, s)| s).sum::<f64>());
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
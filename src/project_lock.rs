@@ -0,0 +1,51 @@
+//! One build at a time in each project.
+//!
+//! Builds of the same project share `target/black_magic` (its Dockerfiles, manifests and artifacts) and the target cache
+//! volume, so two at once would write over each other's output. Each takes a lock on `target/black_magic/.lock`, held until
+//! it finishes, and a second build waits for the first, or fails straight away with `--no-wait`. Builds of different
+//! projects don't get in each other's way: their containers are named after the artifact and black_magic's process.
+
+use crate::error::BmError;
+use crate::output::status;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::fs::TryLockError;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+pub const LOCK_FILE: &str = ".lock";
+
+/// Held for the rest of the build, until this is dropped.
+pub struct ProjectLock {
+    _file: Option<File>,
+}
+
+impl ProjectLock {
+    /// Locks the project with `bm_dir`, waiting for another build to finish with it unless `no_wait`.
+    pub fn acquire(bm_dir: &Path, no_wait: bool) -> Result<ProjectLock, BmError> {
+        let path = bm_dir.join(LOCK_FILE);
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)
+            .map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", path.display(), e)))?;
+
+        let mut announced = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(ProjectLock { _file: Some(file) }),
+                Err(TryLockError::WouldBlock) => {}
+                // E.g. a filesystem without locks, where builds aren't kept apart.
+                Err(TryLockError::Error(_)) => return Ok(ProjectLock { _file: None }),
+            }
+            if no_wait {
+                return Err(BmError::Environment(format!(
+                    "Another black_magic build of this project is in progress, holding `{}`. Wait for it to finish, or leave out \
+                    `--no-wait` to queue behind it.", path.display())));
+            }
+            if !announced {
+                status!("Another black_magic build of this project is in progress, waiting for it to finish...");
+                announced = true;
+            }
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
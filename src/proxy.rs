@@ -0,0 +1,71 @@
+//! Passing the host's HTTP proxy settings on to the containers that reach the network, so cargo (and `apt`, `curl`) can get
+//! out from behind a corporate proxy.
+//!
+//! `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` (and their lowercase spellings, which some tools read instead) are passed through
+//! from the host's environment, by name so any credentials in them don't show up in the command line. A `[proxy]` section in
+//! `BlackMagic.toml` sets them instead:
+//! ```toml
+//! [proxy]
+//! http = "http://proxy.corp:3128"
+//! https = "http://proxy.corp:3128"
+//! no_proxy = "localhost,.corp"
+//! ```
+//! They're `-e` variables for the build container and `--build-arg`s for the builder image and `--layered` builds, which
+//! docker doesn't keep in the image. A proxy on the host's `localhost` isn't reachable from containers, give its LAN address.
+
+use serde::Deserialize;
+use std::env;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub http: Option<String>,
+    pub https: Option<String>,
+    pub no_proxy: Option<String>,
+}
+
+/// The variables to pass on, each with its value if it's from `[proxy]` rather than the host's environment.
+#[derive(Default)]
+pub struct Proxy {
+    variables: Vec<(&'static str, Option<String>)>,
+}
+
+impl Proxy {
+    pub fn new(config: &ProxyConfig) -> Proxy {
+        let mut variables = Vec::new();
+        for (names, configured) in [
+            (["HTTP_PROXY", "http_proxy"], &config.http),
+            (["HTTPS_PROXY", "https_proxy"], &config.https),
+            (["NO_PROXY", "no_proxy"], &config.no_proxy),
+        ] {
+            for name in names {
+                match configured {
+                    Some(value) => variables.push((name, Some(value.clone()))),
+                    None if env::var_os(name).map(|v| !v.is_empty()).unwrap_or(false) => variables.push((name, None)),
+                    None => {}
+                }
+            }
+        }
+        Proxy { variables }
+    }
+
+    fn args(&self, flag: &str) -> Vec<String> {
+        self.variables.iter().flat_map(|(name, value)| {
+            let variable = match value {
+                Some(v) => format!("{}={}", name, v),
+                None => name.to_string(),
+            };
+            vec![flag.to_owned(), variable]
+        }).collect()
+    }
+
+    /// The `-e` arguments for `docker run`.
+    pub fn run_args(&self) -> Vec<String> {
+        self.args("-e")
+    }
+
+    /// The `--build-arg` arguments for `docker build`.
+    pub fn build_args(&self) -> Vec<String> {
+        self.args("--build-arg")
+    }
+}
@@ -0,0 +1,112 @@
+//! Pushing built images to registries, exporting them for registries black_magic can't reach, and `black_magic retag`.
+//!
+//! `--export-oci <path>` saves the image as a tarball `docker load` (or `podman load`) accepts, for carrying into air-gapped
+//! environments. Docker 25 and later write it in the OCI image layout, podman is asked to.
+//!
+//! `retag` promotes an image that's already in a registry, e.g. `registry/bm_api:abc123` to `registry/bm_api:prod`, by copying
+//! its manifest within the registry. No layers are pulled or pushed, and the image doesn't need to be held locally. With docker,
+//! that's `docker buildx imagetools create`, with podman it's `skopeo copy`, which also only copies what the target lacks.
+
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use clap::ArgMatches;
+use serde_json::json;
+use std::path::Path;
+use std::process::Command;
+
+fn run(runtime: Runtime, args: &[&str]) -> Result<(), String> {
+    let output = runtime.command().args(args).output().map_err(|e| format!("Unable to run {}: {}", runtime.name(), e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("`{} {}` failed: {}", runtime.name(), args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/// Tags the local `image` as `other` too.
+pub fn tag(runtime: Runtime, image: &str, other: &str) -> Result<(), String> {
+    run(runtime, &["tag", image, other])
+}
+
+/// Tags the local `image` as `remote` and pushes it.
+pub fn push(runtime: Runtime, image: &str, remote: &str) -> Result<(), String> {
+    tag(runtime, image, remote)?;
+    run(runtime, &["push", remote])
+}
+
+/// Saves the local `image` to the tarball at `path`.
+pub fn export(runtime: Runtime, image: &str, path: &Path) -> Result<(), BmError> {
+    let mut cmd = runtime.command();
+    cmd.arg("save");
+    if runtime == Runtime::Podman {
+        cmd.arg("--format").arg("oci-archive");
+    }
+    cmd.arg("-o").arg(path).arg(image);
+    output::detail(&format!("Running {:?}", cmd));
+    let saved = cmd.output().map_err(|e| BmError::Docker(format!("Unable to run {}: {}", runtime.name(), e)))?;
+    if !saved.status.success() {
+        return Err(BmError::Docker(format!(
+            "Unable to export {} to `{}`.\n\nstderr: {}", image, path.display(), String::from_utf8_lossy(&saved.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// Splits `repo[:tag]` into the repository and tag, defaulting to `latest`.
+/// A `:` before the last `/` is a registry port, not a tag.
+pub fn split_tag(reference: &str) -> (&str, &str) {
+    match reference.rfind(':') {
+        Some(i) if !reference[i..].contains('/') => (&reference[..i], &reference[i + 1..]),
+        _ => (reference, "latest"),
+    }
+}
+
+/// A new reference for `existing`, where a bare `:tag` is another tag of the same repository.
+fn resolve_reference(existing: &str, new: &str) -> String {
+    match new.strip_prefix(':') {
+        Some(tag) => format!("{}:{}", split_tag(existing.split('@').next().unwrap_or(existing)).0, tag),
+        None => new.to_owned(),
+    }
+}
+
+/// Copies the manifest of `existing` to `new`, within the registry.
+fn copy_manifest(runtime: Runtime, existing: &str, new: &str) -> Result<(), BmError> {
+    let mut cmd = match runtime {
+        Runtime::Docker => {
+            let mut cmd = runtime.command();
+            cmd.args(["buildx", "imagetools", "create", "--tag", new, existing]);
+            cmd
+        }
+        Runtime::Podman => {
+            let mut cmd = Command::new("skopeo");
+            cmd.args(["copy", "--all"]).arg(format!("docker://{}", existing)).arg(format!("docker://{}", new));
+            cmd
+        }
+    };
+    output::detail(&format!("Running {:?}", cmd));
+    let copied = cmd.output().map_err(|e| BmError::Environment(format!(
+        "Unable to run {:?}, retagging needs {}: {}", cmd.get_program(),
+        if runtime == Runtime::Docker { "docker's buildx plugin" } else { "skopeo" }, e)))?;
+    if !copied.status.success() {
+        return Err(BmError::Publish(format!(
+            "Unable to tag {} as {}.\n\nstderr: {}", existing, new, String::from_utf8_lossy(&copied.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// The `retag` subcommand.
+pub fn retag(matches: &ArgMatches) -> Result<(), BmError> {
+    let runtime = Runtime::detect(matches.value_of("RUNTIME"))?;
+    let existing = matches.value_of("EXISTING").unwrap();
+    let tags: Vec<String> = matches.values_of("NEW").unwrap().map(|n| resolve_reference(existing, n)).collect();
+    for tag in &tags {
+        copy_manifest(runtime, existing, tag)?;
+        status!("Tagged: {}", tag);
+        output::marker("PUSHED", &[("remote", tag)]);
+    }
+    if output::is_json() {
+        output::emit("done", json!({ "source": existing, "tags": tags }));
+    }
+    Ok(())
+}
@@ -0,0 +1,225 @@
+//! The `release` subcommand: bump the crate version, build every configured artifact, then commit and tag.
+//!
+//! Builds are configured in `BlackMagic.toml`, each entry being the arguments for one black_magic build:
+//! ```toml
+//! [release]
+//! builds = ["--lambda", "--lambda --arch aarch64", "--docker"]
+//! ```
+//! Each is split into arguments as a shell would, so quoted ones can have spaces, e.g. `--features 'a b'`. Nothing is
+//! committed or tagged unless every build succeeds. If one fails, `Cargo.toml` is restored.
+
+use crate::config::Config;
+use crate::error::BmError;
+use crate::github;
+use crate::output::status;
+use clap::ArgMatches;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ReleaseConfig {
+    pub builds: Vec<String>,
+}
+
+/// Reads `package.version` from a `Cargo.toml`.
+pub fn read_version(cargo_toml: &Path) -> Option<String> {
+    let contents = fs::read_to_string(cargo_toml).ok()?;
+    let manifest: toml::Value = toml::from_str(&contents).ok()?;
+    manifest.get("package")?.get("version")?.as_str().map(|v| v.to_owned())
+}
+
+/// Bumps a semver version. Any pre-release or build metadata is dropped.
+fn bump(version: &str, level: &str) -> Option<String> {
+    let core = version.split(['-', '+']).next()?;
+    let parts: Vec<u64> = core.split('.').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    if parts.len() != 3 {
+        return None;
+    }
+
+    let (major, minor, patch) = (parts[0], parts[1], parts[2]);
+    Some(match level {
+        "major" => format!("{}.0.0", major + 1),
+        "minor" => format!("{}.{}.0", major, minor + 1),
+        _ => format!("{}.{}.{}", major, minor, patch + 1),
+    })
+}
+
+/// Replaces the `version` in the `[package]` section, leaving the rest of the file untouched.
+fn set_version(contents: &str, version: &str) -> Option<String> {
+    let mut in_package = false;
+    let mut replaced = false;
+    let mut lines = Vec::new();
+
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_package = trimmed == "[package]";
+        }
+
+        let is_version = trimmed.starts_with("version") && trimmed["version".len()..].trim_start().starts_with('=');
+        if in_package && !replaced && is_version {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            lines.push(format!("{}version = \"{}\"", indent, version));
+            replaced = true;
+        } else {
+            lines.push(line.to_owned());
+        }
+    }
+
+    if !replaced {
+        return None;
+    }
+
+    let mut updated = lines.join("\n");
+    if contents.ends_with('\n') {
+        updated.push('\n');
+    }
+    Some(updated)
+}
+
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git").args(args).output().map_err(|e| format!("Unable to run git: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        Err(format!("`git {}` failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/// Runs the `release` subcommand.
+pub fn release(matches: &ArgMatches) -> Result<(), BmError> {
+    let current_dir = crate::mounts::current_dir()?;
+    let config = Config::load(&current_dir)?;
+    let cargo_toml = current_dir.join("Cargo.toml");
+    let bm_dir = current_dir.join("target").join("black_magic");
+
+    if config.release.builds.is_empty() {
+        return Err(BmError::Environment(
+            "There's nothing to release. Add the builds to make to the `[release]` section of `BlackMagic.toml`.".to_owned()));
+    }
+
+    let status = git(&["status", "--porcelain"]).map_err(BmError::Environment)?;
+    if !status.is_empty() && !matches.is_present("ALLOW_DIRTY") {
+        return Err(BmError::Environment("The git working tree has uncommitted changes. Commit them first, or pass `--allow-dirty`.".to_owned()));
+    }
+
+    let original = fs::read_to_string(&cargo_toml).map_err(|e| BmError::Environment(format!("Unable to read `Cargo.toml`: {}", e)))?;
+    let old_version = read_version(&cargo_toml)
+        .ok_or_else(|| BmError::Environment("Unable to find `package.version` in `Cargo.toml`.".to_owned()))?;
+    let version = bump(&old_version, matches.value_of("LEVEL").unwrap())
+        .ok_or_else(|| BmError::Environment(format!("`{}` isn't a semver version black_magic can bump.", old_version)))?;
+    let tag = format!("v{}", version);
+    let updated = set_version(&original, &version)
+        .ok_or_else(|| BmError::Environment("Unable to find the `version` line in `Cargo.toml`'s `[package]` section to bump.".to_owned()))?;
+    let builds = config.release.builds.iter()
+        .map(|b| shell_words::split(b).map(|args| (b, args)))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| BmError::Environment(format!("Unable to read the release builds in `BlackMagic.toml`: {}", e)))?;
+
+    status!("Releasing {} (from {})...", version, old_version);
+    fs::write(&cargo_toml, updated)
+        .map_err(|e| BmError::Environment(format!("Unable to write `Cargo.toml`: {}", e)))?;
+
+    let started = SystemTime::now();
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    for (build, args) in &builds {
+        status!("Building `black_magic {}`...", build);
+        let build_started = SystemTime::now();
+        let status = Command::new(&exe).args(args).status();
+        let succeeded = status.as_ref().map(|s| s.success()).unwrap_or(false);
+        if !succeeded || !produced_manifest_since(&bm_dir, build_started) {
+            fs::write(&cargo_toml, &original)
+                .map_err(|e| BmError::Environment(format!("Release build `black_magic {}` failed, and `Cargo.toml` couldn't be restored: {}", build, e)))?;
+            // The build has already explained what went wrong, so just pass on what kind of failure it was.
+            let message = format!("Release build `black_magic {}` failed, `Cargo.toml` has been restored.", build);
+            return Err(BmError::from_exit_code(status.ok().and_then(|s| s.code()), message));
+        }
+    }
+
+    // Building will have updated the lockfile's entry for this package too.
+    let mut to_commit = vec!["Cargo.toml"];
+    if git(&["ls-files", "--error-unmatch", "Cargo.lock"]).is_ok() {
+        to_commit.push("Cargo.lock");
+    }
+    let mut add = vec!["add"];
+    add.extend(&to_commit);
+    let message = format!("Release {}", version);
+    let committed = git(&add)
+        .and_then(|_| git(&["commit", "-m", &message]))
+        .and_then(|_| git(&["tag", "-a", &tag, "-m", &message]))
+        .and_then(|_| git(&["rev-parse", "HEAD"]));
+    let commit = committed.map_err(BmError::Publish)?;
+
+    let manifest_paths = manifests_since(&bm_dir, started);
+    let manifests: Vec<serde_json::Value> = manifest_paths
+        .iter()
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .filter_map(|m| serde_json::from_str(&m).ok())
+        .collect();
+    let release_manifest = json!({
+        "version": version,
+        "tag": tag,
+        "commit": commit,
+        "builds": config.release.builds,
+        "artifacts": manifests,
+    });
+    let release_manifest_path = bm_dir.join(format!("release-{}.json", version));
+    fs::write(&release_manifest_path, serde_json::to_string_pretty(&release_manifest).unwrap())
+        .map_err(|e| BmError::Packaging(format!("Unable to write release manifest: {}", e)))?;
+
+    status!("Tagged {}.", tag);
+    status!("Release manifest: {}", release_manifest_path.display());
+
+    if matches.is_present("GITHUB") {
+        // Each artifact, whatever sits next to it (checksums, SBOMs), and the manifests.
+        let mut assets = Vec::new();
+        for manifest in &manifests {
+            if let Some(artifact) = manifest["artifact"].as_str() {
+                assets.extend(
+                    fs::read_dir(&bm_dir)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|e| e.ok())
+                        .map(|e| e.path())
+                        .filter(|p| p.file_name().and_then(|n| n.to_str()).map(|n| n.starts_with(artifact)).unwrap_or(false)));
+            }
+        }
+        assets.extend(manifest_paths);
+        assets.push(release_manifest_path);
+        assets.sort();
+        assets.dedup();
+
+        status!("Publishing GitHub Release {}...", tag);
+        github::publish_release(&tag, &assets).map_err(BmError::Publish)?;
+        status!("Uploaded {} assets.", assets.len());
+    }
+
+    status!("...Done! Push with `git push --follow-tags`.");
+    Ok(())
+}
+
+/// The artifact manifests written by builds since `since`.
+pub fn manifests_since(bm_dir: &Path, since: SystemTime) -> Vec<std::path::PathBuf> {
+    let mut manifests: Vec<_> = fs::read_dir(bm_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.to_string_lossy().ends_with(".manifest.json"))
+        .filter(|p| fs::metadata(p).and_then(|m| m.modified()).map(|m| m >= since).unwrap_or(false))
+        .collect();
+    manifests.sort();
+    manifests
+}
+
+/// Whether a build wrote an artifact manifest since `since`. One that exits successfully without packaging anything, e.g.
+/// with `--dry-run` or `--deps-only` in its arguments, doesn't, and can't be released.
+fn produced_manifest_since(bm_dir: &Path, since: SystemTime) -> bool {
+    !manifests_since(bm_dir, since).is_empty()
+}
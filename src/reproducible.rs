@@ -0,0 +1,37 @@
+//! `--reproducible`: the same source builds the same artifact, byte for byte, on any machine.
+//!
+//! - `SOURCE_DATE_EPOCH` is set for the compile (build scripts embedding a date use it), and every file in the artifact gets
+//!   it as its modification time
+//! - paths that differ between machines (the project directory, cargo's home) are remapped in the executable's debug info
+//! - zip and tar entries are written in sorted order, without owners, extra attributes or gzip's own timestamp
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// Zip can't represent anything earlier than 1980.
+const ZIP_EPOCH: u64 = 315_532_800;
+
+/// `SOURCE_DATE_EPOCH` if it's set already, otherwise the time of the project's last commit.
+pub fn source_date_epoch(project_dir: &Path) -> u64 {
+    let epoch = env::var("SOURCE_DATE_EPOCH").ok().and_then(|e| e.trim().parse().ok()).or_else(|| {
+        let output = Command::new("git").current_dir(project_dir).args(["log", "-1", "--format=%ct"]).output().ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    });
+    epoch.unwrap_or(ZIP_EPOCH).max(ZIP_EPOCH)
+}
+
+/// `RUSTFLAGS` remapping each `(from, to)` prefix in the paths compiled into the executable.
+pub fn remap_rustflags(prefixes: &[(&str, &str)]) -> Vec<String> {
+    prefixes.iter().map(|(from, to)| format!("--remap-path-prefix={}={}", from, to)).collect()
+}
+
+/// Shell command giving `paths` (and everything under them) the fixed modification time.
+pub fn touch_cmd(epoch: u64, paths: &str) -> String {
+    format!(" && find {} -exec touch -h -d @{} {{}} +", paths, epoch)
+}
+
+/// The `tar` options writing entries in a fixed order, with fixed times and owners.
+pub fn tar_options(epoch: u64) -> String {
+    format!(" --sort=name --mtime=@{} --owner=0 --group=0 --numeric-owner", epoch)
+}
@@ -0,0 +1,138 @@
+//! `--resource-report`: what a build cost, for tracking and trimming the cost of a fleet of builds.
+//!
+//! While the build container runs, `docker stats` is sampled for its CPU use, memory and network traffic. From those, the
+//! report has the CPU-seconds the build used, its peak memory, and the bytes it downloaded. The container also records how
+//! much was already in the cache volume and cargo's registry when it started, as the bytes of cache the build reused.
+//!
+//! Only builds in a container are measured, `--layered` builds run inside `docker build`, which has no stats to sample.
+
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct Report {
+    pub cpu_seconds: f64,
+    pub peak_memory_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub cache_reused_bytes: Option<u64>,
+}
+
+/// The build container's name, so it can be sampled (and removed on Ctrl-C, see `interrupt`).
+pub fn container_name(artifact_name: &str) -> String {
+    format!("bm_build_{}_{}", artifact_name, process::id())
+}
+
+/// Prefixed to the build command, recording the size of `dirs` before the build to `file`.
+pub fn cache_size_cmd(dirs: &[&str], file: &str) -> String {
+    format!("(du -sbc {} 2>/dev/null | tail -n 1 | cut -f 1 > {} || true) && ", dirs.join(" "), file)
+}
+
+/// Samples `docker stats` for a running container, until it's finished.
+pub struct Sampler {
+    stop: Arc<AtomicBool>,
+    sampling: JoinHandle<Report>,
+}
+
+impl Sampler {
+    pub fn start(runtime: Runtime, container: String) -> Sampler {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let sampling = thread::spawn(move || {
+            let mut report = Report::default();
+            let mut last = Instant::now();
+            while !stopped.load(Ordering::Relaxed) {
+                // Fails until the container has started, and after it's gone.
+                let sample = runtime.command()
+                    .arg("stats")
+                    .arg("--no-stream")
+                    .arg("--format")
+                    .arg("{{.CPUPerc}}|{{.MemUsage}}|{{.NetIO}}")
+                    .arg(&container)
+                    .output()
+                    .ok()
+                    .filter(|o| o.status.success())
+                    .and_then(|o| parse_sample(&String::from_utf8_lossy(&o.stdout)));
+                // The CPU percentage is of one core, averaged since the last sample.
+                let now = Instant::now();
+                if let Some((cpu, memory, downloaded)) = sample {
+                    report.cpu_seconds += cpu / 100.0 * (now - last).as_secs_f64();
+                    report.peak_memory_bytes = report.peak_memory_bytes.max(memory);
+                    report.downloaded_bytes = report.downloaded_bytes.max(downloaded);
+                } else {
+                    thread::sleep(Duration::from_millis(200));
+                }
+                last = now;
+            }
+            report
+        });
+        Sampler { stop, sampling }
+    }
+
+    /// Stops sampling, reading the cache's size from `cache_size_file` in `project_dir`.
+    pub fn finish(self, project_dir: &Path, cache_size_file: &str) -> Report {
+        self.stop.store(true, Ordering::Relaxed);
+        let mut report = self.sampling.join().unwrap_or_default();
+        let cache_size_file = project_dir.join(cache_size_file);
+        report.cache_reused_bytes = fs::read_to_string(&cache_size_file).ok().and_then(|s| s.trim().parse().ok());
+        let _ = fs::remove_file(cache_size_file);
+        report
+    }
+}
+
+/// Parses `CPU%|used / limit|received / sent`, returning the CPU percentage, memory used and bytes received.
+fn parse_sample(sample: &str) -> Option<(f64, u64, u64)> {
+    let mut fields = sample.trim().split('|');
+    let cpu = fields.next()?.trim().trim_end_matches('%').parse().ok()?;
+    let memory = parse_size(fields.next()?.split('/').next()?)?;
+    let downloaded = parse_size(fields.next()?.split('/').next()?)?;
+    Some((cpu, memory, downloaded))
+}
+
+/// Parses sizes as docker prints them, e.g. `1.5GiB` or `12.3kB`.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let multiplier = match unit.trim() {
+        "" | "B" => 1.0,
+        "kB" | "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    number.parse::<f64>().ok().map(|n| (n * multiplier) as u64)
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+impl Report {
+    pub fn print(&self) {
+        status!(
+            "Build resources: {:.1} CPU-seconds, {:.1} MiB peak memory, {:.1} MiB downloaded{}.",
+            self.cpu_seconds, mib(self.peak_memory_bytes), mib(self.downloaded_bytes),
+            self.cache_reused_bytes.map(|b| format!(", {:.1} MiB of cache reused", mib(b))).unwrap_or_default());
+        output::marker("RESOURCES", &[
+            ("cpu_seconds", &format!("{:.1}", self.cpu_seconds)), ("peak_memory", &self.peak_memory_bytes.to_string()),
+            ("downloaded", &self.downloaded_bytes.to_string()),
+            ("cache_reused", &self.cache_reused_bytes.map(|b| b.to_string()).unwrap_or_default())]);
+    }
+}
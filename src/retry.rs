@@ -0,0 +1,87 @@
+//! `--retries`: running again what failed for reasons that have nothing to do with the project, e.g. a registry timing out
+//! while the builder image's base is pulled, or crates.io while the compile downloads dependencies.
+//!
+//! Only failures that look like a network problem are retried, going by what docker and cargo print, and never once anything
+//! failed to compile. Each retry waits twice as long as the last, starting at `--retry-delay` seconds.
+
+use crate::output;
+use std::io;
+use std::process::Output;
+use std::thread;
+use std::time::Duration;
+
+/// What docker, apt, curl and cargo print when the network, not the build, is the problem.
+const TRANSIENT: &[&str] = &[
+    "TLS handshake timeout",
+    "i/o timeout",
+    "connection reset by peer",
+    "Connection reset by peer",
+    "connection refused",
+    "Connection timed out",
+    "operation timed out",
+    "net/http: request canceled",
+    "Temporary failure in name resolution",
+    "Temporary failure resolving",
+    "Could not resolve host",
+    "toomanyrequests",
+    "502 Bad Gateway",
+    "503 Service Unavailable",
+    "504 Gateway Timeout",
+    "unexpected EOF",
+    "spurious network error",
+    "failed to download",
+    "failed to update registry",
+    "failed to fetch `",
+    "Unable to update registry",
+];
+
+/// What cargo prints once the compile itself failed, which running it again won't fix.
+const PERMANENT: &[&str] = &["error[E", "could not compile", "error: linking with"];
+
+pub struct Retry {
+    retries: u32,
+    delay: Duration,
+}
+
+impl Retry {
+    /// `--retries`, waiting `--retry-delay` seconds first, 5 by default.
+    pub fn new(retries: u32, delay: Option<u64>) -> Retry {
+        Retry { retries, delay: Duration::from_secs(delay.unwrap_or(5)) }
+    }
+
+    /// Never retrying.
+    pub fn none() -> Retry {
+        Retry { retries: 0, delay: Duration::ZERO }
+    }
+
+    /// Runs `attempt` until it succeeds, fails for good, or is out of retries, returning its last output.
+    pub fn run(&self, what: &str, mut attempt: impl FnMut() -> io::Result<Output>) -> io::Result<Output> {
+        let mut delay = self.delay;
+        let mut retried = 0;
+        loop {
+            let output = attempt()?;
+            if output.status.success() || retried == self.retries || !is_transient(&output.stderr) {
+                return Ok(output);
+            }
+            retried += 1;
+            output::warning(&format!(
+                "{} failed with what looks like a network problem, retrying in {}s ({} of {}).", what, delay.as_secs(), retried, self.retries));
+            thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+}
+
+/// Whether `stderr` is from a failure that might not happen again.
+fn is_transient(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr);
+    !PERMANENT.iter().any(|p| stderr.contains(p)) && TRANSIENT.iter().any(|t| stderr.contains(t))
+}
+
+pub fn validate_retries(value: String) -> Result<(), String> {
+    value.parse::<u32>().map(|_| ()).map_err(|_| "It has to be a number of retries.".to_owned())
+}
+
+pub fn validate_delay(value: String) -> Result<(), String> {
+    value.parse::<u64>().map(|_| ()).map_err(|_| "It has to be a number of seconds.".to_owned())
+}
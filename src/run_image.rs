@@ -0,0 +1,125 @@
+//! `black_magic run`: building the project's image if anything's changed, and running it, for the edit, build, run loop
+//! without remembering image names and ports.
+//!
+//! It's a `--skip-unchanged` build, for `--docker` or the build's own options with `--args`, e.g.
+//! `black_magic run --args "--docker --features metrics"`, and then the image from it runs in the foreground, with its logs
+//! streamed, until it exits or Ctrl-C stops it. The container's removed either way. Everything after `--` is passed to the
+//! image as its arguments, e.g. `black_magic run -- --port 8080`.
+//!
+//! Each port the image exposes (with `--expose`, or `EXPOSE` in its Dockerfile template) is published on the same port of
+//! the host, unless `--publish` says which to publish. The project's `.env`, if it has one, is passed with `--env-file`,
+//! unless `--env-file` picks others; `--env` sets variables on top. A container exiting with anything but 0 fails with the
+//! test exit code.
+
+use crate::error::BmError;
+use crate::interrupt;
+use crate::mounts;
+use crate::output;
+use crate::output::status;
+use crate::preset;
+use crate::runtime::Runtime;
+use crate::state::State;
+use clap::ArgMatches;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::IsTerminal;
+use std::iter;
+use std::process;
+
+/// Passed with `--env-file` if it's there, without any others.
+const ENV_FILE: &str = ".env";
+
+/// The build's options that don't end with one image to run.
+const CONFLICTING: &[&str] = &["LAMBDA", "NO_IMAGE", "WATCH", "BUNDLE", "BINS", "PLATFORMS", "DRY_RUN", "NO_SIDE_EFFECTS", "SHELL", "DEPS_ONLY"];
+
+/// The ports `image` exposes, e.g. `8080/tcp`.
+fn exposed_ports(runtime: Runtime, image: &str) -> Result<Vec<String>, BmError> {
+    let inspected = runtime.command()
+        .args(["image", "inspect", "--format", "{{json .Config.ExposedPorts}}", image])
+        .output()
+        .map_err(|e| BmError::Docker(format!("Unable to inspect {}: {}", image, e)))?;
+    if !inspected.status.success() {
+        return Err(BmError::Docker(format!("Unable to inspect {}.\n\nstderr: {}", image, String::from_utf8_lossy(&inspected.stderr).trim())));
+    }
+    let ports: Option<BTreeMap<String, Value>> = serde_json::from_slice(&inspected.stdout).ok();
+    Ok(ports.unwrap_or_default().into_keys().collect())
+}
+
+/// `black_magic run`.
+pub fn run(matches: &ArgMatches) -> Result<(), BmError> {
+    let mut args: Vec<String> = matches.value_of("ARGS").unwrap().split_whitespace().map(|a| a.to_owned()).collect();
+    for (arg, flag) in [("RUNTIME", "--runtime"), ("NAME", "--name")] {
+        if let Some(value) = matches.value_of(arg).filter(|_| !args.iter().any(|a| a == flag)) {
+            args.push(flag.to_owned());
+            args.push(value.to_owned());
+        }
+    }
+    if !args.iter().any(|a| a == "--skip-unchanged") {
+        args.push("--skip-unchanged".to_owned());
+    }
+    let args: Vec<String> = iter::once("black_magic".to_owned()).chain(args).collect();
+    let build_matches = crate::app()
+        .get_matches_from_safe(&args)
+        .map_err(|e| BmError::Environment(format!("Invalid `--args`: {}", e)))?;
+    let build_matches = preset::apply(build_matches, args)?;
+    if build_matches.subcommand_name().is_some() {
+        return Err(BmError::Environment("`--args` are a build's options, not another subcommand.".to_owned()));
+    } else if !build_matches.is_present("DOCKER") || CONFLICTING.iter().any(|a| build_matches.is_present(a)) {
+        return Err(BmError::Environment(
+            "`black_magic run` runs a docker build's image, so its `--args` need `--docker`, and can't have `--lambda`, `--no-image`, \
+            `--watch`, `--bundle`, `--bins`, `--platforms`, `--dry-run`, `--no-side-effects`, `--shell` or `--deps-only`.".to_owned()));
+    }
+    crate::run(&build_matches)?;
+
+    let current_dir = mounts::current_dir()?;
+    let image = State::load(&current_dir.join("target").join("black_magic"))
+        .and_then(|s| s.image)
+        .map(|(image, _)| image)
+        .ok_or_else(|| BmError::Environment("The build didn't record an image to run.".to_owned()))?;
+    let runtime = Runtime::detect(build_matches.value_of("RUNTIME"))?;
+
+    let mut cmd = runtime.command();
+    let container: String = image.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect();
+    let container = format!("{}_run_{}", container, process::id());
+    cmd.arg("run").arg("--rm").arg("-i").arg("--name").arg(&container);
+    if io::stdin().is_terminal() {
+        cmd.arg("-t");
+    }
+    let published: Vec<String> = match matches.values_of("PUBLISH") {
+        Some(ports) => ports.map(|p| p.to_owned()).collect(),
+        None => exposed_ports(runtime, &image)?.iter().map(|p| match p.split_once('/') {
+            Some((port, protocol)) => format!("{}:{}/{}", port, port, protocol),
+            None => format!("{}:{}", p, p),
+        }).collect(),
+    };
+    for port in &published {
+        cmd.arg("-p").arg(port);
+    }
+    let env_files: Vec<String> = match matches.values_of("ENV_FILE") {
+        Some(files) => files.map(|f| f.to_owned()).collect(),
+        None => Some(ENV_FILE.to_owned()).filter(|f| current_dir.join(f).is_file()).into_iter().collect(),
+    };
+    for file in &env_files {
+        cmd.arg("--env-file").arg(current_dir.join(file));
+    }
+    for env in matches.values_of("ENV").into_iter().flatten() {
+        cmd.arg("-e").arg(env);
+    }
+    cmd.arg(&image).args(matches.values_of("IMAGE_ARGS").into_iter().flatten());
+
+    if published.is_empty() {
+        status!("Running {}, Ctrl-C to stop it...", image);
+    } else {
+        status!("Running {}, publishing {}, Ctrl-C to stop it...", image, published.join(", "));
+    }
+    output::detail(&format!("Running {:?}", cmd));
+    interrupt::cleanup_container(runtime, &container);
+    let ran = cmd.status().map_err(|e| BmError::Docker(format!("Unable to run {}: {}", image, e)));
+    interrupt::finished();
+    match ran?.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(BmError::Test(format!("{} exited with {}.", image, code))),
+        None => Err(BmError::Test(format!("{} was stopped by a signal.", image))),
+    }
+}
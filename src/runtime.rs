@@ -0,0 +1,185 @@
+//! The container runtime black_magic drives: docker, or podman.
+//!
+//! Picked with `--runtime`, then `BM_RUNTIME`, otherwise whichever of the two is installed (docker first).
+//! Podman's CLI is close enough to docker's that the same invocations work, apart from the quirks handled here.
+//! Without docker's CLI, a docker daemon that answers is still used, through its Engine API, see `engine`.
+
+use crate::engine;
+use crate::error::BmError;
+use crate::output;
+use std::env;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// Whether docker's commands go to its Engine API, set by `Runtime::detect` when its CLI is missing.
+static ENGINE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Runtime {
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    pub fn from_name(name: &str) -> Option<Runtime> {
+        match name {
+            "docker" => Some(Runtime::Docker),
+            "podman" => Some(Runtime::Podman),
+            _ => None,
+        }
+    }
+
+    /// The name of the runtime's executable.
+    pub fn name(self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+        }
+    }
+
+    /// Picks the runtime, preferring `requested` (from `--runtime`) over `BM_RUNTIME` over detection.
+    pub fn detect(requested: Option<&str>) -> Result<Runtime, BmError> {
+        let from_env = env::var("BM_RUNTIME").ok().filter(|r| !r.is_empty());
+        if let Some(name) = requested.map(|r| r.to_owned()).or(from_env) {
+            let runtime = Runtime::from_name(&name)
+                .ok_or_else(|| BmError::Environment(format!("`{}` isn't a supported container runtime, expected `docker` or `podman`.", name)))?;
+            if !(runtime.installed() || runtime == Runtime::Docker && Runtime::engine()) {
+                return Err(BmError::Environment(format!(
+                    "It looks like {} is not installed on your system. Running `{} --version` did not produce expected result.", name, name)));
+            }
+            return Ok(runtime);
+        }
+
+        [Runtime::Docker, Runtime::Podman]
+            .iter()
+            .copied()
+            .find(|r| r.installed())
+            .or_else(|| Some(Runtime::Docker).filter(|_| Runtime::engine()))
+            .ok_or_else(|| BmError::Environment(
+                "Neither docker nor podman is installed on your system. Install one of them, or pick one with `--runtime` or `BM_RUNTIME`.".to_owned()))
+    }
+
+    /// Whether a docker daemon answers on its API, without its CLI, so docker's commands go there from now on.
+    fn engine() -> bool {
+        match engine::version() {
+            Some(version) => {
+                output::detail(&format!(
+                    "The docker CLI isn't installed, so docker's commands go to the Docker {} daemon through its Engine API.", version));
+                ENGINE.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn installed(self) -> bool {
+        let expected: &[u8] = match self {
+            Runtime::Docker => b"Docker version",
+            Runtime::Podman => b"podman version",
+        };
+        Command::new(self.name())
+            .arg("--version")
+            .output()
+            .map(|o| o.stdout.starts_with(expected))
+            .unwrap_or(false)
+    }
+
+    /// A command running the runtime's executable, or black_magic in docker's place when it talks to its API, see `engine`.
+    pub fn command(self) -> Command {
+        match self {
+            Runtime::Docker if ENGINE.load(Ordering::Relaxed) => engine::command(),
+            _ => Command::new(self.name()),
+        }
+    }
+
+    /// Whether `image` exists locally.
+    /// Docker and podman word the "no such image" error differently, but both exit unsuccessfully.
+    pub fn image_exists(self, image: &str) -> Result<bool, BmError> {
+        let inspect = self.command()
+            .arg("image")
+            .arg("inspect")
+            .arg(image)
+            .output()
+            .map_err(|e| BmError::Docker(format!("Unable to test for `{}` image: {}", image, e)))?;
+        Ok(inspect.status.success())
+    }
+
+    /// Whether the `volume` exists, e.g. a cache volume a build has used before.
+    pub fn volume_exists(self, volume: &str) -> bool {
+        self.command().arg("volume").arg("inspect").arg(volume).output().map(|o| o.status.success()).unwrap_or(false)
+    }
+
+    /// The runtime's own description of its version, e.g. `Docker version 24.0.7, build afdd53b`.
+    pub fn version(self) -> Option<String> {
+        let output = self.command().arg("--version").output().ok().filter(|o| o.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    /// The ID of the local `image`, which changes whenever it's rebuilt.
+    pub fn image_id(self, image: &str) -> Option<String> {
+        let output = self.command()
+            .arg("image")
+            .arg("inspect")
+            .arg("--format")
+            .arg("{{.Id}}")
+            .arg(image)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_owned()).filter(|id| !id.is_empty())
+    }
+
+    /// A `build` command.
+    /// Podman defaults to OCI images, which drop docker-only instructions and that some tools (e.g. `kind load`) can't read.
+    pub fn build(self) -> Command {
+        let mut cmd = self.command();
+        cmd.arg("build");
+        if self == Runtime::Podman {
+            cmd.arg("--format").arg("docker");
+        }
+        cmd
+    }
+
+    /// The daemon's address, if it's on another machine, where nothing on this one can be mounted into containers.
+    /// Docker's is `DOCKER_HOST`, otherwise the current context's endpoint, podman's is `CONTAINER_HOST`. Local sockets,
+    /// including rootless daemons' and the VM-based runtimes', aren't remote.
+    pub fn remote_host(self) -> Option<String> {
+        let from_env = |var| env::var(var).ok().filter(|h| !h.is_empty());
+        let host = match self {
+            Runtime::Docker => from_env("DOCKER_HOST").or_else(|| {
+                let output = self.command().args(["context", "inspect", "--format", "{{.Endpoints.docker.Host}}"]).output().ok()?;
+                Some(String::from_utf8_lossy(&output.stdout).trim().to_owned()).filter(|_| output.status.success())
+            }),
+            Runtime::Podman => from_env("CONTAINER_HOST"),
+        }?;
+        Some(host).filter(|h| !h.is_empty() && !h.starts_with("unix://") && !h.starts_with("npipe://"))
+    }
+
+    /// A `-v` argument for bind mounting `host` at `container`.
+    /// Podman hosts usually run SELinux, which blocks containers reading mounts that aren't relabelled.
+    pub fn bind_mount(self, host: &str, container: &str) -> String {
+        match self {
+            Runtime::Docker => format!("{}:{}", host, container),
+            Runtime::Podman => format!("{}:{}:z", host, container),
+        }
+    }
+
+    /// Fully qualifies an image name for use in a Dockerfile.
+    /// Podman refuses ambiguous short names like `busybox` when it can't prompt for a registry.
+    pub fn qualify(self, image: &str) -> String {
+        if self == Runtime::Docker {
+            return image.to_owned();
+        }
+
+        let first = image.split('/').next().unwrap_or("");
+        let has_registry = image.contains('/') && (first.contains('.') || first.contains(':') || first == "localhost");
+        if has_registry {
+            image.to_owned()
+        } else if image.contains('/') {
+            format!("docker.io/{}", image)
+        } else {
+            format!("docker.io/library/{}", image)
+        }
+    }
+}
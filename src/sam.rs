@@ -0,0 +1,78 @@
+//! `--emit-sam`: a minimal AWS SAM template for the built zip, so `sam deploy` or `sam local start-api` work against
+//! black_magic's output straight away.
+//!
+//! It's written next to the zip as `<artifact>.template.yaml`, with the function's settings from `BlackMagic.toml`:
+//! ```toml
+//! [lambda.function]
+//! memory_size = 256
+//! timeout = 10
+//! environment = { RUST_LOG = "info" }
+//!
+//! [lambda.sam]
+//! # Adds an HTTP API sending every request to the function.
+//! api = true
+//! ```
+
+use crate::config::FunctionConfig;
+use crate::error::BmError;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SamConfig {
+    pub api: bool,
+}
+
+/// `name` as a CloudFormation logical ID, which has to be alphanumeric, e.g. `my-project` to `MyProjectFunction`.
+fn logical_id(name: &str) -> String {
+    let mut id = String::new();
+    for word in name.split(|c: char| !c.is_ascii_alphanumeric()).filter(|w| !w.is_empty()) {
+        let mut chars = word.chars();
+        id.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        id.extend(chars);
+    }
+    format!("{}Function", id)
+}
+
+/// A YAML string, quoted as JSON (which YAML accepts) so nothing in it is taken as YAML syntax.
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// The template for the zip at `zip`, run with `runtime` on `architecture` (`x86_64` or `arm64`).
+pub fn template(function: &FunctionConfig, config: &SamConfig, name: &str, zip: &str, runtime: &str, architecture: &str) -> String {
+    let mut template = format!(
+        "AWSTemplateFormatVersion: \"2010-09-09\"\n\
+        Transform: AWS::Serverless-2016-10-31\n\
+        Description: {description}\n\
+        Resources:\n  \
+          {id}:\n    \
+            Type: AWS::Serverless::Function\n    \
+            Properties:\n      \
+              CodeUri: {zip}\n      \
+              Handler: bootstrap\n      \
+              Runtime: {runtime}\n      \
+              Architectures:\n        \
+                - {architecture}\n      \
+              MemorySize: {memory}\n      \
+              Timeout: {timeout}\n",
+        description = quote(&format!("{}, built by black_magic", name)), id = logical_id(name), zip = quote(zip),
+        runtime = runtime, architecture = architecture, memory = function.memory_size, timeout = function.timeout);
+    if !function.environment.is_empty() {
+        template.push_str("      Environment:\n        Variables:\n");
+        for (key, value) in &function.environment {
+            template.push_str(&format!("          {}: {}\n", quote(key), quote(value)));
+        }
+    }
+    if config.api {
+        template.push_str("      Events:\n        Api:\n          Type: HttpApi\n");
+    }
+    template
+}
+
+/// Writes the template to `path`.
+pub fn write(path: &Path, template: &str) -> Result<(), BmError> {
+    fs::write(path, template).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", path.display(), e)))
+}
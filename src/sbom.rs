@@ -0,0 +1,193 @@
+//! `--sbom <format>`: a software bill of materials for the artifact, next to it, e.g. `target/black_magic/api.cdx.json`.
+//!
+//! It lists what's compiled into the executable: the dependencies `cargo metadata` resolves for the target, without
+//! dev-dependencies, with their licenses, their checksums from `Cargo.lock`, and which depends on which. The formats are:
+//! - `cyclonedx`: CycloneDX 1.5 JSON, as `<name>.cdx.json`
+//! - `spdx`: SPDX 2.3 JSON, as `<name>.spdx.json`
+//!
+//! It can be set in the `[build]` section of `BlackMagic.toml` too, as `sbom`. `--sbom-label` also puts it in the image, in
+//! the `dev.black_magic.sbom` label, so it goes wherever the image does. With `--out-dir`, it's delivered with the artifact.
+
+use crate::error::BmError;
+use crate::manifest;
+use crate::metadata::Metadata;
+use crate::metadata::Package;
+use crate::tags;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+pub const FORMATS: &[&str] = &["cyclonedx", "spdx"];
+
+/// `--sbom`'s format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SbomFormat {
+    CycloneDx,
+    Spdx,
+}
+
+impl SbomFormat {
+    pub fn from_name(name: &str) -> Option<SbomFormat> {
+        match name {
+            "cyclonedx" => Some(SbomFormat::CycloneDx),
+            "spdx" => Some(SbomFormat::Spdx),
+            _ => None,
+        }
+    }
+
+    /// `--sbom`'s format, or else `sbom` in `[build]`'s.
+    pub fn select(requested: Option<SbomFormat>, configured: Option<&str>) -> Result<Option<SbomFormat>, BmError> {
+        match (requested, configured) {
+            (Some(format), _) => Ok(Some(format)),
+            (None, Some(name)) => SbomFormat::from_name(name).map(Some).ok_or_else(|| BmError::Environment(format!(
+                "`sbom` in `BlackMagic.toml` is `{}`, use {}.", name, FORMATS.join(" or ")))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+const CRATES_IO_SPARSE: &str = "sparse+https://index.crates.io/";
+
+/// The image label the SBOM is put in, with `--sbom-label`.
+const LABEL: &str = "dev.black_magic.sbom";
+
+pub struct Sbom {
+    format: &'static str,
+    document: Value,
+}
+
+/// The package URL of a crate, e.g. `pkg:cargo/serde@1.0.100`.
+fn purl(package: &Package) -> String {
+    format!("pkg:cargo/{}@{}", package.name, package.version)
+}
+
+/// The SPDX element ID of a crate, which only takes letters, digits, `.` and `-`.
+fn spdx_id(package: &Package) -> String {
+    let id: String = format!("{}-{}", package.name, package.version)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' })
+        .collect();
+    format!("SPDXRef-Package-{}", id)
+}
+
+/// Where SPDX says a crate comes from: crates.io's download, or its git repository.
+fn download_location(package: &Package) -> String {
+    match package.source.as_deref() {
+        Some(CRATES_IO | CRATES_IO_SPARSE) => format!("https://crates.io/api/v1/crates/{}/{}/download", package.name, package.version),
+        Some(git) if git.starts_with("git+") => git.to_owned(),
+        _ => "NOASSERTION".to_owned(),
+    }
+}
+
+impl Sbom {
+    /// The SBOM of the project's root package, from `metadata`, in `format`. Its time is `source_date_epoch`, or now.
+    pub fn generate(format: SbomFormat, metadata: &Metadata, project_dir: &Path, source_date_epoch: Option<u64>) -> Result<Sbom, BmError> {
+        let root = metadata.resolve.as_ref()
+            .and_then(|r| r.root.as_deref())
+            .and_then(|id| metadata.package(id))
+            .ok_or_else(|| BmError::Environment("`--sbom` needs a package to describe, not a virtual workspace.".to_owned()))?;
+        let dependencies = metadata.dependencies();
+        let checksums = manifest::locked_checksums(project_dir);
+        let checksum = |p: &Package| checksums.get(&(p.name.clone(), p.version.clone())).cloned();
+        // What each package depends on, of what's in the SBOM.
+        let included: Vec<&str> = dependencies.iter().map(|p| p.id.as_str()).chain([root.id.as_str()]).collect();
+        let edges: BTreeMap<&str, Vec<&Package>> = metadata.resolve.iter()
+            .flat_map(|r| &r.nodes)
+            .filter(|n| included.contains(&n.id.as_str()))
+            .map(|n| {
+                let deps = n.deps.iter()
+                    .filter(|d| included.contains(&d.pkg.as_str()))
+                    .filter(|d| d.dep_kinds.is_empty() || d.dep_kinds.iter().any(|k| k.kind.as_deref() != Some("dev")))
+                    .filter_map(|d| metadata.package(&d.pkg))
+                    .collect();
+                (n.id.as_str(), deps)
+            })
+            .collect();
+        let epoch = source_date_epoch.unwrap_or_else(|| SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+        let created = tags::rfc3339(epoch);
+
+        let document = if format == SbomFormat::Spdx {
+            let package = |p: &Package| {
+                let mut entry = json!({
+                    "name": p.name,
+                    "SPDXID": spdx_id(p),
+                    "versionInfo": p.version,
+                    "downloadLocation": download_location(p),
+                    "filesAnalyzed": false,
+                    "licenseConcluded": "NOASSERTION",
+                    "licenseDeclared": p.license.as_deref().unwrap_or("NOASSERTION"),
+                    "externalRefs": [{ "referenceCategory": "PACKAGE-MANAGER", "referenceType": "purl", "referenceLocator": purl(p) }],
+                });
+                if let Some(c) = checksum(p) {
+                    entry["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": c }]);
+                }
+                entry
+            };
+            let packages: Vec<Value> = Some(root).into_iter().chain(dependencies.iter().copied()).map(package).collect();
+            let mut relationships = vec![json!({ "spdxElementId": "SPDXRef-DOCUMENT", "relationshipType": "DESCRIBES", "relatedSpdxElement": spdx_id(root) })];
+            for (id, deps) in &edges {
+                let from = spdx_id(metadata.package(id).unwrap());
+                relationships.extend(deps.iter().map(|d| json!({ "spdxElementId": from, "relationshipType": "DEPENDS_ON", "relatedSpdxElement": spdx_id(d) })));
+            }
+            json!({
+                "spdxVersion": "SPDX-2.3",
+                "dataLicense": "CC0-1.0",
+                "SPDXID": "SPDXRef-DOCUMENT",
+                "name": format!("{}-{}", root.name, root.version),
+                "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}-{}", root.name, root.version, epoch),
+                "creationInfo": { "created": created, "creators": [format!("Tool: black_magic-{}", env!("CARGO_PKG_VERSION"))] },
+                "packages": packages,
+                "relationships": relationships,
+            })
+        } else {
+            let component = |p: &Package, kind: &str| {
+                let mut entry = json!({ "type": kind, "bom-ref": purl(p), "name": p.name, "version": p.version, "purl": purl(p) });
+                if let Some(license) = &p.license {
+                    entry["licenses"] = json!([{ "expression": license }]);
+                }
+                if let Some(c) = checksum(p) {
+                    entry["hashes"] = json!([{ "alg": "SHA-256", "content": c }]);
+                }
+                entry
+            };
+            json!({
+                "bomFormat": "CycloneDX",
+                "specVersion": "1.5",
+                "version": 1,
+                "metadata": {
+                    "timestamp": created,
+                    "tools": { "components": [{ "type": "application", "name": "black_magic", "version": env!("CARGO_PKG_VERSION") }] },
+                    "component": component(root, "application"),
+                },
+                "components": dependencies.iter().map(|p| component(p, "library")).collect::<Vec<_>>(),
+                "dependencies": edges.iter()
+                    .map(|(id, deps)| json!({
+                        "ref": purl(metadata.package(id).unwrap()),
+                        "dependsOn": deps.iter().map(|d| purl(d)).collect::<Vec<_>>(),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        };
+        Ok(Sbom { format: if format == SbomFormat::Spdx { "spdx" } else { "cdx" }, document })
+    }
+
+    /// The SBOM's file name, for an artifact called `name`.
+    pub fn file_name(&self, name: &str) -> String {
+        format!("{}.{}.json", name, self.format)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), BmError> {
+        let json = serde_json::to_string_pretty(&self.document).expect("Unable to serialize SBOM.");
+        fs::write(path, json).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", path.display(), e)))
+    }
+
+    /// The SBOM as a `LABEL` instruction, for `--sbom-label`.
+    pub fn label(&self) -> String {
+        // As in `tags::labels`, docker expands variables in labels.
+        format!("LABEL {}={}\n", LABEL, serde_json::to_string(&self.document.to_string()).unwrap().replace('$', "\\$"))
+    }
+}
@@ -0,0 +1,299 @@
+//! `--scan`: scanning the built image for known vulnerabilities before it's pushed, with trivy or grype, and with
+//! `--scan-fail-on <severity>`, failing the build on any at or above that severity.
+//!
+//! The image is saved to `target/black_magic/scan.tar` for the scanner, which is whichever of `trivy` and `grype` is installed,
+//! trivy first, or else trivy's own image, `aquasec/trivy`, run with the runtime. Their databases are kept in the
+//! `bm_scan_cache` volume between builds. `BlackMagic.toml` can pick the scanner, set the threshold, and ignore findings
+//! that don't apply, e.g. in code the executable never runs:
+//! ```toml
+//! [scan]
+//! scanner = "grype"
+//! fail_on = "high"
+//! ignore = ["CVE-2023-12345"]
+//! ```
+//! `--scan-fail-on` replaces `fail_on`, and with either, the image is scanned without `--scan`. The summary counts the
+//! findings by severity, listing the most severe; they're all in the `scan` event with `--output-format json`.
+
+use crate::error::BmError;
+use crate::interrupt;
+use crate::output;
+use crate::output::status;
+use crate::runtime::Runtime;
+use crate::scheduler;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// The thresholds `--scan-fail-on` takes, from the least severe.
+pub const SEVERITIES: &[&str] = &["low", "medium", "high", "critical"];
+
+/// The findings listed in the summary, from the most severe.
+const ROWS: usize = 10;
+
+const CACHE_VOLUME: &str = "bm_scan_cache";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ScanConfig {
+    pub scanner: Option<String>,
+    pub fail_on: Option<String>,
+    pub ignore: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scanner {
+    Trivy,
+    Grype,
+}
+
+impl Scanner {
+    fn name(self) -> &'static str {
+        match self {
+            Scanner::Trivy => "trivy",
+            Scanner::Grype => "grype",
+        }
+    }
+
+    fn image(self) -> &'static str {
+        match self {
+            Scanner::Trivy => "aquasec/trivy",
+            Scanner::Grype => "anchore/grype",
+        }
+    }
+
+    fn installed(self) -> bool {
+        Command::new(self.name()).arg("--version").output().map(|o| o.status.success()).unwrap_or(false)
+    }
+
+    /// The scanner's arguments, for a JSON report on `archive`, as `docker save` writes it.
+    fn args(self, archive: &str, cache_dir: Option<&str>) -> Vec<String> {
+        let mut args: Vec<String> = match self {
+            Scanner::Trivy => ["image", "--quiet", "--format", "json", "--input", archive].iter().map(|a| a.to_string()).collect(),
+            Scanner::Grype => vec![format!("docker-archive:{}", archive), "-o".to_owned(), "json".to_owned(), "-q".to_owned()],
+        };
+        if let (Scanner::Trivy, Some(cache_dir)) = (self, cache_dir) {
+            args.push("--cache-dir".to_owned());
+            args.push(format!("{}/trivy", cache_dir));
+        }
+        args
+    }
+
+    /// The findings in the scanner's JSON `report`.
+    fn findings(self, report: &Value) -> Vec<Finding> {
+        let text = |v: &Value| v.as_str().unwrap_or_default().to_owned();
+        match self {
+            Scanner::Trivy => report["Results"].as_array().into_iter().flatten()
+                .flat_map(|r| r["Vulnerabilities"].as_array().into_iter().flatten())
+                .map(|v| Finding {
+                    id: text(&v["VulnerabilityID"]),
+                    package: text(&v["PkgName"]),
+                    version: text(&v["InstalledVersion"]),
+                    fixed: v["FixedVersion"].as_str().filter(|f| !f.is_empty()).map(|f| f.to_owned()),
+                    severity: rank(&text(&v["Severity"])),
+                })
+                .collect(),
+            Scanner::Grype => report["matches"].as_array().into_iter().flatten()
+                .map(|m| Finding {
+                    id: text(&m["vulnerability"]["id"]),
+                    package: text(&m["artifact"]["name"]),
+                    version: text(&m["artifact"]["version"]),
+                    fixed: m["vulnerability"]["fix"]["versions"].as_array()
+                        .and_then(|v| v.first())
+                        .and_then(|v| v.as_str())
+                        .map(|f| f.to_owned()),
+                    severity: rank(&text(&m["vulnerability"]["severity"])),
+                })
+                .collect(),
+        }
+    }
+}
+
+struct Finding {
+    id: String,
+    package: String,
+    version: String,
+    fixed: Option<String>,
+    /// See `rank`.
+    severity: usize,
+}
+
+/// How severe `severity` is: 0 for unknown or negligible, then 1 for each of `SEVERITIES`.
+fn rank(severity: &str) -> usize {
+    SEVERITIES.iter().position(|s| s.eq_ignore_ascii_case(severity)).map(|p| p + 1).unwrap_or(0)
+}
+
+fn severity_name(rank: usize) -> &'static str {
+    if rank == 0 { "unknown" } else { SEVERITIES[rank - 1] }
+}
+
+/// `--scan-fail-on`'s threshold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// In any case, as the scanners' reports have them.
+    pub fn from_name(name: &str) -> Option<Severity> {
+        match rank(name) {
+            1 => Some(Severity::Low),
+            2 => Some(Severity::Medium),
+            3 => Some(Severity::High),
+            4 => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+
+    /// See `rank`.
+    fn rank(self) -> usize {
+        self as usize + 1
+    }
+}
+
+pub fn validate_severity(value: String) -> Result<(), String> {
+    if rank(&value) > 0 {
+        Ok(())
+    } else {
+        Err(format!("It has to be one of: {}.", SEVERITIES.join(", ")))
+    }
+}
+
+pub struct Scan {
+    /// The configured scanner, or else the first installed.
+    scanner: Option<Scanner>,
+    /// The least severe finding that fails the build.
+    fail_on: Option<usize>,
+    ignore: Vec<String>,
+}
+
+impl Scan {
+    /// From `--scan` (`enabled`) and `--scan-fail-on`, or `[scan]`, if the image is to be scanned.
+    pub fn new(config: &ScanConfig, enabled: bool, fail_on: Option<Severity>) -> Result<Option<Scan>, BmError> {
+        let fail_on = match (fail_on, config.fail_on.as_deref()) {
+            (Some(f), _) => Some(f),
+            (None, Some(f)) => Some(Severity::from_name(f).ok_or_else(|| BmError::Environment(format!(
+                "`fail_on` in `[scan]` has `{}`, use one of: {}.", f, SEVERITIES.join(", "))))?),
+            (None, None) => None,
+        };
+        if !enabled && fail_on.is_none() {
+            return Ok(None);
+        }
+        let fail_on = fail_on.map(Severity::rank);
+        let scanner = match config.scanner.as_deref() {
+            Some("trivy") => Some(Scanner::Trivy),
+            Some("grype") => Some(Scanner::Grype),
+            Some(other) => return Err(BmError::Environment(format!("`scanner` in `[scan]` has `{}`, use `trivy` or `grype`.", other))),
+            None => None,
+        };
+        Ok(Some(Scan { scanner, fail_on, ignore: config.ignore.clone() }))
+    }
+
+    /// What the scan does, for `--dry-run`.
+    pub fn describe(&self) -> String {
+        let scanner = self.scanner.map(|s| s.name()).unwrap_or("trivy or grype");
+        match self.fail_on {
+            Some(fail_on) => format!("Scan it for vulnerabilities with {}, failing on any {} or above", scanner, severity_name(fail_on)),
+            None => format!("Scan it for vulnerabilities with {}", scanner),
+        }
+    }
+
+    /// The scanner's command for `archive`, in `bm_dir`: the installed one, or else its image.
+    fn command(&self, runtime: Runtime, bm_dir: &Path, archive: &str) -> Result<(Scanner, Command), BmError> {
+        let installed = match self.scanner {
+            Some(scanner) => Some(scanner).filter(|s| s.installed()),
+            None => [Scanner::Trivy, Scanner::Grype].iter().copied().find(|s| s.installed()),
+        };
+        if let Some(scanner) = installed {
+            let mut cmd = Command::new(scanner.name());
+            cmd.args(scanner.args(&bm_dir.join(archive).display().to_string(), None));
+            return Ok((scanner, cmd));
+        }
+        let scanner = self.scanner.unwrap_or(Scanner::Trivy);
+        let bm_dir = bm_dir.to_str().ok_or_else(|| BmError::Environment(format!("`{}` isn't valid UTF-8.", bm_dir.display())))?;
+        let mut cmd = runtime.command();
+        cmd.arg("run").arg("--rm")
+            .arg("-v").arg(runtime.bind_mount(bm_dir, "/scan"))
+            .arg("-v").arg(format!("{}:/cache", CACHE_VOLUME))
+            .arg("-e").arg("GRYPE_DB_CACHE_DIR=/cache/grype")
+            .arg(runtime.qualify(scanner.image()))
+            .args(scanner.args(&format!("/scan/{}", archive), Some("/cache")));
+        Ok((scanner, cmd))
+    }
+
+    /// Scans `image`, failing if it has findings at or above the threshold.
+    pub fn run(&self, runtime: Runtime, bm_dir: &Path, image: &str) -> Result<(), BmError> {
+        let archive = scheduler::job_file("scan.tar");
+        let path = bm_dir.join(&archive);
+        interrupt::cleanup_file(path.clone());
+        let mut save = runtime.command();
+        save.arg("save").arg("-o").arg(&path).arg(image);
+        output::detail(&format!("Running {:?}", save));
+        let saved = save.output().map_err(|e| BmError::Docker(format!("Unable to run {}: {}", runtime.name(), e)))?;
+        if !saved.status.success() {
+            return Err(BmError::Docker(format!(
+                "Unable to save {} for the scan.\n\nstderr: {}", image, String::from_utf8_lossy(&saved.stderr).trim())));
+        }
+
+        let (scanner, mut cmd) = self.command(runtime, bm_dir, &archive)?;
+        output::detail(&format!("Running {:?}", cmd));
+        let scanned = cmd.output();
+        let _ = fs::remove_file(&path);
+        let scanned = scanned.map_err(|e| BmError::Environment(format!("Unable to run {}: {}", scanner.name(), e)))?;
+        if !scanned.status.success() {
+            return Err(BmError::Test(format!(
+                "{} was unable to scan {}.\n\nstderr: {}", scanner.name(), image, String::from_utf8_lossy(&scanned.stderr).trim())));
+        }
+        let report: Value = serde_json::from_slice(&scanned.stdout)
+            .map_err(|e| BmError::Test(format!("Unable to read {}'s report: {}", scanner.name(), e)))?;
+
+        let mut findings: Vec<Finding> = scanner.findings(&report).into_iter().filter(|f| !self.ignore.contains(&f.id)).collect();
+        findings.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.id.cmp(&b.id)));
+        findings.dedup_by(|a, b| a.id == b.id && a.package == b.package);
+        let count = |rank: usize| findings.iter().filter(|f| f.severity == rank).count();
+        let counts: Vec<(&str, usize)> = (0..=SEVERITIES.len()).rev().map(|r| (severity_name(r), count(r))).collect();
+        let failing: Vec<&Finding> = findings.iter().filter(|f| self.fail_on.map(|t| f.severity >= t).unwrap_or(false)).collect();
+
+        if findings.is_empty() {
+            status!("{} found no vulnerabilities.", scanner.name());
+        } else {
+            let summary: Vec<String> = counts.iter().filter(|(_, n)| *n > 0).map(|(s, n)| format!("{} {}", n, s)).collect();
+            status!("{} found {} vulnerabilities: {}.", scanner.name(), findings.len(), summary.join(", "));
+            let width = findings.iter().take(ROWS).map(|f| f.id.len()).max().unwrap_or(0);
+            for finding in findings.iter().take(ROWS) {
+                let fixed = finding.fixed.as_ref().map(|f| format!(", fixed in {}", f)).unwrap_or_default();
+                status!("    {:8}  {:width$}  {} {}{}", severity_name(finding.severity), finding.id, finding.package, finding.version, fixed, width = width);
+            }
+            if findings.len() > ROWS {
+                status!("    and {} more.", findings.len() - ROWS);
+            }
+        }
+
+        if output::is_json() {
+            output::emit("scan", json!({
+                "image": image,
+                "scanner": scanner.name(),
+                "counts": counts.iter().map(|(s, n)| (s.to_string(), json!(n))).collect::<serde_json::Map<String, Value>>(),
+                "fail_on": self.fail_on.map(severity_name),
+                "findings": findings.iter().map(|f| json!({
+                    "id": f.id, "package": f.package, "version": f.version, "fixed": f.fixed, "severity": severity_name(f.severity),
+                })).collect::<Vec<_>>(),
+            }));
+        }
+
+        if failing.is_empty() {
+            Ok(())
+        } else {
+            let ids: Vec<&str> = failing.iter().map(|f| f.id.as_str()).collect();
+            Err(BmError::Test(format!(
+                "The image has {} vulnerabilities that are {} or above: {}\n\nIf they don't apply, add them to `ignore` in the `[scan]` \
+                section of `BlackMagic.toml`.",
+                failing.len(), severity_name(self.fail_on.unwrap_or(0)), ids.join(", "))))
+        }
+    }
+}
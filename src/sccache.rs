@@ -0,0 +1,105 @@
+//! `--sccache`: compiling through sccache in the build container, so compiled crates are shared across projects and machines
+//! rather than just between builds of one project (which the cache volume already does).
+//!
+//! Builder images come with sccache installed, and older ones get it installed at the start of the build. Where it keeps its
+//! cache is set in `BlackMagic.toml`, by default a directory on the host mounted into the container:
+//! ```toml
+//! [sccache]
+//! dir = "~/.cache/sccache"
+//! # or, shared by CI runners:
+//! bucket = "my-build-cache"
+//! prefix = "sccache/"
+//! # or:
+//! redis = "redis://cache.internal:6379"
+//! ```
+//! With a bucket, the host's `AWS_*` credentials are passed into the container (but never written anywhere), and the region
+//! comes from `--region` or `[aws]`.
+
+use crate::cas;
+use crate::error::BmError;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+const VERSION: &str = "v0.8.1";
+
+/// Where the host's cache directory is mounted.
+pub const CONTAINER_DIR: &str = "/bm_sccache";
+
+/// The host's environment variables passed through to the container for the S3 backend.
+pub const AWS_VARIABLES: &[&str] = &["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY", "AWS_SESSION_TOKEN", "AWS_REGION", "AWS_DEFAULT_REGION"];
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SccacheConfig {
+    /// A host directory, absolute or under `~/`.
+    pub dir: Option<String>,
+    pub bucket: Option<String>,
+    /// Prepended to the keys in `bucket`.
+    pub prefix: Option<String>,
+    pub redis: Option<String>,
+}
+
+/// Where sccache keeps what it compiles.
+pub enum Storage {
+    Local(PathBuf),
+    S3 { bucket: String, prefix: Option<String> },
+    Redis(String),
+}
+
+impl SccacheConfig {
+    pub fn storage(&self) -> Result<Storage, BmError> {
+        match (&self.dir, &self.bucket, &self.redis) {
+            (None, Some(bucket), None) => Ok(Storage::S3 { bucket: bucket.clone(), prefix: self.prefix.clone() }),
+            (None, None, Some(redis)) => Ok(Storage::Redis(redis.clone())),
+            (Some(dir), None, None) => {
+                let dir = match dir.strip_prefix("~/") {
+                    Some(rest) => home::home_dir().map(|h| h.join(rest)),
+                    None => Some(PathBuf::from(dir)).filter(|d| d.is_absolute()),
+                };
+                dir.map(Storage::Local).ok_or_else(|| BmError::Environment(
+                    "`dir` in the `[sccache]` section of `BlackMagic.toml` has to be an absolute path, or start with `~/`.".to_owned()))
+            }
+            (None, None, None) => cas::cache_dir()
+                .map(|d| Storage::Local(d.join("sccache")))
+                .ok_or_else(|| BmError::Environment("Unable to find a cache directory for sccache, set `dir` in `[sccache]`.".to_owned())),
+            _ => Err(BmError::Environment(
+                "Only one of `dir`, `bucket` and `redis` can be set in the `[sccache]` section of `BlackMagic.toml`.".to_owned())),
+        }
+    }
+}
+
+impl Storage {
+    /// The build container's environment, compiling through sccache.
+    pub fn env(&self, region: Option<&str>) -> Vec<(&'static str, String)> {
+        // sccache can't cache incremental compilation, so cargo shouldn't ask for it.
+        let mut env = vec![("RUSTC_WRAPPER", "sccache".to_owned()), ("CARGO_INCREMENTAL", "0".to_owned())];
+        match self {
+            Storage::Local(_) => env.push(("SCCACHE_DIR", CONTAINER_DIR.to_owned())),
+            Storage::S3 { bucket, prefix } => {
+                env.push(("SCCACHE_BUCKET", bucket.clone()));
+                if let Some(p) = prefix {
+                    env.push(("SCCACHE_S3_KEY_PREFIX", p.clone()));
+                }
+                if let Some(r) = region {
+                    env.push(("SCCACHE_REGION", r.to_owned()));
+                }
+            }
+            Storage::Redis(url) => env.push(("SCCACHE_REDIS_ENDPOINT", url.clone())),
+        }
+        env
+    }
+}
+
+/// Shell command installing sccache for `target`'s architecture into `/usr/local/bin`.
+pub fn install_cmd(target: &str) -> String {
+    let arch = target.split('-').next().unwrap_or("x86_64");
+    let name = format!("sccache-{}-{}-unknown-linux-musl", VERSION, arch);
+    format!(
+        "curl -sSfL https://github.com/mozilla/sccache/releases/download/{}/{}.tar.gz | tar -xz --strip-components=1 -C /usr/local/bin {}/sccache",
+        VERSION, name, name)
+}
+
+/// Wraps the build container's `build_cmd` so sccache is there first, and has finished writing to its cache afterwards.
+pub fn wrap_cmd(build_cmd: &str, target: &str) -> String {
+    format!("(command -v sccache > /dev/null || {}) && {} && sccache --stop-server > /dev/null", install_cmd(target), build_cmd)
+}
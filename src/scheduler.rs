@@ -0,0 +1,160 @@
+//! Running several builds of the project at once, for `--bins`, `--bundle` and `--platforms` with `--parallel <n>`.
+//!
+//! Each build is black_magic run again, as it is without `--parallel`, and at most `n` run at a time. Their progress is
+//! printed as it comes, each line prefixed with the build's name, e.g. `[worker] Compiling project to lambda zip...`, and a
+//! table of how each one went is printed once they've all finished. A failed build doesn't stop the others.
+//!
+//! This process holds the project's lock (see `project_lock`) for them. The builds share `target/black_magic`, so each one
+//! names what the others would write over after itself: `last_build.<name>.json`, the image's `Dockerfile.<name>`, and the
+//! `layered.<name>` build context.
+
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use crate::project_lock::ProjectLock;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::env;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+/// Set for each build, to its name.
+const JOB_ENV: &str = "BM_JOB";
+
+/// The name of the build this is, when it's one of several running at once.
+pub fn job() -> Option<String> {
+    env::var(JOB_ENV).ok().filter(|j| !j.is_empty())
+}
+
+/// `name` for a file in `target/black_magic` the other builds running at once would write too.
+pub fn job_file(name: &str) -> String {
+    match job() {
+        Some(job) => format!("{}.{}", name, job),
+        None => name.to_owned(),
+    }
+}
+
+pub fn validate_parallel(value: String) -> Result<(), String> {
+    value.parse::<usize>().ok().filter(|n| *n > 0).map(|_| ()).ok_or_else(|| "It has to be a number of builds.".to_owned())
+}
+
+/// A build to run: black_magic with `--output-format json`, whose last line is its `done` or `error` record.
+pub struct Job {
+    pub name: String,
+    pub cmd: Command,
+    /// What the error says happened if it fails, e.g. "Building worker failed".
+    pub failure: String,
+}
+
+pub struct Finished {
+    pub name: String,
+    pub result: Result<Value, BmError>,
+    pub seconds: f64,
+}
+
+/// Runs `job`, printing its progress prefixed with its name when `prefixed`.
+fn run_job(mut job: Job, prefixed: bool) -> Finished {
+    let started = Instant::now();
+    let prefix = if prefixed { format!("[{}] ", job.name) } else { String::new() };
+    let child = job.cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => return Finished {
+            name: job.name,
+            result: Err(BmError::Environment(format!("Unable to run black_magic: {}", e))),
+            seconds: started.elapsed().as_secs_f64(),
+        },
+    };
+    let (stdout, stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+    let (last_line, stderr) = thread::scope(|s| {
+        let stderr = s.spawn(|| {
+            let mut collected = String::new();
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if prefixed {
+                    output::warning(&format!("{}{}", prefix, line));
+                }
+                collected.push_str(&line);
+                collected.push('\n');
+            }
+            collected
+        });
+        let mut last_line = String::new();
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let record: Value = serde_json::from_str(&line).unwrap_or(Value::Null);
+            if let (true, Some(message)) = (prefixed, record["message"].as_str().filter(|_| record["event"] == "progress")) {
+                status!("{}{}", prefix, message);
+            }
+            last_line = line;
+        }
+        (last_line, stderr.join().unwrap_or_default())
+    });
+    let status = child.wait();
+    let record: Value = serde_json::from_str(&last_line).unwrap_or(Value::Null);
+    let result = match status {
+        Ok(status) if status.success() => Ok(record),
+        Ok(status) => {
+            let message = record["message"].as_str().map(|m| m.to_owned()).unwrap_or(stderr);
+            Err(BmError::from_exit_code(status.code(), format!("{}: {}", job.failure, message)))
+        }
+        Err(e) => Err(BmError::Environment(format!("Unable to run black_magic: {}", e))),
+    };
+    Finished { name: job.name, result, seconds: started.elapsed().as_secs_f64() }
+}
+
+/// Runs `jobs`, `parallel` at a time, returning how each went in the same order. With more than one at a time, this holds
+/// the lock on the project with `bm_dir` for them.
+pub fn run(jobs: Vec<Job>, parallel: usize, bm_dir: &Path, no_wait: bool) -> Result<Vec<Finished>, BmError> {
+    if parallel <= 1 {
+        return Ok(jobs.into_iter().map(|job| {
+            status!("Building {}...", job.name);
+            run_job(job, false)
+        }).collect());
+    }
+
+    let _lock = ProjectLock::acquire(bm_dir, no_wait)?;
+    let count = jobs.len();
+    let queue: Mutex<VecDeque<(usize, Job)>> = Mutex::new(jobs.into_iter().enumerate().map(|(i, mut job)| {
+        job.cmd.env(JOB_ENV, &job.name);
+        (i, job)
+    }).collect());
+    let finished: Mutex<Vec<Option<Finished>>> = Mutex::new((0..count).map(|_| None).collect());
+    thread::scope(|s| {
+        for _ in 0..parallel.min(count) {
+            s.spawn(|| loop {
+                let next = queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+                let (i, job) = match next {
+                    Some(n) => n,
+                    None => break,
+                };
+                status!("[{}] Building...", job.name);
+                let done = run_job(job, true);
+                finished.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(done);
+            });
+        }
+    });
+    Ok(finished.into_inner().unwrap_or_else(|e| e.into_inner()).into_iter().flatten().collect())
+}
+
+/// Prints a table of how each build went, with `built` describing a successful one's artifact.
+pub fn summary(finished: &[Finished], built: impl Fn(&Value) -> String) {
+    let width = finished.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    status!("Builds:");
+    for f in finished {
+        let (outcome, detail) = match &f.result {
+            Ok(record) => ("built", built(record)),
+            Err(e) => ("failed", e.to_string().lines().next().unwrap_or("").to_owned()),
+        };
+        status!("    {:width$}  {:6}  {:>7.1}s  {}", f.name, outcome, f.seconds, detail, width = width);
+    }
+}
+
+/// The first failure of `finished`, if any.
+pub fn first_error(finished: Vec<Finished>) -> Result<Vec<(String, Value)>, BmError> {
+    finished.into_iter().map(|Finished { name, result, .. }| result.map(|r| (name, r))).collect()
+}
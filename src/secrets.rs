@@ -0,0 +1,70 @@
+//! `--secret id=<name>,src=<path>`: files the compile needs but the artifact, image layers and logs mustn't keep, e.g. a
+//! registry token or a `.netrc`.
+//!
+//! Builds in a container get the file mounted read-only, for that container only. `--layered` builds (and `--strategy
+//! dockerfile`, and remote daemons) pass it to `docker build --secret`, and mount it into just the steps compiling, as
+//! BuildKit secret mounts, which are never part of a layer. Either way it's at `/run/secrets/<id>`, or `target=<path>`.
+
+use crate::error::BmError;
+use crate::mounts;
+use crate::runtime::Runtime;
+use std::path::Path;
+use std::path::PathBuf;
+
+pub struct Secret {
+    id: String,
+    src: PathBuf,
+    /// Where it is inside the build container.
+    target: String,
+}
+
+impl Secret {
+    /// Parses `id=<name>,src=<path>[,target=<path>]`, like BuildKit's own `--secret`.
+    pub fn parse(value: &str) -> Result<Secret, String> {
+        let (mut id, mut src, mut target) = (None, None, None);
+        for field in value.split(',') {
+            match field.split_once('=') {
+                Some(("id", v)) => id = Some(v),
+                Some(("src" | "source", v)) => src = Some(v),
+                Some(("target" | "dst", v)) => target = Some(v),
+                _ => return Err(format!("`{}` isn't a secret, use e.g. `id=netrc,src=.netrc`.", value)),
+            }
+        }
+        let id = id.filter(|i| !i.is_empty() && i.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)))
+            .ok_or_else(|| format!("The secret `{}` needs an `id`, of letters, digits, `_`, `.` and `-`.", value))?;
+        let src = src.filter(|s| !s.is_empty()).ok_or_else(|| format!("The secret `{}` needs a `src` file.", value))?;
+        Ok(Secret {
+            id: id.to_owned(),
+            src: PathBuf::from(src),
+            target: target.map(|t| t.to_owned()).unwrap_or_else(|| format!("/run/secrets/{}", id)),
+        })
+    }
+
+    /// The file, relative to `project_dir` unless it's absolute, checked to be there.
+    fn source(&self, project_dir: &Path) -> Result<PathBuf, BmError> {
+        let src = project_dir.join(&self.src);
+        if !src.is_file() {
+            return Err(BmError::Environment(format!("The `{}` secret's file `{}` doesn't exist.", self.id, src.display())));
+        }
+        Ok(src)
+    }
+
+    /// The read-only volume for the build container.
+    pub fn volume(&self, runtime: Runtime, project_dir: &Path) -> Result<String, BmError> {
+        Ok(format!("{}:ro", mounts::volume(runtime, &self.source(project_dir)?, &self.target)?))
+    }
+
+    /// The `docker build` arguments passing it to BuildKit.
+    pub fn build_args(&self, project_dir: &Path) -> Result<[String; 2], BmError> {
+        Ok(["--secret".to_owned(), format!("id={},src={}", self.id, self.source(project_dir)?.display())])
+    }
+
+    /// The BuildKit mount for the `RUN` steps compiling, see `layered`.
+    pub fn mount(&self) -> String {
+        format!("type=secret,id={},target={}", self.id, self.target)
+    }
+}
+
+pub fn validate(value: String) -> Result<(), String> {
+    Secret::parse(&value).map(|_| ())
+}
@@ -0,0 +1,329 @@
+//! `black_magic self-update`: replacing the running black_magic with the newest release's, and builds saying when there's one.
+//!
+//! Releases come from the project's GitHub Releases, or its GitLab ones with `--source gitlab`, of `peterreeves/black_magic`
+//! unless `--repo` says otherwise. `--channel stable` (the default) takes the newest release that isn't a pre-release,
+//! `nightly` the newest of all. A release is a pre-release if the forge says so, or its version has a pre-release part (e.g.
+//! `v0.3.0-nightly.20261014`).
+//!
+//! The release needs an asset for this platform, `black_magic-<target>` (e.g. `black_magic-x86_64-unknown-linux-musl`, or
+//! `-gnu` if there's no musl one) as the executable itself or a `.tar.gz` of it, and its `.sha256` next to it, as black_magic
+//! writes for its own artifacts. What's downloaded is checked against that, and against its signature (`.minisig` or
+//! `.sigstore.json`) with `--public-key`, or `--identity` and `--issuer`, as `black_magic verify` checks one. Without them a
+//! signature isn't checked, as there's nobody to check it's from. The new executable has to run before it replaces this
+//! one, which is swapped in place, so a failed update leaves it as it was. `--check` only says whether there's a newer one.
+//!
+//! Builds look for a newer stable release at most once a day, remembered in `~/.cache/black_magic/update-check.json`, and
+//! print a notice when there is one: not in CI (with `CI` set), with `--output-format json` or `--porcelain`, or with
+//! `BLACK_MAGIC_NO_UPDATE_CHECK` set. Looking takes at most a couple of seconds, and a build never fails over it.
+
+use crate::cas;
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use crate::sign;
+use clap::ArgMatches;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::process::Command;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Where releases come from, without `--repo`.
+pub const DEFAULT_REPO: &str = "peterreeves/black_magic";
+
+pub const SOURCES: &[&str] = &["github", "gitlab"];
+
+pub const CHANNELS: &[&str] = &["stable", "nightly"];
+
+/// The version of black_magic running.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Set to anything to stop builds looking for a newer release.
+const NO_CHECK_VAR: &str = "BLACK_MAGIC_NO_UPDATE_CHECK";
+
+const CHECK_FILE: &str = "update-check.json";
+
+/// How often builds look, in seconds.
+const CHECK_INTERVAL: u64 = 24 * 60 * 60;
+
+struct Release {
+    tag: String,
+    prerelease: bool,
+    /// By name, with where to download them.
+    assets: Vec<(String, String)>,
+}
+
+impl Release {
+    /// The tag, without a leading `v`.
+    fn version(&self) -> &str {
+        self.tag.strip_prefix('v').unwrap_or(&self.tag)
+    }
+
+    fn asset(&self, name: &str) -> Option<&str> {
+        self.assets.iter().find(|(n, _)| n == name).map(|(_, url)| url.as_str())
+    }
+}
+
+/// When builds last looked, and what they found.
+#[derive(Serialize, Deserialize)]
+struct Check {
+    checked_at: u64,
+    latest: Option<String>,
+}
+
+/// Orders versions: by their numbers, then a release after its pre-releases, which are ordered by name.
+fn version_key(version: &str) -> (Vec<u64>, bool, String) {
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+    let numbers = core.split('.').map(|n| n.parse().unwrap_or(0)).collect();
+    (numbers, pre.is_none(), pre.unwrap_or("").to_owned())
+}
+
+fn is_newer(version: &str, than: &str) -> bool {
+    version_key(version) > version_key(than)
+}
+
+/// GETs `url` with `curl`, giving up after `timeout` seconds.
+fn get(url: &str, timeout: u32) -> Result<Vec<u8>, String> {
+    let mut curl = Command::new("curl");
+    curl.args(["-sS", "-f", "-L", "--max-time", &timeout.to_string(), "-H", "Accept: application/json"]);
+    // Anonymous requests to GitHub's API are rate limited per address, which CI runners share.
+    if let Some(token) = env::var("GH_TOKEN").or_else(|_| env::var("GITHUB_TOKEN")).ok().filter(|t| !t.is_empty() && url.starts_with("https://api.github.com/")) {
+        curl.arg("-H").arg(format!("Authorization: Bearer {}", token));
+    }
+    let got = curl.arg(url).output().map_err(|e| format!("unable to run curl: {}", e))?;
+    if got.status.success() {
+        Ok(got.stdout)
+    } else {
+        Err(String::from_utf8_lossy(&got.stderr).trim().to_owned())
+    }
+}
+
+/// Downloads `url` to `path`.
+fn download(url: &str, path: &Path) -> Result<(), BmError> {
+    let downloaded = Command::new("curl")
+        .args(["-sS", "-f", "-L", "--max-time", "300", "-o"])
+        .arg(path)
+        .arg(url)
+        .output()
+        .map_err(|e| BmError::Environment(format!("Unable to run curl: {}. Is it installed?", e)))?;
+    if !downloaded.status.success() {
+        return Err(BmError::Publish(format!("Unable to download {}: {}", url, String::from_utf8_lossy(&downloaded.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// The releases of `repo` on `source`, newest first as the forge lists them.
+fn releases(source: &str, repo: &str, timeout: u32) -> Result<Vec<Release>, String> {
+    let url = if source == "gitlab" {
+        let encoded: String = repo.chars().map(|c| if c == '/' { "%2F".to_owned() } else { c.to_string() }).collect();
+        format!("https://gitlab.com/api/v4/projects/{}/releases", encoded)
+    } else {
+        format!("https://api.github.com/repos/{}/releases", repo)
+    };
+    let listed: Value = serde_json::from_slice(&get(&url, timeout)?).map_err(|e| format!("unexpected response from {}: {}", url, e))?;
+    let listed = listed.as_array().ok_or_else(|| format!("unexpected response from {}", url))?;
+    let text = |v: &Value, key: &str| v[key].as_str().unwrap_or("").to_owned();
+    let releases = listed.iter().filter(|r| r["draft"] != true && r["upcoming_release"] != true).map(|r| {
+        let assets: Vec<(String, String)> = if source == "gitlab" {
+            let links = r["assets"]["links"].as_array().into_iter().flatten();
+            links.map(|l| (text(l, "name"), Some(text(l, "direct_asset_url")).filter(|u| !u.is_empty()).unwrap_or_else(|| text(l, "url")))).collect()
+        } else {
+            r["assets"].as_array().into_iter().flatten().map(|a| (text(a, "name"), text(a, "browser_download_url"))).collect()
+        };
+        let tag = text(r, "tag_name");
+        Release { prerelease: r["prerelease"] == true || tag.contains('-'), tag, assets }
+    });
+    Ok(releases.collect())
+}
+
+/// The newest release on `channel`.
+fn newest(releases: Vec<Release>, channel: &str) -> Option<Release> {
+    releases.into_iter()
+        .filter(|r| channel == "nightly" || !r.prerelease)
+        .max_by(|a, b| version_key(a.version()).cmp(&version_key(b.version())))
+}
+
+/// The targets a release could have an executable for this platform for, the best first.
+fn targets() -> Vec<String> {
+    let arch = env::consts::ARCH;
+    match env::consts::OS {
+        "linux" => vec![format!("{}-unknown-linux-musl", arch), format!("{}-unknown-linux-gnu", arch)],
+        "macos" => vec![format!("{}-apple-darwin", arch)],
+        _ => Vec::new(),
+    }
+}
+
+fn check_file() -> Option<PathBuf> {
+    Some(cas::cache_dir()?.join(CHECK_FILE))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Remembers `latest` as the newest stable release, for builds. Nothing fails over it.
+fn remember(latest: Option<&str>) {
+    if let Some(path) = check_file() {
+        let check = Check { checked_at: now(), latest: latest.map(|l| l.to_owned()) };
+        let _ = path.parent().map(fs::create_dir_all);
+        let _ = fs::write(path, serde_json::to_string(&check).unwrap());
+    }
+}
+
+/// After a build: says so if there's a newer stable release, looking for one if builds haven't for a day.
+pub fn notice() {
+    if env::var_os("CI").is_some() || env::var_os(NO_CHECK_VAR).is_some() || output::is_json() || output::is_porcelain() {
+        return;
+    }
+    let recorded: Option<Check> = check_file().and_then(|p| fs::read(p).ok()).and_then(|c| serde_json::from_slice(&c).ok());
+    let latest = match recorded {
+        Some(check) if now().saturating_sub(check.checked_at) < CHECK_INTERVAL => check.latest,
+        _ => {
+            let latest = releases("github", DEFAULT_REPO, 2).ok().and_then(|r| newest(r, "stable")).map(|r| r.version().to_owned());
+            remember(latest.as_deref());
+            latest
+        }
+    };
+    if let Some(latest) = latest.filter(|l| is_newer(l, VERSION)) {
+        status!("black_magic {} is out, this is {}. Update with `black_magic self-update`.", latest, VERSION);
+    }
+}
+
+/// The executable in `archive`, a `.tar.gz`, extracted into `dir`.
+fn extract(archive: &Path, dir: &Path) -> Result<PathBuf, BmError> {
+    let extracted = Command::new("tar")
+        .arg("-xzf")
+        .arg(archive)
+        .arg("-C")
+        .arg(dir)
+        .output()
+        .map_err(|e| BmError::Environment(format!("Unable to run tar: {}", e)))?;
+    if !extracted.status.success() {
+        return Err(BmError::Packaging(format!("Unable to extract `{}`: {}", archive.display(), String::from_utf8_lossy(&extracted.stderr).trim())));
+    }
+    let mut pending = vec![dir.to_owned()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.file_name().and_then(|n| n.to_str()) == Some("black_magic") {
+                return Ok(path);
+            }
+        }
+    }
+    Err(BmError::Packaging(format!("`{}` has no `black_magic` executable in it.", archive.display())))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Swaps `new` in for the running executable, once it's shown it runs.
+fn replace_current(new: &Path) -> Result<PathBuf, BmError> {
+    let current = env::current_exe()
+        .and_then(fs::canonicalize)
+        .map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    // Next to it, so it's renamed over it on the same filesystem, in one step.
+    let staged = current.with_file_name(format!(".black_magic.{}.new", process::id()));
+    let unwritable = |e: std::io::Error| BmError::Environment(format!(
+        "Unable to replace `{}`: {}. If a package manager installed it, update it with that instead.", current.display(), e));
+    fs::copy(new, &staged).map_err(unwritable)?;
+    make_executable(&staged).map_err(unwritable)?;
+    let runs = Command::new(&staged).arg("--version").output().map(|o| o.status.success()).unwrap_or(false);
+    if !runs {
+        let _ = fs::remove_file(&staged);
+        return Err(BmError::Packaging("The downloaded black_magic doesn't run on this machine, so it's kept as it was.".to_owned()));
+    }
+    fs::rename(&staged, &current).map_err(|e| {
+        let _ = fs::remove_file(&staged);
+        unwritable(e)
+    })?;
+    Ok(current)
+}
+
+/// Runs `black_magic self-update`.
+pub fn self_update(matches: &ArgMatches) -> Result<(), BmError> {
+    let source = matches.value_of("SOURCE").unwrap();
+    let repo = matches.value_of("REPO").unwrap_or(DEFAULT_REPO);
+    let channel = matches.value_of("CHANNEL").unwrap();
+    let public_key = matches.value_of("PUBLIC_KEY");
+    let (identity, issuer) = (matches.value_of("IDENTITY"), matches.value_of("ISSUER"));
+
+    status!("Checking {}'s {} releases on {}...", repo, channel, source);
+    let releases = releases(source, repo, 20).map_err(|e| BmError::Publish(format!("Unable to list {}'s releases: {}", repo, e)))?;
+    let release = newest(releases, channel).ok_or_else(|| BmError::Publish(format!("{} has no {} releases.", repo, channel)))?;
+    let version = release.version().to_owned();
+    if channel == "stable" && source == "github" && repo == DEFAULT_REPO {
+        remember(Some(&version));
+    }
+    let newer = is_newer(&version, VERSION);
+    if output::is_json() {
+        output::emit("self_update", json!({ "current": VERSION, "latest": version, "channel": channel, "newer": newer, "check": matches.is_present("CHECK") }));
+    }
+    if !newer && !matches.is_present("FORCE") {
+        status!("black_magic {} is up to date, the newest {} release is {}.", VERSION, channel, version);
+        return Ok(());
+    } else if matches.is_present("CHECK") {
+        status!("black_magic {} is out, this is {}. Update with `black_magic self-update{}`.",
+            version, VERSION, if channel == "nightly" { " --channel nightly" } else { "" });
+        return Ok(());
+    }
+
+    let candidates: Vec<String> = targets().iter().flat_map(|t| vec![format!("black_magic-{}", t), format!("black_magic-{}.tar.gz", t)]).collect();
+    let (asset, url) = candidates.iter()
+        .find_map(|name| release.asset(name).map(|url| (name.clone(), url.to_owned())))
+        .ok_or_else(|| BmError::Publish(format!(
+            "{} has no executable for this platform, expected one of: {}.", release.tag, candidates.join(", "))))?;
+    let checksum_url = release.asset(&format!("{}.sha256", asset))
+        .ok_or_else(|| BmError::Publish(format!("{} has no `{}.sha256` to check `{}` against.", release.tag, asset, asset)))?;
+
+    status!("Updating black_magic {} to {}, from {}...", VERSION, version, asset);
+    let dir = env::temp_dir().join(format!("black_magic-update-{}", process::id()));
+    fs::create_dir_all(&dir).map_err(|e| BmError::Environment(format!("Unable to create `{}`: {}", dir.display(), e)))?;
+    let updated = (|| {
+        let downloaded = dir.join(&asset);
+        download(&url, &downloaded)?;
+        download(checksum_url, &dir.join(format!("{}.sha256", asset)))?;
+        let signatures: Vec<PathBuf> = [sign::SignMethod::Minisign, sign::SignMethod::Cosign].iter().map(|m| sign::signature_file(*m, &downloaded)).collect();
+        for signature in &signatures {
+            let name = signature.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if let Some(url) = release.asset(name) {
+                download(url, signature)?;
+            }
+        }
+        if public_key.is_some() || identity.is_some() || issuer.is_some() {
+            sign::verify_file(&downloaded, public_key, identity, issuer)?;
+        } else {
+            sign::check_checksum(&downloaded)?;
+            if signatures.iter().any(|s| s.is_file()) {
+                output::warning(&format!(
+                    "{} is signed, but without `--public-key` (or `--identity` and `--issuer`) there's nobody to check it's from, so it wasn't checked.",
+                    asset));
+            }
+        }
+        let executable = if asset.ends_with(".tar.gz") { extract(&downloaded, &dir)? } else { downloaded };
+        replace_current(&executable)
+    })();
+    let _ = fs::remove_dir_all(&dir);
+    let current = updated?;
+    status!("Updated {} to black_magic {}.", current.display(), version);
+    Ok(())
+}
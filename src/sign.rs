@@ -0,0 +1,220 @@
+//! `--sign minisign|cosign`: signing the artifact, and the image once it's pushed, and `black_magic verify` to check them.
+//!
+//! The artifact (the zip, or the image's `.tar.gz`) is signed as a file, with the signature next to it:
+//! - `minisign`: `<artifact>.minisig`, with the secret key in `~/.minisign/minisign.key`, or `key`
+//! - `cosign`: `<artifact>.sigstore.json`, a Sigstore bundle holding the signature and, for keyless signing, the certificate
+//!   and its transparency log entry. Without a `key`, it's keyless, with the CI's OIDC identity (or a browser login).
+//!
+//! Pushed images (`--push`, `--ecr`) are signed with `cosign sign`, whichever signs the artifact, which keeps the signature in
+//! the registry next to the image. An image that isn't pushed only has its `.tar.gz` signed, as cosign only signs images
+//! in registries. The keys and who to expect are set in the `[sign]` section of `BlackMagic.toml`:
+//! ```toml
+//! [sign]
+//! method = "cosign"
+//! # A key file, or a KMS URI like `awskms:///alias/signing`. Keyless without one.
+//! key = "cosign.key"
+//! # For `verify`: the public key, or the keyless signer's identity and its OIDC issuer.
+//! public_key = "cosign.pub"
+//! identity = "https://github.com/example/api/.github/workflows/release.yml@refs/heads/main"
+//! issuer = "https://token.actions.githubusercontent.com"
+//! ```
+//! `black_magic verify <artifact>` checks the artifact against its `.sha256` and its signature, and
+//! `black_magic verify <image>` checks an image's signature in its registry, with `--public-key`, `--identity` and `--issuer`
+//! overriding `[sign]`'s.
+
+use crate::checksum::Checksum;
+use crate::config::Config;
+use crate::error::BmError;
+use crate::mounts;
+use crate::output::status;
+use clap::ArgMatches;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+pub const METHODS: &[&str] = &["minisign", "cosign"];
+
+/// What signs the artifact, see `--sign`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignMethod {
+    Minisign,
+    Cosign,
+}
+
+impl SignMethod {
+    pub fn from_name(name: &str) -> Option<SignMethod> {
+        match name {
+            "minisign" => Some(SignMethod::Minisign),
+            "cosign" => Some(SignMethod::Cosign),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SignConfig {
+    /// See `--sign`.
+    pub method: Option<String>,
+    /// The secret key, or cosign's key reference.
+    pub key: Option<String>,
+    pub public_key: Option<String>,
+    /// Who keyless signatures are expected from.
+    pub identity: Option<String>,
+    pub issuer: Option<String>,
+}
+
+/// The signature `method` writes for `artifact`.
+pub fn signature_file(method: SignMethod, artifact: &Path) -> PathBuf {
+    let name = artifact.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    artifact.with_file_name(format!("{}.{}", name, if method == SignMethod::Minisign { "minisig" } else { "sigstore.json" }))
+}
+
+/// Runs `cmd`, with the terminal for passwords and logins, and its output on stderr.
+fn run(mut cmd: Command, what: &str) -> Result<(), BmError> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let status = cmd.stdout(Stdio::null())
+        .status()
+        .map_err(|e| BmError::Environment(format!("Unable to run `{}`: {}. Is it installed?", program, e)))?;
+    if !status.success() {
+        return Err(BmError::Packaging(format!("{}: `{}` failed, see above.", what, program)));
+    }
+    Ok(())
+}
+
+pub struct Signer<'a> {
+    method: SignMethod,
+    key: Option<&'a str>,
+}
+
+impl<'a> Signer<'a> {
+    /// `--sign`'s method, or `[sign]`'s, if either is set.
+    pub fn new(method: Option<SignMethod>, config: &'a SignConfig) -> Result<Option<Signer<'a>>, BmError> {
+        let method = match (method, config.method.as_deref()) {
+            (Some(m), _) => m,
+            (None, Some(name)) => SignMethod::from_name(name).ok_or_else(|| BmError::Environment(format!(
+                "`method` in `[sign]` is `{}`, use {}.", name, METHODS.join(" or "))))?,
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Signer { method, key: config.key.as_deref() }))
+    }
+
+    /// Signs `artifact`, returning its signature.
+    pub fn sign_file(&self, artifact: &Path) -> Result<PathBuf, BmError> {
+        let signature = signature_file(self.method, artifact);
+        let mut cmd;
+        if self.method == SignMethod::Minisign {
+            cmd = Command::new("minisign");
+            cmd.arg("-S").arg("-m").arg(artifact).arg("-x").arg(&signature);
+            if let Some(key) = self.key {
+                cmd.arg("-s").arg(key);
+            }
+        } else {
+            cmd = Command::new("cosign");
+            cmd.arg("sign-blob").arg("--yes").arg("--bundle").arg(&signature);
+            if let Some(key) = self.key {
+                cmd.arg("--key").arg(key);
+            }
+            cmd.arg(artifact);
+        }
+        run(cmd, "Signing the artifact failed")?;
+        Ok(signature)
+    }
+
+    /// Signs the pushed image `remote` in its registry.
+    pub fn sign_image(&self, remote: &str) -> Result<(), BmError> {
+        let mut cmd = Command::new("cosign");
+        cmd.arg("sign").arg("--yes");
+        if let Some(key) = self.key {
+            cmd.arg("--key").arg(key);
+        }
+        cmd.arg(remote);
+        run(cmd, &format!("Signing {} failed", remote)).map_err(|e| BmError::Publish(e.to_string()))
+    }
+}
+
+/// How cosign is told who to expect a signature from.
+fn cosign_identity(cmd: &mut Command, public_key: Option<&str>, identity: Option<&str>, issuer: Option<&str>) -> Result<(), BmError> {
+    match (public_key, identity, issuer) {
+        (Some(key), _, _) => {
+            cmd.arg("--key").arg(key);
+        }
+        (None, Some(identity), Some(issuer)) => {
+            cmd.arg("--certificate-identity").arg(identity).arg("--certificate-oidc-issuer").arg(issuer);
+        }
+        _ => return Err(BmError::Environment(
+            "Checking a cosign signature needs `--public-key`, or `--identity` and `--issuer` for a keyless one, or the same in `[sign]`.".to_owned())),
+    }
+    Ok(())
+}
+
+/// Checks `artifact` against its `.sha256`, if it has one.
+pub fn check_checksum(artifact: &Path) -> Result<(), BmError> {
+    let name = artifact.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let expected = match fs::read_to_string(artifact.with_file_name(format!("{}.sha256", name))) {
+        Ok(e) => e.split_whitespace().next().unwrap_or("").to_owned(),
+        Err(_) => return Ok(()),
+    };
+    let contents = fs::read(artifact).map_err(|e| BmError::Packaging(format!("Unable to read `{}`: {}", artifact.display(), e)))?;
+    let actual = Checksum::of(&contents).hex;
+    if actual != expected {
+        return Err(BmError::Packaging(format!("`{}` doesn't match its `.sha256`: it's {}, not {}.", artifact.display(), actual, expected)));
+    }
+    status!("SHA-256: {} (matches)", actual);
+    Ok(())
+}
+
+/// Runs `black_magic verify`.
+pub fn verify(matches: &ArgMatches) -> Result<(), BmError> {
+    let config = Config::load(&mounts::current_dir()?)?.sign;
+    let public_key = matches.value_of("PUBLIC_KEY").or(config.public_key.as_deref());
+    let identity = matches.value_of("IDENTITY").or(config.identity.as_deref());
+    let issuer = matches.value_of("ISSUER").or(config.issuer.as_deref());
+    let target = matches.value_of("TARGET").unwrap();
+    let artifact = Path::new(target);
+
+    if !artifact.is_file() {
+        status!("Checking {}'s signature in its registry...", target);
+        let mut cmd = Command::new("cosign");
+        cmd.arg("verify");
+        cosign_identity(&mut cmd, public_key, identity, issuer)?;
+        cmd.arg(target);
+        run(cmd, &format!("{} isn't signed as expected", target))?;
+        status!("...Verified!");
+        return Ok(());
+    }
+
+    verify_file(artifact, public_key, identity, issuer)
+}
+
+/// Checks the file `artifact` against its `.sha256`, if it has one, and its signature next to it, which it has to have, from
+/// whoever `public_key` (or, for a keyless cosign one, `identity` and `issuer`) is.
+pub fn verify_file(artifact: &Path, public_key: Option<&str>, identity: Option<&str>, issuer: Option<&str>) -> Result<(), BmError> {
+    check_checksum(artifact)?;
+    let mut cmd;
+    let minisig = signature_file(SignMethod::Minisign, artifact);
+    let bundle = signature_file(SignMethod::Cosign, artifact);
+    if minisig.is_file() {
+        status!("Checking {}...", minisig.display());
+        cmd = Command::new("minisign");
+        cmd.arg("-V").arg("-m").arg(artifact).arg("-x").arg(&minisig);
+        if let Some(key) = public_key {
+            cmd.arg("-p").arg(key);
+        }
+    } else if bundle.is_file() {
+        status!("Checking {}...", bundle.display());
+        cmd = Command::new("cosign");
+        cmd.arg("verify-blob").arg("--bundle").arg(&bundle);
+        cosign_identity(&mut cmd, public_key, identity, issuer)?;
+        cmd.arg(artifact);
+    } else {
+        return Err(BmError::Packaging(format!(
+            "`{}` isn't signed: there's no `{}` or `{}` next to it.", artifact.display(), minisig.display(), bundle.display())));
+    }
+    run(cmd, &format!("`{}` isn't signed as expected", artifact.display()))?;
+    status!("...Verified!");
+    Ok(())
+}
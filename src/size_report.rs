@@ -0,0 +1,149 @@
+//! `--size-report`: how the artifact's size changed since the last build, and which crates the executable's size comes
+//! from, so a dependency that bloats a cold-start-sensitive function shows up when it's built rather than in production.
+//!
+//! The total compares the artifact with the last build's, recorded in `artifact.json` (see `state`), if that was of the same
+//! artifact. The breakdown comes from the executable's symbols, listed with `nm` in the build container (or on this machine,
+//! for the backends compiling on the host) before any `--strip`, and added up by the crate each symbol's from (the first part
+//! of its mangled path, or the type's for a trait implementation), roughly as `cargo bloat --crates` does. Symbols that aren't Rust's, e.g. musl's, are counted together.
+//! The sizes are kept in `target/black_magic/<artifact>.crate_sizes.json` for the next build to compare with. An executable
+//! without symbols (e.g. `strip = true` in the profile), or an artifact reused from the artifact store, only gets the total.
+
+use crate::clean::format_size;
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// The breakdown's rows, for the crates that changed the most, or the biggest ones.
+const ROWS: usize = 10;
+
+/// What symbols that aren't Rust's are counted as.
+const NOT_RUST: &str = "(not Rust)";
+
+/// Appended to the build container's command, listing `binary`'s symbols with their sizes into `file`.
+pub fn symbols_cmd(binary: &str, file: &str) -> String {
+    // `nm` fails on an executable without symbols, which only means there's no breakdown.
+    format!(" && {{ nm --print-size --size-sort {} > {} 2> /dev/null || rm -f {}; }}", binary, file, file)
+}
+
+/// Lists `binary`'s symbols with their sizes into `file` on this machine, as `symbols_cmd` does in the build container, for the
+/// backends compiling on the host.
+pub fn symbols(binary: &Path, file: &Path) {
+    match Command::new("nm").args(["--print-size", "--size-sort"]).arg(binary).output() {
+        Ok(listed) if listed.status.success() => {
+            let _ = fs::write(file, &listed.stdout);
+        }
+        _ => {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+/// The crate a mangled symbol is from, e.g. `serde_json` for `_ZN10serde_json2de10from_slice17h…E`.
+fn crate_of(symbol: &str) -> Option<&str> {
+    let ident = |s: &str| -> Option<(usize, usize)> {
+        let digits = s.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = s[..digits].parse().ok()?;
+        (s.len() >= digits + len).then(|| (digits, digits + len))
+    };
+    let name = if let Some(legacy) = symbol.strip_prefix("_ZN") {
+        let (start, end) = ident(legacy)?;
+        &legacy[start..end]
+    } else {
+        // v0: the first crate root, `C`, with an optional disambiguator, `s<base-62>_`.
+        let v0 = symbol.strip_prefix("_R")?;
+        let root = &v0[v0.find('C')? + 1..];
+        let root = match root.strip_prefix('s') {
+            Some(hashed) => &hashed[hashed.find('_')? + 1..],
+            None => root,
+        };
+        let (start, end) = ident(root)?;
+        &root[start..end]
+    };
+    // A trait implementation's, e.g. `_$LT$serde_json..value..Value$u20$as$u20$core..fmt..Debug$GT$`, is its type's.
+    let name = name.trim_start_matches('_').trim_start_matches("$LT$").trim_start_matches("$RF$");
+    let name = name.split("..").next().unwrap_or(name);
+    Some(name).filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// The sizes of the symbols in `nm`'s listing, by crate.
+fn crate_sizes(listing: &str) -> BTreeMap<String, u64> {
+    let mut sizes = BTreeMap::new();
+    // `<address> <size> <type> <symbol>`, in hex.
+    let symbols = listing.lines().filter_map(|l| {
+        let fields: Vec<&str> = l.split_whitespace().collect();
+        Some((u64::from_str_radix(fields.get(1)?, 16).ok()?, *fields.get(3)?))
+    });
+    for (size, symbol) in symbols {
+        *sizes.entry(crate_of(symbol).unwrap_or(NOT_RUST).to_owned()).or_insert(0) += size;
+    }
+    sizes
+}
+
+fn signed_size(delta: i64) -> String {
+    format!("{}{}", if delta < 0 { "-" } else { "+" }, format_size(delta.unsigned_abs()))
+}
+
+/// Reports the artifact's `size` against the last build's (`previous`), and the executable's crates from `symbols_file`
+/// against theirs, keeping them in `bm_dir` for the next build.
+pub fn report(bm_dir: &Path, artifact_name: &str, symbols_file: &Path, size: u64, previous: Option<u64>) -> Result<(), BmError> {
+    match previous {
+        Some(previous) if previous == size => status!("Size: {}, the same as the last build.", format_size(size)),
+        Some(previous) => {
+            let delta = size as i64 - previous as i64;
+            status!(
+                "Size: {}, {} ({:+.1}%) since the last build.",
+                format_size(size), signed_size(delta), delta as f64 * 100.0 / previous.max(1) as f64);
+        }
+        None => status!("Size: {}, with no previous build of it to compare with.", format_size(size)),
+    }
+
+    let sizes_file = bm_dir.join(format!("{}.crate_sizes.json", artifact_name));
+    let crates = fs::read_to_string(symbols_file).ok().map(|l| crate_sizes(&l)).filter(|c| !c.is_empty());
+    let _ = fs::remove_file(symbols_file);
+    let last: Option<BTreeMap<String, u64>> = fs::read(&sizes_file).ok().and_then(|s| serde_json::from_slice(&s).ok());
+    let mut rows = Vec::new();
+    match &crates {
+        Some(crates) => {
+            let mut names: Vec<&String> = crates.keys().chain(last.iter().flat_map(|l| l.keys())).collect();
+            names.sort();
+            names.dedup();
+            for name in names {
+                let now = crates.get(name).copied().unwrap_or(0);
+                let before = last.as_ref().map(|l| l.get(name).copied().unwrap_or(0));
+                rows.push((name.clone(), now, before));
+            }
+            // The biggest changes first, or without a last build, the biggest crates.
+            rows.sort_by_key(|(_, now, before)| std::cmp::Reverse(before.map(|b| (*now as i64 - b as i64).unsigned_abs()).unwrap_or(*now)));
+            status!(
+                "The executable by crate, from its symbols ({}):",
+                if last.is_some() { "the biggest changes since the last build" } else { "the biggest" });
+            let width = rows.iter().take(ROWS).map(|(name, ..)| name.len()).max().unwrap_or(0);
+            for (name, now, before) in rows.iter().take(ROWS) {
+                let change = match before {
+                    Some(before) if before == now => "  unchanged".to_owned(),
+                    Some(0) => "  new".to_owned(),
+                    Some(before) => format!("  {}", signed_size(*now as i64 - *before as i64)),
+                    None => String::new(),
+                };
+                status!("    {:width$}  {:>10}{}", name, format_size(*now), change, width = width);
+            }
+            fs::write(&sizes_file, serde_json::to_string_pretty(crates).unwrap())
+                .map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", sizes_file.display(), e)))?;
+        }
+        None => status!("There's no breakdown by crate: the executable has no symbols, or was reused rather than built."),
+    }
+
+    if output::is_json() {
+        output::emit("size_report", json!({
+            "size": size,
+            "previous_size": previous,
+            "crates": rows.iter().map(|(name, now, before)| json!({ "crate": name, "size": now, "previous_size": before })).collect::<Vec<_>>(),
+        }));
+    }
+    Ok(())
+}
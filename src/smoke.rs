@@ -0,0 +1,140 @@
+//! `--smoke-test <command>`: running the freshly built image once, to catch an executable that compiles but can't start
+//! in it, e.g. one looking for a runtime file (certificates, time zones, a config) the base image doesn't have.
+//!
+//! The command is split on whitespace, and runs as the image's entrypoint, e.g. `--smoke-test "/my_project --version"`,
+//! so it works on `scratch` images without a shell. With a `sidecar`, the image runs with its own entrypoint instead, and
+//! the command runs with `sh -c` in the sidecar, sharing the image's network, e.g. to check it answers on a port:
+//! ```toml
+//! [smoke_test]
+//! command = "curl --retry 10 --retry-connrefused -fsS http://localhost:8080/health"
+//! sidecar = "curlimages/curl"
+//! timeout = 60
+//! ```
+//! `command` in `[smoke_test]` smoke tests every docker build, as `--smoke-test` does, which replaces it. The test fails
+//! the build if the command exits with anything but 0, or if it's still running after `timeout` seconds (30 by default, or
+//! `--smoke-test-timeout`). The image's containers are removed either way, with a sidecar's image's logs shown on failure.
+
+use crate::bounds::Watchdog;
+use crate::error::BmError;
+use crate::interrupt;
+use crate::runtime::Runtime;
+use serde::Deserialize;
+use std::process;
+use std::process::Command;
+use std::process::Output;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: u64 = 30;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SmokeTestConfig {
+    pub command: Option<String>,
+    pub sidecar: Option<String>,
+    pub timeout: Option<u64>,
+}
+
+pub struct SmokeTest {
+    command: String,
+    sidecar: Option<String>,
+    timeout: u64,
+    /// The container the image runs in.
+    container: String,
+}
+
+impl SmokeTest {
+    /// From `--smoke-test` and `--smoke-test-timeout`, or `[smoke_test]`, if there's a command to run. `name` is what the
+    /// containers are named after.
+    pub fn new(config: &SmokeTestConfig, command: Option<&str>, timeout: Option<u64>, name: &str) -> Result<Option<SmokeTest>, BmError> {
+        let command = match command.or(config.command.as_deref()) {
+            Some(c) if c.trim().is_empty() => return Err(BmError::Environment("The smoke test's command is empty.".to_owned())),
+            Some(c) => c.to_owned(),
+            None => return Ok(None),
+        };
+        Ok(Some(SmokeTest {
+            command,
+            sidecar: config.sidecar.clone(),
+            timeout: timeout.or(config.timeout).unwrap_or(DEFAULT_TIMEOUT),
+            container: format!("bm_smoke_{}_{}", name, process::id()),
+        }))
+    }
+
+    fn sidecar_container(&self) -> String {
+        format!("{}_sidecar", self.container)
+    }
+
+    /// The commands running the test against `image`, on `platform` if it isn't the default: the image's, then with a
+    /// sidecar, the sidecar's.
+    pub fn commands(&self, runtime: Runtime, image: &str, platform: Option<&str>) -> Vec<Command> {
+        let mut run = runtime.command();
+        run.arg("run").arg("--name").arg(&self.container);
+        if let Some(p) = platform {
+            run.arg("--platform").arg(p);
+        }
+        match &self.sidecar {
+            Some(sidecar) => {
+                run.arg("-d").arg(image);
+                let mut check = runtime.command();
+                check.arg("run")
+                    .arg("--rm")
+                    .arg("--name")
+                    .arg(self.sidecar_container())
+                    .arg("--network")
+                    .arg(format!("container:{}", self.container))
+                    .arg(sidecar)
+                    .arg("sh")
+                    .arg("-c")
+                    .arg(&self.command);
+                vec![run, check]
+            }
+            None => {
+                let mut words = self.command.split_whitespace();
+                run.arg("--rm").arg("--entrypoint").arg(words.next().unwrap_or_default()).arg(image).args(words);
+                vec![run]
+            }
+        }
+    }
+
+    /// Runs the test against `image`.
+    pub fn run(&self, runtime: Runtime, image: &str, platform: Option<&str>) -> Result<(), BmError> {
+        let mut commands = self.commands(runtime, image, platform);
+        interrupt::cleanup_container(runtime, &self.container);
+        let (tested, logs) = if self.sidecar.is_some() {
+            let tested = match commands[0].output() {
+                Ok(started) if started.status.success() => self.watched(runtime, &mut commands[1], &self.sidecar_container()),
+                Ok(started) => Ok(Some(started)),
+                Err(e) => Err(BmError::Docker(format!("Unable to start the image for its smoke test: {}", e))),
+            };
+            let logs = runtime.command().arg("logs").arg(&self.container).output().ok();
+            let _ = runtime.command().arg("rm").arg("--force").arg(&self.container).output();
+            (tested, logs)
+        } else {
+            (self.watched(runtime, &mut commands[0], &self.container), None)
+        };
+        interrupt::finished();
+
+        match tested? {
+            None => Err(BmError::Test(format!(
+                "The smoke test `{}` was still running after {} seconds, so it was stopped. Give it longer with `--smoke-test-timeout`.",
+                self.command, self.timeout))),
+            Some(tested) if tested.status.success() => Ok(()),
+            Some(tested) => {
+                let logs = logs.map(|l| format!(
+                    "\nThe image's output:\n{}{}", String::from_utf8_lossy(&l.stdout), String::from_utf8_lossy(&l.stderr))).unwrap_or_default();
+                Err(BmError::Test(format!(
+                    "The smoke test `{}` failed, exiting with {}.\nstdout: {}\nstderr: {}{}",
+                    self.command, tested.status.code().map(|c| c.to_string()).unwrap_or_else(|| "a signal".to_owned()),
+                    String::from_utf8_lossy(&tested.stdout), String::from_utf8_lossy(&tested.stderr), logs)))
+            }
+        }
+    }
+
+    /// Runs `cmd`, whose container is `container`, returning its output unless it timed out.
+    fn watched(&self, runtime: Runtime, cmd: &mut Command, container: &str) -> Result<Option<Output>, BmError> {
+        let watchdog = Watchdog::start(runtime, container.to_owned(), Duration::from_secs(self.timeout));
+        let output = cmd.output().map_err(|e| BmError::Docker(format!("Unable to run the smoke test: {}", e)));
+        let timed_out = watchdog.finish();
+        let output = output?;
+        Ok(Some(output).filter(|_| !timed_out))
+    }
+}
@@ -0,0 +1,81 @@
+//! `--ssh`: fetching git dependencies from private repositories inside the build container, with the host's SSH agent.
+//!
+//! The agent's socket is mounted into the container, so keys never leave the host. On Linux that's `SSH_AUTH_SOCK` itself;
+//! Docker Desktop on macOS can't share sockets from the host, but forwards its agent at `/run/host-services/ssh-auth.sock`.
+//! Windows' agent is a named pipe no container can use, so builds there have to run in WSL.
+//!
+//! Cargo fetches with the `git` CLI, which checks hosts against the host's `~/.ssh/known_hosts`, or the file given with
+//! `--ssh-known-hosts`. `--gitconfig <path>` mounts a git config too, e.g. with `url."git@github.com:".insteadOf` rewrites,
+//! and works without `--ssh`, for credentials in `https` URLs.
+
+use crate::error::BmError;
+use crate::runtime::Runtime;
+use std::env;
+use std::path::Path;
+
+const CONTAINER_DIR: &str = "/bm_ssh";
+
+/// Docker Desktop's forwarded agent, inside its VM.
+const DOCKER_DESKTOP_AGENT: &str = "/run/host-services/ssh-auth.sock";
+
+/// The mounts and environment the build container gets.
+#[derive(Default)]
+pub struct Forwarding {
+    pub volumes: Vec<String>,
+    pub env: Vec<(&'static str, String)>,
+}
+
+fn mount(runtime: Runtime, host: &Path, name: &str) -> Result<String, BmError> {
+    if !host.is_file() {
+        return Err(BmError::Environment(format!("`{}` doesn't exist.", host.display())));
+    }
+    Ok(format!("{}:ro", crate::mounts::volume(runtime, host, &format!("{}/{}", CONTAINER_DIR, name))?))
+}
+
+/// The host's agent socket, as the runtime can mount it.
+fn agent_socket(runtime: Runtime) -> Result<String, BmError> {
+    if cfg!(windows) {
+        return Err(BmError::Environment(
+            "`--ssh` can't forward Windows' SSH agent, which containers can't connect to. Run black_magic in WSL instead.".to_owned()));
+    }
+    if cfg!(target_os = "macos") {
+        return match runtime {
+            Runtime::Docker => Ok(DOCKER_DESKTOP_AGENT.to_owned()),
+            Runtime::Podman => Err(BmError::Environment(
+                "`--ssh` needs Docker Desktop on macOS, podman machine can't forward the SSH agent.".to_owned())),
+        };
+    }
+    match env::var("SSH_AUTH_SOCK").ok().filter(|s| Path::new(s).exists()) {
+        Some(socket) => Ok(socket),
+        None => Err(BmError::Environment(
+            "`--ssh` needs a running SSH agent with your keys, start one with `eval $(ssh-agent)` and `ssh-add`.".to_owned())),
+    }
+}
+
+/// What forwards the agent with `ssh`, and mounts the known hosts and git config, resolved against `current_dir`.
+pub fn forward(runtime: Runtime, ssh: bool, known_hosts: Option<&str>, gitconfig: Option<&str>, current_dir: &Path) -> Result<Forwarding, BmError> {
+    let mut forwarding = Forwarding::default();
+    if ssh {
+        let socket = agent_socket(runtime)?;
+        forwarding.volumes.push(runtime.bind_mount(&socket, &format!("{}/agent.sock", CONTAINER_DIR)));
+        forwarding.env.push(("SSH_AUTH_SOCK", format!("{}/agent.sock", CONTAINER_DIR)));
+
+        let known_hosts = match known_hosts {
+            Some(k) => current_dir.join(k),
+            None => home::home_dir().map(|h| h.join(".ssh").join("known_hosts")).filter(|k| k.is_file()).ok_or_else(|| BmError::Environment(
+                "`--ssh` needs to know the git hosts' keys, from `~/.ssh/known_hosts` or `--ssh-known-hosts <path>`. \
+                Add them with e.g. `ssh-keyscan github.com >> ~/.ssh/known_hosts`, after checking the fingerprints.".to_owned()))?,
+        };
+        forwarding.volumes.push(mount(runtime, &known_hosts, "known_hosts")?);
+        forwarding.env.push(("GIT_SSH_COMMAND", format!("ssh -o UserKnownHostsFile={}/known_hosts -o StrictHostKeyChecking=yes", CONTAINER_DIR)));
+    }
+    if let Some(gitconfig) = gitconfig {
+        forwarding.volumes.push(mount(runtime, &current_dir.join(gitconfig), "gitconfig")?);
+        forwarding.env.push(("GIT_CONFIG_GLOBAL", format!("{}/gitconfig", CONTAINER_DIR)));
+    }
+    // Only the git CLI uses `GIT_SSH_COMMAND` and `GIT_CONFIG_GLOBAL`, cargo's built-in git doesn't.
+    if !forwarding.env.is_empty() {
+        forwarding.env.push(("CARGO_NET_GIT_FETCH_WITH_CLI", "true".to_owned()));
+    }
+    Ok(forwarding)
+}
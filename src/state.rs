@@ -0,0 +1,115 @@
+//! `target/black_magic/artifact.json`: the last successful build's artifact, where it came from, and what it was built from,
+//! for deployment tooling and audits, and so `--skip-unchanged` can skip building the same again, which makes black_magic
+//! cheap to run unconditionally, e.g. from a deploy script.
+//!
+//! It records the artifact (its path, SHA-256 and size, and the project image), its provenance (the target, profile and
+//! features, the builder image and its digest, the commit, the `rustc` and `cargo` that compiled it, and when the build
+//! started and finished), and its inputs: the source (every file git tracks, or would, with the workspace and path
+//! dependencies it's built with, see `cas`), the options (the build's own, and every option it was given but those only
+//! changing how it runs, see `BuildConfig::fingerprint`), and the builder image's ID. With `--skip-unchanged`, a build with
+//! the same inputs, whose artifact (and image) are still there as they were, does nothing but report them, not even pushing
+//! or deploying again. Unlike `<artifact>.manifest.json`, it's written by every build, including ones reusing an artifact.
+
+use crate::cas;
+use crate::checksum::Checksum;
+use crate::error::BmError;
+use crate::manifest::Features;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+const FILE: &str = "artifact.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct Inputs {
+    pub source: String,
+    pub options: String,
+    pub builder_image: Option<String>,
+}
+
+impl Inputs {
+    /// What the build in `project_dir` is from, with its `build_options`, and what it was `given`, see
+    /// `BuildConfig::fingerprint`.
+    pub fn of(project_dir: &Path, build_options: &str, given: &str, builder_image: Option<String>) -> Inputs {
+        let options = cas::hex(&Sha256::digest(format!("{}|{}", build_options, given).as_bytes()));
+        Inputs { source: cas::fingerprint(project_dir, ""), options, builder_image }
+    }
+}
+
+/// The last successful build.
+#[derive(Serialize, Deserialize)]
+pub struct State {
+    pub artifact: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+    /// The project image, and its ID.
+    pub image: Option<(String, String)>,
+    pub provenance: Provenance,
+    pub inputs: Inputs,
+}
+
+/// Where the artifact came from.
+#[derive(Serialize, Deserialize)]
+pub struct Provenance {
+    pub target: String,
+    pub profile: String,
+    pub features: Features,
+    /// Without one for backends compiling on the host.
+    pub builder_image: Option<String>,
+    /// Its ID, the digest of its configuration.
+    pub builder_image_digest: Option<String>,
+    pub git_sha: Option<String>,
+    pub git_dirty: Option<bool>,
+    /// The first line of `rustc -vV` and `cargo -V` where it compiled, e.g. `cargo 1.45.0-nightly (cb06cb269 2020-04-20)`.
+    pub rustc: Option<String>,
+    pub cargo: Option<String>,
+    /// RFC 3339 timestamps.
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+/// The first line of the version a tool recorded in `file`.
+pub fn recorded_version(file: &Path) -> Option<String> {
+    fs::read_to_string(file).ok()?.lines().next().map(|l| l.trim().to_owned()).filter(|l| !l.is_empty())
+}
+
+impl State {
+    pub fn load(bm_dir: &Path) -> Option<State> {
+        serde_json::from_slice(&fs::read(bm_dir.join(FILE)).ok()?).ok()
+    }
+
+    /// The last successful build, if it finished after `since`.
+    pub fn load_since(bm_dir: &Path, since: SystemTime) -> Option<State> {
+        let modified = fs::metadata(bm_dir.join(FILE)).and_then(|m| m.modified()).ok()?;
+        State::load(bm_dir).filter(|_| modified >= since)
+    }
+
+    pub fn save(&self, bm_dir: &Path) -> Result<(), BmError> {
+        let path = bm_dir.join(FILE);
+        let json = serde_json::to_string_pretty(self).expect("Unable to serialize the build state.");
+        fs::write(&path, json).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", path.display(), e)))
+    }
+
+    /// Why this isn't the build of `inputs`, or `None` if it is, and what it produced is unchanged. `image_id` says what a
+    /// local image's ID is now.
+    pub fn changed(&self, inputs: &Inputs, image_id: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+        if self.inputs.source != inputs.source {
+            Some("the source has changed")
+        } else if self.inputs.options != inputs.options {
+            Some("it was built with other options")
+        } else if self.inputs.builder_image != inputs.builder_image {
+            Some("the builder image has changed")
+        } else if !fs::read(&self.artifact).is_ok_and(|c| Checksum::of(&c).hex == self.sha256) {
+            Some("its artifact has changed")
+        } else if self.image.as_ref().is_some_and(|(image, id)| image_id(image).as_ref() != Some(id)) {
+            Some("its image has changed")
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Making sure the executable really is statically linked, which scratch images (and Lambda zips, without `--lambda-runtime`)
+//! rely on.
+//!
+//! musl targets link the C runtime statically by default, but a build can still end up half static: a `.cargo/config.toml`
+//! with `-crt-static` (often copied from a glibc setup), or `-sys` crates whose build scripts find a shared system library to
+//! link against. Before compiling, the project is checked for both, and the flags and variables forcing static linking are
+//! added, each with the reason. `--no-auto-static` turns that off, for projects that set up their linking themselves.
+//!
+//! After compiling, an executable that's still dynamically linked stops the build (see `elf`), rather than being found when
+//! the image fails to run.
+
+use crate::archive;
+use crate::archive::EntryKind;
+use crate::archive::Linkage;
+use crate::manifest;
+use std::fs;
+use std::path::Path;
+use toml::Value;
+
+/// `-sys` crates that link a system library dynamically unless told otherwise, with the variable telling them.
+const STATIC_VARIABLES: &[(&str, &str)] = &[
+    ("openssl-sys", "OPENSSL_STATIC"),
+    ("libz-sys", "LIBZ_SYS_STATIC"),
+    ("pq-sys", "PQ_LIB_STATIC"),
+    ("libsqlite3-sys", "SQLITE3_STATIC"),
+];
+
+const CRT_STATIC: &str = "-C target-feature=+crt-static";
+
+/// What the build needs to link statically.
+#[derive(Default)]
+pub struct StaticLinking {
+    pub rustflags: Vec<String>,
+    pub env: Vec<(&'static str, String)>,
+    /// Why each was added, for the build's output.
+    pub reasons: Vec<String>,
+}
+
+/// The `rustflags` a cargo config sets for `target`, from `build` or `target.<target>`.
+fn config_rustflags(config: &Value, target: &str) -> Vec<String> {
+    let flags = |table: Option<&Value>| -> Vec<String> {
+        match table.and_then(|t| t.get("rustflags")) {
+            Some(Value::String(s)) => s.split_whitespace().map(|f| f.to_owned()).collect(),
+            Some(Value::Array(a)) => a.iter().filter_map(|f| f.as_str()).map(|f| f.to_owned()).collect(),
+            _ => Vec::new(),
+        }
+    };
+    let mut rustflags = flags(config.get("build"));
+    rustflags.extend(flags(config.get("target").and_then(|t| t.get(target))));
+    rustflags
+}
+
+/// Checks the project in `project_dir` for anything that would stop its executable for `target` linking statically.
+pub fn detect(project_dir: &Path, target: &str, rustflags: &[String]) -> StaticLinking {
+    let mut linking = StaticLinking::default();
+    if !target.ends_with("-musl") {
+        return linking;
+    }
+
+    let disables_crt_static = [".cargo/config.toml", ".cargo/config"]
+        .iter()
+        .filter_map(|c| fs::read_to_string(project_dir.join(c)).ok())
+        .filter_map(|c| toml::from_str::<Value>(&c).ok())
+        .any(|c| config_rustflags(&c, target).iter().any(|f| f.contains("-crt-static")));
+    if disables_crt_static && !rustflags.iter().any(|f| f == CRT_STATIC) {
+        linking.rustflags.push(CRT_STATIC.to_owned());
+        linking.reasons.push(format!("`.cargo/config.toml` disables `crt-static`, which musl executables need, adding `{}`", CRT_STATIC));
+    }
+
+    let dependencies = manifest::locked_dependencies(project_dir);
+    for (package, variable) in STATIC_VARIABLES.iter().filter(|(p, _)| dependencies.contains_key(*p)) {
+        linking.env.push((variable, "1".to_owned()));
+        linking.reasons.push(format!("`{}` links its library dynamically by default, setting `{}=1`", package, variable));
+    }
+    if dependencies.keys().any(|p| p.ends_with("-sys")) {
+        linking.env.push(("PKG_CONFIG_ALL_STATIC", "1".to_owned()));
+        linking.reasons.push("`-sys` crates may find libraries with pkg-config, setting `PKG_CONFIG_ALL_STATIC=1`".to_owned());
+    }
+    linking
+}
+
+/// The interpreter the executable in `artifact` needs, if it's dynamically linked.
+pub fn dynamic_interpreter(artifact: &Path, is_docker: bool) -> Option<String> {
+    let data = fs::read(artifact).ok()?;
+    let entries = if is_docker { archive::read_tar(&archive::gunzip(&data).ok()?).ok()? } else { archive::read_zip(&data).ok()? };
+    entries.iter().filter(|e| e.kind == EntryKind::File).find_map(|e| match archive::elf_linkage(&e.contents) {
+        Some(Linkage::Dynamic(interpreter)) => Some(interpreter),
+        _ => None,
+    })
+}
@@ -0,0 +1,154 @@
+//! `--s3 <s3://bucket/key>`: uploading the Lambda zip to S3 while it's still being packaged, rather than after.
+//!
+//! The build container writes the zip to stdout as it compresses it. That's written to `target/black_magic` and uploaded
+//! (see `aws`) at the same time, as the parts of a multipart upload, on a thread of its own as they arrive. For big,
+//! asset-heavy zips most of the upload is done by the time packaging is.
+//!
+//! The zip goes to `<key>.partial` first, and is only moved to `key` once the artifact has passed every check, so nothing ever
+//! deploys a zip that was rejected (or a build that failed halfway through the stream). `--deploy` then points the function at
+//! the S3 object, which also lifts the 50 MiB limit on uploading zips directly.
+//!
+//! The location can use the placeholders of `--artifact-name` (see `out_dir`), e.g. `s3://my-bucket/{name}/{version}-{git_sha}.zip`,
+//! so each build gets its own key. With versioning on the bucket, the object's version ID is reported too, and `--deploy`
+//! deploys that exact version.
+
+use crate::aws::Aws;
+use crate::error::BmError;
+use crate::output;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+
+/// The size of each uploaded part but the last, which S3 needs to be at least 5 MiB.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+pub struct S3Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Location {
+    /// Parses `s3://bucket/key`. A key ending in `/` (or no key at all) is a prefix, the artifact's file name is added to it.
+    pub fn parse(url: &str, artifact_file: &str) -> Result<S3Location, BmError> {
+        let invalid = || BmError::Environment(format!("`{}` isn't an S3 location, use e.g. `s3://my-bucket/lambdas/`.", url));
+        let rest = url.strip_prefix("s3://").ok_or_else(invalid)?;
+        let (bucket, key) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+        if bucket.is_empty() {
+            return Err(invalid());
+        }
+        let key = if key.is_empty() || key.ends_with('/') { format!("{}{}", key, artifact_file) } else { key.to_owned() };
+        Ok(S3Location { bucket: bucket.to_owned(), key })
+    }
+
+    pub fn url(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.key)
+    }
+
+    /// The object's `https://` URL, in `region`.
+    pub fn object_url(&self, region: &str) -> String {
+        format!("https://{}.s3.{}.amazonaws.com/{}", self.bucket, region, self.key)
+    }
+
+    /// Where the zip is streamed to, until it's been checked.
+    fn partial_key(&self) -> String {
+        format!("{}.partial", self.key)
+    }
+
+    fn partial_url(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.partial_key())
+    }
+}
+
+/// Runs the build container `cmd`, which writes the zip to stdout, writing it to `zip` and streaming it to S3 at the same time.
+/// Returns the container's output (with an empty stdout) so a failed build is explained the same way as any other.
+pub fn run(cmd: &mut Command, zip: &Path, s3: &S3Location, aws: &Aws) -> Result<Output, BmError> {
+    let mut file = File::create(zip).map_err(|e| BmError::Packaging(format!("Unable to create `{}`: {}", zip.display(), e)))?;
+    let partial_key = s3.partial_key();
+    let upload_id = aws.start_upload(&s3.bucket, &partial_key).map_err(BmError::Publish)?;
+    let mut container = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| BmError::Docker(format!("Unable to run build command: {}", e)))?;
+
+    // Read on its own thread, so a chatty compile can't fill the pipe and stall the stream.
+    let mut container_stderr = container.stderr.take().unwrap();
+    let stderr = thread::spawn(move || {
+        let mut stderr = Vec::new();
+        let _ = container_stderr.read_to_end(&mut stderr);
+        stderr
+    });
+
+    let mut stdout = container.stdout.take().unwrap();
+    let (status, streamed, uploaded) = thread::scope(|scope| -> Result<_, BmError> {
+        // A couple of parts can wait while one's uploading, then reading the zip waits for the upload.
+        let (parts, to_upload) = mpsc::sync_channel::<Vec<u8>>(2);
+        let uploader = scope.spawn(|| {
+            to_upload.into_iter()
+                .zip(1..)
+                .map(|(part, number)| aws.upload_part(&s3.bucket, &partial_key, &upload_id, number, part))
+                .collect::<Result<Vec<_>, String>>()
+        });
+
+        let mut buffer = vec![0; 1024 * 1024];
+        let mut part = Vec::with_capacity(PART_SIZE);
+        let mut streamed = 0;
+        loop {
+            let read = stdout.read(&mut buffer).map_err(|e| BmError::Docker(format!("Unable to read the zip from the build container: {}", e)))?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read]).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", zip.display(), e)))?;
+            part.extend_from_slice(&buffer[..read]);
+            // Packaging carries on if the upload fails, there's still the local zip.
+            if part.len() >= PART_SIZE {
+                let _ = parts.send(mem::replace(&mut part, Vec::with_capacity(PART_SIZE)));
+            }
+            streamed += read;
+        }
+        let status = container.wait().map_err(|e| BmError::Docker(format!("Unable to run build command: {}", e)))?;
+        if status.success() && !part.is_empty() {
+            let _ = parts.send(part);
+        }
+        drop(parts);
+        Ok((status, streamed, uploader.join().unwrap_or_else(|_| Err("The upload panicked.".to_owned()))))
+    })?;
+
+    let built = Output { status, stdout: Vec::new(), stderr: stderr.join().unwrap_or_default() };
+    if !status.success() {
+        // So what was streamed of the broken zip never becomes an object.
+        aws.abort_upload(&s3.bucket, &partial_key, &upload_id);
+        return Ok(built);
+    }
+
+    let completed = uploaded.and_then(|parts| aws.complete_upload(&s3.bucket, &partial_key, &upload_id, parts));
+    if let Err(e) = completed {
+        aws.abort_upload(&s3.bucket, &partial_key, &upload_id);
+        return Err(BmError::Publish(format!("Unable to stream the zip to {}: {}", s3.partial_url(), e)));
+    }
+    output::detail(&format!("Streamed {} bytes to {}", streamed, s3.partial_url()));
+    Ok(built)
+}
+
+/// Moves the checked zip from where it was streamed to its final key. A zip that wasn't streamed (e.g. restored from the
+/// store, see `cas`) is uploaded from `zip` instead. Returns the object's version ID, if the bucket is versioned.
+pub fn publish(zip: &Path, s3: &S3Location, aws: &Aws, streamed: bool) -> Result<Option<String>, BmError> {
+    let version = if streamed {
+        aws.move_object(&s3.bucket, &s3.partial_key(), &s3.key)
+    } else {
+        aws.put_object(&s3.bucket, &s3.key, zip)
+    };
+    let version = version.map_err(|e| BmError::Publish(format!("Unable to upload the zip to {}: {}", s3.url(), e)))?;
+    // Unversioned buckets report the version as "null".
+    Ok(version.filter(|v| v != "null"))
+}
@@ -0,0 +1,111 @@
+//! System files a scratch image has none of, copied from the build container into the tarball with `--with-ca-certs` and
+//! `--with-tzdata`. They land where the image expects them, so TLS clients find the CA bundle and `chrono-tz`-style code finds
+//! the time zone database.
+//!
+//! `--user <uid[:gid]>` writes `/etc/passwd` and `/etc/group` entries for an unprivileged user into the tarball too, and the
+//! image runs as that user.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Printed to stderr inside the container when a file isn't in the builder image, so the failure can be told apart from a
+/// compile error.
+pub const MISSING: &str = "black_magic: missing system file";
+
+#[derive(Copy, Clone)]
+pub enum SystemFile {
+    CaCerts,
+    Tzdata,
+}
+
+impl SystemFile {
+    /// Where the file (or directory) is, both in the builder image and the project image.
+    pub fn path(self) -> &'static str {
+        match self {
+            SystemFile::CaCerts => "/etc/ssl/certs/ca-certificates.crt",
+            SystemFile::Tzdata => "/usr/share/zoneinfo",
+        }
+    }
+
+    pub fn flag(self) -> &'static str {
+        match self {
+            SystemFile::CaCerts => "--with-ca-certs",
+            SystemFile::Tzdata => "--with-tzdata",
+        }
+    }
+}
+
+/// Where the user's `etc` is written inside the build container, before being added to the tarball.
+const USER_DIR: &str = "/bm_user";
+
+/// The unprivileged user the image runs as.
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl User {
+    /// Parses `uid[:gid]`, the group defaulting to the same ID.
+    pub fn parse(value: &str) -> Result<User, String> {
+        let invalid = || format!("`{}` isn't a user, use a numeric `uid[:gid]` like `1000` or `1000:1000`.", value);
+        let mut parts = value.splitn(2, ':');
+        let uid = parts.next().unwrap_or("").parse::<u32>().map_err(|_| invalid())?;
+        let gid = match parts.next() {
+            Some(g) => g.parse::<u32>().map_err(|_| invalid())?,
+            None => uid,
+        };
+        if uid == 0 {
+            return Err("`--user 0` is root, which is what the image runs as without `--user`.".to_owned());
+        }
+        Ok(User { uid, gid })
+    }
+
+    /// For the Dockerfile's `USER`.
+    pub fn spec(&self) -> String {
+        format!("{}:{}", self.uid, self.gid)
+    }
+
+    /// Shell command writing the user's `passwd` and `group` entries, to run before they're added to the tarball.
+    pub fn files_cmd(&self) -> String {
+        format!(" && mkdir -p {dir}/etc && echo '{}' > {dir}/etc/passwd && echo '{}' > {dir}/etc/group", self.passwd(), self.group(), dir = USER_DIR)
+    }
+
+    /// Writes the entries into `dir` at their paths in the tarball, for the backends packaging on the host.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir.join("etc"))?;
+        fs::write(dir.join("etc/passwd"), format!("{}\n", self.passwd()))?;
+        fs::write(dir.join("etc/group"), format!("{}\n", self.group()))
+    }
+
+    fn passwd(&self) -> String {
+        format!("app:x:{}:{}:app:/:/sbin/nologin", self.uid, self.gid)
+    }
+
+    fn group(&self) -> String {
+        format!("app:x:{}:", self.gid)
+    }
+
+    /// The files, as arguments to `tar`.
+    pub fn tar_args(&self) -> String {
+        format!(" -C {} etc/passwd etc/group", USER_DIR)
+    }
+
+    /// Shell command copying the files into `dir` at their paths in the tarball, for tarring them on the host (see `archivers`).
+    pub fn package_cmd(&self, dir: &str) -> String {
+        format!(" && mkdir -p {dir}/etc && cp {user}/etc/passwd {user}/etc/group {dir}/etc/", dir = crate::shell_quote(dir), user = USER_DIR)
+    }
+}
+
+/// Shell command checking `files` exist in the build container, to run before they're added to the tarball.
+pub fn check_cmd(files: &[SystemFile]) -> String {
+    files
+        .iter()
+        .map(|f| format!(" && (test -e {} || (echo '{} {}' >&2 && exit 1))", f.path(), MISSING, f.flag()))
+        .collect()
+}
+
+/// The paths of `files`, as arguments to `tar`.
+pub fn tar_args(files: &[SystemFile]) -> String {
+    files.iter().map(|f| format!(" {}", f.path())).collect()
+}
@@ -0,0 +1,148 @@
+//! The project image's tags and labels.
+//!
+//! The image is always `bm_<name>`, i.e. `bm_<name>:latest`. `--tag <t>` also tags it `bm_<name>:<t>`, and `--tag-git` with
+//! the commit it was built from, e.g. `bm_api:3f9c2e1`, or `bm_api:3f9c2e1-dirty` with uncommitted changes. A `--push` or
+//! `--ecr` repository without a tag of its own is pushed under those tags, instead of `latest`.
+//!
+//! Every image gets the OCI labels saying where it came from, added after the Dockerfile template:
+//! - `org.opencontainers.image.source`: `repository` from `Cargo.toml`, otherwise the git remote `origin`
+//! - `org.opencontainers.image.revision`: the commit
+//! - `org.opencontainers.image.created`: the time of the build, or `SOURCE_DATE_EPOCH` with `--reproducible`
+//! - `org.opencontainers.image.version`: the crate's version
+//!
+//! Labels whose value isn't known (outside a git repo, say) are left out.
+
+use crate::error::BmError;
+use crate::registry;
+use crate::release;
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+
+/// Checks a `--tag` value against what docker accepts.
+pub fn validate(tag: String) -> Result<(), String> {
+    let valid = tag.len() <= 128
+        && tag.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("`{}` isn't a valid tag, use at most 128 letters, digits, `_`, `.` and `-`, not starting with `.` or `-`.", tag))
+    }
+}
+
+fn git(project_dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git").current_dir(project_dir).args(args).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    Some(stdout).filter(|_| output.status.success())
+}
+
+/// The short hash of the commit `project_dir` is at, with `-dirty` if tracked files have changed since.
+pub fn short_hash(project_dir: &Path) -> Option<String> {
+    let hash = git(project_dir, &["rev-parse", "--short", "HEAD"])?;
+    Some(if dirty(project_dir) { format!("{}-dirty", hash) } else { hash })
+}
+
+/// The full hash of the commit `project_dir` is at.
+pub fn full_hash(project_dir: &Path) -> Option<String> {
+    git(project_dir, &["rev-parse", "HEAD"])
+}
+
+/// Whether tracked files have changed since the commit `project_dir` is at.
+pub fn dirty(project_dir: &Path) -> bool {
+    git(project_dir, &["status", "--porcelain", "--untracked-files=no"]).map(|s| !s.is_empty()).unwrap_or(false)
+}
+
+pub fn git_tag(project_dir: &Path) -> Result<String, BmError> {
+    short_hash(project_dir)
+        .ok_or_else(|| BmError::Environment("`--tag-git` needs the project to be in a git repository with a commit.".to_owned()))
+}
+
+/// The tags from `--tag` and `--tag-git`.
+pub fn requested(tags: &[String], tag_git: bool, project_dir: &Path) -> Result<Vec<String>, BmError> {
+    let mut tags = tags.to_vec();
+    if tag_git {
+        tags.push(git_tag(project_dir)?);
+    }
+    tags.dedup();
+    Ok(tags)
+}
+
+/// The references to push `remotes` to: each as given if it has a tag, otherwise under every one of `tags`.
+pub fn remotes(remotes: &[String], tags: &[String]) -> Vec<String> {
+    remotes.iter().flat_map(|remote| {
+        if tags.is_empty() || registry::split_tag(remote).0 != remote {
+            vec![remote.to_owned()]
+        } else {
+            tags.iter().map(|t| format!("{}:{}", remote, t)).collect()
+        }
+    }).collect()
+}
+
+/// Where the source lives, for `org.opencontainers.image.source`, without any credentials in the remote's URL.
+fn source(project_dir: &Path, cargo_toml: &Path) -> Option<String> {
+    let repository = std::fs::read_to_string(cargo_toml).ok()
+        .and_then(|c| toml::from_str::<toml::Value>(&c).ok())
+        .and_then(|m| m.get("package")?.get("repository")?.as_str().map(|r| r.to_owned()));
+    if repository.is_some() {
+        return repository;
+    }
+    let remote = git(project_dir, &["remote", "get-url", "origin"]).filter(|r| !r.is_empty())?;
+    let remote = remote.trim_end_matches(".git");
+    Some(match remote.split_once("://") {
+        Some((scheme, rest)) => match rest.split_once('/') {
+            Some((host, path)) => format!("{}://{}/{}", scheme, host.rsplit('@').next().unwrap_or(host), path),
+            None => format!("{}://{}", scheme, rest.rsplit('@').next().unwrap_or(rest)),
+        },
+        // `git@github.com:me/api`, the scp-like form.
+        None => match remote.split_once(':') {
+            Some((host, path)) => format!("https://{}/{}", host.rsplit('@').next().unwrap_or(host), path),
+            None => remote.to_owned(),
+        },
+    })
+}
+
+/// `epoch` as an RFC 3339 timestamp in UTC, e.g. `2024-03-01T12:00:00Z`.
+pub fn rfc3339(epoch: u64) -> String {
+    let ((year, month, day), seconds) = (civil(epoch), epoch % 86400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
+
+/// The UTC date of `epoch`: its year, month and day.
+pub fn civil(epoch: u64) -> (i64, i64, i64) {
+    let days = (epoch / 86400) as i64;
+    // Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The OCI labels, as a `LABEL` instruction.
+pub fn labels(project_dir: &Path, cargo_toml: &Path, source_date_epoch: Option<u64>) -> String {
+    let created = source_date_epoch
+        .or_else(|| SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).ok().map(|d| d.as_secs()))
+        .map(rfc3339);
+    let labels = [
+        ("source", source(project_dir, cargo_toml)),
+        ("revision", git(project_dir, &["rev-parse", "HEAD"])),
+        ("created", created),
+        ("version", release::read_version(cargo_toml)),
+    ];
+    let labels: Vec<String> = labels.iter()
+        .filter_map(|(key, value)| value.as_ref().map(|v| (key, v)))
+        // Docker expands variables in labels, so `$` is escaped.
+        .map(|(key, value)| format!("org.opencontainers.image.{}={}", key, serde_json::to_string(value).unwrap().replace('$', "\\$")))
+        .collect();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("LABEL {}\n", labels.join(" \\\n      "))
+    }
+}
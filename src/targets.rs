@@ -0,0 +1,152 @@
+//! The target triple, from `--arch` and `--libc`, and what the base image the executable's going to run on can run.
+//!
+//! Every base runs a static musl executable of its own architecture, but only bases with glibc run a `--libc gnu` one:
+//!
+//! | Base                                         | musl | gnu |
+//! |----------------------------------------------|------|-----|
+//! | `scratch`, `distroless/static`               | yes  | no  |
+//! | `alpine`, and images with `alpine` or `musl` | yes  | no  |
+//! | `distroless` (`cc` with `--libc gnu`)        | yes  | yes |
+//! | `busybox` (`glibc` with `--libc gnu`)        | yes  | yes |
+//! | Lambda's base images, and any other image    | yes  | yes |
+//!
+//! An image that's only for one architecture by its name (`arm64v8/…` or `amd64/…`, or a tag like `…-arm64`, as Lambda's
+//! have) runs only that architecture's executables. Without `--arch`, the build is for the base's architecture, and with a
+//! different one, the build fails before compiling anything, saying why, as it does for `--libc gnu` on a base without glibc.
+//!
+//! With `--docker`, `--target-triple` builds for any other Linux target rustup has, e.g. `armv7-unknown-linux-musleabihf`,
+//! instead of `--arch` and `--libc`. Its C library is the triple's (`musl…` or `gnu…`), and the image's platform its
+//! architecture's, e.g. `linux/arm/v7`. The build container still runs on the host's platform and cross-compiles, after
+//! `rustup target add`, so it needs a builder image with the target's linker, given with `--builder-image` (or `image` in
+//! `[builder]`), and often its `CARGO_TARGET_<TRIPLE>_LINKER`, with `--build-env`. Artifacts are named after the triple,
+//! e.g. `my_project-armv7-unknown-linux-musleabihf.tar.gz`, as is the cache volume.
+
+use crate::error::BmError;
+use crate::Arch;
+use crate::Libc;
+
+/// Docker's platform for each architecture in a target triple.
+const PLATFORMS: &[(&str, &str)] = &[
+    ("x86_64", "linux/amd64"),
+    ("aarch64", "linux/arm64"),
+    ("armv7", "linux/arm/v7"),
+    ("thumbv7neon", "linux/arm/v7"),
+    ("arm", "linux/arm/v6"),
+    ("i686", "linux/386"),
+    ("i586", "linux/386"),
+    ("powerpc64le", "linux/ppc64le"),
+    ("s390x", "linux/s390x"),
+    ("riscv64gc", "linux/riscv64"),
+];
+
+/// A `--target-triple` other than the ones `--arch` and `--libc` pick.
+pub struct CustomTarget {
+    pub triple: String,
+    pub libc: Libc,
+    /// The platform of the images it runs on.
+    pub platform: &'static str,
+}
+
+/// What `--target-triple` builds for.
+pub enum Triple {
+    /// One of `--arch` and `--libc`'s, which builds as they do.
+    Builtin(Arch, Libc),
+    Custom(CustomTarget),
+}
+
+/// Parses `--target-triple`.
+pub fn parse_triple(triple: &str) -> Result<Triple, BmError> {
+    for &arch in &[Arch::X86_64, Arch::Aarch64] {
+        for &libc in &[Libc::Musl, Libc::Gnu] {
+            if libc.target_triple(arch) == triple {
+                return Ok(Triple::Builtin(arch, libc));
+            }
+        }
+    }
+    // It ends up in shell commands inside the container.
+    let parts: Vec<&str> = triple.split('-').collect();
+    if parts.len() < 3 || !triple.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+        return Err(BmError::Environment(format!("`{}` isn't a target triple, e.g. `armv7-unknown-linux-musleabihf`.", triple)));
+    }
+    let env = parts[parts.len() - 1];
+    if parts[parts.len() - 2] != "linux" {
+        return Err(BmError::Environment(format!("`{}` isn't a Linux target, which images need.", triple)));
+    }
+    let libc = if env.starts_with("musl") {
+        Libc::Musl
+    } else if env.starts_with("gnu") {
+        Libc::Gnu
+    } else {
+        return Err(BmError::Environment(format!("`{}` links against neither musl nor glibc, which black_magic packages for.", triple)));
+    };
+    let platform = PLATFORMS.iter().find(|(a, _)| *a == parts[0]).map(|(_, p)| *p).ok_or_else(|| BmError::Environment(format!(
+        "There's no docker platform for `{}`'s architecture, it has to be one of: {}.",
+        triple, PLATFORMS.iter().map(|(a, _)| *a).collect::<Vec<_>>().join(", "))))?;
+    Ok(Triple::Custom(CustomTarget { triple: triple.to_owned(), libc, platform }))
+}
+
+/// Fails if `base` can't run `target`'s executables, as `resolve` does.
+pub fn check_custom(base: Option<&str>, target: &CustomTarget) -> Result<(), BmError> {
+    let compatibility = match base {
+        Some(base) => compatibility(base),
+        None => return Ok(()),
+    };
+    let base = base.unwrap_or_default();
+    if target.libc == Libc::Gnu && !compatibility.gnu {
+        return Err(BmError::Environment(format!(
+            "`{}` executables need glibc, which `--base {}` doesn't have, so use a base with glibc, or a musl target.", target.triple, base)));
+    }
+    if let Some(needed) = compatibility.arch.filter(|a| a.platform().unwrap_or("linux/amd64") != target.platform) {
+        return Err(BmError::Environment(format!(
+            "`--base {}` is an {} image, so it can't run `{}` executables. Use a base for {} (or a multi-architecture one).",
+            base, needed.lambda_name(), target.triple, target.platform)));
+    }
+    Ok(())
+}
+
+/// What can run on `base`, if it says.
+struct Compatibility {
+    gnu: bool,
+    arch: Option<Arch>,
+}
+
+fn compatibility(base: &str) -> Compatibility {
+    let image = base.to_ascii_lowercase();
+    let musl_only = image == "scratch"
+        || image.contains("alpine")
+        || image.contains("musl")
+        || image.contains("distroless/static");
+    // Multi-architecture images are named for neither.
+    let arch = if ["arm64", "aarch64"].iter().any(|a| image.contains(a)) {
+        Some(Arch::Aarch64)
+    } else if ["amd64", "x86_64", "x86-64"].iter().any(|a| image.contains(a)) {
+        Some(Arch::X86_64)
+    } else {
+        None
+    };
+    Compatibility { gnu: !musl_only, arch }
+}
+
+/// The architecture and C library to build for: `arch` and `libc` if they were given, else what `base` needs, else the
+/// defaults. Fails if `base` can't run them.
+pub fn resolve(base: Option<&str>, arch: Option<Arch>, libc: Option<Libc>) -> Result<(Arch, Libc), BmError> {
+    let compatibility = base.map(compatibility).unwrap_or(Compatibility { gnu: true, arch: None });
+    let base = base.unwrap_or_default();
+    let libc = libc.unwrap_or(Libc::Musl);
+    if libc == Libc::Gnu && !compatibility.gnu {
+        return Err(BmError::Environment(format!(
+            "`--libc gnu` executables need glibc, which `--base {}` doesn't have, so build for musl (the default), or use a base \
+            with glibc, e.g. `distroless` (`gcr.io/distroless/cc-debian12`).", base)));
+    }
+    let arch = match (arch, compatibility.arch) {
+        (Some(arch), Some(needed)) if arch != needed => {
+            return Err(BmError::Environment(format!(
+                "`--base {}` is an {} image, so it can't run an {} executable. Leave out `--arch`, or use a base for {} (or a \
+                multi-architecture one).", base, needed.lambda_name(), arch.lambda_name(), arch.lambda_name())));
+        }
+        (Some(arch), _) => arch,
+        (None, Some(needed)) => needed,
+        (None, None) => Arch::X86_64,
+    };
+    Ok((arch, libc))
+}
@@ -0,0 +1,241 @@
+//! The Dockerfile the project image is built from.
+//!
+//! By default it's just the executable on top of `scratch`, or the base image picked with `--base`: `distroless` (CA
+//! certificates, time zone data and a `nonroot` user, but no shell), `alpine` (a shell, a package manager, and musl, so
+//! dynamically linked musl executables run too), `busybox` (just a shell and the usual tools), or any other image.
+//! `--libc gnu` executables need glibc, so they get the glibc variants of distroless (the default for them) and busybox.
+//! A `black_magic.Dockerfile` next to `Cargo.toml` (or the file given with `--dockerfile-template`) replaces it, with
+//! placeholders filled in:
+//! ```dockerfile
+//! FROM {{base}}
+//! ADD {{artifact}} /
+//! EXPOSE 8080
+//! ENV RUST_LOG=info
+//! CMD ["{{binary}}"]
+//! ```
+//! The image is built in `target/black_magic`, so paths in `ADD`/`COPY` are relative to that.
+//!
+//! `--entrypoint`, `--cmd`, `--expose`, and `--env` add instructions after the template, so the image can be run as is.
+//! These can use the placeholders too. With the default template, the entrypoint defaults to the executable. `--user` adds a
+//! `USER`, but doesn't make the image runnable by itself.
+//!
+//! `--lambda-image` builds on Lambda's `provided:al2023` image instead, extracting the tarball into `/var/runtime`, where the
+//! executable is `bootstrap`, so the base image's entrypoint runs it, and `{{binary}}` is `/var/runtime/bootstrap`. There's no
+//! default entrypoint then, as that would replace the base image's.
+
+use crate::error::BmError;
+use crate::runtime::Runtime;
+use std::fs;
+use std::path::Path;
+
+pub const TEMPLATE_FILE: &str = "black_magic.Dockerfile";
+
+const DEFAULT_TEMPLATE: &str = r#"
+FROM {{base}}
+ADD {{artifact}} /
+"#;
+
+/// `--lambda-image`'s base, whose entrypoint runs `/var/runtime/bootstrap`, in Lambda or locally with the emulator it has.
+pub const LAMBDA_BASE: &str = "public.ecr.aws/lambda/provided:al2023";
+
+/// The tarball has the executable as `bootstrap`. The handler is the base image's `CMD`, which `--cmd` replaces.
+const LAMBDA_TEMPLATE: &str = r#"
+FROM {{base}}
+ADD {{artifact}} /var/runtime/
+CMD ["bootstrap"]
+"#;
+
+/// The image the project image is built on, see `--base`.
+#[derive(Clone, PartialEq)]
+pub enum Base {
+    Scratch,
+    Distroless,
+    DistrolessGlibc,
+    Alpine,
+    Busybox,
+    BusyboxGlibc,
+    Custom(String),
+}
+
+impl Base {
+    /// `base`, or the default, for executables linked against glibc if `gnu`.
+    pub fn parse(base: Option<&str>, gnu: bool) -> Result<Base, BmError> {
+        match (base, gnu) {
+            (None | Some("scratch"), false) => Ok(Base::Scratch),
+            (Some("distroless"), false) => Ok(Base::Distroless),
+            (None | Some("distroless"), true) => Ok(Base::DistrolessGlibc),
+            (Some("alpine"), false) => Ok(Base::Alpine),
+            (Some("busybox"), false) => Ok(Base::Busybox),
+            (Some("busybox"), true) => Ok(Base::BusyboxGlibc),
+            (Some(base @ ("scratch" | "alpine")), true) => Err(BmError::Environment(format!(
+                "`--libc gnu` executables need glibc, which `--base {}` doesn't have. Use `distroless`, `busybox`, or a glibc image.", base))),
+            (Some(image), _) => Ok(Base::Custom(image.to_owned())),
+        }
+    }
+
+    /// The image for `FROM`.
+    pub fn image(&self, runtime: Runtime) -> String {
+        match self {
+            Base::Scratch => "scratch".to_owned(),
+            Base::Distroless => "gcr.io/distroless/static-debian12".to_owned(),
+            Base::DistrolessGlibc => "gcr.io/distroless/cc-debian12".to_owned(),
+            Base::Alpine => runtime.qualify("alpine:3"),
+            Base::Busybox => runtime.qualify("busybox:musl"),
+            Base::BusyboxGlibc => runtime.qualify("busybox:glibc"),
+            Base::Custom(image) => image.clone(),
+        }
+    }
+
+    /// What to call it in messages, e.g. "a scratch image".
+    pub fn description(&self) -> String {
+        match self {
+            Base::Scratch => "a scratch image".to_owned(),
+            Base::Distroless | Base::DistrolessGlibc => "a distroless image".to_owned(),
+            Base::Alpine => "an alpine image".to_owned(),
+            Base::Busybox | Base::BusyboxGlibc => "a busybox image".to_owned(),
+            Base::Custom(image) => format!("`{}`", image),
+        }
+    }
+
+    /// Whether it has `interpreter`, the dynamic loader an executable needs.
+    pub fn has_loader(&self, interpreter: &str) -> bool {
+        match self {
+            Base::Alpine => interpreter.contains("ld-musl"),
+            Base::DistrolessGlibc | Base::BusyboxGlibc => interpreter.contains("ld-linux"),
+            _ => false,
+        }
+    }
+
+    /// Whether it has its own `/etc/passwd` and `/etc/group`, which the tarball's would replace. `USER` works with just the
+    /// numeric ids anyway. Custom bases get the tarball's, as scratch does, since there's no telling.
+    pub fn has_users(&self) -> bool {
+        !matches!(self, Base::Scratch | Base::Custom(_))
+    }
+}
+
+pub fn validate_base(base: String) -> Result<(), String> {
+    if base.trim().is_empty() || base.contains(char::is_whitespace) {
+        Err("It has to be `scratch`, `distroless`, `alpine`, `busybox`, or an image.".to_owned())
+    } else {
+        Ok(())
+    }
+}
+
+/// The values placeholders are replaced with.
+pub struct Placeholders<'a> {
+    /// The executable's path inside the image.
+    pub binary: &'a str,
+    /// The name artifacts and images are named after.
+    pub project: &'a str,
+    /// The tarball holding the executable, relative to the build context.
+    pub artifact: &'a str,
+    /// The image picked with `--base`.
+    pub base: &'a str,
+}
+
+/// Instructions making the image runnable, added after the template.
+#[derive(Default)]
+pub struct RunOptions {
+    pub entrypoint: Option<Vec<String>>,
+    pub cmd: Option<Vec<String>>,
+    /// `port[/protocol]`
+    pub expose: Vec<String>,
+    pub env: Vec<(String, String)>,
+    /// `uid:gid`
+    pub user: Option<String>,
+}
+
+/// Checks a `--expose` value: a port or range of ports, optionally with `/tcp` or `/udp`.
+pub fn parse_expose(value: &str) -> Result<(), String> {
+    let mut parts = value.splitn(2, '/');
+    let ports = parts.next().unwrap_or("");
+    let valid_protocol = parts.next().map(|p| p == "tcp" || p == "udp").unwrap_or(true);
+    let valid_ports = ports.splitn(2, '-').all(|p| p.parse::<u16>().map(|p| p > 0).unwrap_or(false));
+    if valid_ports && valid_protocol {
+        Ok(())
+    } else {
+        Err(format!("`{}` isn't a port to expose, use e.g. `80`, `8080/tcp`, or `5000-5010/udp`.", value))
+    }
+}
+
+/// Splits a `--env` value into the variable and its value.
+pub fn parse_env(value: &str) -> Result<(String, String), String> {
+    let mut parts = value.splitn(2, '=');
+    let key = parts.next().unwrap_or("");
+    let valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match parts.next() {
+        Some(val) if valid_key => Ok((key.to_owned(), val.to_owned())),
+        _ => Err(format!("`{}` isn't an environment variable to set, use `KEY=VALUE`.", value)),
+    }
+}
+
+impl RunOptions {
+    /// Whether any options make the image runnable.
+    pub fn is_empty(&self) -> bool {
+        self.entrypoint.is_none() && self.cmd.is_none() && self.expose.is_empty() && self.env.is_empty()
+    }
+
+    /// The Dockerfile instructions, with `default_entrypoint` used if there's no entrypoint but anything else is set.
+    fn instructions(&self, default_entrypoint: Option<&str>) -> String {
+        // JSON is what docker expects for the exec form, which is the only one that works without a shell.
+        let exec_form = |args: &[String]| serde_json::to_string(args).unwrap();
+
+        let mut instructions = String::new();
+        for port in &self.expose {
+            instructions.push_str(&format!("EXPOSE {}\n", port));
+        }
+        for (key, value) in &self.env {
+            instructions.push_str(&format!("ENV {}={}\n", key, serde_json::to_string(value).unwrap()));
+        }
+        if let Some(user) = &self.user {
+            instructions.push_str(&format!("USER {}\n", user));
+        }
+        let default_entrypoint = default_entrypoint.filter(|_| !self.is_empty()).map(|e| vec![e.to_owned()]);
+        if let Some(entrypoint) = self.entrypoint.as_ref().or(default_entrypoint.as_ref()) {
+            instructions.push_str(&format!("ENTRYPOINT {}\n", exec_form(entrypoint)));
+        }
+        if let Some(cmd) = &self.cmd {
+            instructions.push_str(&format!("CMD {}\n", exec_form(cmd)));
+        }
+        instructions
+    }
+}
+
+/// Reads the template: `path` if given, otherwise `black_magic.Dockerfile` if the project has one, otherwise the default, or
+/// for a Lambda container image if `lambda`. The run options are added after it.
+pub fn load(project_dir: &Path, path: Option<&str>, run: &RunOptions, lambda: bool) -> Result<String, BmError> {
+    let path = match path {
+        Some(p) => project_dir.join(p),
+        None if project_dir.join(TEMPLATE_FILE).exists() => project_dir.join(TEMPLATE_FILE),
+        // Lambda's base image already has an entrypoint, which gets the function's events to the executable.
+        None if lambda => return Ok(format!("{}{}", LAMBDA_TEMPLATE, run.instructions(None))),
+        None => return Ok(format!("{}{}", DEFAULT_TEMPLATE, run.instructions(Some("{{binary}}")))),
+    };
+
+    let mut template = fs::read_to_string(&path)
+        .map_err(|e| BmError::Environment(format!("Unable to read Dockerfile template `{}`: {}", path.display(), e)))?;
+    if !template.ends_with('\n') {
+        template.push('\n');
+    }
+    // A custom template might already run something, so there's no default entrypoint.
+    template.push_str(&run.instructions(None));
+    Ok(template)
+}
+
+/// Fills in the placeholders. Anything else that looks like one is an error, rather than ending up in the image.
+pub fn render(template: &str, placeholders: &Placeholders) -> Result<String, BmError> {
+    let rendered = template
+        .replace("{{binary}}", placeholders.binary)
+        .replace("{{project}}", placeholders.project)
+        .replace("{{artifact}}", placeholders.artifact)
+        .replace("{{base}}", placeholders.base);
+
+    if let Some(start) = rendered.find("{{") {
+        let unknown: String = rendered[start..].chars().take_while(|c| *c != '\n').take(40).collect();
+        return Err(BmError::Environment(format!(
+            "Unknown placeholder `{}` in the Dockerfile template. Use `{{{{binary}}}}`, `{{{{project}}}}`, `{{{{artifact}}}}`, or `{{{{base}}}}`.",
+            unknown)));
+    }
+    Ok(rendered)
+}
@@ -0,0 +1,106 @@
+//! `--emit-terraform`: a Terraform fragment for the built artifact, written next to it as `<artifact>.tf`.
+//!
+//! For a zip, it's an `aws_lambda_function` with `Handler: bootstrap`, the zip's runtime and architecture, the settings from
+//! `[lambda.function]` in `BlackMagic.toml`, and the zip's `source_code_hash`, so Terraform only updates the function
+//! when the zip has actually changed. The function's execution role is left to a variable.
+//!
+//! For an image, it's a `docker_image` (from the `kreuzwerker/docker` provider) triggered by the artifact's SHA-256, and with
+//! `--ecr`, an `aws_ecr_image` data source for the pushed tag, to point functions or services at.
+
+use crate::config::FunctionConfig;
+use crate::error::BmError;
+use std::fs;
+use std::path::Path;
+
+/// The function, for a zip.
+pub struct Function<'a> {
+    pub name: &'a str,
+    /// The zip's path, unless it was uploaded to an S3 bucket and key.
+    pub zip: &'a str,
+    pub s3: Option<(&'a str, &'a str)>,
+    pub code_sha256: &'a str,
+    pub runtime: &'a str,
+    pub architecture: &'a str,
+}
+
+/// `name` as a Terraform identifier, e.g. `my_project.v2` to `my_project_v2`.
+fn identifier(name: &str) -> String {
+    let id: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect();
+    if id.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') { id } else { format!("_{}", id) }
+}
+
+/// A Terraform string, with anything that would be taken as an interpolation escaped.
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap().replace("${", "$${").replace("%{", "%%{")
+}
+
+/// The fragment for a zip.
+pub fn lambda(function: &Function, config: &FunctionConfig) -> String {
+    let id = identifier(function.name);
+    let code = match function.s3 {
+        Some((bucket, key)) => format!("  s3_bucket        = {}\n  s3_key           = {}\n", quote(bucket), quote(key)),
+        None => format!("  filename         = {}\n", quote(function.zip)),
+    };
+    let mut fragment = format!(
+        "# {comment}, built by black_magic.\n\
+        \n\
+        variable \"{id}_role_arn\" {{\n  \
+          description = {description}\n  \
+          type        = string\n\
+        }}\n\
+        \n\
+        resource \"aws_lambda_function\" \"{id}\" {{\n  \
+          function_name    = {name}\n  \
+          role             = var.{id}_role_arn\n\
+        {code}  \
+          source_code_hash = {hash}\n  \
+          handler          = \"bootstrap\"\n  \
+          runtime          = {runtime}\n  \
+          architectures    = [{architecture}]\n  \
+          memory_size      = {memory}\n  \
+          timeout          = {timeout}\n",
+        comment = function.name, name = quote(function.name), id = id, code = code,
+        description = quote(&format!("The execution role of the {} Lambda function.", function.name)),
+        hash = quote(function.code_sha256), runtime = quote(function.runtime), architecture = quote(function.architecture),
+        memory = config.memory_size, timeout = config.timeout);
+    if !config.environment.is_empty() {
+        fragment.push_str("\n  environment {\n    variables = {\n");
+        for (key, value) in &config.environment {
+            fragment.push_str(&format!("      {} = {}\n", quote(key), quote(value)));
+        }
+        fragment.push_str("    }\n  }\n");
+    }
+    fragment.push_str("}\n");
+    fragment
+}
+
+/// The fragment for an image, named `image` (or pushed there), with `--ecr`'s repository and tag.
+pub fn image(name: &str, image: &str, sha256: &str, ecr: Option<(&str, &str)>) -> String {
+    let id = identifier(name);
+    let mut fragment = format!(
+        "# {comment}, built by black_magic.\n\
+        \n\
+        resource \"docker_image\" \"{id}\" {{\n  \
+          name         = {image}\n  \
+          keep_locally = true\n  \
+          triggers = {{\n    \
+            sha256 = {sha256}\n  \
+          }}\n\
+        }}\n",
+        comment = image, image = quote(image), id = id, sha256 = quote(sha256));
+    if let Some((repository, tag)) = ecr {
+        fragment.push_str(&format!(
+            "\n\
+            data \"aws_ecr_image\" \"{id}\" {{\n  \
+              repository_name = {repository}\n  \
+              image_tag       = {tag}\n\
+            }}\n",
+            id = id, repository = quote(repository), tag = quote(tag)));
+    }
+    fragment
+}
+
+/// Writes the fragment to `path`.
+pub fn write(path: &Path, fragment: &str) -> Result<(), BmError> {
+    fs::write(path, fragment).map_err(|e| BmError::Packaging(format!("Unable to write `{}`: {}", path.display(), e)))
+}
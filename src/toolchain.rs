@@ -0,0 +1,66 @@
+//! The project's pinned toolchain, from `rust-toolchain.toml` (or the legacy `rust-toolchain` file).
+
+use crate::error::BmError;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct ToolchainFile {
+    toolchain: ToolchainSection,
+}
+
+#[derive(Deserialize)]
+struct ToolchainSection {
+    channel: Option<String>,
+}
+
+/// Printed to stderr inside the container when the toolchain can't be installed, so the failure can be told apart
+/// from a compile error.
+pub const INSTALL_FAILED: &str = "black_magic: unable to install toolchain";
+
+pub struct Toolchain {
+    /// E.g. `stable`, `1.70.0`, or `nightly-2020-04-23`.
+    pub channel: String,
+}
+
+impl Toolchain {
+    /// Reads the toolchain file from the project directory, if there is one that names a channel.
+    pub fn detect(project_dir: &Path) -> Result<Option<Toolchain>, BmError> {
+        for name in &["rust-toolchain.toml", "rust-toolchain"] {
+            let path = project_dir.join(name);
+            if !path.exists() {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&path).map_err(|e| BmError::Environment(format!("Unable to read `{}`: {}", name, e)))?;
+
+            // The legacy file may be toml too, or just the bare channel name.
+            let channel = match toml::from_str::<ToolchainFile>(&contents) {
+                Ok(file) => file.toolchain.channel,
+                Err(_) if *name == "rust-toolchain" => Some(contents.trim().to_owned()),
+                Err(e) => return Err(BmError::Environment(format!("Unable to parse `{}`: {}", name, e))),
+            };
+
+            let channel = match channel.filter(|c| !c.is_empty()) {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+            // It ends up in a shell command inside the container.
+            if !channel.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_') {
+                return Err(BmError::Environment(format!("`{}` doesn't look like a valid toolchain in `{}`.", channel, name)));
+            }
+            return Ok(Some(Toolchain { channel }));
+        }
+
+        Ok(None)
+    }
+
+    /// Shell command installing the toolchain, and the target for it, inside the build container.
+    /// Builder images only ship their own toolchain, so a pinned one always needs installing.
+    pub fn install_cmd(&self, target_triple: &str) -> String {
+        format!(
+            "(rustup toolchain install {} --profile minimal --target {} || (echo '{} {}' >&2 && exit 1))",
+            self.channel, target_triple, INSTALL_FAILED, self.channel)
+    }
+}
@@ -0,0 +1,171 @@
+//! Cargo's feature unification, when the cargo arguments select several workspace packages (`--workspace`, or `-p` more than
+//! once) to compile together.
+//!
+//! Cargo compiles each dependency once per build, with every feature any of the selected packages asks for. So a lambda compiled
+//! alongside another one can end up with dependency features (and their code, and their size) it never asked for. Before
+//! compiling, each package's dependencies are resolved on their own and all together with `cargo tree`, and the features
+//! unification adds are reported.
+//!
+//! `--isolate-features` compiles just the executable's package instead, so it gets exactly its own features.
+
+use crate::metadata::Metadata;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+/// The packages a build selects with its cargo arguments.
+#[derive(Default)]
+pub struct Selection {
+    packages: Vec<String>,
+    workspace: bool,
+    excluded: Vec<String>,
+}
+
+/// Each dependency (`name vX.Y.Z`), and the features it's compiled with.
+type Features = BTreeMap<String, BTreeSet<String>>;
+
+/// The value of the flag `args[*i]` if it's one of `flags`, as `-p x`, `--package=x` or `-px`, moving `i` past it.
+fn flag_value(args: &[String], i: &mut usize, flags: &[&str]) -> Option<String> {
+    let arg = &args[*i];
+    for flag in flags {
+        if arg == flag {
+            *i += 1;
+            return args.get(*i).cloned();
+        }
+        let joined = if flag.starts_with("--") { format!("{}=", flag) } else { (*flag).to_owned() };
+        if let Some(value) = arg.strip_prefix(&joined).filter(|v| !v.is_empty()) {
+            return Some(value.to_owned());
+        }
+    }
+    None
+}
+
+impl Selection {
+    pub fn parse(cargo_args: &[String]) -> Selection {
+        let mut selection = Selection::default();
+        let mut i = 0;
+        while i < cargo_args.len() {
+            if let Some(package) = flag_value(cargo_args, &mut i, &["--package", "-p"]) {
+                selection.packages.push(package);
+            } else if let Some(package) = flag_value(cargo_args, &mut i, &["--exclude"]) {
+                selection.excluded.push(package);
+            } else if cargo_args[i] == "--workspace" || cargo_args[i] == "--all" {
+                selection.workspace = true;
+            }
+            i += 1;
+        }
+        selection
+    }
+
+    /// Whether more than one package might be selected, i.e. whether `packages` is worth resolving.
+    pub fn is_several(&self) -> bool {
+        self.workspace || self.packages.len() > 1
+    }
+
+    /// The names of the selected packages.
+    pub fn packages(&self, metadata: &Metadata) -> Vec<String> {
+        if !self.workspace {
+            return self.packages.clone();
+        }
+        metadata.workspace_members
+            .iter()
+            .filter_map(|id| metadata.package(id))
+            .map(|p| p.name.clone())
+            .filter(|name| !self.excluded.contains(name))
+            .collect()
+    }
+}
+
+/// `cargo_args` without whatever selects packages, since only the executable's package is compiled.
+pub fn isolate(cargo_args: &[String], package: &str) -> Vec<String> {
+    let mut isolated = Vec::new();
+    let mut i = 0;
+    while i < cargo_args.len() {
+        let selects = flag_value(cargo_args, &mut i, &["--package", "-p", "--exclude"]).is_some()
+            || cargo_args[i] == "--workspace"
+            || cargo_args[i] == "--all";
+        if !selects {
+            isolated.push(cargo_args[i].clone());
+        }
+        i += 1;
+    }
+    isolated.push("--package".to_owned());
+    isolated.push(package.to_owned());
+    isolated
+}
+
+/// The workspace package building the executable `binary`.
+pub fn executable_package(metadata: &Metadata, binary: &str) -> Option<String> {
+    metadata.workspace_members
+        .iter()
+        .filter_map(|id| metadata.package(id))
+        .find(|p| p.targets.iter().any(|t| t.name == binary && t.kind.iter().any(|k| k == "bin")))
+        .map(|p| p.name.clone())
+}
+
+/// Just the arguments selecting features, which `cargo tree` understands too.
+fn feature_args(cargo_args: &[String]) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut i = 0;
+    while i < cargo_args.len() {
+        if let Some(features) = flag_value(cargo_args, &mut i, &["--features", "-F"]) {
+            args.push("--features".to_owned());
+            args.push(features);
+        } else if cargo_args[i] == "--no-default-features" || cargo_args[i] == "--all-features" {
+            args.push(cargo_args[i].clone());
+        }
+        i += 1;
+    }
+    args
+}
+
+/// The features of every dependency compiled for `packages` together, as `cargo tree` resolves them.
+fn resolve(project_dir: &Path, target: &str, packages: &[String], cargo_args: &[String], frozen: bool) -> Result<Features, String> {
+    let mut cmd = Command::new("cargo");
+    cmd.current_dir(project_dir)
+        .args(["tree", "--edges", "normal,build", "--prefix", "none", "--format", "{p}|{f}", "--target", target]);
+    for package in packages {
+        cmd.arg("--package").arg(package);
+    }
+    cmd.args(feature_args(cargo_args));
+    if frozen {
+        cmd.arg("--frozen");
+    }
+    let output = cmd.output().map_err(|e| format!("Unable to run `cargo tree`: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("`cargo tree` failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    let mut features = Features::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // Dependencies already listed are marked `(*)`.
+        if let Some((package, enabled)) = line.trim_end_matches(" (*)").split_once('|') {
+            features.entry(package.to_owned()).or_default().extend(enabled.split(',').filter(|f| !f.is_empty()).map(|f| f.to_owned()));
+        }
+    }
+    Ok(features)
+}
+
+/// A dependency of `package` that gets more features compiled with the others than on its own.
+pub struct Change {
+    pub package: String,
+    pub dependency: String,
+    pub added: Vec<String>,
+}
+
+/// Compares each package's dependency features on its own against `packages` compiled together.
+pub fn compare(project_dir: &Path, target: &str, packages: &[String], cargo_args: &[String], frozen: bool) -> Result<Vec<Change>, String> {
+    let unified = resolve(project_dir, target, packages, cargo_args, frozen)?;
+    let mut changes = Vec::new();
+    for package in packages {
+        let alone = resolve(project_dir, target, std::slice::from_ref(package), cargo_args, frozen)?;
+        for (dependency, features) in alone {
+            let added: Vec<String> = unified.get(&dependency).into_iter().flatten().filter(|f| !features.contains(*f)).cloned().collect();
+            if !added.is_empty() {
+                changes.push(Change { package: package.clone(), dependency, added });
+            }
+        }
+    }
+    Ok(changes)
+}
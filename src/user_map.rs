@@ -0,0 +1,56 @@
+//! `--user-map`: giving what the build container writes into the host's directories back to the user running black_magic.
+//!
+//! The build container runs as root, so where the daemon shares the host's users (docker on Linux), what it writes into the
+//! project (`target/black_magic`, and a `Cargo.lock` cargo creates or updates) and into the host's cargo home (the registry
+//! and git checkouts it downloads) is owned by root, and `cargo clean` on the host then fails. Running the container as the
+//! user instead would leave it unable to write its own paths (the executable goes at `/`, and the builder image's toolchain
+//! is root's), so instead, however the container's command ends, it `chown`s those back to the user's uid and gid.
+//!
+//! It's on by default for docker on Linux, unless docker is rootless, which already maps root to the user, as podman does
+//! when rootless. `--user-map` turns it on anywhere (e.g. for rootful podman), and `--no-user-map` or `user_map = false` in
+//! `[build]` turns it off.
+
+use crate::runtime::Runtime;
+
+/// Who the build's files are given to.
+pub struct Owner {
+    uid: u32,
+    gid: u32,
+}
+
+#[cfg(unix)]
+fn current() -> Option<Owner> {
+    // Neither can fail.
+    Some(Owner { uid: unsafe { libc::getuid() }, gid: unsafe { libc::getgid() } })
+}
+
+#[cfg(not(unix))]
+fn current() -> Option<Owner> {
+    None
+}
+
+/// Whether the daemon is rootless docker, whose root is the user already.
+fn rootless(runtime: Runtime) -> bool {
+    runtime.command()
+        .args(["info", "--format", "{{json .SecurityOptions}}"])
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).contains("rootless"))
+        .unwrap_or(false)
+}
+
+/// The owner to give files to, if any: always with `requested`, never with it `false`, otherwise where it's needed.
+pub fn owner(runtime: Runtime, requested: Option<bool>) -> Option<Owner> {
+    match requested {
+        Some(true) => current(),
+        Some(false) => None,
+        None if cfg!(target_os = "linux") && runtime == Runtime::Docker => current().filter(|o| o.uid != 0 && !rootless(runtime)),
+        None => None,
+    }
+}
+
+impl Owner {
+    /// The start of the build container's command, `chown`ing `paths` when it exits, successfully or not.
+    pub fn trap_cmd(&self, paths: &[String]) -> String {
+        format!("trap 'chown -R {}:{} {} > /dev/null 2>&1' EXIT && ", self.uid, self.gid, paths.join(" "))
+    }
+}
@@ -0,0 +1,100 @@
+//! `--offline` and `--vendor <dir>`: builds that never touch the network, for CI without internet access.
+//!
+//! `--offline` compiles with `cargo build --offline`, so everything has to be in the cargo registry (or cache volume) already.
+//! `--vendor <dir>` builds from a directory made with `cargo vendor` instead, and is offline too. The source replacement
+//! `cargo vendor` prints is passed to cargo as `--config` arguments, generated from `Cargo.lock`, so the project's own cargo
+//! config doesn't need it. A directory inside the project is already in the build container, one outside it is mounted.
+
+use crate::error::BmError;
+use crate::runtime::Runtime;
+use std::fs;
+use std::path::Path;
+
+const CONTAINER_DIR: &str = "/bm_vendor";
+
+const VENDORED: &str = "vendored-sources";
+
+/// The vendored sources, as cargo sees them.
+pub struct Vendor {
+    /// Where the directory is in the build container, or on the host for backends compiling there.
+    pub path: String,
+    /// Mounts the directory, if it's outside the project.
+    pub volume: Option<String>,
+}
+
+impl Vendor {
+    /// Checks `dir` is a `cargo vendor` directory, and finds it from where the build runs: the build container `runtime`
+    /// runs, or this machine, for backends compiling on the host.
+    pub fn resolve(runtime: Option<Runtime>, dir: &str, project_dir: &Path, container_dir: &str) -> Result<Vendor, BmError> {
+        let host_dir = project_dir.join(dir);
+        let vendored = fs::read_dir(&host_dir).into_iter().flatten().filter_map(|e| e.ok()).any(|e| e.path().join(".cargo-checksum.json").is_file());
+        if !vendored {
+            return Err(BmError::Environment(format!(
+                "`{}` isn't a `cargo vendor` directory, with a `.cargo-checksum.json` in each crate. Create it with `cargo vendor {}`.",
+                host_dir.display(), dir)));
+        }
+        let runtime = match runtime {
+            Some(runtime) => runtime,
+            None => return Ok(Vendor { path: crate::path_str(&crate::mounts::host_path(&host_dir))?.to_owned(), volume: None }),
+        };
+        let real_dir = crate::mounts::host_path(&host_dir);
+        match real_dir.strip_prefix(crate::mounts::host_path(project_dir)) {
+            Ok(relative) => {
+                let relative: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+                Ok(Vendor { path: format!("{}/{}", container_dir, relative.join("/")), volume: None })
+            }
+            Err(_) => Ok(Vendor {
+                path: CONTAINER_DIR.to_owned(),
+                volume: Some(format!("{}:ro", crate::mounts::volume(runtime, &real_dir, CONTAINER_DIR)?)),
+            }),
+        }
+    }
+
+    /// The `cargo build` arguments replacing crates.io, and every git source in the project's `Cargo.lock`, with the directory.
+    pub fn cargo_args(&self, project_dir: &Path) -> Vec<String> {
+        let mut config = vec![
+            "source.crates-io.replace-with=\"vendored-sources\"".to_owned(),
+            format!("source.{}.directory={}", VENDORED, toml_string(&self.path)),
+        ];
+        for source in git_sources(project_dir) {
+            // `git+<url>[?branch=...|tag=...|rev=...]`, as the source is named.
+            let (url, reference) = match source.trim_start_matches("git+").split_once('?') {
+                Some((url, reference)) => (url, reference.split_once('=')),
+                None => (source.trim_start_matches("git+"), None),
+            };
+            let key = format!("source.{}", toml_string(&source));
+            config.push(format!("{}.git={}", key, toml_string(url)));
+            if let Some((kind, value)) = reference {
+                config.push(format!("{}.{}={}", key, kind, toml_string(value)));
+            }
+            config.push(format!("{}.replace-with=\"{}\"", key, VENDORED));
+        }
+        let mut args = vec!["--offline".to_owned()];
+        for c in config {
+            args.push("--config".to_owned());
+            args.push(c);
+        }
+        args
+    }
+}
+
+fn toml_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// The distinct git sources of the packages in `Cargo.lock`.
+fn git_sources(project_dir: &Path) -> Vec<String> {
+    let lock = fs::read_to_string(project_dir.join("Cargo.lock")).ok().and_then(|l| toml::from_str::<toml::Value>(&l).ok());
+    let mut sources: Vec<String> = lock.as_ref()
+        .and_then(|l| l.get("package")?.as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|p| p.get("source")?.as_str())
+        .filter(|s| s.starts_with("git+"))
+        // Without the commit, after the `#`.
+        .map(|s| s.split('#').next().unwrap_or(s).to_owned())
+        .collect();
+    sources.sort();
+    sources.dedup();
+    sources
+}
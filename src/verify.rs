@@ -0,0 +1,106 @@
+//! Checking the finished artifact, before it goes anywhere: what Lambda or a container would otherwise only find out when it
+//! fails to start.
+//!
+//! - the executables (`bootstrap` at the zip's root, and what it runs if it's a wrapper, or the image's executable) are
+//!   there, as regular files, recorded as executable
+//! - every symlink points at something in the artifact. A zip is unpacked into `/var/task`, so its symlinks have to be
+//!   relative, and stay inside it. A `scratch` image has nothing but the tar, so the same goes for it, except absolute
+//!   symlinks are fine. Images on other bases can point into the base, which isn't checked.
+
+use crate::archive;
+use crate::archive::Entry;
+use crate::archive::EntryKind;
+use crate::error::BmError;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// How many symlinks to follow before giving up on one, like Linux's `MAXSYMLINKS`.
+const MAX_LINKS: usize = 40;
+
+/// `target` of a symlink at `path`, as a path in the archive, or `None` if it leaves it.
+fn resolve(path: &str, target: &str) -> Option<String> {
+    let mut parts: Vec<&str> = if target.starts_with('/') { Vec::new() } else { path.split('/').collect() };
+    if !target.starts_with('/') {
+        parts.pop();
+    }
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            p => parts.push(p),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// What's wrong with the symlink at `path`, following it through any others.
+fn symlink_problem(entries: &HashMap<&str, &Entry>, path: &str, absolute_ok: bool) -> Option<String> {
+    let mut current = path.to_owned();
+    for _ in 0..MAX_LINKS {
+        let entry = match entries.get(current.as_str()) {
+            Some(e) => e,
+            // A directory only there as the parent of what's in it, as tar leaves them.
+            None if entries.keys().any(|p| p.starts_with(&format!("{}/", current))) => return None,
+            None => return Some(format!("`{}` is a symlink to `{}`, which isn't in the artifact.", path, current)),
+        };
+        if entry.kind != EntryKind::Symlink {
+            return None;
+        }
+        let target = String::from_utf8_lossy(&entry.contents).into_owned();
+        if target.starts_with('/') && !absolute_ok {
+            return Some(format!("`{}` is a symlink to `{}`, which is outside the zip once it's unpacked.", path, target));
+        }
+        current = match resolve(&current, &target) {
+            Some(c) => c,
+            None => return Some(format!("`{}` is a symlink to `{}`, which is outside the artifact.", path, target)),
+        };
+    }
+    Some(format!("`{}` is a symlink in a loop.", path))
+}
+
+fn check(entries: &[Entry], executables: &[&str], symlinks: Option<bool>) -> Result<(), BmError> {
+    let by_path: HashMap<&str, &Entry> = entries.iter().map(|e| (e.path.as_str(), e)).collect();
+    let mut problems = Vec::new();
+    for executable in executables {
+        match by_path.get(executable) {
+            None => problems.push(format!("`{}` isn't in the artifact, at its root.", executable)),
+            Some(e) if e.kind != EntryKind::File => problems.push(format!("`{}` isn't a regular file.", executable)),
+            Some(e) => match e.mode {
+                Some(mode) if mode & 0o111 == 0o111 => {}
+                mode => problems.push(format!(
+                    "`{}` is recorded without its executable permissions ({}), so it can't be run.",
+                    executable, mode.map(|m| format!("mode {:o}", m)).unwrap_or_else(|| "no mode at all".to_owned()))),
+            },
+        }
+    }
+    if let Some(absolute_ok) = symlinks {
+        problems.extend(entries.iter()
+            .filter(|e| e.kind == EntryKind::Symlink)
+            .filter_map(|e| symlink_problem(&by_path, &e.path, absolute_ok)));
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(BmError::Packaging(format!("The artifact wouldn't work:\n    - {}", problems.join("\n    - "))))
+}
+
+fn read(artifact: &Path) -> Result<Vec<u8>, BmError> {
+    fs::read(artifact).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))
+}
+
+/// Checks a lambda zip, with `bootstrap` and any other `executables`.
+pub fn zip(zip: &Path, executables: &[&str]) -> Result<(), BmError> {
+    let entries = archive::read_zip(&read(zip)?).map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?;
+    check(&entries, executables, Some(false))
+}
+
+/// Checks an image's tar, with its `executable`. Only a `scratch` image's symlinks are checked.
+pub fn tar(tar: &Path, executable: &str, scratch: bool) -> Result<(), BmError> {
+    let entries = archive::gunzip(&read(tar)?)
+        .and_then(|t| archive::read_tar(&t))
+        .map_err(|e| BmError::Packaging(format!("Unable to read artifact: {}", e)))?;
+    check(&entries, &[executable], scratch.then_some(true))
+}
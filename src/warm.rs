@@ -0,0 +1,45 @@
+//! `black_magic warm`: compiling just the project's dependencies into the cache volumes ahead of time, e.g. after a
+//! `Cargo.lock` change or nightly in CI, so the first real build only compiles the project itself.
+//!
+//! It's a `--deps-only` build, for `--docker` or the build's own options with `--args`, e.g.
+//! `black_magic warm --args "--lambda --arch aarch64"`. That writes the recipe `--layered` builds compile their dependencies
+//! from (`Cargo.toml` and `Cargo.lock`, with empty targets, see `layered`) into `target/black_magic/warm_recipe`, and runs
+//! the build container on it, with the build's mounts, environment and `cargo build` options, so the registry and git
+//! checkouts (the host's cargo home, or `--cargo-home-volume`'s) and the target cache volume end up with exactly what the
+//! build would otherwise download and compile. Nothing's packaged, and there's no artifact.
+//!
+//! The cache volume is the point, so it can't be used with `--no-cache`, nor with `--layered` builds or remote daemons, whose
+//! dependencies are cached as image layers, nor with the host backend.
+
+use crate::error::BmError;
+use crate::preset;
+use clap::ArgMatches;
+use std::iter;
+
+/// Where the recipe's written, in `target/black_magic`.
+pub const RECIPE_DIR: &str = "warm_recipe";
+
+/// The build container's command for `--deps-only`, compiling the recipe with `deps_cmd`.
+pub fn cargo_cmd(deps_cmd: &str) -> String {
+    format!("cd target/black_magic/{} && {}", RECIPE_DIR, deps_cmd)
+}
+
+/// `black_magic warm`.
+pub fn warm(matches: &ArgMatches) -> Result<(), BmError> {
+    let mut args: Vec<String> = matches.value_of("ARGS").unwrap().split_whitespace().map(|a| a.to_owned()).collect();
+    for (arg, flag) in [("RUNTIME", "--runtime"), ("NAME", "--name")] {
+        if let Some(value) = matches.value_of(arg).filter(|_| !args.iter().any(|a| a == flag)) {
+            args.push(flag.to_owned());
+            args.push(value.to_owned());
+        }
+    }
+    let args: Vec<String> = iter::once("black_magic".to_owned()).chain(args).chain(iter::once("--deps-only".to_owned())).collect();
+    let build_matches = crate::app()
+        .get_matches_from_safe(&args)
+        .map_err(|e| BmError::Environment(format!("Invalid `--args`: {}", e)))?;
+    let build_matches = preset::apply(build_matches, args)?;
+    if build_matches.subcommand_name().is_some() {
+        return Err(BmError::Environment("`--args` are a build's options, not another subcommand.".to_owned()));
+    }
+    crate::run(&build_matches)
+}
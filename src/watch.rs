@@ -0,0 +1,158 @@
+//! `--watch`: rebuilding whenever the source changes, for quick local iteration.
+//!
+//! `src/`, `Cargo.toml`, `Cargo.lock` and `BlackMagic.toml` are watched with the OS's file notifications (see `notify`),
+//! without following symlinks. Once they've settled for a moment (so saving several files, or a `git checkout`, is one
+//! rebuild), the same build runs again, as its own black_magic process, and a one line summary is printed. A change during a
+//! build is a rebuild straight after it. A build failing doesn't stop the watch. Rebuilds reuse the cache volume, so only the
+//! project itself is compiled again.
+
+use crate::error::BmError;
+use crate::output;
+use crate::output::status;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify_debouncer_mini::DebounceEventResult;
+use notify_debouncer_mini::Debouncer;
+use serde_json::json;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long nothing has to change for before rebuilding.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// The watched files, besides `src/`.
+const FILES: &[&str] = &["Cargo.toml", "Cargo.lock", "BlackMagic.toml"];
+
+/// Watches the project in `project_dir`, sending settled changes to the returned receiver while the `Debouncer` is kept.
+fn start(project_dir: &Path) -> Result<(Debouncer<RecommendedWatcher>, Receiver<DebounceEventResult>), BmError> {
+    let failed = |e: notify::Error| BmError::Environment(format!("Unable to watch `{}` for changes: {}", project_dir.display(), e));
+    let (sender, receiver) = mpsc::channel();
+    let config = notify_debouncer_mini::Config::default()
+        .with_timeout(DEBOUNCE)
+        .with_notify_config(notify::Config::default().with_follow_symlinks(false));
+    let mut debouncer = notify_debouncer_mini::new_debouncer_opt::<_, RecommendedWatcher>(config, sender).map_err(failed)?;
+    // The project itself for the files, which may not exist yet.
+    debouncer.watcher().watch(project_dir, RecursiveMode::NonRecursive).map_err(failed)?;
+    let src = project_dir.join("src");
+    if src.is_dir() {
+        debouncer.watcher().watch(&src, RecursiveMode::Recursive).map_err(failed)?;
+    }
+    Ok((debouncer, receiver))
+}
+
+fn watched(project_dir: &Path, path: &Path) -> bool {
+    path.starts_with(project_dir.join("src")) || FILES.iter().any(|f| path == project_dir.join(f))
+}
+
+/// Blocks until the watched files change, and then stop changing. Returns what changed.
+fn wait_for_change(project_dir: &Path, changes: &Receiver<DebounceEventResult>) -> Result<Vec<PathBuf>, BmError> {
+    loop {
+        let events = changes.recv().map_err(|_| BmError::Environment("Stopped watching for changes.".to_owned()))?;
+        let mut changed: Vec<PathBuf> = match events {
+            Ok(events) => events.into_iter().map(|e| e.path).filter(|p| watched(project_dir, p)).collect(),
+            Err(e) => {
+                output::warning(&format!("Unable to watch for changes: {}", e));
+                continue;
+            }
+        };
+        // Anything else that's settled since, e.g. during the last build.
+        changed.extend(changes.try_iter().flatten().flatten().map(|e| e.path).filter(|p| watched(project_dir, p)));
+        if !changed.is_empty() {
+            return Ok(changed);
+        }
+    }
+}
+
+/// Runs one build, and describes how it went.
+fn rebuild(exe: &Path, args: &[String], project_dir: &Path) -> (bool, String, Value) {
+    let started = Instant::now();
+    let output = Command::new(exe).arg("--output-format").arg("json").args(args).current_dir(project_dir).output();
+    let seconds = started.elapsed().as_secs_f64();
+    let output = match output {
+        Ok(o) => o,
+        Err(e) => return (false, format!("Unable to run black_magic: {}", e), Value::Null),
+    };
+
+    // The last line is the `done` or `error` record, see `output`.
+    let record: Value = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .last()
+        .and_then(|l| serde_json::from_str(l).ok())
+        .unwrap_or(Value::Null);
+    let summary = if record["event"] == "done" {
+        let artifact = record["image"].as_str().or_else(|| record["artifact"].as_str()).unwrap_or("the artifact");
+        format!("Built {} ({:.2} MiB) in {:.1}s.", artifact, record["size"].as_f64().unwrap_or(0.0) / (1024.0 * 1024.0), seconds)
+    } else {
+        let message = record["message"].as_str().map(|m| m.to_owned()).unwrap_or_else(|| String::from_utf8_lossy(&output.stderr).into_owned());
+        // The full explanation can be long, e.g. the whole compiler output, so the summary is just its start.
+        if output::is_verbose() {
+            eprintln!("{}", message);
+        }
+        format!(
+            "Build failed ({}) in {:.1}s: {}",
+            record["kind"].as_str().unwrap_or("unknown"), seconds, message.lines().next().unwrap_or("").trim())
+    };
+    (output.status.success(), summary, record)
+}
+
+/// Runs the build, then again every time the source changes, until interrupted.
+pub fn watch(matches: &clap::ArgMatches) -> Result<(), BmError> {
+    if matches.is_present("NO_CACHE") || matches.is_present("NO_SIDE_EFFECTS") || matches.is_present("DRY_RUN") {
+        return Err(BmError::Environment("`--watch` can't be used with `--no-cache`, `--no-side-effects` or `--dry-run`.".to_owned()));
+    }
+    let project_dir = crate::mounts::current_dir()?;
+    let exe = env::current_exe().map_err(|e| BmError::Environment(format!("Unable to find the black_magic executable: {}", e)))?;
+    let args = crate::forwarded_args(&["--watch"], &[]);
+
+    // Some platforms report changes by their real paths.
+    let watched_dir = fs::canonicalize(&project_dir).unwrap_or_else(|_| project_dir.clone());
+    let (_debouncer, changes) = start(&watched_dir)?;
+    for build in 1.. {
+        if build > 1 {
+            let changed = wait_for_change(&watched_dir, &changes)?;
+            let changed: Vec<String> = changed.iter().map(|p| p.strip_prefix(&watched_dir).unwrap_or(p).display().to_string()).collect();
+            output::detail(&format!("Changed: {}", changed.join(", ")));
+        }
+        status!("[{}] Building...", build);
+        let (succeeded, summary, record) = rebuild(&exe, &args, &project_dir);
+        output::marker("REBUILD", &[("build", &build.to_string()), ("succeeded", &succeeded.to_string())]);
+        if output::is_json() {
+            output::emit("rebuild", json!({ "build": build, "succeeded": succeeded, "summary": summary, "record": record }));
+        } else {
+            status!("[{}] {}", build, summary);
+            status!("Watching for changes, press Ctrl-C to stop.");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn reports_settled_changes_to_watched_files() {
+        let project = tempfile::tempdir().unwrap();
+        let project = fs::canonicalize(project.path()).unwrap();
+        fs::create_dir_all(project.join("src/bin")).unwrap();
+        // Neither followed nor recursed into forever.
+        symlink("..", project.join("src/bin/loop")).unwrap();
+        let (_debouncer, changes) = start(&project).unwrap();
+
+        fs::write(project.join("README.md"), "not watched").unwrap();
+        fs::write(project.join("src/bin/main.rs"), "fn main() {}").unwrap();
+        fs::write(project.join("Cargo.lock"), "# created").unwrap();
+        let changed = wait_for_change(&project, &changes).unwrap();
+        assert!(changed.contains(&project.join("src/bin/main.rs")), "{:?}", changed);
+        assert!(!changed.contains(&project.join("README.md")), "{:?}", changed);
+    }
+}
@@ -0,0 +1,113 @@
+//! Projects needing more than their own directory to build: a workspace member, e.g. `services/api`, or one depending on
+//! crates outside it by path, e.g. `../../libs/common`.
+//!
+//! The build container only gets the project mounted otherwise, where cargo can't find the workspace, or the crates
+//! outside it. So for these, the directory holding all of them is mounted at `/workdir` instead, and the build runs in the
+//! project inside it, e.g. at `/workdir/services/api`, writing `target/black_magic` there as always. That directory is
+//! found from the manifests, without cargo: it's the closest one holding the project, its workspace's root (where a
+//! `Cargo.toml` has a `[workspace]`, in the project's `workspace` or above it), and every crate depended on by `path`,
+//! from the project, those crates in turn, `[workspace.dependencies]` and `[patch]`.
+//!
+//! `--layered` builds, and remote daemons, send just the project to `docker build` as its context, so they build these
+//! with only the project, warning that it may not build as it does in the workspace.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Where the build container has the project, or the directory holding it and what it needs.
+pub const CONTAINER_DIR: &str = "/workdir";
+
+pub struct Workspace {
+    /// What's mounted at `/workdir`.
+    pub root: PathBuf,
+    /// The project, as the build container has it, e.g. `/workdir/services/api`.
+    pub project_dir: String,
+    /// The `Cargo.lock` cargo uses for the project: its workspace's, or its own when it isn't in one.
+    pub lockfile: PathBuf,
+}
+
+fn manifest(dir: &Path) -> Option<toml::Value> {
+    fs::read_to_string(dir.join("Cargo.toml")).ok().and_then(|m| toml::from_str(&m).ok())
+}
+
+/// The dependency tables of `manifest`, of every kind and for every target.
+fn dependency_tables(manifest: &toml::Value) -> Vec<&toml::value::Table> {
+    let kinds = ["dependencies", "dev-dependencies", "build-dependencies"];
+    let targets = manifest.get("target").and_then(|t| t.as_table()).into_iter().flat_map(|t| t.values());
+    std::iter::once(manifest)
+        .chain(targets)
+        .flat_map(|m| kinds.iter().filter_map(move |k| m.get(*k).and_then(|t| t.as_table())))
+        .collect()
+}
+
+/// The `path` of each dependency in `tables`, from `dir`.
+fn paths<'a>(dir: &'a Path, tables: impl Iterator<Item = &'a toml::value::Table> + 'a) -> impl Iterator<Item = PathBuf> + 'a {
+    tables.flat_map(|t| t.values()).filter_map(move |d| d.get("path").and_then(|p| p.as_str()).map(|p| dir.join(p)))
+}
+
+/// The root of the workspace `dir`'s package is in, if it is in one.
+fn workspace_root(dir: &Path, package: &toml::Value) -> Option<PathBuf> {
+    if package.get("workspace").is_some() {
+        return Some(dir.to_owned());
+    }
+    if let Some(explicit) = package.get("package").and_then(|p| p.get("workspace")).and_then(|w| w.as_str()) {
+        return Some(dir.join(explicit));
+    }
+    // As cargo looks for it, unless that workspace excludes the package.
+    dir.ancestors().skip(1).find_map(|root| {
+        let workspace = manifest(root)?.get("workspace")?.clone();
+        let relative = dir.strip_prefix(root).ok()?;
+        let excludes = workspace.get("exclude").and_then(|e| e.as_array()).into_iter().flatten().filter_map(|e| e.as_str());
+        let excluded = excludes.map(|e| e.trim_end_matches('/')).any(|e| relative.starts_with(e));
+        Some(root.to_owned()).filter(|_| !excluded)
+    })
+}
+
+/// The closest directory holding all of `dirs`.
+fn common_ancestor(dirs: &BTreeSet<PathBuf>) -> Option<PathBuf> {
+    let mut dirs = dirs.iter();
+    let mut common = dirs.next()?.to_owned();
+    for dir in dirs {
+        while !dir.starts_with(&common) {
+            common = common.parent()?.to_owned();
+        }
+    }
+    Some(common)
+}
+
+/// How to mount the project in `project_dir`, if it needs more than itself.
+pub fn detect(project_dir: &Path) -> Option<Workspace> {
+    let project = crate::mounts::host_path(project_dir);
+    let project_manifest = manifest(&project)?;
+    let mut needed: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut pending = vec![project.clone()];
+
+    let mut lockfile = project.join("Cargo.lock");
+    if let Some(root) = workspace_root(&project, &project_manifest).map(|r| crate::mounts::host_path(&r)) {
+        lockfile = root.join("Cargo.lock");
+        if let Some(root_manifest) = manifest(&root) {
+            let workspace_dependencies = root_manifest.get("workspace").and_then(|w| w.get("dependencies")).and_then(|d| d.as_table());
+            let patches = root_manifest.get("patch").and_then(|p| p.as_table()).into_iter().flat_map(|p| p.values()).filter_map(|p| p.as_table());
+            pending.extend(paths(&root, workspace_dependencies.into_iter().chain(patches)));
+        }
+        pending.push(root);
+    }
+    while let Some(dir) = pending.pop() {
+        let dir = crate::mounts::host_path(&dir);
+        if !needed.insert(dir.clone()) {
+            continue;
+        }
+        if let Some(manifest) = manifest(&dir) {
+            pending.extend(paths(&dir, dependency_tables(&manifest).into_iter()));
+        }
+    }
+
+    let root = common_ancestor(&needed)?;
+    if root == project {
+        return None;
+    }
+    let relative: Vec<String> = project.strip_prefix(&root).ok()?.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    Some(Workspace { project_dir: format!("{}/{}", CONTAINER_DIR, relative.join("/")), root, lockfile })
+}
@@ -0,0 +1,110 @@
+// The `bootstrap` wrapper, compiled inside the build container (see `wrapper`), after the settings generated above it.
+// It has to compile with the builder image's own (old) toolchain, with nothing but std.
+
+use std::env;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::os::unix::process::CommandExt;
+use std::process;
+use std::process::Command;
+
+fn fail(message: String) -> ! {
+    eprintln!("bootstrap: {}", message);
+    process::exit(1)
+}
+
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// Reads the JSON string starting at the beginning of `json`, just after its opening quote.
+fn json_string(json: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = json.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let mut code = u32::from_str_radix(&code, 16).ok()?;
+                    // Characters outside the BMP come as a surrogate pair.
+                    if (0xD800..0xDC00).contains(&code) {
+                        let low: String = chars.by_ref().skip(2).take(4).collect();
+                        let low = u32::from_str_radix(&low, 16).ok()?;
+                        code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                    }
+                    value.push(std::char::from_u32(code)?);
+                }
+                other => value.push(other),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Gets `path` from the AWS Parameters and Secrets Lambda Extension, and the string in the response's `field`.
+fn fetch(path: &str, field: &str) -> Result<String, String> {
+    let port = env::var("PARAMETERS_SECRETS_EXTENSION_HTTP_PORT").unwrap_or_else(|_| "2773".to_owned());
+    let token = env::var("AWS_SESSION_TOKEN").map_err(|_| "`AWS_SESSION_TOKEN` isn't set".to_owned())?;
+    let mut stream = TcpStream::connect(("127.0.0.1", port.parse::<u16>().unwrap_or(2773)))
+        .map_err(|e| format!("unable to reach the Parameters and Secrets extension, is its layer added? {}", e))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: localhost\r\nX-Aws-Parameters-Secrets-Token: {}\r\n\r\n", path, token);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(|e| e.to_string())?;
+
+    let status = response.lines().next().unwrap_or("");
+    let body = response.find("\r\n\r\n").map(|i| &response[i + 4..]).unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("{}: {}", status, body.trim()));
+    }
+    let key = format!("\"{}\"", field);
+    let start = body.find(&key).ok_or_else(|| format!("no `{}` in the response", field))? + key.len();
+    let quote = body[start..].find('"').ok_or_else(|| format!("`{}` isn't a string", field))?;
+    json_string(&body[start + quote + 1..]).ok_or_else(|| format!("unable to read `{}`", field))
+}
+
+fn main() {
+    for &(key, value) in DEFAULTS {
+        if env::var_os(key).is_none() {
+            env::set_var(key, value);
+        }
+    }
+    if let (Some(key), Ok(handler)) = (HANDLER, env::var("_HANDLER")) {
+        env::set_var(key, handler);
+    }
+    for &(key, name) in PARAMETERS {
+        let path = format!("/systemsmanager/parameters/get?name={}&withDecryption=true", url_encode(name));
+        match fetch(&path, "Value") {
+            Ok(value) => env::set_var(key, value),
+            Err(e) => fail(format!("unable to get the `{}` parameter for `{}`: {}", name, key, e)),
+        }
+    }
+    for &(key, id) in SECRETS {
+        match fetch(&format!("/secretsmanager/get?secretId={}", url_encode(id)), "SecretString") {
+            Ok(value) => env::set_var(key, value),
+            Err(e) => fail(format!("unable to get the `{}` secret for `{}`: {}", id, key, e)),
+        }
+    }
+
+    let executable = format!("{}/{}", env::var("LAMBDA_TASK_ROOT").unwrap_or_else(|_| "/var/task".to_owned()), EXECUTABLE);
+    let error = Command::new(&executable).args(env::args_os().skip(1)).exec();
+    fail(format!("unable to run `{}`: {}", executable, error))
+}
@@ -0,0 +1,126 @@
+//! The `bootstrap` wrapper: a tiny executable put in the Lambda zip as `bootstrap`, which sets up the environment the way the
+//! project expects and then execs the real executable, which is zipped next to it under its own name.
+//!
+//! It's only added when `BlackMagic.toml` has a `[lambda.wrapper]` section:
+//! ```toml
+//! [lambda.wrapper]
+//! # Set unless the function's configuration already sets them.
+//! env = { RUST_LOG = "info" }
+//! # Copies Lambda's `_HANDLER` into this variable.
+//! handler = "APP_HANDLER"
+//! # Fetched before starting, through the AWS Parameters and Secrets Lambda Extension (which has to be added as a layer).
+//! parameters = { DATABASE_URL = "/prod/database-url" }
+//! secrets = { API_KEY = "prod/api-key" }
+//! ```
+//! It's compiled from `bootstrap.rs` with the settings above it, inside the build container with the builder image's own
+//! compiler, so it needs nothing but std. Failing to fetch a parameter or secret fails the function's init, rather than
+//! starting it without.
+//!
+//! Or the project can bring its own, e.g. a shell script exporting some variables and `exec`ing the executable:
+//! ```toml
+//! [lambda.wrapper]
+//! script = "lambda/bootstrap.sh"
+//! ```
+//! It's zipped as `bootstrap` with mode `0755`, so it needs a `#!` line for Lambda to run it, and can't be combined with the
+//! settings above, which only the generated wrapper has. The executable is next to it, under its own name, as are
+//! the `helpers` in `[lambda]`, for it to start alongside.
+
+use crate::bundle;
+use crate::error::BmError;
+use crate::output;
+use crate::shell_quote;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const SOURCE: &str = include_str!("bootstrap.rs");
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct WrapperConfig {
+    pub env: BTreeMap<String, String>,
+    pub handler: Option<String>,
+    /// Environment variables, and the SSM parameters they're fetched from.
+    pub parameters: BTreeMap<String, String>,
+    /// Environment variables, and the Secrets Manager secrets they're fetched from.
+    pub secrets: BTreeMap<String, String>,
+    /// The project's own wrapper, instead of the generated one.
+    pub script: Option<String>,
+}
+
+fn pairs(map: &BTreeMap<String, String>) -> String {
+    map.iter().map(|(k, v)| format!("({:?}, {:?}), ", k, v)).collect()
+}
+
+impl WrapperConfig {
+    /// The wrapper's source, running `executable` (zipped next to it) once the environment is set up.
+    pub fn source(&self, executable: &str) -> String {
+        format!(
+            "const EXECUTABLE: &str = {:?};\n\
+            const DEFAULTS: &[(&str, &str)] = &[{}];\n\
+            const HANDLER: Option<&str> = {:?};\n\
+            const PARAMETERS: &[(&str, &str)] = &[{}];\n\
+            const SECRETS: &[(&str, &str)] = &[{}];\n\n{}",
+            executable, pairs(&self.env), self.handler, pairs(&self.parameters), pairs(&self.secrets), SOURCE)
+    }
+
+    /// Checks the project's own wrapper, if it has one, is a script inside `project_dir` that Lambda can run, which runs
+    /// `executable`.
+    pub fn check_script(&self, project_dir: &Path, executable: &str) -> Result<(), BmError> {
+        let script = match &self.script {
+            Some(script) => script,
+            None => return Ok(()),
+        };
+        if !self.env.is_empty() || self.handler.is_some() || !self.parameters.is_empty() || !self.secrets.is_empty() {
+            return Err(BmError::Environment(
+                "`script` in `[lambda.wrapper]` replaces the generated wrapper, so it can't be combined with `env`, `handler`, `parameters` or `secrets`.".to_owned()));
+        }
+        let invalid = |reason: &str| Err(BmError::Environment(format!("The `{}` wrapper in `[lambda.wrapper]` {}.", script, reason)));
+        let path = match bundle::contained(script) {
+            Some(relative) => project_dir.join(relative),
+            None => return invalid("has to be a path inside the project"),
+        };
+        let inside = match (fs::canonicalize(&path), fs::canonicalize(project_dir)) {
+            (Ok(path), Ok(project_dir)) => path.starts_with(project_dir),
+            _ => return invalid("doesn't exist"),
+        };
+        if !inside {
+            return invalid("resolves to somewhere outside the project");
+        }
+        let contents = match fs::read(&path) {
+            Ok(contents) if path.is_file() => contents,
+            _ => return invalid("isn't a file that can be read"),
+        };
+        if !contents.starts_with(b"#!") {
+            return invalid("needs a `#!` line, e.g. `#!/bin/sh`, for Lambda to run it");
+        }
+        if !String::from_utf8_lossy(&contents).contains(executable) {
+            output::warning(&format!("The `{}` wrapper doesn't mention `{}`, the executable it's zipped next to.", script, executable));
+        }
+        Ok(())
+    }
+}
+
+/// Writes `source` to `path`, for the build container to compile.
+pub fn write(path: &Path, source: &str) -> Result<(), BmError> {
+    fs::write(path, source).map_err(|e| BmError::Packaging(format!("Unable to write the bootstrap wrapper's source: {}", e)))
+}
+
+/*
+Compile the wrapper to `/bootstrap`:
+    - from its source (relative to the project)
+    - for the same target as the executable
+    - small, as it just sets some variables
+    - without symbols
+*/
+pub fn compile_cmd(source: &str, target: &str) -> String {
+    format!(
+        " && rustc --edition 2018 --crate-name bootstrap --target={} -C opt-level=s -C panic=abort -o /bootstrap {} && strip /bootstrap",
+        target, source)
+}
+
+/// Or, with the project's own `script`, copy it to `/bootstrap`.
+pub fn script_cmd(script: &str) -> String {
+    format!(" && cp {} /bootstrap", shell_quote(script))
+}